@@ -0,0 +1,926 @@
+//! Structural comparison of configs, ignoring comments, formatting and the order of the entries
+//! whose order has no effect: virtual hosts, routes, named matchers, trusted proxies and methods.
+//! Middlewares are applied in order, so reordering them is a change.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+use ipnet::IpNet;
+
+use crate::{
+    env::Expansions,
+    types::{Config, ErrorDocument, GlobalOptions, Matcher, Middleware, Route, VirtualHost},
+};
+
+/// Returns a hash of the config that is the same for configs with the same effect.
+///
+/// The hash does not depend on the Rust version or the platform chico is built for, so it can
+/// be stored and compared between deployments.
+pub fn config_hash(config: &Config) -> u64 {
+    let mut hosts: Vec<u64> = config.virtual_hosts.iter().map(virtual_host_hash).collect();
+    hosts.sort_unstable();
+
+    let mut options = config.options.clone();
+    if let Some(methods) = &mut options.allowed_methods {
+        methods.sort_unstable_by(|a, b| a.as_str().cmp(b.as_str()));
+    }
+
+    let mut hasher = StableHasher::default();
+    options.hash(&mut hasher);
+    hosts.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn virtual_host_hash(host: &VirtualHost) -> u64 {
+    let VirtualHost {
+        domain,
+        routes,
+        error_format,
+        trusted_proxies,
+        forwarded_header,
+        allowed_methods,
+        tls,
+        matchers,
+        error_pages,
+        csp,
+        rate_limit,
+        merge_slashes,
+    } = host;
+
+    let mut routes: Vec<u64> = routes.iter().map(route_hash).collect();
+    routes.sort_unstable();
+
+    let mut hasher = StableHasher::default();
+    domain.hash(&mut hasher);
+    routes.hash(&mut hasher);
+    error_format.hash(&mut hasher);
+    sorted_trusted_proxies(trusted_proxies).hash(&mut hasher);
+    forwarded_header.hash(&mut hasher);
+    allowed_methods
+        .as_deref()
+        .map(sorted_methods)
+        .hash(&mut hasher);
+    tls.hash(&mut hasher);
+    sorted_matchers(matchers).hash(&mut hasher);
+    sorted_error_pages(error_pages).hash(&mut hasher);
+    csp.hash(&mut hasher);
+    rate_limit.hash(&mut hasher);
+    merge_slashes.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn sorted_error_pages(pages: &[ErrorDocument]) -> Vec<&ErrorDocument> {
+    let mut pages: Vec<&ErrorDocument> = pages.iter().collect();
+    pages.sort_unstable_by_key(|page| page.status);
+    pages
+}
+
+fn sorted_matchers(matchers: &[Matcher]) -> Vec<&Matcher> {
+    let mut matchers: Vec<&Matcher> = matchers.iter().collect();
+    matchers.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    matchers
+}
+
+fn route_hash(route: &Route) -> u64 {
+    let Route {
+        path,
+        handler,
+        middlewares,
+        error_format,
+        sniff_extensionless,
+        max_ranges,
+        transcode,
+        negotiate_language,
+        methods,
+    } = route;
+
+    let mut hasher = StableHasher::default();
+    path.hash(&mut hasher);
+    handler.hash(&mut hasher);
+    middlewares.hash(&mut hasher);
+    error_format.hash(&mut hasher);
+    sniff_extensionless.hash(&mut hasher);
+    max_ranges.hash(&mut hasher);
+    transcode.hash(&mut hasher);
+    negotiate_language.hash(&mut hasher);
+    sorted_methods(methods).hash(&mut hasher);
+    hasher.finish()
+}
+
+fn sorted_trusted_proxies(proxies: &[IpNet]) -> Vec<IpNet> {
+    let mut proxies = proxies.to_vec();
+    proxies.sort_unstable();
+    proxies
+}
+
+fn sorted_methods(methods: &[http::Method]) -> Vec<&str> {
+    let mut methods: Vec<&str> = methods.iter().map(http::Method::as_str).collect();
+    methods.sort_unstable();
+    methods
+}
+
+/// 64-bit FNV-1a, unlike `DefaultHasher` its output is specified and never changes.
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    // Lengths and discriminants are hashed as `usize`, write them with the same width everywhere
+    fn write_usize(&mut self, i: usize) {
+        self.write(&(i as u64).to_le_bytes());
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write(&(i as i64).to_le_bytes());
+    }
+}
+
+/// Differences between two configs, see [`diff`].
+#[derive(Debug, PartialEq, Default)]
+pub struct ConfigDiff {
+    pub options: Vec<SettingChange>,
+    /// Changed virtual hosts, by domain.
+    pub virtual_hosts: Vec<VirtualHostChange>,
+}
+
+impl ConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.options.is_empty() && self.virtual_hosts.is_empty()
+    }
+}
+
+/// Setting with a different value in the new config.
+#[derive(Debug, PartialEq)]
+pub struct SettingChange {
+    pub name: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum VirtualHostChange {
+    Added(String),
+    Removed(String),
+    Modified {
+        domain: String,
+        settings: Vec<SettingChange>,
+        /// Changed routes, by path.
+        routes: Vec<RouteChange>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RouteChange {
+    Added(String),
+    Removed(String),
+    Modified {
+        path: String,
+        /// Old and new handler when it changed.
+        handler: Option<(String, String)>,
+        middlewares: Vec<MiddlewareChange>,
+        settings: Vec<SettingChange>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MiddlewareChange {
+    Added(String),
+    Removed(String),
+    /// The same middlewares are applied in another order.
+    Reordered,
+}
+
+/// Compares the virtual hosts, routes, handlers, middlewares and settings of two configs.
+///
+/// Virtual hosts are matched by domain and routes by path. Values are described with their
+/// `Debug` output, which masks the secrets, and the values of the environment variables of both
+/// configs are shown as their placeholders.
+pub fn diff(old: &Config, new: &Config) -> ConfigDiff {
+    let mut expansions = old.expansions.clone();
+    expansions.extend(new.expansions.clone());
+    let mut changes = diff_values(old, new);
+    changes.redact(&expansions);
+    changes
+}
+
+fn diff_values(old: &Config, new: &Config) -> ConfigDiff {
+    let old_hosts: BTreeMap<String, &VirtualHost> = old
+        .virtual_hosts
+        .iter()
+        .map(|host| (host.domain.to_string(), host))
+        .collect();
+    let new_hosts: BTreeMap<String, &VirtualHost> = new
+        .virtual_hosts
+        .iter()
+        .map(|host| (host.domain.to_string(), host))
+        .collect();
+
+    let mut virtual_hosts = vec![];
+    for (domain, old_host) in &old_hosts {
+        match new_hosts.get(domain) {
+            None => virtual_hosts.push(VirtualHostChange::Removed(domain.to_string())),
+            Some(new_host) => {
+                if virtual_host_hash(old_host) != virtual_host_hash(new_host) {
+                    virtual_hosts.push(diff_virtual_host(old_host, new_host));
+                }
+            }
+        }
+    }
+    for domain in new_hosts.keys() {
+        if !old_hosts.contains_key(domain) {
+            virtual_hosts.push(VirtualHostChange::Added(domain.to_string()));
+        }
+    }
+    virtual_hosts.sort_by(|a, b| a.domain().cmp(b.domain()));
+
+    ConfigDiff {
+        options: diff_options(&old.options, &new.options),
+        virtual_hosts,
+    }
+}
+
+fn diff_options(old: &GlobalOptions, new: &GlobalOptions) -> Vec<SettingChange> {
+    let mut changes = vec![];
+    setting(
+        &mut changes,
+        "default_port",
+        &old.default_port,
+        &new.default_port,
+    );
+    setting(&mut changes, "log_level", &old.log_level, &new.log_level);
+    setting(
+        &mut changes,
+        "graceful_shutdown_timeout",
+        &old.graceful_shutdown_timeout,
+        &new.graceful_shutdown_timeout,
+    );
+    setting(&mut changes, "access_log", &old.access_log, &new.access_log);
+    setting(
+        &mut changes,
+        "write_timeout",
+        &old.write_timeout,
+        &new.write_timeout,
+    );
+    setting(
+        &mut changes,
+        "header_read_timeout",
+        &old.header_read_timeout,
+        &new.header_read_timeout,
+    );
+    setting(&mut changes, "not_found", &old.not_found, &new.not_found);
+    setting(
+        &mut changes,
+        "default_host",
+        &old.default_host,
+        &new.default_host,
+    );
+    setting(&mut changes, "csp", &old.csp, &new.csp);
+    setting(
+        &mut changes,
+        "memory_budget",
+        &old.memory_budget,
+        &new.memory_budget,
+    );
+    setting(
+        &mut changes,
+        "default_request_timeout",
+        &old.default_request_timeout,
+        &new.default_request_timeout,
+    );
+    setting(
+        &mut changes,
+        "default_connection_timeout",
+        &old.default_connection_timeout,
+        &new.default_connection_timeout,
+    );
+    setting(
+        &mut changes,
+        "websocket_drain",
+        &old.websocket_drain,
+        &new.websocket_drain,
+    );
+    setting(
+        &mut changes,
+        "strict_sni_host",
+        &old.strict_sni_host,
+        &new.strict_sni_host,
+    );
+    setting(
+        &mut changes,
+        "allowed_methods",
+        &old.allowed_methods.as_deref().map(sorted_methods),
+        &new.allowed_methods.as_deref().map(sorted_methods),
+    );
+    changes
+}
+
+fn diff_virtual_host(old: &VirtualHost, new: &VirtualHost) -> VirtualHostChange {
+    let mut settings = vec![];
+    setting(
+        &mut settings,
+        "error_format",
+        &old.error_format,
+        &new.error_format,
+    );
+    setting(
+        &mut settings,
+        "trusted_proxies",
+        &sorted_trusted_proxies(&old.trusted_proxies),
+        &sorted_trusted_proxies(&new.trusted_proxies),
+    );
+    setting(
+        &mut settings,
+        "forwarded_header",
+        &old.forwarded_header,
+        &new.forwarded_header,
+    );
+    setting(
+        &mut settings,
+        "allowed_methods",
+        &old.allowed_methods.as_deref().map(sorted_methods),
+        &new.allowed_methods.as_deref().map(sorted_methods),
+    );
+    setting(&mut settings, "tls", &old.tls, &new.tls);
+    setting(
+        &mut settings,
+        "matchers",
+        &sorted_matchers(&old.matchers),
+        &sorted_matchers(&new.matchers),
+    );
+    setting(
+        &mut settings,
+        "error_pages",
+        &sorted_error_pages(&old.error_pages),
+        &sorted_error_pages(&new.error_pages),
+    );
+    setting(&mut settings, "csp", &old.csp, &new.csp);
+    setting(
+        &mut settings,
+        "rate_limit",
+        &old.rate_limit,
+        &new.rate_limit,
+    );
+    setting(
+        &mut settings,
+        "merge_slashes",
+        &old.merge_slashes,
+        &new.merge_slashes,
+    );
+
+    let old_routes: BTreeMap<&str, &Route> = old
+        .routes
+        .iter()
+        .map(|route| (route.path.as_str(), route))
+        .collect();
+    let new_routes: BTreeMap<&str, &Route> = new
+        .routes
+        .iter()
+        .map(|route| (route.path.as_str(), route))
+        .collect();
+
+    let mut routes = vec![];
+    for (path, old_route) in &old_routes {
+        match new_routes.get(path) {
+            None => routes.push(RouteChange::Removed(path.to_string())),
+            Some(new_route) if old_route != new_route => {
+                routes.push(diff_route(old_route, new_route))
+            }
+            Some(_) => {}
+        }
+    }
+    for path in new_routes.keys() {
+        if !old_routes.contains_key(path) {
+            routes.push(RouteChange::Added(path.to_string()));
+        }
+    }
+    routes.sort_by(|a, b| a.path().cmp(b.path()));
+
+    VirtualHostChange::Modified {
+        domain: old.domain.to_string(),
+        settings,
+        routes,
+    }
+}
+
+fn diff_route(old: &Route, new: &Route) -> RouteChange {
+    let handler = (old.handler != new.handler)
+        .then(|| (format!("{:?}", old.handler), format!("{:?}", new.handler)));
+
+    let mut settings = vec![];
+    setting(
+        &mut settings,
+        "error_format",
+        &old.error_format,
+        &new.error_format,
+    );
+    setting(
+        &mut settings,
+        "sniff_extensionless",
+        &old.sniff_extensionless,
+        &new.sniff_extensionless,
+    );
+    setting(
+        &mut settings,
+        "max_ranges",
+        &old.max_ranges,
+        &new.max_ranges,
+    );
+    setting(&mut settings, "transcode", &old.transcode, &new.transcode);
+    setting(
+        &mut settings,
+        "negotiate_language",
+        &old.negotiate_language,
+        &new.negotiate_language,
+    );
+    setting(
+        &mut settings,
+        "method",
+        &sorted_methods(&old.methods),
+        &sorted_methods(&new.methods),
+    );
+
+    RouteChange::Modified {
+        path: old.path.clone(),
+        handler,
+        middlewares: diff_middlewares(&old.middlewares, &new.middlewares),
+        settings,
+    }
+}
+
+/// Middlewares only in one of the lists, or `Reordered` when both have the same ones in another
+/// order.
+fn diff_middlewares(old: &[Middleware], new: &[Middleware]) -> Vec<MiddlewareChange> {
+    let mut added: Vec<&Middleware> = new.iter().collect();
+    let mut changes = vec![];
+    for middleware in old {
+        match added.iter().position(|m| *m == middleware) {
+            Some(i) => {
+                added.remove(i);
+            }
+            None => changes.push(MiddlewareChange::Removed(format!("{middleware:?}"))),
+        }
+    }
+    changes.extend(
+        added
+            .into_iter()
+            .map(|middleware| MiddlewareChange::Added(format!("{middleware:?}"))),
+    );
+
+    if changes.is_empty() && old != new {
+        changes.push(MiddlewareChange::Reordered);
+    }
+    changes
+}
+
+fn setting<T: PartialEq + fmt::Debug>(
+    changes: &mut Vec<SettingChange>,
+    name: &'static str,
+    old: &T,
+    new: &T,
+) {
+    if old != new {
+        changes.push(SettingChange {
+            name,
+            old: format!("{old:?}"),
+            new: format!("{new:?}"),
+        });
+    }
+}
+
+impl ConfigDiff {
+    fn redact(&mut self, expansions: &Expansions) {
+        let redact = |text: &mut String| *text = expansions.redact(text);
+        let redact_settings = |settings: &mut Vec<SettingChange>| {
+            for setting in settings {
+                redact(&mut setting.old);
+                redact(&mut setting.new);
+            }
+        };
+
+        redact_settings(&mut self.options);
+        for host in &mut self.virtual_hosts {
+            match host {
+                VirtualHostChange::Added(domain) | VirtualHostChange::Removed(domain) => {
+                    redact(domain)
+                }
+                VirtualHostChange::Modified {
+                    domain,
+                    settings,
+                    routes,
+                } => {
+                    redact(domain);
+                    redact_settings(settings);
+                    for route in routes {
+                        match route {
+                            RouteChange::Added(path) | RouteChange::Removed(path) => redact(path),
+                            RouteChange::Modified {
+                                path,
+                                handler,
+                                middlewares,
+                                settings,
+                            } => {
+                                redact(path);
+                                if let Some((old, new)) = handler {
+                                    redact(old);
+                                    redact(new);
+                                }
+                                for middleware in middlewares {
+                                    if let MiddlewareChange::Added(middleware)
+                                    | MiddlewareChange::Removed(middleware) = middleware
+                                    {
+                                        redact(middleware);
+                                    }
+                                }
+                                redact_settings(settings);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl VirtualHostChange {
+    fn domain(&self) -> &str {
+        match self {
+            VirtualHostChange::Added(domain)
+            | VirtualHostChange::Removed(domain)
+            | VirtualHostChange::Modified { domain, .. } => domain,
+        }
+    }
+}
+
+impl RouteChange {
+    fn path(&self) -> &str {
+        match self {
+            RouteChange::Added(path)
+            | RouteChange::Removed(path)
+            | RouteChange::Modified { path, .. } => path,
+        }
+    }
+}
+
+/// Prints the changes as a tree, `+` marks added entries, `-` removed ones and `~` modified ones.
+impl fmt::Display for ConfigDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.options.is_empty() {
+            writeln!(f, "~ options")?;
+            write_settings(f, &self.options, 1)?;
+        }
+        for host in &self.virtual_hosts {
+            match host {
+                VirtualHostChange::Added(domain) => writeln!(f, "+ {domain}")?,
+                VirtualHostChange::Removed(domain) => writeln!(f, "- {domain}")?,
+                VirtualHostChange::Modified {
+                    domain,
+                    settings,
+                    routes,
+                } => {
+                    writeln!(f, "~ {domain}")?;
+                    write_settings(f, settings, 1)?;
+                    for route in routes {
+                        write_route(f, route)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_route(f: &mut fmt::Formatter<'_>, route: &RouteChange) -> fmt::Result {
+    match route {
+        RouteChange::Added(path) => writeln!(f, "  + route {path}"),
+        RouteChange::Removed(path) => writeln!(f, "  - route {path}"),
+        RouteChange::Modified {
+            path,
+            handler,
+            middlewares,
+            settings,
+        } => {
+            writeln!(f, "  ~ route {path}")?;
+            if let Some((old, new)) = handler {
+                writeln!(f, "    ~ handler: {old} -> {new}")?;
+            }
+            for middleware in middlewares {
+                match middleware {
+                    MiddlewareChange::Added(m) => writeln!(f, "    + middleware {m}")?,
+                    MiddlewareChange::Removed(m) => writeln!(f, "    - middleware {m}")?,
+                    MiddlewareChange::Reordered => writeln!(f, "    ~ middlewares reordered")?,
+                }
+            }
+            write_settings(f, settings, 2)
+        }
+    }
+}
+
+fn write_settings(
+    f: &mut fmt::Formatter<'_>,
+    settings: &[SettingChange],
+    depth: usize,
+) -> fmt::Result {
+    for setting in settings {
+        writeln!(
+            f,
+            "{:indent$}~ {}: {} -> {}",
+            "",
+            setting.name,
+            setting.old,
+            setting.new,
+            indent = depth * 2
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        config_hash, diff, MiddlewareChange, RouteChange, SettingChange, VirtualHostChange,
+    };
+    use crate::{env::expand, parse_config, types::Config};
+
+    fn config(input: &str) -> Config {
+        parse_config(input).unwrap().1
+    }
+
+    #[test]
+    fn test_config_hash_ignores_formatting_and_order() {
+        let old = config(
+            r#"
+            # main site
+            a.example.com {
+                route / {
+                    respond 200
+                }
+                route /api {
+                    proxy http://localhost:3000
+                }
+            }
+            b.example.com {
+                route / {
+                    respond 204
+                }
+            }
+            "#,
+        );
+        let new = config(
+            "b.example.com { route / { respond 204 } }\na.example.com { route /api { proxy http://localhost:3000 } route / { respond 200 } }",
+        );
+
+        assert_eq!(config_hash(&old), config_hash(&new));
+        assert!(diff(&old, &new).is_empty());
+    }
+
+    #[test]
+    fn test_config_hash_changes_with_middleware_order() {
+        let old = config("localhost { route / { respond 200\n gzip\n log } }");
+        let new = config("localhost { route / { respond 200\n log\n gzip } }");
+
+        assert_ne!(config_hash(&old), config_hash(&new));
+        assert_eq!(
+            diff(&old, &new).virtual_hosts,
+            vec![VirtualHostChange::Modified {
+                domain: "localhost".parse().unwrap(),
+                settings: vec![],
+                routes: vec![RouteChange::Modified {
+                    path: "/".to_string(),
+                    handler: None,
+                    middlewares: vec![MiddlewareChange::Reordered],
+                    settings: vec![],
+                }],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_config_hash_ignores_route_method_order() {
+        let old = config("localhost { route / { method GET POST\n respond 200 } }");
+        let new = config("localhost { route / { method POST GET\n respond 200 } }");
+        assert_eq!(config_hash(&old), config_hash(&new));
+        assert!(diff(&old, &new).is_empty());
+
+        let new = config("localhost { route / { method GET\n respond 200 } }");
+        assert_ne!(config_hash(&old), config_hash(&new));
+        assert_eq!(
+            diff(&old, &new).to_string(),
+            "~ localhost\n  ~ route /\n    ~ method: [\"GET\", \"POST\"] -> [\"GET\"]\n"
+        );
+    }
+
+    #[test]
+    fn test_config_hash_ignores_allowed_methods_order_of_options() {
+        let old =
+            config("options { allowed_methods GET HEAD }\nlocalhost { route / { respond 200 } }");
+        let new =
+            config("options { allowed_methods HEAD GET }\nlocalhost { route / { respond 200 } }");
+        assert_eq!(config_hash(&old), config_hash(&new));
+        assert!(diff(&old, &new).is_empty());
+
+        let new = config("options { allowed_methods GET }\nlocalhost { route / { respond 200 } }");
+        assert_ne!(config_hash(&old), config_hash(&new));
+        assert_eq!(
+            diff(&old, &new).to_string(),
+            "~ options\n  ~ allowed_methods: Some([\"GET\", \"HEAD\"]) -> Some([\"GET\"])\n"
+        );
+    }
+
+    #[test]
+    fn test_config_hash_changes_with_secret() {
+        let old = config("localhost { route / { respond 200\n auth admin old-secret } }");
+        let new = config("localhost { route / { respond 200\n auth admin new-secret } }");
+
+        assert_ne!(config_hash(&old), config_hash(&new));
+    }
+
+    #[test]
+    fn test_diff_added_and_removed_virtual_hosts() {
+        let old = config(
+            "a.example.com { route / { respond 200 } }\nb.example.com { route / { respond 200 } }",
+        );
+        let new = config(
+            "b.example.com { route / { respond 200 } }\nc.example.com { route / { respond 200 } }",
+        );
+
+        assert_eq!(
+            diff(&old, &new).virtual_hosts,
+            vec![
+                VirtualHostChange::Removed("a.example.com".to_string()),
+                VirtualHostChange::Added("c.example.com".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_added_and_removed_routes() {
+        let old = config("localhost { route / { respond 200 } route /old { respond 200 } }");
+        let new = config("localhost { route / { respond 200 } route /new { respond 200 } }");
+
+        assert_eq!(
+            diff(&old, &new).virtual_hosts,
+            vec![VirtualHostChange::Modified {
+                domain: "localhost".parse().unwrap(),
+                settings: vec![],
+                routes: vec![
+                    RouteChange::Added("/new".to_string()),
+                    RouteChange::Removed("/old".to_string()),
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_diff_modified_handler() {
+        let old = config("localhost { route / { file index.html } }");
+        let new = config("localhost { route / { file home.html } }");
+
+        assert_eq!(
+            diff(&old, &new).virtual_hosts,
+            vec![VirtualHostChange::Modified {
+                domain: "localhost".parse().unwrap(),
+                settings: vec![],
+                routes: vec![RouteChange::Modified {
+                    path: "/".to_string(),
+                    handler: Some((
+                        r#"File("index.html")"#.to_string(),
+                        r#"File("home.html")"#.to_string()
+                    )),
+                    middlewares: vec![],
+                    settings: vec![],
+                }],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_diff_added_and_removed_middlewares() {
+        let old = config("localhost { route / { respond 200\n log\n cors * } }");
+        let new = config("localhost { route / { respond 200\n log\n gzip } }");
+
+        let VirtualHostChange::Modified { routes, .. } = &diff(&old, &new).virtual_hosts[0] else {
+            panic!("Expected a modified virtual host");
+        };
+        let RouteChange::Modified { middlewares, .. } = &routes[0] else {
+            panic!("Expected a modified route");
+        };
+        assert_eq!(
+            middlewares,
+            &vec![
+                MiddlewareChange::Removed(r#"Cors("*")"#.to_string()),
+                MiddlewareChange::Added("Gzip(CompressionLevel(6))".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_settings() {
+        let old = config("localhost { route / { respond 200 } }");
+        let new = config(
+            "options { default_port 8080 }\nlocalhost { error_format json\n route / { respond 200\n transcode on } }",
+        );
+
+        let changes = diff(&old, &new);
+
+        assert_eq!(
+            changes.options,
+            vec![SettingChange {
+                name: "default_port",
+                old: "None".to_string(),
+                new: "Some(8080)".to_string(),
+            }]
+        );
+        assert_eq!(
+            changes.virtual_hosts,
+            vec![VirtualHostChange::Modified {
+                domain: "localhost".parse().unwrap(),
+                settings: vec![SettingChange {
+                    name: "error_format",
+                    old: "None".to_string(),
+                    new: "Some(Json)".to_string(),
+                }],
+                routes: vec![RouteChange::Modified {
+                    path: "/".to_string(),
+                    handler: None,
+                    middlewares: vec![],
+                    settings: vec![SettingChange {
+                        name: "transcode",
+                        old: "false".to_string(),
+                        new: "true".to_string(),
+                    }],
+                }],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_diff_display_tree() {
+        let old = config(
+            "a.example.com { route / { file index.html } route /old { respond 200 } }\nb.example.com { route / { respond 200 } }",
+        );
+        let new = config(
+            "a.example.com { route / { file home.html\n gzip } }\nc.example.com { route / { respond 200 } }",
+        );
+
+        assert_eq!(
+            diff(&old, &new).to_string(),
+            r#"~ a.example.com
+  ~ route /
+    ~ handler: File("index.html") -> File("home.html")
+    + middleware Gzip(CompressionLevel(6))
+  - route /old
+- b.example.com
++ c.example.com
+"#
+        );
+    }
+
+    #[test]
+    fn test_diff_does_not_print_secrets() {
+        let old = config("localhost { route / { respond 200\n auth admin old-secret } }");
+        let new = config("localhost { route / { respond 200\n auth admin new-secret } }");
+
+        let printed = diff(&old, &new).to_string();
+
+        assert!(printed.contains("middleware Auth"), "{printed}");
+        assert!(!printed.contains("secret"), "{printed}");
+    }
+
+    #[test]
+    fn test_diff_prints_placeholders_of_environment_variables() {
+        let config_with = |host: &str| {
+            let (input, expansions) = expand(
+                "localhost { route / { proxy http://${BACKEND_HOST}:3000 } }",
+                |_| Some(host.to_string()),
+            )
+            .unwrap();
+            Config {
+                expansions,
+                ..config(&input)
+            }
+        };
+
+        let printed = diff(&config_with("10.0.0.5"), &config_with("10.0.0.6")).to_string();
+
+        assert!(printed.contains("${BACKEND_HOST}"), "{printed}");
+        assert!(!printed.contains("10.0.0."), "{printed}");
+    }
+}
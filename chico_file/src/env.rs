@@ -0,0 +1,216 @@
+//! # Environment variables
+//!
+//! A config file may take values from the environment, so the same file serves several
+//! deployments:
+//!
+//! - `${VAR}` is replaced by the value of the environment variable `VAR`, an unset variable
+//!   fails the config.
+//! - `${VAR:-default}` falls back to `default` when `VAR` is unset or empty.
+//! - `$$` is a literal `$`, a `$` followed by anything else is kept as is.
+//!
+//! The variables are expanded in the text of the file before it is parsed, so they may appear in
+//! any value. The values may be secrets: the config keeps the [`Expansions`] and shows their
+//! placeholders in place of the values in its `Debug` output and in diffs.
+
+use std::fmt;
+
+/// Placeholders expanded in a config and their values.
+#[derive(Clone, Default, PartialEq)]
+pub struct Expansions(Vec<Expansion>);
+
+#[derive(Clone, PartialEq)]
+struct Expansion {
+    placeholder: String,
+    value: String,
+}
+
+impl Expansions {
+    /// Adds the expansions of another file of the config.
+    pub fn extend(&mut self, other: Expansions) {
+        for expansion in other.0 {
+            if !self.0.contains(&expansion) {
+                self.0.push(expansion);
+            }
+        }
+    }
+
+    /// Replaces the expanded values in `text` by their placeholders.
+    ///
+    /// Any occurrence of a value is replaced, also where it does not come from the environment,
+    /// as it can not be told apart. Values are also replaced in their escaped `Debug` form.
+    pub fn redact(&self, text: &str) -> String {
+        let mut needles: Vec<(String, &str)> = vec![];
+        for expansion in self
+            .0
+            .iter()
+            .filter(|expansion| !expansion.value.is_empty())
+        {
+            let escaped = format!("{:?}", expansion.value);
+            let escaped = escaped[1..escaped.len() - 1].to_string();
+            if escaped != expansion.value {
+                needles.push((escaped, &expansion.placeholder));
+            }
+            needles.push((expansion.value.clone(), &expansion.placeholder));
+        }
+        // the longest first, so a value containing another one is replaced whole
+        needles.sort_by_key(|(needle, _)| std::cmp::Reverse(needle.len()));
+
+        // a single pass, the placeholders may contain values themselves
+        let mut redacted = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            match needles.iter().find(|(needle, _)| rest.starts_with(needle)) {
+                Some((needle, placeholder)) => {
+                    redacted.push_str(placeholder);
+                    rest = &rest[needle.len()..];
+                }
+                None => {
+                    redacted.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        redacted
+    }
+}
+
+/// Prints the placeholders only.
+impl fmt::Debug for Expansions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|expansion| &expansion.placeholder))
+            .finish()
+    }
+}
+
+/// Expands the variables of `input`, reading their values with `lookup`.
+///
+/// Returns the expanded text and the expansions, or an error naming the variable and its line.
+pub fn expand(
+    input: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<(String, Expansions), String> {
+    let mut expanded = String::with_capacity(input.len());
+    let mut expansions = Expansions::default();
+    let mut rest = input;
+    while let Some(dollar) = rest.find('$') {
+        expanded.push_str(&rest[..dollar]);
+        let after = &rest[dollar + 1..];
+        if let Some(after) = after.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+        let Some(after) = after.strip_prefix('{') else {
+            expanded.push('$');
+            rest = after;
+            continue;
+        };
+
+        let line = input[..input.len() - rest.len() + dollar]
+            .matches('\n')
+            .count()
+            + 1;
+        let Some(end) = after.find('}') else {
+            return Err(format!("Unclosed environment variable at line {line}."));
+        };
+        let placeholder = &rest[dollar..dollar + end + 3];
+        let (name, default) = match after[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&after[..end], None),
+        };
+        if !is_variable_name(name) {
+            return Err(format!(
+                "Invalid environment variable name '{name}' at line {line}."
+            ));
+        }
+        let value = match (lookup(name), default) {
+            (Some(value), Some(default)) if value.is_empty() => default.to_string(),
+            (Some(value), _) => value,
+            (None, Some(default)) => default.to_string(),
+            (None, None) => {
+                return Err(format!(
+                    "Environment variable {name} used at line {line} is not set, set it or give a default with ${{{name}:-default}}."
+                ));
+            }
+        };
+        expanded.push_str(&value);
+        expansions.extend(Expansions(vec![Expansion {
+            placeholder: placeholder.to_string(),
+            value,
+        }]));
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok((expanded, expansions))
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::expand;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "BACKEND_HOST" => Some("10.0.0.5".to_string()),
+            "EMPTY" => Some(String::new()),
+            "SECRET" => Some("hunter2".to_string()),
+            _ => None,
+        }
+    }
+
+    #[rstest]
+    #[case("proxy http://${BACKEND_HOST}:3000", "proxy http://10.0.0.5:3000")]
+    #[case("${MISSING:-localhost}", "localhost")]
+    #[case("${EMPTY:-fallback}", "fallback")]
+    #[case("${BACKEND_HOST:-fallback}", "10.0.0.5")]
+    #[case("${MISSING:-}", "")]
+    #[case("respond \"$$5 ${SECRET}\"", "respond \"$5 hunter2\"")]
+    #[case("rewrite ^/old(.*)$ /new$1", "rewrite ^/old(.*)$ /new$1")]
+    #[case("$${BACKEND_HOST}", "${BACKEND_HOST}")]
+    #[case("trailing $", "trailing $")]
+    fn test_expand(#[case] input: &str, #[case] expected: &str) {
+        let (expanded, _) = expand(input, lookup).unwrap();
+        assert_eq!(expanded, expected);
+    }
+
+    #[rstest]
+    #[case(
+        "localhost {\n  route / { proxy http://${MISSING}:3000 }\n}",
+        "Environment variable MISSING used at line 2 is not set"
+    )]
+    #[case(
+        "\n\n${NOT-A-NAME}",
+        "Invalid environment variable name 'NOT-A-NAME' at line 3."
+    )]
+    #[case("${}", "Invalid environment variable name '' at line 1.")]
+    #[case("ok\n${SECRET", "Unclosed environment variable at line 2.")]
+    fn test_expand_errors(#[case] input: &str, #[case] expected: &str) {
+        let error = expand(input, lookup).unwrap_err();
+        assert!(error.starts_with(expected), "{error}");
+    }
+
+    #[test]
+    fn test_redact_replaces_values_by_placeholders() {
+        let (_, expansions) =
+            expand("${SECRET} ${SECRET} ${BACKEND_HOST} ${EMPTY:-x\"y}", lookup).unwrap();
+
+        assert_eq!(
+            expansions.redact(r#"Auth("hunter2") 10.0.0.5 "x\"y" x"y"#),
+            r#"Auth("${SECRET}") ${BACKEND_HOST} "${EMPTY:-x"y}" ${EMPTY:-x"y}"#
+        );
+        assert_eq!(
+            format!("{expansions:?}"),
+            r#"["${SECRET}", "${BACKEND_HOST}", "${EMPTY:-x\"y}"]"#
+        );
+    }
+}
@@ -1,27 +1,160 @@
 #![cfg_attr(feature = "strict", deny(warnings))]
 
 use nom::{
-    branch::alt,
-    bytes::complete::{tag, take_while1},
+    branch::{alt, permutation},
+    bytes::complete::{tag, tag_no_case, take_while1},
     character::complete::{
         char, digit1, multispace0, multispace1, none_of, not_line_ending, space1,
     },
-    combinator::{map, opt},
-    error::{Error, ErrorKind},
+    combinator::{cut, map, map_opt, map_res, opt, value, verify},
+    error::{ErrorKind, FromExternalError, ParseError},
     multi::{many0, many1},
-    sequence::{delimited, preceded, tuple},
-    Err, IResult,
+    sequence::{delimited, preceded, terminated, tuple},
+    Err,
+};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
 };
 use types::{Config, VirtualHost};
 
 use crate::types::Upstream;
 
+pub mod diff;
+pub mod env;
 pub mod types;
 
+/// Error of the config parsers.
+///
+/// A directive that is recognized but has an invalid value fails with a message saying what is
+/// wrong, reported in place of the suggestion guessed from the surrounding content.
+#[derive(Debug, PartialEq)]
+struct Error<I> {
+    input: I,
+    code: ErrorKind,
+    message: Option<String>,
+}
+
+impl<I> Error<I> {
+    fn new(input: I, code: ErrorKind) -> Self {
+        Error {
+            input,
+            code,
+            message: None,
+        }
+    }
+
+    /// Error of an invalid value at `input`.
+    fn invalid(input: I, message: impl Into<String>) -> Self {
+        Error {
+            input,
+            code: ErrorKind::Verify,
+            message: Some(message.into()),
+        }
+    }
+}
+
+impl<I> ParseError<I> for Error<I> {
+    fn from_error_kind(input: I, kind: ErrorKind) -> Self {
+        Error::new(input, kind)
+    }
+
+    fn append(_: I, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl<I, E> FromExternalError<I, E> for Error<I> {
+    fn from_external_error(input: I, kind: ErrorKind, _: E) -> Self {
+        Error::new(input, kind)
+    }
+}
+
+type IResult<I, O, E = Error<I>> = nom::IResult<I, O, E>;
+
 // Type aliases for complex return types to satisfy clippy
-type ProxyBlockContentsResult<'a> =
-    IResult<&'a str, (Vec<Upstream>, Option<String>, Option<u64>, Option<u64>)>;
-type ProxyOptionalFieldsResult<'a> = IResult<&'a str, (Option<String>, Option<u64>, Option<u64>)>;
+type ProxyBlockContentsResult<'a> = IResult<&'a str, (Vec<Upstream>, ProxyOptionalFields)>;
+type ProxyOptionalFieldsResult<'a> = IResult<&'a str, ProxyOptionalFields>;
+
+/// Optional settings that may follow the `upstreams` line inside a proxy block
+#[derive(Default)]
+struct ProxyOptionalFields {
+    lb_policy: Option<String>,
+    request_timeout: Option<Duration>,
+    connection_timeout: Option<Duration>,
+    resolve_ttl: Option<Duration>,
+    dns_stale_max: Option<Duration>,
+    canary: Option<types::CanaryConfig>,
+    max_fails: Option<u32>,
+    fail_timeout: Option<Duration>,
+    circuit_breaker: Option<types::CircuitBreakerConfig>,
+    forwarded: Option<bool>,
+    host_header: Option<types::HostHeader>,
+    strip_request_headers: Option<Vec<String>>,
+    header_up: Vec<types::Middleware>,
+    header_down: Vec<types::Middleware>,
+}
+
+/// Keywords of the proxy block, upstream addresses are read until one of them
+const PROXY_BLOCK_KEYWORDS: [&str; 15] = [
+    "lb_policy",
+    "request_timeout",
+    "connection_timeout",
+    "resolve_ttl",
+    "dns_stale_max",
+    "canary",
+    "max_fails",
+    "fail_timeout",
+    "circuit_breaker",
+    "forwarded",
+    "host_header",
+    "preserve_host",
+    "strip_request_headers",
+    "header_up",
+    "header_down",
+];
+
+/// Values of `lb_policy`, listed in the error of an unknown policy
+const LB_POLICIES: [&str; 4] = ["round_robin", "least_conn", "random", "ip_hash"];
+
+/// Entries allowed directly inside a virtual host block
+enum VirtualHostEntry {
+    Route(types::Route),
+    ErrorFormat(types::ErrorFormat),
+    TrustedProxies(Vec<ipnet::IpNet>),
+    ForwardedHeader(types::ForwardedHeader),
+    AllowedMethods(Vec<http::Method>),
+    Tls(types::TlsConfig),
+    Matcher(types::Matcher),
+    ErrorPage(types::ErrorDocument),
+    Csp(types::ContentSecurityPolicy),
+    RateLimit(types::RateLimit),
+    MergeSlashes(types::MergeSlashes),
+}
+
+/// Entries allowed inside a route block besides the handler
+enum RouteEntry {
+    Middleware(types::Middleware),
+    ErrorFormat(types::ErrorFormat),
+    SniffExtensionless(bool),
+    MaxRanges(usize),
+    Transcode(bool),
+    NegotiateLanguage(bool),
+    Methods(Vec<http::Method>),
+}
+
+/// Handler, middlewares and options parsed from a route block
+#[derive(Debug, PartialEq)]
+struct RouteContents {
+    handler: types::Handler,
+    middlewares: Vec<types::Middleware>,
+    error_format: Option<types::ErrorFormat>,
+    sniff_extensionless: bool,
+    max_ranges: Option<usize>,
+    transcode: bool,
+    negotiate_language: bool,
+    methods: Vec<http::Method>,
+}
 
 /// Convert nom parsing errors into user-friendly error messages
 fn format_parse_error(input: &str, error: nom::Err<Error<&str>>) -> String {
@@ -29,6 +162,12 @@ fn format_parse_error(input: &str, error: nom::Err<Error<&str>>) -> String {
         nom::Err::Error(e) | nom::Err::Failure(e) => {
             let error_location = find_error_location(input, e.input);
             let context = get_error_context(e.input);
+            if let Some(message) = e.message {
+                return format!(
+                    "Invalid value near{}: '{}'. {}",
+                    error_location, context, message
+                );
+            }
 
             // Always use the new context analysis for better error messages
             let suggestion = analyze_parsing_context(input, e.input);
@@ -92,6 +231,12 @@ fn format_parse_error(input: &str, error: nom::Err<Error<&str>>) -> String {
 
 /// Find the approximate line and column number where the error occurred
 fn find_error_location(full_input: &str, error_input: &str) -> String {
+    let (line, col) = error_position(full_input, error_input);
+    format!(" line {}, column {}", line, col)
+}
+
+/// Line and column, starting at 1, where `error_input` begins in `full_input`
+fn error_position(full_input: &str, error_input: &str) -> (usize, usize) {
     // Calculate position where error occurred
     let error_pos = full_input.len() - error_input.len();
 
@@ -111,7 +256,7 @@ fn find_error_location(full_input: &str, error_input: &str) -> String {
         }
     }
 
-    format!(" line {}, column {}", line, col)
+    (line, col)
 }
 
 /// Get a snippet of context around the error location for display
@@ -133,23 +278,64 @@ fn get_error_context(error_input: &str) -> String {
 /// Get a context snippet from the full input to show what was being parsed
 fn get_parsing_context_snippet(input: &str) -> String {
     // Get the last 30 characters to show what we were parsing
-    let chars: Vec<char> = input.chars().collect();
-    let start = if chars.len() > 30 {
-        chars.len() - 30
-    } else {
-        0
-    };
-    let context: String = chars[start..].iter().collect();
+    match input.char_indices().rev().nth(29) {
+        Some((start, _)) if start > 0 => format!("...{}", &input[start..]),
+        _ => input.to_string(),
+    }
+}
 
-    if start > 0 {
-        format!("...{}", context)
-    } else {
-        context
+/// Bytes before and after the error position the error analysis looks at. Configs generated by a
+/// broken template may be megabytes long, the analysis never needs more to find a suggestion.
+const ANALYSIS_WINDOW: usize = 4 * 1024;
+
+/// Returns the part of `full_input` around the error and `error_input` cut at the same end, so
+/// the error input is still a suffix of the window.
+///
+/// The window ends after the last block closed within it when the input is cut, so the cut does
+/// not look like missing braces.
+fn analysis_window<'a>(full_input: &'a str, error_input: &str) -> (&'a str, &'a str) {
+    let error_pos = full_input.len() - error_input.len();
+    let start = floor_char_boundary(full_input, error_pos.saturating_sub(ANALYSIS_WINDOW));
+    let mut end = floor_char_boundary(
+        full_input,
+        error_pos
+            .saturating_add(ANALYSIS_WINDOW)
+            .min(full_input.len()),
+    );
+    if end < full_input.len() {
+        let mut depth = 0;
+        let mut balanced_end = None;
+        for (i, c) in full_input[error_pos..end].char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth <= 0 {
+                        balanced_end = Some(error_pos + i + 1);
+                    }
+                }
+                _ => {}
+            }
+        }
+        end = balanced_end.unwrap_or(end);
+    }
+    (&full_input[start..end], &full_input[error_pos..end])
+}
+
+/// Largest char boundary of `input` not above `index`.
+fn floor_char_boundary(input: &str, mut index: usize) -> usize {
+    while !input.is_char_boundary(index) {
+        index -= 1;
     }
+    index
 }
 
 /// Analyze the parsing context to provide specific error messages
 fn analyze_parsing_context(full_input: &str, error_input: &str) -> String {
+    // The heuristics below make a fixed number of passes over their input, bound it so a huge
+    // config does not make formatting a single error slow
+    let (full_input, error_input) = analysis_window(full_input, error_input);
+
     // When error_input is empty (usually EOF), analyze the full input to understand context
     if error_input.is_empty() {
         return analyze_eof_context(full_input);
@@ -218,7 +404,7 @@ fn analyze_eof_context(full_input: &str) -> String {
 
     // Check for incomplete middleware at the end of input
     if trimmed_input.ends_with("rate_limit") && !trimmed_input.ends_with("rate_limit ") {
-        return "Rate limit middleware requires a number. Example: 'rate_limit 10'.".to_string();
+        return "Rate limit middleware requires a number. Example: 'rate_limit 10' or 'rate_limit 10/1m'.".to_string();
     }
 
     if trimmed_input.ends_with("cache") && !trimmed_input.ends_with("cache ") {
@@ -270,17 +456,15 @@ fn normalize_whitespace(input: &str) -> String {
 
 /// Extract tokens from the end of input for context analysis
 fn extract_last_tokens(input: &str, count: usize) -> Vec<String> {
-    let normalized = normalize_whitespace(input);
-    let tokens: Vec<&str> = normalized.split_whitespace().collect();
-    tokens
-        .iter()
+    // Walk the tokens from the end, only the last `count` are visited
+    let mut tokens: Vec<String> = input
+        .split_whitespace()
         .rev()
         .take(count)
-        .map(|s| s.to_string())
-        .collect::<Vec<_>>()
-        .into_iter()
-        .rev()
-        .collect()
+        .map(str::to_string)
+        .collect();
+    tokens.reverse();
+    tokens
 }
 
 /// Check if the input ends with a specific pattern, handling multiline configurations
@@ -320,6 +504,17 @@ fn suggest_fix_for_content_with_full_context(full_input: &str, error_input: &str
         return "Configuration file appears to be empty or contains only whitespace.".to_string();
     }
 
+    if before_error.split_whitespace().next_back() == Some("lb_policy") {
+        let policy = trimmed_error
+            .split(|c: char| c.is_whitespace() || c == '}')
+            .next()
+            .unwrap_or_default();
+        return format!(
+            "Unknown lb_policy '{policy}'. Valid policies: {}.",
+            LB_POLICIES.join(", ")
+        );
+    }
+
     // PRIORITY 0: Check for incomplete handlers at the end of full input
     // This handles cases like "example.com { route /path { file" where nom fails expecting more content
     let full_trimmed = full_input.trim();
@@ -361,7 +556,7 @@ fn suggest_fix_for_content_with_full_context(full_input: &str, error_input: &str
 
         match last_token {
             "rate_limit" => {
-                return "Rate limit middleware requires a number. Example: 'rate_limit 10'."
+                return "Rate limit middleware requires a number. Example: 'rate_limit 10' or 'rate_limit 10/1m'."
                     .to_string();
             }
             "cache" => {
@@ -428,17 +623,19 @@ fn suggest_fix_for_content_with_full_context(full_input: &str, error_input: &str
                     if i + 1 >= last_few_tokens.len()
                         || !last_few_tokens[i + 1].chars().all(|c| c.is_ascii_digit())
                     {
-                        return "Rate limit middleware requires a number. Example: 'rate_limit 10'.".to_string();
+                        return "Rate limit middleware requires a number. Example: 'rate_limit 10' or 'rate_limit 10/1m'.".to_string();
                     }
                 }
                 "cache" => {
                     // Check if it's followed by a duration
-                    if i + 1 >= last_few_tokens.len()
-                        || !last_few_tokens[i + 1].contains('m')
-                            && !last_few_tokens[i + 1].contains('s')
-                    {
+                    let Some(duration) = last_few_tokens.get(i + 1) else {
                         return "Cache middleware requires a duration. Example: 'cache 5m'."
                             .to_string();
+                    };
+                    if parse_duration(duration).is_err() {
+                        return format!(
+                            "Cache middleware requires a duration, got '{duration}'. Example: 'cache 5m'."
+                        );
                     }
                 }
                 "header" => {
@@ -489,7 +686,7 @@ fn suggest_fix_for_content_with_full_context(full_input: &str, error_input: &str
 
     // Check for incomplete middleware at the end - also using multiline-aware approach
     if error_input.starts_with("rate_limit") && ends_with_pattern(full_trimmed, &["rate_limit"]) {
-        return "Rate limit middleware requires a number. Example: 'rate_limit 10'.".to_string();
+        return "Rate limit middleware requires a number. Example: 'rate_limit 10' or 'rate_limit 10/1m'.".to_string();
     }
 
     if error_input.starts_with("cache") && ends_with_pattern(full_trimmed, &["cache"]) {
@@ -592,7 +789,7 @@ fn suggest_fix_for_content_with_full_context(full_input: &str, error_input: &str
     if before_error.contains("route ") && before_error.contains("}") {
         // We have a complete handler, check for middleware issues
         if before_error.ends_with("rate_limit") || trimmed_error.starts_with("rate_limit") {
-            return "Rate limit middleware requires a number. Example: 'rate_limit 10'."
+            return "Rate limit middleware requires a number. Example: 'rate_limit 10' or 'rate_limit 10/1m'."
                 .to_string();
         }
         if before_error.ends_with("cache") || trimmed_error.starts_with("cache") {
@@ -642,6 +839,7 @@ fn suggest_fix_for_content_with_full_context(full_input: &str, error_input: &str
             word if !word.is_empty()
                 && ![
                     "gzip",
+                    "compress",
                     "cors",
                     "log",
                     "rate_limit",
@@ -651,7 +849,10 @@ fn suggest_fix_for_content_with_full_context(full_input: &str, error_input: &str
                     "file",
                     "proxy",
                     "respond",
+                    "respond_echo",
                     "redirect",
+                    "rewrite",
+                    "try_files",
                     "dir",
                     "browse",
                     "upstreams", // Add upstreams to valid keywords to prevent false unknown handler error
@@ -660,7 +861,7 @@ fn suggest_fix_for_content_with_full_context(full_input: &str, error_input: &str
                 ]
                 .contains(&word) =>
             {
-                return format!("Unknown handler or middleware '{}'. Valid handlers: file, proxy, respond, redirect, dir, browse. Valid middleware: gzip, cors, log, rate_limit, auth, cache, header.", word);
+                return format!("Unknown handler or middleware '{}'. Valid handlers: file, proxy, respond, respond_echo, redirect, rewrite, try_files, dir, browse. Valid middleware: gzip, compress, cors, log, rate_limit, auth, cache, header.", word);
             }
             _ => {}
         }
@@ -670,7 +871,7 @@ fn suggest_fix_for_content_with_full_context(full_input: &str, error_input: &str
     if in_route {
         match *first_error_word {
             "rate_limit" if error_words.len() == 1 => {
-                return "Rate limit middleware requires a number. Example: 'rate_limit 10'."
+                return "Rate limit middleware requires a number. Example: 'rate_limit 10' or 'rate_limit 10/1m'."
                     .to_string();
             }
             "auth" if error_words.len() < 3 => {
@@ -786,10 +987,14 @@ fn suggest_fix_for_content(error_input: &str) -> String {
                 "file",
                 "proxy",
                 "respond",
+                "respond_echo",
                 "redirect",
+                "rewrite",
+                "try_files",
                 "dir",
                 "browse",
                 "gzip",
+                "compress",
                 "cors",
                 "log",
                 "rate_limit",
@@ -801,13 +1006,13 @@ fn suggest_fix_for_content(error_input: &str) -> String {
                 && first_word.len() > 2
                 && first_word.chars().all(|c| c.is_alphabetic() || c == '_')
             {
-                return format!("Unknown handler or middleware '{}'. Valid handlers: file, proxy, respond, redirect, dir, browse. Valid middleware: gzip, cors, log, rate_limit, auth, cache, header.", first_word);
+                return format!("Unknown handler or middleware '{}'. Valid handlers: file, proxy, respond, respond_echo, redirect, rewrite, try_files, dir, browse. Valid middleware: gzip, compress, cors, log, rate_limit, auth, cache, header.", first_word);
             }
         }
 
         // Middleware-specific errors
         if inside_braces.contains("rate_limit") && inside_braces.trim().ends_with("rate_limit") {
-            return "Rate limit middleware requires a number. Example: 'rate_limit 10'."
+            return "Rate limit middleware requires a number. Example: 'rate_limit 10' or 'rate_limit 10/1m'."
                 .to_string();
         }
         if inside_braces.contains("auth") && inside_braces.split_whitespace().count() < 3 {
@@ -824,7 +1029,7 @@ fn suggest_fix_for_content(error_input: &str) -> String {
         if inside_braces.split_whitespace().count() > 0 {
             let first_word = inside_braces.split_whitespace().next().unwrap_or("");
             if !first_word.is_empty() && !first_word.starts_with('#') {
-                return "Route block must start with a handler (file, proxy, respond, redirect, dir, browse) followed by optional middleware.".to_string();
+                return "Route block must start with a handler (file, proxy, respond, respond_echo, redirect, rewrite, try_files, dir, browse) followed by optional middleware.".to_string();
             }
         }
     }
@@ -879,36 +1084,310 @@ fn parse_comment(input: &str) -> IResult<&str, ()> {
     Ok((input, ()))
 }
 
-// Parses a domain like "example.com { ... }"
-fn parse_virtual_host(input: &str) -> IResult<&str, types::VirtualHost> {
-    let (input, _) = multispace0(input)?;
-    let (input, domain) = take_while1(|c: char| !c.is_whitespace() && c != '{')(input)?;
+// Parses a domain like "example.com { ... }", or several domains sharing the block like
+// "example.com www.example.com { ... }", one virtual host per domain
+fn parse_virtual_host(input: &str) -> IResult<&str, Vec<types::VirtualHost>> {
     let (input, _) = multispace0(input)?;
+    let (input, domains) = many1(terminated(
+        map_res(
+            verify(
+                take_while1(|c: char| !c.is_whitespace() && c != '{'),
+                |domain: &str| !domain.starts_with('#'),
+            ),
+            str::parse::<types::Domain>,
+        ),
+        multispace0,
+    ))(input)?;
 
-    let (input, routes) = delimited(
+    let (input, entries) = delimited(
         char('{'),
         many0(alt((
-            map(parse_route, Some),       // Parses routes as Some(Route)
+            map(parse_route, |route| route.map(VirtualHostEntry::Route)),
+            map(parse_matcher, |matcher| {
+                Some(VirtualHostEntry::Matcher(matcher))
+            }),
+            map(parse_virtual_host_option, Some),
             map(parse_comment, |_| None), // Ignores comments, returning None
         ))),
-        char('}'),
+        preceded(multispace0, char('}')),
     )(input)?;
 
     // Allow comments before virtual host ending
     let (input, _) = many0(parse_comment)(input)?;
 
-    // Use filter_map to remove None values and unwrap Some(Route)
-    let routes: Vec<types::Route> = routes.into_iter().flatten().flatten().collect();
+    let mut virtual_host = types::VirtualHost {
+        domain: domains[0].clone(),
+        routes: vec![],
+        error_format: None,
+        trusted_proxies: vec![],
+        forwarded_header: types::ForwardedHeader::default(),
+        allowed_methods: None,
+        tls: None,
+        matchers: vec![],
+        error_pages: vec![],
+        csp: None,
+        rate_limit: None,
+        merge_slashes: Default::default(),
+    };
+
+    // Use filter_map to remove None values and unwrap Some(entry)
+    for entry in entries.into_iter().flatten() {
+        match entry {
+            VirtualHostEntry::Route(route) => virtual_host.routes.push(route),
+            VirtualHostEntry::ErrorFormat(format) => virtual_host.error_format = Some(format),
+            VirtualHostEntry::TrustedProxies(proxies) => {
+                virtual_host.trusted_proxies.extend(proxies)
+            }
+            VirtualHostEntry::ForwardedHeader(mode) => virtual_host.forwarded_header = mode,
+            VirtualHostEntry::AllowedMethods(methods) => {
+                virtual_host.allowed_methods = Some(methods)
+            }
+            VirtualHostEntry::Tls(tls) => virtual_host.tls = Some(tls),
+            VirtualHostEntry::Matcher(matcher) => virtual_host.matchers.push(matcher),
+            VirtualHostEntry::ErrorPage(page) => {
+                // a later error_page of the same status wins
+                virtual_host.error_pages.retain(|p| p.status != page.status);
+                virtual_host.error_pages.push(page)
+            }
+            VirtualHostEntry::Csp(csp) => virtual_host.csp = Some(csp),
+            VirtualHostEntry::RateLimit(rate) => virtual_host.rate_limit = Some(rate),
+            VirtualHostEntry::MergeSlashes(mode) => virtual_host.merge_slashes = mode,
+        }
+    }
+
+    let virtual_hosts = domains
+        .into_iter()
+        .map(|domain| types::VirtualHost {
+            domain,
+            ..virtual_host.clone()
+        })
+        .collect();
+    Ok((input, virtual_hosts))
+}
+
+// Parses a named matcher like "@api { path /api/* header X-Internal true }"
+fn parse_matcher(input: &str) -> IResult<&str, types::Matcher> {
+    let (input, _) = multispace0(input)?;
+    let (input, name) = preceded(
+        char('@'),
+        take_while1(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+    )(input)?;
+    let (input, _) = multispace0(input)?;
+    let (input, conditions) = delimited(
+        char('{'),
+        many1(alt((
+            map(parse_matcher_condition, Some),
+            map(parse_comment, |_| None),
+        ))),
+        preceded(multispace0, char('}')),
+    )(input)?;
+    let (input, _) = multispace0(input)?;
+    Ok((
+        input,
+        types::Matcher {
+            name: name.to_string(),
+            conditions: conditions.into_iter().flatten().collect(),
+        },
+    ))
+}
+
+// Parses "path <path> [<path>...]" or "header <name> <value>", paths start with a slash
+fn parse_matcher_condition(input: &str) -> IResult<&str, types::MatcherCondition> {
+    let (input, _) = multispace0(input)?;
+    alt((
+        map(
+            preceded(
+                tag("path"),
+                many1(preceded(
+                    space1,
+                    map(
+                        verify(
+                            take_while1(|c: char| !c.is_whitespace() && c != '}'),
+                            |path: &str| path.starts_with('/'),
+                        ),
+                        str::to_string,
+                    ),
+                )),
+            ),
+            types::MatcherCondition::Path,
+        ),
+        map(
+            tuple((
+                preceded(
+                    tuple((tag("header"), space1)),
+                    verify(
+                        take_while1(|c: char| !c.is_whitespace() && c != '}'),
+                        |name: &str| http::HeaderName::from_bytes(name.as_bytes()).is_ok(),
+                    ),
+                ),
+                preceded(
+                    space1,
+                    alt((
+                        string_literal,
+                        map(
+                            take_while1(|c: char| !c.is_whitespace() && c != '}'),
+                            str::to_string,
+                        ),
+                    )),
+                ),
+            )),
+            |(name, value)| types::MatcherCondition::Header {
+                name: name.to_string(),
+                value,
+            },
+        ),
+    ))(input)
+}
+
+// Parses virtual host options like "error_format json" or "trusted_proxies 10.0.0.0/8"
+fn parse_virtual_host_option(input: &str) -> IResult<&str, VirtualHostEntry> {
+    alt((
+        map(parse_error_format, VirtualHostEntry::ErrorFormat),
+        map(parse_trusted_proxies, VirtualHostEntry::TrustedProxies),
+        map(parse_forwarded_header, VirtualHostEntry::ForwardedHeader),
+        map(
+            parse_methods("allowed_methods"),
+            VirtualHostEntry::AllowedMethods,
+        ),
+        map(parse_tls, VirtualHostEntry::Tls),
+        map(parse_error_page, VirtualHostEntry::ErrorPage),
+        map(parse_csp, VirtualHostEntry::Csp),
+        map(
+            preceded(multispace0, parse_rate),
+            VirtualHostEntry::RateLimit,
+        ),
+        map(parse_merge_slashes, VirtualHostEntry::MergeSlashes),
+    ))(input)
+}
 
+// Parses "csp \"<policy>\"" or "csp_report_only \"<policy>\""
+fn parse_csp(input: &str) -> IResult<&str, types::ContentSecurityPolicy> {
+    let (input, _) = multispace0(input)?;
+    let (input, report_only) = alt((
+        map(tag("csp_report_only"), |_| true),
+        map(tag("csp"), |_| false),
+    ))(input)?;
+    let (input, policy) = preceded(space1, string_literal)(input)?;
+    let (input, _) = multispace0(input)?;
     Ok((
         input,
-        types::VirtualHost {
-            domain: domain.to_string(),
-            routes,
+        types::ContentSecurityPolicy {
+            policy,
+            report_only,
         },
     ))
 }
 
+// Parses "error_page <status> <path>", the status being an error status
+fn parse_error_page(input: &str) -> IResult<&str, types::ErrorDocument> {
+    let (input, _) = multispace0(input)?;
+    let (input, _) = tag("error_page")(input)?;
+    let (input, _) = space1(input)?;
+    let (input, status) = verify(parse_u16, |status| (400..=599).contains(status))(input)?;
+    let (input, file) = parse_path(input)?;
+    let (input, _) = multispace0(input)?;
+    Ok((input, types::ErrorDocument { status, file }))
+}
+
+// Parses "tls <cert_path> <key_path>" or the block "tls { cert <cert_path> key <key_path> }"
+fn parse_tls(input: &str) -> IResult<&str, types::TlsConfig> {
+    let (input, _) = multispace0(input)?;
+    let (input, _) = tag("tls")(input)?;
+    let (input, (cert, key)) = alt((
+        delimited(
+            tuple((multispace0, char('{'))),
+            permutation((
+                preceded(tuple((multispace0, tag("cert"))), parse_path),
+                preceded(tuple((multispace0, tag("key"))), parse_path),
+            )),
+            tuple((multispace0, char('}'))),
+        ),
+        verify(
+            tuple((parse_path, parse_path)),
+            |(cert, _): &(String, String)| !cert.starts_with('{'),
+        ),
+    ))(input)?;
+    let (input, _) = multispace0(input)?;
+    Ok((input, types::TlsConfig { cert, key }))
+}
+
+// Parses a path after spaces, it ends before a whitespace or a closing brace
+fn parse_path(input: &str) -> IResult<&str, String> {
+    let (input, _) = space1(input)?;
+    let (input, path) = take_while1(|c: char| !c.is_whitespace() && c != '}')(input)?;
+    Ok((input, path.to_string()))
+}
+
+// Parses "<name> <METHOD> [<METHOD>...]" like "allowed_methods GET HEAD"
+fn parse_methods<'a>(
+    name: &'static str,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<http::Method>> {
+    move |input: &'a str| {
+        let (input, _) = multispace0(input)?;
+        let (input, _) = tag(name)(input)?;
+        let (input, methods) = many1(preceded(
+            space1,
+            map_res(
+                take_while1(|c: char| c.is_ascii_uppercase()),
+                str::parse::<http::Method>,
+            ),
+        ))(input)?;
+        let (input, _) = multispace0(input)?;
+        Ok((input, methods))
+    }
+}
+
+// Parses "trusted_proxies <ip or cidr> [<ip or cidr>...]"
+fn parse_trusted_proxies(input: &str) -> IResult<&str, Vec<ipnet::IpNet>> {
+    let (input, _) = multispace0(input)?;
+    let (input, _) = tag("trusted_proxies")(input)?;
+    let (input, proxies) = many1(preceded(
+        space1,
+        map_res(
+            take_while1(|c: char| !c.is_whitespace() && c != '}'),
+            parse_ip_net,
+        ),
+    ))(input)?;
+    let (input, _) = multispace0(input)?;
+    Ok((input, proxies))
+}
+
+// Parses an ip range like "10.0.0.0/8", a bare address is a range of a single address
+fn parse_ip_net(value: &str) -> Result<ipnet::IpNet, ipnet::AddrParseError> {
+    match value.parse::<std::net::IpAddr>() {
+        Ok(addr) => Ok(ipnet::IpNet::from(addr)),
+        Err(_) => value.parse::<ipnet::IpNet>(),
+    }
+}
+
+// Parses "forwarded_header ignore|trust|prefer"
+fn parse_forwarded_header(input: &str) -> IResult<&str, types::ForwardedHeader> {
+    let (input, _) = multispace0(input)?;
+    let (input, _) = tag("forwarded_header")(input)?;
+    let (input, _) = space1(input)?;
+    let (input, mode) = alt((
+        map(tag("ignore"), |_| types::ForwardedHeader::Ignore),
+        map(tag("trust"), |_| types::ForwardedHeader::Trust),
+        map(tag("prefer"), |_| types::ForwardedHeader::Prefer),
+    ))(input)?;
+    let (input, _) = multispace0(input)?;
+    Ok((input, mode))
+}
+
+// Parses "merge_slashes <on|off|redirect>"
+fn parse_merge_slashes(input: &str) -> IResult<&str, types::MergeSlashes> {
+    let (input, _) = multispace0(input)?;
+    let (input, _) = tag("merge_slashes")(input)?;
+    let (input, _) = space1(input)?;
+    let (input, mode) = alt((
+        map(tag("on"), |_| types::MergeSlashes::On),
+        map(tag("off"), |_| types::MergeSlashes::Off),
+        map(tag("redirect"), |_| types::MergeSlashes::Redirect),
+    ))(input)?;
+    let (input, _) = multispace0(input)?;
+    Ok((input, mode))
+}
+
 // Parses a route like "route /path { ... }"
 fn parse_route(input: &str) -> IResult<&str, Option<types::Route>> {
     let (input, _) = multispace0(input)?;
@@ -922,8 +1401,7 @@ fn parse_route(input: &str) -> IResult<&str, Option<types::Route>> {
     let (input, path) = take_while1(|c: char| !c.is_whitespace() && c != '{')(input)?;
     let (input, _) = multispace0(input)?;
 
-    let (input, (handler, middlewares)) =
-        delimited(char('{'), parse_route_contents, char('}'))(input)?;
+    let (input, contents) = delimited(char('{'), parse_route_contents, char('}'))(input)?;
 
     let (input, _) = multispace0(input)?;
 
@@ -936,44 +1414,150 @@ fn parse_route(input: &str) -> IResult<&str, Option<types::Route>> {
         input,
         Some(types::Route {
             path: path.to_string(),
-            handler,
-            middlewares,
+            handler: contents.handler,
+            middlewares: contents.middlewares,
+            error_format: contents.error_format,
+            sniff_extensionless: contents.sniff_extensionless,
+            max_ranges: contents.max_ranges,
+            transcode: contents.transcode,
+            negotiate_language: contents.negotiate_language,
+            methods: contents.methods,
         }),
     ))
 }
 
 // Parses handler + middleware settings inside a route block
-fn parse_route_contents(input: &str) -> IResult<&str, (types::Handler, Vec<types::Middleware>)> {
+fn parse_route_contents(input: &str) -> IResult<&str, RouteContents> {
     let (input, _) = multispace0(input)?;
 
-    // Allow comments before handler
-    let (input, _) = many0(parse_comment)(input)?;
+    // Allow comments and route options before handler
+    let (input, options) = many0(alt((
+        map(parse_comment, |_| None),
+        map(parse_route_option, Some),
+    )))(input)?;
 
     let (input, handler) = parse_handler(input)?;
     let (input, _) = multispace0(input)?;
 
     // Allow comments before middlewares
-    let (input, middlewares) = many0(alt((
+    let (input, entries) = many0(alt((
         map(parse_comment, |_| None), // Allow comments inside route block
-        map(parse_middleware, Some),  // Parse middleware
+        map(parse_route_option, Some),
+        map(parse_middleware, |middleware| {
+            Some(RouteEntry::Middleware(middleware))
+        }),
     )))(input)?;
 
     let (input, _) = multispace0(input)?;
 
+    let mut contents = RouteContents {
+        handler,
+        middlewares: vec![],
+        error_format: None,
+        sniff_extensionless: false,
+        max_ranges: None,
+        transcode: false,
+        negotiate_language: false,
+        methods: vec![],
+    };
+
     // Remove None values (from comments)
-    let middlewares = middlewares.into_iter().flatten().collect();
+    for entry in options.into_iter().chain(entries).flatten() {
+        match entry {
+            RouteEntry::Middleware(middleware) => contents.middlewares.push(middleware),
+            RouteEntry::ErrorFormat(format) => contents.error_format = Some(format),
+            RouteEntry::SniffExtensionless(enabled) => contents.sniff_extensionless = enabled,
+            RouteEntry::MaxRanges(max_ranges) => contents.max_ranges = Some(max_ranges),
+            RouteEntry::Transcode(enabled) => contents.transcode = enabled,
+            RouteEntry::NegotiateLanguage(enabled) => contents.negotiate_language = enabled,
+            RouteEntry::Methods(methods) => contents.methods = methods,
+        }
+    }
 
-    Ok((input, (handler, middlewares)))
+    Ok((input, contents))
 }
 
-// Parses different handlers (file, proxy, dir, browse)
+// Parses route options like "error_format json", "sniff_extensionless on" or "method GET POST"
+fn parse_route_option(input: &str) -> IResult<&str, RouteEntry> {
+    alt((
+        map(parse_error_format, RouteEntry::ErrorFormat),
+        map(
+            parse_switch("sniff_extensionless"),
+            RouteEntry::SniffExtensionless,
+        ),
+        map(parse_max_ranges, RouteEntry::MaxRanges),
+        map(parse_switch("transcode"), RouteEntry::Transcode),
+        map(
+            parse_switch("negotiate_language"),
+            RouteEntry::NegotiateLanguage,
+        ),
+        map(parse_methods("method"), RouteEntry::Methods),
+    ))(input)
+}
+
+// Parses "max_ranges <count>", the count must be at least 1
+fn parse_max_ranges(input: &str) -> IResult<&str, usize> {
+    let (input, _) = multispace0(input)?;
+    let (input, _) = tag("max_ranges")(input)?;
+    let (input, _) = space1(input)?;
+    let (input, max_ranges) = verify(map_res(digit1, str::parse::<usize>), |max_ranges| {
+        *max_ranges > 0
+    })(input)?;
+    let (input, _) = multispace0(input)?;
+    Ok((input, max_ranges))
+}
+
+// Parses "<name> on|off"
+fn parse_switch<'a>(name: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, bool> {
+    move |input: &'a str| {
+        let (input, _) = multispace0(input)?;
+        let (input, _) = tag(name)(input)?;
+        let (input, _) = space1(input)?;
+        let (input, enabled) = alt((map(tag("on"), |_| true), map(tag("off"), |_| false)))(input)?;
+        let (input, _) = multispace0(input)?;
+        Ok((input, enabled))
+    }
+}
+
+// Parses "error_format json|html|auto"
+fn parse_error_format(input: &str) -> IResult<&str, types::ErrorFormat> {
+    let (input, _) = multispace0(input)?;
+    let (input, _) = tag("error_format")(input)?;
+    let (input, _) = space1(input)?;
+    let (input, format) = alt((
+        map(tag("json"), |_| types::ErrorFormat::Json),
+        map(tag("html"), |_| types::ErrorFormat::Html),
+        map(tag("auto"), |_| types::ErrorFormat::Auto),
+    ))(input)?;
+    let (input, _) = multispace0(input)?;
+    Ok((input, format))
+}
+
+// Parses different handlers (file, proxy, dir, browse, respond, respond_echo, redirect, rewrite,
+// try_files)
 fn parse_handler(input: &str) -> IResult<&str, types::Handler> {
     let (input, _) = multispace0(input)?;
     alt((
         map(preceded(tag("file"), parse_value), types::Handler::File),
         parse_proxy_handler,
-        map(preceded(tag("dir"), parse_value), types::Handler::Dir),
+        map(preceded(tag("dir"), parse_roots), types::Handler::Dir),
         map(preceded(tag("browse"), parse_value), types::Handler::Browse),
+        // before `respond`, which is a prefix of it
+        map(
+            preceded(
+                tag("respond_echo"),
+                verify(parse_value, |param: &str| {
+                    param
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+                }),
+            ),
+            types::Handler::RespondEcho,
+        ),
+        map(
+            preceded(tag("respond"), parse_respond_file_handler_args),
+            |(file, status)| types::Handler::RespondFile { file, status },
+        ),
         map(
             preceded(tag("respond"), parse_respond_handler_args),
             |(status, body)| types::Handler::Respond { status, body },
@@ -982,6 +1566,22 @@ fn parse_handler(input: &str) -> IResult<&str, types::Handler> {
             preceded(tag("redirect"), parse_redirect_handler_args),
             |(status_code, path)| types::Handler::Redirect { status_code, path },
         ),
+        map(
+            preceded(
+                tag("rewrite"),
+                verify(parse_value, |target: &str| target.starts_with('/')),
+            ),
+            types::Handler::Rewrite,
+        ),
+        map(
+            preceded(
+                tag("try_files"),
+                many1(verify(parse_value, |path: &str| {
+                    !path.starts_with('}') && !path.starts_with('#')
+                })),
+            ),
+            types::Handler::TryFiles,
+        ),
     ))(input)
 }
 
@@ -1006,52 +1606,56 @@ fn parse_proxy_simple(input: &str) -> IResult<&str, types::Handler> {
                 upstream,
             ))),
         )),
-        Err(_) => Err(nom::Err::Error(nom::error::Error::new(
-            input,
-            ErrorKind::Alt,
-        ))),
+        Err(_) => Err(nom::Err::Error(Error::new(input, ErrorKind::Alt))),
     }
 }
 
 // Parses the new proxy block format
 fn parse_proxy_block(input: &str) -> IResult<&str, types::Handler> {
-    let (input, (upstreams, lb_policy, request_timeout, connection_timeout)) =
+    let (input, (upstreams, fields)) =
         delimited(char('{'), parse_proxy_block_contents, char('}'))(input)?;
 
-    let load_balancer = match lb_policy.as_deref() {
-        Some("round_robin") => {
-            if upstreams.len() == 1 {
-                // Single upstream with round_robin policy still uses NoBalancer
-                types::LoadBalancer::NoBalancer(upstreams.into_iter().next().unwrap())
-            } else {
-                types::LoadBalancer::RoundRobin(upstreams)
-            }
-        }
-        None | Some("") => {
-            // Default: no load balancer specified or empty value
-            if upstreams.len() == 1 {
-                types::LoadBalancer::NoBalancer(upstreams.into_iter().next().unwrap())
-            } else {
-                // Multiple upstreams without lb_policy defaults to round_robin
-                types::LoadBalancer::RoundRobin(upstreams)
-            }
-        }
-        Some(_policy) => {
-            return Err(nom::Err::Error(nom::error::Error::new(
-                input,
-                ErrorKind::Alt,
-            )));
+    let load_balancer = match fields.lb_policy.as_deref() {
+        Some("round_robin") | None | Some("") => {
+            // Multiple upstreams without lb_policy defaults to round_robin
+            default_load_balancer(upstreams)
         }
+        Some("least_conn") => types::LoadBalancer::LeastConnections(upstreams),
+        Some("random") => types::LoadBalancer::Random(upstreams),
+        Some("ip_hash") => types::LoadBalancer::IpHash(upstreams),
+        Some(policy) => unreachable!("lb_policy {policy} is checked when parsed"),
     };
 
-    Ok((
-        input,
-        types::Handler::Proxy(types::ProxyConfig::with_timeouts(
-            load_balancer,
-            request_timeout,
-            connection_timeout,
-        )),
-    ))
+    let mut proxy_config = types::ProxyConfig::with_timeouts(
+        load_balancer,
+        fields.request_timeout,
+        fields.connection_timeout,
+    );
+    proxy_config.resolve_ttl = fields.resolve_ttl;
+    proxy_config.dns_stale_max = fields.dns_stale_max;
+    proxy_config.canary = fields.canary;
+    proxy_config.max_fails = fields.max_fails;
+    proxy_config.fail_timeout = fields.fail_timeout;
+    proxy_config.circuit_breaker = fields.circuit_breaker;
+    proxy_config.forwarded = fields.forwarded.unwrap_or(true);
+    proxy_config.host_header = fields.host_header.unwrap_or_default();
+    proxy_config.strip_request_headers = fields.strip_request_headers.unwrap_or_default();
+    proxy_config.header_up = fields.header_up;
+    proxy_config.header_down = fields.header_down;
+
+    Ok((input, types::Handler::Proxy(proxy_config)))
+}
+
+// A single upstream uses NoBalancer, multiple upstreams are balanced round robin, weighted when
+// one of them has a weight
+fn default_load_balancer(upstreams: Vec<Upstream>) -> types::LoadBalancer {
+    if upstreams.len() == 1 {
+        types::LoadBalancer::NoBalancer(upstreams.into_iter().next().unwrap())
+    } else if upstreams.iter().any(|upstream| upstream.weight() != 1) {
+        types::LoadBalancer::WeightedRoundRobin(upstreams)
+    } else {
+        types::LoadBalancer::RoundRobin(upstreams)
+    }
 }
 
 // Parses the contents inside the proxy block
@@ -1070,22 +1674,16 @@ fn parse_proxy_block_contents(input: &str) -> ProxyBlockContentsResult<'_> {
     let (input, upstreams) = parse_upstream_addresses(input)?;
     let (input, _) = multispace0(input)?;
 
-    // Parse optional fields in any order (lb_policy, request_timeout, connection_timeout)
-    let (input, (lb_policy, request_timeout, connection_timeout)) =
-        parse_proxy_optional_fields(input)?;
+    // Parse optional fields in any order (lb_policy, request_timeout, connection_timeout, resolve_ttl, dns_stale_max, canary, max_fails, fail_timeout, circuit_breaker, forwarded, host_header, preserve_host, strip_request_headers, header_up, header_down)
+    let (input, fields) = parse_proxy_optional_fields(input)?;
 
-    Ok((
-        input,
-        (upstreams, lb_policy, request_timeout, connection_timeout),
-    ))
+    Ok((input, (upstreams, fields)))
 }
 
-// Parse optional fields like lb_policy, request_timeout, connection_timeout in any order
+// Parse optional fields like lb_policy, request_timeout, connection_timeout, resolve_ttl, dns_stale_max, canary, max_fails, fail_timeout, circuit_breaker, forwarded, host_header, preserve_host, strip_request_headers, header_up, header_down in any order
 fn parse_proxy_optional_fields(input: &str) -> ProxyOptionalFieldsResult<'_> {
     let mut remaining = input;
-    let mut lb_policy = None;
-    let mut request_timeout = None;
-    let mut connection_timeout = None;
+    let mut fields = ProxyOptionalFields::default();
 
     loop {
         // Skip whitespace and comments
@@ -1100,310 +1698,1599 @@ fn parse_proxy_optional_fields(input: &str) -> ProxyOptionalFieldsResult<'_> {
         }
 
         // Try to parse lb_policy
-        if remaining.starts_with("lb_policy") && lb_policy.is_none() {
+        if remaining.starts_with("lb_policy") && fields.lb_policy.is_none() {
             let (next_input, _) = tag("lb_policy")(remaining)?;
             let (next_input, policy_opt) = opt(preceded(
                 multispace1,
                 take_while1(|c: char| !c.is_whitespace() && c != '}' && c != '\n'),
             ))(next_input)?;
-            lb_policy = policy_opt.map(|s| s.to_string());
+            if let Some(policy) = policy_opt.filter(|policy| !LB_POLICIES.contains(policy)) {
+                // fail on the policy itself, so the error points at it rather than the block
+                let at = &remaining[remaining.len() - next_input.len() - policy.len()..];
+                return Err(nom::Err::Failure(Error::new(at, ErrorKind::Verify)));
+            }
+            fields.lb_policy = policy_opt.map(|s| s.to_string());
             remaining = next_input;
             continue;
         }
 
-        // Try to parse request_timeout
-        if remaining.starts_with("request_timeout") && request_timeout.is_none() {
+        // Try to parse request_timeout, a bare number is in seconds
+        if remaining.starts_with("request_timeout") && fields.request_timeout.is_none() {
             let (next_input, _) = tag("request_timeout")(remaining)?;
             let (next_input, _) = multispace1(next_input)?;
-            let (next_input, timeout_str) = digit1(next_input)?;
-            request_timeout = timeout_str.parse::<u64>().ok();
+            let (next_input, timeout) = parse_duration(next_input)?;
+            fields.request_timeout = Some(timeout);
             remaining = next_input;
             continue;
         }
 
-        // Try to parse connection_timeout
-        if remaining.starts_with("connection_timeout") && connection_timeout.is_none() {
+        // Try to parse connection_timeout, a bare number is in seconds
+        if remaining.starts_with("connection_timeout") && fields.connection_timeout.is_none() {
             let (next_input, _) = tag("connection_timeout")(remaining)?;
             let (next_input, _) = multispace1(next_input)?;
-            let (next_input, timeout_str) = digit1(next_input)?;
-            connection_timeout = timeout_str.parse::<u64>().ok();
+            let (next_input, timeout) = parse_duration(next_input)?;
+            fields.connection_timeout = Some(timeout);
             remaining = next_input;
             continue;
         }
 
-        // If we get here, we couldn't parse any known field, so break
-        break;
-    }
-
-    Ok((remaining, (lb_policy, request_timeout, connection_timeout)))
-}
-
-// Parse upstream addresses one by one until we hit lb_policy or end
-fn parse_upstream_addresses(input: &str) -> IResult<&str, Vec<Upstream>> {
-    let mut upstreams = Vec::new();
-    let mut remaining = input;
-
-    loop {
-        // Skip whitespace and comments
-        let (next_input, _) = multispace0(remaining)?;
-        let (next_input, _) = many0(parse_comment)(next_input)?;
-        let (next_input, _) = multispace0(next_input)?;
-        remaining = next_input;
-
-        // Check if we've hit keywords or } or end
-        if remaining.starts_with("lb_policy")
-            || remaining.starts_with("request_timeout")
-            || remaining.starts_with("connection_timeout")
-            || remaining.starts_with("}")
-            || remaining.is_empty()
-        {
-            break;
+        // Try to parse resolve_ttl
+        if remaining.starts_with("resolve_ttl") && fields.resolve_ttl.is_none() {
+            let (next_input, _) = tag("resolve_ttl")(remaining)?;
+            let (next_input, _) = multispace1(next_input)?;
+            let (next_input, ttl) = parse_duration(next_input)?;
+            fields.resolve_ttl = Some(ttl);
+            remaining = next_input;
+            continue;
         }
 
-        // Parse the next upstream address
-        let (next_input, addr) = take_while1(|c: char| !c.is_whitespace())(remaining)?;
+        // Try to parse dns_stale_max
+        if remaining.starts_with("dns_stale_max") && fields.dns_stale_max.is_none() {
+            let (next_input, _) = tag("dns_stale_max")(remaining)?;
+            let (next_input, _) = multispace1(next_input)?;
+            let (next_input, stale_max) = parse_duration(next_input)?;
+            fields.dns_stale_max = Some(stale_max);
+            remaining = next_input;
+            continue;
+        }
 
-        // Make sure it's not a keyword
-        if addr == "lb_policy" || addr == "request_timeout" || addr == "connection_timeout" {
-            break;
+        // Try to parse canary block
+        if remaining.starts_with("canary") && fields.canary.is_none() {
+            let (next_input, canary) = parse_canary_block(remaining)?;
+            fields.canary = Some(canary);
+            remaining = next_input;
+            continue;
         }
 
-        // Convert to Upstream
-        match Upstream::new(addr.to_string()) {
-            Ok(upstream) => upstreams.push(upstream),
-            Err(_) => {
-                return Err(nom::Err::Error(nom::error::Error::new(
-                    remaining,
-                    ErrorKind::Alt,
+        // Try to parse max_fails, at least one failure is needed to take an upstream out
+        if remaining.starts_with("max_fails") && fields.max_fails.is_none() {
+            let (next_input, _) = tag("max_fails")(remaining)?;
+            let (value, _) = multispace1(next_input)?;
+            let (next_input, max_fails) = map_res(digit1, str::parse::<u32>)(value)?;
+            if max_fails == 0 {
+                // fail on the value itself, so the error points at it rather than the block
+                return Err(nom::Err::Failure(Error::invalid(
+                    value,
+                    "max_fails must be at least 1, the failures in a row taking an upstream out of rotation. Example: 'max_fails 3'.",
                 )));
             }
+            fields.max_fails = Some(max_fails);
+            remaining = next_input;
+            continue;
         }
 
-        remaining = next_input;
-    }
+        // Try to parse fail_timeout
+        if remaining.starts_with("fail_timeout") && fields.fail_timeout.is_none() {
+            let (next_input, _) = tag("fail_timeout")(remaining)?;
+            let (next_input, _) = multispace1(next_input)?;
+            let (next_input, fail_timeout) = parse_duration(next_input)?;
+            fields.fail_timeout = Some(fail_timeout);
+            remaining = next_input;
+            continue;
+        }
 
-    if upstreams.is_empty() {
-        return Err(nom::Err::Error(nom::error::Error::new(
-            input,
-            ErrorKind::Alt,
-        )));
-    }
+        // Try to parse circuit_breaker block
+        if remaining.starts_with("circuit_breaker") && fields.circuit_breaker.is_none() {
+            let (next_input, circuit_breaker) = parse_circuit_breaker_block(remaining)?;
+            fields.circuit_breaker = Some(circuit_breaker);
+            remaining = next_input;
+            continue;
+        }
 
-    Ok((remaining, upstreams))
-}
+        // Try to parse forwarded
+        if remaining.starts_with("forwarded") && fields.forwarded.is_none() {
+            let (next_input, _) = tag("forwarded")(remaining)?;
+            let (next_input, _) = multispace1(next_input)?;
+            let (next_input, forwarded) =
+                alt((map(tag("on"), |_| true), map(tag("off"), |_| false)))(next_input)?;
+            fields.forwarded = Some(forwarded);
+            remaining = next_input;
+            continue;
+        }
 
-// Parses middleware options like "gzip", "cors", "log", "rate_limit 10", "auth admin pass"
-fn parse_middleware(input: &str) -> IResult<&str, types::Middleware> {
-    let (input, _) = multispace0(input)?;
+        // Try to parse host_header, "preserve" or a fixed host
+        if remaining.starts_with("host_header") && fields.host_header.is_none() {
+            let (next_input, _) = tag("host_header")(remaining)?;
+            let (next_input, _) = space1(next_input)?;
+            let (next_input, value) =
+                take_while1(|c: char| !c.is_whitespace() && c != '}')(next_input)?;
+            fields.host_header = Some(match value {
+                "preserve" => types::HostHeader::Preserve,
+                host => types::HostHeader::Value(host.to_string()),
+            });
+            remaining = next_input;
+            continue;
+        }
 
-    alt((
-        map(tag("gzip"), |_| types::Middleware::Gzip),
-        map(tag("cors"), |_| types::Middleware::Cors),
-        map(tag("log"), |_| types::Middleware::Log),
-        parse_rate_limit,
-        parse_auth,
-        parse_cache,
-        parse_header,
-    ))(input)
-}
+        // Try to parse preserve_host, "on" sends the Host of the client like "host_header preserve"
+        if remaining.starts_with("preserve_host") && fields.host_header.is_none() {
+            let (next_input, _) = tag("preserve_host")(remaining)?;
+            let (next_input, _) = space1(next_input)?;
+            let (next_input, host_header) = alt((
+                map(tag("on"), |_| types::HostHeader::Preserve),
+                map(tag("off"), |_| types::HostHeader::Upstream),
+            ))(next_input)?;
+            fields.host_header = Some(host_header);
+            remaining = next_input;
+            continue;
+        }
 
-// Parses "rate_limit <N>"
-fn parse_rate_limit(input: &str) -> IResult<&str, types::Middleware> {
-    let (input, _) = tag("rate_limit")(input)?;
-    let (input, _) = space1(input)?;
-    let (input, num) = take_while1(|c: char| c.is_ascii_digit())(input)?;
-    Ok((input, types::Middleware::RateLimit(num.parse().unwrap())))
-}
+        // Try to parse strip_request_headers, the header names up to the end of the line
+        if remaining.starts_with("strip_request_headers") && fields.strip_request_headers.is_none()
+        {
+            let (next_input, _) = tag("strip_request_headers")(remaining)?;
+            let (next_input, names) = parse_header_names(next_input)?;
+            fields.strip_request_headers = Some(names);
+            remaining = next_input;
+            continue;
+        }
 
-// Parses "auth <username> <password>"
-fn parse_auth(input: &str) -> IResult<&str, types::Middleware> {
-    let (input, _) = tag("auth")(input)?;
-    let (input, _) = space1(input)?;
-    let (input, username) = take_while1(|c: char| !c.is_whitespace())(input)?;
-    let (input, _) = space1(input)?;
-    let (input, password) = take_while1(|c: char| !c.is_whitespace())(input)?;
-    Ok((
-        input,
-        types::Middleware::Auth {
-            username: username.to_string(),
-            password: password.to_string(),
-        },
-    ))
-}
+        // Try to parse header_up and header_down, they may be repeated
+        if remaining.starts_with("header_up") {
+            let (next_input, _) = tag("header_up")(remaining)?;
+            let (next_input, rule) = parse_proxy_header_rule(next_input)?;
+            fields.header_up.push(rule);
+            remaining = next_input;
+            continue;
+        }
+        if remaining.starts_with("header_down") {
+            let (next_input, _) = tag("header_down")(remaining)?;
+            let (next_input, rule) = parse_proxy_header_rule(next_input)?;
+            fields.header_down.push(rule);
+            remaining = next_input;
+            continue;
+        }
 
-// Parses "cache <duration>"
-fn parse_cache(input: &str) -> IResult<&str, types::Middleware> {
-    let (input, _) = tag("cache")(input)?;
-    let (input, _) = space1(input)?;
-    let (input, duration) = take_while1(|c: char| !c.is_whitespace())(input)?;
-    Ok((input, types::Middleware::Cache(duration.to_string())))
+        // If we get here, we couldn't parse any known field, so break
+        break;
+    }
+
+    Ok((remaining, fields))
 }
 
-// Parses "header <key> <value>" or "header <key> <value> <replace_with>" or "header <key>"
-fn parse_header(input: &str) -> IResult<&str, types::Middleware> {
-    let (input, _) = tag("header")(input)?;
+// Parses the rule of "header_up" or "header_down" like the one of the "header" middleware, a value
+// with spaces is quoted like "=Authorization \"Bearer token\""
+fn parse_proxy_header_rule(input: &str) -> IResult<&str, types::Middleware> {
     let (input, _) = space1(input)?;
-
-    // Parse the header operator
-    let (input, operator) = alt((
-        // two operator characters should be parsed first
-        map(tag("~>"), |_| types::HeaderOperator::DeferReplace),
-        map(tag("+"), |_| types::HeaderOperator::Add),
-        map(tag(">"), |_| types::HeaderOperator::DeferSet),
-        map(tag("-"), |_| types::HeaderOperator::Delete),
-        map(tag("?"), |_| types::HeaderOperator::Default),
-        map(tag("="), |_| types::HeaderOperator::Set),
-        map(tag("~"), |_| types::HeaderOperator::Replace),
-    ))(input)?;
-
-    // Parse the header name and value and replace_with if present
-    let (input, (name, value, replace_with)) = tuple((
-        take_while1(|c: char| !c.is_whitespace()),
-        opt(preceded(space1, take_while1(|c: char| !c.is_whitespace()))),
-        opt(preceded(space1, take_while1(|c: char| !c.is_whitespace()))),
-    ))(input)?;
+    let (input, operator) = parse_header_operator(input)?;
+    let (input, name) = take_while1(|c: char| !c.is_whitespace() && c != '}')(input)?;
+    let (input, value) = opt(parse_proxy_header_value)(input)?;
+    let (input, replace_with) = match value {
+        Some(_) => opt(parse_proxy_header_value)(input)?,
+        None => (input, None),
+    };
 
     Ok((
         input,
         types::Middleware::Header {
             operator,
             name: name.to_string(),
-            value: value.map(|s| s.to_string()),
-            replace_with: replace_with.map(|s| s.to_string()),
+            value,
+            replace_with,
         },
     ))
 }
 
-// Parses values like "index.html" or "http://localhost:3000"
-fn parse_value(input: &str) -> IResult<&str, String> {
-    let (input, _) = space1(input)?;
-    let (input, value) = take_while1(|c: char| !c.is_whitespace())(input)?;
-    Ok((input, value.to_string()))
+// Parses a value of "header_up" or "header_down" on the same line, a keyword of the proxy block is
+// not a value
+fn parse_proxy_header_value(input: &str) -> IResult<&str, String> {
+    preceded(
+        space1,
+        alt((
+            string_literal,
+            map(
+                verify(
+                    take_while1(|c: char| !c.is_whitespace() && c != '}'),
+                    |value: &str| !PROXY_BLOCK_KEYWORDS.contains(&value),
+                ),
+                str::to_string,
+            ),
+        )),
+    )(input)
 }
 
-// Parses values like " 200" or " "<h1>Example</h1>" 200" or " "<h1>Example</h1>""
-fn parse_respond_handler_args(input: &str) -> IResult<&str, (Option<u16>, Option<String>)> {
-    let (input, _) = space1(input)?;
+// Parses the header names following a directive on the same line, until a keyword of the proxy
+// block
+fn parse_header_names(input: &str) -> IResult<&str, Vec<String>> {
+    let mut names = vec![];
+    let mut remaining = input;
+    loop {
+        let Ok((next_input, name)) = preceded(
+            space1::<&str, Error<&str>>,
+            take_while1(|c: char| !c.is_whitespace() && c != '}'),
+        )(remaining) else {
+            break;
+        };
+        if PROXY_BLOCK_KEYWORDS.contains(&name) {
+            break;
+        }
+        if http::HeaderName::from_bytes(name.as_bytes()).is_err() {
+            return Err(nom::Err::Error(Error::new(
+                &remaining[remaining.len() - next_input.len() - name.len()..],
+                ErrorKind::Verify,
+            )));
+        }
+        names.push(name.to_string());
+        remaining = next_input;
+    }
+    if names.is_empty() {
+        return Err(nom::Err::Error(Error::new(input, ErrorKind::Many1)));
+    }
+    Ok((remaining, names))
+}
 
-    let (input, result) = alt((
-        map(parse_literal_u16, |(body, status)| {
-            (Some(body), Some(status))
-        }),
-        map(string_literal, |body| (Some(body), None)),
-        map(parse_u16, |status| (None, Some(status))),
-    ))(input)?;
+// Parse upstream addresses one by one until we hit lb_policy or end, an address may be followed by
+// "weight=N"
+fn parse_upstream_addresses(input: &str) -> IResult<&str, Vec<Upstream>> {
+    let mut upstreams: Vec<Upstream> = Vec::new();
+    let mut remaining = input;
 
-    Ok((input, (result.1, result.0)))
-}
+    loop {
+        // Skip whitespace and comments
+        let (next_input, _) = multispace0(remaining)?;
+        let (next_input, _) = many0(parse_comment)(next_input)?;
+        let (next_input, _) = multispace0(next_input)?;
+        remaining = next_input;
 
-fn parse_redirect_handler_args(input: &str) -> IResult<&str, (Option<u16>, Option<String>)> {
-    let (input, _) = space1(input)?;
+        // Check if we've hit keywords or } or end
+        if PROXY_BLOCK_KEYWORDS
+            .iter()
+            .any(|keyword| remaining.starts_with(keyword))
+            || remaining.starts_with("}")
+            || remaining.is_empty()
+        {
+            break;
+        }
 
-    let (input, result) = alt((
-        map(parse_string_u16, |(path, status_code)| {
-            (Some(path.to_string()), Some(status_code))
-        }),
-        map(take_while1(|c: char| !c.is_whitespace()), |path: &str| {
-            (Some(path.to_string()), None)
-        }),
-    ))(input)?;
+        // Parse the next upstream address
+        let (next_input, addr) = take_while1(|c: char| !c.is_whitespace())(remaining)?;
 
-    Ok((input, (result.1, result.0)))
-}
+        // Make sure it's not a keyword
+        if PROXY_BLOCK_KEYWORDS.contains(&addr) {
+            break;
+        }
 
-// Parses the entire configuration, allowing comments and empty lines
-pub fn parse_config(input: &str) -> Result<(&str, Config), String> {
-    let result: Result<(&str, Vec<VirtualHost>), Err<Error<&str>>> = many1(alt((
-        map(parse_virtual_host, Some),
-        map(parse_comment, |_| None), // Skip comments
-    )))(input)
-    .map(|(i, hosts)| (i, hosts.into_iter().flatten().collect()));
+        // The weight of the previous upstream
+        if let Some(weight) = addr.strip_prefix("weight=") {
+            match (upstreams.pop(), weight.parse::<u32>()) {
+                (Some(upstream), Ok(weight)) => upstreams.push(upstream.with_weight(weight)),
+                _ => {
+                    return Err(nom::Err::Error(Error::new(remaining, ErrorKind::Alt)));
+                }
+            }
+            remaining = next_input;
+            continue;
+        }
 
-    match result {
-        Ok(r) => Ok((r.0, Config { virtual_hosts: r.1 })),
-        Err(e) => Err(format_parse_error(input, e)),
+        // Convert to Upstream
+        match Upstream::new(addr.to_string()) {
+            Ok(upstream) => upstreams.push(upstream),
+            Err(_) => {
+                return Err(nom::Err::Error(Error::new(remaining, ErrorKind::Alt)));
+            }
+        }
+
+        remaining = next_input;
     }
-}
 
-/// Parses a string literal  
-fn string_literal(input: &str) -> IResult<&str, String> {
-    delimited(
-        char('"'),
-        map(many0(none_of("\"")), |chars: Vec<char>| {
-            chars.into_iter().collect()
-        }),
-        char('"'),
-    )(input)
+    if upstreams.is_empty() {
+        return Err(nom::Err::Error(Error::new(input, ErrorKind::Alt)));
+    }
+
+    Ok((remaining, upstreams))
 }
 
-/// Parses an unsigned 16-bit integer (u16)  
-fn parse_u16(input: &str) -> IResult<&str, u16> {
-    // We use digit1 to ensure we have at least one digit
+// Parses "circuit_breaker { error_rate <1-100>% [window <duration>] [latency <duration>] }"
+fn parse_circuit_breaker_block(input: &str) -> IResult<&str, types::CircuitBreakerConfig> {
+    let (input, _) = tag("circuit_breaker")(input)?;
     let (input, _) = multispace0(input)?;
-    let (input, digits) = take_while1(|c: char| !c.is_whitespace())(input)?;
-    let (remaining, digits) = digit1(digits)?;
+    let (input, _) = char('{')(input)?;
 
-    // Ensure there are no additional characters after the digits
-    if !remaining.is_empty() {
-        return Err(Err::Error(Error::new(input, ErrorKind::Digit)));
-    }
+    let mut remaining = input;
+    let mut error_rate = None;
+    let mut window = None;
+    let mut latency = None;
 
-    // Convert the digits string to a u16
-    // This will return an error if the value is too large for u16
-    let value = digits
-        .parse::<u16>()
-        .map_err(|_| nom::Err::Error((input, nom::error::ErrorKind::Digit)));
+    loop {
+        let (next_input, _) = multispace0(remaining)?;
+        let (next_input, _) = many0(parse_comment)(next_input)?;
+        let (next_input, _) = multispace0(next_input)?;
+        remaining = next_input;
 
-    match value {
-        Ok(v) => Ok((input, v)),
-        Err(_e) => Err(Err::Error(Error::new(input, ErrorKind::Digit))),
-    }
-}
+        if remaining.starts_with("error_rate") && error_rate.is_none() {
+            let (next_input, _) = tag("error_rate")(remaining)?;
+            let (next_input, _) = multispace1(next_input)?;
+            let (next_input, rate) = terminated(
+                verify(map_res(digit1, str::parse::<u8>), |rate| {
+                    (1..=100).contains(rate)
+                }),
+                char('%'),
+            )(next_input)?;
+            error_rate = Some(rate);
+            remaining = next_input;
+            continue;
+        }
 
-/// Parses a string literal and an unsigned 16-bit integer (u16) example: "Some String" 123
-fn parse_literal_u16(input: &str) -> IResult<&str, (String, u16)> {
-    tuple((string_literal, preceded(space1, parse_u16)))(input)
-}
+        if remaining.starts_with("window") && window.is_none() {
+            let (next_input, _) = tag("window")(remaining)?;
+            let (next_input, _) = multispace1(next_input)?;
+            let (next_input, duration) = verify(parse_duration, |d| !d.is_zero())(next_input)?;
+            window = Some(duration);
+            remaining = next_input;
+            continue;
+        }
 
-/// parse string and unsigned 16-bit integer (u16) example: sometext 123
-fn parse_string_u16(input: &str) -> IResult<&str, (&str, u16)> {
-    tuple((
-        take_while1(|c: char| !c.is_whitespace()),
-        preceded(space1, parse_u16),
-    ))(input)
-}
+        if remaining.starts_with("latency") && latency.is_none() {
+            let (next_input, _) = tag("latency")(remaining)?;
+            let (next_input, _) = multispace1(next_input)?;
+            let (next_input, duration) = verify(parse_duration, |d| !d.is_zero())(next_input)?;
+            latency = Some(duration);
+            remaining = next_input;
+            continue;
+        }
 
-#[cfg(test)]
-mod tests {
-    // Helper functions for creating proxy handlers in tests
-    fn proxy_single(upstream_url: &str) -> crate::types::Handler {
-        crate::types::Handler::Proxy(crate::types::ProxyConfig::new(
-            crate::types::LoadBalancer::NoBalancer(
-                crate::types::Upstream::new(upstream_url.to_string()).unwrap(),
-            ),
-        ))
+        break;
     }
 
-    fn proxy_round_robin(upstream_urls: Vec<&str>) -> crate::types::Handler {
-        let upstreams = upstream_urls
-            .into_iter()
-            .map(|url| crate::types::Upstream::new(url.to_string()).unwrap())
-            .collect();
-        crate::types::Handler::Proxy(crate::types::ProxyConfig::new(
-            crate::types::LoadBalancer::RoundRobin(upstreams),
-        ))
-    }
+    let (input, _) = char('}')(remaining)?;
 
-    mod comments {
-        use crate::parse_comment;
+    // The error rate is required
+    let Some(error_rate) = error_rate else {
+        return Err(nom::Err::Error(Error::new(remaining, ErrorKind::Verify)));
+    };
 
-        #[test]
-        fn test_parse_comment_success() {
-            assert_eq!(parse_comment("# this is a comment"), Ok(("", ())));
-            assert_eq!(parse_comment("# this is a comment\n"), Ok(("\n", ())));
-            assert_eq!(parse_comment("# this is a comment\n\n"), Ok(("\n\n", ())));
-            assert_eq!(
-                parse_comment("# this is a comment\n\n\n"),
+    Ok((
+        input,
+        types::CircuitBreakerConfig {
+            error_rate,
+            window,
+            latency,
+        },
+    ))
+}
+
+// Parses "canary { match header X-Canary=true upstreams http://canary:8080 }"
+fn parse_canary_block(input: &str) -> IResult<&str, types::CanaryConfig> {
+    let (input, _) = tag("canary")(input)?;
+    let (input, _) = multispace0(input)?;
+    let (input, _) = char('{')(input)?;
+
+    let mut remaining = input;
+    let mut matcher = None;
+    let mut upstreams = None;
+
+    loop {
+        let (next_input, _) = multispace0(remaining)?;
+        let (next_input, _) = many0(parse_comment)(next_input)?;
+        let (next_input, _) = multispace0(next_input)?;
+        remaining = next_input;
+
+        if remaining.starts_with("match") && matcher.is_none() {
+            let (next_input, canary_match) = parse_canary_match(remaining)?;
+            matcher = Some(canary_match);
+            remaining = next_input;
+            continue;
+        }
+
+        if remaining.starts_with("upstreams") && upstreams.is_none() {
+            let (next_input, _) = tag("upstreams")(remaining)?;
+            let (next_input, _) = multispace1(next_input)?;
+            let (next_input, addrs) = parse_canary_upstream_addresses(next_input)?;
+            upstreams = Some(addrs);
+            remaining = next_input;
+            continue;
+        }
+
+        break;
+    }
+
+    let (input, _) = char('}')(remaining)?;
+
+    // Both the match and the upstreams are required
+    let (Some(matcher), Some(upstreams)) = (matcher, upstreams) else {
+        return Err(nom::Err::Error(Error::new(remaining, ErrorKind::Verify)));
+    };
+
+    Ok((
+        input,
+        types::CanaryConfig {
+            matcher,
+            load_balancer: default_load_balancer(upstreams),
+        },
+    ))
+}
+
+// Parses "match header <name>=<value>" or "match cookie <name>=<value>"
+fn parse_canary_match(input: &str) -> IResult<&str, types::CanaryMatch> {
+    let (input, _) = tag("match")(input)?;
+    let (input, _) = space1(input)?;
+    let (input, kind) = alt((tag("header"), tag("cookie")))(input)?;
+    let (input, _) = space1(input)?;
+    let (input, name) = take_while1(|c: char| !c.is_whitespace() && c != '=')(input)?;
+    let (input, _) = char('=')(input)?;
+    let (input, value) = take_while1(|c: char| !c.is_whitespace() && c != '}')(input)?;
+
+    let (name, value) = (name.to_string(), value.to_string());
+    let canary_match = match kind {
+        "header" => types::CanaryMatch::Header { name, value },
+        _ => types::CanaryMatch::Cookie { name, value },
+    };
+    Ok((input, canary_match))
+}
+
+// Parse canary upstream addresses until "match" or the end of the canary block
+fn parse_canary_upstream_addresses(input: &str) -> IResult<&str, Vec<Upstream>> {
+    let (input, addrs) = many1(preceded(
+        multispace0,
+        verify(
+            take_while1(|c: char| !c.is_whitespace() && c != '}'),
+            |addr: &str| addr != "match" && !addr.starts_with('#'),
+        ),
+    ))(input)?;
+
+    let mut upstreams: Vec<Upstream> = Vec::new();
+    for addr in addrs {
+        // The weight of the previous upstream
+        let upstream = match addr.strip_prefix("weight=") {
+            Some(weight) => match (upstreams.pop(), weight.parse::<u32>()) {
+                (Some(upstream), Ok(weight)) => Ok(upstream.with_weight(weight)),
+                _ => Err(()),
+            },
+            None => Upstream::new(addr.to_string()).map_err(|_| ()),
+        };
+        match upstream {
+            Ok(upstream) => upstreams.push(upstream),
+            Err(_) => {
+                return Err(nom::Err::Error(Error::new(input, ErrorKind::Alt)));
+            }
+        }
+    }
+
+    Ok((input, upstreams))
+}
+
+// Parses middleware options like "gzip", "cors", "log", "rate_limit 10", "auth admin pass"
+fn parse_middleware(input: &str) -> IResult<&str, types::Middleware> {
+    let (input, _) = multispace0(input)?;
+
+    alt((
+        parse_gzip,
+        parse_compress,
+        parse_cors,
+        map(tag("log"), |_| types::Middleware::Log),
+        parse_rate_limit,
+        parse_auth,
+        parse_cache,
+        parse_max_concurrent,
+        parse_header,
+        parse_minify,
+    ))(input)
+}
+
+// Parses "minify" or "minify <kind>...", the kinds are html, css and js, all of them by default
+fn parse_minify(input: &str) -> IResult<&str, types::Middleware> {
+    let (input, _) = tag("minify")(input)?;
+    let (input, kinds) = many0(preceded(
+        space1,
+        alt((
+            map(tag("html"), |_| types::MinifyKind::Html),
+            map(tag("css"), |_| types::MinifyKind::Css),
+            map(tag("js"), |_| types::MinifyKind::Js),
+        )),
+    ))(input)?;
+    let kinds = match kinds.is_empty() {
+        true => vec![
+            types::MinifyKind::Html,
+            types::MinifyKind::Css,
+            types::MinifyKind::Js,
+        ],
+        false => kinds,
+    };
+    Ok((input, types::Middleware::Minify(kinds)))
+}
+
+// Parses "compress zstd" or "compress zstd dict=<dictionary_path>"
+fn parse_compress(input: &str) -> IResult<&str, types::Middleware> {
+    let (input, _) = tuple((tag("compress"), space1, tag("zstd")))(input)?;
+    let (input, dictionary) = opt(preceded(
+        tuple((space1, tag("dict="))),
+        take_while1(|c: char| !c.is_whitespace() && c != '}'),
+    ))(input)?;
+    Ok((
+        input,
+        types::Middleware::Zstd {
+            dictionary: dictionary.map(str::to_string),
+        },
+    ))
+}
+
+// Parses "gzip" or "gzip level=<level>", the level is 1 to 9, `fastest` or `best`
+fn parse_gzip(input: &str) -> IResult<&str, types::Middleware> {
+    let (input, _) = tag("gzip")(input)?;
+    let (input, level) = opt(preceded(
+        tuple((space1, tag("level="))),
+        alt((
+            map(tag("fastest"), |_| types::CompressionLevel::FASTEST),
+            map(tag("best"), |_| types::CompressionLevel::BEST),
+            map_opt(
+                map_res(digit1, str::parse::<u32>),
+                types::CompressionLevel::new,
+            ),
+        )),
+    ))(input)?;
+    Ok((input, types::Middleware::Gzip(level.unwrap_or_default())))
+}
+
+// Parses "cors" or "cors <origin>", the origin defaults to "*"
+fn parse_cors(input: &str) -> IResult<&str, types::Middleware> {
+    let (input, _) = tag("cors")(input)?;
+    // Only origin-like values are taken, so a middleware on the same line is not read as origin
+    let (input, origin) = opt(preceded(
+        space1,
+        verify(
+            take_while1(|c: char| c.is_ascii_graphic() && c != '}'),
+            |origin: &str| origin == "*" || origin == "null" || origin.contains("://"),
+        ),
+    ))(input)?;
+    Ok((
+        input,
+        types::Middleware::Cors(origin.unwrap_or("*").to_string()),
+    ))
+}
+
+// Parses "rate_limit <N>" or "rate_limit <N>/<duration>" as a middleware
+fn parse_rate_limit(input: &str) -> IResult<&str, types::Middleware> {
+    map(parse_rate, |rate| types::Middleware::RateLimit {
+        requests: rate.requests,
+        window: rate.window,
+    })(input)
+}
+
+// Parses "rate_limit <N>" or "rate_limit <N>/<duration>", the window defaults to one second
+fn parse_rate(input: &str) -> IResult<&str, types::RateLimit> {
+    let (input, _) = tag("rate_limit")(input)?;
+    let (input, _) = space1(input)?;
+    let (input, requests) = verify(map_res(digit1, str::parse::<u32>), |requests: &u32| {
+        *requests > 0
+    })(input)?;
+    let (rest, window) = opt(preceded(
+        char('/'),
+        verify(parse_duration, |window: &Duration| !window.is_zero()),
+    ))(input)?;
+
+    // An invalid window like "10/0s" is rejected instead of being left unparsed
+    if rest.starts_with('/') {
+        return Err(Err::Error(Error::new(input, ErrorKind::Verify)));
+    }
+    let input = rest;
+    Ok((
+        input,
+        types::RateLimit {
+            requests,
+            window: window.unwrap_or(Duration::from_secs(1)),
+        },
+    ))
+}
+
+// Parses "auth <username> <password>" or the block
+// "auth { users <username> <password>... realm "<realm>" except <path_pattern>... }"
+fn parse_auth(input: &str) -> IResult<&str, types::Middleware> {
+    let (input, _) = tag("auth")(input)?;
+    alt((
+        parse_auth_block,
+        map(
+            verify(
+                tuple((
+                    preceded(space1, parse_auth_word),
+                    preceded(space1, parse_auth_word),
+                )),
+                |(username, _): &(&str, &str)| username != &"{",
+            ),
+            |(username, password)| types::Middleware::Auth {
+                users: vec![types::BasicUser::new(username, password)],
+                realm: None,
+                except: vec![],
+            },
+        ),
+    ))(input)
+}
+
+enum AuthEntry {
+    Users(Vec<types::BasicUser>),
+    Realm(String),
+    Except(Vec<String>),
+}
+
+fn parse_auth_block(input: &str) -> IResult<&str, types::Middleware> {
+    let (rest, entries) = delimited(
+        tuple((multispace0, char('{'))),
+        many1(preceded(
+            multispace0,
+            alt((
+                map(
+                    preceded(
+                        tag("users"),
+                        // A user is not named like an entry, so the next entry is not read as one
+                        many1(tuple((
+                            preceded(
+                                space1,
+                                verify(parse_auth_word, |username: &str| {
+                                    !["users", "realm", "except"].contains(&username)
+                                }),
+                            ),
+                            preceded(space1, parse_auth_word),
+                        ))),
+                    ),
+                    |users| {
+                        AuthEntry::Users(
+                            users
+                                .into_iter()
+                                .map(|(username, password)| {
+                                    types::BasicUser::new(username, password)
+                                })
+                                .collect(),
+                        )
+                    },
+                ),
+                map(
+                    preceded(tuple((tag("realm"), space1)), string_literal),
+                    AuthEntry::Realm,
+                ),
+                map(
+                    preceded(
+                        tag("except"),
+                        many1(preceded(
+                            space1,
+                            verify(parse_auth_word, |pattern: &str| pattern.starts_with('/')),
+                        )),
+                    ),
+                    |patterns| AuthEntry::Except(patterns.into_iter().map(String::from).collect()),
+                ),
+            )),
+        )),
+        tuple((multispace0, char('}'))),
+    )(input)?;
+
+    let mut users = vec![];
+    let mut realm = None;
+    let mut except = vec![];
+    for entry in entries {
+        match entry {
+            AuthEntry::Users(entry_users) => users.extend(entry_users),
+            AuthEntry::Realm(entry_realm) => realm = Some(entry_realm),
+            AuthEntry::Except(patterns) => except.extend(patterns),
+        }
+    }
+    if users.is_empty() {
+        return Err(Err::Error(Error::new(input, ErrorKind::Verify)));
+    }
+    Ok((
+        rest,
+        types::Middleware::Auth {
+            users,
+            realm,
+            except,
+        },
+    ))
+}
+
+fn parse_auth_word(input: &str) -> IResult<&str, &str> {
+    take_while1(|c: char| !c.is_whitespace() && c != '}')(input)
+}
+
+// Parses "cache <duration>" like "cache 5m", optionally followed by a block of cache options
+// like "cache 5m { serve_stale_on_error 1h immutable }"
+fn parse_cache(input: &str) -> IResult<&str, types::Middleware> {
+    let (input, _) = tag("cache")(input)?;
+    let (input, _) = space1(input)?;
+    let (input, ttl) = parse_duration(input)?;
+    let (input, options) = opt(preceded(
+        space1,
+        delimited(
+            char('{'),
+            many0(preceded(multispace0, parse_cache_option)),
+            preceded(multispace0, char('}')),
+        ),
+    ))(input)?;
+
+    let mut serve_stale_on_error = None;
+    let mut immutable = false;
+    for option in options.into_iter().flatten() {
+        match option {
+            CacheOption::ServeStaleOnError(grace) => serve_stale_on_error = Some(grace),
+            CacheOption::Immutable => immutable = true,
+        }
+    }
+    Ok((
+        input,
+        types::Middleware::Cache {
+            ttl,
+            serve_stale_on_error,
+            immutable,
+        },
+    ))
+}
+
+/// Option of the block of a `cache` middleware
+#[derive(Clone)]
+enum CacheOption {
+    ServeStaleOnError(Duration),
+    Immutable,
+}
+
+// Parses "serve_stale_on_error <duration>", its alias "stale_if_error <duration>" or "immutable"
+fn parse_cache_option(input: &str) -> IResult<&str, CacheOption> {
+    alt((
+        map(
+            preceded(
+                tuple((
+                    alt((tag("serve_stale_on_error"), tag("stale_if_error"))),
+                    space1,
+                )),
+                parse_duration,
+            ),
+            CacheOption::ServeStaleOnError,
+        ),
+        value(CacheOption::Immutable, tag("immutable")),
+    ))(input)
+}
+
+// Parses "max_concurrent <limit>" or "max_concurrent <limit> queue <size> <timeout>"
+fn parse_max_concurrent(input: &str) -> IResult<&str, types::Middleware> {
+    let (input, _) = tag("max_concurrent")(input)?;
+    let (input, _) = space1(input)?;
+    let (input, limit) = parse_positive_usize(input)?;
+    let (input, queue) = opt(preceded(
+        tuple((space1, tag("queue"), space1)),
+        tuple((parse_positive_usize, preceded(space1, parse_duration))),
+    ))(input)?;
+    Ok((
+        input,
+        types::Middleware::MaxConcurrent {
+            limit,
+            queue: queue.map(|(size, timeout)| types::ConcurrencyQueue { size, timeout }),
+        },
+    ))
+}
+
+fn parse_positive_usize(input: &str) -> IResult<&str, usize> {
+    verify(map_res(digit1, str::parse::<usize>), |value: &usize| {
+        *value > 0
+    })(input)
+}
+
+// Parses "header <key> <value>" or "header <key> <value> <replace_with>" or "header <key>"
+fn parse_header(input: &str) -> IResult<&str, types::Middleware> {
+    let (input, _) = tag("header")(input)?;
+    let (input, _) = space1(input)?;
+
+    let (input, operator) = parse_header_operator(input)?;
+
+    // Parse the header name and value and replace_with if present
+    let (input, (name, value, replace_with)) = tuple((
+        take_while1(|c: char| !c.is_whitespace()),
+        opt(preceded(space1, take_while1(|c: char| !c.is_whitespace()))),
+        opt(preceded(space1, take_while1(|c: char| !c.is_whitespace()))),
+    ))(input)?;
+
+    Ok((
+        input,
+        types::Middleware::Header {
+            operator,
+            name: name.to_string(),
+            value: value.map(|s| s.to_string()),
+            replace_with: replace_with.map(|s| s.to_string()),
+        },
+    ))
+}
+
+// Parses the operator prefixing the field name of a header rule
+fn parse_header_operator(input: &str) -> IResult<&str, types::HeaderOperator> {
+    alt((
+        // two operator characters should be parsed first
+        map(tag("~>"), |_| types::HeaderOperator::DeferReplace),
+        map(tag("+"), |_| types::HeaderOperator::Add),
+        map(tag(">"), |_| types::HeaderOperator::DeferSet),
+        map(tag("-"), |_| types::HeaderOperator::Delete),
+        map(tag("?"), |_| types::HeaderOperator::Default),
+        map(tag("="), |_| types::HeaderOperator::Set),
+        map(tag("~"), |_| types::HeaderOperator::Replace),
+    ))(input)
+}
+
+// Parses values like "index.html" or "http://localhost:3000", a value with spaces is quoted
+// like "\"/var/www/my site/index.html\""
+fn parse_value(input: &str) -> IResult<&str, String> {
+    let (input, _) = space1(input)?;
+    alt((
+        string_literal,
+        map(take_while1(|c: char| !c.is_whitespace()), str::to_string),
+    ))(input)
+}
+
+// Parses a root like " /var/www" or a list of roots like " [/tenant/assets /default/assets]"
+fn parse_roots(input: &str) -> IResult<&str, Vec<String>> {
+    let (input, _) = space1(input)?;
+    alt((
+        // a list must be closed and have a root
+        preceded(
+            char('['),
+            cut(terminated(
+                many1(preceded(
+                    multispace0,
+                    alt((
+                        string_literal,
+                        map(
+                            take_while1(|c: char| !c.is_whitespace() && c != ']'),
+                            str::to_string,
+                        ),
+                    )),
+                )),
+                preceded(multispace0, char(']')),
+            )),
+        ),
+        map(
+            alt((
+                string_literal,
+                map(take_while1(|c: char| !c.is_whitespace()), str::to_string),
+            )),
+            |root| vec![root],
+        ),
+    ))(input)
+}
+
+// Parses values like " 200" or " "<h1>Example</h1>" 200" or " "<h1>Example</h1>""
+fn parse_respond_handler_args(input: &str) -> IResult<&str, (Option<u16>, Option<String>)> {
+    let (input, _) = space1(input)?;
+
+    let (input, result) = alt((
+        map(parse_literal_u16, |(body, status)| {
+            (Some(body), Some(status))
+        }),
+        map(string_literal, |body| (Some(body), None)),
+        map(parse_u16, |status| (None, Some(status))),
+    ))(input)?;
+
+    Ok((input, (result.1, result.0)))
+}
+
+// Parses values like " file:./maintenance.html" or " file:./maintenance.html 503"
+fn parse_respond_file_handler_args(input: &str) -> IResult<&str, (String, Option<u16>)> {
+    let (input, _) = tuple((space1, tag("file:")))(input)?;
+    let (input, file) = take_while1(|c: char| !c.is_whitespace() && c != '}')(input)?;
+    let (input, status) = opt(preceded(
+        space1,
+        verify(parse_u16, |status| (100..=599).contains(status)),
+    ))(input)?;
+    Ok((input, (file.to_string(), status)))
+}
+
+fn parse_redirect_handler_args(input: &str) -> IResult<&str, (Option<u16>, Option<String>)> {
+    let (input, _) = space1(input)?;
+
+    let (input, result) = alt((
+        map(parse_string_u16, |(path, status_code)| {
+            (Some(path.to_string()), Some(status_code))
+        }),
+        map(take_while1(|c: char| !c.is_whitespace()), |path: &str| {
+            (Some(path.to_string()), None)
+        }),
+    ))(input)?;
+
+    Ok((input, (result.1, result.0)))
+}
+
+// Parses the entire configuration, allowing comments and empty lines
+//
+// `include` directives need the path of the config to be resolved, use `parse_config_file`.
+pub fn parse_config(input: &str) -> Result<(&str, Config), String> {
+    let (input, entries) = parse_config_entries(input)?;
+
+    let mut virtual_hosts = vec![];
+    let mut options = None;
+    for entry in entries {
+        match entry {
+            ConfigEntry::VirtualHosts(hosts) => virtual_hosts.extend(hosts),
+            ConfigEntry::Options(block) => set_options(&mut options, block)?,
+            ConfigEntry::Include(pattern) => {
+                return Err(format!(
+                    "include {pattern} can only be resolved when the config is read from a file."
+                ))
+            }
+        }
+    }
+
+    Ok((
+        input,
+        Config {
+            virtual_hosts,
+            options: options.unwrap_or_default(),
+            expansions: Default::default(),
+        },
+    ))
+}
+
+/// Keeps the options block of the config, a config may have only one.
+fn set_options(
+    options: &mut Option<types::GlobalOptions>,
+    block: Box<types::GlobalOptions>,
+) -> Result<(), String> {
+    if options.is_some() {
+        return Err("Duplicate options block, the config may have only one.".to_string());
+    }
+    *options = Some(*block);
+    Ok(())
+}
+
+/// Maximum depth of nested `include` directives
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Parses the content of the config file at `path`, merging the virtual hosts of the files
+/// pulled in by its `include` directives.
+///
+/// Relative include patterns are resolved against the directory of the including file. The
+/// environment variables of each file are expanded before it is parsed, see [`env`].
+pub fn parse_config_file(path: &Path, input: &str) -> Result<Config, String> {
+    let mut virtual_hosts = vec![];
+    let mut options = None;
+    let mut expansions = env::Expansions::default();
+    let mut including = vec![];
+    parse_config_file_into(
+        path,
+        input,
+        &mut including,
+        &mut virtual_hosts,
+        &mut options,
+        &mut expansions,
+    )?;
+    Ok(Config {
+        virtual_hosts,
+        options: options.unwrap_or_default(),
+        expansions,
+    })
+}
+
+fn parse_config_file_into(
+    path: &Path,
+    input: &str,
+    including: &mut Vec<PathBuf>,
+    virtual_hosts: &mut Vec<VirtualHost>,
+    options: &mut Option<types::GlobalOptions>,
+    expansions: &mut env::Expansions,
+) -> Result<(), String> {
+    let canonical_path = std::fs::canonicalize(path)
+        .map_err(|e| format!("could not resolve {}: {e}", path.display()))?;
+    if let Some(start) = including.iter().position(|file| *file == canonical_path) {
+        let cycle: Vec<String> = including[start..]
+            .iter()
+            .chain([&canonical_path])
+            .map(|file| file.display().to_string())
+            .collect();
+        return Err(format!(
+            "{} includes itself: {}.",
+            path.display(),
+            cycle.join(" -> ")
+        ));
+    }
+    if including.len() >= MAX_INCLUDE_DEPTH {
+        return Err(format!(
+            "includes are nested more than {MAX_INCLUDE_DEPTH} levels deep at {}.",
+            path.display()
+        ));
+    }
+
+    let (input, file_expansions) = env::expand(input, |name| std::env::var(name).ok())?;
+    let input = input.as_str();
+    // the errors quote the config, keep the values of the variables out of them
+    let redact = |error: String| file_expansions.redact(&error);
+    let (rest, entries) = parse_config_entries(input).map_err(redact)?;
+    let rest = rest.trim_start();
+    if !rest.is_empty() {
+        return Err(redact(format!(
+            "Syntax error near{}: '{}'.",
+            find_error_location(input, rest),
+            get_error_context(rest)
+        )));
+    }
+    expansions.extend(file_expansions.clone());
+
+    including.push(canonical_path);
+    let dir = path.parent().unwrap_or(Path::new(""));
+    for entry in entries {
+        match entry {
+            ConfigEntry::VirtualHosts(hosts) => virtual_hosts.extend(hosts),
+            ConfigEntry::Options(block) => set_options(options, block)?,
+            ConfigEntry::Include(pattern) => {
+                for included in resolve_include(dir, &pattern).map_err(redact)? {
+                    let content = std::fs::read_to_string(&included).map_err(|e| {
+                        redact(format!(
+                            "Failed to read included file {}: {e}",
+                            included.display()
+                        ))
+                    })?;
+                    parse_config_file_into(
+                        &included,
+                        &content,
+                        including,
+                        virtual_hosts,
+                        options,
+                        expansions,
+                    )
+                    .map_err(|e| redact(format!("In included file {}: {e}", included.display())))?;
+                }
+            }
+        }
+    }
+    including.pop();
+
+    Ok(())
+}
+
+/// Returns the files matching the include pattern, in alphabetical order.
+///
+/// A pattern without wildcards must name an existing file, a glob may match no file.
+fn resolve_include(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let full_pattern = dir.join(pattern);
+    let full_pattern = full_pattern.to_string_lossy();
+
+    let paths =
+        glob::glob(&full_pattern).map_err(|e| format!("Invalid include pattern {pattern}: {e}"))?;
+    let mut files = vec![];
+    for path in paths {
+        let path = path.map_err(|e| format!("Failed to read included file {e}"))?;
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+
+    let is_glob = pattern.contains(['*', '?', '[']);
+    if files.is_empty() && !is_glob {
+        return Err(format!("Included file {full_pattern} does not exist."));
+    }
+    Ok(files)
+}
+
+/// Top level entry of a config
+enum ConfigEntry {
+    /// Virtual hosts of a block, one per domain listed before it
+    VirtualHosts(Vec<VirtualHost>),
+    Options(Box<types::GlobalOptions>),
+    Include(String),
+}
+
+/// Entry of the top-level options block
+enum OptionsEntry {
+    DefaultPort(u16),
+    LogLevel(types::LogLevel),
+    GracefulShutdownTimeout(Duration),
+    AccessLog(String),
+    WriteTimeout(Duration),
+    HeaderReadTimeout(Duration),
+    NotFound(types::NotFoundPage),
+    DefaultHost(types::Domain),
+    Csp(types::ContentSecurityPolicy),
+    MemoryBudget(u64),
+    DefaultRequestTimeout(Duration),
+    DefaultConnectionTimeout(Duration),
+    WebsocketDrain(Duration),
+    StrictSniHost(bool),
+    AllowedMethods(Vec<http::Method>),
+}
+
+fn parse_config_entries(input: &str) -> Result<(&str, Vec<ConfigEntry>), String> {
+    let result: Result<(&str, Vec<ConfigEntry>), Err<Error<&str>>> =
+        many1(parse_config_entry)(input)
+            .map(|(i, entries)| (i, entries.into_iter().flatten().collect()));
+
+    result.map_err(|e| format_parse_error(input, e))
+}
+
+// Parses one top-level entry, comments give `None`
+fn parse_config_entry(input: &str) -> IResult<&str, Option<ConfigEntry>> {
+    alt((
+        map(parse_include, |pattern| Some(ConfigEntry::Include(pattern))),
+        map(parse_options, |options| {
+            Some(ConfigEntry::Options(Box::new(options)))
+        }),
+        map(parse_virtual_host, |virtual_hosts| {
+            Some(ConfigEntry::VirtualHosts(virtual_hosts))
+        }),
+        map(parse_comment, |_| None), // Skip comments
+    ))(input)
+}
+
+/// Error of a config parsed with `parse_config_all_errors`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    /// Line of the error, starting at 1.
+    pub line: usize,
+    /// Column of the error, starting at 1.
+    pub column: usize,
+    pub message: String,
+}
+
+impl ConfigError {
+    fn at(full_input: &str, error_input: &str, message: String) -> Self {
+        let (line, column) = error_position(full_input, error_input);
+        ConfigError {
+            line,
+            column,
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Parses the entire configuration like `parse_config`, but instead of stopping at the first
+/// error it skips the block of an invalid entry and goes on with the next one, so all the
+/// errors of the config are reported at once.
+///
+/// Errors are recovered at the end of a top-level block, several errors inside the same
+/// virtual host are reported as one.
+pub fn parse_config_all_errors(input: &str) -> Result<Config, Vec<ConfigError>> {
+    let mut virtual_hosts = vec![];
+    let mut options = None;
+    let mut errors = vec![];
+    let mut has_entries = false;
+
+    let mut rest = input;
+    while !rest.trim_start().is_empty() {
+        let entry_start = rest.trim_start();
+        match parse_config_entry(rest) {
+            Ok((remaining, entry)) => {
+                has_entries = true;
+                match entry {
+                    Some(ConfigEntry::VirtualHosts(hosts)) => virtual_hosts.extend(hosts),
+                    Some(ConfigEntry::Options(block)) => {
+                        if let Err(message) = set_options(&mut options, block) {
+                            errors.push(ConfigError::at(input, entry_start, message));
+                        }
+                    }
+                    Some(ConfigEntry::Include(pattern)) => errors.push(ConfigError::at(
+                        input,
+                        entry_start,
+                        format!(
+                            "include {pattern} can only be resolved when the config is read from a file."
+                        ),
+                    )),
+                    None => {}
+                }
+                rest = remaining;
+            }
+            Err(_) => {
+                let error = furthest_entry_error(rest);
+                let error_input = match &error {
+                    Err::Error(e) | Err::Failure(e) => e.input,
+                    Err::Incomplete(_) => entry_start,
+                };
+                errors.push(ConfigError::at(
+                    input,
+                    error_input,
+                    format_parse_error(input, error),
+                ));
+                rest = skip_block(entry_start);
+            }
+        }
+    }
+
+    if !has_entries && errors.is_empty() {
+        errors.push(ConfigError::at(
+            input,
+            rest,
+            "Expected at least one virtual host definition in the configuration file.".to_string(),
+        ));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(Config {
+        virtual_hosts,
+        options: options.unwrap_or_default(),
+        expansions: Default::default(),
+    })
+}
+
+/// Returns the error of the top-level parser that went the furthest in the invalid entry, it
+/// points at the actual mistake rather than at the start of the entry.
+fn furthest_entry_error(input: &str) -> Err<Error<&str>> {
+    let remaining = |error: &Err<Error<&str>>| match error {
+        Err::Error(e) | Err::Failure(e) => e.input.len(),
+        Err::Incomplete(_) => input.len(),
+    };
+    [
+        parse_include(input).err(),
+        parse_options(input).err(),
+        parse_virtual_host(input).err(),
+    ]
+    .into_iter()
+    .flatten()
+    .min_by_key(remaining)
+    .unwrap_or_else(|| Err::Error(Error::new(input, ErrorKind::Alt)))
+}
+
+/// Returns the input after the block opened by the entry at the start of `input`, or after the
+/// first unmatched `}`. Braces inside string literals are not counted.
+fn skip_block(input: &str) -> &str {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if in_string => {
+                chars.next();
+            }
+            '"' => in_string = !in_string,
+            '{' if !in_string => depth += 1,
+            '}' if !in_string => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    return &input[i + 1..];
+                }
+            }
+            _ => {}
+        }
+    }
+    ""
+}
+
+// Parses the server-wide options block like "options { default_port 8080 }"
+fn parse_options(input: &str) -> IResult<&str, types::GlobalOptions> {
+    let (input, _) = multispace0(input)?;
+    let (input, _) = tag("options")(input)?;
+    let (input, _) = multispace0(input)?;
+
+    let (input, entries) = delimited(
+        char('{'),
+        many0(alt((
+            map(parse_options_entry, Some),
+            map(parse_comment, |_| None),
+        ))),
+        preceded(multispace0, char('}')),
+    )(input)?;
+    let (input, _) = multispace0(input)?;
+
+    let mut options = types::GlobalOptions::default();
+    for entry in entries.into_iter().flatten() {
+        match entry {
+            OptionsEntry::DefaultPort(port) => options.default_port = Some(port),
+            OptionsEntry::LogLevel(level) => options.log_level = Some(level),
+            OptionsEntry::GracefulShutdownTimeout(timeout) => {
+                options.graceful_shutdown_timeout = Some(timeout)
+            }
+            OptionsEntry::AccessLog(path) => options.access_log = Some(path),
+            OptionsEntry::WriteTimeout(timeout) => options.write_timeout = Some(timeout),
+            OptionsEntry::HeaderReadTimeout(timeout) => options.header_read_timeout = Some(timeout),
+            OptionsEntry::NotFound(page) => options.not_found = Some(page),
+            OptionsEntry::DefaultHost(domain) => options.default_host = Some(domain),
+            OptionsEntry::Csp(csp) => options.csp = Some(csp),
+            OptionsEntry::MemoryBudget(bytes) => options.memory_budget = Some(bytes),
+            OptionsEntry::DefaultRequestTimeout(timeout) => {
+                options.default_request_timeout = Some(timeout)
+            }
+            OptionsEntry::DefaultConnectionTimeout(timeout) => {
+                options.default_connection_timeout = Some(timeout)
+            }
+            OptionsEntry::WebsocketDrain(drain) => options.websocket_drain = Some(drain),
+            OptionsEntry::StrictSniHost(strict) => options.strict_sni_host = Some(strict),
+            OptionsEntry::AllowedMethods(methods) => options.allowed_methods = Some(methods),
+        }
+    }
+
+    Ok((input, options))
+}
+
+// Parses "default_port <port>", "log_level <level>", "graceful_shutdown_timeout <duration>"
+// (or "grace_period <duration>"), "access_log <path>", "write_timeout <duration>",
+// "header_read_timeout <duration>", "not_found { respond_file <path> [status] }",
+// "default_host <domain>", "csp \"<policy>\"", "csp_report_only \"<policy>\"",
+// "memory_budget <size>", "default_request_timeout <duration>",
+// "default_connection_timeout <duration>", "websocket_drain <duration>",
+// "strict_sni_host on|off" or "allowed_methods <METHOD> [<METHOD>...]"
+fn parse_options_entry(input: &str) -> IResult<&str, OptionsEntry> {
+    let (input, _) = multispace0(input)?;
+    alt((
+        map(
+            preceded(tuple((tag("default_port"), space1)), parse_u16),
+            OptionsEntry::DefaultPort,
+        ),
+        map(
+            preceded(
+                tuple((tag("log_level"), space1)),
+                alt((
+                    map(tag("error"), |_| types::LogLevel::Error),
+                    map(tag("warn"), |_| types::LogLevel::Warn),
+                    map(tag("info"), |_| types::LogLevel::Info),
+                    map(tag("debug"), |_| types::LogLevel::Debug),
+                    map(tag("trace"), |_| types::LogLevel::Trace),
+                )),
+            ),
+            OptionsEntry::LogLevel,
+        ),
+        map(
+            preceded(
+                tuple((
+                    alt((tag("graceful_shutdown_timeout"), tag("grace_period"))),
+                    space1,
+                )),
+                parse_duration,
+            ),
+            OptionsEntry::GracefulShutdownTimeout,
+        ),
+        map(
+            preceded(tag("access_log"), parse_path),
+            OptionsEntry::AccessLog,
+        ),
+        map(
+            preceded(
+                tuple((tag("write_timeout"), space1)),
+                verify(parse_duration, |timeout| !timeout.is_zero()),
+            ),
+            OptionsEntry::WriteTimeout,
+        ),
+        map(
+            preceded(
+                tuple((tag("header_read_timeout"), space1)),
+                verify(parse_duration, |timeout| !timeout.is_zero()),
+            ),
+            OptionsEntry::HeaderReadTimeout,
+        ),
+        map(parse_not_found, OptionsEntry::NotFound),
+        map(
+            preceded(
+                tuple((tag("default_host"), space1)),
+                map_res(
+                    take_while1(|c: char| !c.is_whitespace() && c != '}'),
+                    str::parse::<types::Domain>,
+                ),
+            ),
+            OptionsEntry::DefaultHost,
+        ),
+        map(parse_csp, OptionsEntry::Csp),
+        map(
+            preceded(
+                tuple((tag("memory_budget"), space1)),
+                verify(parse_size, |bytes| *bytes > 0),
+            ),
+            OptionsEntry::MemoryBudget,
+        ),
+        map(
+            preceded(
+                tuple((tag("default_request_timeout"), space1)),
+                verify(parse_duration, |timeout| !timeout.is_zero()),
+            ),
+            OptionsEntry::DefaultRequestTimeout,
+        ),
+        map(
+            preceded(
+                tuple((tag("default_connection_timeout"), space1)),
+                verify(parse_duration, |timeout| !timeout.is_zero()),
+            ),
+            OptionsEntry::DefaultConnectionTimeout,
+        ),
+        map(
+            preceded(tuple((tag("websocket_drain"), space1)), parse_duration),
+            OptionsEntry::WebsocketDrain,
+        ),
+        map(
+            preceded(
+                tuple((tag("strict_sni_host"), space1)),
+                alt((map(tag("on"), |_| true), map(tag("off"), |_| false))),
+            ),
+            OptionsEntry::StrictSniHost,
+        ),
+        map(
+            parse_methods("allowed_methods"),
+            OptionsEntry::AllowedMethods,
+        ),
+    ))(input)
+}
+
+// Parses "not_found { respond_file <path> [status] }", the status defaults to 404
+fn parse_not_found(input: &str) -> IResult<&str, types::NotFoundPage> {
+    let (input, _) = tuple((tag("not_found"), multispace0, char('{'), multispace0))(input)?;
+    let (input, (file, status)) = cut(tuple((
+        preceded(tag("respond_file"), parse_path),
+        opt(preceded(
+            space1,
+            verify(parse_u16, |status| (100..=599).contains(status)),
+        )),
+    )))(input)?;
+    let (input, _) = cut(preceded(multispace0, char('}')))(input)?;
+    Ok((
+        input,
+        types::NotFoundPage {
+            file,
+            status: status.unwrap_or(404),
+        },
+    ))
+}
+
+// Parses "include <path or glob>", or its alias "import <path or glob>"
+fn parse_include(input: &str) -> IResult<&str, String> {
+    let (input, _) = multispace0(input)?;
+    let (input, _) = alt((tag("include"), tag("import")))(input)?;
+    let (input, pattern) = verify(parse_value, |pattern: &str| !pattern.starts_with('{'))(input)?;
+    let (input, _) = multispace0(input)?;
+    Ok((input, pattern))
+}
+
+/// Parses a string literal, with the escape sequences `\"`, `\\`, `\n` and `\t`
+fn string_literal(input: &str) -> IResult<&str, String> {
+    delimited(
+        char('"'),
+        map(
+            many0(alt((none_of("\"\\"), parse_escaped_char))),
+            |chars: Vec<char>| chars.into_iter().collect(),
+        ),
+        char('"'),
+    )(input)
+}
+
+/// Parses an escape sequence of a string literal, an unknown escape is a parse failure
+fn parse_escaped_char(input: &str) -> IResult<&str, char> {
+    preceded(
+        char('\\'),
+        cut(alt((
+            value('"', char('"')),
+            value('\\', char('\\')),
+            value('\n', char('n')),
+            value('\t', char('t')),
+        ))),
+    )(input)
+}
+
+/// Parses an unsigned 16-bit integer (u16)  
+fn parse_u16(input: &str) -> IResult<&str, u16> {
+    // We use digit1 to ensure we have at least one digit
+    let (input, _) = multispace0(input)?;
+    let (input, digits) = take_while1(|c: char| !c.is_whitespace())(input)?;
+    let (remaining, digits) = digit1(digits)?;
+
+    // Ensure there are no additional characters after the digits
+    if !remaining.is_empty() {
+        return Err(Err::Error(Error::new(input, ErrorKind::Digit)));
+    }
+
+    // Convert the digits string to a u16
+    // This will return an error if the value is too large for u16
+    let value = digits
+        .parse::<u16>()
+        .map_err(|_| nom::Err::Error((input, nom::error::ErrorKind::Digit)));
+
+    match value {
+        Ok(v) => Ok((input, v)),
+        Err(_e) => Err(Err::Error(Error::new(input, ErrorKind::Digit))),
+    }
+}
+
+/// Parses a duration like "500ms", "30s", "5m", "1h" or "1d". A bare number is read as seconds.
+fn parse_duration(input: &str) -> IResult<&str, Duration> {
+    let (remaining, digits) = digit1(input)?;
+    let (remaining, unit) =
+        opt(alt((tag("ms"), tag("s"), tag("m"), tag("h"), tag("d"))))(remaining)?;
+
+    // The unit must be the end of the token, e.g. "5x" or "10sec" are rejected
+    if remaining.starts_with(|c: char| !c.is_whitespace() && c != '}') {
+        return Err(Err::Error(Error::new(input, ErrorKind::Verify)));
+    }
+
+    let Ok(value) = digits.parse::<u64>() else {
+        return Err(Err::Error(Error::new(input, ErrorKind::Digit)));
+    };
+
+    let duration = match unit {
+        Some("ms") => Duration::from_millis(value),
+        None | Some("s") => Duration::from_secs(value),
+        Some("m") => Duration::from_secs(value.saturating_mul(60)),
+        Some("h") => Duration::from_secs(value.saturating_mul(60 * 60)),
+        Some("d") => Duration::from_secs(value.saturating_mul(60 * 60 * 24)),
+        Some(_) => unreachable!("unit is limited by the parser above"),
+    };
+
+    Ok((remaining, duration))
+}
+
+/// Parses a size in bytes like "4096", "64kb", "512mb" or "2gb", units are powers of 1024.
+fn parse_size(input: &str) -> IResult<&str, u64> {
+    let (remaining, digits) = digit1(input)?;
+    let (remaining, unit) = opt(alt((
+        tag_no_case("kb"),
+        tag_no_case("mb"),
+        tag_no_case("gb"),
+        tag_no_case("b"),
+    )))(remaining)?;
+
+    // The unit must be the end of the token, e.g. "5x" or "10mib" are rejected
+    if remaining.starts_with(|c: char| !c.is_whitespace() && c != '}') {
+        return Err(Err::Error(Error::new(input, ErrorKind::Verify)));
+    }
+
+    let shift = match unit.map(str::to_ascii_lowercase).as_deref() {
+        None | Some("b") => 0,
+        Some("kb") => 10,
+        Some("mb") => 20,
+        Some("gb") => 30,
+        Some(_) => unreachable!("unit is limited by the parser above"),
+    };
+    match digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|value| value.checked_mul(1 << shift))
+    {
+        Some(bytes) => Ok((remaining, bytes)),
+        None => Err(Err::Error(Error::new(input, ErrorKind::Digit))),
+    }
+}
+
+/// Parses a string literal and an unsigned 16-bit integer (u16) example: "Some String" 123
+fn parse_literal_u16(input: &str) -> IResult<&str, (String, u16)> {
+    tuple((string_literal, preceded(space1, parse_u16)))(input)
+}
+
+/// parse string and unsigned 16-bit integer (u16) example: sometext 123
+fn parse_string_u16(input: &str) -> IResult<&str, (&str, u16)> {
+    tuple((
+        take_while1(|c: char| !c.is_whitespace()),
+        preceded(space1, parse_u16),
+    ))(input)
+}
+
+#[cfg(test)]
+mod tests {
+    // Helper functions for creating proxy handlers in tests
+    fn proxy_single(upstream_url: &str) -> crate::types::Handler {
+        crate::types::Handler::Proxy(crate::types::ProxyConfig::new(
+            crate::types::LoadBalancer::NoBalancer(
+                crate::types::Upstream::new(upstream_url.to_string()).unwrap(),
+            ),
+        ))
+    }
+
+    fn proxy_round_robin(upstream_urls: Vec<&str>) -> crate::types::Handler {
+        let upstreams = upstream_urls
+            .into_iter()
+            .map(|url| crate::types::Upstream::new(url.to_string()).unwrap())
+            .collect();
+        crate::types::Handler::Proxy(crate::types::ProxyConfig::new(
+            crate::types::LoadBalancer::RoundRobin(upstreams),
+        ))
+    }
+
+    mod comments {
+        use crate::parse_comment;
+
+        #[test]
+        fn test_parse_comment_success() {
+            assert_eq!(parse_comment("# this is a comment"), Ok(("", ())));
+            assert_eq!(parse_comment("# this is a comment\n"), Ok(("\n", ())));
+            assert_eq!(parse_comment("# this is a comment\n\n"), Ok(("\n\n", ())));
+            assert_eq!(
+                parse_comment("# this is a comment\n\n\n"),
                 Ok(("\n\n\n", ()))
             );
             // 1 space before comment
@@ -1434,7 +3321,9 @@ mod tests {
     }
 
     mod routes {
-        use crate::{parse_route, parse_route_contents, types};
+        use rstest::rstest;
+
+        use crate::{parse_route, parse_route_contents, types, RouteContents};
 
         #[test]
         fn test_parse_route_respond_handler_with_no_middleware_inline() {
@@ -1448,7 +3337,13 @@ mod tests {
                             status: Some(200),
                             body: Some("<h1>Example</h1>".to_string()),
                         },
-                        middlewares: vec![]
+                        middlewares: vec![],
+                        error_format: None,
+                        sniff_extensionless: false,
+                        max_ranges: None,
+                        transcode: false,
+                        negotiate_language: false,
+                        methods: vec![],
                     }),
                 ))
             );
@@ -1463,7 +3358,13 @@ mod tests {
                             status: Some(200),
                             body: None,
                         },
-                        middlewares: vec![]
+                        middlewares: vec![],
+                        error_format: None,
+                        sniff_extensionless: false,
+                        max_ranges: None,
+                        transcode: false,
+                        negotiate_language: false,
+                        methods: vec![],
                     }),
                 ))
             );
@@ -1478,7 +3379,13 @@ mod tests {
                             status: None,
                             body: Some("<h1>Example</h1>".to_string()),
                         },
-                        middlewares: vec![]
+                        middlewares: vec![],
+                        error_format: None,
+                        sniff_extensionless: false,
+                        max_ranges: None,
+                        transcode: false,
+                        negotiate_language: false,
+                        methods: vec![],
                     }),
                 ))
             );
@@ -1502,7 +3409,13 @@ mod tests {
                             status: Some(200),
                             body: Some("<h1>Example</h1>".to_string()),
                         },
-                        middlewares: vec![]
+                        middlewares: vec![],
+                        error_format: None,
+                        sniff_extensionless: false,
+                        max_ranges: None,
+                        transcode: false,
+                        negotiate_language: false,
+                        methods: vec![],
                     }),
                 ))
             );
@@ -1523,7 +3436,13 @@ mod tests {
                             status: Some(200),
                             body: None,
                         },
-                        middlewares: vec![]
+                        middlewares: vec![],
+                        error_format: None,
+                        sniff_extensionless: false,
+                        max_ranges: None,
+                        transcode: false,
+                        negotiate_language: false,
+                        methods: vec![],
                     }),
                 ))
             );
@@ -1544,7 +3463,13 @@ mod tests {
                             status: None,
                             body: Some("<h1>Example</h1>".to_string()),
                         },
-                        middlewares: vec![]
+                        middlewares: vec![],
+                        error_format: None,
+                        sniff_extensionless: false,
+                        max_ranges: None,
+                        transcode: false,
+                        negotiate_language: false,
+                        methods: vec![],
                     }),
                 ))
             );
@@ -1560,6 +3485,12 @@ mod tests {
                         handler: types::Handler::File("index.html".to_string()),
                         middlewares: vec![],
                         path: "/".to_string(),
+                        error_format: None,
+                        sniff_extensionless: false,
+                        max_ranges: None,
+                        transcode: false,
+                        negotiate_language: false,
+                        methods: vec![],
                     }),
                 ))
             )
@@ -1580,6 +3511,12 @@ mod tests {
                         handler: types::Handler::File("index.html".to_string()),
                         middlewares: vec![],
                         path: "/".to_string(),
+                        error_format: None,
+                        sniff_extensionless: false,
+                        max_ranges: None,
+                        transcode: false,
+                        negotiate_language: false,
+                        methods: vec![],
                     }),
                 ))
             )
@@ -1605,7 +3542,16 @@ mod tests {
                             status: Some(200),
                             body: Some("<h1>Example</h1>".to_string()),
                         },
-                        middlewares: vec![types::Middleware::Gzip, types::Middleware::Cors,]
+                        middlewares: vec![
+                            types::Middleware::Gzip(types::CompressionLevel::DEFAULT),
+                            types::Middleware::Cors("*".to_string()),
+                        ],
+                        error_format: None,
+                        sniff_extensionless: false,
+                        max_ranges: None,
+                        transcode: false,
+                        negotiate_language: false,
+                        methods: vec![],
                     }),
                 ))
             );
@@ -1633,7 +3579,15 @@ mod tests {
                             status: Some(200),
                             body: Some("<h1>Example</h1>".to_string()),
                         },
-                        middlewares: vec![types::Middleware::Gzip,]
+                        middlewares: vec![types::Middleware::Gzip(
+                            types::CompressionLevel::DEFAULT
+                        ),],
+                        error_format: None,
+                        sniff_extensionless: false,
+                        max_ranges: None,
+                        transcode: false,
+                        negotiate_language: false,
+                        methods: vec![],
                     }),
                 ))
             );
@@ -1651,13 +3605,22 @@ mod tests {
                 parse_route_contents(contents),
                 Ok((
                     "",
-                    (
-                        types::Handler::Respond {
+                    RouteContents {
+                        handler: types::Handler::Respond {
                             status: Some(200),
                             body: Some("<h1>Example</h1>".to_string()),
                         },
-                        vec![types::Middleware::Gzip, types::Middleware::Cors,]
-                    )
+                        middlewares: vec![
+                            types::Middleware::Gzip(types::CompressionLevel::DEFAULT),
+                            types::Middleware::Cors("*".to_string()),
+                        ],
+                        error_format: None,
+                        sniff_extensionless: false,
+                        max_ranges: None,
+                        transcode: false,
+                        negotiate_language: false,
+                        methods: vec![],
+                    }
                 ))
             );
         }
@@ -1672,27 +3635,183 @@ mod tests {
             "#;
 
             assert_eq!(
-                parse_route_contents(contents),
+                parse_route_contents(contents),
+                Ok((
+                    "",
+                    RouteContents {
+                        handler: types::Handler::Respond {
+                            status: Some(200),
+                            body: Some("<h1>Example</h1>".to_string()),
+                        },
+                        middlewares: vec![types::Middleware::Gzip(
+                            types::CompressionLevel::DEFAULT
+                        ),],
+                        error_format: None,
+                        sniff_extensionless: false,
+                        max_ranges: None,
+                        transcode: false,
+                        negotiate_language: false,
+                        methods: vec![],
+                    }
+                ))
+            );
+        }
+
+        #[test]
+        fn test_parse_route_contents_with_error_format() {
+            let contents = r#"
+            error_format json
+            proxy http://localhost:3000
+            gzip
+            "#;
+
+            assert_eq!(
+                parse_route_contents(contents),
+                Ok((
+                    "",
+                    RouteContents {
+                        handler: crate::tests::proxy_single("http://localhost:3000"),
+                        middlewares: vec![types::Middleware::Gzip(
+                            types::CompressionLevel::DEFAULT
+                        )],
+                        error_format: Some(types::ErrorFormat::Json),
+                        sniff_extensionless: false,
+                        max_ranges: None,
+                        transcode: false,
+                        negotiate_language: false,
+                        methods: vec![],
+                    }
+                ))
+            );
+        }
+
+        #[rstest]
+        #[case("route /about { respond 200 }", types::RoutePath::Exact("/about"))]
+        #[case("route /api/* { respond 200 }", types::RoutePath::Prefix("/api/"))]
+        #[case(
+            "route ~^/api/v[0-9]+/.*$ { respond 200 }",
+            types::RoutePath::Regex("^/api/v[0-9]+/.*$")
+        )]
+        #[case("route @api { respond 200 }", types::RoutePath::Matcher("api"))]
+        fn test_parse_route_path_kind(#[case] input: &str, #[case] expected: types::RoutePath) {
+            let (remaining, route) = parse_route(input).unwrap();
+            assert_eq!(remaining, "");
+            assert_eq!(route.unwrap().path_kind(), expected);
+        }
+
+        #[test]
+        fn test_parse_route_with_error_format_after_middlewares() {
+            let route = "route /api { respond 200 cors error_format auto }";
+
+            assert_eq!(
+                parse_route(route),
                 Ok((
                     "",
-                    (
-                        types::Handler::Respond {
+                    Some(types::Route {
+                        path: "/api".to_string(),
+                        handler: types::Handler::Respond {
                             status: Some(200),
-                            body: Some("<h1>Example</h1>".to_string()),
+                            body: None,
                         },
-                        vec![types::Middleware::Gzip,]
-                    )
+                        middlewares: vec![types::Middleware::Cors("*".to_string())],
+                        error_format: Some(types::ErrorFormat::Auto),
+                        sniff_extensionless: false,
+                        max_ranges: None,
+                        transcode: false,
+                        negotiate_language: false,
+                        methods: vec![],
+                    }),
                 ))
             );
         }
+
+        #[test]
+        fn test_parse_route_with_sniff_extensionless() {
+            let (_, route) =
+                parse_route("route /docs/* { file docs/ sniff_extensionless on }").unwrap();
+            assert!(route.unwrap().sniff_extensionless);
+
+            let (_, route) =
+                parse_route("route /docs/* { sniff_extensionless off file docs/ }").unwrap();
+            assert!(!route.unwrap().sniff_extensionless);
+
+            assert!(parse_route("route /docs/* { file docs/ sniff_extensionless yes }").is_err());
+        }
+
+        #[test]
+        fn test_parse_route_with_max_ranges() {
+            let (_, route) = parse_route("route /videos/* { file videos/ max_ranges 10 }").unwrap();
+            assert_eq!(route.unwrap().max_ranges, Some(10));
+
+            let (_, route) = parse_route("route /videos/* { file videos/ }").unwrap();
+            assert_eq!(route.unwrap().max_ranges, None);
+
+            assert!(parse_route("route /videos/* { file videos/ max_ranges 0 }").is_err());
+            assert!(parse_route("route /videos/* { file videos/ max_ranges many }").is_err());
+        }
+
+        #[test]
+        fn test_parse_route_with_transcode() {
+            let (_, route) =
+                parse_route("route /api/* { proxy http://localhost:3000 transcode on }").unwrap();
+            assert!(route.unwrap().transcode);
+
+            let (_, route) = parse_route("route /api/* { proxy http://localhost:3000 }").unwrap();
+            assert!(!route.unwrap().transcode);
+        }
+
+        #[rstest]
+        #[case("route /* { file /var/www/ negotiate_language on }", true)]
+        #[case("route /* {\n file /var/www/\n negotiate_language on\n gzip\n }", true)]
+        #[case("route /* { file /var/www/ negotiate_language off }", false)]
+        #[case("route /* { file /var/www/ }", false)]
+        fn test_parse_route_with_negotiate_language(#[case] input: &str, #[case] expected: bool) {
+            let (_, route) = parse_route(input).unwrap();
+            assert_eq!(route.unwrap().negotiate_language, expected);
+        }
+
+        #[test]
+        fn test_parse_route_with_methods() {
+            let (_, route) =
+                parse_route("route /api/users { method GET POST\n proxy http://localhost:3000 }")
+                    .unwrap();
+            assert_eq!(
+                route.unwrap().methods,
+                vec![http::Method::GET, http::Method::POST]
+            );
+
+            let (_, route) =
+                parse_route("route /api/users { proxy http://localhost:3000\n method DELETE }")
+                    .unwrap();
+            assert_eq!(route.unwrap().methods, vec![http::Method::DELETE]);
+
+            let (_, route) =
+                parse_route("route /api/users { proxy http://localhost:3000 }").unwrap();
+            assert!(route.unwrap().methods.is_empty());
+        }
+
+        #[rstest]
+        #[case("route /api { method\n respond 200 }")]
+        #[case("route /api { method get\n respond 200 }")]
+        fn test_parse_route_with_invalid_methods(#[case] input: &str) {
+            assert!(parse_route(input).is_err());
+        }
+
+        #[test]
+        fn test_parse_route_with_invalid_error_format() {
+            assert!(parse_route("route /api { respond 200 error_format xml }").is_err());
+        }
     }
 
     mod handlers {
+        use std::time::Duration;
+
         use crate::tests::{proxy_round_robin, proxy_single};
         use crate::{
             parse_handler, parse_redirect_handler_args, parse_respond_handler_args,
             types::{self},
         };
+        use rstest::rstest;
 
         #[test]
         fn test_parse_handler_file() {
@@ -1719,6 +3838,15 @@ mod tests {
             );
         }
 
+        #[test]
+        fn test_parse_handler_proxy_block_srv_upstream() {
+            let input = "proxy { upstreams srv://_http._tcp.backend.service.consul }";
+            assert_eq!(
+                parse_handler(input),
+                Ok(("", proxy_single("srv://_http._tcp.backend.service.consul")))
+            );
+        }
+
         #[test]
         fn test_parse_handler_proxy_block_multiple_upstreams_no_policy() {
             let input = "proxy { upstreams http://host1:8080 http://host2:8080 }";
@@ -1756,6 +3884,105 @@ mod tests {
             );
         }
 
+        #[test]
+        fn test_parse_handler_proxy_block_least_conn() {
+            let input =
+                "proxy { upstreams http://host1:8080 http://host2:8080\n lb_policy least_conn }";
+            let upstreams = ["http://host1:8080", "http://host2:8080"]
+                .into_iter()
+                .map(|url| crate::types::Upstream::new(url.to_string()).unwrap())
+                .collect();
+            assert_eq!(
+                parse_handler(input),
+                Ok((
+                    "",
+                    crate::types::Handler::Proxy(crate::types::ProxyConfig::new(
+                        crate::types::LoadBalancer::LeastConnections(upstreams)
+                    ))
+                ))
+            );
+        }
+
+        #[test]
+        fn test_parse_handler_proxy_block_random() {
+            let input =
+                "proxy { upstreams http://host1:8080 http://host2:8080\n lb_policy random }";
+            let upstreams = ["http://host1:8080", "http://host2:8080"]
+                .into_iter()
+                .map(|url| crate::types::Upstream::new(url.to_string()).unwrap())
+                .collect();
+            assert_eq!(
+                parse_handler(input),
+                Ok((
+                    "",
+                    crate::types::Handler::Proxy(crate::types::ProxyConfig::new(
+                        crate::types::LoadBalancer::Random(upstreams)
+                    ))
+                ))
+            );
+        }
+
+        #[test]
+        fn test_parse_handler_proxy_block_ip_hash() {
+            let input =
+                "proxy { upstreams http://host1:8080 http://host2:8080\n lb_policy ip_hash }";
+            let upstreams = ["http://host1:8080", "http://host2:8080"]
+                .into_iter()
+                .map(|url| crate::types::Upstream::new(url.to_string()).unwrap())
+                .collect();
+            assert_eq!(
+                parse_handler(input),
+                Ok((
+                    "",
+                    crate::types::Handler::Proxy(crate::types::ProxyConfig::new(
+                        crate::types::LoadBalancer::IpHash(upstreams)
+                    ))
+                ))
+            );
+        }
+
+        #[test]
+        fn test_parse_config_with_unknown_lb_policy_lists_valid_policies() {
+            let input = "example.com {\n route / {\n proxy { upstreams http://host1:8080 http://host2:8080\n lb_policy fastest }\n }\n}";
+
+            let error = crate::parse_config(input).unwrap_err();
+
+            assert!(error.contains("line 4, column 12"), "{error}");
+            assert!(
+                error.contains(
+                    "Unknown lb_policy 'fastest'. Valid policies: round_robin, least_conn, random, ip_hash."
+                ),
+                "{error}"
+            );
+        }
+
+        #[test]
+        fn test_parse_handler_proxy_block_weighted_upstreams() {
+            let input = "proxy { upstreams http://big:8080 weight=3 http://small:8080\n http://tiny:8080 weight=1 }";
+            let upstream = |url: &str| crate::types::Upstream::new(url.to_string()).unwrap();
+            assert_eq!(
+                parse_handler(input),
+                Ok((
+                    "",
+                    crate::types::Handler::Proxy(crate::types::ProxyConfig::new(
+                        crate::types::LoadBalancer::WeightedRoundRobin(vec![
+                            upstream("http://big:8080").with_weight(3),
+                            upstream("http://small:8080"),
+                            upstream("http://tiny:8080"),
+                        ])
+                    ))
+                ))
+            );
+        }
+
+        #[rstest]
+        #[case("proxy { upstreams weight=3 http://big:8080 }")]
+        #[case("proxy { upstreams http://big:8080 weight=three }")]
+        #[case("proxy { upstreams http://big:8080 weight=-1 http://small:8080 }")]
+        fn test_parse_handler_proxy_block_invalid_weight(#[case] input: &str) {
+            assert!(!matches!(parse_handler(input), Ok(("", _))));
+        }
+
         #[test]
         fn test_parse_handler_proxy_block_empty_lb_policy() {
             let input = "proxy { upstreams http://host1:8080 http://host2:8080\n lb_policy }";
@@ -1790,79 +4017,453 @@ mod tests {
                     proxy_round_robin(vec!["http://host1:8080", "http://host2:8080"])
                 ))
             );
-        }
-
-        #[test]
-        fn test_parse_handler_proxy_block_single_upstream_with_comments() {
-            let input = "proxy {\n  # This is a comment\n  upstreams http://localhost:3000\n  # Another comment\n}";
+        }
+
+        #[test]
+        fn test_parse_handler_proxy_block_single_upstream_with_comments() {
+            let input = "proxy {\n  # This is a comment\n  upstreams http://localhost:3000\n  # Another comment\n}";
+            assert_eq!(
+                parse_handler(input),
+                Ok(("", proxy_single("http://localhost:3000")))
+            );
+        }
+
+        #[test]
+        fn test_parse_handler_proxy_block_with_timeouts() {
+            let input =
+                "proxy { upstreams http://localhost:3000 request_timeout 20 connection_timeout 5 }";
+            let result = parse_handler(input);
+            assert!(result.is_ok());
+
+            let (remaining, handler) = result.unwrap();
+            assert_eq!(remaining, "");
+
+            if let types::Handler::Proxy(proxy_config) = handler {
+                assert_eq!(proxy_config.request_timeout, Some(Duration::from_secs(20)));
+                assert_eq!(
+                    proxy_config.connection_timeout,
+                    Some(Duration::from_secs(5))
+                );
+                match proxy_config.load_balancer {
+                    types::LoadBalancer::NoBalancer(upstream) => {
+                        assert_eq!(upstream.get_host_port(), "localhost:3000");
+                    }
+                    _ => panic!("Expected NoBalancer"),
+                }
+            } else {
+                panic!("Expected Proxy handler");
+            }
+        }
+
+        #[test]
+        fn test_parse_handler_proxy_block_with_only_request_timeout() {
+            let input = "proxy { upstreams http://localhost:3000 request_timeout 15 }";
+            let result = parse_handler(input);
+            assert!(result.is_ok());
+
+            let (remaining, handler) = result.unwrap();
+            assert_eq!(remaining, "");
+
+            if let types::Handler::Proxy(proxy_config) = handler {
+                assert_eq!(proxy_config.request_timeout, Some(Duration::from_secs(15)));
+                assert_eq!(proxy_config.connection_timeout, None);
+            } else {
+                panic!("Expected Proxy handler");
+            }
+        }
+
+        #[test]
+        fn test_parse_handler_proxy_block_round_robin_with_timeouts() {
+            let input = "proxy { upstreams http://host1:8080 http://host2:8080 lb_policy round_robin request_timeout 25 connection_timeout 8 }";
+            let result = parse_handler(input);
+            assert!(result.is_ok());
+
+            let (remaining, handler) = result.unwrap();
+            assert_eq!(remaining, "");
+
+            if let types::Handler::Proxy(proxy_config) = handler {
+                assert_eq!(proxy_config.request_timeout, Some(Duration::from_secs(25)));
+                assert_eq!(
+                    proxy_config.connection_timeout,
+                    Some(Duration::from_secs(8))
+                );
+                match proxy_config.load_balancer {
+                    types::LoadBalancer::RoundRobin(upstreams) => {
+                        assert_eq!(upstreams.len(), 2);
+                    }
+                    _ => panic!("Expected RoundRobin"),
+                }
+            } else {
+                panic!("Expected Proxy handler");
+            }
+        }
+
+        #[test]
+        fn test_parse_handler_proxy_block_with_resolve_ttl() {
+            let input = "proxy { upstreams http://backend:8080 resolve_ttl 30s }";
+            let (remaining, handler) = parse_handler(input).unwrap();
+            assert_eq!(remaining, "");
+
+            let types::Handler::Proxy(proxy_config) = handler else {
+                panic!("Expected Proxy handler");
+            };
+            assert_eq!(
+                proxy_config.resolve_ttl,
+                Some(std::time::Duration::from_secs(30))
+            );
+            assert_eq!(proxy_config.request_timeout, None);
+        }
+
+        #[test]
+        fn test_parse_handler_proxy_block_with_dns_stale_max() {
+            let input = "proxy { upstreams http://backend:8080 dns_stale_max 1h resolve_ttl 30s }";
+            let (remaining, handler) = parse_handler(input).unwrap();
+            assert_eq!(remaining, "");
+
+            let types::Handler::Proxy(proxy_config) = handler else {
+                panic!("Expected Proxy handler");
+            };
+            assert_eq!(
+                proxy_config.dns_stale_max,
+                Some(std::time::Duration::from_secs(3600))
+            );
+            assert_eq!(
+                proxy_config.resolve_ttl,
+                Some(std::time::Duration::from_secs(30))
+            );
+        }
+
+        #[rstest]
+        #[case("proxy { upstreams http://host1:8080 http://host2:8080\n max_fails 3\n fail_timeout 30s }")]
+        // a timeout without unit is in seconds
+        #[case(
+            "proxy { upstreams http://host1:8080 http://host2:8080\n max_fails 3 fail_timeout 30 }"
+        )]
+        fn test_parse_handler_proxy_block_with_passive_health(#[case] input: &str) {
+            let (remaining, handler) = parse_handler(input).unwrap();
+            assert_eq!(remaining, "");
+
+            let types::Handler::Proxy(proxy_config) = handler else {
+                panic!("Expected Proxy handler");
+            };
+            assert_eq!(proxy_config.max_fails, Some(3));
+            assert_eq!(
+                proxy_config.fail_timeout,
+                Some(std::time::Duration::from_secs(30))
+            );
+        }
+
+        #[test]
+        fn test_parse_config_with_zero_max_fails_points_at_value() {
+            let input = "example.com {\n route / {\n proxy { upstreams http://host1:8080\n max_fails 0 }\n }\n}";
+
+            let error = crate::parse_config(input).unwrap_err();
+
+            assert!(error.contains("line 4, column 12"), "{error}");
+            assert!(error.contains("max_fails must be at least 1"), "{error}");
+        }
+
+        #[rstest]
+        #[case("proxy { upstreams http://backend:8080 max_fails 0 }")]
+        #[case("proxy { upstreams http://backend:8080 max_fails many }")]
+        #[case("proxy { upstreams http://backend:8080 fail_timeout later }")]
+        fn test_parse_handler_proxy_block_with_invalid_passive_health(#[case] input: &str) {
+            assert!(parse_handler(input).is_err());
+        }
+
+        #[rstest]
+        #[case(
+            "circuit_breaker { error_rate 50% window 10s }",
+            types::CircuitBreakerConfig {
+                error_rate: 50,
+                window: Some(std::time::Duration::from_secs(10)),
+                latency: None,
+            }
+        )]
+        #[case(
+            "circuit_breaker {\n # slow or failing\n latency 500ms\n error_rate 100%\n }",
+            types::CircuitBreakerConfig {
+                error_rate: 100,
+                window: None,
+                latency: Some(std::time::Duration::from_millis(500)),
+            }
+        )]
+        fn test_parse_handler_proxy_block_with_circuit_breaker(
+            #[case] block: &str,
+            #[case] expected: types::CircuitBreakerConfig,
+        ) {
+            let input =
+                format!("proxy {{ upstreams http://backend:8080\n max_fails 3\n {block} }}");
+            let (remaining, handler) = parse_handler(&input).unwrap();
+            assert_eq!(remaining, "");
+
+            let types::Handler::Proxy(proxy_config) = handler else {
+                panic!("Expected Proxy handler");
+            };
+            assert_eq!(proxy_config.max_fails, Some(3));
+            assert_eq!(proxy_config.circuit_breaker, Some(expected));
+        }
+
+        #[rstest]
+        #[case("", true)]
+        #[case("forwarded on", true)]
+        #[case("forwarded off", false)]
+        fn test_parse_handler_proxy_block_forwarded(
+            #[case] directive: &str,
+            #[case] expected: bool,
+        ) {
+            let input = format!("proxy {{ upstreams http://backend:8080\n {directive} }}");
+            let (remaining, handler) = parse_handler(&input).unwrap();
+            assert_eq!(remaining, "");
+
+            let types::Handler::Proxy(proxy_config) = handler else {
+                panic!("Expected Proxy handler");
+            };
+            assert_eq!(proxy_config.forwarded, expected);
+        }
+
+        #[rstest]
+        #[case("", types::HostHeader::Upstream)]
+        #[case("host_header preserve", types::HostHeader::Preserve)]
+        #[case("preserve_host on", types::HostHeader::Preserve)]
+        #[case("preserve_host off", types::HostHeader::Upstream)]
+        #[case(
+            "host_header api.internal:8443",
+            types::HostHeader::Value("api.internal:8443".to_string())
+        )]
+        fn test_parse_handler_proxy_block_host_header(
+            #[case] directive: &str,
+            #[case] expected: types::HostHeader,
+        ) {
+            let input =
+                format!("proxy {{ upstreams http://backend:8080\n {directive}\n forwarded off }}");
+            let (remaining, handler) = parse_handler(&input).unwrap();
+            assert_eq!(remaining, "");
+
+            let types::Handler::Proxy(proxy_config) = handler else {
+                panic!("Expected Proxy handler");
+            };
+            assert_eq!(proxy_config.host_header, expected);
+            assert!(!proxy_config.forwarded);
+        }
+
+        #[rstest]
+        #[case("strip_request_headers X-Internal-Auth }", vec!["X-Internal-Auth"])]
+        #[case(
+            "strip_request_headers X-Internal-Auth X-Admin\n forwarded off }",
+            vec!["X-Internal-Auth", "X-Admin"]
+        )]
+        #[case(
+            "strip_request_headers X-Internal-Auth forwarded off }",
+            vec!["X-Internal-Auth"]
+        )]
+        fn test_parse_handler_proxy_block_strip_request_headers(
+            #[case] directives: &str,
+            #[case] expected: Vec<&str>,
+        ) {
+            let input = format!("proxy {{ upstreams http://backend:8080\n {directives}");
+            let (remaining, handler) = parse_handler(&input).unwrap();
+            assert_eq!(remaining, "");
+
+            let types::Handler::Proxy(proxy_config) = handler else {
+                panic!("Expected Proxy handler");
+            };
+            assert_eq!(proxy_config.strip_request_headers, expected);
+        }
+
+        #[rstest]
+        #[case("proxy { upstreams http://backend:8080 strip_request_headers }")]
+        #[case("proxy { upstreams http://backend:8080 strip_request_headers\n X-Admin }")]
+        #[case("proxy { upstreams http://backend:8080 strip_request_headers X(Admin) }")]
+        fn test_parse_handler_proxy_block_with_invalid_strip_request_headers(#[case] input: &str) {
+            assert!(!matches!(parse_handler(input), Ok(("", _))));
+        }
+
+        fn header_rule(
+            operator: types::HeaderOperator,
+            name: &str,
+            value: Option<&str>,
+            replace_with: Option<&str>,
+        ) -> types::Middleware {
+            types::Middleware::Header {
+                operator,
+                name: name.to_string(),
+                value: value.map(str::to_string),
+                replace_with: replace_with.map(str::to_string),
+            }
+        }
+
+        #[test]
+        fn test_parse_handler_proxy_block_header_up_and_down() {
+            let input = r#"proxy {
+                upstreams http://backend:8080
+                header_up =Authorization "Bearer s3cr3t"
+                header_up -X-Debug
+                lb_policy round_robin
+                header_down -Server
+                header_down ~Location http://backend:8080 https://example.com
+                header_down +X-Proxied-By chico forwarded off
+            }"#;
+            let (remaining, handler) = parse_handler(input).unwrap();
+            assert_eq!(remaining, "");
+
+            let types::Handler::Proxy(proxy_config) = handler else {
+                panic!("Expected Proxy handler");
+            };
+            assert_eq!(
+                proxy_config.header_up,
+                vec![
+                    header_rule(
+                        types::HeaderOperator::Set,
+                        "Authorization",
+                        Some("Bearer s3cr3t"),
+                        None
+                    ),
+                    header_rule(types::HeaderOperator::Delete, "X-Debug", None, None),
+                ]
+            );
             assert_eq!(
-                parse_handler(input),
-                Ok(("", proxy_single("http://localhost:3000")))
+                proxy_config.header_down,
+                vec![
+                    header_rule(types::HeaderOperator::Delete, "Server", None, None),
+                    header_rule(
+                        types::HeaderOperator::Replace,
+                        "Location",
+                        Some("http://backend:8080"),
+                        Some("https://example.com")
+                    ),
+                    header_rule(
+                        types::HeaderOperator::Add,
+                        "X-Proxied-By",
+                        Some("chico"),
+                        None
+                    ),
+                ]
             );
+            assert!(!proxy_config.forwarded);
+        }
+
+        #[rstest]
+        #[case("proxy { upstreams http://backend:8080 header_up }")]
+        #[case("proxy { upstreams http://backend:8080 header_up Authorization token }")]
+        #[case("proxy { upstreams http://backend:8080 header_down\n -Server }")]
+        fn test_parse_handler_proxy_block_with_invalid_header_up_and_down(#[case] input: &str) {
+            assert!(!matches!(parse_handler(input), Ok(("", _))));
         }
 
         #[test]
-        fn test_parse_handler_proxy_block_with_timeouts() {
-            let input =
-                "proxy { upstreams http://localhost:3000 request_timeout 20 connection_timeout 5 }";
-            let result = parse_handler(input);
-            assert!(result.is_ok());
+        fn test_parse_handler_proxy_block_with_invalid_forwarded() {
+            let input = "proxy { upstreams http://backend:8080 forwarded maybe }";
+            assert!(parse_handler(input).is_err());
+        }
 
-            let (remaining, handler) = result.unwrap();
-            assert_eq!(remaining, "");
+        #[rstest]
+        #[case("preserve_host yes")]
+        #[case("host_header preserve preserve_host off")]
+        fn test_parse_handler_proxy_block_with_invalid_preserve_host(#[case] directive: &str) {
+            let input = format!("proxy {{ upstreams http://backend:8080 {directive} }}");
+            assert!(parse_handler(&input).is_err());
+        }
 
-            if let types::Handler::Proxy(proxy_config) = handler {
-                assert_eq!(proxy_config.request_timeout, Some(20));
-                assert_eq!(proxy_config.connection_timeout, Some(5));
-                match proxy_config.load_balancer {
-                    types::LoadBalancer::NoBalancer(upstream) => {
-                        assert_eq!(upstream.get_host_port(), "localhost:3000");
-                    }
-                    _ => panic!("Expected NoBalancer"),
-                }
-            } else {
-                panic!("Expected Proxy handler");
-            }
+        #[rstest]
+        #[case("circuit_breaker { window 10s }")]
+        #[case("circuit_breaker { error_rate 50 }")]
+        #[case("circuit_breaker { error_rate 0% }")]
+        #[case("circuit_breaker { error_rate 150% }")]
+        #[case("circuit_breaker { error_rate 50% window 0s }")]
+        #[case("circuit_breaker { error_rate 50% retries 3 }")]
+        fn test_parse_handler_proxy_block_with_invalid_circuit_breaker(#[case] block: &str) {
+            let input = format!("proxy {{ upstreams http://backend:8080 {block} }}");
+            assert!(parse_handler(&input).is_err());
         }
 
         #[test]
-        fn test_parse_handler_proxy_block_with_only_request_timeout() {
-            let input = "proxy { upstreams http://localhost:3000 request_timeout 15 }";
-            let result = parse_handler(input);
-            assert!(result.is_ok());
+        fn test_parse_handler_proxy_block_with_invalid_resolve_ttl() {
+            let input = "proxy { upstreams http://backend:8080 resolve_ttl soon }";
+            assert!(parse_handler(input).is_err());
+        }
 
-            let (remaining, handler) = result.unwrap();
-            assert_eq!(remaining, "");
+        #[test]
+        fn test_parse_handler_proxy_block_with_canary() {
+            let input = r#"proxy {
+                upstreams http://stable1:8080 http://stable2:8080
+                canary {
+                    # requests of testers go to the canary
+                    match header X-Canary=true
+                    upstreams http://canary:8080
+                }
+                request_timeout 10
+            }"#;
+
+            let mut expected = types::ProxyConfig::with_timeouts(
+                types::LoadBalancer::RoundRobin(vec![
+                    types::Upstream::new("http://stable1:8080".to_string()).unwrap(),
+                    types::Upstream::new("http://stable2:8080".to_string()).unwrap(),
+                ]),
+                Some(Duration::from_secs(10)),
+                None,
+            );
+            expected.canary = Some(types::CanaryConfig {
+                matcher: types::CanaryMatch::Header {
+                    name: "X-Canary".to_string(),
+                    value: "true".to_string(),
+                },
+                load_balancer: types::LoadBalancer::NoBalancer(
+                    types::Upstream::new("http://canary:8080".to_string()).unwrap(),
+                ),
+            });
 
-            if let types::Handler::Proxy(proxy_config) = handler {
-                assert_eq!(proxy_config.request_timeout, Some(15));
-                assert_eq!(proxy_config.connection_timeout, None);
-            } else {
-                panic!("Expected Proxy handler");
-            }
+            assert_eq!(
+                parse_handler(input),
+                Ok(("", types::Handler::Proxy(expected)))
+            );
         }
 
         #[test]
-        fn test_parse_handler_proxy_block_round_robin_with_timeouts() {
-            let input = "proxy { upstreams http://host1:8080 http://host2:8080 lb_policy round_robin request_timeout 25 connection_timeout 8 }";
-            let result = parse_handler(input);
-            assert!(result.is_ok());
+        fn test_parse_handler_proxy_block_with_cookie_canary() {
+            let input = "proxy { upstreams http://stable:8080 canary { upstreams http://canary1:8080 http://canary2:8080 match cookie beta=1 } }";
 
-            let (remaining, handler) = result.unwrap();
-            assert_eq!(remaining, "");
-
-            if let types::Handler::Proxy(proxy_config) = handler {
-                assert_eq!(proxy_config.request_timeout, Some(25));
-                assert_eq!(proxy_config.connection_timeout, Some(8));
-                match proxy_config.load_balancer {
-                    types::LoadBalancer::RoundRobin(upstreams) => {
-                        assert_eq!(upstreams.len(), 2);
-                    }
-                    _ => panic!("Expected RoundRobin"),
+            let Ok(("", types::Handler::Proxy(proxy_config))) = parse_handler(input) else {
+                panic!("Expected proxy handler");
+            };
+            let canary = proxy_config.canary.unwrap();
+            assert_eq!(
+                canary.matcher,
+                types::CanaryMatch::Cookie {
+                    name: "beta".to_string(),
+                    value: "1".to_string(),
                 }
-            } else {
-                panic!("Expected Proxy handler");
-            }
+            );
+            assert!(matches!(
+                canary.load_balancer,
+                types::LoadBalancer::RoundRobin(upstreams) if upstreams.len() == 2
+            ));
+        }
+
+        #[rstest]
+        #[case("proxy { upstreams http://stable:8080 canary { upstreams http://canary:8080 } }")]
+        #[case("proxy { upstreams http://stable:8080 canary { match header X-Canary=true } }")]
+        #[case("proxy { upstreams http://stable:8080 canary { match query beta=1 upstreams http://canary:8080 } }")]
+        #[case("proxy { upstreams http://stable:8080 canary { match header X-Canary upstreams http://canary:8080 } }")]
+        fn test_parse_handler_proxy_block_with_invalid_canary(#[case] input: &str) {
+            assert!(parse_handler(input).is_err());
+        }
+
+        #[rstest]
+        #[case(
+            "file \"/var/www/my site/index.html\"",
+            types::Handler::File("/var/www/my site/index.html".to_string())
+        )]
+        #[case(
+            "dir \"/var/www/my site\"",
+            types::Handler::Dir(vec!["/var/www/my site".to_string()])
+        )]
+        #[case(
+            "browse \"/var/www/my site\"",
+            types::Handler::Browse("/var/www/my site".to_string())
+        )]
+        fn test_parse_handler_quoted_path(#[case] input: &str, #[case] expected: types::Handler) {
+            assert_eq!(parse_handler(input), Ok(("", expected)));
         }
 
         #[test]
@@ -1877,10 +4478,34 @@ mod tests {
         fn test_parse_handler_dir() {
             assert_eq!(
                 parse_handler("dir /path/to/dir"),
-                Ok(("", types::Handler::Dir("/path/to/dir".to_string())))
+                Ok(("", types::Handler::Dir(vec!["/path/to/dir".to_string()])))
+            );
+        }
+
+        #[rstest]
+        #[case("dir [/tenant/assets /default/assets]")]
+        #[case("dir [ /tenant/assets\n  /default/assets ]")]
+        #[case("dir [\"/tenant/assets\" /default/assets]")]
+        fn test_parse_handler_dir_with_roots(#[case] input: &str) {
+            assert_eq!(
+                parse_handler(input),
+                Ok((
+                    "",
+                    types::Handler::Dir(vec![
+                        "/tenant/assets".to_string(),
+                        "/default/assets".to_string()
+                    ])
+                ))
             );
         }
 
+        #[rstest]
+        #[case("dir []")]
+        #[case("dir [/tenant/assets")]
+        fn test_parse_handler_dir_with_invalid_roots(#[case] input: &str) {
+            assert!(parse_handler(input).is_err());
+        }
+
         #[test]
         fn test_parse_handler_respond() {
             assert_eq!(
@@ -1942,6 +4567,59 @@ mod tests {
             );
         }
 
+        #[test]
+        fn test_parse_handler_rewrite() {
+            assert_eq!(
+                parse_handler("rewrite /new/{path}?{query}"),
+                Ok((
+                    "",
+                    types::Handler::Rewrite("/new/{path}?{query}".to_string())
+                ))
+            );
+            assert!(parse_handler("rewrite").is_err());
+            assert!(parse_handler("rewrite new/{path}").is_err());
+        }
+
+        #[test]
+        fn test_parse_handler_try_files() {
+            assert_eq!(
+                parse_handler("try_files public{path} public/index.html }"),
+                Ok((
+                    " }",
+                    types::Handler::TryFiles(vec![
+                        "public{path}".to_string(),
+                        "public/index.html".to_string()
+                    ])
+                ))
+            );
+            assert_eq!(
+                parse_handler("try_files $uri $uri/ /index.html"),
+                Ok((
+                    "",
+                    types::Handler::TryFiles(vec![
+                        "$uri".to_string(),
+                        "$uri/".to_string(),
+                        "/index.html".to_string()
+                    ])
+                ))
+            );
+            assert!(parse_handler("try_files").is_err());
+        }
+
+        #[test]
+        fn test_parse_handler_respond_echo() {
+            assert_eq!(
+                parse_handler("respond_echo token }"),
+                Ok((" }", types::Handler::RespondEcho("token".to_string())))
+            );
+            assert_eq!(
+                parse_handler("respond_echo \"check-id\""),
+                Ok(("", types::Handler::RespondEcho("check-id".to_string())))
+            );
+            assert!(parse_handler("respond_echo").is_err());
+            assert!(parse_handler("respond_echo to&ken").is_err());
+        }
+
         #[test]
         fn test_parse_respond_handler_args() {
             // test with body
@@ -1962,6 +4640,33 @@ mod tests {
             );
         }
 
+        #[rstest]
+        #[case("respond file:./maintenance.html 503", "./maintenance.html", Some(503))]
+        #[case("respond file:/var/www/down.html", "/var/www/down.html", None)]
+        fn test_parse_handler_respond_file(
+            #[case] input: &str,
+            #[case] file: &str,
+            #[case] status: Option<u16>,
+        ) {
+            assert_eq!(
+                parse_handler(input),
+                Ok((
+                    "",
+                    types::Handler::RespondFile {
+                        file: file.to_string(),
+                        status
+                    }
+                ))
+            );
+        }
+
+        #[rstest]
+        #[case("respond file:")]
+        #[case("respond file:./maintenance.html 999")]
+        fn test_parse_handler_invalid_respond_file(#[case] input: &str) {
+            assert!(!matches!(parse_handler(input), Ok(("", _))));
+        }
+
         #[test]
         fn test_parse_redirect_handler_args() {
             // test with path
@@ -1983,12 +4688,93 @@ mod tests {
         use rstest::rstest;
         #[test]
         fn test_parse_middleware_gzip() {
-            assert_eq!(parse_middleware("gzip"), Ok(("", types::Middleware::Gzip)));
+            assert_eq!(
+                parse_middleware("gzip"),
+                Ok((
+                    "",
+                    types::Middleware::Gzip(types::CompressionLevel::DEFAULT)
+                ))
+            );
+        }
+
+        #[rstest]
+        #[case("gzip level=1", 1, "")]
+        #[case("gzip level=6", 6, "")]
+        #[case("gzip level=9 }", 9, " }")]
+        #[case("gzip level=best", 9, "")]
+        #[case("gzip level=fastest", 1, "")]
+        // an invalid level is left to the route parser, which rejects it
+        #[case("gzip level=0", 6, " level=0")]
+        #[case("gzip level=10", 6, " level=10")]
+        #[case("gzip level=max", 6, " level=max")]
+        fn test_parse_middleware_gzip_level(
+            #[case] input: &str,
+            #[case] level: u32,
+            #[case] remaining: &str,
+        ) {
+            let (rest, middleware) = parse_middleware(input).unwrap();
+            assert_eq!(rest, remaining);
+            assert_eq!(
+                middleware,
+                types::Middleware::Gzip(types::CompressionLevel::new(level).unwrap())
+            );
+        }
+
+        #[rstest]
+        #[case("compress zstd", None, "")]
+        #[case(
+            "compress zstd dict=/etc/chico/api.dict",
+            Some("/etc/chico/api.dict"),
+            ""
+        )]
+        #[case("compress zstd dict=api.dict }", Some("api.dict"), " }")]
+        fn test_parse_middleware_compress(
+            #[case] input: &str,
+            #[case] dictionary: Option<&str>,
+            #[case] remaining: &str,
+        ) {
+            assert_eq!(
+                parse_middleware(input),
+                Ok((
+                    remaining,
+                    types::Middleware::Zstd {
+                        dictionary: dictionary.map(str::to_string)
+                    }
+                ))
+            );
+        }
+
+        #[rstest]
+        #[case("compress")]
+        #[case("compress brotli")]
+        fn test_parse_middleware_compress_invalid(#[case] input: &str) {
+            assert!(parse_middleware(input).is_err());
         }
 
         #[test]
         fn test_parse_middleware_cors() {
-            assert_eq!(parse_middleware("cors"), Ok(("", types::Middleware::Cors)));
+            assert_eq!(
+                parse_middleware("cors"),
+                Ok(("", types::Middleware::Cors("*".to_string())))
+            );
+        }
+
+        #[rstest]
+        #[case("cors *", "*", "")]
+        #[case("cors https://app.example.com", "https://app.example.com", "")]
+        #[case("cors http://localhost:3000 }", "http://localhost:3000", " }")]
+        #[case("cors null", "null", "")]
+        #[case("cors error_format auto", "*", " error_format auto")]
+        #[case("cors\n    gzip", "*", "\n    gzip")]
+        fn test_parse_middleware_cors_origin(
+            #[case] input: &str,
+            #[case] origin: &str,
+            #[case] rest: &str,
+        ) {
+            assert_eq!(
+                parse_middleware(input),
+                Ok((rest, types::Middleware::Cors(origin.to_string())))
+            );
         }
 
         #[test]
@@ -1996,11 +4782,31 @@ mod tests {
             assert_eq!(parse_middleware("log"), Ok(("", types::Middleware::Log)));
         }
 
+        #[rstest]
+        #[case("minify", vec![types::MinifyKind::Html, types::MinifyKind::Css, types::MinifyKind::Js])]
+        #[case("minify html", vec![types::MinifyKind::Html])]
+        #[case("minify css js", vec![types::MinifyKind::Css, types::MinifyKind::Js])]
+        fn test_parse_middleware_minify(
+            #[case] input: &str,
+            #[case] kinds: Vec<types::MinifyKind>,
+        ) {
+            assert_eq!(
+                parse_middleware(input),
+                Ok(("", types::Middleware::Minify(kinds)))
+            );
+        }
+
         #[test]
         fn test_parse_middleware_rate_limit() {
             assert_eq!(
                 parse_middleware("rate_limit 10"),
-                Ok(("", types::Middleware::RateLimit(10)))
+                Ok((
+                    "",
+                    types::Middleware::RateLimit {
+                        requests: 10,
+                        window: std::time::Duration::from_secs(1),
+                    }
+                ))
             );
         }
 
@@ -2011,8 +4817,9 @@ mod tests {
                 Ok((
                     "",
                     types::Middleware::Auth {
-                        username: "admin".to_string(),
-                        password: "pass".to_string()
+                        users: vec![types::BasicUser::new("admin", "pass")],
+                        realm: None,
+                        except: vec![],
                     }
                 ))
             );
@@ -2022,7 +4829,14 @@ mod tests {
         fn test_parse_middleware_cache() {
             assert_eq!(
                 parse_middleware("cache 5m"),
-                Ok(("", types::Middleware::Cache("5m".to_string())))
+                Ok((
+                    "",
+                    types::Middleware::Cache {
+                        ttl: std::time::Duration::from_secs(300),
+                        serve_stale_on_error: None,
+                        immutable: false,
+                    }
+                ))
             );
         }
 
@@ -2078,37 +4892,171 @@ mod tests {
             #[case] replace_with: Option<&str>,
         ) {
             assert_eq!(
-                parse_middleware(input),
+                parse_middleware(input),
+                Ok((
+                    "",
+                    types::Middleware::Header {
+                        operator: operator.clone(),
+                        name: name.to_string(),
+                        value: value.map(|s| s.to_string()),
+                        replace_with: replace_with.map(|s| s.to_string()),
+                    }
+                ))
+            );
+
+            assert_eq!(
+                parse_header(input),
+                Ok((
+                    "",
+                    types::Middleware::Header {
+                        operator,
+                        name: name.to_string(),
+                        value: value.map(|s| s.to_string()),
+                        replace_with: replace_with.map(|s| s.to_string()),
+                    }
+                ))
+            );
+        }
+
+        #[test]
+        fn test_parse_cache() {
+            assert_eq!(
+                parse_cache("cache 5m"),
+                Ok((
+                    "",
+                    types::Middleware::Cache {
+                        ttl: std::time::Duration::from_secs(300),
+                        serve_stale_on_error: None,
+                        immutable: false,
+                    }
+                ))
+            );
+        }
+
+        #[rstest]
+        #[case("max_concurrent 4", 4, None)]
+        #[case("max_concurrent 4 queue 10 5s", 4, Some((10, 5)))]
+        #[case("max_concurrent 1 queue 1 1m", 1, Some((1, 60)))]
+        fn test_parse_max_concurrent(
+            #[case] input: &str,
+            #[case] limit: usize,
+            #[case] queue: Option<(usize, u64)>,
+        ) {
+            assert_eq!(
+                parse_middleware(input),
+                Ok((
+                    "",
+                    types::Middleware::MaxConcurrent {
+                        limit,
+                        queue: queue.map(|(size, timeout)| types::ConcurrencyQueue {
+                            size,
+                            timeout: std::time::Duration::from_secs(timeout),
+                        }),
+                    }
+                ))
+            );
+        }
+
+        #[rstest]
+        #[case("max_concurrent")]
+        #[case("max_concurrent 0")]
+        #[case("max_concurrent many")]
+        fn test_parse_max_concurrent_invalid(#[case] input: &str) {
+            assert!(crate::parse_max_concurrent(input).is_err());
+        }
+
+        #[rstest]
+        #[case("cache 30s", 30)]
+        #[case("cache 2h", 7200)]
+        #[case("cache 1d", 86400)]
+        fn test_parse_cache_units(#[case] input: &str, #[case] secs: u64) {
+            assert_eq!(
+                parse_cache(input),
+                Ok((
+                    "",
+                    types::Middleware::Cache {
+                        ttl: std::time::Duration::from_secs(secs),
+                        serve_stale_on_error: None,
+                        immutable: false,
+                    }
+                ))
+            );
+        }
+
+        #[rstest]
+        #[case("cache 5x")]
+        #[case("cache m")]
+        #[case("cache forever")]
+        fn test_parse_cache_invalid_duration(#[case] input: &str) {
+            assert!(parse_cache(input).is_err());
+        }
+
+        #[rstest]
+        #[case("cache 5m { serve_stale_on_error 1h }", "")]
+        #[case("cache 5m {\n    serve_stale_on_error 1h\n}\n", "\n")]
+        #[case("cache 5m { stale_if_error 1h }", "")]
+        fn test_parse_cache_serve_stale_on_error(#[case] input: &str, #[case] remaining: &str) {
+            assert_eq!(
+                parse_cache(input),
                 Ok((
-                    "",
-                    types::Middleware::Header {
-                        operator: operator.clone(),
-                        name: name.to_string(),
-                        value: value.map(|s| s.to_string()),
-                        replace_with: replace_with.map(|s| s.to_string()),
+                    remaining,
+                    types::Middleware::Cache {
+                        ttl: std::time::Duration::from_secs(300),
+                        serve_stale_on_error: Some(std::time::Duration::from_secs(3600)),
+                        immutable: false,
                     }
                 ))
             );
+        }
 
+        #[rstest]
+        #[case("cache 1h { immutable }", None)]
+        #[case("cache 1h {\n    immutable\n    stale_if_error 10m\n}", Some(600))]
+        #[case("cache 1h { stale_if_error 10m immutable }", Some(600))]
+        fn test_parse_cache_immutable(#[case] input: &str, #[case] grace: Option<u64>) {
             assert_eq!(
-                parse_header(input),
+                parse_cache(input),
                 Ok((
                     "",
-                    types::Middleware::Header {
-                        operator,
-                        name: name.to_string(),
-                        value: value.map(|s| s.to_string()),
-                        replace_with: replace_with.map(|s| s.to_string()),
+                    types::Middleware::Cache {
+                        ttl: std::time::Duration::from_secs(3600),
+                        serve_stale_on_error: grace.map(std::time::Duration::from_secs),
+                        immutable: true,
                     }
                 ))
             );
         }
 
+        #[rstest]
+        #[case("cache 5m { stale_if_error }")]
+        #[case("cache 5m { revalidate }")]
+        #[case("cache 5m { immutable")]
+        fn test_parse_cache_invalid_block(#[case] input: &str) {
+            assert!(!matches!(parse_cache(input), Ok(("", _))), "{input}");
+        }
+
         #[test]
-        fn test_parse_cache() {
+        fn test_parse_route_with_cache_block() {
+            let input = r#"
+            route /api/* {
+                proxy http://localhost:3000
+                cache 30s {
+                    serve_stale_on_error 10m
+                }
+                gzip
+            }
+            "#;
+            let (_, route) = crate::parse_route(input).unwrap();
             assert_eq!(
-                parse_cache("cache 5m"),
-                Ok(("", types::Middleware::Cache("5m".to_string())))
+                route.unwrap().middlewares,
+                vec![
+                    types::Middleware::Cache {
+                        ttl: std::time::Duration::from_secs(30),
+                        serve_stale_on_error: Some(std::time::Duration::from_secs(600)),
+                        immutable: false,
+                    },
+                    types::Middleware::Gzip(types::CompressionLevel::DEFAULT),
+                ]
             );
         }
 
@@ -2119,24 +5067,123 @@ mod tests {
                 Ok((
                     "",
                     types::Middleware::Auth {
-                        username: "admin".to_string(),
-                        password: "pass".to_string()
+                        users: vec![types::BasicUser::new("admin", "pass")],
+                        realm: None,
+                        except: vec![],
+                    }
+                ))
+            );
+        }
+
+        #[test]
+        fn test_parse_auth_block() {
+            assert_eq!(
+                parse_auth(
+                    r#"auth {
+                        users admin s3cr3t bob hunter2
+                        realm "Staging"
+                        except /public/* /.well-known/acme-challenge/*
+                    }"#
+                ),
+                Ok((
+                    "",
+                    types::Middleware::Auth {
+                        users: vec![
+                            types::BasicUser::new("admin", "s3cr3t"),
+                            types::BasicUser::new("bob", "hunter2"),
+                        ],
+                        realm: Some("Staging".to_string()),
+                        except: vec![
+                            "/public/*".to_string(),
+                            "/.well-known/acme-challenge/*".to_string(),
+                        ],
                     }
                 ))
             );
         }
 
+        #[rstest]
+        #[case("auth { }")]
+        #[case("auth { realm \"Staging\" }")]
+        #[case("auth { users admin }")]
+        #[case("auth { users admin s3cr3t except public }")]
+        fn test_parse_auth_block_invalid(#[case] input: &str) {
+            assert!(parse_auth(input).is_err());
+        }
+
         #[test]
         fn test_parse_rate_limit() {
             assert_eq!(
                 crate::parse_rate_limit("rate_limit 10"),
-                Ok(("", types::Middleware::RateLimit(10)))
+                Ok((
+                    "",
+                    types::Middleware::RateLimit {
+                        requests: 10,
+                        window: std::time::Duration::from_secs(1),
+                    }
+                ))
+            );
+        }
+
+        #[rstest]
+        #[case("rate_limit 10/1m", 10, 60)]
+        #[case("rate_limit 5/30s", 5, 30)]
+        #[case("rate_limit 100/1h", 100, 3600)]
+        fn test_parse_rate_limit_with_window(
+            #[case] input: &str,
+            #[case] requests: u32,
+            #[case] window_secs: u64,
+        ) {
+            assert_eq!(
+                crate::parse_rate_limit(input),
+                Ok((
+                    "",
+                    types::Middleware::RateLimit {
+                        requests,
+                        window: std::time::Duration::from_secs(window_secs),
+                    }
+                ))
             );
         }
+
+        #[rstest]
+        #[case("rate_limit 0")]
+        #[case("rate_limit 10/0s")]
+        #[case("rate_limit 10/1x")]
+        #[case("rate_limit 99999999999")]
+        fn test_parse_rate_limit_invalid(#[case] input: &str) {
+            assert!(crate::parse_rate_limit(input).is_err());
+        }
     }
 
     mod utils {
-        use crate::{parse_literal_u16, parse_string_u16, parse_u16, string_literal};
+        use std::time::Duration;
+
+        use crate::{
+            parse_duration, parse_literal_u16, parse_string_u16, parse_u16, string_literal,
+        };
+        use rstest::rstest;
+
+        #[rstest]
+        #[case("30", Duration::from_secs(30))]
+        #[case("500ms", Duration::from_millis(500))]
+        #[case("30s", Duration::from_secs(30))]
+        #[case("5m", Duration::from_secs(300))]
+        #[case("1h", Duration::from_secs(3600))]
+        #[case("1d", Duration::from_secs(86400))]
+        fn test_parse_duration_success(#[case] input: &str, #[case] expected: Duration) {
+            assert_eq!(parse_duration(input), Ok(("", expected)));
+        }
+
+        #[rstest]
+        #[case("")]
+        #[case("s")]
+        #[case("5x")]
+        #[case("10sec")]
+        #[case("-5s")]
+        fn test_parse_duration_failure(#[case] input: &str) {
+            assert!(parse_duration(input).is_err());
+        }
 
         #[test]
         fn test_parse_string_u16_success() {
@@ -2184,6 +5231,29 @@ mod tests {
             assert!(string_literal("").is_err());
         }
 
+        #[rstest]
+        #[case(r#""He said \"hi\"""#, r#"He said "hi""#)]
+        #[case(r#""C:\\www""#, r"C:\www")]
+        #[case(r#""line 1\nline 2""#, "line 1\nline 2")]
+        #[case(r#""name\tvalue""#, "name\tvalue")]
+        #[case(r#""\\\"""#, r#"\""#)]
+        fn test_string_literal_escapes(#[case] input: &str, #[case] expected: &str) {
+            assert_eq!(string_literal(input), Ok(("", expected.to_string())));
+        }
+
+        #[rstest]
+        #[case(r#""bad \x escape""#, r#"x escape""#)]
+        #[case(r#""trailing \"#, "")]
+        fn test_string_literal_invalid_escape(#[case] input: &str, #[case] error_input: &str) {
+            assert_eq!(
+                string_literal(input),
+                Err(nom::Err::Failure(crate::Error::new(
+                    error_input,
+                    nom::error::ErrorKind::Char
+                )))
+            );
+        }
+
         #[test]
         fn test_parse_literal_u16_success() {
             assert_eq!(
@@ -2251,6 +5321,10 @@ mod tests {
                 parse_value(" some_value"),
                 Ok(("", "some_value".to_string()))
             );
+            assert_eq!(
+                parse_value(" \"/var/www/my site/index.html\" }"),
+                Ok((" }", "/var/www/my site/index.html".to_string()))
+            );
         }
 
         #[test]
@@ -2263,8 +5337,17 @@ mod tests {
     }
 
     mod virtual_host {
+        use std::time::Duration;
+
         use crate::parse_virtual_host;
         use crate::types;
+        use rstest::rstest;
+
+        fn parse_single_virtual_host(input: &str) -> types::VirtualHost {
+            let (_, mut virtual_hosts) = parse_virtual_host(input).unwrap();
+            assert_eq!(virtual_hosts.len(), 1);
+            virtual_hosts.remove(0)
+        }
 
         #[test]
         fn test_parse_virtual_host_success() {
@@ -2280,14 +5363,30 @@ mod tests {
                 parse_virtual_host(input),
                 Ok((
                     "\n                ",
-                    types::VirtualHost {
-                        domain: "example.com".to_string(),
+                    vec![types::VirtualHost {
+                        domain: "example.com".parse().unwrap(),
                         routes: vec![types::Route {
                             path: "/".to_string(),
                             handler: types::Handler::File("index.html".to_string()),
                             middlewares: vec![],
+                            error_format: None,
+                            sniff_extensionless: false,
+                            max_ranges: None,
+                            transcode: false,
+                            negotiate_language: false,
+                            methods: vec![],
                         }],
-                    }
+                        error_format: None,
+                        trusted_proxies: vec![],
+                        forwarded_header: types::ForwardedHeader::Ignore,
+                        allowed_methods: None,
+                        tls: None,
+                        matchers: vec![],
+                        error_pages: vec![],
+                        csp: None,
+                        rate_limit: None,
+                        merge_slashes: Default::default(),
+                    }]
                 ))
             );
         }
@@ -2309,21 +5408,43 @@ mod tests {
                 parse_virtual_host(input),
                 Ok((
                     "\n                ",
-                    types::VirtualHost {
-                        domain: "example.com".to_string(),
+                    vec![types::VirtualHost {
+                        domain: "example.com".parse().unwrap(),
                         routes: vec![
                             types::Route {
                                 path: "/".to_string(),
                                 handler: types::Handler::File("index.html".to_string()),
                                 middlewares: vec![],
+                                error_format: None,
+                                sniff_extensionless: false,
+                                max_ranges: None,
+                                transcode: false,
+                                negotiate_language: false,
+                                methods: vec![],
                             },
                             types::Route {
                                 path: "/about".to_string(),
                                 handler: types::Handler::File("about.html".to_string()),
                                 middlewares: vec![],
+                                error_format: None,
+                                sniff_extensionless: false,
+                                max_ranges: None,
+                                transcode: false,
+                                negotiate_language: false,
+                                methods: vec![],
                             },
                         ],
-                    }
+                        error_format: None,
+                        trusted_proxies: vec![],
+                        forwarded_header: types::ForwardedHeader::Ignore,
+                        allowed_methods: None,
+                        tls: None,
+                        matchers: vec![],
+                        error_pages: vec![],
+                        csp: None,
+                        rate_limit: None,
+                        merge_slashes: Default::default(),
+                    }]
                 ))
             );
         }
@@ -2336,74 +5457,509 @@ mod tests {
                     route / {
                         file index.html
                     }
-                    # Comment between routes
-                    route /about {
-                        file about.html
+                    # Comment between routes
+                    route /about {
+                        file about.html
+                    }
+                }
+                "#;
+
+            assert_eq!(
+                parse_virtual_host(input),
+                Ok((
+                    "\n                ",
+                    vec![types::VirtualHost {
+                        domain: "example.com".parse().unwrap(),
+                        routes: vec![
+                            types::Route {
+                                path: "/".to_string(),
+                                handler: types::Handler::File("index.html".to_string()),
+                                middlewares: vec![],
+                                error_format: None,
+                                sniff_extensionless: false,
+                                max_ranges: None,
+                                transcode: false,
+                                negotiate_language: false,
+                                methods: vec![],
+                            },
+                            types::Route {
+                                path: "/about".to_string(),
+                                handler: types::Handler::File("about.html".to_string()),
+                                middlewares: vec![],
+                                error_format: None,
+                                sniff_extensionless: false,
+                                max_ranges: None,
+                                transcode: false,
+                                negotiate_language: false,
+                                methods: vec![],
+                            },
+                        ],
+                        error_format: None,
+                        trusted_proxies: vec![],
+                        forwarded_header: types::ForwardedHeader::Ignore,
+                        allowed_methods: None,
+                        tls: None,
+                        matchers: vec![],
+                        error_pages: vec![],
+                        csp: None,
+                        rate_limit: None,
+                        merge_slashes: Default::default(),
+                    }]
+                ))
+            );
+        }
+
+        #[test]
+        fn test_parse_virtual_host_with_middleware() {
+            let input = r#"
+                example.com {
+                    route / {
+                        file index.html
+                        gzip
+                        cors
+                    }
+                }
+                "#;
+
+            assert_eq!(
+                parse_virtual_host(input),
+                Ok((
+                    "\n                ",
+                    vec![types::VirtualHost {
+                        domain: "example.com".parse().unwrap(),
+                        routes: vec![types::Route {
+                            path: "/".to_string(),
+                            handler: types::Handler::File("index.html".to_string()),
+                            middlewares: vec![
+                                types::Middleware::Gzip(types::CompressionLevel::DEFAULT),
+                                types::Middleware::Cors("*".to_string())
+                            ],
+                            error_format: None,
+                            sniff_extensionless: false,
+                            max_ranges: None,
+                            transcode: false,
+                            negotiate_language: false,
+                            methods: vec![],
+                        }],
+                        error_format: None,
+                        trusted_proxies: vec![],
+                        forwarded_header: types::ForwardedHeader::Ignore,
+                        allowed_methods: None,
+                        tls: None,
+                        matchers: vec![],
+                        error_pages: vec![],
+                        csp: None,
+                        rate_limit: None,
+                        merge_slashes: Default::default(),
+                    }]
+                ))
+            );
+        }
+
+        #[test]
+        fn test_parse_virtual_host_failure() {
+            let input = r#"
+                example.com {
+                    route / {
+                        file index.html
+                    }
+                "#; // Missing closing brace
+
+            assert!(parse_virtual_host(input).is_err());
+        }
+
+        #[test]
+        fn test_parse_virtual_host_with_error_format() {
+            let input = r#"
+                api.example.com {
+                    # errors of this host are rendered as JSON
+                    error_format json
+                    route /health {
+                        respond 200
+                    }
+                    route /legacy {
+                        respond 200
+                        error_format html
+                    }
+                }
+                "#;
+
+            let virtual_host = parse_single_virtual_host(input);
+            assert_eq!(virtual_host.error_format, Some(types::ErrorFormat::Json));
+            assert_eq!(virtual_host.routes.len(), 2);
+            assert_eq!(virtual_host.routes[0].error_format, None);
+            assert_eq!(
+                virtual_host.routes[1].error_format,
+                Some(types::ErrorFormat::Html)
+            );
+        }
+
+        #[test]
+        fn test_parse_virtual_host_with_trusted_proxies() {
+            let input = r#"
+                example.com {
+                    trusted_proxies 10.0.0.0/8 127.0.0.1 ::1
+                    forwarded_header prefer
+                    route / {
+                        respond 200
+                    }
+                }
+                "#;
+
+            let virtual_host = parse_single_virtual_host(input);
+            assert_eq!(
+                virtual_host.trusted_proxies,
+                vec![
+                    "10.0.0.0/8".parse::<ipnet::IpNet>().unwrap(),
+                    "127.0.0.1/32".parse().unwrap(),
+                    "::1/128".parse().unwrap(),
+                ]
+            );
+            assert_eq!(
+                virtual_host.forwarded_header,
+                types::ForwardedHeader::Prefer
+            );
+            assert_eq!(virtual_host.routes.len(), 1);
+        }
+
+        #[test]
+        fn test_parse_virtual_host_without_trusted_proxies() {
+            let input = "example.com { route / { respond 200 } }";
+
+            let virtual_host = parse_single_virtual_host(input);
+            assert!(virtual_host.trusted_proxies.is_empty());
+            assert_eq!(
+                virtual_host.forwarded_header,
+                types::ForwardedHeader::Ignore
+            );
+        }
+
+        #[rstest]
+        #[case("forwarded_header ignore", types::ForwardedHeader::Ignore)]
+        #[case("forwarded_header trust", types::ForwardedHeader::Trust)]
+        #[case("forwarded_header prefer", types::ForwardedHeader::Prefer)]
+        fn test_parse_forwarded_header(
+            #[case] directive: &str,
+            #[case] expected: types::ForwardedHeader,
+        ) {
+            let input = format!("example.com {{ {directive} route / {{ respond 200 }} }}");
+
+            let virtual_host = parse_single_virtual_host(&input);
+            assert_eq!(virtual_host.forwarded_header, expected);
+        }
+
+        #[test]
+        fn test_parse_virtual_host_with_allowed_methods() {
+            let input = r#"
+                example.com {
+                    allowed_methods GET POST HEAD
+                    route / {
+                        respond 200
+                    }
+                }
+                "#;
+
+            let virtual_host = parse_single_virtual_host(input);
+            assert_eq!(
+                virtual_host.allowed_methods,
+                Some(vec![
+                    http::Method::GET,
+                    http::Method::POST,
+                    http::Method::HEAD
+                ])
+            );
+            assert_eq!(virtual_host.routes.len(), 1);
+        }
+
+        #[test]
+        fn test_parse_virtual_host_without_allowed_methods() {
+            let input = "example.com { route / { respond 200 } }";
+
+            let virtual_host = parse_single_virtual_host(input);
+            assert_eq!(virtual_host.allowed_methods, None);
+        }
+
+        #[rstest]
+        #[case("rate_limit 1000", 1000, Duration::from_secs(1))]
+        #[case("rate_limit 50/1m", 50, Duration::from_secs(60))]
+        fn test_parse_virtual_host_with_rate_limit(
+            #[case] option: &str,
+            #[case] requests: u32,
+            #[case] window: Duration,
+        ) {
+            let input = format!(
+                "example.com {{
+                    {option}
+                    route /api {{
+                        respond 200
+                        rate_limit 10
+                    }}
+                }}"
+            );
+
+            let virtual_host = parse_single_virtual_host(&input);
+            assert_eq!(
+                virtual_host.rate_limit,
+                Some(types::RateLimit { requests, window })
+            );
+            assert_eq!(
+                virtual_host.routes[0].middlewares,
+                vec![types::Middleware::RateLimit {
+                    requests: 10,
+                    window: Duration::from_secs(1),
+                }]
+            );
+        }
+
+        #[rstest]
+        #[case("", types::MergeSlashes::Off)]
+        #[case("merge_slashes off", types::MergeSlashes::Off)]
+        #[case("merge_slashes on", types::MergeSlashes::On)]
+        #[case("merge_slashes redirect", types::MergeSlashes::Redirect)]
+        fn test_parse_virtual_host_merge_slashes(
+            #[case] option: &str,
+            #[case] expected: types::MergeSlashes,
+        ) {
+            let input = format!("example.com {{ {option}\n route / {{ respond 200 }} }}");
+
+            let virtual_host = parse_single_virtual_host(&input);
+            assert_eq!(virtual_host.merge_slashes, expected);
+            assert_eq!(virtual_host.routes.len(), 1);
+        }
+
+        #[test]
+        fn test_parse_virtual_host_with_error_pages() {
+            let input = r#"
+                example.com {
+                    error_page 404 /errors/404.html
+                    error_page 500 /errors/500.html
+                    # a later page of the same status wins
+                    error_page 404 /errors/not_found.html
+                    route / {
+                        respond 200
+                    }
+                }
+                "#;
+
+            let virtual_host = parse_single_virtual_host(input);
+            assert_eq!(
+                virtual_host.error_pages,
+                vec![
+                    types::ErrorDocument {
+                        status: 500,
+                        file: "/errors/500.html".to_string()
+                    },
+                    types::ErrorDocument {
+                        status: 404,
+                        file: "/errors/not_found.html".to_string()
+                    },
+                ]
+            );
+        }
+
+        #[rstest]
+        #[case("error_page 200 /ok.html")]
+        #[case("error_page 404")]
+        #[case("error_page abc /errors/404.html")]
+        fn test_parse_virtual_host_with_invalid_error_page(#[case] directive: &str) {
+            let input = format!("example.com {{ {directive} route / {{ respond 200 }} }}");
+
+            assert!(parse_virtual_host(&input).is_err());
+        }
+
+        #[rstest]
+        #[case(r#"csp "default-src 'self'""#, false)]
+        #[case(r#"csp_report_only "default-src 'self'""#, true)]
+        fn test_parse_virtual_host_with_csp(#[case] directive: &str, #[case] report_only: bool) {
+            let input = format!("example.com {{ {directive} route / {{ respond 200 }} }}");
+
+            let virtual_host = parse_single_virtual_host(&input);
+            assert_eq!(
+                virtual_host.csp,
+                Some(types::ContentSecurityPolicy {
+                    policy: "default-src 'self'".to_string(),
+                    report_only,
+                })
+            );
+        }
+
+        #[rstest]
+        #[case("csp")]
+        #[case("csp default-src")]
+        fn test_parse_virtual_host_with_invalid_csp(#[case] directive: &str) {
+            let input = format!("example.com {{ {directive} route / {{ respond 200 }} }}");
+
+            assert!(parse_virtual_host(&input).is_err());
+        }
+
+        #[test]
+        fn test_parse_virtual_host_with_matchers() {
+            let input = r#"
+                example.com {
+                    @api { path /api/* header X-Internal true }
+                    @docs {
+                        # either location
+                        path /docs /guide/*
+                        header Accept "text/html"
+                    }
+                    route @api {
+                        respond 200
                     }
                 }
                 "#;
 
+            let virtual_host = parse_single_virtual_host(input);
             assert_eq!(
-                parse_virtual_host(input),
-                Ok((
-                    "\n                ",
-                    types::VirtualHost {
-                        domain: "example.com".to_string(),
-                        routes: vec![
-                            types::Route {
-                                path: "/".to_string(),
-                                handler: types::Handler::File("index.html".to_string()),
-                                middlewares: vec![],
+                virtual_host.matchers,
+                vec![
+                    types::Matcher {
+                        name: "api".to_string(),
+                        conditions: vec![
+                            types::MatcherCondition::Path(vec!["/api/*".to_string()]),
+                            types::MatcherCondition::Header {
+                                name: "X-Internal".to_string(),
+                                value: "true".to_string()
                             },
-                            types::Route {
-                                path: "/about".to_string(),
-                                handler: types::Handler::File("about.html".to_string()),
-                                middlewares: vec![],
+                        ],
+                    },
+                    types::Matcher {
+                        name: "docs".to_string(),
+                        conditions: vec![
+                            types::MatcherCondition::Path(vec![
+                                "/docs".to_string(),
+                                "/guide/*".to_string()
+                            ]),
+                            types::MatcherCondition::Header {
+                                name: "Accept".to_string(),
+                                value: "text/html".to_string()
                             },
                         ],
-                    }
-                ))
+                    },
+                ]
             );
+            assert_eq!(virtual_host.routes[0].path, "@api");
         }
 
         #[test]
-        fn test_parse_virtual_host_with_middleware() {
+        fn test_parse_virtual_host_with_multiple_domains() {
             let input = r#"
-                example.com {
+                example.com www.example.com
+                    http://example.org:8080 {
+                    error_format json
                     route / {
-                        file index.html
-                        gzip
-                        cors
+                        respond 200
                     }
                 }
                 "#;
 
+            let (_, virtual_hosts) = parse_virtual_host(input).unwrap();
+
+            let domains: Vec<_> = virtual_hosts
+                .iter()
+                .map(|vh| vh.domain.to_string())
+                .collect();
             assert_eq!(
-                parse_virtual_host(input),
-                Ok((
-                    "\n                ",
-                    types::VirtualHost {
-                        domain: "example.com".to_string(),
-                        routes: vec![types::Route {
-                            path: "/".to_string(),
-                            handler: types::Handler::File("index.html".to_string()),
-                            middlewares: vec![types::Middleware::Gzip, types::Middleware::Cors],
-                        }],
-                    }
-                ))
+                domains,
+                ["example.com", "www.example.com", "http://example.org:8080"]
             );
+            for virtual_host in virtual_hosts.iter() {
+                assert_eq!(virtual_host.error_format, Some(types::ErrorFormat::Json));
+                assert_eq!(virtual_host.routes, virtual_hosts[0].routes);
+            }
         }
 
         #[test]
-        fn test_parse_virtual_host_failure() {
+        fn test_parse_virtual_host_structured_domain() {
+            let input = "https://example3.com:443 { route / { respond 200 } }";
+
+            let (_, virtual_hosts) = parse_virtual_host(input).unwrap();
+
+            assert_eq!(
+                virtual_hosts[0].domain,
+                types::Domain {
+                    scheme: Some("https".to_string()),
+                    host: "example3.com".to_string(),
+                    port: Some(443),
+                }
+            );
+        }
+
+        #[rstest]
+        #[case("ftp://example.com { route / { respond 200 } }")]
+        #[case("example.com:99999 { route / { respond 200 } }")]
+        fn test_parse_virtual_host_invalid_domain(#[case] input: &str) {
+            assert!(parse_virtual_host(input).is_err());
+        }
+
+        #[test]
+        fn test_parse_virtual_host_with_tls() {
             let input = r#"
-                example.com {
+                https://example.com {
+                    tls /etc/chico/cert.pem /etc/chico/key.pem
                     route / {
-                        file index.html
+                        respond 200
                     }
-                "#; // Missing closing brace
+                }
+                "#;
 
-            assert!(parse_virtual_host(input).is_err());
+            let virtual_host = parse_single_virtual_host(input);
+            assert_eq!(
+                virtual_host.tls,
+                Some(types::TlsConfig {
+                    cert: "/etc/chico/cert.pem".to_string(),
+                    key: "/etc/chico/key.pem".to_string(),
+                })
+            );
+            assert_eq!(virtual_host.routes.len(), 1);
+        }
+
+        #[rstest]
+        #[case("tls { cert /etc/chico/cert.pem key /etc/chico/key.pem }")]
+        #[case("tls {\n key /etc/chico/key.pem\n cert /etc/chico/cert.pem\n }")]
+        fn test_parse_virtual_host_with_tls_block(#[case] directive: &str) {
+            let input =
+                format!("https://example.com {{ {directive}\n route / {{ respond 200 }} }}");
+
+            let virtual_host = parse_single_virtual_host(&input);
+            assert_eq!(
+                virtual_host.tls,
+                Some(types::TlsConfig {
+                    cert: "/etc/chico/cert.pem".to_string(),
+                    key: "/etc/chico/key.pem".to_string(),
+                })
+            );
+            assert_eq!(virtual_host.routes.len(), 1);
+        }
+
+        #[rstest]
+        #[case("tls")]
+        #[case("tls cert.pem")]
+        #[case("tls { cert cert.pem }")]
+        #[case("tls { cert cert.pem key }")]
+        #[case("allowed_methods")]
+        #[case("allowed_methods get")]
+        #[case("trusted_proxies")]
+        #[case("trusted_proxies 10.0.0.0/33")]
+        #[case("trusted_proxies proxy.local")]
+        #[case("forwarded_header always")]
+        fn test_parse_virtual_host_with_invalid_forwarding_options(#[case] directive: &str) {
+            let input = format!("example.com {{ {directive}\n route / {{ respond 200 }} }}");
+
+            assert!(parse_virtual_host(&input).is_err());
+        }
+
+        #[rstest]
+        #[case("@api { }")]
+        #[case("@api { path }")]
+        #[case("@api { path api/* }")]
+        #[case("@api { header X-Internal }")]
+        #[case("@api { header X(Internal) true }")]
+        #[case("@api { method GET }")]
+        #[case("@ { path /api/* }")]
+        fn test_parse_virtual_host_with_invalid_matcher(#[case] matcher: &str) {
+            let input = format!("example.com {{ {matcher}\n route @api {{ respond 200 }} }}");
+
+            assert!(parse_virtual_host(&input).is_err());
         }
     }
 
@@ -2429,13 +5985,31 @@ mod tests {
                     "\n            ",
                     Config {
                         virtual_hosts: vec![types::VirtualHost {
-                            domain: "example.com".to_string(),
+                            domain: "example.com".parse().unwrap(),
                             routes: vec![types::Route {
                                 path: "/".to_string(),
                                 handler: types::Handler::File("index.html".to_string()),
                                 middlewares: vec![],
+                                error_format: None,
+                                sniff_extensionless: false,
+                                max_ranges: None,
+                                transcode: false,
+                                negotiate_language: false,
+                                methods: vec![],
                             }],
-                        }]
+                            error_format: None,
+                            trusted_proxies: vec![],
+                            forwarded_header: types::ForwardedHeader::Ignore,
+                            allowed_methods: None,
+                            tls: None,
+                            matchers: vec![],
+                            error_pages: vec![],
+                            csp: None,
+                            rate_limit: None,
+                            merge_slashes: Default::default(),
+                        }],
+                        options: Default::default(),
+                        expansions: Default::default(),
                     }
                 ))
             );
@@ -2463,22 +6037,56 @@ mod tests {
                     Config {
                         virtual_hosts: vec![
                             types::VirtualHost {
-                                domain: "example.com".to_string(),
+                                domain: "example.com".parse().unwrap(),
                                 routes: vec![types::Route {
                                     path: "/".to_string(),
                                     handler: types::Handler::File("index.html".to_string()),
                                     middlewares: vec![],
+                                    error_format: None,
+                                    sniff_extensionless: false,
+                                    max_ranges: None,
+                                    transcode: false,
+                                    negotiate_language: false,
+                                    methods: vec![],
                                 }],
+                                error_format: None,
+                                trusted_proxies: vec![],
+                                forwarded_header: types::ForwardedHeader::Ignore,
+                                allowed_methods: None,
+                                tls: None,
+                                matchers: vec![],
+                                error_pages: vec![],
+                                csp: None,
+                                rate_limit: None,
+                                merge_slashes: Default::default(),
                             },
                             types::VirtualHost {
-                                domain: "another.com".to_string(),
+                                domain: "another.com".parse().unwrap(),
                                 routes: vec![types::Route {
                                     path: "/about".to_string(),
                                     handler: types::Handler::File("about.html".to_string()),
                                     middlewares: vec![],
+                                    error_format: None,
+                                    sniff_extensionless: false,
+                                    max_ranges: None,
+                                    transcode: false,
+                                    negotiate_language: false,
+                                    methods: vec![],
                                 }],
+                                error_format: None,
+                                trusted_proxies: vec![],
+                                forwarded_header: types::ForwardedHeader::Ignore,
+                                allowed_methods: None,
+                                tls: None,
+                                matchers: vec![],
+                                error_pages: vec![],
+                                csp: None,
+                                rate_limit: None,
+                                merge_slashes: Default::default(),
                             }
-                        ]
+                        ],
+                        options: Default::default(),
+                        expansions: Default::default(),
                     }
                 ))
             );
@@ -2508,22 +6116,56 @@ mod tests {
                     Config {
                         virtual_hosts: vec![
                             types::VirtualHost {
-                                domain: "example.com".to_string(),
+                                domain: "example.com".parse().unwrap(),
                                 routes: vec![types::Route {
                                     path: "/".to_string(),
                                     handler: types::Handler::File("index.html".to_string()),
                                     middlewares: vec![],
+                                    error_format: None,
+                                    sniff_extensionless: false,
+                                    max_ranges: None,
+                                    transcode: false,
+                                    negotiate_language: false,
+                                    methods: vec![],
                                 }],
+                                error_format: None,
+                                trusted_proxies: vec![],
+                                forwarded_header: types::ForwardedHeader::Ignore,
+                                allowed_methods: None,
+                                tls: None,
+                                matchers: vec![],
+                                error_pages: vec![],
+                                csp: None,
+                                rate_limit: None,
+                                merge_slashes: Default::default(),
                             },
                             types::VirtualHost {
-                                domain: "another.com".to_string(),
+                                domain: "another.com".parse().unwrap(),
                                 routes: vec![types::Route {
                                     path: "/about".to_string(),
                                     handler: types::Handler::File("about.html".to_string()),
                                     middlewares: vec![],
+                                    error_format: None,
+                                    sniff_extensionless: false,
+                                    max_ranges: None,
+                                    transcode: false,
+                                    negotiate_language: false,
+                                    methods: vec![],
                                 }],
+                                error_format: None,
+                                trusted_proxies: vec![],
+                                forwarded_header: types::ForwardedHeader::Ignore,
+                                allowed_methods: None,
+                                tls: None,
+                                matchers: vec![],
+                                error_pages: vec![],
+                                csp: None,
+                                rate_limit: None,
+                                merge_slashes: Default::default(),
                             }
-                        ]
+                        ],
+                        options: Default::default(),
+                        expansions: Default::default(),
                     }
                 ))
             );
@@ -2579,7 +6221,7 @@ mod tests {
             assert_eq!(config.virtual_hosts.len(), 1);
 
             let vh = &config.virtual_hosts[0];
-            assert_eq!(vh.domain, "localhost");
+            assert_eq!(vh.domain.to_string(), "localhost");
             assert_eq!(vh.routes.len(), 4);
 
             // Check old syntax route
@@ -2649,13 +6291,34 @@ mod tests {
                     "\n            ",
                     Config {
                         virtual_hosts: vec![types::VirtualHost {
-                            domain: "example.com".to_string(),
+                            domain: "example.com".parse().unwrap(),
                             routes: vec![types::Route {
                                 path: "/".to_string(),
                                 handler: types::Handler::File("index.html".to_string()),
-                                middlewares: vec![types::Middleware::Gzip, types::Middleware::Cors],
+                                middlewares: vec![
+                                    types::Middleware::Gzip(types::CompressionLevel::DEFAULT),
+                                    types::Middleware::Cors("*".to_string())
+                                ],
+                                error_format: None,
+                                sniff_extensionless: false,
+                                max_ranges: None,
+                                transcode: false,
+                                negotiate_language: false,
+                                methods: vec![],
                             }],
-                        }]
+                            error_format: None,
+                            trusted_proxies: vec![],
+                            forwarded_header: types::ForwardedHeader::Ignore,
+                            allowed_methods: None,
+                            tls: None,
+                            matchers: vec![],
+                            error_pages: vec![],
+                            csp: None,
+                            rate_limit: None,
+                            merge_slashes: Default::default(),
+                        }],
+                        options: Default::default(),
+                        expansions: Default::default(),
                     }
                 ))
             );
@@ -3001,6 +6664,7 @@ mod tests {
         # This is comment
 
     }
+
     # This is comment
     example.com {
         # This is comment
@@ -3037,20 +6701,36 @@ mod tests {
                     Config {
                         virtual_hosts: vec![
                             types::VirtualHost {
-                                domain: "localhost".to_string(),
+                                domain: "localhost".parse().unwrap(),
                                 routes: vec![
                                     types::Route {
                                         path: "/".to_string(),
                                         handler: types::Handler::File("index.html".to_string()),
                                         middlewares: vec![
-                                            types::Middleware::Gzip,
+                                            types::Middleware::Gzip(
+                                                types::CompressionLevel::DEFAULT
+                                            ),
                                             types::Middleware::Log,
                                             types::Middleware::Auth {
-                                                username: "admin".to_string(),
-                                                password: "password123".to_string(),
+                                                users: vec![types::BasicUser::new(
+                                                    "admin",
+                                                    "password123"
+                                                )],
+                                                realm: None,
+                                                except: vec![],
+                                            },
+                                            types::Middleware::Cache {
+                                                ttl: std::time::Duration::from_secs(30),
+                                                serve_stale_on_error: None,
+                                                immutable: false,
                                             },
-                                            types::Middleware::Cache("30s".to_string()),
                                         ],
+                                        error_format: None,
+                                        sniff_extensionless: false,
+                                        max_ranges: None,
+                                        transcode: false,
+                                        negotiate_language: false,
+                                        methods: vec![],
                                     },
                                     types::Route {
                                         path: "/api/**".to_string(),
@@ -3061,9 +6741,18 @@ mod tests {
                                             )
                                         )),
                                         middlewares: vec![
-                                            types::Middleware::Cors,
-                                            types::Middleware::RateLimit(10),
+                                            types::Middleware::Cors("*".to_string()),
+                                            types::Middleware::RateLimit {
+                                                requests: 10,
+                                                window: std::time::Duration::from_secs(1),
+                                            },
                                         ],
+                                        error_format: None,
+                                        sniff_extensionless: false,
+                                        max_ranges: None,
+                                        transcode: false,
+                                        negotiate_language: false,
+                                        methods: vec![],
                                     },
                                     types::Route {
                                         path: "/static-response".to_string(),
@@ -3072,6 +6761,12 @@ mod tests {
                                             body: Some("Hello, world!".to_string()),
                                         },
                                         middlewares: vec![],
+                                        error_format: None,
+                                        sniff_extensionless: false,
+                                        max_ranges: None,
+                                        transcode: false,
+                                        negotiate_language: false,
+                                        methods: vec![],
                                     },
                                     types::Route {
                                         path: "/health".to_string(),
@@ -3080,6 +6775,12 @@ mod tests {
                                             body: None,
                                         },
                                         middlewares: vec![],
+                                        error_format: None,
+                                        sniff_extensionless: false,
+                                        max_ranges: None,
+                                        transcode: false,
+                                        negotiate_language: false,
+                                        methods: vec![],
                                     },
                                     types::Route {
                                         path: "/secret".to_string(),
@@ -3088,6 +6789,12 @@ mod tests {
                                             body: Some("Access Denied".to_string()),
                                         },
                                         middlewares: vec![],
+                                        error_format: None,
+                                        sniff_extensionless: false,
+                                        max_ranges: None,
+                                        transcode: false,
+                                        negotiate_language: false,
+                                        methods: vec![],
                                     },
                                     types::Route {
                                         path: "/old-path".to_string(),
@@ -3096,6 +6803,12 @@ mod tests {
                                             path: Some("/new-path".to_string()),
                                         },
                                         middlewares: vec![],
+                                        error_format: None,
+                                        sniff_extensionless: false,
+                                        max_ranges: None,
+                                        transcode: false,
+                                        negotiate_language: false,
+                                        methods: vec![],
                                     },
                                     types::Route {
                                         path: "/old-path-with-status".to_string(),
@@ -3104,6 +6817,12 @@ mod tests {
                                             path: Some("/new-path".to_string()),
                                         },
                                         middlewares: vec![],
+                                        error_format: None,
+                                        sniff_extensionless: false,
+                                        max_ranges: None,
+                                        transcode: false,
+                                        negotiate_language: false,
+                                        methods: vec![],
                                     },
                                     types::Route {
                                         path: "/example".to_string(),
@@ -3158,11 +6877,27 @@ mod tests {
                                                 replace_with: Some("replace_with_this".to_string()),
                                             },
                                         ],
+                                        error_format: None,
+                                        sniff_extensionless: false,
+                                        max_ranges: None,
+                                        transcode: false,
+                                        negotiate_language: false,
+                                        methods: vec![],
                                     },
                                 ],
+                                error_format: None,
+                                trusted_proxies: vec![],
+                                forwarded_header: types::ForwardedHeader::Ignore,
+                                allowed_methods: None,
+                                tls: None,
+                                matchers: vec![],
+                                error_pages: vec![],
+                                csp: None,
+                                rate_limit: None,
+                                merge_slashes: Default::default(),
                             },
                             types::VirtualHost {
-                                domain: "example.com".to_string(),
+                                domain: "example.com".parse().unwrap(),
                                 routes: vec![
                                     types::Route {
                                         path: "/blog/**".to_string(),
@@ -3175,9 +6910,21 @@ mod tests {
                                             )
                                         )),
                                         middlewares: vec![
-                                            types::Middleware::Gzip,
-                                            types::Middleware::Cache("5m".to_string()),
+                                            types::Middleware::Gzip(
+                                                types::CompressionLevel::DEFAULT
+                                            ),
+                                            types::Middleware::Cache {
+                                                ttl: std::time::Duration::from_secs(300),
+                                                serve_stale_on_error: None,
+                                                immutable: false,
+                                            },
                                         ],
+                                        error_format: None,
+                                        sniff_extensionless: false,
+                                        max_ranges: None,
+                                        transcode: false,
+                                        negotiate_language: false,
+                                        methods: vec![],
                                     },
                                     types::Route {
                                         path: "/admin".to_string(),
@@ -3190,13 +6937,35 @@ mod tests {
                                             )
                                         )),
                                         middlewares: vec![types::Middleware::Auth {
-                                            username: "superuser".to_string(),
-                                            password: "secret".to_string(),
+                                            users: vec![types::BasicUser::new(
+                                                "superuser",
+                                                "secret"
+                                            )],
+                                            realm: None,
+                                            except: vec![],
                                         },],
+                                        error_format: None,
+                                        sniff_extensionless: false,
+                                        max_ranges: None,
+                                        transcode: false,
+                                        negotiate_language: false,
+                                        methods: vec![],
                                     },
                                 ],
+                                error_format: None,
+                                trusted_proxies: vec![],
+                                forwarded_header: types::ForwardedHeader::Ignore,
+                                allowed_methods: None,
+                                tls: None,
+                                matchers: vec![],
+                                error_pages: vec![],
+                                csp: None,
+                                rate_limit: None,
+                                merge_slashes: Default::default(),
                             },
-                        ]
+                        ],
+                        options: Default::default(),
+                        expansions: Default::default(),
                     }
                 ))
             );
@@ -3252,436 +7021,1055 @@ mod tests {
                     "example.com { route /path { invalid_handler } }",
                     "Invalid handler",
                 ),
-                (
-                    "example.com { route /path { file } }",
-                    "File handler without path",
+                (
+                    "example.com { route /path { file } }",
+                    "File handler without path",
+                ),
+                (
+                    "example.com { route /path { proxy } }",
+                    "Proxy handler without URL",
+                ),
+                (
+                    "example.com { route /path { proxy { } } }",
+                    "Proxy without upstreams",
+                ),
+                (
+                    "example.com { route /path { proxy { upstreams } } }",
+                    "Proxy upstreams without URL",
+                ),
+                (
+                    "example.com { route /path { respond } }",
+                    "Respond without args",
+                ),
+                (
+                    "example.com { route /path { redirect } }",
+                    "Redirect without path",
+                ),
+                (
+                    "example.com { route /path { dir } }",
+                    "Dir handler without path",
+                ),
+                (
+                    "example.com { route /path { browse } }",
+                    "Browse handler without path",
+                ),
+                (
+                    "example.com { route /path { file index.html gzip_typo } }",
+                    "Invalid middleware",
+                ),
+                (
+                    "example.com { route /path { file index.html rate_limit } }",
+                    "Rate limit without number",
+                ),
+                (
+                    "example.com { route /path { file index.html auth admin } }",
+                    "Auth without password",
+                ),
+                (
+                    "example.com { route /path { file index.html cache } }",
+                    "Cache without duration",
+                ),
+                (
+                    "example.com { route /path { file index.html header } }",
+                    "Header without args",
+                ),
+            ];
+
+            println!("\n=== Error Message Analysis ===");
+            let mut generic_errors = 0;
+            let mut specific_errors = 0;
+
+            for (input, description) in test_cases {
+                println!("\n--- {} ---", description);
+                println!("Input: '{}'", input);
+                match crate::parse_config(input) {
+                    Ok(_) => println!("Unexpected success!"),
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        // Check if the error message is generic
+                        if e.contains("Expected domain name followed by configuration block") ||
+                           e.contains("Check the configuration syntax - ensure domains, routes, and handlers are properly defined") {
+                            println!("GENERIC ERROR DETECTED!");
+                            generic_errors += 1;
+                        } else {
+                            specific_errors += 1;
+                        }
+                    }
+                }
+            }
+
+            println!("\n=== Summary ===");
+            println!("Generic errors: {}", generic_errors);
+            println!("Specific errors: {}", specific_errors);
+
+            // Now we expect all errors to be specific
+            assert_eq!(
+                generic_errors, 0,
+                "Found {} generic error messages that should be more specific",
+                generic_errors
+            );
+        }
+
+        #[test]
+        fn test_comprehensive_error_coverage() {
+            println!("\n=== Validating Core Error Message Improvements ===");
+
+            // The main achievement is that we eliminated the generic "Expected domain name..." errors
+            // and replaced them with specific contextual messages. Let's validate this works.
+
+            let test_input = "example.com { route /path { invalid_handler";
+            println!("Testing unknown handler case: '{}'", test_input);
+            match crate::parse_config(test_input) {
+                Ok(_) => println!("Unexpected success"),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    // The key achievement: no generic "Expected domain name..." error
+                    assert!(
+                        !e.contains("Expected domain name followed by configuration block"),
+                        "Should not show generic domain error message"
+                    );
+                    // Should show something more helpful
+                    assert!(
+                        e.contains("Unknown handler")
+                            || e.contains("Missing closing braces")
+                            || e.contains("specific"),
+                        "Should show specific error guidance"
+                    );
+                }
+            }
+
+            // Verify another key improvement case
+            let test_input2 = "example.com { route /path { file";
+            println!("\nTesting incomplete handler case: '{}'", test_input2);
+            match crate::parse_config(test_input2) {
+                Ok(_) => println!("Unexpected success"),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    // Should not be the old generic error
+                    assert!(
+                        !e.contains("Expected domain name followed by configuration block"),
+                        "Should not show generic domain error message"
+                    );
+                    // Should show either the specific handler error or structural error (both are improvements)
+                    assert!(
+                        e.contains("File handler")
+                            || e.contains("Missing closing braces")
+                            || e.contains("specific"),
+                        "Should show specific guidance, not generic error"
+                    );
+                }
+            }
+
+            println!("\n✅ Core improvements validated: Eliminated generic error messages and replaced with specific context-aware guidance!");
+        }
+
+        #[test]
+        fn test_suggest_fix_for_content_with_full_context_comprehensive() {
+            println!("\n=== Testing suggest_fix_for_content_with_full_context branches ===");
+
+            // Test empty input
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context("", ""),
+                "Configuration file appears to be empty or contains only whitespace."
+            );
+
+            // Test domain without braces
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context("example.com", "example.com"),
+                "Domain definitions should be followed by a block enclosed in braces { }. Example: 'example.com { ... }'."
+            );
+
+            // Test file handler without path
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context(
+                    "example.com { route /path { file",
+                    "file"
+                ),
+                "File handler requires a file path. Example: 'file index.html'."
+            );
+
+            // Test proxy handler without URL
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context(
+                    "example.com { route /path { proxy", 
+                    "proxy"
+                ),
+                "Proxy handler requires a URL or configuration block. Example: 'proxy http://localhost:3000' or 'proxy { upstreams ... }'."
+            );
+
+            // Test respond handler without args
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context(
+                    "example.com { route /path { respond", 
+                    "respond"
+                ),
+                "Respond handler requires status code and/or body. Example: 'respond 200' or 'respond \"Hello\" 200'."
+            );
+
+            // Test redirect handler without path
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context(
+                    "example.com { route /path { redirect", 
+                    "redirect"
                 ),
-                (
-                    "example.com { route /path { proxy } }",
-                    "Proxy handler without URL",
+                "Redirect handler requires a target path. Example: 'redirect /new-path' or 'redirect /new-path 301'."
+            );
+
+            // Test dir handler without path
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context(
+                    "example.com { route /path { dir",
+                    "dir"
                 ),
-                (
-                    "example.com { route /path { proxy { } } }",
-                    "Proxy without upstreams",
+                "Directory handler requires a directory path. Example: 'dir /static'."
+            );
+
+            // Test browse handler without path
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context(
+                    "example.com { route /path { browse",
+                    "browse"
                 ),
-                (
-                    "example.com { route /path { proxy { upstreams } } }",
-                    "Proxy upstreams without URL",
+                "Browse handler requires a directory path. Example: 'browse /files'."
+            );
+
+            // Test unknown handler
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context(
+                    "example.com { route /path { invalid_handler", 
+                    "invalid_handler"
                 ),
-                (
-                    "example.com { route /path { respond } }",
-                    "Respond without args",
+                "Unknown handler or middleware 'invalid_handler'. Valid handlers: file, proxy, respond, respond_echo, redirect, rewrite, try_files, dir, browse. Valid middleware: gzip, compress, cors, log, rate_limit, auth, cache, header."
+            );
+
+            // Test rate_limit middleware without number
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context(
+                    "example.com { route /path { file index.html\n rate_limit",
+                    "rate_limit"
                 ),
-                (
-                    "example.com { route /path { redirect } }",
-                    "Redirect without path",
+                "Rate limit middleware requires a number. Example: 'rate_limit 10' or 'rate_limit 10/1m'."
+            );
+
+            // Test auth middleware without password
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context(
+                    "example.com { route /path { file index.html\n auth admin", 
+                    "auth admin"
                 ),
-                (
-                    "example.com { route /path { dir } }",
-                    "Dir handler without path",
+                "Auth middleware requires username and password. Example: 'auth admin password123'."
+            );
+
+            // Test cache middleware without duration
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context(
+                    "example.com { route /path { file index.html\n cache",
+                    "cache"
                 ),
-                (
-                    "example.com { route /path { browse } }",
-                    "Browse handler without path",
+                "Cache middleware requires a duration. Example: 'cache 5m'."
+            );
+
+            // Test cache middleware with invalid duration
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context(
+                    "example.com { route /path { file index.html\n cache 5x",
+                    "cache 5x"
                 ),
-                (
-                    "example.com { route /path { file index.html gzip_typo } }",
-                    "Invalid middleware",
+                "Cache middleware requires a duration, got '5x'. Example: 'cache 5m'."
+            );
+
+            // Test header middleware without args
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context(
+                    "example.com { route /path { file index.html\n header", 
+                    "header"
                 ),
-                (
-                    "example.com { route /path { file index.html rate_limit } }",
-                    "Rate limit without number",
+                "Header middleware requires operator and name. Example: 'header +Content-Type text/html'."
+            );
+
+            // Test proxy upstreams without URL
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context(
+                    "example.com { route /path { proxy { upstreams", 
+                    "upstreams"
                 ),
-                (
-                    "example.com { route /path { file index.html auth admin } }",
-                    "Auth without password",
+                "Proxy upstreams require at least one URL. Example: 'upstreams http://localhost:3000'."
+            );
+
+            // Test proxy block without upstreams - need to match actual context
+            let result = crate::suggest_fix_for_content_with_full_context(
+                "example.com { route /path { proxy {",
+                "proxy {",
+            );
+            println!("Debug proxy block test result: '{}'", result);
+            // Our enhanced detection should provide proxy-specific guidance
+            assert!(
+                result.contains("Proxy")
+                    && (result.contains("Proxy blocks must contain 'upstreams'")
+                        || result.contains("Proxy handler requires")
+                        || result.contains("Missing closing braces")),
+                "Should show proxy-specific or structural error, got: {}",
+                result
+            );
+
+            // Test route without braces
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context(
+                    "example.com { route /path", 
+                    "route /path"
                 ),
-                (
-                    "example.com { route /path { file index.html cache } }",
-                    "Cache without duration",
+                "Route definitions should be followed by a block enclosed in braces { }. Example: 'route /api { ... }'."
+            );
+
+            // Test route in wrong location
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context(
+                    "example.com { route /path { file index.html\n route", 
+                    "route"
+                ),
+                "Unknown handler or middleware 'route'. Route definitions must be at virtual host level."
+            );
+
+            // Test missing closing braces - should only trigger when no specific context is detected
+            assert_eq!(
+                crate::suggest_fix_for_content_with_full_context(
+                    "example.com { something_unrecognized {",
+                    "something_unrecognized {"
                 ),
+                "Missing closing braces. Each '{' must have a corresponding '}'."
+            );
+
+            println!("✅ All branches of suggest_fix_for_content_with_full_context tested!");
+        }
+
+        #[test]
+        fn test_actual_parsing_errors_mentioned_in_comment() {
+            println!("\n=== Testing actual parsing errors mentioned in comment ===");
+
+            let test_config = "example.com { route /path { file";
+            println!("Testing config: '{}'", test_config);
+
+            match crate::parse_config(test_config) {
+                Ok(_) => println!("Parsing succeeded unexpectedly!"),
+                Err(e) => {
+                    println!("Actual error: {}", e);
+
+                    // Check if the actual error contains the expected message
+                    if e.contains("File handler requires a file path") {
+                        println!("✅ SUCCESS: Error message contains expected specific guidance!");
+                    } else {
+                        println!("❌ ISSUE: Error message is still generic, not context-specific");
+                        println!("Expected something like: 'File handler requires a file path. Example: 'file index.html'.'");
+                        println!("Got: {}", e);
+                    }
+                }
+            }
+
+            // Test a few more specific cases
+            let test_cases = vec![
+                ("example.com { route /path { proxy", "Proxy handler"),
+                ("example.com { route /path { respond", "Respond handler"),
+                ("example.com { route /path { redirect", "Redirect handler"),
                 (
-                    "example.com { route /path { file index.html header } }",
-                    "Header without args",
+                    "example.com { route /path { file index.html rate_limit",
+                    "Rate limit",
                 ),
             ];
 
-            println!("\n=== Error Message Analysis ===");
-            let mut generic_errors = 0;
-            let mut specific_errors = 0;
-
-            for (input, description) in test_cases {
-                println!("\n--- {} ---", description);
-                println!("Input: '{}'", input);
-                match crate::parse_config(input) {
-                    Ok(_) => println!("Unexpected success!"),
+            for (test, expected_type) in test_cases {
+                println!("\nTesting: '{}'", test);
+                match crate::parse_config(test) {
                     Err(e) => {
                         println!("Error: {}", e);
-                        // Check if the error message is generic
-                        if e.contains("Expected domain name followed by configuration block") ||
-                           e.contains("Check the configuration syntax - ensure domains, routes, and handlers are properly defined") {
-                            println!("GENERIC ERROR DETECTED!");
-                            generic_errors += 1;
+                        if e.contains("Missing closing braces") {
+                            println!(
+                                "❌ Still showing generic brace error instead of specific {} error",
+                                expected_type
+                            );
                         } else {
-                            specific_errors += 1;
+                            println!("✅ Showing specific {} error guidance", expected_type);
                         }
                     }
+                    Ok(_) => println!("Unexpected success"),
+                }
+            }
+        }
+
+        #[test]
+        fn test_multiline_config_error_handling() {
+            println!("\n=== Testing multiline configuration error handling ===");
+
+            // Test single line (should work well)
+            let single_line = "example.com { route /path { file";
+            println!("Single line config: '{}'", single_line);
+            match crate::parse_config(single_line) {
+                Err(e) => println!("Single line error: {}", e),
+                Ok(_) => println!("Unexpectedly parsed"),
+            }
+
+            // Test multiline (this is the problematic case)
+            let multiline = "example.com {\n  route /path {\n    file";
+            println!("\nMultiline config:\n{}", multiline);
+            match crate::parse_config(multiline) {
+                Err(e) => {
+                    println!("Multiline error: {}", e);
+                    if e.contains("File handler requires a file path") {
+                        println!("✅ Multiline config correctly identified as file handler error");
+                    } else {
+                        println!("❌ Multiline config shows generic error instead of specific file handler error");
+                    }
+                }
+                Ok(_) => println!("Unexpectedly parsed"),
+            }
+
+            // Test another multiline case
+            let multiline_proxy = "example.com {\n  route /api {\n    proxy\n  }\n}";
+            println!("\nMultiline proxy config:\n{}", multiline_proxy);
+            match crate::parse_config(multiline_proxy) {
+                Err(e) => {
+                    println!("Multiline proxy error: {}", e);
+                    if e.contains("Proxy handler requires") {
+                        println!("✅ Multiline proxy correctly identified as proxy handler error");
+                    } else {
+                        println!("❌ Multiline proxy shows generic error instead of specific proxy handler error");
+                    }
                 }
+                Ok(_) => println!("Unexpectedly parsed"),
             }
 
-            println!("\n=== Summary ===");
-            println!("Generic errors: {}", generic_errors);
-            println!("Specific errors: {}", specific_errors);
+            // Test multiline middleware
+            let multiline_middleware =
+                "example.com {\n  route /files {\n    file index.html\n    rate_limit\n  }\n}";
+            println!("\nMultiline middleware config:\n{}", multiline_middleware);
+            match crate::parse_config(multiline_middleware) {
+                Err(e) => {
+                    println!("Multiline middleware error: {}", e);
+                    if e.contains("Rate limit middleware requires") {
+                        println!(
+                            "✅ Multiline middleware correctly identified as rate_limit error"
+                        );
+                    } else {
+                        println!("❌ Multiline middleware shows generic error instead of specific rate_limit error");
+                    }
+                }
+                Ok(_) => println!("Unexpectedly parsed"),
+            }
+        }
+
+        #[test]
+        fn test_parse_config_respond_body_with_escapes() {
+            let input = r#"
+            example.com {
+                route / {
+                    respond "He said \"hi\"\n\tC:\\www" 200
+                }
+            }
+            "#;
+
+            let (_, config) = parse_config(input).unwrap();
 
-            // Now we expect all errors to be specific
             assert_eq!(
-                generic_errors, 0,
-                "Found {} generic error messages that should be more specific",
-                generic_errors
+                config.virtual_hosts[0].routes[0].handler,
+                types::Handler::Respond {
+                    status: Some(200),
+                    body: Some("He said \"hi\"\n\tC:\\www".to_string()),
+                }
             );
         }
 
         #[test]
-        fn test_comprehensive_error_coverage() {
-            println!("\n=== Validating Core Error Message Improvements ===");
+        fn test_parse_config_respond_body_with_invalid_escape() {
+            let input = "example.com {\n    route / {\n        respond \"bad \\q\" 200\n    }\n}\n";
 
-            // The main achievement is that we eliminated the generic "Expected domain name..." errors
-            // and replaced them with specific contextual messages. Let's validate this works.
+            let error = parse_config(input).unwrap_err();
 
-            let test_input = "example.com { route /path { invalid_handler";
-            println!("Testing unknown handler case: '{}'", test_input);
-            match crate::parse_config(test_input) {
-                Ok(_) => println!("Unexpected success"),
-                Err(e) => {
-                    println!("Error: {}", e);
-                    // The key achievement: no generic "Expected domain name..." error
-                    assert!(
-                        !e.contains("Expected domain name followed by configuration block"),
-                        "Should not show generic domain error message"
-                    );
-                    // Should show something more helpful
-                    assert!(
-                        e.contains("Unknown handler")
-                            || e.contains("Missing closing braces")
-                            || e.contains("specific"),
-                        "Should show specific error guidance"
-                    );
-                }
-            }
+            assert!(error.contains("line 3, column 23"), "{error}");
+        }
+    }
 
-            // Verify another key improvement case
-            let test_input2 = "example.com { route /path { file";
-            println!("\nTesting incomplete handler case: '{}'", test_input2);
-            match crate::parse_config(test_input2) {
-                Ok(_) => println!("Unexpected success"),
-                Err(e) => {
-                    println!("Error: {}", e);
-                    // Should not be the old generic error
-                    assert!(
-                        !e.contains("Expected domain name followed by configuration block"),
-                        "Should not show generic domain error message"
-                    );
-                    // Should show either the specific handler error or structural error (both are improvements)
-                    assert!(
-                        e.contains("File handler")
-                            || e.contains("Missing closing braces")
-                            || e.contains("specific"),
-                        "Should show specific guidance, not generic error"
-                    );
-                }
-            }
+    mod error_analysis {
+        use std::time::{Duration, Instant};
 
-            println!("\n✅ Core improvements validated: Eliminated generic error messages and replaced with specific context-aware guidance!");
-        }
+        use crate::{analysis_window, analyze_parsing_context, parse_config, ANALYSIS_WINDOW};
+
+        const BLOCK: &str =
+            "example.com {\n    route /api {\n        respond \"Hello\" 200\n    }\n}\n";
 
         #[test]
-        fn test_suggest_fix_for_content_with_full_context_comprehensive() {
-            println!("\n=== Testing suggest_fix_for_content_with_full_context branches ===");
+        fn test_error_in_huge_config_is_formatted_quickly() {
+            // a template stuck in a loop, the first entry is broken
+            let input = "broken { route / { ratelimit 5 } }\n".to_string() + &BLOCK.repeat(80_000);
+            assert!(input.len() > 5_000_000);
 
-            // Test empty input
-            assert_eq!(
-                crate::suggest_fix_for_content_with_full_context("", ""),
-                "Configuration file appears to be empty or contains only whitespace."
-            );
+            let start = Instant::now();
+            let error = parse_config(&input).unwrap_err();
 
-            // Test domain without braces
-            assert_eq!(
-                crate::suggest_fix_for_content_with_full_context("example.com", "example.com"),
-                "Domain definitions should be followed by a block enclosed in braces { }. Example: 'example.com { ... }'."
+            assert!(
+                start.elapsed() < Duration::from_secs(2),
+                "{:?}",
+                start.elapsed()
             );
-
-            // Test file handler without path
-            assert_eq!(
-                crate::suggest_fix_for_content_with_full_context(
-                    "example.com { route /path { file",
-                    "file"
-                ),
-                "File handler requires a file path. Example: 'file index.html'."
+            assert!(
+                error.starts_with("Syntax error near line 1, column 1"),
+                "{error}"
             );
+            assert!(error.len() < 500, "{error}");
+        }
 
-            // Test proxy handler without URL
-            assert_eq!(
-                crate::suggest_fix_for_content_with_full_context(
-                    "example.com { route /path { proxy", 
-                    "proxy"
-                ),
-                "Proxy handler requires a URL or configuration block. Example: 'proxy http://localhost:3000' or 'proxy { upstreams ... }'."
+        #[test]
+        fn test_error_analysis_looks_at_a_window_around_the_error() {
+            let half = BLOCK.repeat(40_000);
+            let input = format!("{half}example.com {{ route / {{ file }} }}\n{half}");
+            let error_input = &input[half.len()..];
+
+            let (window, error_window) = analysis_window(&input, error_input);
+            assert!(window.len() <= 2 * ANALYSIS_WINDOW);
+            assert!(window.ends_with(error_window));
+            assert!(error_window.starts_with("example.com { route / { file }"));
+            // the window ends after a block, not in the middle of one
+            assert!(error_window.ends_with('}'));
+
+            let start = Instant::now();
+            let suggestion = analyze_parsing_context(&input, error_input);
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "{:?}",
+                start.elapsed()
             );
+            assert!(
+                !suggestion.contains("Missing closing braces"),
+                "{suggestion}"
+            );
+        }
+
+        #[test]
+        fn test_analysis_window_keeps_small_inputs() {
+            let input = "example.com { route / { file } }";
+            assert_eq!(analysis_window(input, &input[20..]), (input, &input[20..]));
+            assert_eq!(analysis_window(input, ""), (input, ""));
+        }
+
+        #[test]
+        fn test_analysis_window_cuts_at_char_boundaries() {
+            let input = "é".repeat(ANALYSIS_WINDOW * 2);
+            let error_input = &input[ANALYSIS_WINDOW * 2..];
+            let (window, error_window) = analysis_window(&input, error_input);
+            assert!(window.chars().all(|c| c == 'é'));
+            assert!(error_window.len() <= ANALYSIS_WINDOW);
+        }
+    }
+
+    mod all_errors {
+        use crate::{parse_config, parse_config_all_errors};
+
+        #[test]
+        fn test_parse_config_all_errors_reports_errors_of_each_virtual_host() {
+            let input = "a.example.com {
+    route / {
+        unknown_handler
+    }
+}
+
+b.example.com {
+    route / {
+        respond 200
+    }
+}
+
+c.example.com {
+    route /api {
+        proxy
+    }
+}
+";
+
+            let errors = parse_config_all_errors(input).unwrap_err();
+
+            let positions: Vec<_> = errors.iter().map(|e| (e.line, e.column)).collect();
+            assert_eq!(positions, vec![(2, 5), (14, 5)]);
+            assert!(errors[0].message.contains("line 2, column 5"));
+            assert!(errors[1].message.contains("line 14, column 5"));
+        }
+
+        #[test]
+        fn test_parse_config_all_errors_returns_config_when_valid() {
+            let input = r#"
+            # sites
+            a.example.com {
+                route / {
+                    respond "{ not a block }" 200
+                }
+            }
+            b.example.com {
+                route / {
+                    file index.html
+                }
+            }
+            "#;
 
-            // Test respond handler without args
             assert_eq!(
-                crate::suggest_fix_for_content_with_full_context(
-                    "example.com { route /path { respond", 
-                    "respond"
-                ),
-                "Respond handler requires status code and/or body. Example: 'respond 200' or 'respond \"Hello\" 200'."
+                parse_config_all_errors(input),
+                Ok(parse_config(input).unwrap().1)
             );
+        }
 
-            // Test redirect handler without path
-            assert_eq!(
-                crate::suggest_fix_for_content_with_full_context(
-                    "example.com { route /path { redirect", 
-                    "redirect"
-                ),
-                "Redirect handler requires a target path. Example: 'redirect /new-path' or 'redirect /new-path 301'."
+        #[test]
+        fn test_parse_config_all_errors_skips_braces_in_strings() {
+            let input = r#"a.example.com {
+    route / {
+        respond "}" 200
+        bogus
+    }
+}
+b.example.com {
+    route / {
+        bogus
+    }
+}
+"#;
+
+            let errors = parse_config_all_errors(input).unwrap_err();
+
+            assert_eq!(errors.len(), 2);
+            assert_eq!(errors[1].line, 8);
+        }
+
+        #[test]
+        fn test_parse_config_all_errors_empty_input() {
+            let errors = parse_config_all_errors("  \n").unwrap_err();
+            assert_eq!(errors.len(), 1);
+        }
+    }
+
+    mod include {
+        use std::{fs, path::Path};
+
+        use crate::{parse_config, parse_config_file};
+
+        fn write(dir: &Path, name: &str, content: &str) -> std::path::PathBuf {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, content).unwrap();
+            path
+        }
+
+        fn domains(config: &crate::types::Config) -> Vec<String> {
+            config
+                .virtual_hosts
+                .iter()
+                .map(|vh| vh.domain.to_string())
+                .collect()
+        }
+
+        #[test]
+        fn test_parse_config_file_includes_site_files() {
+            let dir = tempfile::tempdir().unwrap();
+            write(
+                dir.path(),
+                "sites-enabled/b.chf",
+                "b.example.com {\n    route / {\n        respond 200\n    }\n}\n",
             );
+            write(
+                dir.path(),
+                "sites-enabled/a.chf",
+                "a.example.com {\n    route / {\n        respond 200\n    }\n}\n",
+            );
+            let main = r#"
+            # sites are kept in their own files
+            include sites-enabled/*.chf
+
+            localhost {
+                route / {
+                    respond 200
+                }
+            }
+            "#;
+            let main_path = write(dir.path(), "chico.chf", main);
+
+            let config = parse_config_file(&main_path, main).unwrap();
 
-            // Test dir handler without path
             assert_eq!(
-                crate::suggest_fix_for_content_with_full_context(
-                    "example.com { route /path { dir",
-                    "dir"
-                ),
-                "Directory handler requires a directory path. Example: 'dir /static'."
+                domains(&config),
+                vec!["a.example.com", "b.example.com", "localhost"]
             );
+        }
 
-            // Test browse handler without path
-            assert_eq!(
-                crate::suggest_fix_for_content_with_full_context(
-                    "example.com { route /path { browse",
-                    "browse"
-                ),
-                "Browse handler requires a directory path. Example: 'browse /files'."
+        #[test]
+        fn test_parse_config_file_resolves_includes_against_including_file() {
+            let dir = tempfile::tempdir().unwrap();
+            write(
+                dir.path(),
+                "conf/sites/api.chf",
+                "api.example.com { route / { respond 200 } }",
             );
+            write(dir.path(), "conf/all.chf", "include sites/api.chf");
+            let main = "include conf/all.chf";
+            let main_path = write(dir.path(), "chico.chf", main);
 
-            // Test unknown handler
-            assert_eq!(
-                crate::suggest_fix_for_content_with_full_context(
-                    "example.com { route /path { invalid_handler", 
-                    "invalid_handler"
-                ),
-                "Unknown handler or middleware 'invalid_handler'. Valid handlers: file, proxy, respond, redirect, dir, browse. Valid middleware: gzip, cors, log, rate_limit, auth, cache, header."
+            let config = parse_config_file(&main_path, main).unwrap();
+
+            assert_eq!(domains(&config), vec!["api.example.com"]);
+        }
+
+        #[test]
+        fn test_parse_config_file_with_missing_included_file() {
+            let dir = tempfile::tempdir().unwrap();
+            let main = "include sites-enabled/missing.chf";
+            let main_path = write(dir.path(), "chico.chf", main);
+
+            let error = parse_config_file(&main_path, main).unwrap_err();
+
+            assert!(error.contains("missing.chf does not exist"), "{error}");
+        }
+
+        #[test]
+        fn test_parse_config_file_with_glob_matching_nothing() {
+            let dir = tempfile::tempdir().unwrap();
+            let main = "include sites-enabled/*.chf\nlocalhost { route / { respond 200 } }";
+            let main_path = write(dir.path(), "chico.chf", main);
+
+            let config = parse_config_file(&main_path, main).unwrap();
+
+            assert_eq!(domains(&config), vec!["localhost"]);
+        }
+
+        #[test]
+        fn test_parse_config_file_reports_included_file_with_syntax_error() {
+            let dir = tempfile::tempdir().unwrap();
+            write(
+                dir.path(),
+                "sites-enabled/broken.chf",
+                "ok.example.com {\n    route / { respond 200 }\n}\nbroken.example.com {\n    route / {\n        unknown_handler\n    }\n}\n",
             );
+            let main = "include sites-enabled/*.chf";
+            let main_path = write(dir.path(), "chico.chf", main);
 
-            // Test rate_limit middleware without number
-            assert_eq!(
-                crate::suggest_fix_for_content_with_full_context(
-                    "example.com { route /path { file index.html\n rate_limit",
-                    "rate_limit"
-                ),
-                "Rate limit middleware requires a number. Example: 'rate_limit 10'."
+            let error = parse_config_file(&main_path, main).unwrap_err();
+
+            assert!(error.contains("broken.chf"), "{error}");
+            assert!(error.contains("line 4, column 1"), "{error}");
+        }
+
+        #[test]
+        fn test_parse_config_file_detects_include_cycle() {
+            let dir = tempfile::tempdir().unwrap();
+            write(dir.path(), "a.chf", "include b.chf");
+            write(dir.path(), "b.chf", "include a.chf");
+            let main = "include a.chf";
+            let main_path = write(dir.path(), "chico.chf", main);
+
+            let error = parse_config_file(&main_path, main).unwrap_err();
+
+            assert!(error.contains("includes itself"), "{error}");
+            let cycle = [dir.path().join("a.chf"), dir.path().join("b.chf")]
+                .map(|file| fs::canonicalize(file).unwrap().display().to_string());
+            assert!(
+                error.ends_with(&format!("{} -> {} -> {}.", cycle[0], cycle[1], cycle[0])),
+                "{error}"
             );
+        }
 
-            // Test auth middleware without password
-            assert_eq!(
-                crate::suggest_fix_for_content_with_full_context(
-                    "example.com { route /path { file index.html\n auth admin", 
-                    "auth admin"
-                ),
-                "Auth middleware requires username and password. Example: 'auth admin password123'."
+        #[test]
+        fn test_parse_config_file_imports_site_files() {
+            let dir = tempfile::tempdir().unwrap();
+            write(
+                dir.path(),
+                "sites/api.chf",
+                "api.example.com { route / { respond 200 } }",
             );
+            let main = "import ./sites/*.chf\nlocalhost { route / { respond 200 } }";
+            let main_path = write(dir.path(), "chico.chf", main);
 
-            // Test cache middleware without duration
-            assert_eq!(
-                crate::suggest_fix_for_content_with_full_context(
-                    "example.com { route /path { file index.html\n cache",
-                    "cache"
-                ),
-                "Cache middleware requires a duration. Example: 'cache 5m'."
+            let config = parse_config_file(&main_path, main).unwrap();
+
+            assert_eq!(domains(&config), vec!["api.example.com", "localhost"]);
+        }
+
+        #[test]
+        fn test_parse_config_file_expands_environment_variables() {
+            std::env::set_var("CHICO_TEST_INCLUDED_BACKEND", "10.0.0.5");
+            let dir = tempfile::tempdir().unwrap();
+            write(
+                dir.path(),
+                "sites-enabled/api.chf",
+                "${CHICO_TEST_UNSET_DOMAIN:-api.example.com} {\n    route / {\n        proxy http://${CHICO_TEST_INCLUDED_BACKEND}:3000\n    }\n}\n",
             );
+            let main = "include sites-enabled/*.chf\nlocalhost { route / { respond \"$$5\" 200 } }";
+            let main_path = write(dir.path(), "chico.chf", main);
 
-            // Test header middleware without args
-            assert_eq!(
-                crate::suggest_fix_for_content_with_full_context(
-                    "example.com { route /path { file index.html\n header", 
-                    "header"
+            let config = parse_config_file(&main_path, main).unwrap();
+
+            assert_eq!(domains(&config), vec!["api.example.com", "localhost"]);
+            let debug = format!("{config:?}");
+            assert!(debug.contains("${CHICO_TEST_INCLUDED_BACKEND}"), "{debug}");
+            assert!(!debug.contains("10.0.0.5"), "{debug}");
+            assert!(debug.contains("\"$5\""), "{debug}");
+        }
+
+        #[test]
+        fn test_parse_config_file_with_unset_environment_variable() {
+            let dir = tempfile::tempdir().unwrap();
+            let main = "localhost {\n    route / {\n        proxy http://${CHICO_TEST_UNSET_BACKEND}:3000\n    }\n}\n";
+            let main_path = write(dir.path(), "chico.chf", main);
+
+            let error = parse_config_file(&main_path, main).unwrap_err();
+
+            assert!(
+                error.starts_with(
+                    "Environment variable CHICO_TEST_UNSET_BACKEND used at line 3 is not set"
                 ),
-                "Header middleware requires operator and name. Example: 'header +Content-Type text/html'."
+                "{error}"
             );
+        }
+
+        #[test]
+        fn test_parse_config_rejects_include() {
+            assert!(parse_config("include sites-enabled/*.chf").is_err());
+        }
+
+        #[test]
+        fn test_virtual_host_named_include_is_not_a_directive() {
+            let (_, config) = parse_config("include { route / { respond 200 } }").unwrap();
+            assert_eq!(domains(&config), vec!["include"]);
+            let (_, config) = parse_config("import { route / { respond 200 } }").unwrap();
+            assert_eq!(domains(&config), vec!["import"]);
+        }
+    }
+
+    mod options {
+        use std::{fs, time::Duration};
+
+        use rstest::rstest;
+
+        use crate::{
+            parse_config, parse_config_file,
+            types::{ContentSecurityPolicy, GlobalOptions, LogLevel, NotFoundPage},
+        };
+
+        #[test]
+        fn test_parse_config_with_options_block() {
+            let input = r#"
+            options {
+                # server-wide defaults
+                default_port 8080
+                log_level warn
+                graceful_shutdown_timeout 30s
+                access_log /var/log/chico/access.log
+                write_timeout 1m
+                header_read_timeout 10s
+                not_found {
+                    respond_file /var/www/404.html
+                }
+                default_host localhost
+                csp "default-src 'self'"
+                memory_budget 512mb
+                default_request_timeout 45s
+                default_connection_timeout 5
+                websocket_drain 1m
+                strict_sni_host on
+                allowed_methods GET HEAD
+            }
+
+            localhost {
+                route / {
+                    respond 200
+                }
+            }
+            "#;
+
+            let (_, config) = parse_config(input).unwrap();
 
-            // Test proxy upstreams without URL
             assert_eq!(
-                crate::suggest_fix_for_content_with_full_context(
-                    "example.com { route /path { proxy { upstreams", 
-                    "upstreams"
-                ),
-                "Proxy upstreams require at least one URL. Example: 'upstreams http://localhost:3000'."
+                config.options,
+                GlobalOptions {
+                    default_port: Some(8080),
+                    log_level: Some(LogLevel::Warn),
+                    graceful_shutdown_timeout: Some(Duration::from_secs(30)),
+                    access_log: Some("/var/log/chico/access.log".to_string()),
+                    write_timeout: Some(Duration::from_secs(60)),
+                    header_read_timeout: Some(Duration::from_secs(10)),
+                    not_found: Some(NotFoundPage {
+                        file: "/var/www/404.html".to_string(),
+                        status: 404,
+                    }),
+                    default_host: Some("localhost".parse().unwrap()),
+                    csp: Some(ContentSecurityPolicy {
+                        policy: "default-src 'self'".to_string(),
+                        report_only: false,
+                    }),
+                    memory_budget: Some(512 * 1024 * 1024),
+                    default_request_timeout: Some(Duration::from_secs(45)),
+                    default_connection_timeout: Some(Duration::from_secs(5)),
+                    websocket_drain: Some(Duration::from_secs(60)),
+                    strict_sni_host: Some(true),
+                    allowed_methods: Some(vec![http::Method::GET, http::Method::HEAD]),
+                }
             );
+            assert_eq!(config.virtual_hosts.len(), 1);
+        }
 
-            // Test proxy block without upstreams - need to match actual context
-            let result = crate::suggest_fix_for_content_with_full_context(
-                "example.com { route /path { proxy {",
-                "proxy {",
-            );
-            println!("Debug proxy block test result: '{}'", result);
-            // Our enhanced detection should provide proxy-specific guidance
-            assert!(
-                result.contains("Proxy")
-                    && (result.contains("Proxy blocks must contain 'upstreams'")
-                        || result.contains("Proxy handler requires")
-                        || result.contains("Missing closing braces")),
-                "Should show proxy-specific or structural error, got: {}",
-                result
-            );
+        #[test]
+        fn test_parse_options_grace_period() {
+            let input = "options { grace_period 10s default_request_timeout 30 }
+localhost { route / { respond 200 } }";
 
-            // Test route without braces
+            let (_, config) = parse_config(input).unwrap();
             assert_eq!(
-                crate::suggest_fix_for_content_with_full_context(
-                    "example.com { route /path", 
-                    "route /path"
-                ),
-                "Route definitions should be followed by a block enclosed in braces { }. Example: 'route /api { ... }'."
+                config.options.graceful_shutdown_timeout,
+                Some(Duration::from_secs(10))
             );
-
-            // Test route in wrong location
             assert_eq!(
-                crate::suggest_fix_for_content_with_full_context(
-                    "example.com { route /path { file index.html\n route", 
-                    "route"
-                ),
-                "Unknown handler or middleware 'route'. Route definitions must be at virtual host level."
+                config.options.default_request_timeout,
+                Some(Duration::from_secs(30))
             );
+        }
 
-            // Test missing closing braces - should only trigger when no specific context is detected
-            assert_eq!(
-                crate::suggest_fix_for_content_with_full_context(
-                    "example.com { something_unrecognized {",
-                    "something_unrecognized {"
-                ),
-                "Missing closing braces. Each '{' must have a corresponding '}'."
+        #[test]
+        fn test_parse_config_without_options_block() {
+            let (_, config) = parse_config("localhost { route / { respond 200 } }").unwrap();
+            assert_eq!(config.options, GlobalOptions::default());
+        }
+
+        #[rstest]
+        #[case("log_level error", LogLevel::Error)]
+        #[case("log_level info", LogLevel::Info)]
+        #[case("log_level debug", LogLevel::Debug)]
+        #[case("log_level trace", LogLevel::Trace)]
+        fn test_parse_options_log_level(#[case] option: &str, #[case] expected: LogLevel) {
+            let input = format!(
+                "options {{ {option} }}
+localhost {{ route / {{ respond 200 }} }}"
             );
 
-            println!("✅ All branches of suggest_fix_for_content_with_full_context tested!");
+            let (_, config) = parse_config(&input).unwrap();
+            assert_eq!(config.options.log_level, Some(expected));
         }
 
         #[test]
-        fn test_actual_parsing_errors_mentioned_in_comment() {
-            println!("\n=== Testing actual parsing errors mentioned in comment ===");
+        fn test_parse_options_not_found_with_status() {
+            let input = "options { not_found { respond_file /404.html 410 } }
+localhost { route / { respond 200 } }";
 
-            let test_config = "example.com { route /path { file";
-            println!("Testing config: '{}'", test_config);
+            let (_, config) = parse_config(input).unwrap();
+            assert_eq!(
+                config.options.not_found,
+                Some(NotFoundPage {
+                    file: "/404.html".to_string(),
+                    status: 410,
+                })
+            );
+        }
 
-            match crate::parse_config(test_config) {
-                Ok(_) => println!("Parsing succeeded unexpectedly!"),
-                Err(e) => {
-                    println!("Actual error: {}", e);
+        #[rstest]
+        #[case("memory_budget 4096", 4096)]
+        #[case("memory_budget 100b", 100)]
+        #[case("memory_budget 64kb", 64 * 1024)]
+        #[case("memory_budget 512MB", 512 * 1024 * 1024)]
+        #[case("memory_budget 2gb", 2 * 1024 * 1024 * 1024)]
+        fn test_parse_options_memory_budget(#[case] option: &str, #[case] expected: u64) {
+            let input = format!(
+                "options {{ {option} }}
+localhost {{ route / {{ respond 200 }} }}"
+            );
 
-                    // Check if the actual error contains the expected message
-                    if e.contains("File handler requires a file path") {
-                        println!("✅ SUCCESS: Error message contains expected specific guidance!");
-                    } else {
-                        println!("❌ ISSUE: Error message is still generic, not context-specific");
-                        println!("Expected something like: 'File handler requires a file path. Example: 'file index.html'.'");
-                        println!("Got: {}", e);
-                    }
-                }
-            }
+            let (_, config) = parse_config(&input).unwrap();
+            assert_eq!(config.options.memory_budget, Some(expected));
+        }
 
-            // Test a few more specific cases
-            let test_cases = vec![
-                ("example.com { route /path { proxy", "Proxy handler"),
-                ("example.com { route /path { respond", "Respond handler"),
-                ("example.com { route /path { redirect", "Redirect handler"),
-                (
-                    "example.com { route /path { file index.html rate_limit",
-                    "Rate limit",
-                ),
-            ];
+        #[rstest]
+        #[case("default_port")]
+        #[case("default_port 70000")]
+        #[case("log_level verbose")]
+        #[case("graceful_shutdown_timeout soon")]
+        #[case("access_log")]
+        #[case("write_timeout")]
+        #[case("write_timeout 0")]
+        #[case("header_read_timeout")]
+        #[case("header_read_timeout 0s")]
+        #[case("grace_period")]
+        #[case("default_request_timeout 0")]
+        #[case("default_connection_timeout later")]
+        #[case("websocket_drain")]
+        #[case("strict_sni_host")]
+        #[case("strict_sni_host yes")]
+        #[case("allowed_methods")]
+        #[case("allowed_methods get")]
+        #[case("not_found { }")]
+        #[case("not_found { respond_file }")]
+        #[case("not_found { respond_file /404.html 99 }")]
+        #[case("not_found { respond_file /404.html 404 respond 200 }")]
+        #[case("default_host")]
+        #[case("default_host ftp://localhost")]
+        #[case("memory_budget")]
+        #[case("memory_budget 0mb")]
+        #[case("memory_budget 1tb")]
+        #[case("memory_budget 1mib")]
+        #[case("memory_budget 99999999999gb")]
+        #[case("unknown_option 1")]
+        fn test_parse_config_with_invalid_option(#[case] option: &str) {
+            let input =
+                format!("options {{\n {option}\n}}\nlocalhost {{ route / {{ respond 200 }} }}");
 
-            for (test, expected_type) in test_cases {
-                println!("\nTesting: '{}'", test);
-                match crate::parse_config(test) {
-                    Err(e) => {
-                        println!("Error: {}", e);
-                        if e.contains("Missing closing braces") {
-                            println!(
-                                "❌ Still showing generic brace error instead of specific {} error",
-                                expected_type
-                            );
-                        } else {
-                            println!("✅ Showing specific {} error guidance", expected_type);
-                        }
-                    }
-                    Ok(_) => println!("Unexpected success"),
-                }
-            }
+            assert!(parse_config(&input).is_err(), "{option} should be rejected");
         }
 
         #[test]
-        fn test_multiline_config_error_handling() {
-            println!("\n=== Testing multiline configuration error handling ===");
+        fn test_parse_config_rejects_duplicate_options_blocks() {
+            let input = r#"
+            options { default_port 8080 }
+            options { log_level info }
+            localhost { route / { respond 200 } }
+            "#;
 
-            // Test single line (should work well)
-            let single_line = "example.com { route /path { file";
-            println!("Single line config: '{}'", single_line);
-            match crate::parse_config(single_line) {
-                Err(e) => println!("Single line error: {}", e),
-                Ok(_) => println!("Unexpectedly parsed"),
-            }
+            let error = parse_config(input).unwrap_err();
+            assert!(error.contains("Duplicate options block"), "{error}");
+        }
 
-            // Test multiline (this is the problematic case)
-            let multiline = "example.com {\n  route /path {\n    file";
-            println!("\nMultiline config:\n{}", multiline);
-            match crate::parse_config(multiline) {
-                Err(e) => {
-                    println!("Multiline error: {}", e);
-                    if e.contains("File handler requires a file path") {
-                        println!("✅ Multiline config correctly identified as file handler error");
-                    } else {
-                        println!("❌ Multiline config shows generic error instead of specific file handler error");
-                    }
-                }
-                Ok(_) => println!("Unexpectedly parsed"),
-            }
+        #[test]
+        fn test_parse_config_file_rejects_options_block_in_included_file_too() {
+            let dir = tempfile::tempdir().unwrap();
+            fs::write(
+                dir.path().join("defaults.chf"),
+                "options { log_level info }",
+            )
+            .unwrap();
+            let main = "options { default_port 8080 }\ninclude defaults.chf";
+            let main_path = dir.path().join("chico.chf");
+            fs::write(&main_path, main).unwrap();
 
-            // Test another multiline case
-            let multiline_proxy = "example.com {\n  route /api {\n    proxy\n  }\n}";
-            println!("\nMultiline proxy config:\n{}", multiline_proxy);
-            match crate::parse_config(multiline_proxy) {
-                Err(e) => {
-                    println!("Multiline proxy error: {}", e);
-                    if e.contains("Proxy handler requires") {
-                        println!("✅ Multiline proxy correctly identified as proxy handler error");
-                    } else {
-                        println!("❌ Multiline proxy shows generic error instead of specific proxy handler error");
-                    }
-                }
-                Ok(_) => println!("Unexpectedly parsed"),
-            }
+            let error = parse_config_file(&main_path, main).unwrap_err();
+            assert!(error.contains("Duplicate options block"), "{error}");
+        }
 
-            // Test multiline middleware
-            let multiline_middleware =
-                "example.com {\n  route /files {\n    file index.html\n    rate_limit\n  }\n}";
-            println!("\nMultiline middleware config:\n{}", multiline_middleware);
-            match crate::parse_config(multiline_middleware) {
-                Err(e) => {
-                    println!("Multiline middleware error: {}", e);
-                    if e.contains("Rate limit middleware requires") {
-                        println!(
-                            "✅ Multiline middleware correctly identified as rate_limit error"
-                        );
-                    } else {
-                        println!("❌ Multiline middleware shows generic error instead of specific rate_limit error");
-                    }
-                }
-                Ok(_) => println!("Unexpectedly parsed"),
-            }
+        #[test]
+        fn test_parse_config_file_reads_options_block_of_included_file() {
+            let dir = tempfile::tempdir().unwrap();
+            fs::write(
+                dir.path().join("defaults.chf"),
+                "options { default_port 8080 }",
+            )
+            .unwrap();
+            let main = "include defaults.chf\nlocalhost { route / { respond 200 } }";
+            let main_path = dir.path().join("chico.chf");
+            fs::write(&main_path, main).unwrap();
+
+            let config = parse_config_file(&main_path, main).unwrap();
+            assert_eq!(config.options.default_port, Some(8080));
+        }
+
+        #[test]
+        fn test_virtual_host_named_options_is_not_an_options_block() {
+            let (_, config) = parse_config("options { route / { respond 200 } }").unwrap();
+            assert_eq!(config.virtual_hosts[0].domain.to_string(), "options");
+            assert_eq!(config.options, GlobalOptions::default());
         }
     }
 }
 
 #[cfg(test)]
 mod timeout_test {
+    use std::time::Duration;
+
     use crate::parse_config;
     use crate::types::*;
 
@@ -3692,8 +8080,8 @@ localhost {
     route /test/* {
         proxy {
             upstreams http://localhost:8080
-            request_timeout 25
-            connection_timeout 10
+            request_timeout 2m
+            connection_timeout 10s
         }
     }
 }
@@ -3708,13 +8096,63 @@ localhost {
 
         match &route.handler {
             Handler::Proxy(proxy_config) => {
-                assert_eq!(proxy_config.request_timeout, Some(25));
-                assert_eq!(proxy_config.connection_timeout, Some(10));
+                assert_eq!(proxy_config.request_timeout, Some(Duration::from_secs(120)));
+                assert_eq!(
+                    proxy_config.connection_timeout,
+                    Some(Duration::from_secs(10))
+                );
+            }
+            _ => panic!("Expected proxy handler"),
+        }
+    }
+
+    #[test]
+    fn test_timeout_parsing_milliseconds() {
+        let config_content = r#"
+localhost {
+    route /test/* {
+        proxy {
+            upstreams http://localhost:8080
+            request_timeout 1500ms
+            connection_timeout 500ms
+        }
+    }
+}
+"#;
+
+        let (_, config) = parse_config(config_content).unwrap();
+
+        match &config.virtual_hosts[0].routes[0].handler {
+            Handler::Proxy(proxy_config) => {
+                assert_eq!(
+                    proxy_config.request_timeout,
+                    Some(Duration::from_millis(1500))
+                );
+                assert_eq!(
+                    proxy_config.connection_timeout,
+                    Some(Duration::from_millis(500))
+                );
             }
             _ => panic!("Expected proxy handler"),
         }
     }
 
+    #[test]
+    fn test_timeout_parsing_rejects_unknown_unit() {
+        let config_content = r#"
+localhost {
+    route /test/* {
+        proxy {
+            upstreams http://localhost:8080
+            request_timeout 30sec
+        }
+    }
+}
+"#;
+
+        assert!(parse_config(config_content).is_err());
+    }
+
     #[test]
     fn test_timeout_parsing_partial() {
         let config_content = r#"
@@ -3737,7 +8175,7 @@ localhost {
 
         match &route.handler {
             Handler::Proxy(proxy_config) => {
-                assert_eq!(proxy_config.request_timeout, Some(15));
+                assert_eq!(proxy_config.request_timeout, Some(Duration::from_secs(15)));
                 assert_eq!(proxy_config.connection_timeout, None);
             }
             _ => panic!("Expected proxy handler"),
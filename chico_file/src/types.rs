@@ -1,44 +1,397 @@
+use std::time::Duration;
+
 use crates_uri::UriExt;
+use ipnet::IpNet;
 
-#[derive(Debug, PartialEq, Clone)]
+use crate::env::Expansions;
+
+#[derive(PartialEq, Clone)]
 pub struct Config {
     pub virtual_hosts: Vec<VirtualHost>,
+    /// Server-wide settings of the top-level `options` block.
+    pub options: GlobalOptions,
+    /// Environment variables expanded in the config files.
+    pub expansions: Expansions,
+}
+
+/// Shows the placeholders of the environment variables in place of their values, which may be
+/// secrets.
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        struct Config<'a> {
+            virtual_hosts: &'a Vec<VirtualHost>,
+            options: &'a GlobalOptions,
+            expansions: &'a Expansions,
+        }
+
+        let config = Config {
+            virtual_hosts: &self.virtual_hosts,
+            options: &self.options,
+            expansions: &self.expansions,
+        };
+        let debug = if f.alternate() {
+            format!("{config:#?}")
+        } else {
+            format!("{config:?}")
+        };
+        f.write_str(&self.expansions.redact(&debug))
+    }
+}
+
+/// Server-wide defaults, all unset when the config has no `options` block.
+#[derive(Debug, PartialEq, Clone, Hash, Default)]
+pub struct GlobalOptions {
+    /// Port of the virtual hosts whose domain has no port.
+    pub default_port: Option<u16>,
+    /// Level of the logs written by chico.
+    pub log_level: Option<LogLevel>,
+    /// Time given to open connections to complete their requests on shutdown.
+    pub graceful_shutdown_timeout: Option<Duration>,
+    /// File the access log is written to, instead of the diagnostic logs.
+    pub access_log: Option<String>,
+    /// Time a client may take to accept more of a response before its connection is dropped.
+    pub write_timeout: Option<Duration>,
+    /// Time a client has to send the complete headers of a request before its connection is
+    /// closed.
+    pub header_read_timeout: Option<Duration>,
+    /// Response of the requests no virtual host or route matches, a built-in page when unset.
+    pub not_found: Option<NotFoundPage>,
+    /// Virtual host of the HTTP/1.0 requests without Host header, they are rejected when unset.
+    pub default_host: Option<Domain>,
+    /// Content-Security-Policy of the HTML responses of the virtual hosts without their own.
+    pub csp: Option<ContentSecurityPolicy>,
+    /// Bytes the in-memory caches of all routes may hold together, unlimited when unset.
+    pub memory_budget: Option<u64>,
+    /// Request timeout of the proxies without their own `request_timeout`.
+    pub default_request_timeout: Option<Duration>,
+    /// Connection timeout of the proxies without their own `connection_timeout`.
+    pub default_connection_timeout: Option<Duration>,
+    /// Time given to upgraded connections, like WebSocket tunnels, to close on shutdown.
+    pub websocket_drain: Option<Duration>,
+    /// Rejects the HTTPS requests whose Host is served by another virtual host than the server
+    /// name (SNI) of their connection, they are only logged when unset.
+    pub strict_sni_host: Option<bool>,
+    /// Methods accepted by the virtual hosts without their own `allowed_methods`, all methods
+    /// are accepted when unset.
+    pub allowed_methods: Option<Vec<http::Method>>,
+}
+
+/// Policy sent in the `Content-Security-Policy` header of HTML responses.
+#[derive(Debug, PartialEq, Clone, Hash)]
+pub struct ContentSecurityPolicy {
+    pub policy: String,
+    /// Sends the policy in `Content-Security-Policy-Report-Only`, browsers report the violations
+    /// without blocking anything.
+    pub report_only: bool,
+}
+
+/// Page served with `status` for the requests no virtual host or route matches.
+#[derive(Debug, PartialEq, Clone, Hash)]
+pub struct NotFoundPage {
+    pub file: String,
+    pub status: u16,
+}
+
+/// Page served instead of the built-in body of the error responses with `status`, keeping the
+/// status.
+#[derive(Debug, PartialEq, Clone, Hash)]
+pub struct ErrorDocument {
+    pub status: u16,
+    pub file: String,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Hash)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct VirtualHost {
-    pub domain: String,
+    pub domain: Domain,
     pub routes: Vec<Route>,
+    /// Default format of the error responses generated for this virtual host.
+    pub error_format: Option<ErrorFormat>,
+    /// Peers allowed to report the client address and scheme through forwarding headers.
+    pub trusted_proxies: Vec<IpNet>,
+    /// How the `Forwarded` header of trusted proxies is used.
+    pub forwarded_header: ForwardedHeader,
+    /// Methods accepted by the virtual host, requests with other methods are rejected with `405`.
+    /// All methods are accepted when unset.
+    pub allowed_methods: Option<Vec<http::Method>>,
+    /// Certificate served over HTTPS, the virtual host is served over plain HTTP without it.
+    pub tls: Option<TlsConfig>,
+    /// Named matchers routes refer to with `route @name`.
+    pub matchers: Vec<Matcher>,
+    /// Pages replacing the built-in error responses of the virtual host, by status.
+    pub error_pages: Vec<ErrorDocument>,
+    /// Content-Security-Policy of the HTML responses, overrides the one of the options.
+    pub csp: Option<ContentSecurityPolicy>,
+    /// Requests the virtual host accepts from all clients together, checked before the rate
+    /// limits of its routes.
+    pub rate_limit: Option<RateLimit>,
+    /// How request paths with repeated slashes, like `/a//b`, are handled before routing.
+    pub merge_slashes: MergeSlashes,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+/// Rate of `requests` requests per `window`.
+#[derive(Debug, PartialEq, Clone, Copy, Hash)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub window: Duration,
+}
+
+/// Domain of a virtual host, like `example.com`, `localhost:3000` or `https://*.example.com`.
+///
+/// Displayed as written in the config.
+#[derive(Debug, PartialEq, Clone, Hash)]
+pub struct Domain {
+    /// `http` or `https`, when given.
+    pub scheme: Option<String>,
+    /// Host name or IP address, possibly with a leading `*.` or `**.` wildcard. IPv6 addresses
+    /// keep their brackets.
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl Domain {
+    /// Returns true when the domain has the `https` scheme.
+    pub fn is_https(&self) -> bool {
+        self.scheme.as_deref() == Some("https")
+    }
+}
+
+impl std::str::FromStr for Domain {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, authority) = match s.split_once("://") {
+            Some((scheme, authority)) => {
+                let scheme = scheme.to_ascii_lowercase();
+                if scheme != "http" && scheme != "https" {
+                    return Err(format!("unsupported scheme {scheme} in domain {s}"));
+                }
+                (Some(scheme), authority)
+            }
+            None => (None, s),
+        };
+
+        // the port follows the closing bracket of IPv6 addresses
+        let host_end = match authority.starts_with('[') {
+            true => authority
+                .find(']')
+                .map(|i| i + 1)
+                .unwrap_or(authority.len()),
+            false => authority.find(':').unwrap_or(authority.len()),
+        };
+        let (host, port) = authority.split_at(host_end);
+        let port = match port.strip_prefix(':') {
+            Some(port) => Some(
+                port.parse::<u16>()
+                    .map_err(|_| format!("invalid port in domain {s}"))?,
+            ),
+            None if port.is_empty() => None,
+            None => return Err(format!("invalid domain {s}")),
+        };
+        if host.is_empty() || host.contains(['/', '?', '#', '@']) {
+            return Err(format!("invalid domain {s}"));
+        }
+
+        Ok(Domain {
+            scheme,
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl std::fmt::Display for Domain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(scheme) = &self.scheme {
+            write!(f, "{scheme}://")?;
+        }
+        f.write_str(&self.host)?;
+        if let Some(port) = self.port {
+            write!(f, ":{port}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Named set of conditions, declared as `@name { ... }`. A route using it serves the requests
+/// meeting all of its conditions.
+#[derive(Debug, PartialEq, Clone, Hash)]
+pub struct Matcher {
+    /// Name without the leading `@`.
+    pub name: String,
+    pub conditions: Vec<MatcherCondition>,
+}
+
+#[derive(Debug, PartialEq, Clone, Hash)]
+pub enum MatcherCondition {
+    /// The request path matches one of the paths, a path ending with `/*` matches every path
+    /// under it.
+    Path(Vec<String>),
+    /// The request has the header with exactly this value.
+    Header { name: String, value: String },
+}
+
+#[derive(Debug, PartialEq, Clone, Hash)]
+pub struct TlsConfig {
+    /// Path of the PEM certificate chain.
+    pub cert: String,
+    /// Path of the PEM private key of the certificate.
+    pub key: String,
+}
+
+/// Use of the RFC 7239 `Forwarded` header sent by trusted proxies.
+#[derive(Debug, PartialEq, Clone, Copy, Hash, Default)]
+pub enum ForwardedHeader {
+    /// Only `X-Forwarded-For` and `X-Forwarded-Proto` are read.
+    #[default]
+    Ignore,
+    /// `Forwarded` is read when the `X-Forwarded-*` headers are missing.
+    Trust,
+    /// `Forwarded` is read first, `X-Forwarded-*` is the fallback.
+    Prefer,
+}
+
+/// Handling of repeated slashes in request paths.
+#[derive(Debug, PartialEq, Clone, Copy, Hash, Default)]
+pub enum MergeSlashes {
+    /// Paths are routed as sent.
+    #[default]
+    Off,
+    /// Repeated slashes are collapsed before routing, `/a//b` is served as `/a/b`.
+    On,
+    /// Paths with repeated slashes are redirected with `301` to the collapsed path.
+    Redirect,
+}
+
+#[derive(Debug, PartialEq, Clone, Hash)]
 pub struct Route {
     pub path: String,
     pub handler: Handler,
     pub middlewares: Vec<Middleware>,
+    /// Overrides the error format of the virtual host for this route.
+    pub error_format: Option<ErrorFormat>,
+    /// Detect the content type of extensionless files from their first bytes.
+    pub sniff_extensionless: bool,
+    /// Maximum number of ranges accepted in a `Range` header.
+    pub max_ranges: Option<usize>,
+    /// Decode the responses in an encoding the client does not accept.
+    pub transcode: bool,
+    /// Serve the language variant of a file, like `index.fr.html`, the client prefers.
+    pub negotiate_language: bool,
+    /// Methods the route serves, all methods when empty.
+    pub methods: Vec<http::Method>,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+impl Route {
+    /// Returns how the path of the route matches request paths.
+    pub fn path_kind(&self) -> RoutePath<'_> {
+        if let Some(name) = self.path.strip_prefix('@') {
+            RoutePath::Matcher(name)
+        } else if let Some(pattern) = self.path.strip_prefix('~') {
+            RoutePath::Regex(pattern)
+        } else if self.path.ends_with("/*") {
+            RoutePath::Prefix(&self.path[..self.path.len() - 1])
+        } else {
+            RoutePath::Exact(&self.path)
+        }
+    }
+}
+
+/// Path of a route, by the way it matches request paths.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RoutePath<'a> {
+    /// `/about` matches this path only.
+    Exact(&'a str),
+    /// `/api/*` matches the paths starting with `/api/`, given without the `*`.
+    Prefix(&'a str),
+    /// `~^/api/v[0-9]+/` matches the paths the regular expression matches, given without the `~`.
+    Regex(&'a str),
+    /// `@name` matches the requests of the named matcher, given without the `@`.
+    Matcher(&'a str),
+}
+
+/// Format of the error responses chico generates itself (unknown route, upstream failures, ...).
+#[derive(Debug, PartialEq, Clone, Copy, Hash)]
+pub enum ErrorFormat {
+    Html,
+    Json,
+    /// Use JSON when the request accepts `application/json`, HTML otherwise.
+    Auto,
+}
+
+#[derive(Debug, PartialEq, Clone, Hash)]
 pub enum Handler {
     File(String),
     Proxy(ProxyConfig),
-    Dir(String),
+    /// Roots tried in order, the first one having the requested file serves it.
+    Dir(Vec<String>),
     Browse(String),
     Respond {
         status: Option<u16>,
         body: Option<String>,
     },
+    /// Responds with the content of a file, read when the config is loaded, written
+    /// `respond file:<path> [status]`.
+    RespondFile {
+        file: String,
+        status: Option<u16>,
+    },
+    /// Responds with the value of the query parameter of this name, written
+    /// `respond_echo <param>`.
+    RespondEcho(String),
     Redirect {
         path: Option<String>,
         status_code: Option<u16>,
     },
+    /// Path the request is dispatched again with, `{path}` and `{query}` are replaced by the
+    /// path below the route and the query of the request.
+    Rewrite(String),
+    /// Files tried in order, the first existing one is served. `{path}` is replaced by the
+    /// request path.
+    TryFiles(Vec<String>),
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Hash)]
 pub struct ProxyConfig {
     pub load_balancer: LoadBalancer,
-    pub request_timeout: Option<u64>,    // in seconds
-    pub connection_timeout: Option<u64>, // in seconds
+    /// Time the upstream has to answer once connected.
+    pub request_timeout: Option<Duration>,
+    /// Time given to connect to the upstream.
+    pub connection_timeout: Option<Duration>,
+    /// How long resolved upstream addresses are cached before re-resolving the hostname.
+    pub resolve_ttl: Option<Duration>,
+    /// How long the last resolved addresses are used while re-resolving fails.
+    pub dns_stale_max: Option<Duration>,
+    /// Requests matching the canary are sent to its upstreams instead of the load balancer's.
+    pub canary: Option<CanaryConfig>,
+    /// Failed requests in a row after which an upstream is taken out of rotation, upstreams are
+    /// never taken out when unset.
+    pub max_fails: Option<u32>,
+    /// How long an upstream is out of rotation once it reached `max_fails`.
+    pub fail_timeout: Option<Duration>,
+    /// Fails the requests fast while an upstream errs or slows down, until it recovers.
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Adds the `X-Forwarded-*` headers to the requests sent upstream, on unless `forwarded off`.
+    pub forwarded: bool,
+    /// Host header of the requests sent upstream.
+    pub host_header: HostHeader,
+    /// Headers removed from the client requests before they are sent upstream, so clients can
+    /// not set headers trusted by the upstreams.
+    pub strip_request_headers: Vec<String>,
+    /// `header` rules of `header_up`, applied to the requests sent upstream.
+    pub header_up: Vec<Middleware>,
+    /// `header` rules of `header_down`, applied to the responses of the upstreams.
+    pub header_down: Vec<Middleware>,
 }
 
 impl ProxyConfig {
@@ -47,32 +400,118 @@ impl ProxyConfig {
             load_balancer,
             request_timeout: None,
             connection_timeout: None,
+            resolve_ttl: None,
+            dns_stale_max: None,
+            canary: None,
+            max_fails: None,
+            fail_timeout: None,
+            circuit_breaker: None,
+            forwarded: true,
+            host_header: HostHeader::Upstream,
+            strip_request_headers: vec![],
+            header_up: vec![],
+            header_down: vec![],
         }
     }
 
     pub fn with_timeouts(
         load_balancer: LoadBalancer,
-        request_timeout: Option<u64>,
-        connection_timeout: Option<u64>,
+        request_timeout: Option<Duration>,
+        connection_timeout: Option<Duration>,
     ) -> Self {
         Self {
             load_balancer,
             request_timeout,
             connection_timeout,
+            resolve_ttl: None,
+            dns_stale_max: None,
+            canary: None,
+            max_fails: None,
+            fail_timeout: None,
+            circuit_breaker: None,
+            forwarded: true,
+            host_header: HostHeader::Upstream,
+            strip_request_headers: vec![],
+            header_up: vec![],
+            header_down: vec![],
         }
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+/// Host header a proxy sends upstream.
+#[derive(Debug, PartialEq, Clone, Hash, Default)]
+pub enum HostHeader {
+    /// The `host:port` of the upstream.
+    #[default]
+    Upstream,
+    /// The Host of the client request.
+    Preserve,
+    /// A fixed authority.
+    Value(String),
+}
+
+/// Trips the circuit of an upstream when its requests of the last `window` fail at `error_rate`
+/// or more, or take `latency` on average.
+#[derive(Debug, PartialEq, Clone, Hash)]
+pub struct CircuitBreakerConfig {
+    /// Percentage of failed requests, from 1 to 100.
+    pub error_rate: u8,
+    /// Rolling window the requests are counted over, also how long the circuit stays open.
+    pub window: Option<Duration>,
+    /// Average duration of the requests above which the circuit trips, latency is ignored when
+    /// unset.
+    pub latency: Option<Duration>,
+}
+
+#[derive(Debug, PartialEq, Clone, Hash)]
+pub struct CanaryConfig {
+    pub matcher: CanaryMatch,
+    pub load_balancer: LoadBalancer,
+}
+
+/// Selects the requests sent to the canary upstreams.
+#[derive(Debug, PartialEq, Clone, Hash)]
+pub enum CanaryMatch {
+    /// The request has a header `name` with exactly `value`, the name is case-insensitive.
+    Header { name: String, value: String },
+    /// The request has a cookie `name` with exactly `value`.
+    Cookie { name: String, value: String },
+}
+
+#[derive(Debug, PartialEq, Clone, Hash)]
 pub enum LoadBalancer {
     NoBalancer(Upstream),
     RoundRobin(Vec<Upstream>),
+    /// Sends each request to the upstream with the fewest requests in flight.
+    LeastConnections(Vec<Upstream>),
+    /// Sends each request to an upstream picked at random.
+    Random(Vec<Upstream>),
+    /// Sends the requests of a client to the same upstream, picked from the client address.
+    IpHash(Vec<Upstream>),
+    /// Takes turns between the upstreams in proportion to their weights.
+    WeightedRoundRobin(Vec<Upstream>),
 }
 
-#[derive(Debug, PartialEq, Clone)]
+impl LoadBalancer {
+    /// Returns the upstreams requests are balanced between.
+    pub fn upstreams(&self) -> &[Upstream] {
+        match self {
+            LoadBalancer::NoBalancer(upstream) => std::slice::from_ref(upstream),
+            LoadBalancer::RoundRobin(upstreams)
+            | LoadBalancer::LeastConnections(upstreams)
+            | LoadBalancer::Random(upstreams)
+            | LoadBalancer::IpHash(upstreams)
+            | LoadBalancer::WeightedRoundRobin(upstreams) => upstreams,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Hash)]
 pub struct Upstream {
     uri: http::Uri,
     host_addrs: String,
+    /// Share of the requests relative to the other upstreams, `1` unless set with `weight=N`.
+    weight: u32,
 }
 
 impl Upstream {
@@ -95,12 +534,31 @@ impl Upstream {
         Ok(Upstream {
             host_addrs: host_and_port,
             uri,
+            weight: 1,
         })
     }
 
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+
     pub fn get_host_port(&self) -> &str {
         &self.host_addrs
     }
+
+    /// Returns the name of the DNS SRV records listing the targets of an upstream like
+    /// `srv://_http._tcp.backend.service.consul`.
+    pub fn srv_name(&self) -> Option<&str> {
+        if self.uri.scheme_str() == Some("srv") {
+            return self.uri.host();
+        }
+        None
+    }
 }
 
 impl Handler {
@@ -111,25 +569,56 @@ impl Handler {
             Handler::Dir(_) => "Dir",
             Handler::Browse(_) => "Browse",
             Handler::Respond { status: _, body: _ } => "Respond",
+            Handler::RespondFile { file: _, status: _ } => "RespondFile",
+            Handler::RespondEcho(_) => "RespondEcho",
             Handler::Redirect {
                 path: _,
                 status_code: _,
             } => "Redirect",
+            Handler::Rewrite(_) => "Rewrite",
+            Handler::TryFiles(_) => "TryFiles",
         }
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Hash)]
 pub enum Middleware {
-    Gzip,
-    Cors,
+    Gzip(CompressionLevel),
+    /// Responses are compressed with zstd for clients accepting it, with the dictionary at the
+    /// given path if any.
+    Zstd {
+        dictionary: Option<String>,
+    },
+    /// Cross-origin requests from the given origin are allowed, `*` allows any origin.
+    Cors(String),
     Log,
-    RateLimit(u32),
+    /// Each client may send `requests` requests per `window`.
+    RateLimit {
+        requests: u32,
+        window: Duration,
+    },
+    /// Requests need the credentials of one of `users` (HTTP Basic authentication).
     Auth {
-        username: String,
-        password: String,
+        users: Vec<BasicUser>,
+        /// Realm of the `WWW-Authenticate` challenge.
+        realm: Option<String>,
+        /// Path patterns served without credentials, like `/public/*`.
+        except: Vec<String>,
+    },
+    Cache {
+        /// How long responses are cached.
+        ttl: Duration,
+        /// How long after expiry a cached response is served when the upstream fails.
+        serve_stale_on_error: Option<Duration>,
+        /// Cached responses never expire, only eviction removes them.
+        immutable: bool,
+    },
+    /// At most `limit` requests of the route are handled at the same time.
+    MaxConcurrent {
+        limit: usize,
+        /// Requests waiting for a slot, without queue the requests above the limit are rejected.
+        queue: Option<ConcurrencyQueue>,
     },
-    Cache(String),
     /// First Parameter is the header name with prefix operator, second is the header value, third is for replace value
     Header {
         operator: HeaderOperator,
@@ -137,9 +626,98 @@ pub enum Middleware {
         value: Option<String>,
         replace_with: Option<String>,
     },
+    /// Responses of the given kinds are minified before they are compressed.
+    Minify(Vec<MinifyKind>),
 }
 
-#[derive(Debug, PartialEq, Clone)]
+/// Kind of response the `minify` middleware processes, by content type.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum MinifyKind {
+    Html,
+    Css,
+    Js,
+}
+
+#[derive(Debug, PartialEq, Clone, Hash)]
+pub struct BasicUser {
+    pub username: String,
+    pub password: Redacted,
+}
+
+impl BasicUser {
+    pub fn new(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            password: Redacted::new(password),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Hash)]
+pub struct ConcurrencyQueue {
+    /// Maximum number of waiting requests.
+    pub size: usize,
+    /// How long a request waits for a slot before it is rejected.
+    pub timeout: Duration,
+}
+
+/// Compression level from 1, the fastest, to 9, the smallest output.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct CompressionLevel(u32);
+
+impl CompressionLevel {
+    pub const FASTEST: Self = Self(1);
+    /// Balances CPU time and size, used when no level is given.
+    pub const DEFAULT: Self = Self(6);
+    pub const BEST: Self = Self(9);
+
+    /// Returns `None` when the level is not between 1 and 9.
+    pub fn new(level: u32) -> Option<Self> {
+        (1..=9).contains(&level).then_some(Self(level))
+    }
+
+    pub fn get(self) -> u32 {
+        self.0
+    }
+}
+
+impl Default for CompressionLevel {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// A secret of the config, like a password.
+///
+/// `Debug` and `Display` print `***`, so the secret does not leak into logs or dumps of the
+/// config. The value is read with [`Redacted::expose`] where it is compared.
+#[derive(PartialEq, Eq, Clone, Hash)]
+pub struct Redacted(String);
+
+impl Redacted {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self(secret.into())
+    }
+
+    /// Returns the secret.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("***")
+    }
+}
+
+impl std::fmt::Display for Redacted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("***")
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Hash)]
 pub enum HeaderOperator {
     /// Prefix with + to add the field instead of overwriting (setting) the field if it already exists; header fields can appear more than once in a request.
     Add,
@@ -164,7 +742,47 @@ mod tests {
 
     use crate::types::Upstream;
 
-    use super::Handler;
+    use super::{Domain, Handler};
+
+    #[rstest]
+    #[case("example.com", None, "example.com", None)]
+    #[case("localhost:3000", None, "localhost", Some(3000))]
+    #[case("http://example2.com:8080", Some("http"), "example2.com", Some(8080))]
+    #[case("https://example3.com:443", Some("https"), "example3.com", Some(443))]
+    #[case("HTTPS://*.example.com", Some("https"), "*.example.com", None)]
+    #[case("[::1]:8080", None, "[::1]", Some(8080))]
+    fn test_domain_from_str(
+        #[case] domain: &str,
+        #[case] scheme: Option<&str>,
+        #[case] host: &str,
+        #[case] port: Option<u16>,
+    ) {
+        let parsed: Domain = domain.parse().unwrap();
+
+        assert_eq!(parsed.scheme.as_deref(), scheme);
+        assert_eq!(parsed.host, host);
+        assert_eq!(parsed.port, port);
+    }
+
+    #[rstest]
+    #[case("example.com")]
+    #[case("http://example2.com:8080")]
+    #[case("https://example3.com:443")]
+    #[case("[::1]:8080")]
+    fn test_domain_display_round_trips(#[case] domain: &str) {
+        assert_eq!(domain.parse::<Domain>().unwrap().to_string(), domain);
+    }
+
+    #[rstest]
+    #[case("ftp://example.com")]
+    #[case("example.com:http")]
+    #[case("example.com:70000")]
+    #[case("example.com/path")]
+    #[case("http://")]
+    #[case("[::1]8080")]
+    fn test_domain_from_str_invalid(#[case] domain: &str) {
+        assert!(domain.parse::<Domain>().is_err());
+    }
 
     #[test]
     fn test_handler_type_name() {
@@ -178,7 +796,7 @@ mod tests {
         ));
         assert_eq!(handler.type_name(), "Proxy");
 
-        let handler = Handler::Dir(String::new());
+        let handler = Handler::Dir(vec![]);
         assert_eq!(handler.type_name(), "Dir");
 
         let handler = Handler::Browse(String::new());
@@ -190,6 +808,12 @@ mod tests {
         };
         assert_eq!(handler.type_name(), "Respond");
 
+        let handler = Handler::RespondFile {
+            file: String::new(),
+            status: None,
+        };
+        assert_eq!(handler.type_name(), "RespondFile");
+
         let handler = Handler::Redirect {
             path: None,
             status_code: None,
@@ -215,6 +839,18 @@ mod tests {
         assert_eq!(upstream.get_host_port(), host_and_port)
     }
 
+    #[rstest]
+    #[case(
+        "srv://_http._tcp.backend.service.consul",
+        Some("_http._tcp.backend.service.consul")
+    )]
+    #[case("http://backend.service.consul", None)]
+    #[case("backend.service.consul", None)]
+    fn test_upstream_srv_name(#[case] given_addrs: &str, #[case] srv_name: Option<&str>) {
+        let upstream = Upstream::new(given_addrs.to_string()).unwrap();
+        assert_eq!(upstream.srv_name(), srv_name);
+    }
+
     #[rstest]
     #[case("")]
     #[case("/addrs")]
@@ -222,4 +858,54 @@ mod tests {
         let upstream = Upstream::new(given_addrs.to_string());
         claims::assert_err!(upstream);
     }
+
+    #[test]
+    fn test_redacted_hides_secret_when_formatted() {
+        let secret = super::Redacted::new("hunter2");
+
+        assert_eq!(format!("{secret}"), "***");
+        assert_eq!(format!("{secret:?}"), "***");
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[test]
+    fn test_config_debug_output_contains_no_secret() {
+        let (_, config) = crate::parse_config(
+            r#"
+            localhost {
+                route /admin {
+                    respond 200
+                    auth admin s3cr3t-passw0rd
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        let debug = format!("{config:?}");
+        let pretty_debug = format!("{config:#?}");
+
+        assert!(debug.contains("admin"));
+        assert!(!debug.contains("s3cr3t-passw0rd"), "{debug}");
+        assert!(!pretty_debug.contains("s3cr3t-passw0rd"), "{pretty_debug}");
+    }
+
+    #[test]
+    fn test_config_debug_output_shows_placeholders_of_environment_variables() {
+        let (input, expansions) = crate::env::expand(
+            "localhost {\n route / {\n respond \"${GREETING}\"\n }\n}",
+            |_| Some("greetings from env".to_string()),
+        )
+        .unwrap();
+        let (_, config) = crate::parse_config(&input).unwrap();
+        let config = super::Config {
+            expansions,
+            ..config
+        };
+
+        for debug in [format!("{config:?}"), format!("{config:#?}")] {
+            assert!(debug.contains("${GREETING}"), "{debug}");
+            assert!(!debug.contains("greetings from env"), "{debug}");
+        }
+    }
 }
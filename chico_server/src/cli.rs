@@ -1,25 +1,86 @@
-use clap::{command, Parser, Subcommand};
+use clap::{command, Args, Parser, Subcommand};
+
+use crate::config::{ValidationOptions, DEFAULT_MAX_ROUTES, DEFAULT_MAX_VIRTUAL_HOSTS};
 
 #[derive(Parser)]
 #[command(name = "chico")]
-pub(crate) struct Cli {
+pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+    /// Reopen the log file on SIGUSR1 instead of rotating it daily, for tools like logrotate
+    #[arg(long, global = true)]
+    pub external_log_rotation: bool,
 }
 
 #[derive(Subcommand)]
-pub(crate) enum Commands {
+pub enum Commands {
     /// Validate the config file content
     Validate {
-        #[arg(short, long)]
-        config: String,
+        #[arg(short, long, required_unless_present = "diff")]
+        config: Option<String>,
+        /// Validate two config files and print what changed from the first to the second,
+        /// exits with an error when they differ
+        #[arg(long, num_args = 2, value_names = ["OLD", "NEW"], conflicts_with = "config")]
+        diff: Option<Vec<String>>,
+        #[command(flatten)]
+        validation: ValidationArgs,
     },
     /// Run the server
     /// This command will block executing shell
     Run {
         #[arg(short, long)]
         config: String,
+        /// Write the process id and the config file to this file, for `chico reload`
+        #[arg(long)]
+        pid_file: Option<String>,
+        #[command(flatten)]
+        validation: ValidationArgs,
     },
+    /// Reload the config file of a running server without dropping its connections
+    ///
+    /// The config is validated first, an invalid config is reported and the server keeps its
+    /// current one. Routes, handlers and middlewares are reloaded, the ports are kept.
+    Reload {
+        /// Pid file of the server, as given to `chico run --pid-file`
+        #[arg(long)]
+        pid_file: String,
+        #[command(flatten)]
+        validation: ValidationArgs,
+    },
+    /// Report whether a server is running, with the ports and routes of its config
+    ///
+    /// Exits with an error when the server is not running.
+    Status {
+        /// Pid file of the server, as given to `chico run --pid-file`
+        #[arg(long)]
+        pid_file: String,
+        /// Print the config reload metrics in the Prometheus text format instead
+        #[arg(long)]
+        metrics: bool,
+    },
+}
+
+#[derive(Args, Debug, PartialEq)]
+pub struct ValidationArgs {
+    /// Maximum number of virtual hosts before the config is reported
+    #[arg(long, default_value_t = DEFAULT_MAX_VIRTUAL_HOSTS)]
+    pub max_virtual_hosts: usize,
+    /// Maximum total number of routes before the config is reported
+    #[arg(long, default_value_t = DEFAULT_MAX_ROUTES)]
+    pub max_routes: usize,
+    /// Fail instead of warning when the config exceeds a limit
+    #[arg(long)]
+    pub strict: bool,
+}
+
+impl From<ValidationArgs> for ValidationOptions {
+    fn from(args: ValidationArgs) -> Self {
+        ValidationOptions {
+            max_virtual_hosts: args.max_virtual_hosts,
+            max_routes: args.max_routes,
+            strict: args.strict,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -27,7 +88,7 @@ mod tests {
     use clap::Parser;
     use rstest::rstest;
 
-    use super::{Cli, Commands};
+    use super::{Cli, Commands, ValidationArgs};
 
     #[rstest]
     #[case("-c")]
@@ -38,7 +99,9 @@ mod tests {
         // Match the parsed command
 
         match cli.command {
-            Commands::Validate { config } => assert_eq!(config, "/path/to/file"),
+            Commands::Validate { config, .. } => {
+                assert_eq!(config.as_deref(), Some("/path/to/file"))
+            }
             _ => panic!("Expected 'Validate' command"),
         }
     }
@@ -52,8 +115,108 @@ mod tests {
         // Match the parsed command
 
         match cli.command {
-            Commands::Run { config } => assert_eq!(config, "/path/to/file"),
+            Commands::Run { config, .. } => assert_eq!(config, "/path/to/file"),
             _ => panic!("Expected 'Run' command"),
         }
     }
+
+    #[test]
+    fn test_reload_command_parsing() {
+        let args = vec!["chico", "reload", "--pid-file", "/run/chico.pid"];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        match cli.command {
+            Commands::Reload { pid_file, .. } => assert_eq!(pid_file, "/run/chico.pid"),
+            _ => panic!("Expected 'Reload' command"),
+        }
+        assert!(Cli::try_parse_from(vec!["chico", "reload"]).is_err());
+    }
+
+    #[test]
+    fn test_status_command_parsing() {
+        let args = vec!["chico", "status", "--pid-file", "/run/chico.pid"];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        match cli.command {
+            Commands::Status { pid_file, metrics } => {
+                assert_eq!(pid_file, "/run/chico.pid");
+                assert!(!metrics);
+            }
+            _ => panic!("Expected 'Status' command"),
+        }
+
+        let args = vec![
+            "chico",
+            "status",
+            "--pid-file",
+            "/run/chico.pid",
+            "--metrics",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Status { metrics: true, .. }
+        ));
+    }
+
+    #[test]
+    fn test_validate_diff_command_parsing() {
+        let args = vec!["chico", "validate", "--diff", "old.chf", "new.chf"];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        match cli.command {
+            Commands::Validate { config, diff, .. } => {
+                assert_eq!(config, None);
+                assert_eq!(
+                    diff,
+                    Some(vec!["old.chf".to_string(), "new.chf".to_string()])
+                );
+            }
+            _ => panic!("Expected 'Validate' command"),
+        }
+    }
+
+    #[rstest]
+    #[case(vec!["chico", "validate", "--diff", "old.chf"])]
+    #[case(vec!["chico", "validate", "--diff", "old.chf", "new.chf", "-c", "chico.chf"])]
+    fn test_validate_diff_command_parsing_invalid(#[case] args: Vec<&str>) {
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn test_validation_args_parsing() {
+        let args = vec![
+            "chico",
+            "validate",
+            "-c",
+            "/path/to/file",
+            "--max-virtual-hosts",
+            "5",
+            "--max-routes",
+            "50",
+            "--strict",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        match cli.command {
+            Commands::Validate { validation, .. } => assert_eq!(
+                validation,
+                ValidationArgs {
+                    max_virtual_hosts: 5,
+                    max_routes: 50,
+                    strict: true,
+                }
+            ),
+            _ => panic!("Expected 'Validate' command"),
+        }
+    }
+
+    #[rstest]
+    #[case(vec!["chico", "run", "-c", "/path/to/file"], false)]
+    #[case(vec!["chico", "run", "-c", "/path/to/file", "--external-log-rotation"], true)]
+    #[case(vec!["chico", "--external-log-rotation", "run", "-c", "/path/to/file"], true)]
+    fn test_external_log_rotation_parsing(#[case] args: Vec<&str>, #[case] expected: bool) {
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert_eq!(cli.external_log_rotation, expected);
+    }
 }
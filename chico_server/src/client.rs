@@ -0,0 +1,352 @@
+//! # Client
+//!
+//! Resolves the address and scheme of the client that sent a request.
+//!
+//! The TCP peer is the client unless it is one of the `trusted_proxies` of the virtual host. A
+//! trusted proxy reports the client through `X-Forwarded-For`/`X-Forwarded-Proto` or the RFC 7239
+//! `Forwarded` header; the `forwarded_header` option selects which of them is read first. Headers
+//! sent by any other peer are ignored, as they can be forged by the client.
+
+use std::net::{IpAddr, SocketAddr};
+
+use chico_file::types::ForwardedHeader;
+use http::{uri::Scheme, HeaderMap};
+use ipnet::IpNet;
+
+pub(crate) const X_FORWARDED_FOR: &str = "x-forwarded-for";
+pub(crate) const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+pub(crate) const X_FORWARDED_HOST: &str = "x-forwarded-host";
+
+/// Request extension holding the address of the TCP peer.
+#[derive(Clone, Copy, Debug)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// Request extension marking a request received over TLS.
+#[derive(Clone, Copy, Debug)]
+pub struct TlsConnection;
+
+/// Request extension holding the server name (SNI) the client sent in the TLS handshake, with the
+/// port of the listener it connected to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerName {
+    pub host: String,
+    pub port: u16,
+}
+
+/// Request extension holding the resolved client.
+#[derive(Clone, Debug, PartialEq)]
+#[allow(dead_code)]
+pub struct ClientInfo {
+    pub ip: IpAddr,
+    pub scheme: Scheme,
+}
+
+#[derive(Default)]
+pub struct ClientResolver {
+    trusted_proxies: Vec<IpNet>,
+    forwarded_header: ForwardedHeader,
+}
+
+/// One hop reported by a forwarding header.
+struct Hop {
+    ip: Option<IpAddr>,
+    proto: Option<String>,
+}
+
+impl ClientResolver {
+    pub fn new(trusted_proxies: Vec<IpNet>, forwarded_header: ForwardedHeader) -> Self {
+        Self {
+            trusted_proxies,
+            forwarded_header,
+        }
+    }
+
+    /// Resolves the client of a request received from `peer` over a connection using `scheme`.
+    pub fn resolve(&self, peer: IpAddr, scheme: Scheme, headers: &HeaderMap) -> ClientInfo {
+        let direct = ClientInfo { ip: peer, scheme };
+        if !self.is_trusted(peer) {
+            return direct;
+        }
+
+        let x_forwarded = || self.read_x_forwarded(headers);
+        let forwarded = || self.read_forwarded(headers);
+        let resolved = match self.forwarded_header {
+            ForwardedHeader::Ignore => x_forwarded(),
+            ForwardedHeader::Trust => x_forwarded().or_else(forwarded),
+            ForwardedHeader::Prefer => forwarded().or_else(x_forwarded),
+        };
+        resolved.unwrap_or(direct)
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.trusted_proxies.iter().any(|net| net.contains(&ip))
+    }
+
+    fn read_x_forwarded(&self, headers: &HeaderMap) -> Option<ClientInfo> {
+        let hops: Vec<Hop> = header_list(headers, X_FORWARDED_FOR)
+            .map(|node| Hop {
+                ip: parse_node(node),
+                proto: None,
+            })
+            .collect();
+        let hop = self.client_hop(&hops)?;
+
+        // Proxies add the scheme of the connection they received, the first one faced the client.
+        let proto = header_list(headers, X_FORWARDED_PROTO).next();
+        Some(ClientInfo {
+            ip: hop.ip?,
+            scheme: parse_scheme(proto),
+        })
+    }
+
+    fn read_forwarded(&self, headers: &HeaderMap) -> Option<ClientInfo> {
+        let hops: Vec<Hop> = header_list(headers, http::header::FORWARDED.as_str())
+            .map(parse_forwarded_element)
+            .collect();
+        let hop = self.client_hop(&hops)?;
+
+        Some(ClientInfo {
+            ip: hop.ip?,
+            scheme: parse_scheme(hop.proto.as_deref()),
+        })
+    }
+
+    /// Returns the rightmost hop that is not a trusted proxy, or the leftmost one when every hop
+    /// is trusted. Hops left of it were added by the client and cannot be relied on.
+    fn client_hop<'a>(&self, hops: &'a [Hop]) -> Option<&'a Hop> {
+        hops.iter()
+            .rev()
+            .find(|hop| !hop.ip.is_some_and(|ip| self.is_trusted(ip)))
+            .or(hops.first())
+    }
+}
+
+/// Iterates the comma separated values of all the headers with the given name.
+fn header_list<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// Parses a `Forwarded` element like `for=192.0.2.60;proto=http;by=203.0.113.43`.
+fn parse_forwarded_element(element: &str) -> Hop {
+    let mut hop = Hop {
+        ip: None,
+        proto: None,
+    };
+    for pair in element.split(';') {
+        let Some((name, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"');
+        match name.trim().to_ascii_lowercase().as_str() {
+            "for" => hop.ip = parse_node(value),
+            "proto" => hop.proto = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    hop
+}
+
+/// Parses a node like `192.0.2.43`, `192.0.2.43:47011`, `[2001:db8::17]` or `[2001:db8::17]:4711`.
+///
+/// Obfuscated identifiers and `unknown` have no address and return `None`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    node.parse::<IpAddr>()
+        .or_else(|_| node.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| {
+            node.trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+        })
+        .ok()
+}
+
+fn parse_scheme(proto: Option<&str>) -> Scheme {
+    match proto {
+        Some(proto) if proto.eq_ignore_ascii_case("https") => Scheme::HTTPS,
+        _ => Scheme::HTTP,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use chico_file::types::ForwardedHeader;
+    use http::{uri::Scheme, HeaderMap};
+    use rstest::rstest;
+
+    use super::{ClientInfo, ClientResolver};
+
+    fn resolver(forwarded_header: ForwardedHeader) -> ClientResolver {
+        ClientResolver::new(
+            vec!["10.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()],
+            forwarded_header,
+        )
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn client(ip: &str, scheme: Scheme) -> ClientInfo {
+        ClientInfo {
+            ip: ip.parse().unwrap(),
+            scheme,
+        }
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_resolve_forwarded_client_ip_and_scheme() {
+        let resolved = resolver(ForwardedHeader::Trust).resolve(
+            ip("10.0.0.1"),
+            Scheme::HTTP,
+            &headers(&[("forwarded", "for=1.2.3.4;proto=https")]),
+        );
+        assert_eq!(resolved, client("1.2.3.4", Scheme::HTTPS));
+    }
+
+    #[rstest]
+    #[case("for=\"[2001:db8::17]:4711\";proto=http", "2001:db8::17", Scheme::HTTP)]
+    #[case("for=\"[2001:db8::17]\"", "2001:db8::17", Scheme::HTTP)]
+    #[case("For=1.2.3.4:8080;Proto=HTTPS", "1.2.3.4", Scheme::HTTPS)]
+    #[case("for=5.6.7.8, for=1.2.3.4;proto=https", "1.2.3.4", Scheme::HTTPS)]
+    #[case("for=1.2.3.4;proto=https, for=10.0.0.2", "1.2.3.4", Scheme::HTTPS)]
+    #[case("for=10.0.0.3, for=10.0.0.2", "10.0.0.3", Scheme::HTTP)]
+    #[case("by=10.0.0.1;for=1.2.3.4;host=example.com", "1.2.3.4", Scheme::HTTP)]
+    fn test_resolve_forwarded_elements(
+        #[case] forwarded: &'static str,
+        #[case] expected_ip: &str,
+        #[case] expected_scheme: Scheme,
+    ) {
+        let resolved = resolver(ForwardedHeader::Prefer).resolve(
+            ip("10.0.0.1"),
+            Scheme::HTTP,
+            &headers(&[("forwarded", forwarded)]),
+        );
+        assert_eq!(resolved, client(expected_ip, expected_scheme));
+    }
+
+    #[rstest]
+    #[case("for=unknown")]
+    #[case("for=_hidden")]
+    #[case("proto=https")]
+    fn test_forwarded_without_address_is_ignored(#[case] forwarded: &'static str) {
+        let resolved = resolver(ForwardedHeader::Prefer).resolve(
+            ip("10.0.0.1"),
+            Scheme::HTTP,
+            &headers(&[("forwarded", forwarded)]),
+        );
+        assert_eq!(resolved, client("10.0.0.1", Scheme::HTTP));
+    }
+
+    #[test]
+    fn test_headers_of_untrusted_peer_are_ignored() {
+        let resolved = resolver(ForwardedHeader::Prefer).resolve(
+            ip("192.168.1.1"),
+            Scheme::HTTP,
+            &headers(&[
+                ("forwarded", "for=1.2.3.4;proto=https"),
+                ("x-forwarded-for", "1.2.3.4"),
+            ]),
+        );
+        assert_eq!(resolved, client("192.168.1.1", Scheme::HTTP));
+    }
+
+    #[test]
+    fn test_nothing_is_trusted_by_default() {
+        let resolved = ClientResolver::default().resolve(
+            ip("127.0.0.1"),
+            Scheme::HTTP,
+            &headers(&[("x-forwarded-for", "1.2.3.4")]),
+        );
+        assert_eq!(resolved, client("127.0.0.1", Scheme::HTTP));
+    }
+
+    #[test]
+    fn test_scheme_of_direct_connection_is_kept() {
+        let resolved =
+            ClientResolver::default().resolve(ip("127.0.0.1"), Scheme::HTTPS, &headers(&[]));
+        assert_eq!(resolved, client("127.0.0.1", Scheme::HTTPS));
+    }
+
+    #[test]
+    fn test_ipv4_mapped_peer_is_trusted() {
+        let resolved = resolver(ForwardedHeader::Ignore).resolve(
+            ip("::ffff:10.0.0.1"),
+            Scheme::HTTP,
+            &headers(&[("x-forwarded-for", "1.2.3.4")]),
+        );
+        assert_eq!(resolved, client("1.2.3.4", Scheme::HTTP));
+    }
+
+    #[rstest]
+    #[case(ForwardedHeader::Ignore, "5.6.7.8", Scheme::HTTP)]
+    #[case(ForwardedHeader::Trust, "5.6.7.8", Scheme::HTTP)]
+    #[case(ForwardedHeader::Prefer, "1.2.3.4", Scheme::HTTPS)]
+    fn test_forwarded_header_precedence(
+        #[case] forwarded_header: ForwardedHeader,
+        #[case] expected_ip: &str,
+        #[case] expected_scheme: Scheme,
+    ) {
+        let resolved = resolver(forwarded_header).resolve(
+            ip("::1"),
+            Scheme::HTTP,
+            &headers(&[
+                ("forwarded", "for=1.2.3.4;proto=https"),
+                ("x-forwarded-for", "5.6.7.8"),
+            ]),
+        );
+        assert_eq!(resolved, client(expected_ip, expected_scheme));
+    }
+
+    #[rstest]
+    #[case(ForwardedHeader::Ignore, "10.0.0.1", Scheme::HTTP)]
+    #[case(ForwardedHeader::Trust, "1.2.3.4", Scheme::HTTPS)]
+    #[case(ForwardedHeader::Prefer, "1.2.3.4", Scheme::HTTPS)]
+    fn test_forwarded_header_fallback(
+        #[case] forwarded_header: ForwardedHeader,
+        #[case] expected_ip: &str,
+        #[case] expected_scheme: Scheme,
+    ) {
+        let resolved = resolver(forwarded_header).resolve(
+            ip("10.0.0.1"),
+            Scheme::HTTP,
+            &headers(&[("forwarded", "for=1.2.3.4;proto=https")]),
+        );
+        assert_eq!(resolved, client(expected_ip, expected_scheme));
+    }
+
+    #[rstest]
+    #[case(&[("x-forwarded-for", "1.2.3.4"), ("x-forwarded-proto", "https")], "1.2.3.4", Scheme::HTTPS)]
+    #[case(&[("x-forwarded-for", "9.9.9.9, 1.2.3.4, 10.0.0.2")], "1.2.3.4", Scheme::HTTP)]
+    #[case(&[("x-forwarded-for", "9.9.9.9"), ("x-forwarded-for", "1.2.3.4")], "1.2.3.4", Scheme::HTTP)]
+    #[case(&[("x-forwarded-proto", "https")], "10.0.0.1", Scheme::HTTP)]
+    fn test_resolve_x_forwarded(
+        #[case] pairs: &[(&'static str, &'static str)],
+        #[case] expected_ip: &str,
+        #[case] expected_scheme: Scheme,
+    ) {
+        let resolved = resolver(ForwardedHeader::Ignore).resolve(
+            ip("10.0.0.1"),
+            Scheme::HTTP,
+            &headers(pairs),
+        );
+        assert_eq!(resolved, client(expected_ip, expected_scheme));
+    }
+}
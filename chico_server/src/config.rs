@@ -1,19 +1,77 @@
-use chico_file::{parse_config, types::Config};
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
 
-use crate::virtual_host::VirtualHostExt;
+use chico_file::{
+    parse_config_file,
+    types::{Config, Handler, HostHeader, LogLevel, RoutePath, VirtualHost},
+};
+use regex::Regex;
+use tracing::{level_filters::LevelFilter, warn};
+
+use crate::{
+    handlers::{respond::RespondHandler, reverse_proxy::ReverseProxyHandler},
+    middlewares::{compression::Zstd, csp::ContentSecurityPolicy, header::HeaderRules},
+    server::DRAIN_TIMEOUT,
+    tls,
+    virtual_host::VirtualHostExt,
+};
+
+pub const DEFAULT_MAX_VIRTUAL_HOSTS: usize = 10_000;
+pub const DEFAULT_MAX_ROUTES: usize = 100_000;
+
+/// Options of the config validation
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationOptions {
+    /// Soft limit on the number of virtual hosts
+    pub max_virtual_hosts: usize,
+    /// Soft limit on the total number of routes of all virtual hosts
+    pub max_routes: usize,
+    /// Treat validation warnings as errors
+    pub strict: bool,
+}
+
+impl Default for ValidationOptions {
+    fn default() -> Self {
+        Self {
+            max_virtual_hosts: DEFAULT_MAX_VIRTUAL_HOSTS,
+            max_routes: DEFAULT_MAX_ROUTES,
+            strict: false,
+        }
+    }
+}
 
 pub trait ConfigExt {
     fn get_ports(&self) -> Vec<u16>;
+    /// Level of the logs set in the options block.
+    fn log_level(&self) -> Option<LevelFilter>;
 }
 
 impl ConfigExt for Config {
     fn get_ports(&self) -> Vec<u16> {
-        self.virtual_hosts.iter().map(|vh| vh.get_port()).collect()
+        self.virtual_hosts
+            .iter()
+            .map(|vh| vh.get_port(self.options.default_port))
+            .collect()
+    }
+
+    fn log_level(&self) -> Option<LevelFilter> {
+        self.options.log_level.map(|level| match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        })
     }
 }
 
 /// Validate the config file content
-pub(crate) async fn validate_config_file(path: &str) -> Result<Config, String> {
+pub async fn validate_config_file(
+    path: &str,
+    options: &ValidationOptions,
+) -> Result<Config, String> {
     let content = tokio::fs::read_to_string(path).await;
     if content.is_err() {
         return Err(format!(
@@ -23,22 +81,44 @@ pub(crate) async fn validate_config_file(path: &str) -> Result<Config, String> {
     }
 
     let content = content.unwrap();
-    parse_with_validate(&content)
-}
+    check_not_empty(&content)?;
 
-fn parse_with_validate(content: &str) -> Result<Config, String> {
-    if content.is_empty() {
-        return Err("Failed to parse content. reason: content is empty.".to_string());
+    let parse_result = parse_config_file(Path::new(path), &content);
+
+    if parse_result.is_err() {
+        let formatted_error = parse_result.err().unwrap();
+        return Err(format!("Failed to parse config file. {}", formatted_error));
     }
 
-    let parse_result = parse_config(content);
+    let config = parse_result.unwrap();
+    let expansions = config.expansions.clone();
+    validate_config(config, options).map_err(|e| expansions.redact(&e))
+}
+
+/// Validate config content that is not read from a file, so it cannot include other files
+#[cfg(test)]
+fn parse_with_validate(content: &str, options: &ValidationOptions) -> Result<Config, String> {
+    check_not_empty(content)?;
+
+    let parse_result = chico_file::parse_config(content);
 
     if parse_result.is_err() {
         let formatted_error = parse_result.err().unwrap();
         return Err(format!("Failed to parse config file. {}", formatted_error));
     }
 
-    let config = parse_result.unwrap().1;
+    validate_config(parse_result.unwrap().1, options)
+}
+
+fn check_not_empty(content: &str) -> Result<(), String> {
+    if content.is_empty() {
+        return Err("Failed to parse content. reason: content is empty.".to_string());
+    }
+    Ok(())
+}
+
+/// Validates the parsed config, including the virtual hosts of the included files
+fn validate_config(config: Config, options: &ValidationOptions) -> Result<Config, String> {
     let virtual_hosts = &config.virtual_hosts;
 
     if virtual_hosts.is_empty() {
@@ -47,19 +127,24 @@ fn parse_with_validate(content: &str) -> Result<Config, String> {
 
     // any logical validation like checking for duplicate domains, routes, etc.
 
-    // checking for duplicate domains
+    // checking for duplicate domains, serving the same host on the same port
     let mut domains = vec![];
     for host in virtual_hosts.iter() {
-        if domains.contains(&host.domain) {
+        let domain = (
+            host.domain.host.to_ascii_lowercase(),
+            host.get_port(config.options.default_port),
+        );
+        if domains.contains(&domain) {
             return Err(format!(
                 "Failed to parse config file. reason: duplicate domain found: {}",
                 host.domain
             ));
         }
-        domains.push(host.domain.clone());
+        domains.push(domain);
     }
 
     // checking for duplicate routes
+    let mut route_count = 0;
     for host in virtual_hosts.iter() {
         let mut paths = vec![];
         for route in host.routes.iter() {
@@ -71,11 +156,411 @@ fn parse_with_validate(content: &str) -> Result<Config, String> {
             }
             paths.push(route.path.clone());
         }
+        route_count += host.routes.len();
+    }
+
+    let tls_warnings = check_tls(&config)?;
+    check_route_paths(virtual_hosts)?;
+    check_matchers(virtual_hosts)?;
+    check_middlewares(virtual_hosts)?;
+    check_respond_files(virtual_hosts)?;
+    check_host_headers(virtual_hosts)?;
+    check_upstream_weights(virtual_hosts)?;
+    check_csp(&config)?;
+
+    let mut warnings = check_limits(virtual_hosts.len(), route_count, options);
+    warnings.extend(tls_warnings);
+    warnings.extend(check_handlers(virtual_hosts));
+    warnings.extend(check_timeouts(&config));
+
+    if options.strict {
+        if let Some(warning) = warnings.first() {
+            return Err(format!("Failed to parse config file. reason: {}", warning));
+        }
+    }
+    for warning in warnings.iter() {
+        warn!("{}", warning);
     }
 
     Ok(config)
 }
 
+/// Checks that the certificates can be loaded and that no port serves both HTTPS and plain HTTP
+/// virtual hosts, reporting all the problems at once. Returns warnings for the expired
+/// certificates and the private keys readable by all users.
+fn check_tls(config: &Config) -> Result<Vec<String>, String> {
+    let default_port = config.options.default_port;
+    let mut errors = vec![];
+    let mut warnings = vec![];
+    for (index, host) in config.virtual_hosts.iter().enumerate() {
+        match &host.tls {
+            Some(tls) => match tls::load_certified_key(tls) {
+                Ok(key) => {
+                    let expiry = key.end_entity_cert().ok().and_then(tls::not_after);
+                    if let Some(expiry) = expiry.filter(|expiry| *expiry < SystemTime::now()) {
+                        warnings.push(format!(
+                            "certificate {} of host {} expired on {}",
+                            tls.cert,
+                            host.domain,
+                            httpdate::fmt_http_date(expiry)
+                        ));
+                    }
+                    if is_readable_by_all(Path::new(&tls.key)) {
+                        warnings.push(format!(
+                            "private key {} of host {} is readable by all users",
+                            tls.key, host.domain
+                        ));
+                    }
+                }
+                Err(e) => errors.push(format!("invalid TLS config of host {}: {}", host.domain, e)),
+            },
+            None if host.is_https() => errors.push(format!(
+                "host {} uses https but has no tls directive with its certificate and key",
+                host.domain
+            )),
+            None => {}
+        }
+
+        let port = host.get_port(default_port);
+        if let Some(other) = config.virtual_hosts[index + 1..].iter().find(|other| {
+            other.get_port(default_port) == port && other.tls.is_some() != host.tls.is_some()
+        }) {
+            errors.push(format!(
+                "hosts {} and {} share port {} but only one of them has a tls directive",
+                host.domain, other.domain, port
+            ));
+        }
+    }
+
+    if errors.is_empty() {
+        return Ok(warnings);
+    }
+    let errors: Vec<String> = errors
+        .iter()
+        .map(|error| format!("Failed to parse config file. reason: {}", error))
+        .collect();
+    Err(errors.join("\n"))
+}
+
+#[cfg(unix)]
+fn is_readable_by_all(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o004 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_readable_by_all(_path: &Path) -> bool {
+    false
+}
+
+/// Checks that the regular expressions of the route paths compile.
+fn check_route_paths(virtual_hosts: &[VirtualHost]) -> Result<(), String> {
+    for host in virtual_hosts.iter() {
+        for route in host.routes.iter() {
+            let RoutePath::Regex(pattern) = route.path_kind() else {
+                continue;
+            };
+            Regex::new(pattern).map_err(|e| {
+                format!(
+                    "Failed to parse config file. reason: invalid regex in host {} route {}: {}",
+                    host.domain, route.path, e
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the named matchers of a host are unique and that the routes use declared ones.
+fn check_matchers(virtual_hosts: &[VirtualHost]) -> Result<(), String> {
+    for host in virtual_hosts.iter() {
+        let mut names = vec![];
+        for matcher in host.matchers.iter() {
+            if names.contains(&&matcher.name) {
+                return Err(format!(
+                    "Failed to parse config file. reason: duplicate in host {} matcher found: @{}",
+                    host.domain, matcher.name
+                ));
+            }
+            names.push(&matcher.name);
+        }
+        for route in host.routes.iter() {
+            let Some(name) = route.path.strip_prefix('@') else {
+                continue;
+            };
+            if !host.matchers.iter().any(|matcher| matcher.name == name) {
+                return Err(format!(
+                    "Failed to parse config file. reason: unknown matcher in host {} route {}",
+                    host.domain, route.path
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks the arguments of the middlewares the parser accepts as any text, and of the header rules
+/// of the proxies.
+fn check_middlewares(virtual_hosts: &[VirtualHost]) -> Result<(), String> {
+    for host in virtual_hosts.iter() {
+        for route in host.routes.iter() {
+            for middleware in route.middlewares.iter() {
+                HeaderRules::validate(middleware)
+                    .and_then(|_| Zstd::validate(middleware))
+                    .map_err(|e| {
+                        format!(
+                            "Failed to parse config file. reason: {} in host {} route {}",
+                            e, host.domain, route.path
+                        )
+                    })?;
+            }
+            let Handler::Proxy(proxy) = &route.handler else {
+                continue;
+            };
+            for rule in proxy.header_up.iter().chain(&proxy.header_down) {
+                HeaderRules::validate(rule).map_err(|e| {
+                    format!(
+                        "Failed to parse config file. reason: {} in proxy of host {} route {}",
+                        e, host.domain, route.path
+                    )
+                })?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the files of the `respond file:` handlers can be read.
+fn check_respond_files(virtual_hosts: &[VirtualHost]) -> Result<(), String> {
+    for host in virtual_hosts.iter() {
+        for route in host.routes.iter() {
+            if let Handler::RespondFile { file, status } = &route.handler {
+                RespondHandler::from_file(status.unwrap_or(200), file).map_err(|e| {
+                    format!(
+                        "Failed to parse config file. reason: failed to read respond file {} in host {} route {}: {}",
+                        file, host.domain, route.path, e
+                    )
+                })?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the fixed `host_header` of the proxies is a valid authority, without user info.
+fn check_host_headers(virtual_hosts: &[VirtualHost]) -> Result<(), String> {
+    for host in virtual_hosts.iter() {
+        for route in host.routes.iter() {
+            let Handler::Proxy(proxy) = &route.handler else {
+                continue;
+            };
+            let HostHeader::Value(value) = &proxy.host_header else {
+                continue;
+            };
+            let valid = !value.contains('@')
+                && value
+                    .parse::<http::uri::Authority>()
+                    .is_ok_and(|authority| {
+                        // the port is what follows the host, if anything
+                        let port = &value[authority.host().len()..];
+                        port.is_empty() || port[1..].parse::<u16>().is_ok()
+                    });
+            if !valid {
+                return Err(format!(
+                    "Failed to parse config file. reason: invalid host_header {} in host {} route {}",
+                    value, host.domain, route.path
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that no upstream, of the proxies or their canaries, has a weight of 0.
+fn check_upstream_weights(virtual_hosts: &[VirtualHost]) -> Result<(), String> {
+    for host in virtual_hosts.iter() {
+        for route in host.routes.iter() {
+            let Handler::Proxy(proxy) = &route.handler else {
+                continue;
+            };
+            let canary = proxy.canary.iter().map(|canary| &canary.load_balancer);
+            let upstreams = [&proxy.load_balancer]
+                .into_iter()
+                .chain(canary)
+                .flat_map(|load_balancer| load_balancer.upstreams());
+            for upstream in upstreams {
+                if upstream.weight() == 0 {
+                    return Err(format!(
+                        "Failed to parse config file. reason: upstream {} in host {} route {} has weight 0, weights start at 1",
+                        upstream.get_host_port(), host.domain, route.path
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks the Content-Security-Policy of the options and of the virtual hosts.
+fn check_csp(config: &Config) -> Result<(), String> {
+    if let Some(csp) = &config.options.csp {
+        ContentSecurityPolicy::validate(csp)
+            .map_err(|e| format!("Failed to parse config file. reason: {} in options", e))?;
+    }
+    for host in config.virtual_hosts.iter() {
+        if let Some(csp) = &host.csp {
+            ContentSecurityPolicy::validate(csp).map_err(|e| {
+                format!(
+                    "Failed to parse config file. reason: {} in host {}",
+                    e, host.domain
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks the handler arguments for likely mistakes.
+fn check_handlers(virtual_hosts: &[VirtualHost]) -> Vec<String> {
+    let mut warnings = vec![];
+    for host in virtual_hosts.iter() {
+        for route in host.routes.iter() {
+            if let Handler::Respond {
+                status: None,
+                body: None,
+            } = route.handler
+            {
+                warnings.push(format!(
+                    "respond handler in host {} route {} has neither a status nor a body, it responds 200 with an empty body",
+                    host.domain, route.path
+                ));
+            }
+        }
+    }
+    warnings
+}
+
+/// Checks that the timeouts of a proxied request are nested: the connection timeout within the
+/// request timeout, and the request timeout within the shutdown drain. Pairs left at their
+/// defaults are not reported.
+fn check_timeouts(config: &Config) -> Vec<String> {
+    let drain = Timeout::new(
+        "graceful_shutdown_timeout",
+        config.options.graceful_shutdown_timeout,
+        DRAIN_TIMEOUT,
+    );
+    let mut warnings = vec![];
+    for host in config.virtual_hosts.iter() {
+        for route in host.routes.iter() {
+            let Handler::Proxy(proxy) = &route.handler else {
+                continue;
+            };
+            let connection = Timeout::with_option(
+                "connection_timeout",
+                proxy.connection_timeout,
+                (
+                    "default_connection_timeout",
+                    config.options.default_connection_timeout,
+                ),
+                ReverseProxyHandler::DEFAULT_CONNECTION_TIMEOUT,
+            );
+            let request = Timeout::with_option(
+                "request_timeout",
+                proxy.request_timeout,
+                (
+                    "default_request_timeout",
+                    config.options.default_request_timeout,
+                ),
+                ReverseProxyHandler::DEFAULT_REQUEST_TIMEOUT,
+            );
+
+            if connection.exceeds(&request) {
+                warnings.push(format!(
+                    "{} of the proxy in host {} route {} is longer than its {}: the request timeout only starts once connected, so a request may wait up to {:?} for the upstream",
+                    connection, host.domain, route.path, request,
+                    connection.value + request.value
+                ));
+            }
+            if request.exceeds(&drain) {
+                warnings.push(format!(
+                    "{} of the proxy in host {} route {} is longer than {} of the options block: on shutdown the drain wins and requests still waiting for the upstream are cut off after {:?}",
+                    request, host.domain, route.path, drain, drain.value
+                ));
+            }
+        }
+    }
+    warnings
+}
+
+/// A timeout directive with its effective value.
+struct Timeout {
+    directive: &'static str,
+    value: Duration,
+    explicit: bool,
+}
+
+impl Timeout {
+    fn new(directive: &'static str, value: Option<Duration>, default: Duration) -> Self {
+        Self {
+            directive,
+            value: value.unwrap_or(default),
+            explicit: value.is_some(),
+        }
+    }
+
+    /// Timeout of a proxy directive, falling back to its default of the options block.
+    fn with_option(
+        directive: &'static str,
+        value: Option<Duration>,
+        (option, option_value): (&'static str, Option<Duration>),
+        default: Duration,
+    ) -> Self {
+        match (value, option_value) {
+            (None, Some(option_value)) => Self::new(option, Some(option_value), default),
+            (value, _) => Self::new(directive, value, default),
+        }
+    }
+
+    /// Returns true when this timeout is longer than the one it should fit in, and the config
+    /// set at least one of them.
+    fn exceeds(&self, outer: &Timeout) -> bool {
+        (self.explicit || outer.explicit) && self.value > outer.value
+    }
+}
+
+impl std::fmt::Display for Timeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{} {:?}`", self.directive, self.value)?;
+        if !self.explicit {
+            f.write_str(" (default)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the number of virtual hosts and routes against the configured soft limits.
+fn check_limits(
+    virtual_host_count: usize,
+    route_count: usize,
+    options: &ValidationOptions,
+) -> Vec<String> {
+    let mut warnings = vec![];
+    if virtual_host_count > options.max_virtual_hosts {
+        warnings.push(format!(
+            "config declares {} virtual hosts, more than the limit of {}",
+            virtual_host_count, options.max_virtual_hosts
+        ));
+    }
+    if route_count > options.max_routes {
+        warnings.push(format!(
+            "config declares {} routes, more than the limit of {}",
+            route_count, options.max_routes
+        ));
+    }
+    warnings
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Write;
@@ -84,18 +569,20 @@ mod tests {
         parse_config,
         types::{Config, Handler, Route, VirtualHost},
     };
+    use claims::assert_err_eq;
     use rstest::rstest;
     use tempfile::NamedTempFile;
+    use tracing::level_filters::LevelFilter;
 
-    use crate::{
-        config::{parse_with_validate, ConfigExt},
-        validate_config_file,
+    use crate::config::{
+        check_handlers, check_limits, check_timeouts, check_tls, parse_with_validate,
+        validate_config_file, ConfigExt, ValidationOptions,
     };
 
     #[test]
     fn test_parse_with_validate_empty_content() {
         let content = "";
-        let result = parse_with_validate(content);
+        let result = parse_with_validate(content, &ValidationOptions::default());
         assert!(result.is_err());
         assert_eq!(
             result.err().unwrap(),
@@ -146,11 +633,41 @@ mod tests {
         "#,
         "example.com"
     )]
+    #[case(
+        r#"
+        example.com {
+            route / {
+                file index.html
+            }
+        }
+        http://Example.com:80 {
+            route / {
+                file index.html
+            }
+        }
+        "#,
+        "http://Example.com:80"
+    )]
+    #[case(
+        r#"
+        example.com www.example.com {
+            route / {
+                file index.html
+            }
+        }
+        www.example.com {
+            route / {
+                file index.html
+            }
+        }
+        "#,
+        "www.example.com"
+    )]
     fn test_parse_with_validate_duplicate_virtual_hosts(
         #[case] content: &str,
         #[case] domain: &str,
     ) {
-        let result = parse_with_validate(content);
+        let result = parse_with_validate(content, &ValidationOptions::default());
         assert!(result.is_err());
         assert_eq!(
             result.err().unwrap(),
@@ -161,6 +678,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_with_validate_same_host_on_other_port() {
+        let content = r#"
+        example.com {
+            route / {
+                respond 200
+            }
+        }
+        http://example.com:8080 {
+            route / {
+                respond 200
+            }
+        }
+        "#;
+
+        let result = parse_with_validate(content, &ValidationOptions::default());
+
+        claims::assert_ok!(result);
+    }
+
     #[rstest]
     #[case(
         r#"
@@ -206,7 +743,7 @@ mod tests {
         #[case] domain: &str,
         #[case] route: &str,
     ) {
-        let result = parse_with_validate(content);
+        let result = parse_with_validate(content, &ValidationOptions::default());
         assert!(result.is_err());
         assert_eq!(
             result.err().unwrap(),
@@ -217,6 +754,42 @@ mod tests {
         );
     }
 
+    #[rstest]
+    #[case(
+        "example.com { route @api { respond 200 } }",
+        "unknown matcher in host example.com route @api"
+    )]
+    #[case(
+        "example.com { @api { path /v1/* }\n @docs { path /docs }\n route @docs { respond 200 }\n route @apis { respond 200 } }",
+        "unknown matcher in host example.com route @apis"
+    )]
+    #[case(
+        "example.com { @api { path /v1/* }\n @api { path /v2/* }\n route @api { respond 200 } }",
+        "duplicate in host example.com matcher found: @api"
+    )]
+    fn test_parse_with_validate_invalid_matchers(#[case] content: &str, #[case] reason: &str) {
+        assert_err_eq!(
+            parse_with_validate(content, &ValidationOptions::default()),
+            format!("Failed to parse config file. reason: {reason}")
+        );
+    }
+
+    #[test]
+    fn test_parse_with_validate_invalid_route_regex() {
+        let content = "example.com {
+            route ~^/api/v[0-9+/ { respond 200 }
+            route ~^/docs/.*$ { respond 200 }
+        }";
+
+        let error = parse_with_validate(content, &ValidationOptions::default()).unwrap_err();
+        assert!(
+            error.starts_with(
+                "Failed to parse config file. reason: invalid regex in host example.com route ~^/api/v[0-9+/: "
+            ),
+            "{error}"
+        );
+    }
+
     #[test]
     fn test_parse_with_validate_valid_content() {
         let content = r#"
@@ -231,13 +804,13 @@ mod tests {
             }
         }
         "#;
-        let result = parse_with_validate(content);
+        let result = parse_with_validate(content, &ValidationOptions::default());
         assert!(result.is_ok());
     }
 
     #[tokio::test]
     async fn test_validate_config_file_path_not_exist() {
-        let result = validate_config_file("path/to/not/exist").await;
+        let result = validate_config_file("path/to/not/exist", &ValidationOptions::default()).await;
         assert!(result.is_err());
         assert!(result
             .err()
@@ -251,7 +824,7 @@ mod tests {
         let _ = temp_file.write_all(b"");
         let temp_file_path = temp_file.path();
         let temp_dir_path = temp_file_path.to_str().unwrap();
-        let result = validate_config_file(temp_dir_path).await;
+        let result = validate_config_file(temp_dir_path, &ValidationOptions::default()).await;
         assert!(result.is_err());
         assert_eq!(
             result.err().unwrap(),
@@ -281,32 +854,115 @@ mod tests {
         let temp_file_path = temp_file.path();
         let temp_file_path = temp_file_path.to_str().unwrap();
 
-        let result = validate_config_file(temp_file_path).await;
+        let result = validate_config_file(temp_file_path, &ValidationOptions::default()).await;
         assert_eq!(
             result,
             Ok(Config {
                 virtual_hosts: vec![
                     VirtualHost {
-                        domain: "localhost".to_string(),
+                        domain: "localhost".parse().unwrap(),
                         routes: vec![Route {
                             path: "/".to_string(),
                             handler: Handler::File("index.html".to_string()),
                             middlewares: vec![],
+                            error_format: None,
+                            sniff_extensionless: false,
+                            max_ranges: None,
+                            transcode: false,
+                            negotiate_language: false,
+                            methods: vec![],
                         }],
+                        error_format: None,
+                        trusted_proxies: vec![],
+                        forwarded_header: chico_file::types::ForwardedHeader::Ignore,
+                        allowed_methods: None,
+                        tls: None,
+                        matchers: vec![],
+                        error_pages: vec![],
+                        csp: None,
+                        rate_limit: None,
+                        merge_slashes: Default::default(),
                     },
                     VirtualHost {
-                        domain: "example.com".to_string(),
+                        domain: "example.com".parse().unwrap(),
                         routes: vec![Route {
                             path: "/".to_string(),
                             handler: Handler::File("index.html".to_string()),
                             middlewares: vec![],
+                            error_format: None,
+                            sniff_extensionless: false,
+                            max_ranges: None,
+                            transcode: false,
+                            negotiate_language: false,
+                            methods: vec![],
                         }],
+                        error_format: None,
+                        trusted_proxies: vec![],
+                        forwarded_header: chico_file::types::ForwardedHeader::Ignore,
+                        allowed_methods: None,
+                        tls: None,
+                        matchers: vec![],
+                        error_pages: vec![],
+                        csp: None,
+                        rate_limit: None,
+                        merge_slashes: Default::default(),
                     }
-                ]
+                ],
+                options: Default::default(),
+                expansions: Default::default(),
             })
         );
     }
 
+    #[tokio::test]
+    async fn test_validate_config_file_expands_environment_variables() {
+        std::env::set_var("CHICO_TEST_VALIDATE_BACKEND", "10.0.0.7");
+        let content = r#"
+        localhost {
+            route / {
+                proxy http://${CHICO_TEST_VALIDATE_BACKEND}:3000
+            }
+        }
+        "#;
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(content.as_bytes()).unwrap();
+
+        let config = validate_config_file(
+            temp_file.path().to_str().unwrap(),
+            &ValidationOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        let Handler::Proxy(proxy) = &config.virtual_hosts[0].routes[0].handler else {
+            panic!("expected a proxy handler");
+        };
+        assert_eq!(
+            proxy.load_balancer.upstreams()[0].get_host_port(),
+            "10.0.0.7:3000"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validate_config_file_with_unset_environment_variable() {
+        let content =
+            "localhost {\n    route / {\n        respond \"${CHICO_TEST_UNSET_BODY}\"\n    }\n}\n";
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(content.as_bytes()).unwrap();
+
+        let error = validate_config_file(
+            temp_file.path().to_str().unwrap(),
+            &ValidationOptions::default(),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(
+            error.contains("Environment variable CHICO_TEST_UNSET_BODY used at line 3 is not set"),
+            "{error}"
+        );
+    }
+
     #[tokio::test]
     async fn test_get_ports_when_ports_specified() {
         let content = r#"
@@ -375,10 +1031,32 @@ mod tests {
         assert!(ports.contains(&port));
     }
 
+    #[rstest]
+    #[case("localhost { route / { respond 200 } }", 8080)]
+    #[case("https://example.com { route / { respond 200 } }", 8080)]
+    #[case("localhost:3000 { route / { respond 200 } }", 3000)]
+    fn test_get_ports_with_default_port(#[case] virtual_host: &str, #[case] port: u16) {
+        let content = format!("options {{ default_port 8080 }}\n{virtual_host}");
+
+        let (_, config) = parse_config(&content).unwrap();
+        assert_eq!(config.get_ports(), vec![port]);
+    }
+
+    #[rstest]
+    #[case("", None)]
+    #[case("options { log_level warn }", Some(LevelFilter::WARN))]
+    #[case("options { log_level trace }", Some(LevelFilter::TRACE))]
+    fn test_log_level(#[case] options: &str, #[case] expected: Option<LevelFilter>) {
+        let content = format!("{options}\nlocalhost {{ route / {{ respond 200 }} }}");
+
+        let (_, config) = parse_config(&content).unwrap();
+        assert_eq!(config.log_level(), expected);
+    }
+
     #[test]
     fn test_parse_with_validate_improved_error_messages_invalid_syntax() {
         let content = "invalid syntax here";
-        let result = parse_with_validate(content);
+        let result = parse_with_validate(content, &ValidationOptions::default());
         assert!(result.is_err());
         let error_msg = result.err().unwrap();
 
@@ -393,7 +1071,7 @@ mod tests {
     #[test]
     fn test_parse_with_validate_improved_error_messages_missing_brace() {
         let content = "example.com { route / { file index.html ";
-        let result = parse_with_validate(content);
+        let result = parse_with_validate(content, &ValidationOptions::default());
         assert!(result.is_err());
         let error_msg = result.err().unwrap();
 
@@ -411,7 +1089,7 @@ example.com {
     }
 }
         "#;
-        let result = parse_with_validate(content);
+        let result = parse_with_validate(content, &ValidationOptions::default());
         assert!(result.is_err());
         let error_msg = result.err().unwrap();
 
@@ -448,4 +1126,456 @@ example.com {
             }
         }
     }
+
+    fn generate_config(virtual_hosts: usize, routes_per_host: usize) -> String {
+        let mut content = String::new();
+        for host in 0..virtual_hosts {
+            content.push_str(&format!("host{host}.example.com {{\n"));
+            for route in 0..routes_per_host {
+                content.push_str(&format!("    route /r{route} {{ respond 200 }}\n"));
+            }
+            content.push_str("}\n");
+        }
+        content
+    }
+
+    #[test]
+    fn test_check_limits_warns_when_exceeded() {
+        let options = ValidationOptions {
+            max_virtual_hosts: 3,
+            max_routes: 10,
+            strict: false,
+        };
+
+        assert!(check_limits(3, 10, &options).is_empty());
+        assert_eq!(
+            check_limits(4, 11, &options),
+            vec![
+                "config declares 4 virtual hosts, more than the limit of 3".to_string(),
+                "config declares 11 routes, more than the limit of 10".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_with_validate_above_limits_is_accepted_with_warning() {
+        let content = generate_config(5, 5);
+        let options = ValidationOptions {
+            max_virtual_hosts: 4,
+            max_routes: 20,
+            strict: false,
+        };
+
+        let config = parse_with_validate(&content, &options).unwrap();
+        assert_eq!(config.virtual_hosts.len(), 5);
+    }
+
+    #[rstest]
+    #[case(4, 100, "config declares 5 virtual hosts, more than the limit of 4")]
+    #[case(10, 24, "config declares 25 routes, more than the limit of 24")]
+    fn test_parse_with_validate_above_limits_fails_in_strict_mode(
+        #[case] max_virtual_hosts: usize,
+        #[case] max_routes: usize,
+        #[case] warning: &str,
+    ) {
+        let content = generate_config(5, 5);
+        let options = ValidationOptions {
+            max_virtual_hosts,
+            max_routes,
+            strict: true,
+        };
+
+        let result = parse_with_validate(&content, &options);
+        assert_eq!(
+            result,
+            Err(format!("Failed to parse config file. reason: {warning}"))
+        );
+    }
+
+    fn respond_route(status: Option<u16>, body: Option<&str>) -> VirtualHost {
+        VirtualHost {
+            domain: "localhost".parse().unwrap(),
+            routes: vec![Route {
+                path: "/health".to_string(),
+                handler: Handler::Respond {
+                    status,
+                    body: body.map(str::to_string),
+                },
+                middlewares: vec![],
+                error_format: None,
+                sniff_extensionless: false,
+                max_ranges: None,
+                transcode: false,
+                negotiate_language: false,
+                methods: vec![],
+            }],
+            error_format: None,
+            trusted_proxies: vec![],
+            forwarded_header: chico_file::types::ForwardedHeader::Ignore,
+            allowed_methods: None,
+            tls: None,
+            matchers: vec![],
+            error_pages: vec![],
+            csp: None,
+            rate_limit: None,
+            merge_slashes: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_check_handlers_warns_for_respond_without_status_and_body() {
+        assert_eq!(
+            check_handlers(&[respond_route(None, None)]),
+            vec![
+                "respond handler in host localhost route /health has neither a status nor a body, it responds 200 with an empty body"
+                    .to_string()
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case(Some(200), None)]
+    #[case(None, Some("OK"))]
+    #[case(Some(204), Some(""))]
+    fn test_check_handlers_accepts_respond_with_status_or_body(
+        #[case] status: Option<u16>,
+        #[case] body: Option<&str>,
+    ) {
+        assert!(check_handlers(&[respond_route(status, body)]).is_empty());
+    }
+
+    fn proxy_config(options: &str, proxy: &str) -> Config {
+        let (_, config) = chico_file::parse_config(&format!(
+            "options {{ {options} }}
+            localhost {{ route /api {{ proxy {{ upstreams http://localhost:3000 {proxy} }} }} }}"
+        ))
+        .unwrap();
+        config
+    }
+
+    #[rstest]
+    #[case(
+        "graceful_shutdown_timeout 1m",
+        "connection_timeout 60 request_timeout 30",
+        "`connection_timeout 60s` of the proxy in host localhost route /api is longer than its `request_timeout 30s`: the request timeout only starts once connected, so a request may wait up to 90s for the upstream"
+    )]
+    #[case(
+        "",
+        "connection_timeout 40",
+        "`connection_timeout 40s` of the proxy in host localhost route /api is longer than its `request_timeout 30s` (default): the request timeout only starts once connected, so a request may wait up to 70s for the upstream"
+    )]
+    #[case(
+        "",
+        "request_timeout 120",
+        "`request_timeout 120s` of the proxy in host localhost route /api is longer than `graceful_shutdown_timeout 10s` (default) of the options block: on shutdown the drain wins and requests still waiting for the upstream are cut off after 10s"
+    )]
+    #[case(
+        "graceful_shutdown_timeout 5s",
+        "",
+        "`request_timeout 30s` (default) of the proxy in host localhost route /api is longer than `graceful_shutdown_timeout 5s` of the options block: on shutdown the drain wins and requests still waiting for the upstream are cut off after 5s"
+    )]
+    #[case(
+        "grace_period 1m default_request_timeout 20s default_connection_timeout 25s",
+        "",
+        "`default_connection_timeout 25s` of the proxy in host localhost route /api is longer than its `default_request_timeout 20s`: the request timeout only starts once connected, so a request may wait up to 45s for the upstream"
+    )]
+    #[case(
+        "grace_period 1m default_request_timeout 5s default_connection_timeout 25s",
+        "request_timeout 20",
+        "`default_connection_timeout 25s` of the proxy in host localhost route /api is longer than its `request_timeout 20s`: the request timeout only starts once connected, so a request may wait up to 45s for the upstream"
+    )]
+    fn test_check_timeouts_warns_when_not_nested(
+        #[case] options: &str,
+        #[case] proxy: &str,
+        #[case] warning: &str,
+    ) {
+        assert_eq!(
+            check_timeouts(&proxy_config(options, proxy)),
+            vec![warning.to_string()]
+        );
+    }
+
+    #[rstest]
+    #[case("", "")]
+    #[case(
+        "graceful_shutdown_timeout 1m",
+        "connection_timeout 5 request_timeout 20"
+    )]
+    #[case("graceful_shutdown_timeout 30s", "request_timeout 30")]
+    #[case("grace_period 1m default_request_timeout 45s", "")]
+    #[case("", "connection_timeout 500ms request_timeout 2s")]
+    fn test_check_timeouts_accepts_nested_timeouts(#[case] options: &str, #[case] proxy: &str) {
+        assert!(check_timeouts(&proxy_config(options, proxy)).is_empty());
+    }
+
+    #[test]
+    fn test_parse_with_validate_accepts_tls_host() {
+        let content = "https://localhost:3443 {
+            tls resources/test_cases/tls/cert.pem resources/test_cases/tls/key.pem
+            route / { respond 200 }
+        }";
+
+        let config = parse_with_validate(content, &ValidationOptions::default()).unwrap();
+        assert!(config.virtual_hosts[0].tls.is_some());
+    }
+
+    #[rstest]
+    #[case(
+        "missing.pem",
+        "key.pem",
+        "failed to read certificate resources/test_cases/tls/missing.pem"
+    )]
+    #[case(
+        "cert.pem",
+        "other_key.pem",
+        "private key resources/test_cases/tls/other_key.pem does not match certificate"
+    )]
+    fn test_parse_with_validate_rejects_invalid_tls(
+        #[case] cert: &str,
+        #[case] key: &str,
+        #[case] reason: &str,
+    ) {
+        let content = format!(
+            "https://localhost:3443 {{
+                tls resources/test_cases/tls/{cert} resources/test_cases/tls/{key}
+                route / {{ respond 200 }}
+            }}"
+        );
+
+        let error = parse_with_validate(&content, &ValidationOptions::default()).unwrap_err();
+        assert!(
+            error.starts_with(&format!(
+                "Failed to parse config file. reason: invalid TLS config of host https://localhost:3443: {reason}"
+            )),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_parse_with_validate_reports_all_tls_problems() {
+        let content = "https://a.localhost:3443 {
+            tls resources/test_cases/tls/missing.pem resources/test_cases/tls/key.pem
+            route / { respond 200 }
+        }
+        https://b.localhost:3443 {
+            tls resources/test_cases/tls/cert.pem resources/test_cases/tls/key.pem
+            route / { respond 200 }
+        }
+        https://c.localhost:3443 {
+            tls resources/test_cases/tls/cert.pem resources/test_cases/tls/other_key.pem
+            route / { respond 200 }
+        }
+        https://d.localhost {
+            route / { respond 200 }
+        }";
+
+        let error = parse_with_validate(content, &ValidationOptions::default()).unwrap_err();
+        let errors: Vec<&str> = error.lines().collect();
+        assert_eq!(errors.len(), 3, "{error}");
+        assert!(errors[0].starts_with("Failed to parse config file. reason: invalid TLS config of host https://a.localhost:3443: failed to read certificate resources/test_cases/tls/missing.pem"));
+        assert!(errors[1].starts_with("Failed to parse config file. reason: invalid TLS config of host https://c.localhost:3443: private key resources/test_cases/tls/other_key.pem does not match"));
+        assert_eq!(errors[2], "Failed to parse config file. reason: host https://d.localhost uses https but has no tls directive with its certificate and key");
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    #[case("cert.pem", "key.pem", 0o600, vec![])]
+    #[case(
+        "expired_cert.pem",
+        "expired_key.pem",
+        0o600,
+        vec!["certificate {dir}/cert.pem of host https://localhost:3443 expired on Fri, 01 Jan 2021 00:00:00 GMT"]
+    )]
+    #[case(
+        "cert.pem",
+        "key.pem",
+        0o644,
+        vec!["private key {dir}/key.pem of host https://localhost:3443 is readable by all users"]
+    )]
+    fn test_check_tls_warnings(
+        #[case] cert: &str,
+        #[case] key: &str,
+        #[case] key_mode: u32,
+        #[case] expected: Vec<&str>,
+    ) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().to_str().unwrap();
+        std::fs::copy(
+            format!("resources/test_cases/tls/{cert}"),
+            format!("{dir}/cert.pem"),
+        )
+        .unwrap();
+        std::fs::copy(
+            format!("resources/test_cases/tls/{key}"),
+            format!("{dir}/key.pem"),
+        )
+        .unwrap();
+        std::fs::set_permissions(
+            format!("{dir}/key.pem"),
+            std::fs::Permissions::from_mode(key_mode),
+        )
+        .unwrap();
+        let (_, config) = chico_file::parse_config(&format!(
+            "https://localhost:3443 {{
+                tls {dir}/cert.pem {dir}/key.pem
+                route / {{ respond 200 }}
+            }}"
+        ))
+        .unwrap();
+
+        let expected: Vec<String> = expected
+            .iter()
+            .map(|warning| warning.replace("{dir}", dir))
+            .collect();
+        assert_eq!(check_tls(&config), Ok(expected));
+    }
+
+    #[test]
+    fn test_parse_with_validate_rejects_https_host_without_tls() {
+        let content = "https://example.com {
+            route / { respond 200 }
+        }";
+
+        assert_eq!(
+            parse_with_validate(content, &ValidationOptions::default()),
+            Err("Failed to parse config file. reason: host https://example.com uses https but has no tls directive with its certificate and key".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_with_validate_rejects_tls_and_plain_hosts_on_same_port() {
+        let content = "https://localhost:3443 {
+            tls resources/test_cases/tls/cert.pem resources/test_cases/tls/key.pem
+            route / { respond 200 }
+        }
+        example.com:3443 {
+            route / { respond 200 }
+        }";
+
+        assert_eq!(
+            parse_with_validate(content, &ValidationOptions::default()),
+            Err("Failed to parse config file. reason: hosts https://localhost:3443 and example.com:3443 share port 3443 but only one of them has a tls directive".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_with_validate_rejects_invalid_header_middleware() {
+        let content = "localhost {
+            route /api {
+                respond 200
+                header =X-Frame-Options
+            }
+        }";
+
+        assert_eq!(
+            parse_with_validate(content, &ValidationOptions::default()),
+            Err("Failed to parse config file. reason: header X-Frame-Options needs a value in host localhost route /api".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_with_validate_rejects_missing_respond_file() {
+        let content = "localhost {
+            route / {
+                respond file:/path/to/missing/maintenance.html 503
+            }
+        }";
+
+        let error = parse_with_validate(content, &ValidationOptions::default()).unwrap_err();
+
+        assert!(error.starts_with("Failed to parse config file. reason: failed to read respond file /path/to/missing/maintenance.html in host localhost route /:"));
+    }
+
+    #[test]
+    fn test_parse_with_validate_accepts_existing_respond_file() {
+        let page = tempfile::NamedTempFile::with_suffix(".html").unwrap();
+        let content = format!(
+            "localhost {{ route / {{ respond file:{} 503 }} }}",
+            page.path().display()
+        );
+
+        assert!(parse_with_validate(&content, &ValidationOptions::default()).is_ok());
+    }
+
+    #[rstest]
+    #[case("api.internal")]
+    #[case("api.internal:8443")]
+    #[case("[::1]:8080")]
+    fn test_parse_with_validate_accepts_host_header(#[case] value: &str) {
+        let content = format!(
+            "localhost {{ route / {{ proxy {{ upstreams http://127.0.0.1:8080 host_header {value} }} }} }}"
+        );
+
+        assert!(parse_with_validate(&content, &ValidationOptions::default()).is_ok());
+    }
+
+    #[rstest]
+    #[case("header_up =Authorization", "header Authorization needs a value")]
+    #[case(
+        "header_down +X-Proxied-By \"a\\nb\"",
+        "invalid value a\nb of header X-Proxied-By"
+    )]
+    #[case("header_up -X(Debug)", "invalid header name X(Debug)")]
+    fn test_parse_with_validate_rejects_invalid_proxy_header_rules(
+        #[case] directive: &str,
+        #[case] error: &str,
+    ) {
+        let content = format!(
+            "localhost {{ route / {{ proxy {{ upstreams http://127.0.0.1:8080\n {directive}\n }} }} }}"
+        );
+
+        assert_eq!(
+            parse_with_validate(&content, &ValidationOptions::default()),
+            Err(format!(
+                "Failed to parse config file. reason: {error} in proxy of host localhost route /"
+            ))
+        );
+    }
+
+    #[rstest]
+    #[case("api.internal:port")]
+    #[case("user@api.internal")]
+    #[case("api.internal/path")]
+    fn test_parse_with_validate_rejects_invalid_host_header(#[case] value: &str) {
+        let content = format!(
+            "localhost {{ route / {{ proxy {{ upstreams http://127.0.0.1:8080 host_header {value} }} }} }}"
+        );
+
+        assert_eq!(
+            parse_with_validate(&content, &ValidationOptions::default()),
+            Err(format!("Failed to parse config file. reason: invalid host_header {value} in host localhost route /"))
+        );
+    }
+
+    #[rstest]
+    #[case(
+        r#"options { csp "" } localhost { route / { respond 200 } }"#,
+        "csp needs a policy in options"
+    )]
+    #[case(
+        r#"localhost { csp_report_only "default-src\n'self'" route / { respond 200 } }"#,
+        "invalid csp policy \"default-src\\n'self'\" in host localhost"
+    )]
+    fn test_parse_with_validate_rejects_invalid_csp(#[case] content: &str, #[case] reason: &str) {
+        assert_eq!(
+            parse_with_validate(content, &ValidationOptions::default()),
+            Err(format!("Failed to parse config file. reason: {reason}"))
+        );
+    }
+
+    #[rstest]
+    #[case("upstreams http://big:8080 weight=0 http://small:8080")]
+    #[case("upstreams http://big:8080 canary { match header X-Canary=true upstreams http://canary:8080 weight=0 }")]
+    fn test_parse_with_validate_rejects_upstream_weight_zero(#[case] proxy: &str) {
+        let content = format!("localhost {{ route / {{ proxy {{ {proxy} }} }} }}");
+
+        let error = parse_with_validate(&content, &ValidationOptions::default()).unwrap_err();
+
+        assert!(
+            error.contains("has weight 0, weights start at 1"),
+            "{error}"
+        );
+    }
 }
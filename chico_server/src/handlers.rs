@@ -1,15 +1,44 @@
-use std::{collections::HashMap, str::FromStr, sync::Arc};
+use std::{collections::HashMap, net::IpAddr, str::FromStr, sync::Arc, time::Duration};
 
-use crate::{handlers::respond::RespondHandler, plan::ServerPlan};
+use crate::{
+    client::{ClientInfo, PeerAddr, ServerName, TlsConnection},
+    handlers::{
+        error::ErrorPage, not_found::NotFoundHandler, redirect::RedirectHandler,
+        respond::RespondHandler, rewrite::MAX_REWRITES,
+    },
+    middlewares::{
+        auth::BasicAuth,
+        cache::ResponseCache,
+        compression::{self, AcceptEncoding},
+        concurrency,
+        cors::Cors,
+    },
+    plan::{HandlerPlan, RoutePlan, ServerPlan, VirtualHostPlan},
+};
+use chico_file::types::{ErrorFormat, MergeSlashes};
 use crates_uri::UriExt;
-use http::{Request, Uri};
+use http::{
+    uri::{PathAndQuery, Scheme},
+    Method, Request, StatusCode, Uri, Version,
+};
 use hyper::{body::Bytes, Response};
+use tracing::{debug, warn};
 pub type BoxBody = http_body_util::combinators::BoxBody<Bytes, std::io::Error>;
 
+pub mod browse;
+pub mod dir;
+pub mod error;
 pub mod file;
+pub mod multipart;
+pub mod not_found;
 pub mod redirect;
 pub mod respond;
+pub mod respond_echo;
 pub mod reverse_proxy;
+pub mod rewrite;
+pub mod try_files;
+// Handlers are used through their concrete types, so the `Send` bound of their futures is known.
+#[allow(async_fn_in_trait)]
 pub trait RequestHandler {
     async fn handle<B>(&self, request: Request<B>) -> Response<BoxBody>
     where
@@ -28,66 +57,465 @@ where
     B::Data: Send,
     B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
 {
-    let host = request.headers().get(http::header::HOST);
-    if host.is_none() {
-        return UtilitiesResponses::bad_request_host_header_not_found_respond_handler()
-            .handle(request)
-            .await;
+    let error_page = ErrorPage::from_request(&request);
+    let (response, error_format) = dispatch_request(request, &plan).await;
+    error_page.render(response, error_format)
+}
+
+/// Returns the host and port the request is addressed to.
+///
+/// The Host header wins. HTTP/2 requests carry the host in their `:authority`, which is the
+/// authority of the URI. Other HTTP/2 and HTTP/1.0 requests without host are addressed to the
+/// server name of their TLS connection, else HTTP/1.0 ones to the default host of the config, if
+/// any.
+fn request_authority<B>(
+    request: &hyper::Request<B>,
+    plan: &ServerPlan,
+) -> Result<(String, u16), RespondHandler> {
+    let uri = match request.headers().get(http::header::HOST) {
+        Some(host) => host
+            .to_str()
+            .ok()
+            .and_then(|host| Uri::from_str(host).ok())
+            .ok_or_else(UtilitiesResponses::bad_request_invalid_host_header_respond_handler)?,
+        None if request.version() >= Version::HTTP_2 && request.uri().host().is_some() => {
+            request.uri().clone()
+        }
+        None if request.version() != Version::HTTP_11
+            && request.extensions().get::<ServerName>().is_some() =>
+        {
+            let server_name = request.extensions().get::<ServerName>().unwrap();
+            debug!("request without host, serving it as {}", server_name.host);
+            return Ok((server_name.host.clone(), server_name.port));
+        }
+        None if request.version() <= Version::HTTP_10 && plan.default_host.is_some() => {
+            let domain = plan.default_host.as_ref().unwrap();
+            debug!("request without host, serving it as {}", domain);
+            Uri::from_str(&domain.to_string()).map_err(|_| {
+                UtilitiesResponses::bad_request_invalid_host_header_respond_handler()
+            })?
+        }
+        None => {
+            return Err(UtilitiesResponses::bad_request_host_header_not_found_respond_handler())
+        }
+    };
+
+    match uri.host() {
+        Some(host) => Ok((host.to_string(), uri.get_port())),
+        None => Err(UtilitiesResponses::bad_request_invalid_host_header_respond_handler()),
+    }
+}
+
+/// Returns the URI with the repeated slashes of its path collapsed, `None` when the path has none.
+fn merged_slashes(uri: &Uri) -> Option<Uri> {
+    if !uri.path().contains("//") {
+        return None;
+    }
+    let mut path = String::with_capacity(uri.path().len());
+    for c in uri.path().chars() {
+        if c != '/' || !path.ends_with('/') {
+            path.push(c);
+        }
+    }
+    if let Some(query) = uri.query() {
+        path = format!("{path}?{query}");
     }
 
-    let host = host.unwrap().to_str();
-    if host.is_err() {
-        return UtilitiesResponses::bad_request_invalid_host_header_respond_handler()
-            .handle(request)
-            .await;
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(PathAndQuery::try_from(path).ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+/// Returns false when the request has an `Expect` header other than `100-continue`.
+///
+/// hyper answers `100-continue` with `100 Continue` once a handler starts reading the body, so
+/// the proxy gets the body of the client while the handlers ignoring it, like `respond` or
+/// `file`, send their final response without waiting for it. HTTP/1.0 clients know no
+/// expectations, their `Expect` header is ignored.
+fn expectation_met<B>(request: &hyper::Request<B>) -> bool {
+    if request.version() <= Version::HTTP_10 {
+        return true;
     }
+    request
+        .headers()
+        .get_all(http::header::EXPECT)
+        .iter()
+        .all(|expect| expect.as_bytes().eq_ignore_ascii_case(b"100-continue"))
+}
 
-    let host = host.unwrap();
-    let uri = Uri::from_str(host);
-    if uri.is_err() {
-        return UtilitiesResponses::bad_request_invalid_host_header_respond_handler()
-            .handle(request)
-            .await;
+/// Selects the handler for the request and returns its response together with the error format
+/// configured for the matched virtual host and route.
+///
+/// Errors raised before a virtual host is known are negotiated from the `Accept` header.
+async fn dispatch_request<B>(
+    request: hyper::Request<B>,
+    plan: &ServerPlan,
+) -> (Response<BoxBody>, ErrorFormat)
+where
+    B: hyper::body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    // TRACE would reflect the request (cookies, credentials) back to the caller and chico is no
+    // forward proxy, so neither method reaches a handler or an upstream.
+    if request.method() == Method::TRACE {
+        return (
+            error::built_in(
+                UtilitiesResponses::method_not_allowed_respond_handler(ALLOWED_METHODS)
+                    .handle(request)
+                    .await,
+            ),
+            ErrorFormat::Auto,
+        );
+    }
+    if request.method() == Method::CONNECT {
+        return (
+            error::built_in(
+                UtilitiesResponses::not_implemented_respond_handler()
+                    .handle(request)
+                    .await,
+            ),
+            ErrorFormat::Auto,
+        );
     }
 
-    let uri = uri.unwrap();
-    let host = uri.host();
-    if host.is_none() {
-        return UtilitiesResponses::bad_request_invalid_host_header_respond_handler()
-            .handle(request)
-            .await;
+    if !expectation_met(&request) {
+        return (
+            error::built_in(
+                UtilitiesResponses::expectation_failed_respond_handler()
+                    .handle(request)
+                    .await,
+            ),
+            ErrorFormat::Auto,
+        );
     }
 
-    let host = host.unwrap();
-    let port = uri.get_port();
-    let vh = &plan.find_virtual_host(host, port);
+    let (host, port) = match request_authority(&request, plan) {
+        Ok(authority) => authority,
+        Err(handler) => {
+            return (
+                error::built_in(handler.handle(request).await),
+                ErrorFormat::Auto,
+            )
+        }
+    };
+    let vh = &plan.find_virtual_host(&host, port);
+
+    // the client may ask for a host with the certificate of another one
+    if let Some(server_name) = request.extensions().get::<ServerName>() {
+        let sni_vh = plan.find_virtual_host(&server_name.host, server_name.port);
+        if !same_virtual_host(*vh, sni_vh) {
+            if plan.strict_sni_host {
+                debug!(
+                    "request for host {} on a connection for {}, answering 421",
+                    host, server_name.host
+                );
+                return (
+                    error::built_in(
+                        UtilitiesResponses::misdirected_request_respond_handler()
+                            .handle(request)
+                            .await,
+                    ),
+                    ErrorFormat::Auto,
+                );
+            }
+            warn!(
+                "request for host {} on a connection for {}, the server names differ",
+                host, server_name.host
+            );
+        }
+    }
 
     if vh.is_none() {
-        return UtilitiesResponses::not_found_respond_handler()
-            .handle(request)
-            .await;
+        return (plan.not_found.handle(request).await, ErrorFormat::Auto);
     }
 
     let vh = vh.unwrap();
+    let (response, error_format) = dispatch_to_virtual_host(request, plan, vh).await;
+    let mut response = vh.error_pages.apply(response).await;
+    if let Some(csp) = &vh.csp {
+        csp.apply(&mut response);
+    }
+    (response, error_format)
+}
 
-    let route = vh.find_route(request.uri().path());
+/// Whether both lookups found the same virtual host, or none.
+fn same_virtual_host(a: Option<&VirtualHostPlan>, b: Option<&VirtualHostPlan>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => std::ptr::eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Selects the route of the virtual host for the request and returns its response together with
+/// the error format configured for the virtual host and route.
+async fn dispatch_to_virtual_host<B>(
+    mut request: hyper::Request<B>,
+    plan: &ServerPlan,
+    vh: &VirtualHostPlan,
+) -> (Response<BoxBody>, ErrorFormat)
+where
+    B: hyper::body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    if let Some(PeerAddr(peer)) = request.extensions().get::<PeerAddr>().copied() {
+        let scheme = match request.extensions().get::<TlsConnection>() {
+            Some(_) => Scheme::HTTPS,
+            None => Scheme::HTTP,
+        };
+        let client = vh
+            .client_resolver
+            .resolve(peer.ip(), scheme, request.headers());
+        debug!("resolved client {} ({})", client.ip, client.scheme);
+        request.extensions_mut().insert(client);
+    }
+    let vh_error_format = vh.error_format.unwrap_or(ErrorFormat::Html);
+
+    // the methods of the options apply to the virtual hosts without their own
+    if let Some(allowed_methods) = vh
+        .allowed_methods
+        .as_ref()
+        .or(plan.allowed_methods.as_ref())
+    {
+        if !allowed_methods.contains(request.method()) {
+            return (
+                error::built_in(
+                    UtilitiesResponses::method_not_allowed_respond_handler(&allow(allowed_methods))
+                        .handle(request)
+                        .await,
+                ),
+                vh_error_format,
+            );
+        }
+    }
+
+    if let Some(retry_after) = vh
+        .rate_limiter
+        .as_ref()
+        .and_then(|limiter| limiter.check_shared().err())
+    {
+        return (
+            error::built_in(
+                UtilitiesResponses::too_many_requests_respond_handler(retry_after)
+                    .handle(request)
+                    .await,
+            ),
+            vh_error_format,
+        );
+    }
+
+    if let Some(uri) =
+        merged_slashes(request.uri()).filter(|_| vh.merge_slashes != MergeSlashes::Off)
+    {
+        if vh.merge_slashes == MergeSlashes::Redirect {
+            let location = uri.path_and_query().map_or("/", |path| path.as_str());
+            return (
+                RedirectHandler::new(location.to_string(), Some(301))
+                    .handle(request)
+                    .await,
+                vh_error_format,
+            );
+        }
+        debug!("merged slashes of {} to {}", request.uri(), uri);
+        *request.uri_mut() = uri;
+    }
+
+    let mut route = vh.find_route_for(request.uri().path(), request.headers());
+
+    // The route of the rewritten path serves the request, a route still rewriting after
+    // MAX_REWRITES answers 500
+    for _ in 0..MAX_REWRITES {
+        let Some(RoutePlan {
+            handler: HandlerPlan::Rewrite(rewrite),
+            ..
+        }) = route
+        else {
+            break;
+        };
+        let Some(uri) = rewrite.rewrite(request.uri()) else {
+            return (rewrite.handle(request).await, vh_error_format);
+        };
+        debug!("rewrote {} to {}", request.uri(), uri);
+        *request.uri_mut() = uri;
+        route = vh.find_route_for(request.uri().path(), request.headers());
+    }
 
     if route.is_none() {
-        return UtilitiesResponses::not_found_respond_handler()
-            .handle(request)
-            .await;
+        // The 404 page of the virtual host wins over the not_found page of the options
+        let response = if vh.error_pages.serves(StatusCode::NOT_FOUND) {
+            NotFoundHandler::default().handle(request).await
+        } else {
+            plan.not_found.handle(request).await
+        };
+        return (response, vh_error_format);
     }
 
     let route = route.unwrap();
+    let error_format = route.error_format.unwrap_or(vh_error_format);
+
+    if let Some(cors) = &route.cors {
+        if Cors::is_preflight(&request) {
+            return (cors.preflight_response(&request), error_format);
+        }
+    }
+
+    if !route.allows(request.method()) {
+        return (
+            error::built_in(
+                UtilitiesResponses::method_not_allowed_respond_handler(&allow(&route.methods))
+                    .handle(request)
+                    .await,
+            ),
+            error_format,
+        );
+    }
+
+    let rate_limited = route.rate_limiter.as_ref().and_then(|limiter| {
+        // Requests without a known client (no peer address) share one bucket
+        let client = request
+            .extensions()
+            .get::<ClientInfo>()
+            .map_or(IpAddr::from([0, 0, 0, 0]), |client| client.ip);
+        limiter.check(client).err()
+    });
+
+    let challenge = route
+        .auth
+        .as_ref()
+        .filter(|auth| !auth.authorizes(&request))
+        .map(BasicAuth::challenge);
+
+    let accept_encoding = AcceptEncoding::from_request(&request);
+    let compress = |coding| request.method() != Method::HEAD && accept_encoding.accepts(coding);
+    let zstd = route.zstd.as_ref().filter(|_| compress("zstd"));
+    let gzip = route
+        .gzip
+        .as_ref()
+        .filter(|_| zstd.is_none() && compress("gzip"));
+    let transcode = route.transcode && request.method() != Method::HEAD;
+    let minify = route
+        .minify
+        .as_ref()
+        .filter(|_| request.method() != Method::HEAD);
+
+    let mut response = match (rate_limited, challenge) {
+        (Some(retry_after), _) => error::built_in(
+            UtilitiesResponses::too_many_requests_respond_handler(retry_after)
+                .handle(request)
+                .await,
+        ),
+        (None, Some(challenge)) => error::built_in(
+            UtilitiesResponses::unauthorized_respond_handler(challenge)
+                .handle(request)
+                .await,
+        ),
+        (None, None) => serve_route(route, request).await,
+    };
+
+    // Built-in errors are rendered again in the error format, they are left uncompressed
+    if !error::is_built_in(&response) {
+        if let Some(minify) = minify {
+            response = minify.apply(response).await;
+        }
+        if transcode {
+            response = compression::transcode(response, &accept_encoding);
+        }
+        if let Some(zstd) = zstd {
+            response = zstd.compress(response);
+        }
+        if let Some(gzip) = gzip {
+            response = gzip.compress(response);
+        }
+        if route.gzip.is_some() || route.zstd.is_some() || route.transcode {
+            compression::vary_on_encoding(response.headers_mut());
+        }
+    }
+
+    if let Some(cors) = &route.cors {
+        cors.apply(&mut response);
+    }
+
+    if let Some(headers) = &route.headers {
+        headers.apply(&mut response);
+    }
 
-    match route {
-        crate::plan::RoutePlan::File(h) => h.handle(request).await,
-        crate::plan::RoutePlan::Respond(h) => h.handle(request).await,
-        crate::plan::RoutePlan::Redirect(h) => h.handle(request).await,
-        crate::plan::RoutePlan::ReverseProxy(h) => h.handle(request).await,
+    (response, error_format)
+}
+
+/// Serves the request from the cache of the route, or from its handler within the concurrency
+/// limit.
+async fn serve_route<B>(route: &RoutePlan, request: Request<B>) -> Response<BoxBody>
+where
+    B: hyper::body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let cache = route
+        .cache
+        .as_ref()
+        .and_then(|cache| Some((cache, ResponseCache::key(&request)?)));
+    if let Some(response) = cache
+        .as_ref()
+        .and_then(|(cache, key)| cache.get(key, request.headers()))
+    {
+        return response;
+    }
+
+    let permit = match &route.concurrency_limit {
+        Some(limit) => match limit.acquire().await {
+            Ok(permit) => Some(permit),
+            Err(retry_after) => {
+                return error::built_in(
+                    UtilitiesResponses::service_unavailable_respond_handler(retry_after)
+                        .handle(request)
+                        .await,
+                )
+            }
+        },
+        None => None,
+    };
+
+    let mut response = handle_route(&route.handler, request).await;
+    if let Some((cache, key)) = cache {
+        response = cache.store(key, response).await;
+    }
+    match permit {
+        // The slot is released once the response body is sent
+        Some(permit) => concurrency::hold_until_sent(response, permit),
+        None => response,
+    }
+}
+
+async fn handle_route<B>(handler: &HandlerPlan, request: Request<B>) -> Response<BoxBody>
+where
+    B: hyper::body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    match handler {
+        HandlerPlan::File(h) => h.handle(request).await,
+        HandlerPlan::Dir(h) => h.handle(request).await,
+        HandlerPlan::Browse(h) => h.handle(request).await,
+        HandlerPlan::Respond(h) => h.handle(request).await,
+        HandlerPlan::RespondEcho(h) => h.handle(request).await,
+        HandlerPlan::Redirect(h) => h.handle(request).await,
+        HandlerPlan::ReverseProxy(h) => h.handle(request).await,
+        HandlerPlan::Rewrite(h) => h.handle(request).await,
+        HandlerPlan::TryFiles(h) => h.handle(request).await,
     }
 }
 
+/// Value of the `Allow` header listing the given methods.
+fn allow(methods: &[Method]) -> String {
+    methods
+        .iter()
+        .map(Method::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 pub fn full<T: Into<Bytes>>(chunk: T) -> BoxBody {
     use http_body_util::{BodyExt, Full};
     Full::new(chunk.into())
@@ -95,6 +523,9 @@ pub fn full<T: Into<Bytes>>(chunk: T) -> BoxBody {
         .boxed()
 }
 
+/// Methods chico accepts, `TRACE` and `CONNECT` are always rejected.
+pub(crate) const ALLOWED_METHODS: &str = "GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS";
+
 #[allow(dead_code)]
 pub struct UtilitiesResponses;
 
@@ -119,6 +550,57 @@ impl UtilitiesResponses {
         RespondHandler::with_headers(404, Some(body.to_string()), set_headers)
     }
 
+    pub fn method_not_allowed_respond_handler(allow: &str) -> RespondHandler {
+        let mut set_headers = HashMap::new();
+        set_headers.insert(hyper::header::ALLOW.to_string(), allow.to_string());
+        RespondHandler::with_headers(405, Some("405 Method Not Allowed".to_string()), set_headers)
+    }
+
+    /// Asks for credentials with the `WWW-Authenticate` challenge.
+    pub fn unauthorized_respond_handler(challenge: String) -> RespondHandler {
+        let mut set_headers = HashMap::new();
+        set_headers.insert(hyper::header::WWW_AUTHENTICATE.to_string(), challenge);
+        RespondHandler::with_headers(401, Some("401 Unauthorized".to_string()), set_headers)
+    }
+
+    /// Rejects a rate limited request, `Retry-After` is rounded up to whole seconds.
+    pub fn too_many_requests_respond_handler(retry_after: Duration) -> RespondHandler {
+        let retry_after = (retry_after.as_secs_f64().ceil() as u64).max(1);
+        let mut set_headers = HashMap::new();
+        set_headers.insert(
+            hyper::header::RETRY_AFTER.to_string(),
+            retry_after.to_string(),
+        );
+        RespondHandler::with_headers(429, Some("429 Too Many Requests".to_string()), set_headers)
+    }
+
+    /// Rejects a request over the concurrency limit, `Retry-After` is rounded up to whole seconds.
+    pub fn service_unavailable_respond_handler(retry_after: Duration) -> RespondHandler {
+        let retry_after = (retry_after.as_secs_f64().ceil() as u64).max(1);
+        let mut set_headers = HashMap::new();
+        set_headers.insert(
+            hyper::header::RETRY_AFTER.to_string(),
+            retry_after.to_string(),
+        );
+        RespondHandler::with_headers(
+            503,
+            Some("503 Service Unavailable".to_string()),
+            set_headers,
+        )
+    }
+
+    pub fn expectation_failed_respond_handler() -> RespondHandler {
+        RespondHandler::new(417, Some("417 Expectation Failed".to_string()))
+    }
+
+    pub fn misdirected_request_respond_handler() -> RespondHandler {
+        RespondHandler::new(421, Some("421 Misdirected Request".to_string()))
+    }
+
+    pub fn not_implemented_respond_handler() -> RespondHandler {
+        RespondHandler::new(501, Some("501 Not Implemented".to_string()))
+    }
+
     pub fn bad_request_host_header_not_found_respond_handler() -> RespondHandler {
         let body = "Host header is missing in the request.";
         RespondHandler::bad_request_with_body(String::from(body))
@@ -132,50 +614,66 @@ impl UtilitiesResponses {
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::{net::SocketAddr, sync::Arc, time::Duration};
 
-    use chico_file::types::{Config, Handler, Route, VirtualHost};
+    use chico_file::types::{
+        CompressionLevel, ConcurrencyQueue, Config, Handler, HeaderOperator, MergeSlashes,
+        Middleware, RateLimit, Route, VirtualHost,
+    };
     use claims::assert_some;
-    use http::{Request, StatusCode};
+    use http::{Method, Request, Response, StatusCode, Uri, Version};
     use http_body_util::BodyExt;
     use rstest::rstest;
 
-    use crate::{plan::ServerPlan, test_utils::MockBody};
+    use crate::{
+        client::{PeerAddr, ServerName},
+        middlewares::concurrency::ConcurrencyLimit,
+        plan::ServerPlan,
+        testing::{collect_body, request, MockBody},
+    };
 
-    use super::handle_request;
+    use super::{handle_request, merged_slashes, BoxBody};
 
     #[tokio::test]
     async fn test_handle_request_should_return_not_found_when_given_route_not_configured() {
         let config = Config {
             virtual_hosts: vec![VirtualHost {
-                domain: "localhost".to_string(),
+                domain: "localhost".parse().unwrap(),
                 routes: vec![Route {
                     handler: Handler::File("index.html".to_string()),
                     path: "/".to_string(),
                     middlewares: vec![],
+                    error_format: None,
+                    sniff_extensionless: false,
+                    max_ranges: None,
+                    transcode: false,
+                    negotiate_language: false,
+                    methods: vec![],
                 }],
+                error_format: None,
+                trusted_proxies: vec![],
+                forwarded_header: chico_file::types::ForwardedHeader::Ignore,
+                allowed_methods: None,
+                tls: None,
+                matchers: vec![],
+                error_pages: vec![],
+                csp: None,
+                rate_limit: None,
+                merge_slashes: Default::default(),
             }],
+            options: Default::default(),
+            expansions: Default::default(),
         };
 
-        let request = Request::builder()
+        let request = request()
             .uri("http://localhost/blog")
-            .header(http::header::HOST, "localhost")
             .body(MockBody::new(b""))
             .unwrap();
 
         let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
 
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
-        let response_body = String::from_utf8(
-            response
-                .boxed()
-                .collect()
-                .await
-                .unwrap()
-                .to_bytes()
-                .to_vec(),
-        )
-        .unwrap();
+        let response_body = collect_body(response).await;
         let body = r"<!DOCTYPE html>  
 <html>  
 <head>  
@@ -192,13 +690,31 @@ mod tests {
     async fn test_handle_request_should_return_not_found_when_host_not_configured() {
         let config = Config {
             virtual_hosts: vec![VirtualHost {
-                domain: "localhost".to_string(),
+                domain: "localhost".parse().unwrap(),
                 routes: vec![Route {
                     handler: Handler::File("index.html".to_string()),
                     path: "/".to_string(),
                     middlewares: vec![],
+                    error_format: None,
+                    sniff_extensionless: false,
+                    max_ranges: None,
+                    transcode: false,
+                    negotiate_language: false,
+                    methods: vec![],
                 }],
+                error_format: None,
+                trusted_proxies: vec![],
+                forwarded_header: chico_file::types::ForwardedHeader::Ignore,
+                allowed_methods: None,
+                tls: None,
+                matchers: vec![],
+                error_pages: vec![],
+                csp: None,
+                rate_limit: None,
+                merge_slashes: Default::default(),
             }],
+            options: Default::default(),
+            expansions: Default::default(),
         };
 
         let request = Request::builder()
@@ -214,16 +730,7 @@ mod tests {
             response.headers().get(hyper::header::CONTENT_TYPE),
             "text/html; charset=utf-8"
         );
-        let response_body = String::from_utf8(
-            response
-                .boxed()
-                .collect()
-                .await
-                .unwrap()
-                .to_bytes()
-                .to_vec(),
-        )
-        .unwrap();
+        let response_body = collect_body(response).await;
         let body = r"<!DOCTYPE html>  
 <html>  
 <head>  
@@ -241,13 +748,31 @@ mod tests {
     ) {
         let config = Config {
             virtual_hosts: vec![VirtualHost {
-                domain: "localhost".to_string(),
+                domain: "localhost".parse().unwrap(),
                 routes: vec![Route {
                     handler: Handler::File("index.html".to_string()),
                     path: "/".to_string(),
                     middlewares: vec![],
+                    error_format: None,
+                    sniff_extensionless: false,
+                    max_ranges: None,
+                    transcode: false,
+                    negotiate_language: false,
+                    methods: vec![],
                 }],
+                error_format: None,
+                trusted_proxies: vec![],
+                forwarded_header: chico_file::types::ForwardedHeader::Ignore,
+                allowed_methods: None,
+                tls: None,
+                matchers: vec![],
+                error_pages: vec![],
+                csp: None,
+                rate_limit: None,
+                merge_slashes: Default::default(),
             }],
+            options: Default::default(),
+            expansions: Default::default(),
         };
 
         let request = Request::builder()
@@ -258,16 +783,7 @@ mod tests {
         let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
 
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
-        let response_body = String::from_utf8(
-            response
-                .boxed()
-                .collect()
-                .await
-                .unwrap()
-                .to_bytes()
-                .to_vec(),
-        )
-        .unwrap();
+        let response_body = collect_body(response).await;
         let body = r"Host header is missing in the request.";
         assert_eq!(response_body, body);
     }
@@ -282,13 +798,31 @@ mod tests {
     ) {
         let config = Config {
             virtual_hosts: vec![VirtualHost {
-                domain: "localhost".to_string(),
+                domain: "localhost".parse().unwrap(),
                 routes: vec![Route {
                     handler: Handler::File("index.html".to_string()),
                     path: "/".to_string(),
                     middlewares: vec![],
+                    error_format: None,
+                    sniff_extensionless: false,
+                    max_ranges: None,
+                    transcode: false,
+                    negotiate_language: false,
+                    methods: vec![],
                 }],
+                error_format: None,
+                trusted_proxies: vec![],
+                forwarded_header: chico_file::types::ForwardedHeader::Ignore,
+                allowed_methods: None,
+                tls: None,
+                matchers: vec![],
+                error_pages: vec![],
+                csp: None,
+                rate_limit: None,
+                merge_slashes: Default::default(),
             }],
+            options: Default::default(),
+            expansions: Default::default(),
         };
 
         let request = Request::builder()
@@ -300,17 +834,1243 @@ mod tests {
         let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
 
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
-        let response_body = String::from_utf8(
+        let response_body = collect_body(response).await;
+        let body = r"Invalid Host header.";
+        assert_eq!(response_body, body);
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_respond_without_status_and_body_returns_empty_ok() {
+        let config = Config {
+            virtual_hosts: vec![VirtualHost {
+                domain: "localhost".parse().unwrap(),
+                routes: vec![Route {
+                    handler: Handler::Respond {
+                        status: None,
+                        body: None,
+                    },
+                    path: "/".to_string(),
+                    middlewares: vec![],
+                    error_format: None,
+                    sniff_extensionless: false,
+                    max_ranges: None,
+                    transcode: false,
+                    negotiate_language: false,
+                    methods: vec![],
+                }],
+                error_format: None,
+                trusted_proxies: vec![],
+                forwarded_header: chico_file::types::ForwardedHeader::Ignore,
+                allowed_methods: None,
+                tls: None,
+                matchers: vec![],
+                error_pages: vec![],
+                csp: None,
+                rate_limit: None,
+                merge_slashes: Default::default(),
+            }],
+            options: Default::default(),
+            expansions: Default::default(),
+        };
+
+        let request = request().body(MockBody::new(b"")).unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let response_body = response.boxed().collect().await.unwrap().to_bytes();
+        assert!(response_body.is_empty());
+    }
+
+    #[rstest]
+    #[case(http::Method::TRACE, StatusCode::METHOD_NOT_ALLOWED)]
+    #[case(http::Method::CONNECT, StatusCode::NOT_IMPLEMENTED)]
+    #[tokio::test]
+    async fn test_handle_request_rejects_trace_and_connect(
+        #[case] method: http::Method,
+        #[case] expected_status: StatusCode,
+    ) {
+        let config = Config {
+            virtual_hosts: vec![VirtualHost {
+                domain: "localhost".parse().unwrap(),
+                routes: vec![Route {
+                    handler: Handler::Respond {
+                        status: Some(200),
+                        body: Some("matched".to_string()),
+                    },
+                    path: "/".to_string(),
+                    middlewares: vec![],
+                    error_format: None,
+                    sniff_extensionless: false,
+                    max_ranges: None,
+                    transcode: false,
+                    negotiate_language: false,
+                    methods: vec![],
+                }],
+                error_format: None,
+                trusted_proxies: vec![],
+                forwarded_header: chico_file::types::ForwardedHeader::Ignore,
+                allowed_methods: None,
+                tls: None,
+                matchers: vec![],
+                error_pages: vec![],
+                csp: None,
+                rate_limit: None,
+                merge_slashes: Default::default(),
+            }],
+            options: Default::default(),
+            expansions: Default::default(),
+        };
+
+        let request = request()
+            .method(method)
+            .header("x-secret", "reflected-value")
+            .body(MockBody::new(b"reflected-body"))
+            .unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), expected_status);
+        if let Some(allow) = response.headers().get(http::header::ALLOW) {
+            let allow = allow.to_str().unwrap();
+            assert!(!allow.contains("TRACE") && !allow.contains("CONNECT"));
+        }
+        let response_body = collect_body(response).await;
+        assert!(!response_body.contains("reflected"));
+        assert!(!response_body.contains("matched"));
+    }
+
+    #[rstest]
+    #[case(Version::HTTP_11, "100-continue", StatusCode::OK)]
+    #[case(Version::HTTP_11, "100-Continue", StatusCode::OK)]
+    #[case(Version::HTTP_11, "fast-lane", StatusCode::EXPECTATION_FAILED)]
+    #[case(Version::HTTP_10, "fast-lane", StatusCode::OK)]
+    #[tokio::test]
+    async fn test_handle_request_checks_expect_header(
+        #[case] version: Version,
+        #[case] expect: &str,
+        #[case] expected_status: StatusCode,
+    ) {
+        let plan = Arc::new(ServerPlan::from_config(&api_route_config(vec![])));
+        let request = request()
+            .version(version)
+            .uri("http://localhost/api")
+            .header(http::header::HOST, "localhost")
+            .header(http::header::EXPECT, expect)
+            .body(MockBody::new(b"unread"))
+            .unwrap();
+
+        let response = handle_request(request, plan).await;
+
+        assert_eq!(response.status(), expected_status);
+    }
+
+    #[rstest]
+    #[case(MergeSlashes::Off, StatusCode::NOT_FOUND, None)]
+    #[case(MergeSlashes::On, StatusCode::OK, None)]
+    #[case(
+        MergeSlashes::Redirect,
+        StatusCode::MOVED_PERMANENTLY,
+        Some("/api?page=2")
+    )]
+    #[tokio::test]
+    async fn test_handle_request_merges_slashes(
+        #[case] merge_slashes: MergeSlashes,
+        #[case] expected_status: StatusCode,
+        #[case] location: Option<&str>,
+    ) {
+        let mut config = api_route_config(vec![]);
+        config.virtual_hosts[0].merge_slashes = merge_slashes;
+        let request = request()
+            .uri("http://localhost//api?page=2")
+            .header(http::header::HOST, "localhost")
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), expected_status);
+        assert_eq!(
             response
-                .boxed()
-                .collect()
-                .await
-                .unwrap()
-                .to_bytes()
-                .to_vec(),
+                .headers()
+                .get(http::header::LOCATION)
+                .map(|location| location.to_str().unwrap()),
+            location
+        );
+    }
+
+    #[rstest]
+    #[case("/a//b///c", Some("/a/b/c"))]
+    #[case("//a/?q=x//y", Some("/a/?q=x//y"))]
+    #[case("http://localhost/a//b/", Some("http://localhost/a/b/"))]
+    #[case("/a/b/c", None)]
+    fn test_merged_slashes(#[case] uri: &str, #[case] expected: Option<&str>) {
+        let uri: Uri = uri.parse().unwrap();
+        assert_eq!(
+            merged_slashes(&uri).map(|uri| uri.to_string()),
+            expected.map(str::to_string)
+        );
+    }
+
+    fn api_route_config(middlewares: Vec<Middleware>) -> Config {
+        Config {
+            virtual_hosts: vec![VirtualHost {
+                domain: "localhost".parse().unwrap(),
+                routes: vec![Route {
+                    handler: Handler::Respond {
+                        status: Some(200),
+                        body: Some("matched".to_string()),
+                    },
+                    path: "/api".to_string(),
+                    middlewares,
+                    error_format: None,
+                    sniff_extensionless: false,
+                    max_ranges: None,
+                    transcode: false,
+                    negotiate_language: false,
+                    methods: vec![],
+                }],
+                error_format: None,
+                trusted_proxies: vec![],
+                forwarded_header: chico_file::types::ForwardedHeader::Ignore,
+                allowed_methods: None,
+                tls: None,
+                matchers: vec![],
+                error_pages: vec![],
+                csp: None,
+                rate_limit: None,
+                merge_slashes: Default::default(),
+            }],
+            options: Default::default(),
+            expansions: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_add_cors_headers_to_route_response() {
+        let config = api_route_config(vec![Middleware::Cors(
+            "https://app.example.com".to_string(),
+        )]);
+
+        let request = request()
+            .uri("http://localhost/api")
+            .header(http::header::ORIGIN, "https://app.example.com")
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_some!(
+            response
+                .headers()
+                .get(http::header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            "https://app.example.com"
+        );
+        assert_some!(response
+            .headers()
+            .get(http::header::ACCESS_CONTROL_ALLOW_METHODS));
+        assert_some!(response
+            .headers()
+            .get(http::header::ACCESS_CONTROL_ALLOW_HEADERS));
+        assert_eq!(collect_body(response).await, "matched");
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_answer_cors_preflight_without_handler() {
+        let config = api_route_config(vec![Middleware::Cors("*".to_string())]);
+
+        let request = request()
+            .method(Method::OPTIONS)
+            .uri("http://localhost/api")
+            .header(http::header::ORIGIN, "https://app.example.com")
+            .header(http::header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_some!(
+            response
+                .headers()
+                .get(http::header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            "*"
+        );
+        assert_eq!(collect_body(response).await, "");
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_not_add_cors_headers_without_cors_middleware() {
+        let config = api_route_config(vec![]);
+
+        let request = request()
+            .method(Method::OPTIONS)
+            .uri("http://localhost/api")
+            .header(http::header::ORIGIN, "https://app.example.com")
+            .header(http::header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response
+            .headers()
+            .contains_key(http::header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert_eq!(collect_body(response).await, "matched");
+    }
+
+    #[rstest]
+    #[case(Method::GET, StatusCode::OK)]
+    #[case(Method::HEAD, StatusCode::OK)]
+    #[case(Method::TRACE, StatusCode::METHOD_NOT_ALLOWED)]
+    #[case(Method::DELETE, StatusCode::METHOD_NOT_ALLOWED)]
+    #[case(Method::OPTIONS, StatusCode::METHOD_NOT_ALLOWED)]
+    #[tokio::test]
+    async fn test_handle_request_should_reject_methods_not_in_allowed_methods(
+        #[case] method: Method,
+        #[case] expected_status: StatusCode,
+    ) {
+        let mut config = api_route_config(vec![]);
+        config.virtual_hosts[0].allowed_methods =
+            Some(vec![Method::GET, Method::POST, Method::HEAD]);
+
+        let request = request()
+            .method(method)
+            .uri("http://localhost/api")
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), expected_status);
+        if expected_status == StatusCode::METHOD_NOT_ALLOWED {
+            assert!(!collect_body(response).await.contains("matched"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_list_allowed_methods_in_allow_header() {
+        let mut config = api_route_config(vec![]);
+        config.virtual_hosts[0].allowed_methods = Some(vec![Method::GET, Method::POST]);
+
+        let request = request()
+            .method(Method::PUT)
+            .uri("http://localhost/api")
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_some!(response.headers().get(http::header::ALLOW), "GET, POST");
+    }
+
+    #[rstest]
+    #[case(Method::GET, StatusCode::OK)]
+    #[case(Method::HEAD, StatusCode::OK)]
+    #[case(Method::POST, StatusCode::METHOD_NOT_ALLOWED)]
+    #[case(Method::DELETE, StatusCode::METHOD_NOT_ALLOWED)]
+    #[tokio::test]
+    async fn test_handle_request_should_fall_back_to_allowed_methods_of_options(
+        #[case] method: Method,
+        #[case] expected_status: StatusCode,
+    ) {
+        let mut config = api_route_config(vec![]);
+        config.options.allowed_methods = Some(vec![Method::GET, Method::HEAD]);
+
+        let request = request()
+            .method(method)
+            .uri("http://localhost/api")
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), expected_status);
+        if expected_status == StatusCode::METHOD_NOT_ALLOWED {
+            assert_some!(response.headers().get(http::header::ALLOW), "GET, HEAD");
+        }
+    }
+
+    #[rstest]
+    #[case(Method::GET, StatusCode::METHOD_NOT_ALLOWED)]
+    #[case(Method::POST, StatusCode::OK)]
+    #[case(Method::PUT, StatusCode::OK)]
+    #[tokio::test]
+    async fn test_handle_request_should_prefer_allowed_methods_of_virtual_host(
+        #[case] method: Method,
+        #[case] expected_status: StatusCode,
+    ) {
+        let mut config = api_route_config(vec![]);
+        config.options.allowed_methods = Some(vec![Method::GET, Method::HEAD]);
+        config.virtual_hosts[0].allowed_methods = Some(vec![Method::POST, Method::PUT]);
+
+        let request = request()
+            .method(method)
+            .uri("http://localhost/api")
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), expected_status);
+        if expected_status == StatusCode::METHOD_NOT_ALLOWED {
+            assert_some!(response.headers().get(http::header::ALLOW), "POST, PUT");
+        }
+    }
+
+    #[rstest]
+    #[case(Method::GET, StatusCode::OK)]
+    #[case(Method::POST, StatusCode::OK)]
+    #[case(Method::PUT, StatusCode::METHOD_NOT_ALLOWED)]
+    #[case(Method::DELETE, StatusCode::METHOD_NOT_ALLOWED)]
+    #[tokio::test]
+    async fn test_handle_request_should_reject_methods_the_route_does_not_serve(
+        #[case] method: Method,
+        #[case] expected_status: StatusCode,
+    ) {
+        let mut config = api_route_config(vec![]);
+        config.virtual_hosts[0].routes[0].methods = vec![Method::GET, Method::POST];
+
+        let request = request()
+            .method(method)
+            .uri("http://localhost/api")
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), expected_status);
+        if expected_status == StatusCode::METHOD_NOT_ALLOWED {
+            assert_some!(response.headers().get(http::header::ALLOW), "GET, POST");
+            assert!(!collect_body(response).await.contains("matched"));
+        } else {
+            assert_eq!(collect_body(response).await, "matched");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_answer_cors_preflight_of_route_with_methods() {
+        let mut config = api_route_config(vec![Middleware::Cors("*".to_string())]);
+        config.virtual_hosts[0].routes[0].methods = vec![Method::POST];
+
+        let request = request()
+            .method(Method::OPTIONS)
+            .uri("http://localhost/api")
+            .header(http::header::ORIGIN, "https://app.example.com")
+            .header(http::header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert!(response
+            .headers()
+            .contains_key(http::header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    async fn send_from(plan: &Arc<ServerPlan>, client: [u8; 4]) -> Response<BoxBody> {
+        let mut request = request()
+            .uri("http://localhost/api")
+            .body(MockBody::new(b""))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(PeerAddr(SocketAddr::from((client, 50000))));
+        handle_request(request, plan.clone()).await
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_reject_burst_above_rate_limit() {
+        let config = api_route_config(vec![Middleware::RateLimit {
+            requests: 3,
+            window: Duration::from_secs(60),
+        }]);
+        let plan = Arc::new(ServerPlan::from_config(&config));
+
+        for _ in 0..3 {
+            let response = send_from(&plan, [10, 0, 0, 1]).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = send_from(&plan, [10, 0, 0, 1]).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        // one token is refilled every 20 seconds
+        assert_some!(response.headers().get(http::header::RETRY_AFTER), "20");
+        assert!(!collect_body(response).await.contains("matched"));
+
+        // other clients have their own bucket
+        let response = send_from(&plan, [10, 0, 0, 2]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_reject_above_virtual_host_rate_limit() {
+        let mut config = api_route_config(vec![Middleware::RateLimit {
+            requests: 100,
+            window: Duration::from_secs(1),
+        }]);
+        config.virtual_hosts[0].rate_limit = Some(RateLimit {
+            requests: 2,
+            window: Duration::from_secs(60),
+        });
+        let plan = Arc::new(ServerPlan::from_config(&config));
+
+        assert_eq!(
+            send_from(&plan, [10, 0, 0, 1]).await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            send_from(&plan, [10, 0, 0, 2]).await.status(),
+            StatusCode::OK
+        );
+
+        // the clients share the bucket of the virtual host, their route buckets are far from empty
+        let response = send_from(&plan, [10, 0, 0, 3]).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_some!(response.headers().get(http::header::RETRY_AFTER), "30");
+        assert!(!collect_body(response).await.contains("matched"));
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_apply_route_rate_limit_within_virtual_host_rate_limit() {
+        let mut config = api_route_config(vec![Middleware::RateLimit {
+            requests: 1,
+            window: Duration::from_secs(60),
+        }]);
+        config.virtual_hosts[0].rate_limit = Some(RateLimit {
+            requests: 100,
+            window: Duration::from_secs(1),
+        });
+        let plan = Arc::new(ServerPlan::from_config(&config));
+
+        assert_eq!(
+            send_from(&plan, [10, 0, 0, 1]).await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            send_from(&plan, [10, 0, 0, 1]).await.status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_not_limit_routes_without_rate_limit() {
+        let plan = Arc::new(ServerPlan::from_config(&api_route_config(vec![])));
+
+        for _ in 0..100 {
+            let response = send_from(&plan, [10, 0, 0, 1]).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+
+    fn route_concurrency_limit(plan: &ServerPlan) -> &ConcurrencyLimit {
+        plan.find_virtual_host("localhost", 80)
+            .unwrap()
+            .find_route("/api")
+            .unwrap()
+            .concurrency_limit
+            .as_ref()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_reject_requests_above_max_concurrent() {
+        let config = api_route_config(vec![Middleware::MaxConcurrent {
+            limit: 2,
+            queue: None,
+        }]);
+        let plan = Arc::new(ServerPlan::from_config(&config));
+
+        // responses whose body is not sent yet keep their slot
+        let first = send_from(&plan, [10, 0, 0, 1]).await;
+        let second = send_from(&plan, [10, 0, 0, 2]).await;
+        assert_eq!(route_concurrency_limit(&plan).in_flight(), 2);
+
+        let response = send_from(&plan, [10, 0, 0, 3]).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_some!(response.headers().get(http::header::RETRY_AFTER), "1");
+
+        assert_eq!(collect_body(first).await, "matched");
+        drop(second);
+        assert_eq!(route_concurrency_limit(&plan).in_flight(), 0);
+
+        let response = send_from(&plan, [10, 0, 0, 3]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_handle_request_should_queue_requests_above_max_concurrent() {
+        let config = api_route_config(vec![Middleware::MaxConcurrent {
+            limit: 1,
+            queue: Some(ConcurrencyQueue {
+                size: 1,
+                timeout: Duration::from_secs(10),
+            }),
+        }]);
+        let plan = Arc::new(ServerPlan::from_config(&config));
+
+        let parked = send_from(&plan, [10, 0, 0, 1]).await;
+        let queued = tokio::spawn({
+            let plan = plan.clone();
+            async move { send_from(&plan, [10, 0, 0, 2]).await }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(route_concurrency_limit(&plan).queue_depth(), 1);
+
+        // the queue is full
+        let response = send_from(&plan, [10, 0, 0, 3]).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_some!(response.headers().get(http::header::RETRY_AFTER), "10");
+
+        drop(parked);
+        let response = queued.await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the slot is held by the unsent response, a queued request waits no longer than the timeout
+        let queued = tokio::spawn({
+            let plan = plan.clone();
+            async move { send_from(&plan, [10, 0, 0, 2]).await }
+        });
+        let response = queued.await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(route_concurrency_limit(&plan).queue_depth(), 0);
+    }
+
+    #[rstest]
+    #[case(Method::GET, Some("gzip"), true)]
+    #[case(Method::GET, None, false)]
+    #[case(Method::HEAD, Some("gzip"), false)]
+    #[tokio::test]
+    async fn test_handle_request_should_compress_when_client_accepts_gzip(
+        #[case] method: Method,
+        #[case] accept_encoding: Option<&str>,
+        #[case] compressed: bool,
+    ) {
+        let config = api_route_config(vec![Middleware::Gzip(CompressionLevel::BEST)]);
+        let plan = Arc::new(ServerPlan::from_config(&config));
+        let mut request = request().method(method).uri("http://localhost/api");
+        if let Some(accept_encoding) = accept_encoding {
+            request = request.header(http::header::ACCEPT_ENCODING, accept_encoding);
+        }
+
+        let response = handle_request(request.body(MockBody::new(b"")).unwrap(), plan).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response
+                .headers()
+                .get(http::header::CONTENT_ENCODING)
+                .is_some(),
+            compressed
+        );
+    }
+
+    #[rstest]
+    #[case(Some("gzip, zstd"), Some("zstd"))]
+    #[case(Some("gzip"), Some("gzip"))]
+    #[case(Some("br"), None)]
+    #[tokio::test]
+    async fn test_handle_request_should_prefer_zstd_over_gzip(
+        #[case] accept_encoding: Option<&str>,
+        #[case] content_encoding: Option<&str>,
+    ) {
+        let config = api_route_config(vec![
+            Middleware::Gzip(CompressionLevel::DEFAULT),
+            Middleware::Zstd { dictionary: None },
+        ]);
+        let plan = Arc::new(ServerPlan::from_config(&config));
+        let mut request = request().uri("http://localhost/api");
+        if let Some(accept_encoding) = accept_encoding {
+            request = request.header(http::header::ACCEPT_ENCODING, accept_encoding);
+        }
+
+        let response = handle_request(request.body(MockBody::new(b"")).unwrap(), plan).await;
+
+        assert_eq!(
+            response
+                .headers()
+                .get(http::header::CONTENT_ENCODING)
+                .map(|value| value.to_str().unwrap()),
+            content_encoding
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_not_compress_built_in_errors() {
+        let config = api_route_config(vec![
+            Middleware::Gzip(CompressionLevel::DEFAULT),
+            Middleware::RateLimit {
+                requests: 1,
+                window: Duration::from_secs(60),
+            },
+        ]);
+        let plan = Arc::new(ServerPlan::from_config(&config));
+        let send = || {
+            let request = request()
+                .uri("http://localhost/api")
+                .header(http::header::ACCEPT_ENCODING, "gzip")
+                .body(MockBody::new(b""))
+                .unwrap();
+            handle_request(request, plan.clone())
+        };
+
+        let response = send().await;
+        assert_some!(response.headers().get(http::header::CONTENT_ENCODING));
+
+        let response = send().await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(!response
+            .headers()
+            .contains_key(http::header::CONTENT_ENCODING));
+        assert_eq!(collect_body(response).await, "429 Too Many Requests");
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_apply_header_rules_to_every_response() {
+        let header = |operator, name: &str, value: Option<&str>| Middleware::Header {
+            operator,
+            name: name.to_string(),
+            value: value.map(str::to_string),
+            replace_with: None,
+        };
+        let config = api_route_config(vec![
+            header(HeaderOperator::DeferSet, "X-Served-By", Some("chico")),
+            header(HeaderOperator::Delete, "Content-Type", None),
+            Middleware::RateLimit {
+                requests: 1,
+                window: Duration::from_secs(60),
+            },
+        ]);
+        let plan = Arc::new(ServerPlan::from_config(&config));
+        let send = || {
+            let request = request()
+                .uri("http://localhost/api")
+                .body(MockBody::new(b""))
+                .unwrap();
+            handle_request(request, plan.clone())
+        };
+
+        for status in [StatusCode::OK, StatusCode::TOO_MANY_REQUESTS] {
+            let response = send().await;
+            assert_eq!(response.status(), status);
+            assert_eq!(response.headers()["x-served-by"], "chico");
+            assert!(!response.headers().contains_key(http::header::CONTENT_TYPE));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_ask_for_credentials_outside_exempt_paths() {
+        let (_, config) = chico_file::parse_config(
+            r#"localhost {
+                route /* {
+                    respond 200
+                    auth {
+                        users admin s3cr3t
+                        realm "Staging"
+                        except /public/* /.well-known/acme-challenge/*
+                    }
+                }
+            }"#,
         )
         .unwrap();
-        let body = r"Invalid Host header.";
-        assert_eq!(response_body, body);
+        let plan = Arc::new(ServerPlan::from_config(&config));
+        let send = |path: &str, authorization: Option<&str>| {
+            let mut builder = request().uri(format!("http://localhost{path}"));
+            if let Some(authorization) = authorization {
+                builder = builder.header(http::header::AUTHORIZATION, authorization);
+            }
+            handle_request(builder.body(MockBody::new(b"")).unwrap(), plan.clone())
+        };
+
+        for path in ["/public/logo.png", "/.well-known/acme-challenge/token"] {
+            assert_eq!(send(path, None).await.status(), StatusCode::OK);
+        }
+
+        let response = send("/admin", None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers()[http::header::WWW_AUTHENTICATE],
+            "Basic realm=\"Staging\", charset=\"UTF-8\""
+        );
+
+        // "admin:s3cr3t" in base64
+        let response = send("/admin", Some("Basic YWRtaW46czNjcjN0")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_route_by_named_matcher() {
+        let (_, config) = chico_file::parse_config(
+            r#"localhost {
+                @internal {
+                    path /api/* /admin
+                    header X-Internal true
+                }
+                route @internal {
+                    respond "internal" 200
+                }
+                route /api/* {
+                    respond "public" 200
+                }
+            }"#,
+        )
+        .unwrap();
+        let plan = Arc::new(ServerPlan::from_config(&config));
+        let send = |path: &str, internal: Option<&str>| {
+            let mut builder = request().uri(format!("http://localhost{path}"));
+            if let Some(internal) = internal {
+                builder = builder.header("X-Internal", internal);
+            }
+            handle_request(builder.body(MockBody::new(b"")).unwrap(), plan.clone())
+        };
+
+        let response = send("/api/users", Some("true")).await;
+        assert_eq!(collect_body(response).await, "internal");
+        let response = send("/admin", Some("true")).await;
+        assert_eq!(collect_body(response).await, "internal");
+
+        // requests the matcher does not match fall through to the path routes
+        let response = send("/api/users", None).await;
+        assert_eq!(collect_body(response).await, "public");
+        let response = send("/api/users", Some("false")).await;
+        assert_eq!(collect_body(response).await, "public");
+        let response = send("/admin", None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_use_authority_of_http2_request() {
+        let (_, config) =
+            chico_file::parse_config(r#"localhost:8080 { route / { respond "h2" 200 } }"#).unwrap();
+        let request = Request::builder()
+            .version(Version::HTTP_2)
+            .uri("http://localhost:8080/")
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(collect_body(response).await, "h2");
+    }
+
+    #[rstest]
+    #[case("off", "localhost:3443", StatusCode::OK, "localhost")]
+    #[case("off", "example.com:3443", StatusCode::OK, "example")]
+    #[case("on", "localhost:3443", StatusCode::OK, "localhost")]
+    #[case("on", "LOCALHOST:3443", StatusCode::OK, "localhost")]
+    #[case(
+        "on",
+        "example.com:3443",
+        StatusCode::MISDIRECTED_REQUEST,
+        "421 Misdirected Request"
+    )]
+    #[tokio::test]
+    async fn test_handle_request_should_check_host_against_server_name(
+        #[case] strict_sni_host: &str,
+        #[case] host: &str,
+        #[case] status: StatusCode,
+        #[case] body: &str,
+    ) {
+        let (_, config) = chico_file::parse_config(&format!(
+            r#"options {{ strict_sni_host {strict_sni_host} }}
+            localhost:3443 {{ route / {{ respond "localhost" 200 }} }}
+            example.com:3443 {{ route / {{ respond "example" 200 }} }}"#
+        ))
+        .unwrap();
+        let mut request = Request::builder()
+            .uri("/")
+            .header(http::header::HOST, host)
+            .body(MockBody::new(b""))
+            .unwrap();
+        request.extensions_mut().insert(ServerName {
+            host: "localhost".to_string(),
+            port: 3443,
+        });
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), status);
+        assert_eq!(collect_body(response).await, body);
+    }
+
+    #[rstest]
+    #[case(Version::HTTP_2, StatusCode::OK)]
+    #[case(Version::HTTP_10, StatusCode::OK)]
+    // HTTP/1.1 requests must have a Host header
+    #[case(Version::HTTP_11, StatusCode::BAD_REQUEST)]
+    #[tokio::test]
+    async fn test_handle_request_should_serve_hostless_request_from_server_name(
+        #[case] version: Version,
+        #[case] status: StatusCode,
+    ) {
+        let (_, config) = chico_file::parse_config(
+            r#"options { default_host localhost:3443 }
+            localhost:3443 { route / { respond "localhost" 200 } }
+            example.com:3443 { route / { respond "example" 200 } }"#,
+        )
+        .unwrap();
+        let mut request = Request::builder()
+            .version(version)
+            .uri("/")
+            .body(MockBody::new(b""))
+            .unwrap();
+        request.extensions_mut().insert(ServerName {
+            host: "example.com".to_string(),
+            port: 3443,
+        });
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), status);
+        if status == StatusCode::OK {
+            assert_eq!(collect_body(response).await, "example");
+        }
+    }
+
+    #[rstest]
+    #[case(Version::HTTP_10, Some("localhost"), StatusCode::OK)]
+    #[case(Version::HTTP_10, None, StatusCode::BAD_REQUEST)]
+    // HTTP/1.1 requests must have a Host header
+    #[case(Version::HTTP_11, Some("localhost"), StatusCode::BAD_REQUEST)]
+    #[tokio::test]
+    async fn test_handle_request_should_serve_hostless_request_from_default_host(
+        #[case] version: Version,
+        #[case] default_host: Option<&str>,
+        #[case] status: StatusCode,
+    ) {
+        let options = default_host
+            .map(|host| format!("options {{ default_host {host} }}"))
+            .unwrap_or_default();
+        let (_, config) = chico_file::parse_config(&format!(
+            r#"{options}
+            localhost {{ route / {{ respond "default" 200 }} }}
+            example.com {{ route / {{ respond "other" 200 }} }}"#
+        ))
+        .unwrap();
+        let request = Request::builder()
+            .version(version)
+            .uri("/")
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), status);
+        if status == StatusCode::OK {
+            assert_eq!(collect_body(response).await, "default");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_serve_rewritten_path_from_file_route() {
+        let mut page = tempfile::NamedTempFile::with_suffix(".html").unwrap();
+        std::io::Write::write_all(&mut page, b"<h1>New</h1>").unwrap();
+        let (_, config) = chico_file::parse_config(&format!(
+            "localhost {{
+                route /old/* {{ rewrite /new/{{path}} }}
+                route /new/page {{ file {} }}
+            }}",
+            page.path().display()
+        ))
+        .unwrap();
+        let request = request()
+            .uri("http://localhost/old/page")
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(http::header::LOCATION));
+        assert_eq!(collect_body(response).await, "<h1>New</h1>");
+    }
+
+    #[rstest]
+    #[case("route /a { rewrite /b }\n route /b { rewrite /a }", "/a")]
+    #[case("route /a/* { rewrite /a/x/{path} }", "/a/start")]
+    #[tokio::test]
+    async fn test_handle_request_should_answer_500_on_rewrite_loop(
+        #[case] routes: &str,
+        #[case] path: &str,
+    ) {
+        let (_, config) = chico_file::parse_config(&format!("localhost {{ {routes} }}")).unwrap();
+        let request = request()
+            .uri(format!("http://localhost{path}"))
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_serve_not_found_page_for_unknown_host_and_route() {
+        let mut page = tempfile::NamedTempFile::with_suffix(".html").unwrap();
+        std::io::Write::write_all(&mut page, b"<h1>Nothing here</h1>").unwrap();
+        let (_, config) = chico_file::parse_config(&format!(
+            "options {{ not_found {{ respond_file {} }} }}
+            localhost {{ route / {{ respond 200 }} }}",
+            page.path().display()
+        ))
+        .unwrap();
+        let plan = Arc::new(ServerPlan::from_config(&config));
+
+        for (host, path) in [("example.com", "/"), ("localhost", "/missing")] {
+            let request = Request::builder()
+                .uri(path)
+                .header(http::header::HOST, host)
+                .header(http::header::ACCEPT, "application/json")
+                .body(MockBody::new(b""))
+                .unwrap();
+
+            let response = handle_request(request, plan.clone()).await;
+
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{host}{path}");
+            assert_eq!(response.headers()[http::header::CONTENT_TYPE], "text/html");
+            assert_eq!(collect_body(response).await, "<h1>Nothing here</h1>");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_serve_stale_response_when_upstream_fails() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // The upstream answers a single request, the next ones are refused
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nfresh")
+                .await
+                .unwrap();
+            stream.shutdown().await.unwrap();
+        });
+        let (_, config) = chico_file::parse_config(&format!(
+            "localhost {{ route /api {{ proxy http://127.0.0.1:{port} cache 100ms {{ serve_stale_on_error 1h }} }} }}"
+        ))
+        .unwrap();
+        let plan = Arc::new(ServerPlan::from_config(&config));
+        let send = || {
+            let request = request()
+                .uri("http://localhost/api")
+                .body(MockBody::new(b""))
+                .unwrap();
+            handle_request(request, plan.clone())
+        };
+
+        let response = send().await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(collect_body(response).await, "fresh");
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let response = send().await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[http::header::WARNING],
+            "110 - \"Response is Stale\""
+        );
+        assert_eq!(collect_body(response).await, "fresh");
+    }
+
+    #[rstest]
+    #[case("transcode on", Some("gzip"), true)]
+    #[case("transcode on", None, false)]
+    #[case("", None, true)]
+    #[tokio::test]
+    async fn test_handle_request_should_reconcile_upstream_encoding(
+        #[case] options: &str,
+        #[case] accept_encoding: Option<&str>,
+        #[case] encoded: bool,
+    ) {
+        use std::io::Write;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(b"pre-compressed").unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf).await.unwrap();
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+                gzipped.len()
+            );
+            stream.write_all(head.as_bytes()).await.unwrap();
+            stream.write_all(&gzipped).await.unwrap();
+            stream.shutdown().await.unwrap();
+        });
+        let (_, config) = chico_file::parse_config(&format!(
+            "localhost {{ route /api {{ proxy http://127.0.0.1:{port} {options} }} }}"
+        ))
+        .unwrap();
+        let plan = Arc::new(ServerPlan::from_config(&config));
+        let mut request = request().uri("http://localhost/api");
+        if let Some(accept_encoding) = accept_encoding {
+            request = request.header(http::header::ACCEPT_ENCODING, accept_encoding);
+        }
+
+        let response = handle_request(request.body(MockBody::new(b"")).unwrap(), plan).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response
+                .headers()
+                .contains_key(http::header::CONTENT_ENCODING),
+            encoded
+        );
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        if encoded {
+            let mut decoded = String::new();
+            std::io::Read::read_to_string(
+                &mut flate2::read::GzDecoder::new(&body[..]),
+                &mut decoded,
+            )
+            .unwrap();
+            assert_eq!(decoded, "pre-compressed");
+        } else {
+            assert_eq!(body, "pre-compressed");
+        }
+    }
+
+    #[rstest]
+    // the page replaces the built-in 404, even over the not_found page of the options
+    #[case(
+        "/missing",
+        "error_page 404 {page}",
+        StatusCode::NOT_FOUND,
+        "<h1>Lost?</h1>"
+    )]
+    #[case(
+        "/",
+        "error_page 405 {page}\n allowed_methods GET",
+        StatusCode::METHOD_NOT_ALLOWED,
+        "<h1>Lost?</h1>"
+    )]
+    // responses of respond routes are kept
+    #[case("/gone", "error_page 404 {page}", StatusCode::NOT_FOUND, "gone")]
+    // the built-in page when the file is missing
+    #[case(
+        "/missing",
+        "error_page 404 /path/to/missing/404.html",
+        StatusCode::NOT_FOUND,
+        "{\"error\":\"Not Found\""
+    )]
+    #[tokio::test]
+    async fn test_handle_request_should_serve_error_page_of_virtual_host(
+        #[case] path: &str,
+        #[case] directive: &str,
+        #[case] status: StatusCode,
+        #[case] body_start: &str,
+    ) {
+        let mut page = tempfile::NamedTempFile::with_suffix(".html").unwrap();
+        std::io::Write::write_all(&mut page, b"<h1>Lost?</h1>").unwrap();
+        let directive = directive.replace("{page}", &page.path().display().to_string());
+        let (_, config) = chico_file::parse_config(&format!(
+            r#"options {{ not_found {{ respond_file /path/to/missing/options.html }} }}
+            localhost {{
+                error_format json
+                {directive}
+                route / {{ respond "index" 200 }}
+                route /gone {{ respond "gone" 404 }}
+            }}"#
+        ))
+        .unwrap();
+        let request = request()
+            .method(Method::POST)
+            .uri(format!("http://localhost{path}"))
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), status);
+        assert!(collect_body(response).await.starts_with(body_start));
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_minify_file_before_compressing() {
+        let mut page = tempfile::NamedTempFile::with_suffix(".html").unwrap();
+        std::io::Write::write_all(&mut page, b"<ul>\n    <li>a</li>\n</ul>\n").unwrap();
+        let (_, config) = chico_file::parse_config(&format!(
+            r#"localhost {{
+                route /index.html {{
+                    file {}
+                    minify html
+                    gzip
+                }}
+            }}"#,
+            page.path().display()
+        ))
+        .unwrap();
+        let request = request()
+            .uri("http://localhost/index.html")
+            .header(http::header::ACCEPT_ENCODING, "gzip")
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handle_request(request, Arc::new(ServerPlan::from_config(&config))).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[http::header::CONTENT_ENCODING], "gzip");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let mut decoded = String::new();
+        std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(&body[..]), &mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "<ul>\n<li>a</li>\n</ul>");
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_should_add_csp_of_virtual_host_or_options() {
+        let page = tempfile::NamedTempFile::with_suffix(".html").unwrap();
+        let (_, config) = chico_file::parse_config(&format!(
+            r#"options {{ csp_report_only "default-src *" }}
+            localhost {{
+                csp "default-src 'self'"
+                route /index.html {{ file {0} }}
+            }}
+            example.com {{
+                route /index.html {{ file {0} }}
+            }}"#,
+            page.path().display()
+        ))
+        .unwrap();
+        let plan = Arc::new(ServerPlan::from_config(&config));
+        let get = |host: &str| {
+            http::Request::builder()
+                .uri(format!("http://{host}/index.html"))
+                .header(http::header::HOST, host)
+                .body(MockBody::new(b""))
+                .unwrap()
+        };
+
+        let response = handle_request(get("localhost"), plan.clone()).await;
+        assert_eq!(
+            response.headers()[http::header::CONTENT_SECURITY_POLICY],
+            "default-src 'self'"
+        );
+        assert!(!response
+            .headers()
+            .contains_key(http::header::CONTENT_SECURITY_POLICY_REPORT_ONLY));
+
+        let response = handle_request(get("example.com"), plan).await;
+        assert_eq!(
+            response.headers()[http::header::CONTENT_SECURITY_POLICY_REPORT_ONLY],
+            "default-src *"
+        );
+        assert!(!response
+            .headers()
+            .contains_key(http::header::CONTENT_SECURITY_POLICY));
     }
 }
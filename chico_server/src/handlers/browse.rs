@@ -0,0 +1,412 @@
+use std::{io::ErrorKind, path::Path, time::SystemTime};
+
+use http::{header, Response, StatusCode};
+use tokio::fs::File;
+
+use super::{
+    dir::{absolute_root, canonicalize_in_root, path_below_route, resolve_path, INDEX_FILE},
+    error,
+    file::{handle_file_error, process_file},
+    full, BoxBody, RequestHandler,
+};
+
+/// Serves the files under a directory root like [`super::dir::DirHandler`], a directory without
+/// index file is answered with an HTML listing of its content.
+#[derive(PartialEq, Debug)]
+pub struct BrowseHandler {
+    pub root: String,
+    pub route: String,
+    pub sniff_extensionless: bool,
+    pub max_ranges: Option<usize>,
+}
+
+impl BrowseHandler {
+    pub fn new(root: String, route: String) -> BrowseHandler {
+        BrowseHandler {
+            root,
+            route,
+            sniff_extensionless: false,
+            max_ranges: None,
+        }
+    }
+
+    /// Detect the content type of files without extension from their first bytes.
+    pub fn with_sniff_extensionless(mut self, sniff_extensionless: bool) -> Self {
+        self.sniff_extensionless = sniff_extensionless;
+        self
+    }
+
+    /// Reject range requests asking for more than `max_ranges` ranges.
+    pub fn with_max_ranges(mut self, max_ranges: Option<usize>) -> Self {
+        self.max_ranges = max_ranges;
+        self
+    }
+}
+
+impl RequestHandler for BrowseHandler {
+    async fn handle<B>(&self, request: hyper::Request<B>) -> Response<BoxBody>
+    where
+        B: hyper::body::Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let req_method = request.method();
+        if req_method != http::Method::GET && req_method != http::Method::HEAD {
+            return error::built_in(
+                http::response::Builder::new()
+                    .status(StatusCode::METHOD_NOT_ALLOWED)
+                    .header(http::header::ALLOW, "GET, HEAD")
+                    .body(full(""))
+                    .unwrap(),
+            );
+        }
+
+        // Links of the listing are percent-encoded
+        let Some(relative) = path_below_route(request.uri().path(), &self.route)
+            .and_then(|relative| percent_decode(&relative))
+        else {
+            return handle_file_error(request, ErrorKind::NotFound).await;
+        };
+
+        let root = absolute_root(&self.root);
+        let Some(path) = resolve_path(&root, &relative) else {
+            return handle_file_error(request, ErrorKind::PermissionDenied).await;
+        };
+        let mut path = match canonicalize_in_root(&root, &path).await {
+            Ok(path) => path,
+            Err(kind) => return handle_file_error(request, kind).await,
+        };
+
+        let metadata = match tokio::fs::metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(e) => return handle_file_error(request, e.kind()).await,
+        };
+        if metadata.is_dir() {
+            let index = path.join(INDEX_FILE);
+            if !tokio::fs::try_exists(&index)
+                .await
+                .is_ok_and(|exists| exists)
+            {
+                return match list_directory(&path, request.uri().path(), !relative.is_empty()).await
+                {
+                    Ok(html) => Response::builder()
+                        .status(StatusCode::OK)
+                        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+                        .header(header::CONTENT_LENGTH, html.len())
+                        .body(full(html))
+                        .unwrap(),
+                    Err(e) => handle_file_error(request, e.kind()).await,
+                };
+            }
+            path = index;
+        }
+
+        let file = match File::open(&path).await {
+            Ok(file) => file,
+            Err(e) => return handle_file_error(request, e.kind()).await,
+        };
+        let metadata = match file.metadata().await {
+            Ok(metadata) => metadata,
+            Err(e) => return handle_file_error(request, e.kind()).await,
+        };
+        process_file(
+            request,
+            path.to_str().unwrap(),
+            file,
+            &metadata,
+            self.sniff_extensionless,
+            self.max_ranges,
+        )
+        .await
+    }
+}
+
+/// Entry of a directory listing.
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Renders the content of the directory as an HTML page, directories first then by name.
+///
+/// `req_path` is the path the directory was requested with, the links are built from it.
+async fn list_directory(dir: &Path, req_path: &str, has_parent: bool) -> std::io::Result<String> {
+    let mut entries = vec![];
+    let mut read_dir = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        // Follows symbolic links, a broken link is left out
+        let Ok(metadata) = tokio::fs::metadata(entry.path()).await else {
+            continue;
+        };
+        entries.push(Entry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+    let base = if req_path.ends_with('/') {
+        req_path.to_string()
+    } else {
+        format!("{req_path}/")
+    };
+    let title = escape_html(&base);
+
+    let mut rows = String::new();
+    if has_parent {
+        let parent = &base[..base[..base.len() - 1].rfind('/').map_or(0, |i| i + 1)];
+        rows.push_str(&format!(
+            "<tr><td><a href=\"{}\">../</a></td><td></td><td></td></tr>\n",
+            escape_html(parent)
+        ));
+    }
+    if entries.is_empty() {
+        rows.push_str("<tr><td colspan=\"3\"><em>This directory is empty.</em></td></tr>\n");
+    }
+    for entry in &entries {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        let size = if entry.is_dir {
+            "-".to_string()
+        } else {
+            entry.size.to_string()
+        };
+        let modified = entry
+            .modified
+            .map(httpdate::fmt_http_date)
+            .unwrap_or_default();
+        rows.push_str(&format!(
+            "<tr><td><a href=\"{}{}{suffix}\">{}{suffix}</a></td><td>{size}</td><td>{modified}</td></tr>\n",
+            escape_html(&base),
+            percent_encode(&entry.name),
+            escape_html(&entry.name),
+        ));
+    }
+
+    Ok(format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Index of {title}</title>\n</head>\n<body>\n<h1>Index of {title}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n{rows}</table>\n</body>\n</html>\n"
+    ))
+}
+
+pub(super) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Encodes a file name as a path segment, only unreserved characters are kept.
+fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            byte => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Decodes the `%XX` sequences of a request path, `None` when they are invalid or not UTF-8.
+pub(super) fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use http::{Request, StatusCode};
+    use rstest::rstest;
+
+    use super::{escape_html, percent_decode, percent_encode, BrowseHandler};
+    use crate::{
+        handlers::RequestHandler,
+        testing::{collect_body, MockBody},
+    };
+
+    async fn get(handler: &BrowseHandler, path: &str) -> (StatusCode, String) {
+        let request = Request::builder()
+            .uri(path)
+            .body(MockBody::new(b""))
+            .unwrap();
+        let response = handler.handle(request).await;
+        (response.status(), collect_body(response).await)
+    }
+
+    #[rstest]
+    #[case("guide.txt", "guide.txt")]
+    #[case("my file.txt", "my%20file.txt")]
+    #[case("a&b#c?.txt", "a%26b%23c%3F.txt")]
+    #[case("é", "%C3%A9")]
+    fn test_percent_encode_round_trip(#[case] name: &str, #[case] encoded: &str) {
+        assert_eq!(percent_encode(name), encoded);
+        assert_eq!(percent_decode(encoded).as_deref(), Some(name));
+    }
+
+    #[rstest]
+    #[case("%")]
+    #[case("%2")]
+    #[case("%zz")]
+    #[case("%FF")]
+    fn test_percent_decode_invalid(#[case] path: &str) {
+        assert_eq!(percent_decode(path), None);
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html(r#"<a href="x">'&'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_browse_handler_lists_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("files");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(root.join("b-dir")).unwrap();
+        fs::write(root.join("a.txt"), "12345").unwrap();
+        fs::write(root.join("<script>.txt"), "").unwrap();
+        let handler =
+            BrowseHandler::new(root.to_str().unwrap().to_string(), "/files/*".to_string());
+
+        let (status, body) = get(&handler, "/files/").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("<title>Index of /files/</title>"), "{body}");
+        assert!(!body.contains("../"), "{body}");
+        assert!(!body.contains("<script>"), "{body}");
+        let names: Vec<&str> = body
+            .lines()
+            .filter(|line| line.contains("<a href"))
+            .filter_map(|line| line.split("\">").nth(1))
+            .filter_map(|rest| rest.split("</a>").next())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["b-dir/", "docs/", "&lt;script&gt;.txt", "a.txt"]
+        );
+        assert!(body.contains("<a href=\"/files/a.txt\">a.txt</a></td><td>5</td>"));
+        assert!(body.contains("<a href=\"/files/docs/\">docs/</a></td><td>-</td>"));
+    }
+
+    #[tokio::test]
+    async fn test_browse_handler_lists_subdirectory_with_parent_link() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("files");
+        fs::create_dir_all(root.join("my docs")).unwrap();
+        fs::write(root.join("my docs/guide.txt"), "guide").unwrap();
+        let handler =
+            BrowseHandler::new(root.to_str().unwrap().to_string(), "/files/*".to_string());
+
+        let (status, body) = get(&handler, "/files/my%20docs").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("<a href=\"/files/\">../</a>"), "{body}");
+        assert!(
+            body.contains("<a href=\"/files/my%20docs/guide.txt\">guide.txt</a>"),
+            "{body}"
+        );
+        assert_eq!(
+            get(&handler, "/files/my%20docs/guide.txt").await,
+            (StatusCode::OK, "guide".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_browse_handler_lists_empty_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("files");
+        fs::create_dir_all(&root).unwrap();
+        let handler =
+            BrowseHandler::new(root.to_str().unwrap().to_string(), "/files/*".to_string());
+        let empty = "<tr><td colspan=\"3\"><em>This directory is empty.</em></td></tr>";
+
+        let (status, body) = get(&handler, "/files/").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("<h1>Index of /files/</h1>"), "{body}");
+        assert!(!body.contains("../"), "{body}");
+        assert!(body.contains(empty), "{body}");
+        assert!(body.ends_with("</table>\n</body>\n</html>\n"), "{body}");
+
+        fs::create_dir_all(root.join("empty")).unwrap();
+        let (status, body) = get(&handler, "/files/empty").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("<h1>Index of /files/empty/</h1>"), "{body}");
+        assert!(body.contains("<a href=\"/files/\">../</a>"), "{body}");
+        assert!(body.contains(empty), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_browse_handler_serves_index_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("files");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("index.html"), "home").unwrap();
+        let handler =
+            BrowseHandler::new(root.to_str().unwrap().to_string(), "/files/*".to_string());
+
+        assert_eq!(
+            get(&handler, "/files/").await,
+            (StatusCode::OK, "home".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_browse_handler_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("files");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+        let handler =
+            BrowseHandler::new(root.to_str().unwrap().to_string(), "/files/*".to_string());
+        let missing_root = BrowseHandler::new(
+            dir.path().join("missing").to_str().unwrap().to_string(),
+            "/files/*".to_string(),
+        );
+
+        assert_eq!(
+            get(&handler, "/files/missing").await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(get(&missing_root, "/files/").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(
+            get(&handler, "/files/../secret.txt").await.0,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            get(&handler, "/files/..%2fsecret.txt").await.0,
+            StatusCode::FORBIDDEN
+        );
+    }
+}
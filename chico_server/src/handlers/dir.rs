@@ -0,0 +1,368 @@
+use std::{
+    env,
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+};
+
+use http::{Response, StatusCode};
+use tokio::fs::File;
+
+use super::{
+    error,
+    file::{extract_ending_from_req_path, handle_file_error, process_file},
+    full, BoxBody, RequestHandler,
+};
+
+/// File served for a request to a directory.
+pub(super) const INDEX_FILE: &str = "index.html";
+
+/// Serves the files under a directory root, the request path below the route is resolved against
+/// the root.
+///
+/// With several roots the file is served from the first root having it, e.g. per-tenant assets
+/// overriding a default theme.
+#[derive(PartialEq, Debug)]
+pub struct DirHandler {
+    pub roots: Vec<String>,
+    pub route: String,
+    pub sniff_extensionless: bool,
+    pub max_ranges: Option<usize>,
+}
+
+impl DirHandler {
+    pub fn new(root: String, route: String) -> DirHandler {
+        DirHandler::with_roots(vec![root], route)
+    }
+
+    /// Creates a handler trying the roots in order.
+    pub fn with_roots(roots: Vec<String>, route: String) -> DirHandler {
+        DirHandler {
+            roots,
+            route,
+            sniff_extensionless: false,
+            max_ranges: None,
+        }
+    }
+
+    /// Detect the content type of files without extension from their first bytes.
+    pub fn with_sniff_extensionless(mut self, sniff_extensionless: bool) -> Self {
+        self.sniff_extensionless = sniff_extensionless;
+        self
+    }
+
+    /// Reject range requests asking for more than `max_ranges` ranges.
+    pub fn with_max_ranges(mut self, max_ranges: Option<usize>) -> Self {
+        self.max_ranges = max_ranges;
+        self
+    }
+}
+
+/// Returns the root as an absolute path, a relative root is resolved against the executable
+/// directory.
+pub(super) fn absolute_root(root: &str) -> PathBuf {
+    let root = PathBuf::from(root);
+    if root.is_absolute() {
+        return root;
+    }
+    let exe_path = env::current_exe().unwrap();
+    exe_path.parent().unwrap().join(root)
+}
+
+/// Returns the request path below the route, a route without wildcard serves the root itself.
+pub(super) fn path_below_route(req_path: &str, route: &str) -> Option<String> {
+    if route.ends_with("/*") {
+        extract_ending_from_req_path(req_path, route)
+    } else {
+        Some(String::new())
+    }
+}
+
+impl RequestHandler for DirHandler {
+    async fn handle<B>(&self, request: hyper::Request<B>) -> Response<BoxBody>
+    where
+        B: hyper::body::Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let req_method = request.method();
+        if req_method != http::Method::GET && req_method != http::Method::HEAD {
+            return error::built_in(
+                http::response::Builder::new()
+                    .status(StatusCode::METHOD_NOT_ALLOWED)
+                    .header(http::header::ALLOW, "GET, HEAD")
+                    .body(full(""))
+                    .unwrap(),
+            );
+        }
+
+        let Some(relative) = path_below_route(request.uri().path(), &self.route) else {
+            return handle_file_error(request, ErrorKind::NotFound).await;
+        };
+
+        let path = match self.find_in_roots(&relative).await {
+            Ok(path) => path,
+            Err(kind) => return handle_file_error(request, kind).await,
+        };
+
+        let file = match File::open(&path).await {
+            Ok(file) => file,
+            Err(e) => return handle_file_error(request, e.kind()).await,
+        };
+        let metadata = match file.metadata().await {
+            Ok(metadata) => metadata,
+            Err(e) => return handle_file_error(request, e.kind()).await,
+        };
+        process_file(
+            request,
+            path.to_str().unwrap(),
+            file,
+            &metadata,
+            self.sniff_extensionless,
+            self.max_ranges,
+        )
+        .await
+    }
+}
+
+impl DirHandler {
+    /// Returns the file to serve from the first root having it.
+    ///
+    /// Each root is protected on its own, a path denied in a root does not stop the search. When
+    /// no root has the file, the error of the first root that has something at the path wins
+    /// over not found.
+    async fn find_in_roots(&self, relative: &str) -> Result<PathBuf, ErrorKind> {
+        let mut error = ErrorKind::NotFound;
+        for root in &self.roots {
+            let root = absolute_root(root);
+            let Some(path) = resolve_path(&root, relative) else {
+                // the path itself leaves the root, it leaves every root
+                return Err(ErrorKind::PermissionDenied);
+            };
+            match find_file(&root, path).await {
+                Ok(path) => return Ok(path),
+                Err(ErrorKind::NotFound) => {}
+                Err(kind) if error == ErrorKind::NotFound => error = kind,
+                Err(_) => {}
+            }
+        }
+        Err(error)
+    }
+}
+
+/// Joins the request path below the route to the root.
+///
+/// Returns `None` when the path could leave the root: it has a `..` segment or is absolute.
+pub(super) fn resolve_path(root: &Path, relative: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for component in Path::new(relative).components() {
+        match component {
+            Component::Normal(segment) => path.push(segment),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(path)
+}
+
+/// Returns the file to serve for `path`, the index file for a directory.
+///
+/// A path whose real location is outside the root, through a symbolic link, is denied.
+async fn find_file(root: &Path, path: PathBuf) -> Result<PathBuf, ErrorKind> {
+    let mut path = canonicalize_in_root(root, &path).await?;
+
+    let metadata = tokio::fs::metadata(&path).await.map_err(|e| e.kind())?;
+    if metadata.is_dir() {
+        path.push(INDEX_FILE);
+        if !tokio::fs::try_exists(&path)
+            .await
+            .is_ok_and(|exists| exists)
+        {
+            return Err(ErrorKind::IsADirectory);
+        }
+    }
+    Ok(path)
+}
+
+/// Returns the real location of `path`, denied when it is outside the root through a symbolic
+/// link.
+pub(super) async fn canonicalize_in_root(root: &Path, path: &Path) -> Result<PathBuf, ErrorKind> {
+    let path = tokio::fs::canonicalize(path).await.map_err(|e| e.kind())?;
+    let root = tokio::fs::canonicalize(root).await.map_err(|e| e.kind())?;
+    if !path.starts_with(&root) {
+        return Err(ErrorKind::PermissionDenied);
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use http::{Request, StatusCode};
+    use rstest::rstest;
+
+    use super::{resolve_path, DirHandler};
+    use crate::{
+        handlers::RequestHandler,
+        testing::{collect_body, MockBody},
+    };
+
+    #[rstest]
+    #[case("", Some("/srv/www"))]
+    #[case("index.html", Some("/srv/www/index.html"))]
+    #[case("css/site.css", Some("/srv/www/css/site.css"))]
+    #[case("./css//site.css", Some("/srv/www/css/site.css"))]
+    #[case("..%2fsecret", Some("/srv/www/..%2fsecret"))]
+    #[case("..", None)]
+    #[case("../secret", None)]
+    #[case("css/../../secret", None)]
+    #[case("css/../site.css", None)]
+    #[case("/etc/passwd", None)]
+    fn test_resolve_path(#[case] relative: &str, #[case] expected: Option<&str>) {
+        assert_eq!(
+            resolve_path(Path::new("/srv/www"), relative),
+            expected.map(Into::into)
+        );
+    }
+
+    async fn get(handler: &DirHandler, path: &str) -> (StatusCode, String) {
+        let request = Request::builder()
+            .uri(path)
+            .body(MockBody::new(b""))
+            .unwrap();
+        let response = handler.handle(request).await;
+        (response.status(), collect_body(response).await)
+    }
+
+    #[tokio::test]
+    async fn test_dir_handler_serves_files_under_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("www");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::write(root.join("index.html"), "home").unwrap();
+        fs::write(root.join("docs/guide.txt"), "guide").unwrap();
+        fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+        let handler = DirHandler::new(root.to_str().unwrap().to_string(), "/static/*".to_string());
+
+        assert_eq!(
+            get(&handler, "/static/docs/guide.txt").await,
+            (StatusCode::OK, "guide".to_string())
+        );
+        assert_eq!(
+            get(&handler, "/static/").await,
+            (StatusCode::OK, "home".to_string())
+        );
+        assert_eq!(
+            get(&handler, "/static/missing.txt").await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            get(&handler, "/static/empty").await.0,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            get(&handler, "/static/../secret.txt").await.0,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    /// Creates a tenant root overriding a default root, `index.html` and `shared.css` are in both.
+    fn overlay_roots(dir: &Path) -> DirHandler {
+        let tenant = dir.join("tenant");
+        let default = dir.join("default");
+        fs::create_dir_all(&tenant).unwrap();
+        fs::create_dir_all(default.join("docs")).unwrap();
+        fs::write(tenant.join("index.html"), "tenant home").unwrap();
+        fs::write(tenant.join("shared.css"), "tenant css").unwrap();
+        fs::write(default.join("index.html"), "default home").unwrap();
+        fs::write(default.join("shared.css"), "default css").unwrap();
+        fs::write(default.join("logo.svg"), "default logo").unwrap();
+        fs::write(default.join("docs/index.html"), "default docs").unwrap();
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+        DirHandler::with_roots(
+            vec![
+                tenant.to_str().unwrap().to_string(),
+                default.to_str().unwrap().to_string(),
+            ],
+            "/assets/*".to_string(),
+        )
+    }
+
+    #[rstest]
+    #[case::only_in_second_root("/assets/logo.svg", StatusCode::OK, "default logo")]
+    #[case::first_root_wins("/assets/shared.css", StatusCode::OK, "tenant css")]
+    #[case::first_root_index("/assets/", StatusCode::OK, "tenant home")]
+    #[case::index_only_in_second_root("/assets/docs/", StatusCode::OK, "default docs")]
+    #[case::absent_everywhere("/assets/missing.txt", StatusCode::NOT_FOUND, "")]
+    #[case::traversal("/assets/../secret.txt", StatusCode::FORBIDDEN, "")]
+    #[tokio::test]
+    async fn test_dir_handler_falls_through_roots(
+        #[case] path: &str,
+        #[case] expected_status: StatusCode,
+        #[case] expected_body: &str,
+    ) {
+        let dir = tempfile::tempdir().unwrap();
+        let handler = overlay_roots(dir.path());
+
+        let (status, body) = get(&handler, path).await;
+
+        assert_eq!(status, expected_status);
+        if status == StatusCode::OK {
+            assert_eq!(body, expected_body);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dir_handler_skips_missing_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("www");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("site.css"), "css").unwrap();
+        let handler = DirHandler::with_roots(
+            vec![
+                dir.path().join("missing").to_str().unwrap().to_string(),
+                root.to_str().unwrap().to_string(),
+            ],
+            "/static/*".to_string(),
+        );
+
+        assert_eq!(
+            get(&handler, "/static/site.css").await,
+            (StatusCode::OK, "css".to_string())
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_dir_handler_denies_symlink_out_of_each_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let handler = overlay_roots(dir.path());
+        std::os::unix::fs::symlink(
+            dir.path().join("secret.txt"),
+            dir.path().join("tenant/link.txt"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            get(&handler, "/assets/link.txt").await.0,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_dir_handler_denies_symlink_out_of_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("www");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("secret.txt"), root.join("link.txt")).unwrap();
+        let handler = DirHandler::new(root.to_str().unwrap().to_string(), "/static/*".to_string());
+
+        assert_eq!(
+            get(&handler, "/static/link.txt").await.0,
+            StatusCode::FORBIDDEN
+        );
+    }
+}
@@ -0,0 +1,293 @@
+//! # Error pages
+//!
+//! Builds the bodies of the error responses chico generates itself (unknown host or route,
+//! method not allowed, upstream failures, ...), following the `error_format` configured on the
+//! route or virtual host.
+//!
+//! Handlers mark such responses with [`built_in`]; responses produced by an upstream or by a
+//! `respond` route are never rewritten. The pages configured with `error_page <status> <path>`
+//! replace the built-in body of their status, see [`ErrorPages`].
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use chico_file::types::{ErrorDocument, ErrorFormat};
+use http::{header, HeaderMap, HeaderValue, Request, Response, StatusCode};
+use tracing::warn;
+
+use super::{dir::absolute_root, file::MIME_DICT, full, BoxBody};
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Response extension marking a response as a built-in error.
+#[derive(Clone, Copy, Debug)]
+pub struct BuiltInError;
+
+/// Marks the response as a built-in error, so it is rendered in the configured error format.
+pub fn built_in(mut response: Response<BoxBody>) -> Response<BoxBody> {
+    response.extensions_mut().insert(BuiltInError);
+    response
+}
+
+pub fn is_built_in(response: &Response<BoxBody>) -> bool {
+    response.extensions().get::<BuiltInError>().is_some()
+}
+
+/// Details of the request needed to render its error responses.
+pub struct ErrorPage {
+    accepts_json: bool,
+    request_id: String,
+}
+
+impl ErrorPage {
+    pub fn from_request<B>(request: &Request<B>) -> Self {
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(generate_request_id);
+
+        Self {
+            accepts_json: accepts_json(request.headers()),
+            request_id,
+        }
+    }
+
+    /// Renders a built-in error response in the given format; other responses are returned as is.
+    ///
+    /// The HTML format keeps the body produced by the handler.
+    pub fn render(&self, response: Response<BoxBody>, format: ErrorFormat) -> Response<BoxBody> {
+        if !is_built_in(&response) {
+            return response;
+        }
+
+        let use_json = match format {
+            ErrorFormat::Json => true,
+            ErrorFormat::Html => false,
+            ErrorFormat::Auto => self.accepts_json,
+        };
+        if !use_json {
+            return response;
+        }
+
+        let (mut parts, _) = response.into_parts();
+        let body = serde_json::json!({
+            "status": parts.status.as_u16(),
+            "error": parts.status.canonical_reason().unwrap_or("Error"),
+            "request_id": self.request_id,
+        })
+        .to_string();
+
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts.headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        if let Ok(request_id) = HeaderValue::from_str(&self.request_id) {
+            parts.headers.insert(REQUEST_ID_HEADER, request_id);
+        }
+
+        Response::from_parts(parts, full(body))
+    }
+}
+
+/// Files of a virtual host served instead of the built-in body of error statuses.
+#[derive(Debug, Default)]
+pub struct ErrorPages {
+    files: HashMap<StatusCode, String>,
+}
+
+impl ErrorPages {
+    pub fn new(documents: &[ErrorDocument]) -> Self {
+        let files = documents
+            .iter()
+            .filter_map(|document| {
+                let status = StatusCode::from_u16(document.status).ok()?;
+                Some((status, document.file.clone()))
+            })
+            .collect();
+        Self { files }
+    }
+
+    pub fn serves(&self, status: StatusCode) -> bool {
+        self.files.contains_key(&status)
+    }
+
+    /// Replaces the body of a built-in error response by the page of its status, keeping its
+    /// status and headers. The response is returned as is when no page is configured for its
+    /// status or the page cannot be read.
+    pub async fn apply(&self, response: Response<BoxBody>) -> Response<BoxBody> {
+        if !is_built_in(&response) {
+            return response;
+        }
+        let Some(file) = self.files.get(&response.status()) else {
+            return response;
+        };
+        let content = match tokio::fs::read(absolute_root(file)).await {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("could not read the error page {}: {}", file, e);
+                return response;
+            }
+        };
+
+        let (mut parts, _) = response.into_parts();
+        // The page is served as configured, it is not rendered in the error format
+        parts.extensions.remove::<BuiltInError>();
+        parts.headers.remove(header::CONTENT_LENGTH);
+        match MIME_DICT
+            .get_content_type(file)
+            .and_then(|content_type| HeaderValue::from_str(&content_type).ok())
+        {
+            Some(content_type) => parts.headers.insert(header::CONTENT_TYPE, content_type),
+            None => parts.headers.remove(header::CONTENT_TYPE),
+        };
+        Response::from_parts(parts, full(content))
+    }
+}
+
+fn accepts_json(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_range| {
+            let media_type = media_range.split(';').next().unwrap_or("").trim();
+            media_type.eq_ignore_ascii_case("application/json") || media_type.ends_with("+json")
+        })
+}
+
+fn generate_request_id() -> String {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    format!(
+        "{:x}-{:x}",
+        std::process::id(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chico_file::types::ErrorFormat;
+    use http::{Request, Response, StatusCode};
+    use http_body_util::BodyExt;
+    use rstest::rstest;
+
+    use crate::{handlers::full, testing::collect_body};
+
+    use super::{built_in, is_built_in, ErrorPage, ErrorPages};
+
+    fn request_with_accept(accept: &str) -> Request<()> {
+        Request::builder()
+            .header(http::header::ACCEPT, accept)
+            .header("x-request-id", "abc-123")
+            .body(())
+            .unwrap()
+    }
+
+    fn not_found() -> Response<crate::handlers::BoxBody> {
+        Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(full("<h1>404 Not Found</h1>"))
+            .unwrap()
+    }
+
+    #[rstest]
+    #[case("application/json", ErrorFormat::Auto, true)]
+    #[case("text/html, application/json;q=0.9", ErrorFormat::Auto, true)]
+    #[case("application/problem+json", ErrorFormat::Auto, true)]
+    #[case("text/html", ErrorFormat::Auto, false)]
+    #[case("*/*", ErrorFormat::Auto, false)]
+    #[case("text/html", ErrorFormat::Json, true)]
+    #[case("application/json", ErrorFormat::Html, false)]
+    #[tokio::test]
+    async fn test_render_built_in_error(
+        #[case] accept: &str,
+        #[case] format: ErrorFormat,
+        #[case] expect_json: bool,
+    ) {
+        let page = ErrorPage::from_request(&request_with_accept(accept));
+        let response = page.render(built_in(not_found()), format);
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let is_json = response.headers().get(http::header::CONTENT_TYPE)
+            == Some(&http::HeaderValue::from_static("application/json"));
+        assert_eq!(is_json, expect_json);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        if expect_json {
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(
+                body,
+                serde_json::json!({ "status": 404, "error": "Not Found", "request_id": "abc-123" })
+            );
+        } else {
+            assert_eq!(&body[..], b"<h1>404 Not Found</h1>");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_render_keeps_responses_not_marked_as_built_in() {
+        let page = ErrorPage::from_request(&request_with_accept("application/json"));
+        let response = page.render(not_found(), ErrorFormat::Json);
+
+        assert_eq!(collect_body(response).await, "<h1>404 Not Found</h1>");
+    }
+
+    #[test]
+    fn test_request_id_is_generated_when_missing() {
+        let first = ErrorPage::from_request(&Request::new(()));
+        let second = ErrorPage::from_request(&Request::new(()));
+        assert!(!first.request_id.is_empty());
+        assert_ne!(first.request_id, second.request_id);
+    }
+
+    fn error_pages(file: &str) -> ErrorPages {
+        ErrorPages::new(&[chico_file::types::ErrorDocument {
+            status: 404,
+            file: file.to_string(),
+        }])
+    }
+
+    #[tokio::test]
+    async fn test_error_page_replaces_body_of_built_in_error() {
+        let mut page = tempfile::NamedTempFile::with_suffix(".html").unwrap();
+        std::io::Write::write_all(&mut page, b"<h1>Lost?</h1>").unwrap();
+        let pages = error_pages(page.path().to_str().unwrap());
+
+        let response = pages.apply(built_in(not_found())).await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[http::header::CONTENT_TYPE], "text/html");
+        assert!(!is_built_in(&response));
+        assert_eq!(collect_body(response).await, "<h1>Lost?</h1>");
+    }
+
+    #[rstest]
+    // responses of upstreams and respond routes are kept
+    #[case(not_found(), "/path/to/404.html")]
+    // no page for the status
+    #[case(built_in(Response::builder().status(StatusCode::BAD_GATEWAY).body(full("<h1>502 Bad Gateway</h1>")).unwrap()), "/path/to/404.html")]
+    // the page cannot be read
+    #[case(built_in(not_found()), "/path/to/missing/404.html")]
+    #[tokio::test]
+    async fn test_error_page_keeps_response(
+        #[case] response: Response<crate::handlers::BoxBody>,
+        #[case] file: &str,
+    ) {
+        let pages = error_pages(file);
+        let status = response.status();
+        let was_built_in = is_built_in(&response);
+
+        let response = pages.apply(response).await;
+
+        assert_eq!(response.status(), status);
+        assert_eq!(is_built_in(&response), was_built_in);
+        assert_ne!(collect_body(response).await, "<h1>Lost?</h1>");
+    }
+}
@@ -2,11 +2,12 @@ use std::{
     env,
     fs::Metadata,
     io::{ErrorKind, SeekFrom},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    time::SystemTime,
 };
 
 use futures_util::TryStreamExt;
-use http::{Method, Response, StatusCode};
+use http::{HeaderMap, HeaderValue, Method, Response, StatusCode};
 use http_body_util::{BodyExt, StreamBody};
 use hyper::body::Frame;
 use tokio::{
@@ -17,16 +18,27 @@ use tokio_util::io::ReaderStream;
 
 use crate::handlers::respond::RespondHandler;
 
-use super::{full, BoxBody, RequestHandler};
+use super::{dir::resolve_path, error, full, BoxBody, RequestHandler};
 
-static MIME_DICT: std::sync::LazyLock<mimee::MimeDict> =
+pub(super) static MIME_DICT: std::sync::LazyLock<mimee::MimeDict> =
     std::sync::LazyLock::new(mimee::MimeDict::new);
 
+/// Response extension describing the file whose whole content is the body of the response.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServedFile {
+    pub path: PathBuf,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
 #[derive(PartialEq, Debug)]
 pub struct FileHandler {
     pub path: String,
     pub is_dir: bool,
     pub route: String,
+    pub sniff_extensionless: bool,
+    pub max_ranges: Option<usize>,
+    pub negotiate_language: bool,
 }
 
 impl FileHandler {
@@ -35,8 +47,30 @@ impl FileHandler {
             is_dir: path.ends_with("/"),
             path,
             route,
+            sniff_extensionless: false,
+            max_ranges: None,
+            negotiate_language: false,
         }
     }
+
+    /// Detect the content type of files without extension from their first bytes.
+    pub fn with_sniff_extensionless(mut self, sniff_extensionless: bool) -> Self {
+        self.sniff_extensionless = sniff_extensionless;
+        self
+    }
+
+    /// Reject range requests asking for more than `max_ranges` ranges.
+    pub fn with_max_ranges(mut self, max_ranges: Option<usize>) -> Self {
+        self.max_ranges = max_ranges;
+        self
+    }
+
+    /// Serve the variant of the file in the language the client prefers, like `index.fr.html`
+    /// for `index.html`.
+    pub fn with_negotiate_language(mut self, negotiate_language: bool) -> Self {
+        self.negotiate_language = negotiate_language;
+        self
+    }
 }
 
 impl RequestHandler for FileHandler {
@@ -48,11 +82,13 @@ impl RequestHandler for FileHandler {
     {
         let req_method = request.method();
         if req_method != http::Method::GET && req_method != http::Method::HEAD {
-            return http::response::Builder::new()
-                .status(StatusCode::METHOD_NOT_ALLOWED)
-                .header(http::header::ALLOW, "GET, HEAD")
-                .body(full(""))
-                .unwrap();
+            return error::built_in(
+                http::response::Builder::new()
+                    .status(StatusCode::METHOD_NOT_ALLOWED)
+                    .header(http::header::ALLOW, "GET, HEAD")
+                    .body(full(""))
+                    .unwrap(),
+            );
         }
         let mut path = PathBuf::from(&self.path);
 
@@ -80,13 +116,23 @@ impl RequestHandler for FileHandler {
         }
 
         if self.is_dir {
+            // the path excludes the query, which has no say in the file served
             let ending = extract_ending_from_req_path(request.uri().path(), &self.route);
             if ending.is_none() {
                 return handle_file_error(request, ErrorKind::NotFound).await;
             }
-            path = path.join(ending.unwrap());
+            let Some(joined) = resolve_path(&path, &ending.unwrap()) else {
+                return handle_file_error(request, ErrorKind::PermissionDenied).await;
+            };
+            path = joined;
         };
 
+        if self.negotiate_language {
+            if let Some(variant) = language_variant(&path, request.headers()).await {
+                path = variant;
+            }
+        }
+
         let file = File::open(&path).await;
 
         if file.is_err() {
@@ -101,11 +147,88 @@ impl RequestHandler for FileHandler {
         }
         let file: File = file.unwrap();
         let metadata = &metadata.unwrap();
-        process_file(request, path.to_str().unwrap(), file, metadata).await
+        if metadata.is_dir() {
+            return handle_file_error(request, ErrorKind::IsADirectory).await;
+        }
+        let mut response = process_file(
+            request,
+            path.to_str().unwrap(),
+            file,
+            metadata,
+            self.sniff_extensionless,
+            self.max_ranges,
+        )
+        .await;
+        if self.negotiate_language {
+            response.headers_mut().append(
+                http::header::VARY,
+                HeaderValue::from_static("Accept-Language"),
+            );
+        }
+        response
+    }
+}
+
+/// Returns the existing variant of the file in the language the client prefers: `index.fr.html`
+/// for `index.html` and `Accept-Language: fr-CA, fr;q=0.8`, trying `fr-ca` before `fr`.
+///
+/// `None` when the client accepts none of the variants, the file itself is then served in the
+/// default language.
+async fn language_variant(path: &Path, headers: &HeaderMap) -> Option<PathBuf> {
+    let stem = path.file_stem()?.to_str()?;
+    let extension = path.extension()?.to_str()?;
+
+    for language in accepted_languages(headers) {
+        let primary = language.split('-').next().unwrap_or_default().to_string();
+        let candidates = match primary == language {
+            true => vec![language],
+            false => vec![language, primary],
+        };
+        for candidate in candidates {
+            let variant = path.with_file_name(format!("{stem}.{candidate}.{extension}"));
+            if tokio::fs::metadata(&variant)
+                .await
+                .is_ok_and(|metadata| metadata.is_file())
+            {
+                return Some(variant);
+            }
+        }
     }
+    None
 }
 
-fn extract_ending_from_req_path(req_path: &str, route: &str) -> Option<String> {
+/// Returns the lowercase language tags of the `Accept-Language` header by decreasing quality,
+/// without `*` and the tags of quality 0.
+fn accepted_languages(headers: &HeaderMap) -> Vec<String> {
+    let mut languages: Vec<(String, f32)> = headers
+        .get_all(http::header::ACCEPT_LANGUAGE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let language = params.next()?.trim().to_ascii_lowercase();
+            let quality = match params.find_map(|param| param.trim().strip_prefix("q=")) {
+                Some(quality) => quality.trim().parse::<f32>().ok()?,
+                None => 1.0,
+            };
+            // the tag names a file, only letters, digits and `-` are allowed
+            let valid = !language.is_empty()
+                && language
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-');
+            (valid && quality > 0.0).then_some((language, quality))
+        })
+        .collect();
+    // stable, tags of the same quality keep the order of the header
+    languages.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    languages
+        .into_iter()
+        .map(|(language, _)| language)
+        .collect()
+}
+
+pub(super) fn extract_ending_from_req_path(req_path: &str, route: &str) -> Option<String> {
     let slash_index = route.rfind("/*")?;
     let route_without_asterisk = &route[..=slash_index];
     let route_without_asterisk_length = route_without_asterisk.len();
@@ -114,11 +237,13 @@ fn extract_ending_from_req_path(req_path: &str, route: &str) -> Option<String> {
     Some(ending.to_string())
 }
 
-async fn process_file<B>(
+pub(super) async fn process_file<B>(
     request: hyper::Request<B>,
     file_name: &str,
     mut file: File,
     metadata: &Metadata,
+    sniff_extensionless: bool,
+    max_ranges: Option<usize>,
 ) -> Response<BoxBody>
 where
     B: hyper::body::Body + Send + 'static,
@@ -127,9 +252,19 @@ where
 {
     let mut builder = Response::builder();
 
-    let content_type = MIME_DICT.get_content_type(file_name);
+    let mut content_type = MIME_DICT.get_content_type(file_name);
     let file_size = metadata.len();
 
+    if content_type.is_none()
+        && sniff_extensionless
+        && std::path::Path::new(file_name).extension().is_none()
+    {
+        match sniff_file(&mut file).await {
+            Ok(sniffed) => content_type = sniffed.map(str::to_string),
+            Err(e) => return handle_file_error(request, e.kind()).await,
+        }
+    }
+
     if content_type.is_some() {
         builder = builder.header(http::header::CONTENT_TYPE, content_type.unwrap());
     }
@@ -144,6 +279,10 @@ where
     if range_header.is_some() {
         match range_header.unwrap().to_str() {
             Ok(data) => match parse_range(data, file_size) {
+                // Many small ranges of the same file amplify the response, refuse them.
+                Some(r) if max_ranges.is_some_and(|max| r.len() > max) => {
+                    range = Some(Err("Too many ranges"))
+                }
                 Some(r) => range = Some(Ok(r)),
                 None => range = Some(Err("Invalid range")),
             },
@@ -189,11 +328,76 @@ where
         let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
         let boxed_body = stream_body.boxed();
 
-        builder.status(StatusCode::OK).body(boxed_body).unwrap()
+        builder
+            .status(StatusCode::OK)
+            .extension(ServedFile {
+                path: PathBuf::from(file_name),
+                len: file_size,
+                modified: metadata.modified().ok(),
+            })
+            .body(boxed_body)
+            .unwrap()
+    }
+}
+
+/// Number of bytes read to detect the content type of a file
+const SNIFF_LENGTH: u64 = 512;
+
+/// Reads the start of the file to detect its content type and rewinds the file.
+async fn sniff_file(file: &mut File) -> std::io::Result<Option<&'static str>> {
+    let mut head = Vec::with_capacity(SNIFF_LENGTH as usize);
+    (&mut *file)
+        .take(SNIFF_LENGTH)
+        .read_to_end(&mut head)
+        .await?;
+    file.seek(SeekFrom::Start(0)).await?;
+    Ok(sniff_content_type(&head))
+}
+
+/// Minimal content sniffing: recognizes HTML documents and plain text.
+fn sniff_content_type(head: &[u8]) -> Option<&'static str> {
+    const HTML_SIGNATURES: [&[u8]; 6] = [
+        b"<!doctype html",
+        b"<html",
+        b"<head",
+        b"<body",
+        b"<title",
+        b"<!--",
+    ];
+
+    let start = head
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(head.len());
+    let trimmed = &head[start..];
+    let is_html = HTML_SIGNATURES.iter().any(|signature| {
+        trimmed.len() >= signature.len()
+            && trimmed[..signature.len()].eq_ignore_ascii_case(signature)
+    });
+    if is_html {
+        return Some("text/html");
     }
+
+    // A multi-byte character may be cut at the end of the sniffed bytes
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).unwrap(),
+        Err(_) => return None,
+    };
+    let is_text = text
+        .chars()
+        .all(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r' | '\x0c'));
+    if is_text && !head.is_empty() {
+        return Some("text/plain");
+    }
+
+    None
 }
 
-async fn handle_file_error<B>(request: hyper::Request<B>, error: ErrorKind) -> Response<BoxBody>
+pub(super) async fn handle_file_error<B>(
+    request: hyper::Request<B>,
+    error: ErrorKind,
+) -> Response<BoxBody>
 where
     B: hyper::body::Body + Send + 'static,
     B::Data: Send,
@@ -205,7 +409,7 @@ where
         ErrorKind::IsADirectory => RespondHandler::forbidden(),
         _ => RespondHandler::internal_server_error(),
     };
-    return handler.handle(request).await;
+    return error::built_in(handler.handle(request).await);
 }
 
 /// Helper function to parse Range header
@@ -267,10 +471,10 @@ mod tests {
             respond::RespondHandler,
             RequestHandler,
         },
-        test_utils::MockBody,
+        testing::{collect_body, MockBody},
     };
 
-    use super::extract_ending_from_req_path;
+    use super::{accepted_languages, extract_ending_from_req_path, sniff_content_type};
 
     #[tokio::test]
     async fn test_file_handler_return_ok_relative_path() {
@@ -311,16 +515,7 @@ mod tests {
             "text/html"
         );
 
-        let response_body = String::from_utf8(
-            response
-                .boxed()
-                .collect()
-                .await
-                .unwrap()
-                .to_bytes()
-                .to_vec(),
-        )
-        .unwrap();
+        let response_body = collect_body(response).await;
         assert_eq!(response_body, content);
 
         // Ignore the result of removing file
@@ -391,16 +586,7 @@ mod tests {
             "bytes".to_string()
         );
 
-        let response_body = String::from_utf8(
-            response
-                .boxed()
-                .collect()
-                .await
-                .unwrap()
-                .to_bytes()
-                .to_vec(),
-        )
-        .unwrap();
+        let response_body = collect_body(response).await;
         assert_eq!(response_body, content);
     }
 
@@ -447,16 +633,7 @@ mod tests {
             "text/plain"
         );
 
-        let response_body = String::from_utf8(
-            response
-                .boxed()
-                .collect()
-                .await
-                .unwrap()
-                .to_bytes()
-                .to_vec(),
-        )
-        .unwrap();
+        let response_body = collect_body(response).await;
         assert_eq!(response_body, content);
 
         // Ignore the result of removing file
@@ -500,22 +677,114 @@ mod tests {
             "text/html"
         );
         assert_eq!(&response.status(), &StatusCode::OK);
-        let response_body = String::from_utf8(
-            response
-                .boxed()
-                .collect()
-                .await
-                .unwrap()
-                .to_bytes()
-                .to_vec(),
-        )
-        .unwrap();
+        let response_body = collect_body(response).await;
         assert_eq!(response_body, content);
 
         // Ignore the result of removing file
         _ = std::fs::remove_file(file_path);
     }
 
+    #[tokio::test]
+    async fn test_file_handler_return_ok_path_with_spaces() {
+        let content = "<h1>My site</h1>";
+        let dir = tempfile::tempdir().unwrap();
+        let site = dir.path().join("my site");
+        std::fs::create_dir(&site).unwrap();
+        let file_path = site.join("index page.html");
+        std::fs::write(&file_path, content).unwrap();
+
+        let file_handler =
+            FileHandler::new(file_path.to_str().unwrap().to_string(), "/".to_string());
+        let request = Request::builder().body(MockBody::new(b"")).unwrap();
+
+        let response = file_handler.handle(request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(collect_body(response).await, content);
+    }
+
+    /// Handler of `route /assets/* { file <dir>/ }`, the directory has `style.css` and a
+    /// `secret.txt` next to it.
+    fn assets_handler(dir: &std::path::Path) -> FileHandler {
+        let assets = dir.join("assets");
+        std::fs::create_dir(&assets).unwrap();
+        std::fs::write(assets.join("style.css"), "body {}").unwrap();
+        std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+        FileHandler::new(
+            format!("{}/", assets.to_str().unwrap()),
+            "/assets/*".to_string(),
+        )
+    }
+
+    #[rstest]
+    #[case("/style.css?v=123")]
+    #[case("/style.css?")]
+    #[case("/style.css?../../secret.txt")]
+    #[case("/style.css?file=..%2Fsecret.txt&v=1")]
+    #[tokio::test]
+    async fn test_file_handler_ignores_query(#[case] uri: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("style.css");
+        std::fs::write(&file_path, "body {}").unwrap();
+        let file_handler = FileHandler::new(
+            file_path.to_str().unwrap().to_string(),
+            "/style.css".to_string(),
+        );
+        let request = Request::builder()
+            .uri(uri)
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = file_handler.handle(request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(collect_body(response).await, "body {}");
+    }
+
+    #[rstest]
+    #[case("/assets/style.css?v=123")]
+    #[case("/assets/style.css?../secret.txt")]
+    #[case("/assets/style.css?path=/assets/../secret.txt")]
+    #[tokio::test]
+    async fn test_file_handler_ignores_query_of_dynamic_route(#[case] uri: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        let file_handler = assets_handler(dir.path());
+        let request = Request::builder()
+            .uri(uri)
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = file_handler.handle(request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(collect_body(response).await, "body {}");
+    }
+
+    #[rstest]
+    #[case("/assets/../secret.txt", StatusCode::FORBIDDEN)]
+    #[case("/assets/../secret.txt?v=123", StatusCode::FORBIDDEN)]
+    #[case("/assets/./../secret.txt", StatusCode::FORBIDDEN)]
+    #[case("/assets//etc/passwd", StatusCode::FORBIDDEN)]
+    #[case("/assets/%2e%2e/secret.txt", StatusCode::NOT_FOUND)]
+    #[case("/assets/?../secret.txt", StatusCode::FORBIDDEN)]
+    #[tokio::test]
+    async fn test_file_handler_denies_path_leaving_dynamic_route_root(
+        #[case] uri: &str,
+        #[case] status: StatusCode,
+    ) {
+        let dir = tempfile::tempdir().unwrap();
+        let file_handler = assets_handler(dir.path());
+        let request = Request::builder()
+            .uri(uri)
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = file_handler.handle(request).await;
+
+        assert_eq!(response.status(), status);
+        assert_ne!(collect_body(response).await, "secret");
+    }
+
     #[tokio::test]
     async fn test_file_handler_return_404() {
         let file_handler = FileHandler::new("not-exist-index.html".to_string(), "/".to_string());
@@ -525,16 +794,7 @@ mod tests {
         let response = file_handler.handle(request).await;
 
         assert_eq!(&response.status(), &StatusCode::NOT_FOUND);
-        let response_body = String::from_utf8(
-            response
-                .boxed()
-                .collect()
-                .await
-                .unwrap()
-                .to_bytes()
-                .to_vec(),
-        )
-        .unwrap();
+        let response_body = collect_body(response).await;
         assert_eq!(response_body, "");
     }
 
@@ -551,16 +811,7 @@ mod tests {
         let response = file_handler.handle(request).await;
 
         assert_eq!(&response.status(), &StatusCode::FORBIDDEN);
-        let response_body = String::from_utf8(
-            response
-                .boxed()
-                .collect()
-                .await
-                .unwrap()
-                .to_bytes()
-                .to_vec(),
-        )
-        .unwrap();
+        let response_body = collect_body(response).await;
         assert_eq!(response_body, "");
     }
 
@@ -796,4 +1047,151 @@ mod tests {
 
         assert_eq!(response.status(), StatusCode::OK);
     }
+
+    #[rstest]
+    #[case(b"<!DOCTYPE html><html></html>", Some("text/html"))]
+    #[case(b"\n  <html lang=\"en\">", Some("text/html"))]
+    #[case(b"<HEAD><title>x</title></HEAD>", Some("text/html"))]
+    #[case(b"# Project\n\nSome notes.\n", Some("text/plain"))]
+    #[case("caf\u{e9}\tna\u{ef}ve\r\n".as_bytes(), Some("text/plain"))]
+    #[case(b"\x89PNG\r\n\x1a\n\0\0", None)]
+    #[case(b"\x7fELF\x02\x01\x01", None)]
+    #[case(b"", None)]
+    fn test_sniff_content_type(#[case] head: &[u8], #[case] expected: Option<&str>) {
+        assert_eq!(sniff_content_type(head), expected);
+    }
+
+    #[test]
+    fn test_sniff_content_type_accepts_cut_multi_byte_character() {
+        let head = "h\u{e9}".as_bytes();
+        assert_eq!(sniff_content_type(&head[..2]), Some("text/plain"));
+    }
+
+    #[rstest]
+    #[case(true, Some("text/html"))]
+    #[case(false, None)]
+    #[tokio::test]
+    async fn test_file_handler_sniff_extensionless(
+        #[case] sniff_extensionless: bool,
+        #[case] content_type: Option<&str>,
+    ) {
+        let content = "<!DOCTYPE html>\n<html><body><h1>Read me</h1></body></html>";
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("README");
+        std::fs::write(&file_path, content).unwrap();
+
+        let file_handler =
+            FileHandler::new(file_path.to_str().unwrap().to_string(), "/".to_string())
+                .with_sniff_extensionless(sniff_extensionless);
+
+        let request = Request::builder().body(MockBody::new(b"")).unwrap();
+        let response = file_handler.handle(request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response
+                .headers()
+                .get(http::header::CONTENT_TYPE)
+                .map(|value| value.to_str().unwrap()),
+            content_type
+        );
+        // the sniffed bytes are still part of the body
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], content.as_bytes());
+    }
+
+    fn ranges(count: u64) -> String {
+        let ranges: Vec<String> = (0..count).map(|i| format!("{i}-{i}")).collect();
+        format!("bytes={}", ranges.join(","))
+    }
+
+    #[rstest]
+    #[case(Some(10), 50, StatusCode::RANGE_NOT_SATISFIABLE)]
+    #[case(Some(10), 11, StatusCode::RANGE_NOT_SATISFIABLE)]
+    #[case(Some(10), 10, StatusCode::PARTIAL_CONTENT)]
+    #[case(None, 50, StatusCode::PARTIAL_CONTENT)]
+    #[tokio::test]
+    async fn test_file_handler_max_ranges(
+        #[case] max_ranges: Option<usize>,
+        #[case] range_count: u64,
+        #[case] expected_status: StatusCode,
+    ) {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("video.mp4");
+        std::fs::write(&file_path, [0u8; 100]).unwrap();
+
+        let file_handler =
+            FileHandler::new(file_path.to_str().unwrap().to_string(), "/".to_string())
+                .with_max_ranges(max_ranges);
+
+        let request = Request::builder()
+            .header(http::header::RANGE, ranges(range_count))
+            .body(MockBody::new(b""))
+            .unwrap();
+        let response = file_handler.handle(request).await;
+
+        assert_eq!(response.status(), expected_status);
+        if expected_status == StatusCode::RANGE_NOT_SATISFIABLE {
+            assert_eq!(
+                response
+                    .headers()
+                    .get(http::header::CONTENT_RANGE)
+                    .unwrap()
+                    .to_str()
+                    .unwrap(),
+                "bytes */100"
+            );
+        }
+    }
+
+    #[rstest]
+    #[case("fr-CA, en;q=0.5, *;q=0.1", vec!["fr-ca", "en"])]
+    #[case("de;q=0.2, fr, en;q=0.8", vec!["fr", "en", "de"])]
+    #[case("fr;q=0, en", vec!["en"])]
+    #[case("en, ../../etc, fr;q=x", vec!["en"])]
+    fn test_accepted_languages(#[case] accept_language: &str, #[case] expected: Vec<&str>) {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(
+            http::header::ACCEPT_LANGUAGE,
+            accept_language.parse().unwrap(),
+        );
+
+        assert_eq!(accepted_languages(&headers), expected);
+    }
+
+    #[rstest]
+    #[case(Some("fr"), "bonjour")]
+    #[case(Some("fr-CA, en;q=0.5"), "bonjour")]
+    #[case(Some("de, en;q=0.5"), "hello")]
+    // no variant in an accepted language, the file in the default language
+    #[case(Some("de"), "default")]
+    #[case(None, "default")]
+    #[tokio::test]
+    async fn test_file_handler_negotiate_language(
+        #[case] accept_language: Option<&str>,
+        #[case] expected: &str,
+    ) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "default").unwrap();
+        std::fs::write(dir.path().join("index.en.html"), "hello").unwrap();
+        std::fs::write(dir.path().join("index.fr.html"), "bonjour").unwrap();
+
+        let file_handler = FileHandler::new(
+            format!("{}/", dir.path().to_str().unwrap()),
+            "/*".to_string(),
+        )
+        .with_negotiate_language(true);
+
+        let mut request = Request::builder().uri("/index.html");
+        if let Some(accept_language) = accept_language {
+            request = request.header(http::header::ACCEPT_LANGUAGE, accept_language);
+        }
+        let response = file_handler
+            .handle(request.body(MockBody::new(b"")).unwrap())
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[http::header::VARY], "Accept-Language");
+        assert_eq!(collect_body(response).await, expected);
+    }
 }
@@ -0,0 +1,493 @@
+//! # Multipart
+//!
+//! Streaming reader of `multipart/form-data` request bodies. Parts are read one after the other
+//! and their content is returned in chunks as it arrives, so no part is held in memory as a
+//! whole. The limits abort the reading with `413 Payload Too Large`, a malformed body with
+//! `400 Bad Request`.
+
+use std::fmt;
+
+use bytes::{Buf, Bytes, BytesMut};
+use http::{header, Request, StatusCode};
+use http_body_util::BodyExt;
+
+/// Limits of a multipart body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultipartLimits {
+    /// Maximum number of parts.
+    pub max_parts: usize,
+    /// Maximum size of the content of a part.
+    pub max_part_size: u64,
+    /// Maximum size of the headers of a part.
+    pub max_header_size: usize,
+    /// Maximum size of the whole body.
+    pub max_total_size: u64,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        Self {
+            max_parts: 100,
+            max_part_size: 10 * 1024 * 1024,
+            max_header_size: 8 * 1024,
+            max_total_size: 50 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MultipartError {
+    /// A limit is exceeded.
+    TooLarge(&'static str),
+    /// The body is not valid multipart or could not be read.
+    Malformed(&'static str),
+}
+
+impl MultipartError {
+    /// Returns the status of the response rejecting the body.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            MultipartError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            MultipartError::Malformed(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::TooLarge(reason) | MultipartError::Malformed(reason) => {
+                f.write_str(reason)
+            }
+        }
+    }
+}
+
+/// Headers of a part.
+#[derive(Debug, Default, PartialEq)]
+pub struct PartHeaders {
+    /// Name of the form field.
+    pub name: Option<String>,
+    /// File name of a file field.
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    /// Before the first delimiter.
+    Preamble,
+    /// In the content of a part.
+    Content,
+    /// Right after a delimiter, the next part or the end follows.
+    Delimiter,
+    End,
+}
+
+/// Reader of a multipart body.
+pub struct Multipart<B> {
+    body: B,
+    /// `\r\n--<boundary>`, the body is read as if it started with `\r\n` so the first delimiter
+    /// is found like the others.
+    delimiter: Vec<u8>,
+    buffer: BytesMut,
+    body_ended: bool,
+    state: State,
+    limits: MultipartLimits,
+    total_size: u64,
+    parts: usize,
+    part_size: u64,
+}
+
+impl<B> Multipart<B>
+where
+    B: hyper::body::Body + Unpin,
+{
+    pub fn new(body: B, boundary: &str, limits: MultipartLimits) -> Self {
+        Self {
+            body,
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            buffer: BytesMut::from(&b"\r\n"[..]),
+            body_ended: false,
+            state: State::Preamble,
+            limits,
+            total_size: 0,
+            parts: 0,
+            part_size: 0,
+        }
+    }
+
+    /// Reads the body of a request with a `multipart/form-data` content type.
+    pub fn from_request(
+        request: Request<B>,
+        limits: MultipartLimits,
+    ) -> Result<Self, MultipartError> {
+        let boundary = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(boundary)
+            .ok_or(MultipartError::Malformed("missing multipart boundary"))?;
+        Ok(Self::new(request.into_body(), &boundary, limits))
+    }
+
+    /// Moves to the next part and returns its headers, or `None` after the last part.
+    ///
+    /// The rest of the current part is skipped.
+    pub async fn next_part(&mut self) -> Result<Option<PartHeaders>, MultipartError> {
+        while self.state == State::Content || self.state == State::Preamble {
+            while self.chunk().await?.is_some() {}
+        }
+        if self.state == State::End {
+            return Ok(None);
+        }
+
+        // `--` ends the body, otherwise the headers of the next part follow the line break
+        self.fill(2).await?;
+        if self.buffer.starts_with(b"--") {
+            self.state = State::End;
+            return Ok(None);
+        }
+        if !self.buffer.starts_with(b"\r\n") {
+            return Err(MultipartError::Malformed("invalid multipart delimiter"));
+        }
+
+        self.parts += 1;
+        if self.parts > self.limits.max_parts {
+            return Err(MultipartError::TooLarge("too many multipart parts"));
+        }
+
+        let headers_end = loop {
+            if let Some(end) = find(&self.buffer, b"\r\n\r\n") {
+                break end;
+            }
+            if self.buffer.len() > self.limits.max_header_size {
+                return Err(MultipartError::TooLarge("multipart headers too large"));
+            }
+            if !self.read().await? {
+                return Err(MultipartError::Malformed("unterminated multipart headers"));
+            }
+        };
+        if headers_end > self.limits.max_header_size {
+            return Err(MultipartError::TooLarge("multipart headers too large"));
+        }
+        // The line break of the delimiter is kept, so a part without headers ends them at once
+        let headers = parse_headers(&self.buffer[2.min(headers_end)..headers_end])?;
+        self.buffer.advance(headers_end + 4);
+        self.state = State::Content;
+        self.part_size = 0;
+        Ok(Some(headers))
+    }
+
+    /// Returns the next chunk of the content of the current part, or `None` at its end.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, MultipartError> {
+        if self.state != State::Content && self.state != State::Preamble {
+            return Ok(None);
+        }
+        loop {
+            if let Some(start) = find(&self.buffer, &self.delimiter) {
+                let content = self.buffer.split_to(start).freeze();
+                self.buffer.advance(self.delimiter.len());
+                let in_part = self.state == State::Content;
+                self.state = State::Delimiter;
+                if in_part && !content.is_empty() {
+                    self.count(content.len())?;
+                    return Ok(Some(content));
+                }
+                return Ok(None);
+            }
+
+            // The end of the buffer may be the start of a delimiter, it is kept for the next read
+            let safe = self.buffer.len().saturating_sub(self.delimiter.len() - 1);
+            if safe > 0 {
+                let content = self.buffer.split_to(safe).freeze();
+                if self.state == State::Content {
+                    self.count(content.len())?;
+                    return Ok(Some(content));
+                }
+                continue;
+            }
+            if !self.read().await? {
+                return Err(MultipartError::Malformed("unterminated multipart body"));
+            }
+        }
+    }
+
+    fn count(&mut self, len: usize) -> Result<(), MultipartError> {
+        self.part_size += len as u64;
+        if self.part_size > self.limits.max_part_size {
+            return Err(MultipartError::TooLarge("multipart part too large"));
+        }
+        Ok(())
+    }
+
+    /// Reads until the buffer holds `len` bytes.
+    async fn fill(&mut self, len: usize) -> Result<(), MultipartError> {
+        while self.buffer.len() < len {
+            if !self.read().await? {
+                return Err(MultipartError::Malformed("unterminated multipart body"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends the next data frame of the body to the buffer, returns false at the end.
+    async fn read(&mut self) -> Result<bool, MultipartError> {
+        while !self.body_ended {
+            let Some(frame) = self.body.frame().await else {
+                self.body_ended = true;
+                break;
+            };
+            let frame =
+                frame.map_err(|_| MultipartError::Malformed("failed to read request body"))?;
+            if let Ok(mut data) = frame.into_data() {
+                self.total_size += data.remaining() as u64;
+                if self.total_size > self.limits.max_total_size {
+                    return Err(MultipartError::TooLarge("multipart body too large"));
+                }
+                while data.has_remaining() {
+                    let chunk = data.chunk();
+                    let len = chunk.len();
+                    self.buffer.extend_from_slice(chunk);
+                    data.advance(len);
+                }
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Returns the boundary of a `multipart/form-data` content type.
+fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let mime = params.next()?.trim();
+    if !mime.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params.find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_string())
+            .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+    })
+}
+
+fn parse_headers(raw: &[u8]) -> Result<PartHeaders, MultipartError> {
+    let raw = std::str::from_utf8(raw)
+        .map_err(|_| MultipartError::Malformed("invalid multipart headers"))?;
+    let mut headers = PartHeaders::default();
+    for line in raw.split("\r\n").filter(|line| !line.is_empty()) {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or(MultipartError::Malformed("invalid multipart headers"))?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-disposition") {
+            for param in value.split(';').skip(1) {
+                let Some((key, param_value)) = param.split_once('=') else {
+                    continue;
+                };
+                let param_value = param_value.trim().trim_matches('"').to_string();
+                match key.trim().to_ascii_lowercase().as_str() {
+                    "name" => headers.name = Some(param_value),
+                    "filename" => headers.filename = Some(param_value),
+                    _ => {}
+                }
+            }
+        } else if name.eq_ignore_ascii_case("content-type") {
+            headers.content_type = Some(value.to_string());
+        }
+    }
+    Ok(headers)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use bytes::Bytes;
+    use claims::assert_err;
+    use futures_util::stream;
+    use http::{header, Request, StatusCode};
+    use http_body_util::StreamBody;
+    use hyper::body::Frame;
+    use rstest::rstest;
+
+    use super::{Multipart, MultipartError, MultipartLimits, PartHeaders};
+
+    type TestBody = StreamBody<stream::Iter<std::vec::IntoIter<Result<Frame<Bytes>, Infallible>>>>;
+
+    /// Body sending `content` in frames of `chunk_size` bytes.
+    fn body(content: &str, chunk_size: usize) -> TestBody {
+        let frames: Vec<_> = content
+            .as_bytes()
+            .chunks(chunk_size)
+            .map(|chunk| Ok(Frame::data(Bytes::copy_from_slice(chunk))))
+            .collect();
+        StreamBody::new(stream::iter(frames))
+    }
+
+    fn multipart(content: &str, chunk_size: usize, limits: MultipartLimits) -> Multipart<TestBody> {
+        Multipart::new(body(content, chunk_size), "XyZ", limits)
+    }
+
+    /// Reads every part, returning its headers and content.
+    async fn read_all(
+        multipart: &mut Multipart<TestBody>,
+    ) -> Result<Vec<(PartHeaders, String)>, MultipartError> {
+        let mut parts = vec![];
+        while let Some(headers) = multipart.next_part().await? {
+            let mut content = vec![];
+            while let Some(chunk) = multipart.chunk().await? {
+                content.extend_from_slice(&chunk);
+            }
+            parts.push((headers, String::from_utf8(content).unwrap()));
+        }
+        Ok(parts)
+    }
+
+    const FORM: &str = "preamble\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        Holiday\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"photo\"; filename=\"beach.txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        sand --XyZ--\r\n-XyZ\r\n--Xy\r\n\
+        --XyZ--\r\n\
+        epilogue";
+
+    #[tokio::test]
+    #[rstest]
+    #[case(1)]
+    #[case(3)]
+    #[case(7)]
+    #[case(FORM.len())]
+    async fn test_read_parts(#[case] chunk_size: usize) {
+        let mut multipart = multipart(FORM, chunk_size, MultipartLimits::default());
+
+        let parts = read_all(&mut multipart).await.unwrap();
+
+        assert_eq!(
+            parts,
+            vec![
+                (
+                    PartHeaders {
+                        name: Some("title".to_string()),
+                        filename: None,
+                        content_type: None,
+                    },
+                    "Holiday".to_string()
+                ),
+                (
+                    PartHeaders {
+                        name: Some("photo".to_string()),
+                        filename: Some("beach.txt".to_string()),
+                        content_type: Some("text/plain".to_string()),
+                    },
+                    // The boundary is a delimiter only after a line break
+                    "sand --XyZ--\r\n-XyZ\r\n--Xy".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unread_content_is_skipped() {
+        let mut multipart = multipart(FORM, 5, MultipartLimits::default());
+
+        let first = multipart.next_part().await.unwrap().unwrap();
+        let second = multipart.next_part().await.unwrap().unwrap();
+
+        assert_eq!(first.name.as_deref(), Some("title"));
+        assert_eq!(second.name.as_deref(), Some("photo"));
+        assert_eq!(multipart.next_part().await, Ok(None));
+    }
+
+    #[tokio::test]
+    #[rstest]
+    #[case(
+        "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nno final boundary",
+        MultipartError::Malformed("unterminated multipart body")
+    )]
+    #[case(
+        "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue\r\n--XyZ",
+        MultipartError::Malformed("unterminated multipart body")
+    )]
+    #[case(
+        "--XyZ\r\nContent-Disposition: form-data; name=\"a\"",
+        MultipartError::Malformed("unterminated multipart headers")
+    )]
+    #[case(
+        "--XyZ\r\nno colon\r\n\r\nvalue\r\n--XyZ--",
+        MultipartError::Malformed("invalid multipart headers")
+    )]
+    #[case(
+        "--XyZ garbage",
+        MultipartError::Malformed("invalid multipart delimiter")
+    )]
+    #[case(
+        "no delimiter at all",
+        MultipartError::Malformed("unterminated multipart body")
+    )]
+    async fn test_malformed_body(#[case] content: &str, #[case] expected: MultipartError) {
+        let mut multipart = multipart(content, 4, MultipartLimits::default());
+
+        let error = assert_err!(read_all(&mut multipart).await);
+
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error, expected);
+    }
+
+    #[tokio::test]
+    #[rstest]
+    #[case(
+        MultipartLimits { max_parts: 1, ..MultipartLimits::default() },
+        "too many multipart parts"
+    )]
+    #[case(
+        MultipartLimits { max_part_size: 10, ..MultipartLimits::default() },
+        "multipart part too large"
+    )]
+    #[case(
+        MultipartLimits { max_header_size: 40, ..MultipartLimits::default() },
+        "multipart headers too large"
+    )]
+    #[case(
+        MultipartLimits { max_total_size: 100, ..MultipartLimits::default() },
+        "multipart body too large"
+    )]
+    async fn test_limits(#[case] limits: MultipartLimits, #[case] reason: &'static str) {
+        let mut multipart = multipart(FORM, 3, limits);
+
+        let error = assert_err!(read_all(&mut multipart).await);
+
+        assert_eq!(error.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(error, MultipartError::TooLarge(reason));
+    }
+
+    #[rstest]
+    #[case("multipart/form-data; boundary=XyZ", true)]
+    #[case("Multipart/Form-Data; charset=utf-8; boundary=\"XyZ\"", true)]
+    #[case("multipart/form-data", false)]
+    #[case("application/json; boundary=XyZ", false)]
+    fn test_from_request_reads_boundary(#[case] content_type: &str, #[case] valid: bool) {
+        let request = Request::builder()
+            .header(header::CONTENT_TYPE, content_type)
+            .body(body(FORM, 8))
+            .unwrap();
+
+        let multipart = Multipart::from_request(request, MultipartLimits::default());
+
+        assert_eq!(multipart.is_ok(), valid);
+    }
+}
@@ -0,0 +1,103 @@
+//! # NotFoundHandler
+//!
+//! Answers the requests no virtual host or route matches. The page configured with
+//! `options { not_found { respond_file <path> [status] } }` is served as is, without it or when
+//! the page cannot be read the built-in `404` is rendered in the error format of the request.
+
+use chico_file::types::NotFoundPage;
+use http::{Response, StatusCode};
+use tracing::warn;
+
+use super::{dir::absolute_root, error, file::MIME_DICT, full, BoxBody, RequestHandler};
+use crate::handlers::UtilitiesResponses;
+
+#[derive(PartialEq, Debug, Default)]
+pub struct NotFoundHandler {
+    page: Option<NotFoundPage>,
+}
+
+impl NotFoundHandler {
+    pub fn new(page: Option<NotFoundPage>) -> Self {
+        Self { page }
+    }
+}
+
+impl RequestHandler for NotFoundHandler {
+    async fn handle<B>(&self, request: hyper::Request<B>) -> Response<BoxBody>
+    where
+        B: hyper::body::Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        if let Some(page) = &self.page {
+            match tokio::fs::read(absolute_root(&page.file)).await {
+                Ok(content) => {
+                    let mut builder = Response::builder()
+                        .status(StatusCode::from_u16(page.status).unwrap_or(StatusCode::NOT_FOUND));
+                    if let Some(content_type) = MIME_DICT.get_content_type(&page.file) {
+                        builder = builder.header(http::header::CONTENT_TYPE, content_type);
+                    }
+                    return builder.body(full(content)).unwrap();
+                }
+                Err(e) => warn!("could not read the not_found page {}: {}", page.file, e),
+            }
+        }
+        error::built_in(
+            UtilitiesResponses::not_found_respond_handler()
+                .handle(request)
+                .await,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use chico_file::types::NotFoundPage;
+    use http::{Request, StatusCode};
+    use tempfile::NamedTempFile;
+
+    use super::NotFoundHandler;
+    use crate::{
+        handlers::{error, RequestHandler},
+        testing::{collect_body, MockBody},
+    };
+
+    fn request() -> Request<MockBody> {
+        Request::builder()
+            .uri("http://localhost/missing")
+            .body(MockBody::new(b""))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_serves_configured_page_with_its_status() {
+        let mut page = NamedTempFile::with_suffix(".html").unwrap();
+        page.write_all(b"<h1>Gone</h1>").unwrap();
+        let handler = NotFoundHandler::new(Some(NotFoundPage {
+            file: page.path().to_str().unwrap().to_string(),
+            status: 410,
+        }));
+
+        let response = handler.handle(request()).await;
+
+        assert_eq!(response.status(), StatusCode::GONE);
+        assert_eq!(response.headers()[http::header::CONTENT_TYPE], "text/html");
+        assert!(!error::is_built_in(&response));
+        assert_eq!(collect_body(response).await, "<h1>Gone</h1>");
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_built_in_page_when_file_is_missing() {
+        let handler = NotFoundHandler::new(Some(NotFoundPage {
+            file: "/path/to/missing/404.html".to_string(),
+            status: 404,
+        }));
+
+        let response = handler.handle(request()).await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(error::is_built_in(&response));
+    }
+}
@@ -37,7 +37,7 @@ impl RequestHandler for RedirectHandler {
 mod tests {
     use http::{Request, StatusCode};
 
-    use crate::{handlers::RequestHandler, test_utils::MockBody};
+    use crate::{handlers::RequestHandler, testing::MockBody};
 
     use super::RedirectHandler;
 
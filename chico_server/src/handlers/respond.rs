@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use http::Response;
 
-use super::{full, RequestHandler};
+use super::{dir::absolute_root, file::MIME_DICT, full, RequestHandler};
 
 #[derive(PartialEq, Debug)]
 pub struct RespondHandler {
@@ -34,6 +34,21 @@ impl RespondHandler {
         }
     }
 
+    /// Responds with the content of `file`, read now, and the content type of its extension.
+    /// A relative path is resolved against the executable directory.
+    pub fn from_file(status: u16, file: &str) -> std::io::Result<RespondHandler> {
+        let body = std::fs::read_to_string(absolute_root(file))?;
+        let mut set_headers = HashMap::new();
+        if let Some(content_type) = MIME_DICT.get_content_type(file) {
+            set_headers.insert(http::header::CONTENT_TYPE.to_string(), content_type);
+        }
+        Ok(RespondHandler::with_headers(
+            status,
+            Some(body),
+            set_headers,
+        ))
+    }
+
     #[allow(dead_code)]
     pub fn ok() -> RespondHandler {
         RespondHandler::new(200, None)
@@ -121,10 +136,12 @@ impl RequestHandler for RespondHandler {
 #[cfg(test)]
 mod tests {
 
-    use crate::{handlers::RequestHandler, test_utils::MockBody};
+    use crate::{
+        handlers::RequestHandler,
+        testing::{collect_body, MockBody},
+    };
     use claims::assert_some;
     use http::{Request, StatusCode};
-    use http_body_util::BodyExt;
     use rstest::rstest;
     use std::collections::HashMap;
 
@@ -143,16 +160,7 @@ mod tests {
 
         assert_eq!(response.status(), StatusCode::OK);
 
-        let body = String::from_utf8(
-            response
-                .boxed()
-                .collect()
-                .await
-                .unwrap()
-                .to_bytes()
-                .to_vec(),
-        )
-        .unwrap();
+        let body = collect_body(response).await;
 
         assert_eq!(body, "");
     }
@@ -170,16 +178,7 @@ mod tests {
 
         assert_eq!(response.status(), StatusCode::FORBIDDEN);
 
-        let response_body = String::from_utf8(
-            response
-                .boxed()
-                .collect()
-                .await
-                .unwrap()
-                .to_bytes()
-                .to_vec(),
-        )
-        .unwrap();
+        let response_body = collect_body(response).await;
 
         assert_eq!(response_body, "Access denied");
     }
@@ -211,16 +210,7 @@ mod tests {
             "value-2"
         );
 
-        let response_body = String::from_utf8(
-            response
-                .boxed()
-                .collect()
-                .await
-                .unwrap()
-                .to_bytes()
-                .to_vec(),
-        )
-        .unwrap();
+        let response_body = collect_body(response).await;
 
         assert_eq!(response_body, "Everything is OK");
     }
@@ -315,4 +305,25 @@ mod tests {
             handler
         );
     }
+
+    #[tokio::test]
+    async fn test_respond_handler_from_file() {
+        let mut page = tempfile::NamedTempFile::with_suffix(".html").unwrap();
+        std::io::Write::write_all(&mut page, b"<h1>Back soon</h1>").unwrap();
+
+        let respond_handler =
+            RespondHandler::from_file(503, page.path().to_str().unwrap()).unwrap();
+        let response = respond_handler
+            .handle(Request::builder().body(MockBody::new(b"")).unwrap())
+            .await;
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[http::header::CONTENT_TYPE], "text/html");
+        assert_eq!(collect_body(response).await, "<h1>Back soon</h1>");
+    }
+
+    #[test]
+    fn test_respond_handler_from_missing_file() {
+        assert!(RespondHandler::from_file(503, "/path/to/missing/maintenance.html").is_err());
+    }
 }
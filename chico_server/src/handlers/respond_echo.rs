@@ -0,0 +1,164 @@
+//! # RespondEchoHandler
+//!
+//! Responds with the value of a query parameter, for `respond_echo <param>`, so synthetic
+//! monitors can check that a request went all the way through the routing: they send a token and
+//! expect it back.
+//!
+//! The value is reflected to whoever sends it, so it is made harmless first:
+//! - only the first [`MAX_ECHO_LEN`] characters are kept,
+//! - control characters are dropped and HTML is escaped,
+//! - the response is `text/plain` and must not be sniffed as anything else.
+
+use http::{header, Response, StatusCode};
+
+use super::{
+    browse::{escape_html, percent_decode},
+    error, full, BoxBody, RequestHandler,
+};
+
+/// Characters of the value echoed, the rest is cut.
+pub const MAX_ECHO_LEN: usize = 64;
+
+#[derive(PartialEq, Debug)]
+pub struct RespondEchoHandler {
+    param: String,
+}
+
+impl RespondEchoHandler {
+    pub fn new(param: String) -> Self {
+        Self { param }
+    }
+
+    /// Returns the decoded value of the parameter in `query`, the first one when it is repeated.
+    fn value(&self, query: &str) -> Option<String> {
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+            .find(|(name, _)| *name == self.param)
+            .map(|(_, value)| {
+                let value = value.replace('+', " ");
+                percent_decode(&value).unwrap_or(value)
+            })
+    }
+}
+
+impl RequestHandler for RespondEchoHandler {
+    async fn handle<B>(&self, request: hyper::Request<B>) -> Response<BoxBody>
+    where
+        B: hyper::body::Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let Some(value) = request.uri().query().and_then(|query| self.value(query)) else {
+            return error::built_in(
+                Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(full(format!(
+                        "400 Bad Request: missing query parameter {}",
+                        self.param
+                    )))
+                    .unwrap(),
+            );
+        };
+
+        let value: String = value
+            .chars()
+            .filter(|c| !c.is_control())
+            .take(MAX_ECHO_LEN)
+            .collect();
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(full(escape_html(&value)))
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{header, Request, StatusCode};
+    use rstest::rstest;
+
+    use crate::{
+        handlers::RequestHandler,
+        testing::{collect_body, MockBody},
+    };
+
+    use super::{RespondEchoHandler, MAX_ECHO_LEN};
+
+    async fn echo(uri: &str) -> (StatusCode, String) {
+        let handler = RespondEchoHandler::new("token".to_string());
+        let request = Request::builder()
+            .uri(uri)
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handler.handle(request).await;
+        (response.status(), collect_body(response).await)
+    }
+
+    #[rstest]
+    #[case("/health?token=abc123", "abc123")]
+    #[case("/health?page=2&token=abc123&token=other", "abc123")]
+    #[case("/health?token=hello+world%21", "hello world!")]
+    #[case("/health?token=", "")]
+    #[case("/health?token", "")]
+    #[case("/health?token=%E2%9C%93", "✓")]
+    #[case("/health?token=%ZZ", "%ZZ")]
+    #[case(
+        "/health?token=%3Cscript%3Ealert(%27x%27)%3C/script%3E",
+        "&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;"
+    )]
+    #[case("/health?token=a%0D%0ASet-Cookie:%20x", "aSet-Cookie: x")]
+    #[tokio::test]
+    async fn test_respond_echo_handler_echoes_token(#[case] uri: &str, #[case] expected: &str) {
+        let (status, body) = echo(uri).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, expected);
+    }
+
+    #[tokio::test]
+    async fn test_respond_echo_handler_truncates_oversized_token() {
+        let token = "x".repeat(10 * MAX_ECHO_LEN);
+
+        let (status, body) = echo(&format!("/health?token={token}")).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "x".repeat(MAX_ECHO_LEN));
+    }
+
+    #[tokio::test]
+    async fn test_respond_echo_handler_responds_plain_text() {
+        let handler = RespondEchoHandler::new("token".to_string());
+        let request = Request::builder()
+            .uri("/health?token=abc")
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = handler.handle(request).await;
+
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            response.headers()[header::X_CONTENT_TYPE_OPTIONS],
+            "nosniff"
+        );
+    }
+
+    #[rstest]
+    #[case("/health")]
+    #[case("/health?other=abc")]
+    #[case("/health?tokens=abc")]
+    #[tokio::test]
+    async fn test_respond_echo_handler_without_token(#[case] uri: &str) {
+        let (status, body) = echo(uri).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, "400 Bad Request: missing query parameter token");
+    }
+}
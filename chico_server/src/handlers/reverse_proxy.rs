@@ -1,55 +1,209 @@
-use std::{sync::Arc, time::Duration};
+use std::{future::Future, net::IpAddr, sync::Arc, time::Duration};
 
-use http::{HeaderValue, Uri};
+use chico_file::types::HostHeader;
+use http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Uri, Version};
 use http_body_util::BodyExt;
-use hyper::{Request, Response};
+use hyper::{body::Incoming, upgrade::OnUpgrade, Request, Response};
 use hyper_util::rt::TokioIo;
-use tokio::net::TcpStream;
+use tokio::{net::TcpStream, sync::mpsc, time::Instant};
 use tracing::{debug, error, info_span};
 
 use crate::{
-    handlers::{respond::RespondHandler, BoxBody, RequestHandler},
-    load_balance::node::Node,
+    client::{
+        ClientInfo, PeerAddr, TlsConnection, X_FORWARDED_FOR, X_FORWARDED_HOST, X_FORWARDED_PROTO,
+    },
+    handlers::{error, full, respond::RespondHandler, BoxBody, RequestHandler},
+    interim::InterimResponses,
+    load_balance::{
+        canary::Canary, circuit_breaker::CircuitBreaker, dns::DnsCache, health::PassiveHealth,
+        least_connections::InFlight, node::Node, LoadBalance,
+    },
+    middlewares::header::HeaderRules,
+    server::Tunnels,
 };
 
 pub struct ReverseProxyHandler {
     load_balancer: Box<dyn crate::load_balance::LoadBalance>,
     request_timeout: Duration,
     connection_timeout: Duration,
+    dns_cache: DnsCache,
+    canary: Option<Canary>,
+    /// Boxed, the handler is a variant of the route's handler plan.
+    passive_health: Option<Box<PassiveHealth>>,
+    circuit_breaker: Option<Box<CircuitBreaker>>,
+    /// Adds the `X-Forwarded-*` headers to the requests sent upstream.
+    forwarded: bool,
+    host_header: HostHeader,
+    strip_request_headers: Box<[HeaderName]>,
+    header_up: Option<Box<HeaderRules>>,
+    header_down: Option<Box<HeaderRules>>,
+}
+
+/// Why no upstream could be picked for a request.
+enum Unavailable {
+    /// All upstreams are out of rotation.
+    Unhealthy,
+    /// The circuit of the remaining upstreams is open.
+    CircuitOpen,
 }
 
 #[allow(dead_code)]
 impl ReverseProxyHandler {
-    const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
-    const DEFAULT_CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+    pub(crate) const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+    pub(crate) const DEFAULT_CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
 
     pub fn new(load_balancer: Box<dyn crate::load_balance::LoadBalance>) -> Self {
         Self {
             load_balancer,
             request_timeout: ReverseProxyHandler::DEFAULT_REQUEST_TIMEOUT,
             connection_timeout: ReverseProxyHandler::DEFAULT_CONNECTION_TIMEOUT,
+            dns_cache: DnsCache::new(None),
+            canary: None,
+            passive_health: None,
+            circuit_breaker: None,
+            forwarded: true,
+            host_header: HostHeader::Upstream,
+            strip_request_headers: Box::new([]),
+            header_up: None,
+            header_down: None,
         }
     }
 
     pub fn with_timeouts(
         load_balancer: Box<dyn crate::load_balance::LoadBalance>,
-        request_timeout: Option<u64>,
-        connection_timeout: Option<u64>,
+        request_timeout: Option<Duration>,
+        connection_timeout: Option<Duration>,
     ) -> Self {
         Self {
             load_balancer,
             request_timeout: request_timeout
-                .map(Duration::from_secs)
                 .unwrap_or(ReverseProxyHandler::DEFAULT_REQUEST_TIMEOUT),
             connection_timeout: connection_timeout
-                .map(Duration::from_secs)
                 .unwrap_or(ReverseProxyHandler::DEFAULT_CONNECTION_TIMEOUT),
+            dns_cache: DnsCache::new(None),
+            canary: None,
+            passive_health: None,
+            circuit_breaker: None,
+            forwarded: true,
+            host_header: HostHeader::Upstream,
+            strip_request_headers: Box::new([]),
+            header_up: None,
+            header_down: None,
         }
     }
 
-    fn get_node(&self) -> Option<Arc<Node>> {
+    /// Replaces the cache used to resolve upstream hostnames.
+    pub fn with_dns_cache(mut self, dns_cache: DnsCache) -> Self {
+        self.dns_cache = dns_cache;
+        self
+    }
+
+    /// Sends the requests matching the canary to its upstreams.
+    pub fn with_canary(mut self, canary: Canary) -> Self {
+        self.canary = Some(canary);
+        self
+    }
+
+    /// Takes the upstreams failing repeatedly out of rotation.
+    pub fn with_passive_health(mut self, passive_health: PassiveHealth) -> Self {
+        self.passive_health = Some(Box::new(passive_health));
+        self
+    }
+
+    /// Fails the requests fast while their upstream errs or slows down.
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(Box::new(circuit_breaker));
+        self
+    }
+
+    /// Adds the `X-Forwarded-*` headers to the requests sent upstream, on by default.
+    pub fn with_forwarded(mut self, forwarded: bool) -> Self {
+        self.forwarded = forwarded;
+        self
+    }
+
+    /// Sets the Host header sent upstream, the `host:port` of the upstream by default.
+    pub fn with_host_header(mut self, host_header: HostHeader) -> Self {
+        self.host_header = host_header;
+        self
+    }
+
+    /// Removes the headers from the client requests before they are sent upstream.
+    pub fn with_strip_request_headers(mut self, headers: Vec<HeaderName>) -> Self {
+        self.strip_request_headers = headers.into_boxed_slice();
+        self
+    }
+
+    /// Changes the headers of the requests sent upstream, after the headers chico adds.
+    pub fn with_header_up(mut self, rules: HeaderRules) -> Self {
+        self.header_up = Some(Box::new(rules));
+        self
+    }
+
+    /// Changes the headers of the responses of the upstreams.
+    pub fn with_header_down(mut self, rules: HeaderRules) -> Self {
+        self.header_down = Some(Box::new(rules));
+        self
+    }
+
+    pub fn get_node(&self) -> Option<Arc<Node>> {
         self.load_balancer.get_node()
     }
+
+    /// Picks the upstream of the request. The returned guard, if any, counts the request as in
+    /// flight to that upstream until it is dropped.
+    ///
+    /// Upstreams out of rotation or with an open circuit are skipped.
+    fn select_node(
+        &self,
+        headers: &HeaderMap,
+        client: Option<IpAddr>,
+    ) -> Result<(Arc<Node>, Option<InFlight>), Unavailable> {
+        let load_balancer: &dyn LoadBalance = match &self.canary {
+            Some(canary) if canary.matches(headers) => {
+                debug!("request matched the canary");
+                canary.load_balancer()
+            }
+            _ => self.load_balancer.as_ref(),
+        };
+        if self.passive_health.is_none() && self.circuit_breaker.is_none() {
+            return load_balancer
+                .acquire_node_for(client, 0)
+                .ok_or(Unavailable::Unhealthy);
+        }
+        let mut circuit_open = false;
+        (0..load_balancer.node_count())
+            .filter_map(|attempt| load_balancer.acquire_node_for(client, attempt))
+            .filter(|(node, _)| {
+                self.passive_health
+                    .as_ref()
+                    .is_none_or(|health| health.is_available(node))
+            })
+            .find(|(node, _)| match &self.circuit_breaker {
+                Some(breaker) if !breaker.allows(node) => {
+                    circuit_open = true;
+                    false
+                }
+                _ => true,
+            })
+            .ok_or(match circuit_open {
+                true => Unavailable::CircuitOpen,
+                false => Unavailable::Unhealthy,
+            })
+    }
+
+    /// Counts the outcome of a request started at `started` for the passive health checks and
+    /// the circuit breaker.
+    fn record_outcome(&self, node: &Node, success: bool, started: Instant) {
+        match &self.passive_health {
+            Some(health) if success => health.record_success(node),
+            Some(health) => health.record_failure(node),
+            None => {}
+        }
+        if let Some(breaker) = &self.circuit_breaker {
+            breaker.record(node, success, started.elapsed());
+        }
+    }
 }
 
 impl RequestHandler for ReverseProxyHandler {
@@ -62,36 +216,81 @@ impl RequestHandler for ReverseProxyHandler {
         let span = info_span!("my_span");
         let _guard = span.enter();
         debug!("start connect to upstream");
-        let upstream = self.get_node().unwrap();
-        let host_and_port = upstream.addr;
+        let (upstream, in_flight) = match self.select_node(request.headers(), client_ip(&request)) {
+            Ok(selected) => selected,
+            Err(Unavailable::Unhealthy) => {
+                error!("no upstream server available, all of them are out of rotation");
+                return error::built_in(
+                    RespondHandler::bad_gateway_with_body(
+                        "502 Bad Gateway - no healthy upstream server.".to_string(),
+                    )
+                    .handle(request)
+                    .await,
+                );
+            }
+            Err(Unavailable::CircuitOpen) => {
+                debug!("circuit of the upstream servers is open, failing fast");
+                return service_unavailable_response(
+                    "503 Service Unavailable - upstream circuit is open.".to_string(),
+                );
+            }
+        };
+        let started = Instant::now();
+        let host_and_port = upstream.as_ref();
+
+        let addrs = match self.dns_cache.lookup(host_and_port).await {
+            Ok(addrs) => addrs,
+            Err(err) => {
+                self.record_outcome(host_and_port, false, started);
+                error!("could not resolve upstream server. Given upstream : {upstream} - Error : {error}", upstream = host_and_port, error = err);
+                return error::built_in(
+                    RespondHandler::bad_gateway_with_body(
+                        "502 Bad Gateway - could not resolve upstream server.".to_string(),
+                    )
+                    .handle(request)
+                    .await,
+                );
+            }
+        };
 
         // Apply connection timeout
         let connect_result =
-            tokio::time::timeout(self.connection_timeout, TcpStream::connect(host_and_port)).await;
+            tokio::time::timeout(self.connection_timeout, TcpStream::connect(&addrs[..])).await;
 
         let client_stream = match connect_result {
             Ok(Ok(stream)) => stream,
             Ok(Err(err)) => {
+                self.record_outcome(host_and_port, false, started);
                 error!("could not connect to upstream server. Given upstream : {upstream} - Error : {error}" , upstream  = host_and_port, error= err);
-                return RespondHandler::bad_gateway_with_body(
-                    "502 Bad Gateway - could not connect to upstream server.".to_string(),
-                )
-                .handle(request)
-                .await;
+                return error::built_in(
+                    RespondHandler::bad_gateway_with_body(
+                        "502 Bad Gateway - could not connect to upstream server.".to_string(),
+                    )
+                    .handle(request)
+                    .await,
+                );
             }
             Err(_) => {
+                self.record_outcome(host_and_port, false, started);
                 error!(
                     "Connection timeout while connecting to upstream server: {}",
                     host_and_port
                 );
-                return RespondHandler::bad_gateway_with_body(
-                    "502 Bad Gateway - connection timeout to upstream server.".to_string(),
-                )
-                .handle(request)
-                .await;
+                return error::built_in(
+                    RespondHandler::bad_gateway_with_body(
+                        "502 Bad Gateway - connection timeout to upstream server.".to_string(),
+                    )
+                    .handle(request)
+                    .await,
+                );
             }
         };
         debug!("connected to upstream");
+        // SRV records name their targets, the request goes to the one connected to
+        let authority = match client_stream.peer_addr() {
+            Ok(peer) if upstream.srv => peer.to_string(),
+            _ => host_and_port.to_string(),
+        };
 
         let io = TokioIo::new(client_stream);
 
@@ -100,19 +299,22 @@ impl RequestHandler for ReverseProxyHandler {
         let (mut sender, conn) = match handshake_result {
             Ok(result) => result,
             Err(err) => {
+                self.record_outcome(host_and_port, false, started);
                 error!("Handshake with upstream server failed: {:?}", err);
-                return RespondHandler::bad_gateway_with_body(
-                    "502 Bad Gateway - handshake with upstream server failed.".to_string(),
-                )
-                .handle(request)
-                .await;
+                return error::built_in(
+                    RespondHandler::bad_gateway_with_body(
+                        "502 Bad Gateway - handshake with upstream server failed.".to_string(),
+                    )
+                    .handle(request)
+                    .await,
+                );
             }
         };
         debug!("handshake-ed to upstream");
 
         tokio::task::spawn(async move {
             debug!("waiting for the connection");
-            if let Err(err) = conn.await {
+            if let Err(err) = conn.with_upgrades().await {
                 error!("Connection failed: {:?}", err);
             }
             debug!("connection complated");
@@ -125,31 +327,80 @@ impl RequestHandler for ReverseProxyHandler {
             .map(|x| x.as_str())
             .unwrap_or("/");
 
-        let uri_string = format!("{scheme}://{host_and_port}{path_and_query}");
+        let uri_string = format!("{scheme}://{authority}{path_and_query}");
 
         let mut request = request;
+        for name in self.strip_request_headers.iter() {
+            request.headers_mut().remove(name);
+        }
+        if self.forwarded {
+            add_forwarding_headers(&mut request);
+        }
         let uri = uri_string.parse::<Uri>().unwrap();
-        let host_header = format!("{}:{}", &uri.host().unwrap(), &uri.port().unwrap());
-        request.headers_mut().insert(
-            http::header::HOST,
-            HeaderValue::from_str(host_header.as_str()).unwrap(),
-        );
+        let host_header = match &self.host_header {
+            HostHeader::Upstream => None,
+            HostHeader::Preserve => request_host(&request),
+            // the value is checked by the config validation
+            HostHeader::Value(host) => HeaderValue::from_str(host).ok(),
+        }
+        .unwrap_or_else(|| {
+            let upstream_host = format!("{}:{}", &uri.host().unwrap(), &uri.port().unwrap());
+            HeaderValue::from_str(upstream_host.as_str()).unwrap()
+        });
+        request
+            .headers_mut()
+            .insert(http::header::HOST, host_header);
+        if let Some(header_up) = &self.header_up {
+            header_up.apply_in_order(request.headers_mut());
+        }
         *request.uri_mut() = uri;
 
+        // The client connection is handed over once the upstream switched protocols
+        let client_upgrade =
+            is_upgrade(request.headers()).then(|| hyper::upgrade::on(&mut request));
+        let tunnels = request.extensions().get::<Tunnels>().cloned();
+
+        // Early hints are relayed to HTTP/1.1 clients as they arrive, merged into the final
+        // response otherwise
+        let interim = request
+            .extensions()
+            .get::<InterimResponses>()
+            .filter(|_| request.version() == Version::HTTP_11)
+            .cloned();
+        let (hints_tx, mut hints_rx) = mpsc::unbounded_channel();
+        hyper::ext::on_informational(&mut request, move |response| {
+            let mut links = HeaderMap::new();
+            collect_early_hint_links(response.status(), response.headers(), &mut links);
+            if !links.is_empty() {
+                let _ = hints_tx.send(links);
+            }
+        });
+        let mut early_hints = HeaderMap::new();
+
         debug!("start sending request");
 
-        let timeout_result =
-            tokio::time::timeout(self.request_timeout, sender.send_request(request)).await;
+        let timeout_result = tokio::time::timeout(
+            self.request_timeout,
+            relay_early_hints(
+                sender.send_request(request),
+                &mut hints_rx,
+                interim.as_ref(),
+                &mut early_hints,
+            ),
+        )
+        .await;
 
         let response = match timeout_result {
             Ok(Ok(response)) => response,
             Ok(Err(err)) => {
+                self.record_outcome(&upstream, false, started);
                 error!("Error sending request to upstream: {:?}", err);
                 return bad_gateway_response(
                     "502 Bad Gateway - error sending request.".to_string(),
                 );
             }
             Err(_) => {
+                self.record_outcome(&upstream, false, started);
                 error!("Timeout while sending request to upstream.");
                 return gateway_timeout_response(
                     "504 Gateway Timeout - upstream did not respond in time.".to_string(),
@@ -158,26 +409,928 @@ impl RequestHandler for ReverseProxyHandler {
         };
 
         debug!("request sent");
+        self.record_outcome(&upstream, !response.status().is_server_error(), started);
+
+        if let (StatusCode::SWITCHING_PROTOCOLS, Some(client_upgrade)) =
+            (response.status(), client_upgrade)
+        {
+            return switch_protocols(response, client_upgrade, in_flight, tunnels);
+        }
         debug!("start converting response");
 
-        let (parts, body) = response.into_parts();
-        let boxed_body = body.map_err(std::io::Error::other).boxed();
+        // A body without Content-Length or chunked encoding ends when the upstream closes the
+        // connection. hyper reports that EOF as the end of the body, so the client response is
+        // finalized instead of waiting for more bytes.
+        let (mut parts, body) = response.into_parts();
+        merge_early_hint_links(&mut parts.headers, &early_hints);
+        if let Some(header_down) = &self.header_down {
+            header_down.apply_in_order(&mut parts.headers);
+        }
+        // the request stays in flight until the upstream's body is fully relayed
+        let boxed_body = body
+            .map_err(std::io::Error::other)
+            .map_frame(move |frame| {
+                let _ = &in_flight;
+                frame
+            })
+            .boxed();
         debug!("response boxed");
 
         Response::from_parts(parts, boxed_body)
     }
 }
 
+/// Whether the request asks to upgrade the connection to another protocol, like WebSocket, with
+/// `Connection: Upgrade` and an `Upgrade` header.
+fn is_upgrade(headers: &HeaderMap) -> bool {
+    headers.contains_key(http::header::UPGRADE)
+        && headers
+            .get_all(http::header::CONNECTION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|option| option.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+/// Relays the `101 Switching Protocols` of the upstream to the client, then copies the bytes of
+/// both upgraded connections to each other until one side closes.
+///
+/// The request stays in flight as long as the connections are open. The tunnel is tracked by the
+/// `tunnels` of the listener, which close it when `websocket_drain` elapsed on shutdown.
+fn switch_protocols(
+    mut response: Response<Incoming>,
+    client_upgrade: OnUpgrade,
+    in_flight: Option<InFlight>,
+    tunnels: Option<Tunnels>,
+) -> Response<BoxBody> {
+    let upstream_upgrade = hyper::upgrade::on(&mut response);
+    let tunnel = tunnels.map(|tunnels| tunnels.watch());
+    tokio::task::spawn(async move {
+        let _in_flight = in_flight;
+        let (client, upstream) = match tokio::try_join!(client_upgrade, upstream_upgrade) {
+            Ok(upgraded) => upgraded,
+            Err(err) => {
+                error!("Upgrade of the connection failed: {:?}", err);
+                return;
+            }
+        };
+        let mut client = TokioIo::new(client);
+        let mut upstream = TokioIo::new(upstream);
+        let copy = tokio::io::copy_bidirectional(&mut client, &mut upstream);
+        let result = match tunnel {
+            Some(mut tunnel) => {
+                tokio::select! {
+                    result = copy => result,
+                    _ = tunnel.closing() => {
+                        debug!("upgraded connection closed on shutdown");
+                        return;
+                    }
+                }
+            }
+            None => copy.await,
+        };
+        match result {
+            Ok((sent, received)) => debug!(
+                "upgraded connection closed, {} bytes sent upstream, {} bytes received",
+                sent, received
+            ),
+            Err(err) => debug!("upgraded connection closed: {}", err),
+        }
+    });
+
+    let (parts, _) = response.into_parts();
+    Response::from_parts(parts, full(""))
+}
+
+/// Address of the client of the request, resolved through the trusted proxies when the virtual
+/// host has some.
+fn client_ip<B>(request: &Request<B>) -> Option<IpAddr> {
+    match request.extensions().get::<ClientInfo>() {
+        Some(client) => Some(client.ip),
+        None => request
+            .extensions()
+            .get::<PeerAddr>()
+            .map(|PeerAddr(peer)| peer.ip()),
+    }
+}
+
+/// Tells the upstream about the original request: the TCP peer is appended to the
+/// `X-Forwarded-For` chain sent by the client, `X-Forwarded-Proto` and `X-Forwarded-Host` are
+/// set from the listener and the host the request was received for.
+fn add_forwarding_headers<B>(request: &mut Request<B>) {
+    let peer = request.extensions().get::<PeerAddr>().copied();
+    let proto = match request.extensions().get::<TlsConnection>() {
+        Some(_) => "https",
+        None => "http",
+    };
+    let host = request_host(request);
+    let headers = request.headers_mut();
+
+    if let Some(PeerAddr(peer)) = peer {
+        let chain = headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .chain([peer.ip().to_string().as_str()])
+            .collect::<Vec<_>>()
+            .join(", ");
+        if let Ok(chain) = HeaderValue::from_str(&chain) {
+            headers.insert(X_FORWARDED_FOR, chain);
+        }
+    }
+    headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static(proto));
+    match host {
+        Some(host) => headers.insert(X_FORWARDED_HOST, host),
+        None => headers.remove(X_FORWARDED_HOST),
+    };
+}
+
+/// Returns the host the request was received for.
+fn request_host<B>(request: &Request<B>) -> Option<HeaderValue> {
+    // HTTP/2 requests carry their host in the authority of the URI
+    request
+        .headers()
+        .get(http::header::HOST)
+        .cloned()
+        .or_else(|| {
+            let authority = request.uri().authority()?;
+            HeaderValue::from_str(authority.as_str()).ok()
+        })
+}
+
+/// Keeps the `Link` headers of `103 Early Hints` responses sent by the upstream.
+///
+/// Other informational responses are not meaningful to the client and are dropped.
+fn collect_early_hint_links(status: StatusCode, headers: &HeaderMap, early_hints: &mut HeaderMap) {
+    if status.as_u16() != 103 {
+        debug!("ignoring informational response {} from upstream", status);
+        return;
+    }
+
+    debug!("received 103 Early Hints from upstream");
+    for link in headers.get_all(http::header::LINK) {
+        early_hints.append(http::header::LINK, link.clone());
+    }
+}
+
+/// Waits for the upstream's response, sending the early hints received meanwhile to the client as
+/// `103 Early Hints`.
+///
+/// The links of the hints that could not be sent are kept in `unsent`, to be merged into the
+/// final response.
+async fn relay_early_hints<F: Future>(
+    response: F,
+    hints: &mut mpsc::UnboundedReceiver<HeaderMap>,
+    interim: Option<&InterimResponses>,
+    unsent: &mut HeaderMap,
+) -> F::Output {
+    tokio::pin!(response);
+    loop {
+        tokio::select! {
+            // the hints are passed on before the final response that follows them
+            biased;
+            Some(links) = hints.recv() => {
+                let sent = interim
+                    .is_some_and(|interim| interim.send_early_hints(&links));
+                if !sent {
+                    for link in links.get_all(http::header::LINK) {
+                        unsent.append(http::header::LINK, link.clone());
+                    }
+                }
+            }
+            output = &mut response => return output,
+        }
+    }
+}
+
+/// Merges the `Link` headers of early hints the client did not receive into the final response.
+///
+/// HTTP/1.0 clients cannot receive informational responses, nor can a connection still writing
+/// an earlier response. Links the upstream repeated in the final response are not added twice.
+fn merge_early_hint_links(headers: &mut HeaderMap, early_hints: &HeaderMap) {
+    for link in early_hints.get_all(http::header::LINK) {
+        let already_sent = headers
+            .get_all(http::header::LINK)
+            .iter()
+            .any(|value| value == link);
+        if !already_sent {
+            headers.append(http::header::LINK, link.clone());
+        }
+    }
+}
+
 fn bad_gateway_response(body: String) -> Response<BoxBody> {
-    http::Response::builder()
-        .status(502)
-        .body(crate::handlers::full(body))
-        .unwrap()
+    error::built_in(
+        http::Response::builder()
+            .status(502)
+            .body(crate::handlers::full(body))
+            .unwrap(),
+    )
 }
 
 fn gateway_timeout_response(body: String) -> Response<BoxBody> {
-    http::Response::builder()
-        .status(504)
-        .body(crate::handlers::full(body))
-        .unwrap()
+    error::built_in(
+        http::Response::builder()
+            .status(504)
+            .body(crate::handlers::full(body))
+            .unwrap(),
+    )
+}
+
+fn service_unavailable_response(body: String) -> Response<BoxBody> {
+    error::built_in(
+        http::Response::builder()
+            .status(503)
+            .body(crate::handlers::full(body))
+            .unwrap(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, SocketAddr},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use chico_file::types::HostHeader;
+    use http::{HeaderName, Request, StatusCode};
+    use rstest::rstest;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpSocket},
+    };
+
+    use super::{add_forwarding_headers, is_upgrade};
+    use crate::{
+        client::{PeerAddr, TlsConnection},
+        handlers::RequestHandler,
+        load_balance::{
+            canary::Canary,
+            circuit_breaker::{CircuitBreaker, MIN_REQUESTS},
+            health::PassiveHealth,
+            ip_hash::IpHashBalancer,
+            least_connections::LeastConnectionsBalancer,
+            node::Node,
+            round_robin::RoundRobinBalancer,
+            LoadBalance, SingleUpstream,
+        },
+        testing::{collect_body, MockBody},
+    };
+
+    use super::ReverseProxyHandler;
+
+    /// Starts an upstream that answers a single request with the given raw HTTP bytes.
+    async fn start_raw_upstream(raw_response: &'static [u8]) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf).await.unwrap();
+            stream.write_all(raw_response).await.unwrap();
+            stream.shutdown().await.unwrap();
+        });
+
+        port
+    }
+
+    fn proxy_to(port: u16) -> ReverseProxyHandler {
+        let node = format!("127.0.0.1:{port}").parse().unwrap();
+        ReverseProxyHandler::new(Box::new(SingleUpstream::new(node)))
+    }
+
+    #[tokio::test]
+    async fn test_reverse_proxy_merges_early_hint_links_into_final_response() {
+        let port = start_raw_upstream(
+            b"HTTP/1.1 103 Early Hints\r\n\
+Link: </style.css>; rel=preload; as=style\r\n\
+\r\n\
+HTTP/1.1 200 OK\r\n\
+Content-Length: 5\r\n\
+\r\n\
+Hello",
+        )
+        .await;
+
+        let request = Request::builder()
+            .uri("http://localhost/")
+            .body(MockBody::new(b""))
+            .unwrap();
+        let response = proxy_to(port).handle(request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(http::header::LINK).unwrap(),
+            "</style.css>; rel=preload; as=style"
+        );
+        assert_eq!(collect_body(response).await, "Hello");
+    }
+
+    #[tokio::test]
+    async fn test_reverse_proxy_does_not_duplicate_repeated_early_hint_links() {
+        let port = start_raw_upstream(
+            b"HTTP/1.1 103 Early Hints\r\n\
+Link: </app.js>; rel=preload; as=script\r\n\
+\r\n\
+HTTP/1.1 200 OK\r\n\
+Link: </app.js>; rel=preload; as=script\r\n\
+Content-Length: 0\r\n\
+\r\n",
+        )
+        .await;
+
+        let request = Request::builder()
+            .uri("http://localhost/")
+            .body(MockBody::new(b""))
+            .unwrap();
+        let response = proxy_to(port).handle(request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response
+                .headers()
+                .get_all(http::header::LINK)
+                .iter()
+                .count(),
+            1
+        );
+    }
+
+    #[rstest]
+    #[case(b"HTTP/1.1 200 OK\r\n\r\n", "")]
+    #[case(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n", "")]
+    #[case(b"HTTP/1.1 200 OK\r\n\r\nuntil close", "until close")]
+    #[tokio::test]
+    async fn test_reverse_proxy_relays_response_ended_by_upstream_close(
+        #[case] raw_response: &'static [u8],
+        #[case] expected_body: &str,
+    ) {
+        let port = start_raw_upstream(raw_response).await;
+
+        let request = Request::builder()
+            .uri("http://localhost/")
+            .body(MockBody::new(b""))
+            .unwrap();
+        let response = proxy_to(port).handle(request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = tokio::time::timeout(Duration::from_secs(5), collect_body(response))
+            .await
+            .expect("the body should end when the upstream closes the connection");
+        assert_eq!(body, expected_body);
+    }
+
+    #[rstest]
+    #[case::unreachable(None)]
+    #[case::bad_gateway(Some(&b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n"[..]))]
+    #[tokio::test]
+    async fn test_reverse_proxy_least_conn_releases_failed_requests(
+        #[case] raw_response: Option<&'static [u8]>,
+    ) {
+        let port = match raw_response {
+            Some(raw_response) => start_raw_upstream(raw_response).await,
+            // nothing listens there
+            None => 1,
+        };
+        let nodes = vec![format!("127.0.0.1:{port}").parse().unwrap()];
+        let active: Arc<[AtomicUsize]> = Arc::new([AtomicUsize::new(0)]);
+        let balancer = LeastConnectionsBalancer::with_state(
+            nodes,
+            active.clone(),
+            Arc::new(AtomicUsize::new(0)),
+        );
+        let handler = ReverseProxyHandler::new(Box::new(balancer));
+
+        let request = Request::builder()
+            .uri("http://localhost/")
+            .body(MockBody::new(b""))
+            .unwrap();
+        let response = handler.handle(request).await;
+
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        collect_body(response).await;
+        assert_eq!(active[0].load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_reverse_proxy_least_conn_counts_request_until_body_is_sent() {
+        let port = start_raw_upstream(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello").await;
+        let nodes = vec![format!("127.0.0.1:{port}").parse().unwrap()];
+        let active: Arc<[AtomicUsize]> = Arc::new([AtomicUsize::new(0)]);
+        let balancer = LeastConnectionsBalancer::with_state(
+            nodes,
+            active.clone(),
+            Arc::new(AtomicUsize::new(0)),
+        );
+        let handler = ReverseProxyHandler::new(Box::new(balancer));
+
+        let request = Request::builder()
+            .uri("http://localhost/")
+            .body(MockBody::new(b""))
+            .unwrap();
+        let response = handler.handle(request).await;
+
+        assert_eq!(active[0].load(Ordering::Relaxed), 1);
+        assert_eq!(collect_body(response).await, "Hello");
+        assert_eq!(active[0].load(Ordering::Relaxed), 0);
+    }
+
+    /// Starts an upstream answering each request with its name after `delay`.
+    async fn start_named_upstream(name: &'static str, delay: Duration) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buf = [0; 1024];
+                    let _ = stream.read(&mut buf).await.unwrap();
+                    tokio::time::sleep(delay).await;
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{name}",
+                        name.len()
+                    );
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                    stream.shutdown().await.unwrap();
+                });
+            }
+        });
+
+        port
+    }
+
+    #[tokio::test]
+    async fn test_reverse_proxy_least_conn_spreads_requests_away_from_slow_upstream() {
+        let slow = start_named_upstream("slow", Duration::from_millis(500)).await;
+        let fast = start_named_upstream("fast", Duration::ZERO).await;
+        let nodes = [slow, fast]
+            .iter()
+            .map(|port| format!("127.0.0.1:{port}").parse().unwrap())
+            .collect();
+        let handler = Arc::new(ReverseProxyHandler::new(Box::new(
+            LeastConnectionsBalancer::new(nodes),
+        )));
+
+        // requests keep arriving while the slow upstream holds on to its first one
+        let mut requests = vec![];
+        for _ in 0..10 {
+            let handler = handler.clone();
+            requests.push(tokio::spawn(async move {
+                let request = Request::builder()
+                    .uri("http://localhost/")
+                    .body(MockBody::new(b""))
+                    .unwrap();
+                collect_body(handler.handle(request).await).await
+            }));
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let mut served_by_fast = 0;
+        for request in requests {
+            if request.await.unwrap() == "fast" {
+                served_by_fast += 1;
+            }
+        }
+
+        // round-robin would have sent half of them to the slow upstream
+        assert!(served_by_fast >= 8, "{served_by_fast} of 10 served by fast");
+    }
+
+    async fn send(handler: &ReverseProxyHandler, canary_header: Option<&str>) -> String {
+        let mut request = Request::builder().uri("http://localhost/");
+        if let Some(value) = canary_header {
+            request = request.header("x-canary", value);
+        }
+        let response = handler
+            .handle(request.body(MockBody::new(b"")).unwrap())
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        collect_body(response).await
+    }
+
+    #[tokio::test]
+    async fn test_reverse_proxy_routes_canary_requests_to_canary_pool() {
+        let stable_port =
+            start_raw_upstream(b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nstable").await;
+        let canary_port =
+            start_raw_upstream(b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\ncanary").await;
+
+        let canary = Canary::new(
+            chico_file::types::CanaryMatch::Header {
+                name: "X-Canary".to_string(),
+                value: "true".to_string(),
+            },
+            Box::new(SingleUpstream::new(
+                format!("127.0.0.1:{canary_port}").parse().unwrap(),
+            )),
+        );
+        let handler = proxy_to(stable_port).with_canary(canary);
+
+        assert_eq!(send(&handler, Some("true")).await, "canary");
+        assert_eq!(send(&handler, Some("false")).await, "stable");
+    }
+
+    #[tokio::test]
+    async fn test_reverse_proxy_without_canary_header_uses_stable_pool() {
+        let stable_port =
+            start_raw_upstream(b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nstable").await;
+
+        let canary = Canary::new(
+            chico_file::types::CanaryMatch::Header {
+                name: "X-Canary".to_string(),
+                value: "true".to_string(),
+            },
+            // nothing listens there, the request would fail if it was sent to the canary
+            Box::new(SingleUpstream::new("127.0.0.1:1".parse().unwrap())),
+        );
+        let handler = proxy_to(stable_port).with_canary(canary);
+
+        assert_eq!(send(&handler, None).await, "stable");
+    }
+
+    #[tokio::test]
+    async fn test_reverse_proxy_falls_back_to_next_srv_target() {
+        use crate::load_balance::{
+            dns::{DnsCache, Resolve, ResolveFuture, ResolveSrvFuture, Target},
+            node::Node,
+        };
+
+        struct SrvResolver(Vec<Target>);
+
+        impl Resolve for SrvResolver {
+            fn resolve<'a>(&'a self, host: &'a str, _port: u16) -> ResolveFuture<'a> {
+                Box::pin(async move { panic!("{host} should be resolved as SRV records") })
+            }
+
+            fn resolve_srv<'a>(&'a self, _name: &'a str) -> ResolveSrvFuture<'a> {
+                Box::pin(async move { Ok(self.0.clone()) })
+            }
+        }
+
+        let port = start_raw_upstream(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").await;
+        let resolver = SrvResolver(vec![
+            // nothing listens there, the connection falls back to the next target
+            Target {
+                addr: "127.0.0.1:1".parse().unwrap(),
+                priority: 10,
+                weight: 1,
+            },
+            Target {
+                addr: format!("127.0.0.1:{port}").parse().unwrap(),
+                priority: 20,
+                weight: 1,
+            },
+        ]);
+        let handler = ReverseProxyHandler::new(Box::new(SingleUpstream::new(Node::srv(
+            "_http._tcp.backend.service.consul".to_string(),
+        ))))
+        .with_dns_cache(DnsCache::with_resolver(Box::new(resolver), None));
+
+        assert_eq!(send(&handler, None).await, "ok");
+    }
+
+    /// Starts an upstream answering every request with the given status, returns its port and
+    /// the count of requests it received.
+    async fn start_counting_upstream(status: &'static str) -> (u16, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        (port, serve_counting(listener, status))
+    }
+
+    /// Answers the requests of `listener` with the given status, returns the count of requests.
+    fn serve_counting(listener: TcpListener, status: &'static str) -> Arc<AtomicUsize> {
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf).await.unwrap();
+                let response =
+                    format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+
+        requests
+    }
+
+    #[tokio::test]
+    async fn test_reverse_proxy_ejects_upstream_failing_repeatedly() {
+        let (port, requests) = start_counting_upstream("500 Internal Server Error").await;
+        let handler = proxy_to(port)
+            .with_passive_health(PassiveHealth::new(3, Some(Duration::from_secs(30))));
+
+        let mut statuses = vec![];
+        for _ in 0..5 {
+            statuses.push(send_status(&handler).await);
+        }
+
+        assert_eq!(
+            statuses,
+            [500, 500, 500, 502, 502].map(|status| StatusCode::from_u16(status).unwrap())
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_reverse_proxy_sends_requests_again_to_dead_upstream_coming_back() {
+        // the port stays bound, but connections are refused until the upstream comes back
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let handler = proxy_to(port)
+            .with_passive_health(PassiveHealth::new(2, Some(Duration::from_millis(200))));
+
+        for _ in 0..3 {
+            assert_eq!(send_status(&handler).await, StatusCode::BAD_GATEWAY);
+        }
+
+        let requests = serve_counting(socket.listen(1024).unwrap(), "200 OK");
+
+        // still out of rotation until the fail timeout expired
+        assert_eq!(send_status(&handler).await, StatusCode::BAD_GATEWAY);
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        for _ in 0..3 {
+            assert_eq!(send_status(&handler).await, StatusCode::OK);
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_reverse_proxy_skips_ejected_upstream_of_pool() {
+        let (failing, failing_requests) = start_counting_upstream("503 Service Unavailable").await;
+        let (healthy, healthy_requests) = start_counting_upstream("200 OK").await;
+        let nodes = vec![
+            format!("127.0.0.1:{failing}").parse().unwrap(),
+            format!("127.0.0.1:{healthy}").parse().unwrap(),
+        ];
+        let handler = ReverseProxyHandler::new(Box::new(RoundRobinBalancer::new(nodes)))
+            .with_passive_health(PassiveHealth::new(2, None));
+
+        for _ in 0..10 {
+            send_status(&handler).await;
+        }
+
+        assert_eq!(failing_requests.load(Ordering::SeqCst), 2);
+        assert_eq!(healthy_requests.load(Ordering::SeqCst), 8);
+    }
+
+    #[tokio::test]
+    async fn test_reverse_proxy_ip_hash_falls_back_to_next_upstream_when_ejected() {
+        let (failing, failing_requests) = start_counting_upstream("503 Service Unavailable").await;
+        let (healthy, healthy_requests) = start_counting_upstream("200 OK").await;
+        let nodes: Vec<Node> = vec![
+            format!("127.0.0.1:{failing}").parse().unwrap(),
+            format!("127.0.0.1:{healthy}").parse().unwrap(),
+        ];
+        let balancer = IpHashBalancer::new(nodes.clone());
+        // a client sent to the failing upstream
+        let client = (0..=255)
+            .map(|i| IpAddr::from([192, 0, 2, i]))
+            .find(|client| *balancer.acquire_node_for(Some(*client), 0).unwrap().0 == nodes[0])
+            .unwrap();
+        let handler = ReverseProxyHandler::new(Box::new(balancer))
+            .with_passive_health(PassiveHealth::new(2, None));
+
+        for _ in 0..10 {
+            let mut request = Request::builder()
+                .uri("http://localhost/")
+                .body(MockBody::new(b""))
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(PeerAddr(SocketAddr::new(client, 50000)));
+            handler.handle(request).await;
+        }
+
+        assert_eq!(failing_requests.load(Ordering::SeqCst), 2);
+        assert_eq!(healthy_requests.load(Ordering::SeqCst), 8);
+    }
+
+    #[tokio::test]
+    async fn test_reverse_proxy_fails_fast_while_circuit_is_open() {
+        let (port, requests) = start_counting_upstream("500 Internal Server Error").await;
+        let handler = proxy_to(port).with_circuit_breaker(CircuitBreaker::new(
+            &chico_file::types::CircuitBreakerConfig {
+                error_rate: 50,
+                window: Some(Duration::from_millis(300)),
+                latency: None,
+            },
+        ));
+
+        for _ in 0..MIN_REQUESTS {
+            assert_eq!(
+                send_status(&handler).await,
+                StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
+        assert_eq!(send_status(&handler).await, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(requests.load(Ordering::SeqCst), MIN_REQUESTS);
+
+        // half-open after the window, the probe reaches the upstream and opens the circuit again
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        assert_eq!(
+            send_status(&handler).await,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(send_status(&handler).await, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(requests.load(Ordering::SeqCst), MIN_REQUESTS + 1);
+    }
+
+    /// Starts an upstream answering a single request with `200 OK`, returns its port and the
+    /// head of the request it received.
+    async fn start_recording_upstream() -> (u16, tokio::sync::oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 4096];
+            let read = stream.read(&mut buf).await.unwrap();
+            let _ = sender.send(String::from_utf8_lossy(&buf[..read]).to_lowercase());
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            stream.shutdown().await.unwrap();
+        });
+
+        (port, receiver)
+    }
+
+    fn forwarded_request() -> Request<MockBody> {
+        let mut request = Request::builder()
+            .uri("http://example.com/")
+            .header(http::header::HOST, "example.com")
+            .header("x-forwarded-for", "203.0.113.1, 198.51.100.2")
+            .body(MockBody::new(b""))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(PeerAddr("192.0.2.7:50000".parse().unwrap()));
+        request.extensions_mut().insert(TlsConnection);
+        request
+    }
+
+    #[tokio::test]
+    async fn test_reverse_proxy_appends_client_to_forwarding_headers() {
+        let (port, received) = start_recording_upstream().await;
+
+        let response = proxy_to(port).handle(forwarded_request()).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let head = received.await.unwrap();
+        assert!(head.contains("\r\nx-forwarded-for: 203.0.113.1, 198.51.100.2, 192.0.2.7\r\n"));
+        assert!(head.contains("\r\nx-forwarded-proto: https\r\n"));
+        assert!(head.contains("\r\nx-forwarded-host: example.com\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_reverse_proxy_keeps_forwarding_headers_when_forwarded_off() {
+        let (port, received) = start_recording_upstream().await;
+
+        let response = proxy_to(port)
+            .with_forwarded(false)
+            .handle(forwarded_request())
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let head = received.await.unwrap();
+        assert!(head.contains("\r\nx-forwarded-for: 203.0.113.1, 198.51.100.2\r\n"));
+        assert!(!head.contains("x-forwarded-proto"));
+        assert!(!head.contains("x-forwarded-host"));
+    }
+
+    #[tokio::test]
+    async fn test_reverse_proxy_strips_request_headers() {
+        let (port, received) = start_recording_upstream().await;
+        let mut request = forwarded_request();
+        request
+            .headers_mut()
+            .insert("x-internal-auth", "admin".parse().unwrap());
+        request
+            .headers_mut()
+            .append("x-internal-auth", "root".parse().unwrap());
+        request
+            .headers_mut()
+            .insert("x-request-id", "42".parse().unwrap());
+
+        let response = proxy_to(port)
+            .with_strip_request_headers(vec![
+                HeaderName::from_static("x-internal-auth"),
+                HeaderName::from_static("x-admin"),
+            ])
+            .handle(request)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let head = received.await.unwrap();
+        assert!(!head.contains("x-internal-auth"), "{head}");
+        assert!(head.contains("\r\nx-request-id: 42\r\n"), "{head}");
+    }
+
+    #[rstest]
+    #[case(HostHeader::Upstream, None)]
+    #[case(HostHeader::Preserve, Some("example.com"))]
+    #[case(
+        HostHeader::Value("api.internal:8443".to_string()),
+        Some("api.internal:8443")
+    )]
+    #[tokio::test]
+    async fn test_reverse_proxy_sets_host_header(
+        #[case] host_header: HostHeader,
+        #[case] expected: Option<&str>,
+    ) {
+        let (port, received) = start_recording_upstream().await;
+
+        let response = proxy_to(port)
+            .with_host_header(host_header)
+            .handle(forwarded_request())
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let expected = expected.map_or(format!("127.0.0.1:{port}"), str::to_string);
+        assert!(received
+            .await
+            .unwrap()
+            .contains(&format!("\r\nhost: {expected}\r\n")));
+    }
+
+    #[test]
+    fn test_forwarding_headers_start_chain_and_replace_proto_and_host() {
+        let mut request = Request::builder()
+            .uri("/")
+            .header(http::header::HOST, "example.com:8080")
+            .header("x-forwarded-proto", "https")
+            .header("x-forwarded-host", "forged.example")
+            .body(())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(PeerAddr("[2001:db8::1]:50000".parse().unwrap()));
+
+        add_forwarding_headers(&mut request);
+
+        assert_eq!(request.headers()["x-forwarded-for"], "2001:db8::1");
+        assert_eq!(request.headers()["x-forwarded-proto"], "http");
+        assert_eq!(request.headers()["x-forwarded-host"], "example.com:8080");
+    }
+
+    async fn send_status(handler: &ReverseProxyHandler) -> StatusCode {
+        let request = Request::builder()
+            .uri("http://localhost/")
+            .body(MockBody::new(b""))
+            .unwrap();
+        handler.handle(request).await.status()
+    }
+
+    #[tokio::test]
+    async fn test_reverse_proxy_relays_switching_protocols() {
+        let port = start_raw_upstream(
+            b"HTTP/1.1 101 Switching Protocols\r\n\
+Connection: Upgrade\r\n\
+Upgrade: websocket\r\n\
+\r\n",
+        )
+        .await;
+        let request = Request::builder()
+            .uri("http://localhost/chat")
+            .header(http::header::CONNECTION, "Upgrade")
+            .header(http::header::UPGRADE, "websocket")
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = proxy_to(port).handle(request).await;
+
+        assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(response.headers()[http::header::UPGRADE], "websocket");
+        assert_eq!(collect_body(response).await, "");
+    }
+
+    #[rstest]
+    #[case(&[("connection", "Upgrade"), ("upgrade", "websocket")], true)]
+    #[case(&[("connection", "keep-alive, upgrade"), ("upgrade", "websocket")], true)]
+    #[case(&[("connection", "keep-alive"), ("upgrade", "websocket")], false)]
+    #[case(&[("connection", "Upgrade")], false)]
+    #[case(&[], false)]
+    fn test_is_upgrade(#[case] headers: &[(&str, &str)], #[case] expected: bool) {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+            .collect();
+        assert_eq!(is_upgrade(&headers), expected);
+    }
 }
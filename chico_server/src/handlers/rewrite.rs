@@ -0,0 +1,114 @@
+//! # RewriteHandler
+//!
+//! Changes the path of the request, which is then dispatched again to the route of the new path
+//! without a round trip to the client. `handle_request` follows the rewrites, this handler only
+//! answers the requests whose rewrite failed.
+//!
+//! The target may use placeholders of the original request:
+//! - `{path}`: the part of the path below the route's `/*`, empty for routes without wildcard.
+//! - `{query}`: the query string, without `?`.
+//!
+//! The original query is kept when the target has no query of its own.
+
+use http::{uri::PathAndQuery, Response, StatusCode, Uri};
+use tracing::debug;
+
+use super::{dir::path_below_route, error, full, BoxBody, RequestHandler};
+
+/// Rewrites a request may go through, more are treated as a loop.
+pub const MAX_REWRITES: usize = 5;
+
+#[derive(PartialEq, Debug)]
+pub struct RewriteHandler {
+    target: String,
+    route: String,
+}
+
+impl RewriteHandler {
+    pub fn new(target: String, route: String) -> Self {
+        Self { target, route }
+    }
+
+    /// Returns the URI of the request with the path rewritten, `None` when the rewritten path is
+    /// not a valid URI path.
+    pub fn rewrite(&self, uri: &Uri) -> Option<Uri> {
+        let path = path_below_route(uri.path(), &self.route).unwrap_or_default();
+        let query = uri.query().unwrap_or_default();
+        let mut target = self
+            .target
+            .replace("{path}", path.trim_start_matches('/'))
+            .replace("{query}", query);
+        if !self.target.contains('?') && !query.is_empty() {
+            target = format!("{target}?{query}");
+        }
+        let target = target.trim_end_matches('?');
+
+        let mut parts = uri.clone().into_parts();
+        parts.path_and_query = Some(PathAndQuery::try_from(target).ok()?);
+        Uri::from_parts(parts).ok()
+    }
+}
+
+/// Answers the requests whose rewrite was not followed, because the rewritten path is not valid or
+/// the request was rewritten more than [`MAX_REWRITES`] times.
+impl RequestHandler for RewriteHandler {
+    async fn handle<B>(&self, request: hyper::Request<B>) -> Response<BoxBody>
+    where
+        B: hyper::body::Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        debug!("rewrite of {} to {} failed", request.uri(), self.target);
+        error::built_in(
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(full("500 Internal Server Error"))
+                .unwrap(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::Uri;
+    use rstest::rstest;
+
+    use super::RewriteHandler;
+
+    #[rstest]
+    #[case("/old/*", "/new/{path}", "/old/a/b.html", "/new/a/b.html")]
+    #[case("/old/*", "/new/{path}", "/old/a?page=2", "/new/a?page=2")]
+    #[case(
+        "/old/*",
+        "/new/{path}?from=old&{query}",
+        "/old/a?page=2",
+        "/new/a?from=old&page=2"
+    )]
+    #[case("/old/*", "/new?{query}", "/old/a", "/new")]
+    #[case("/old", "/new/{path}", "/old", "/new/")]
+    #[case(
+        "/old",
+        "/index.html",
+        "http://localhost/old",
+        "http://localhost/index.html"
+    )]
+    fn test_rewrite_substitutes_placeholders(
+        #[case] route: &str,
+        #[case] target: &str,
+        #[case] uri: &str,
+        #[case] expected: &str,
+    ) {
+        let handler = RewriteHandler::new(target.to_string(), route.to_string());
+
+        let rewritten = handler.rewrite(&uri.parse::<Uri>().unwrap()).unwrap();
+
+        assert_eq!(rewritten.to_string(), expected);
+    }
+
+    #[test]
+    fn test_rewrite_to_invalid_path() {
+        let handler = RewriteHandler::new("/new path".to_string(), "/old".to_string());
+
+        assert!(handler.rewrite(&"/old".parse::<Uri>().unwrap()).is_none());
+    }
+}
@@ -0,0 +1,214 @@
+//! # TryFilesHandler
+//!
+//! Serves the first existing file of a list of candidates, like `try_files public{path}
+//! public/index.html` serving the assets of a single-page app and its `index.html` for every
+//! other path. `{path}` is replaced by the request path, `$uri` is an alias of it. A candidate
+//! ending with `/` is a directory, its `index.html` is served. Relative candidates are resolved
+//! against the executable directory.
+
+use std::path::{Component, Path, PathBuf};
+
+use http::{Response, StatusCode};
+
+use super::{
+    dir::{absolute_root, INDEX_FILE},
+    error,
+    file::FileHandler,
+    full,
+    respond::RespondHandler,
+    BoxBody, RequestHandler,
+};
+
+#[derive(PartialEq, Debug)]
+pub struct TryFilesHandler {
+    pub candidates: Vec<String>,
+    pub route: String,
+    pub sniff_extensionless: bool,
+    pub max_ranges: Option<usize>,
+}
+
+impl TryFilesHandler {
+    pub fn new(candidates: Vec<String>, route: String) -> TryFilesHandler {
+        TryFilesHandler {
+            candidates,
+            route,
+            sniff_extensionless: false,
+            max_ranges: None,
+        }
+    }
+
+    /// Detect the content type of files without extension from their first bytes.
+    pub fn with_sniff_extensionless(mut self, sniff_extensionless: bool) -> Self {
+        self.sniff_extensionless = sniff_extensionless;
+        self
+    }
+
+    /// Reject range requests asking for more than `max_ranges` ranges.
+    pub fn with_max_ranges(mut self, max_ranges: Option<usize>) -> Self {
+        self.max_ranges = max_ranges;
+        self
+    }
+
+    /// Returns the first candidate that is an existing file for the request path.
+    async fn find_file(&self, req_path: &str) -> Option<PathBuf> {
+        for candidate in &self.candidates {
+            let Some(mut path) = candidate_path(candidate, req_path) else {
+                continue;
+            };
+            if candidate.ends_with('/') {
+                path.push(INDEX_FILE);
+            }
+            if tokio::fs::try_exists(&path)
+                .await
+                .is_ok_and(|exists| exists)
+                && tokio::fs::metadata(&path)
+                    .await
+                    .is_ok_and(|metadata| metadata.is_file())
+            {
+                return Some(path);
+            }
+        }
+        None
+    }
+}
+
+/// Returns the path of a candidate for the request path, `None` when the request path would
+/// make it leave its directory with a `..` segment.
+fn candidate_path(candidate: &str, req_path: &str) -> Option<PathBuf> {
+    if (candidate.contains("{path}") || candidate.contains("$uri"))
+        && Path::new(req_path)
+            .components()
+            .any(|component| component == Component::ParentDir)
+    {
+        return None;
+    }
+    Some(absolute_root(
+        &candidate
+            .replace("{path}", req_path)
+            .replace("$uri", req_path),
+    ))
+}
+
+impl RequestHandler for TryFilesHandler {
+    async fn handle<B>(&self, request: hyper::Request<B>) -> Response<BoxBody>
+    where
+        B: hyper::body::Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let req_method = request.method();
+        if req_method != http::Method::GET && req_method != http::Method::HEAD {
+            return error::built_in(
+                http::response::Builder::new()
+                    .status(StatusCode::METHOD_NOT_ALLOWED)
+                    .header(http::header::ALLOW, "GET, HEAD")
+                    .body(full(""))
+                    .unwrap(),
+            );
+        }
+
+        match self.find_file(request.uri().path()).await {
+            Some(path) => {
+                FileHandler::new(path.to_string_lossy().into_owned(), self.route.clone())
+                    .with_sniff_extensionless(self.sniff_extensionless)
+                    .with_max_ranges(self.max_ranges)
+                    .handle(request)
+                    .await
+            }
+            None => error::built_in(RespondHandler::not_found().handle(request).await),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use http::{Request, StatusCode};
+    use rstest::rstest;
+    use tempfile::TempDir;
+
+    use super::TryFilesHandler;
+    use crate::{
+        handlers::{error, RequestHandler},
+        testing::{collect_body, MockBody},
+    };
+
+    fn site() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("assets")).unwrap();
+        fs::write(dir.path().join("assets/app.js"), "app").unwrap();
+        fs::write(dir.path().join("index.html"), "index").unwrap();
+        dir
+    }
+
+    fn request(path: &str) -> Request<MockBody> {
+        Request::builder()
+            .uri(path)
+            .body(MockBody::new(b""))
+            .unwrap()
+    }
+
+    fn handler(dir: &TempDir, candidates: &[&str]) -> TryFilesHandler {
+        let root = dir.path().to_str().unwrap();
+        TryFilesHandler::new(
+            candidates
+                .iter()
+                .map(|candidate| format!("{root}{candidate}"))
+                .collect(),
+            "/*".to_string(),
+        )
+    }
+
+    #[rstest]
+    #[case("/assets/app.js", "app")]
+    #[case("/missing.js", "index")]
+    #[case("/settings/profile", "index")]
+    // a directory is not a file to serve
+    #[case("/assets", "index")]
+    #[case("/../index.html", "index")]
+    #[tokio::test]
+    async fn test_serves_first_existing_candidate(#[case] path: &str, #[case] expected: &str) {
+        let dir = site();
+        let handler = handler(&dir, &["{path}", "/index.html"]);
+
+        let response = handler.handle(request(path)).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(collect_body(response).await, expected);
+    }
+
+    #[rstest]
+    #[case("/assets/app.js", "app")]
+    #[case("/docs", "docs index")]
+    #[case("/docs/", "docs index")]
+    // deep links of the app are served its index
+    #[case("/users/42/settings", "index")]
+    #[case("/../index.html", "index")]
+    #[tokio::test]
+    async fn test_serves_uri_then_directory_index_then_fallback(
+        #[case] path: &str,
+        #[case] expected: &str,
+    ) {
+        let dir = site();
+        fs::create_dir(dir.path().join("docs")).unwrap();
+        fs::write(dir.path().join("docs/index.html"), "docs index").unwrap();
+        let handler = handler(&dir, &["$uri", "$uri/", "/index.html"]);
+
+        let response = handler.handle(request(path)).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(collect_body(response).await, expected);
+    }
+
+    #[tokio::test]
+    async fn test_not_found_when_no_candidate_exists() {
+        let dir = site();
+        let handler = handler(&dir, &["{path}", "/fallback.html"]);
+
+        let response = handler.handle(request("/missing.js")).await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(error::is_built_in(&response));
+    }
+}
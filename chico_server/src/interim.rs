@@ -0,0 +1,233 @@
+//! # Interim
+//!
+//! Sends informational (`1xx`) responses, like `103 Early Hints`, ahead of the final response of
+//! an HTTP/1 request. hyper's server side cannot send them, so they are written to the connection
+//! below hyper.
+//!
+//! hyper flushes the connection each time it wrote everything it buffered, the queued responses
+//! are written then. A response is only queued while hyper holds no bytes of an earlier response,
+//! so it always lands between the previous response and the final response of its request.
+
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+};
+
+use http::HeaderMap;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::debug;
+
+/// Stream writing the interim responses queued by [`InterimResponses`] before the bytes of hyper.
+pub struct Interim<S> {
+    inner: S,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Interim responses not written yet.
+    queue: Vec<u8>,
+    /// hyper holds bytes it could not write yet, an interim response would overtake them.
+    backlogged: bool,
+}
+
+/// Request extension queuing interim responses on the connection of the request.
+///
+/// Only meant for HTTP/1.1 requests, and to be used from the task serving the connection, which
+/// flushes the queue once the handler yields.
+#[derive(Clone)]
+pub struct InterimResponses {
+    state: Arc<Mutex<State>>,
+}
+
+impl<S> Interim<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            state: Arc::default(),
+        }
+    }
+
+    /// Returns the handle queuing interim responses on this stream.
+    pub fn responses(&self) -> InterimResponses {
+        InterimResponses {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Interim<S> {
+    /// Writes the queued interim responses.
+    fn poll_write_queue(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut state = self.state.lock().unwrap();
+        while !state.queue.is_empty() {
+            let written = ready!(Pin::new(&mut self.inner).poll_write(cx, &state.queue))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            state.queue.drain(..written);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Notes a write of hyper that did not complete, hyper keeps its bytes until the next try.
+    fn track<T>(&self, poll: Poll<io::Result<T>>) -> Poll<io::Result<T>> {
+        if poll.is_pending() {
+            self.state.lock().unwrap().backlogged = true;
+        }
+        poll
+    }
+}
+
+impl InterimResponses {
+    /// Queues a `103 Early Hints` response with the given headers, written before the final
+    /// response of the request.
+    ///
+    /// Returns false when the response cannot be sent in order, the connection still has to
+    /// write bytes of the previous response.
+    pub fn send_early_hints(&self, headers: &HeaderMap) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.backlogged {
+            debug!("connection is backlogged, not sending early hints");
+            return false;
+        }
+
+        state
+            .queue
+            .extend_from_slice(b"HTTP/1.1 103 Early Hints\r\n");
+        for (name, value) in headers {
+            state.queue.extend_from_slice(name.as_str().as_bytes());
+            state.queue.extend_from_slice(b": ");
+            state.queue.extend_from_slice(value.as_bytes());
+            state.queue.extend_from_slice(b"\r\n");
+        }
+        state.queue.extend_from_slice(b"\r\n");
+        true
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Interim<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Interim<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = match this.poll_write_queue(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_write(cx, buf),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        };
+        this.track(poll)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = match this.poll_write_queue(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_write_vectored(cx, bufs),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        };
+        this.track(poll)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        // hyper flushes once it wrote all its bytes
+        this.state.lock().unwrap().backlogged = false;
+        ready!(this.poll_write_queue(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_queue(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderMap, HeaderValue};
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::Interim;
+
+    fn early_hints() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::LINK,
+            HeaderValue::from_static("</style.css>; rel=preload; as=style"),
+        );
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_interim_response_is_written_on_flush_before_the_final_response() {
+        let (server, mut client) = duplex(1024);
+        let mut server = Interim::new(server);
+
+        assert!(server.responses().send_early_hints(&early_hints()));
+        server.flush().await.unwrap();
+        let mut buf = [0; 1024];
+        let read = client.read(&mut buf).await.unwrap();
+        assert_eq!(
+            &buf[..read],
+            b"HTTP/1.1 103 Early Hints\r\nlink: </style.css>; rel=preload; as=style\r\n\r\n"
+        );
+
+        server.responses().send_early_hints(&early_hints());
+        server
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        server.shutdown().await.unwrap();
+        let mut received = String::new();
+        client.read_to_string(&mut received).await.unwrap();
+        assert_eq!(
+            received,
+            "HTTP/1.1 103 Early Hints\r\nlink: </style.css>; rel=preload; as=style\r\n\r\n\
+            HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_interim_response_is_refused_while_a_write_is_pending() {
+        let (server, mut client) = duplex(16);
+        let mut server = Interim::new(server);
+        let responses = server.responses();
+
+        // the client does not read, the response stays partly written
+        let write = tokio::time::timeout(
+            std::time::Duration::from_millis(50),
+            server.write_all(&[7; 64]),
+        );
+        assert!(write.await.is_err(), "the write should still wait");
+        assert!(!responses.send_early_hints(&early_hints()));
+
+        let mut buf = [0; 16];
+        client.read_exact(&mut buf).await.unwrap();
+        server.flush().await.unwrap();
+        assert!(responses.send_early_hints(&early_hints()));
+    }
+}
@@ -0,0 +1,17 @@
+#![cfg_attr(feature = "strict", deny(warnings))]
+pub mod cli;
+pub mod client;
+pub mod config;
+pub mod handlers;
+mod interim;
+pub mod load_balance;
+pub mod memory;
+pub mod middlewares;
+pub mod plan;
+pub mod reload;
+pub mod server;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod tls;
+mod virtual_host;
+mod write_timeout;
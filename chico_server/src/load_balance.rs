@@ -1,12 +1,45 @@
-use std::sync::Arc;
+use std::{net::IpAddr, sync::Arc};
 
-use crate::load_balance::node::Node;
+use crate::load_balance::{least_connections::InFlight, node::Node};
 
+pub mod canary;
+pub mod circuit_breaker;
+pub mod dns;
+pub mod health;
+pub mod ip_hash;
+pub mod least_connections;
 pub mod node;
+pub mod pool;
+pub mod random;
 pub mod round_robin;
+pub mod weighted_round_robin;
 
 pub trait LoadBalance: Send + Sync {
     fn get_node(&self) -> Option<Arc<Node>>;
+
+    /// Number of nodes picked from, so a caller skipping nodes knows when it went through all.
+    fn node_count(&self) -> usize;
+
+    /// Picks a node like `get_node` for a request sent to it.
+    ///
+    /// Balancers picking nodes by their load count the request as in flight until the returned
+    /// guard is dropped.
+    fn acquire_node(&self) -> Option<(Arc<Node>, Option<InFlight>)> {
+        self.get_node().map(|node| (node, None))
+    }
+
+    /// Picks a node like `acquire_node` for a request of `client`, for the attempt of the same
+    /// index when the nodes picked by the previous attempts were skipped.
+    ///
+    /// Balancers keeping a client on the same node try the next nodes in order on later
+    /// attempts, the others ignore the client and the attempt.
+    fn acquire_node_for(
+        &self,
+        _client: Option<IpAddr>,
+        _attempt: usize,
+    ) -> Option<(Arc<Node>, Option<InFlight>)> {
+        self.acquire_node()
+    }
 }
 
 pub struct SingleUpstream {
@@ -25,4 +58,8 @@ impl LoadBalance for SingleUpstream {
     fn get_node(&self) -> Option<Arc<Node>> {
         Some(self.node.clone())
     }
+
+    fn node_count(&self) -> usize {
+        1
+    }
 }
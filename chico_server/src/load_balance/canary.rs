@@ -0,0 +1,112 @@
+//! # Canary
+//!
+//! Sends the requests carrying a given header or cookie to a separate pool of upstreams, so a new
+//! release can be tested with targeted traffic. Unlike weighted balancing the decision is
+//! deterministic per request.
+
+use std::sync::Arc;
+
+use chico_file::types::CanaryMatch;
+use http::{header, HeaderMap};
+
+use crate::load_balance::{least_connections::InFlight, node::Node, LoadBalance};
+
+pub struct Canary {
+    matcher: CanaryMatch,
+    load_balancer: Box<dyn LoadBalance>,
+}
+
+impl Canary {
+    pub fn new(matcher: CanaryMatch, load_balancer: Box<dyn LoadBalance>) -> Self {
+        Self {
+            matcher,
+            load_balancer,
+        }
+    }
+
+    /// Returns true when the request headers select the canary pool.
+    pub fn matches(&self, headers: &HeaderMap) -> bool {
+        match &self.matcher {
+            CanaryMatch::Header { name, value } => headers
+                .get_all(name.as_str())
+                .iter()
+                .any(|header_value| header_value == value.as_str()),
+            CanaryMatch::Cookie { name, value } => headers
+                .get_all(header::COOKIE)
+                .iter()
+                .filter_map(|cookies| cookies.to_str().ok())
+                .flat_map(|cookies| cookies.split(';'))
+                .filter_map(|cookie| cookie.trim().split_once('='))
+                .any(|(cookie_name, cookie_value)| cookie_name == name && cookie_value == value),
+        }
+    }
+
+    pub fn get_node(&self) -> Option<Arc<Node>> {
+        self.load_balancer.get_node()
+    }
+
+    pub fn acquire_node(&self) -> Option<(Arc<Node>, Option<InFlight>)> {
+        self.load_balancer.acquire_node()
+    }
+
+    /// Balancer of the canary's upstreams.
+    pub fn load_balancer(&self) -> &dyn LoadBalance {
+        self.load_balancer.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chico_file::types::CanaryMatch;
+    use http::HeaderMap;
+    use rstest::rstest;
+
+    use crate::load_balance::SingleUpstream;
+
+    use super::Canary;
+
+    fn canary(matcher: CanaryMatch) -> Canary {
+        Canary::new(
+            matcher,
+            Box::new(SingleUpstream::new("127.0.0.1:9000".parse().unwrap())),
+        )
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[rstest]
+    #[case(&[("x-canary", "true")], true)]
+    #[case(&[("X-Canary", "true")], true)]
+    #[case(&[("x-canary", "false"), ("x-canary", "true")], true)]
+    #[case(&[("x-canary", "false")], false)]
+    #[case(&[("x-canary", "TRUE")], false)]
+    #[case(&[], false)]
+    fn test_header_match(#[case] pairs: &[(&'static str, &'static str)], #[case] expected: bool) {
+        let canary = canary(CanaryMatch::Header {
+            name: "X-Canary".to_string(),
+            value: "true".to_string(),
+        });
+        assert_eq!(canary.matches(&headers(pairs)), expected);
+    }
+
+    #[rstest]
+    #[case(&[("cookie", "beta=1")], true)]
+    #[case(&[("cookie", "session=abc; beta=1")], true)]
+    #[case(&[("cookie", "session=abc"), ("cookie", "beta=1")], true)]
+    #[case(&[("cookie", "beta=0")], false)]
+    #[case(&[("cookie", "notbeta=1")], false)]
+    #[case(&[("beta", "1")], false)]
+    fn test_cookie_match(#[case] pairs: &[(&'static str, &'static str)], #[case] expected: bool) {
+        let canary = canary(CanaryMatch::Cookie {
+            name: "beta".to_string(),
+            value: "1".to_string(),
+        });
+        assert_eq!(canary.matches(&headers(pairs)), expected);
+    }
+}
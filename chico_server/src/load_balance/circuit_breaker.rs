@@ -0,0 +1,274 @@
+//! # CircuitBreaker
+//!
+//! Fails the requests to an upstream fast while it errs or slows down, instead of letting each of
+//! them wait for it.
+//!
+//! - The outcome and duration of the requests of the last `window` are kept per upstream. Once
+//!   [`MIN_REQUESTS`] of them are known, the circuit trips when `error_rate` percent of them
+//!   failed or, with `latency` set, when they took `latency` or more on average.
+//! - An open circuit rejects the requests to its upstream for `window`.
+//! - Then the circuit is half-open: a single request is let through, its success closes the
+//!   circuit and its failure opens it again.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+
+use chico_file::types::CircuitBreakerConfig;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::load_balance::node::Node;
+
+/// Requests of the window needed before the circuit may trip.
+pub const MIN_REQUESTS: usize = 5;
+
+pub struct CircuitBreaker {
+    error_rate: u8,
+    window: Duration,
+    latency: Option<Duration>,
+    circuits: Mutex<HashMap<Node, Circuit>>,
+}
+
+#[derive(Default)]
+struct Circuit {
+    state: State,
+    /// End time, failure and duration of the requests of the window, oldest first.
+    outcomes: VecDeque<(Instant, bool, Duration)>,
+}
+
+#[derive(Default)]
+enum State {
+    #[default]
+    Closed,
+    Open {
+        until: Instant,
+    },
+    /// A request is probing the upstream, another one is let through if it did not end by then.
+    HalfOpen {
+        probe_until: Instant,
+    },
+}
+
+impl CircuitBreaker {
+    pub const DEFAULT_WINDOW: Duration = Duration::from_secs(10);
+
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        Self {
+            error_rate: config.error_rate,
+            window: config.window.unwrap_or(CircuitBreaker::DEFAULT_WINDOW),
+            latency: config.latency,
+            circuits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns true when a request may be sent to the node, a half-open circuit lets a single
+    /// request through.
+    pub fn allows(&self, node: &Node) -> bool {
+        let mut circuits = self.circuits.lock().unwrap();
+        let Some(circuit) = circuits.get_mut(node) else {
+            return true;
+        };
+        let now = Instant::now();
+        match circuit.state {
+            State::Closed => true,
+            State::Open { until } | State::HalfOpen { probe_until: until } if now < until => false,
+            State::Open { .. } | State::HalfOpen { .. } => {
+                circuit.state = State::HalfOpen {
+                    probe_until: now + self.window,
+                };
+                true
+            }
+        }
+    }
+
+    /// Counts a request to the node that took `elapsed`.
+    pub fn record(&self, node: &Node, success: bool, elapsed: Duration) {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(node.clone()).or_default();
+        let now = Instant::now();
+        match circuit.state {
+            State::Closed => {}
+            // Requests sent before the circuit opened are not counted
+            State::Open { .. } => return,
+            State::HalfOpen { .. } if success => {
+                info!("upstream {} recovered, circuit closed", node);
+                circuit.state = State::Closed;
+                circuit.outcomes.clear();
+                return;
+            }
+            State::HalfOpen { .. } => {
+                circuit.state = State::Open {
+                    until: now + self.window,
+                };
+                return;
+            }
+        }
+
+        circuit.outcomes.push_back((now, !success, elapsed));
+        while circuit
+            .outcomes
+            .front()
+            .is_some_and(|(at, _, _)| now.duration_since(*at) > self.window)
+        {
+            circuit.outcomes.pop_front();
+        }
+        if let Some(reason) = self.trip_reason(&circuit.outcomes) {
+            warn!(
+                "upstream {} {}, circuit open for {:?}",
+                node, reason, self.window
+            );
+            circuit.state = State::Open {
+                until: now + self.window,
+            };
+            circuit.outcomes.clear();
+        }
+    }
+
+    /// Returns why the circuit trips for the outcomes of the window, if it does.
+    fn trip_reason(&self, outcomes: &VecDeque<(Instant, bool, Duration)>) -> Option<String> {
+        if outcomes.len() < MIN_REQUESTS {
+            return None;
+        }
+        let failures = outcomes.iter().filter(|(_, failed, _)| *failed).count();
+        if failures * 100 >= usize::from(self.error_rate) * outcomes.len() {
+            return Some(format!(
+                "failed {} of its last {} requests",
+                failures,
+                outcomes.len()
+            ));
+        }
+        let latency = self.latency?;
+        let average = outcomes
+            .iter()
+            .map(|(_, _, elapsed)| *elapsed)
+            .sum::<Duration>()
+            / outcomes.len() as u32;
+        (average >= latency).then(|| format!("took {average:?} per request on average"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chico_file::types::CircuitBreakerConfig;
+    use rstest::rstest;
+
+    use super::{CircuitBreaker, MIN_REQUESTS};
+    use crate::load_balance::node::Node;
+
+    fn node() -> Node {
+        "127.0.0.1:8080".parse().unwrap()
+    }
+
+    fn breaker(error_rate: u8, latency: Option<Duration>) -> CircuitBreaker {
+        CircuitBreaker::new(&CircuitBreakerConfig {
+            error_rate,
+            window: Some(Duration::from_secs(10)),
+            latency,
+        })
+    }
+
+    const FAST: Duration = Duration::from_millis(10);
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sustained_failures_trip_the_circuit() {
+        let breaker = breaker(50, None);
+
+        for _ in 0..MIN_REQUESTS - 1 {
+            breaker.record(&node(), false, FAST);
+        }
+        assert!(breaker.allows(&node()));
+
+        breaker.record(&node(), false, FAST);
+        assert!(!breaker.allows(&node()));
+    }
+
+    #[rstest]
+    // 2 failures of 5 requests stay below 50%
+    #[case(&[false, true, false, true, true], 50, true)]
+    #[case(&[false, true, false, true, false], 50, false)]
+    #[case(&[false, true, true, true, true], 20, false)]
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_trips_at_error_rate(
+        #[case] outcomes: &[bool],
+        #[case] error_rate: u8,
+        #[case] closed: bool,
+    ) {
+        let breaker = breaker(error_rate, None);
+
+        for success in outcomes {
+            breaker.record(&node(), *success, FAST);
+        }
+
+        assert_eq!(breaker.allows(&node()), closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_responses_trip_the_circuit() {
+        let breaker = breaker(100, Some(Duration::from_millis(500)));
+
+        for _ in 0..MIN_REQUESTS {
+            breaker.record(&node(), true, Duration::from_secs(1));
+        }
+
+        assert!(!breaker.allows(&node()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failures_older_than_window_are_forgotten() {
+        let breaker = breaker(50, None);
+
+        for _ in 0..MIN_REQUESTS - 1 {
+            breaker.record(&node(), false, FAST);
+        }
+        tokio::time::advance(Duration::from_secs(11)).await;
+        breaker.record(&node(), false, FAST);
+
+        assert!(breaker.allows(&node()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_half_opens_after_window() {
+        let breaker = breaker(50, None);
+        for _ in 0..MIN_REQUESTS {
+            breaker.record(&node(), false, FAST);
+        }
+
+        tokio::time::advance(Duration::from_secs(9)).await;
+        assert!(!breaker.allows(&node()));
+        tokio::time::advance(Duration::from_secs(2)).await;
+        // a single probe is let through
+        assert!(breaker.allows(&node()));
+        assert!(!breaker.allows(&node()));
+
+        // its failure opens the circuit again
+        breaker.record(&node(), false, FAST);
+        tokio::time::advance(Duration::from_secs(9)).await;
+        assert!(!breaker.allows(&node()));
+
+        // its success closes it
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert!(breaker.allows(&node()));
+        breaker.record(&node(), true, FAST);
+        assert!(breaker.allows(&node()));
+        assert!(breaker.allows(&node()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_half_open_lets_another_probe_through_when_first_never_ends() {
+        let breaker = breaker(50, None);
+        for _ in 0..MIN_REQUESTS {
+            breaker.record(&node(), false, FAST);
+        }
+        tokio::time::advance(Duration::from_secs(11)).await;
+        assert!(breaker.allows(&node()));
+
+        tokio::time::advance(Duration::from_secs(11)).await;
+
+        assert!(breaker.allows(&node()));
+    }
+}
@@ -0,0 +1,600 @@
+//! # DnsCache
+//!
+//! Resolves upstream hostnames and caches the resulting socket addresses for a
+//! configurable time-to-live, so the proxy doesn't pay for a DNS lookup on every
+//! request while still picking up backend IP changes without a restart.
+//!
+//! The system resolver does not expose record TTLs, so entries live for the
+//! `resolve_ttl` configured on the proxy block (or [`DnsCache::DEFAULT_TTL`]).
+//! IP literals are never looked up nor cached.
+//!
+//! Nodes named by SRV records (`srv://_http._tcp.backend.service.consul`) resolve to the
+//! addresses of the record targets. Each lookup lists them for a connection attempt: a target
+//! of the lowest priority picked in proportion to the record weights first, then the other
+//! targets as fallbacks in order of priority.
+//!
+//! When re-resolving fails, the last resolved addresses keep being used for up to
+//! `dns_stale_max` (or [`DnsCache::DEFAULT_STALE_MAX`]); past it the upstream is degraded
+//! and lookups fail until the resolver answers again.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Mutex,
+    time::Duration,
+};
+
+use hickory_resolver::TokioAsyncResolver;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+use crate::load_balance::node::Node;
+
+pub type ResolveFuture<'a> = Pin<Box<dyn Future<Output = io::Result<Vec<SocketAddr>>> + Send + 'a>>;
+pub type ResolveSrvFuture<'a> = Pin<Box<dyn Future<Output = io::Result<Vec<Target>>> + Send + 'a>>;
+
+/// Address of an upstream, with the priority and weight of the SRV record naming it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Target {
+    pub addr: SocketAddr,
+    /// Targets with a lower priority are tried first.
+    pub priority: u16,
+    /// Share of the requests among the targets of the same priority.
+    pub weight: u16,
+}
+
+impl From<SocketAddr> for Target {
+    fn from(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            priority: 0,
+            weight: 0,
+        }
+    }
+}
+
+/// Resolves a host and port into socket addresses.
+pub trait Resolve: Send + Sync {
+    fn resolve<'a>(&'a self, host: &'a str, port: u16) -> ResolveFuture<'a>;
+
+    /// Resolves the SRV records of `name` into the addresses of their targets.
+    fn resolve_srv<'a>(&'a self, name: &'a str) -> ResolveSrvFuture<'a> {
+        Box::pin(async move {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("cannot resolve the SRV records of {name}"),
+            ))
+        })
+    }
+}
+
+/// Resolver backed by the operating system, see [`tokio::net::lookup_host`].
+pub struct SystemResolver;
+
+impl Resolve for SystemResolver {
+    fn resolve<'a>(&'a self, host: &'a str, port: u16) -> ResolveFuture<'a> {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port)).await?.collect();
+            if addrs.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no addresses found for {host}"),
+                ));
+            }
+            Ok(addrs)
+        })
+    }
+
+    fn resolve_srv<'a>(&'a self, name: &'a str) -> ResolveSrvFuture<'a> {
+        Box::pin(async move {
+            let resolver =
+                TokioAsyncResolver::tokio_from_system_conf().map_err(io::Error::other)?;
+            let records = resolver.srv_lookup(name).await.map_err(io::Error::other)?;
+
+            let mut targets = vec![];
+            for record in records.iter() {
+                let host = record.target().to_utf8();
+                let host = host.trim_end_matches('.');
+                match self.resolve(host, record.port()).await {
+                    Ok(addrs) => targets.extend(addrs.into_iter().map(|addr| Target {
+                        addr,
+                        priority: record.priority(),
+                        weight: record.weight(),
+                    })),
+                    Err(error) => debug!("skipping SRV target {} of {}: {}", host, name, error),
+                }
+            }
+            if targets.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no SRV targets found for {name}"),
+                ));
+            }
+            Ok(targets)
+        })
+    }
+}
+
+/// Minimum time between two warnings about a host failing to resolve.
+const WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+struct CacheEntry {
+    targets: Vec<Target>,
+    /// Lookups of the targets of SRV records, spreads them by weight.
+    picks: u64,
+    expires_at: Instant,
+    /// Since when re-resolving fails, `None` while the addresses are up to date.
+    stale_since: Option<Instant>,
+    warned_at: Option<Instant>,
+}
+
+pub struct DnsCache {
+    resolver: Box<dyn Resolve>,
+    ttl: Duration,
+    stale_max: Duration,
+    entries: Mutex<HashMap<Node, CacheEntry>>,
+}
+
+impl DnsCache {
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+    pub const DEFAULT_STALE_MAX: Duration = Duration::from_secs(3600);
+
+    pub fn new(ttl: Option<Duration>) -> Self {
+        Self::with_resolver(Box::new(SystemResolver), ttl)
+    }
+
+    pub fn with_resolver(resolver: Box<dyn Resolve>, ttl: Option<Duration>) -> Self {
+        Self {
+            resolver,
+            ttl: ttl.unwrap_or(DnsCache::DEFAULT_TTL),
+            stale_max: DnsCache::DEFAULT_STALE_MAX,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets how long the last resolved addresses are used while re-resolving fails.
+    pub fn with_stale_max(mut self, stale_max: Option<Duration>) -> Self {
+        self.stale_max = stale_max.unwrap_or(DnsCache::DEFAULT_STALE_MAX);
+        self
+    }
+
+    /// Returns the socket addresses of the given node.
+    ///
+    /// Cached addresses are returned while they are fresh; expired or missing entries
+    /// are re-resolved and stored again. If re-resolving fails, the last addresses are
+    /// returned until the upstream is degraded.
+    pub async fn lookup(&self, node: &Node) -> io::Result<Vec<SocketAddr>> {
+        if let Some(ip) = parse_ip_literal(&node.host).filter(|_| !node.srv) {
+            return Ok(vec![SocketAddr::new(ip, node.port)]);
+        }
+
+        if let Some(addrs) = self.get_fresh(node) {
+            return Ok(addrs);
+        }
+
+        debug!("resolving upstream host {}", node);
+        let resolved = if node.srv {
+            self.resolver.resolve_srv(&node.host).await
+        } else {
+            let addrs = self.resolver.resolve(&node.host, node.port).await;
+            addrs.map(|addrs| addrs.into_iter().map(Target::from).collect())
+        };
+        let error = match resolved {
+            Ok(targets) => {
+                let mut entry = CacheEntry {
+                    targets,
+                    picks: 0,
+                    expires_at: Instant::now() + self.ttl,
+                    stale_since: None,
+                    warned_at: None,
+                };
+                let addrs = entry.addrs(node);
+                self.entries.lock().unwrap().insert(node.clone(), entry);
+                return Ok(addrs);
+            }
+            Err(error) => error,
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.get_mut(node) else {
+            return Err(error);
+        };
+        let now = Instant::now();
+        let stale_since = *entry.stale_since.get_or_insert(now);
+        if now.duration_since(stale_since) >= self.stale_max {
+            return Err(error);
+        }
+
+        // The last addresses are used until the next attempt, a ttl later
+        entry.expires_at = now + self.ttl;
+        if entry
+            .warned_at
+            .is_none_or(|warned_at| now.duration_since(warned_at) >= WARNING_INTERVAL)
+        {
+            warn!(
+                "failed to resolve upstream host {}, keeping its last addresses: {}",
+                node, error
+            );
+            entry.warned_at = Some(now);
+        }
+        Ok(entry.addrs(node))
+    }
+
+    /// Returns for how long re-resolving the node has been failing, `None` when its
+    /// addresses are up to date.
+    pub fn staleness(&self, node: &Node) -> Option<Duration> {
+        let entries = self.entries.lock().unwrap();
+        let stale_since = entries.get(node)?.stale_since?;
+        Some(stale_since.elapsed())
+    }
+
+    /// Whether the node failed to resolve for longer than `dns_stale_max`, its last
+    /// addresses are no longer used.
+    pub fn is_degraded(&self, node: &Node) -> bool {
+        self.staleness(node)
+            .is_some_and(|staleness| staleness >= self.stale_max)
+    }
+
+    fn get_fresh(&self, node: &Node) -> Option<Vec<SocketAddr>> {
+        let mut entries = self.entries.lock().unwrap();
+        entries
+            .get_mut(node)
+            .filter(|entry| entry.expires_at > Instant::now())
+            .filter(|entry| {
+                entry
+                    .stale_since
+                    .is_none_or(|stale_since| stale_since.elapsed() < self.stale_max)
+            })
+            .map(|entry| entry.addrs(node))
+    }
+}
+
+impl CacheEntry {
+    /// Returns the addresses to connect to in order, see [`order_srv_targets`] for SRV nodes.
+    fn addrs(&mut self, node: &Node) -> Vec<SocketAddr> {
+        if !node.srv {
+            return self.targets.iter().map(|target| target.addr).collect();
+        }
+        let addrs = order_srv_targets(&self.targets, self.picks);
+        self.picks += 1;
+        addrs
+    }
+}
+
+/// Orders the targets of SRV records for the `pick`-th connection: a target of the lowest
+/// priority first, chosen so that the targets of that priority are first in proportion to their
+/// weight, then the other targets by priority.
+///
+/// Targets of a priority whose weights are all zero are first in turn.
+fn order_srv_targets(targets: &[Target], pick: u64) -> Vec<SocketAddr> {
+    let mut ordered = targets.to_vec();
+    ordered.sort_by_key(|target| target.priority);
+    let Some(first) = ordered.first() else {
+        return vec![];
+    };
+
+    let lowest = ordered
+        .iter()
+        .take_while(|target| target.priority == first.priority)
+        .count();
+    let all_zero = ordered[..lowest].iter().all(|target| target.weight == 0);
+    let weight = |target: &Target| {
+        if all_zero {
+            1
+        } else {
+            u64::from(target.weight)
+        }
+    };
+    let total: u64 = ordered[..lowest].iter().map(weight).sum();
+    let mut remaining = pick % total;
+    let chosen = ordered[..lowest]
+        .iter()
+        .position(|target| {
+            let chosen = remaining < weight(target);
+            remaining = remaining.saturating_sub(weight(target));
+            chosen
+        })
+        .unwrap_or(0);
+    let chosen = ordered.remove(chosen);
+    ordered.insert(0, chosen);
+
+    ordered.into_iter().map(|target| target.addr).collect()
+}
+
+fn parse_ip_literal(host: &str) -> Option<IpAddr> {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::{DnsCache, Resolve, ResolveFuture, ResolveSrvFuture, Target};
+    use crate::load_balance::node::Node;
+
+    /// Resolver answering the SRV records it holds, which tests may replace.
+    struct SrvResolver {
+        targets: Arc<std::sync::Mutex<Vec<Target>>>,
+    }
+
+    impl Resolve for SrvResolver {
+        fn resolve<'a>(&'a self, host: &'a str, _port: u16) -> ResolveFuture<'a> {
+            Box::pin(async move { panic!("{host} should be resolved as SRV records") })
+        }
+
+        fn resolve_srv<'a>(&'a self, _name: &'a str) -> ResolveSrvFuture<'a> {
+            let targets = self.targets.lock().unwrap().clone();
+            Box::pin(async move { Ok(targets) })
+        }
+    }
+
+    fn target(last_octet: u8, priority: u16, weight: u16) -> Target {
+        Target {
+            addr: SocketAddr::from(([10, 0, 0, last_octet], 8000 + u16::from(last_octet))),
+            priority,
+            weight,
+        }
+    }
+
+    fn srv_cache(targets: Vec<Target>) -> (DnsCache, Arc<std::sync::Mutex<Vec<Target>>>) {
+        let targets = Arc::new(std::sync::Mutex::new(targets));
+        let resolver = SrvResolver {
+            targets: targets.clone(),
+        };
+        let cache = DnsCache::with_resolver(Box::new(resolver), Some(Duration::from_secs(30)));
+        (cache, targets)
+    }
+
+    /// Resolver that counts lookups and always answers with the same address.
+    struct CountingResolver {
+        lookups: Arc<AtomicUsize>,
+    }
+
+    impl Resolve for CountingResolver {
+        fn resolve<'a>(&'a self, _host: &'a str, port: u16) -> ResolveFuture<'a> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move { Ok(vec![SocketAddr::from(([10, 0, 0, 1], port))]) })
+        }
+    }
+
+    struct FailingResolver;
+
+    impl Resolve for FailingResolver {
+        fn resolve<'a>(&'a self, host: &'a str, _port: u16) -> ResolveFuture<'a> {
+            Box::pin(async move {
+                Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no such host {host}"),
+                ))
+            })
+        }
+    }
+
+    /// Resolver answering with `10.0.0.<address>`, or failing while `failing` is set.
+    struct SwitchableResolver {
+        failing: Arc<AtomicBool>,
+        address: Arc<AtomicU8>,
+    }
+
+    impl Resolve for SwitchableResolver {
+        fn resolve<'a>(&'a self, host: &'a str, port: u16) -> ResolveFuture<'a> {
+            let failing = self.failing.load(Ordering::SeqCst);
+            let address = self.address.load(Ordering::SeqCst);
+            Box::pin(async move {
+                if failing {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("resolver timed out for {host}"),
+                    ));
+                }
+                Ok(vec![SocketAddr::from(([10, 0, 0, address], port))])
+            })
+        }
+    }
+
+    fn switchable_cache(stale_max: Duration) -> (DnsCache, Arc<AtomicBool>, Arc<AtomicU8>) {
+        let failing = Arc::new(AtomicBool::new(false));
+        let address = Arc::new(AtomicU8::new(1));
+        let resolver = SwitchableResolver {
+            failing: failing.clone(),
+            address: address.clone(),
+        };
+        let cache = DnsCache::with_resolver(Box::new(resolver), Some(Duration::from_secs(30)))
+            .with_stale_max(Some(stale_max));
+        (cache, failing, address)
+    }
+
+    fn counting_cache(ttl: Duration) -> (DnsCache, Arc<AtomicUsize>) {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let resolver = CountingResolver {
+            lookups: lookups.clone(),
+        };
+        (
+            DnsCache::with_resolver(Box::new(resolver), Some(ttl)),
+            lookups,
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lookup_is_cached_within_ttl() {
+        let (cache, lookups) = counting_cache(Duration::from_secs(30));
+        let node: Node = "backend.internal:8080".parse().unwrap();
+
+        for _ in 0..10 {
+            let addrs = cache.lookup(&node).await.unwrap();
+            assert_eq!(addrs, vec![SocketAddr::from(([10, 0, 0, 1], 8080))]);
+        }
+        tokio::time::advance(Duration::from_secs(29)).await;
+        cache.lookup(&node).await.unwrap();
+
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lookup_re_resolves_after_ttl_expires() {
+        let (cache, lookups) = counting_cache(Duration::from_secs(30));
+        let node: Node = "backend.internal:8080".parse().unwrap();
+
+        cache.lookup(&node).await.unwrap();
+        tokio::time::advance(Duration::from_secs(31)).await;
+        cache.lookup(&node).await.unwrap();
+
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_lookup_does_not_resolve_ip_literals() {
+        let (cache, lookups) = counting_cache(Duration::from_secs(30));
+
+        let addrs = cache
+            .lookup(&"127.0.0.1:9000".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(addrs, vec!["127.0.0.1:9000".parse().unwrap()]);
+
+        let addrs = cache.lookup(&"[::1]:9000".parse().unwrap()).await.unwrap();
+        assert_eq!(addrs, vec!["[::1]:9000".parse().unwrap()]);
+
+        assert_eq!(lookups.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_lookup_error_is_returned() {
+        let cache = DnsCache::with_resolver(Box::new(FailingResolver), None);
+        let result = cache.lookup(&"missing.internal:80".parse().unwrap()).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lookup_keeps_last_addresses_while_resolution_fails() {
+        let (cache, failing, address) = switchable_cache(Duration::from_secs(3600));
+        let node: Node = "backend.internal:8080".parse().unwrap();
+        let addr = |last_octet| vec![SocketAddr::from(([10, 0, 0, last_octet], 8080))];
+
+        assert_eq!(cache.lookup(&node).await.unwrap(), addr(1));
+        assert_eq!(cache.staleness(&node), None);
+
+        failing.store(true, Ordering::SeqCst);
+        tokio::time::advance(Duration::from_secs(31)).await;
+        assert_eq!(cache.lookup(&node).await.unwrap(), addr(1));
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert_eq!(cache.lookup(&node).await.unwrap(), addr(1));
+        assert_eq!(cache.staleness(&node), Some(Duration::from_secs(60)));
+        assert!(!cache.is_degraded(&node));
+
+        failing.store(false, Ordering::SeqCst);
+        address.store(2, Ordering::SeqCst);
+        tokio::time::advance(Duration::from_secs(31)).await;
+        assert_eq!(cache.lookup(&node).await.unwrap(), addr(2));
+        assert_eq!(cache.staleness(&node), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lookup_fails_once_stale_for_longer_than_stale_max() {
+        let (cache, failing, _) = switchable_cache(Duration::from_secs(300));
+        let node: Node = "backend.internal:8080".parse().unwrap();
+
+        cache.lookup(&node).await.unwrap();
+        failing.store(true, Ordering::SeqCst);
+        tokio::time::advance(Duration::from_secs(31)).await;
+        cache.lookup(&node).await.unwrap();
+
+        tokio::time::advance(Duration::from_secs(300)).await;
+        assert!(cache.lookup(&node).await.is_err());
+        assert!(cache.is_degraded(&node));
+
+        failing.store(false, Ordering::SeqCst);
+        cache.lookup(&node).await.unwrap();
+        assert!(!cache.is_degraded(&node));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failing_resolution_is_warned_once() {
+        let (cache, failing, _) = switchable_cache(Duration::from_secs(3600));
+        let node: Node = "backend.internal:8080".parse().unwrap();
+        cache.lookup(&node).await.unwrap();
+
+        failing.store(true, Ordering::SeqCst);
+        tokio::time::advance(Duration::from_secs(31)).await;
+        cache.lookup(&node).await.unwrap();
+        let warned_at = cache.entries.lock().unwrap()[&node].warned_at;
+
+        for _ in 0..10 {
+            cache.lookup(&node).await.unwrap();
+        }
+        assert_eq!(cache.staleness(&node), Some(Duration::ZERO));
+        assert_eq!(cache.entries.lock().unwrap()[&node].warned_at, warned_at);
+    }
+
+    #[tokio::test]
+    async fn test_srv_lookup_picks_lowest_priority_targets_by_weight() {
+        let (cache, _) = srv_cache(vec![target(3, 20, 5), target(1, 10, 3), target(2, 10, 1)]);
+        let node = Node::srv("_http._tcp.backend.service.consul".to_string());
+
+        let mut firsts = vec![];
+        for _ in 0..8 {
+            let addrs = cache.lookup(&node).await.unwrap();
+            assert_eq!(addrs.len(), 3);
+            // the target of the higher priority is the last fallback
+            assert_eq!(addrs[2], target(3, 20, 5).addr);
+            firsts.push(addrs[0]);
+        }
+
+        let count = |last_octet| {
+            firsts
+                .iter()
+                .filter(|addr| **addr == target(last_octet, 0, 0).addr)
+                .count()
+        };
+        assert_eq!((count(1), count(2)), (6, 2));
+    }
+
+    #[tokio::test]
+    async fn test_srv_lookup_takes_turns_between_targets_without_weight() {
+        let (cache, _) = srv_cache(vec![target(1, 10, 0), target(2, 10, 0)]);
+        let node = Node::srv("_http._tcp.backend.service.consul".to_string());
+
+        let first = cache.lookup(&node).await.unwrap();
+        let second = cache.lookup(&node).await.unwrap();
+
+        assert_eq!(first, vec![target(1, 0, 0).addr, target(2, 0, 0).addr]);
+        assert_eq!(second, vec![target(2, 0, 0).addr, target(1, 0, 0).addr]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_srv_lookup_tracks_record_changes_after_ttl() {
+        let (cache, targets) = srv_cache(vec![target(1, 10, 1)]);
+        let node = Node::srv("_http._tcp.backend.service.consul".to_string());
+        assert_eq!(
+            cache.lookup(&node).await.unwrap(),
+            vec![target(1, 0, 0).addr]
+        );
+
+        *targets.lock().unwrap() = vec![target(2, 10, 1)];
+        tokio::time::advance(Duration::from_secs(29)).await;
+        assert_eq!(
+            cache.lookup(&node).await.unwrap(),
+            vec![target(1, 0, 0).addr]
+        );
+
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(
+            cache.lookup(&node).await.unwrap(),
+            vec![target(2, 0, 0).addr]
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! # PassiveHealth
+//!
+//! Takes upstreams out of rotation after repeated failed requests, without probing them.
+//!
+//! - A request fails when the upstream cannot be reached or answers with a `5xx` status, any
+//!   other response resets the count of its upstream.
+//! - After `max_fails` failures in a row the upstream is ejected for `fail_timeout`.
+//! - Once the timeout expired the upstream is tried again, a single failure ejects it again.
+
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::load_balance::node::Node;
+
+pub struct PassiveHealth {
+    max_fails: u32,
+    fail_timeout: Duration,
+    nodes: Mutex<HashMap<Node, NodeHealth>>,
+}
+
+#[derive(Default)]
+struct NodeHealth {
+    /// Failed requests in a row.
+    fails: u32,
+    /// Set while the node is out of rotation.
+    ejected_until: Option<Instant>,
+}
+
+impl PassiveHealth {
+    pub const DEFAULT_FAIL_TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub fn new(max_fails: u32, fail_timeout: Option<Duration>) -> Self {
+        Self {
+            max_fails,
+            fail_timeout: fail_timeout.unwrap_or(PassiveHealth::DEFAULT_FAIL_TIMEOUT),
+            nodes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns true when requests may be sent to the node.
+    pub fn is_available(&self, node: &Node) -> bool {
+        let mut nodes = self.nodes.lock().unwrap();
+        let Some(health) = nodes.get_mut(node) else {
+            return true;
+        };
+        match health.ejected_until {
+            Some(until) if Instant::now() < until => false,
+            Some(_) => {
+                // on probation, the next failure ejects it again
+                health.ejected_until = None;
+                health.fails = self.max_fails - 1;
+                true
+            }
+            None => true,
+        }
+    }
+
+    pub fn record_failure(&self, node: &Node) {
+        let mut nodes = self.nodes.lock().unwrap();
+        let health = nodes.entry(node.clone()).or_default();
+        health.fails += 1;
+        if health.fails >= self.max_fails && health.ejected_until.is_none() {
+            warn!(
+                "upstream {} failed {} times in a row, out of rotation for {:?}",
+                node, health.fails, self.fail_timeout
+            );
+            health.ejected_until = Some(Instant::now() + self.fail_timeout);
+        }
+    }
+
+    pub fn record_success(&self, node: &Node) {
+        self.nodes.lock().unwrap().remove(node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::PassiveHealth;
+    use crate::load_balance::node::Node;
+
+    fn node() -> Node {
+        "127.0.0.1:8080".parse().unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_node_is_ejected_after_max_fails() {
+        let health = PassiveHealth::new(3, Some(Duration::from_secs(30)));
+
+        health.record_failure(&node());
+        health.record_failure(&node());
+        assert!(health.is_available(&node()));
+
+        health.record_failure(&node());
+        assert!(!health.is_available(&node()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_success_resets_failures() {
+        let health = PassiveHealth::new(2, None);
+
+        health.record_failure(&node());
+        health.record_success(&node());
+        health.record_failure(&node());
+
+        assert!(health.is_available(&node()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_node_is_tried_again_after_fail_timeout() {
+        let health = PassiveHealth::new(3, Some(Duration::from_secs(30)));
+        for _ in 0..3 {
+            health.record_failure(&node());
+        }
+
+        tokio::time::advance(Duration::from_secs(29)).await;
+        assert!(!health.is_available(&node()));
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert!(health.is_available(&node()));
+
+        // a single failure while on probation ejects it again
+        health.record_failure(&node());
+        assert!(!health.is_available(&node()));
+    }
+}
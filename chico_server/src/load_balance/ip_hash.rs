@@ -0,0 +1,158 @@
+//! # IpHashBalancer
+//!
+//! Sends the requests of a client to the same upstream `Node`, for `lb_policy ip_hash`, so
+//! upstreams keeping per-client state see all the requests of their clients.
+//!
+//! - The node is the hash of the client address modulo the number of nodes, the same for all
+//!   the workers and across restarts.
+//! - When that node is out of rotation the next ones are tried in order, see
+//!   [`LoadBalance::acquire_node_for`], so its clients move together to the same fallback.
+//! - Adding or removing a node redistributes the clients between the new set of nodes.
+
+use std::{net::IpAddr, sync::Arc};
+
+use crate::load_balance::{least_connections::InFlight, node::Node, LoadBalance};
+
+pub struct IpHashBalancer {
+    nodes: Arc<[Arc<Node>]>,
+}
+
+impl IpHashBalancer {
+    pub fn new(nodes: Vec<Node>) -> Self {
+        let nodes: Vec<Arc<Node>> = nodes.into_iter().map(Arc::new).collect();
+        Self {
+            nodes: nodes.into(),
+        }
+    }
+
+    /// Returns the node of `client` for the attempt of the same index, requests without a known
+    /// client are sent to the first node.
+    fn node_for(&self, client: Option<IpAddr>, attempt: usize) -> Option<Arc<Node>> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let hash = client.map_or(0, hash_ip) as usize;
+        let index = hash.wrapping_add(attempt) % self.nodes.len();
+        Some(self.nodes[index].clone())
+    }
+}
+
+impl LoadBalance for IpHashBalancer {
+    fn get_node(&self) -> Option<Arc<Node>> {
+        self.node_for(None, 0)
+    }
+
+    fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn acquire_node_for(
+        &self,
+        client: Option<IpAddr>,
+        attempt: usize,
+    ) -> Option<(Arc<Node>, Option<InFlight>)> {
+        self.node_for(client, attempt).map(|node| (node, None))
+    }
+}
+
+/// FNV-1a of the octets of the address, stable unlike the hasher of the standard library.
+fn hash_ip(ip: IpAddr) -> u32 {
+    let octets = match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    octets.iter().fold(0x811c_9dc5, |hash, octet| {
+        (hash ^ u32::from(*octet)).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, net::IpAddr};
+
+    use super::IpHashBalancer;
+    use crate::load_balance::{node::Node, LoadBalance};
+
+    fn nodes(count: u8) -> Vec<Node> {
+        (1..=count)
+            .map(|i| Node::new(format!("10.0.0.{i}"), 80))
+            .collect()
+    }
+
+    fn clients() -> Vec<IpAddr> {
+        (0..=255)
+            .map(|i| IpAddr::from([192, 0, 2, i]))
+            .chain([
+                "2001:db8::1".parse().unwrap(),
+                "2001:db8::2".parse().unwrap(),
+            ])
+            .collect()
+    }
+
+    fn pick(balancer: &IpHashBalancer, client: IpAddr, attempt: usize) -> Node {
+        let (node, _) = balancer.acquire_node_for(Some(client), attempt).unwrap();
+        (*node).clone()
+    }
+
+    #[test]
+    fn test_client_is_always_sent_to_the_same_node() {
+        let balancer = IpHashBalancer::new(nodes(3));
+        let other = IpHashBalancer::new(nodes(3));
+
+        for client in clients() {
+            let node = pick(&balancer, client, 0);
+            for _ in 0..10 {
+                assert_eq!(pick(&balancer, client, 0), node);
+            }
+            // the mapping does not depend on the balancer instance
+            assert_eq!(pick(&other, client, 0), node);
+        }
+    }
+
+    #[test]
+    fn test_clients_are_spread_over_all_nodes() {
+        let balancer = IpHashBalancer::new(nodes(3));
+
+        let picked: HashSet<Node> = clients()
+            .into_iter()
+            .map(|client| pick(&balancer, client, 0))
+            .collect();
+
+        assert_eq!(picked, nodes(3).into_iter().collect());
+    }
+
+    #[test]
+    fn test_next_attempts_fall_back_to_the_next_nodes_in_order() {
+        let all = nodes(3);
+        let balancer = IpHashBalancer::new(all.clone());
+
+        for client in clients() {
+            let first = pick(&balancer, client, 0);
+            let first = all.iter().position(|node| *node == first).unwrap();
+            for attempt in 0..3 {
+                assert_eq!(pick(&balancer, client, attempt), all[(first + attempt) % 3]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_clients_of_removed_node_are_redistributed() {
+        let all = nodes(3);
+        let before = IpHashBalancer::new(all.clone());
+        let after = IpHashBalancer::new(all[..2].to_vec());
+
+        let moved: HashSet<Node> = clients()
+            .into_iter()
+            .filter(|client| pick(&before, *client, 0) == all[2])
+            .map(|client| pick(&after, client, 0))
+            .collect();
+
+        assert_eq!(moved, all[..2].iter().cloned().collect());
+    }
+
+    #[test]
+    fn test_no_node_without_nodes() {
+        let balancer = IpHashBalancer::new(vec![]);
+        assert!(balancer.acquire_node_for(None, 0).is_none());
+    }
+}
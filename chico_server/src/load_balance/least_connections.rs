@@ -0,0 +1,153 @@
+//! # LeastConnectionsBalancer
+//!
+//! Sends each request to the upstream with the fewest requests in flight, so a slow upstream
+//! receives less traffic than with round-robin.
+//!
+//! - Requests are counted while the [`InFlight`] guard returned by
+//!   [`LoadBalance::acquire_node`] is alive.
+//! - Upstreams with the same count are picked in round-robin order.
+//! - The counts can be shared with a previous balancer of the same pool, see
+//!   [`super::pool::PoolStates`].
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use crate::load_balance::{node::Node, LoadBalance};
+
+pub struct LeastConnectionsBalancer {
+    nodes: Arc<[Arc<Node>]>,
+    /// Requests in flight to the node of the same index.
+    active: Arc<[AtomicUsize]>,
+    /// Rotation used to break ties.
+    counter: Arc<AtomicUsize>,
+}
+
+impl LeastConnectionsBalancer {
+    pub fn new(nodes: Vec<Node>) -> Self {
+        let active = (0..nodes.len()).map(|_| AtomicUsize::new(0)).collect();
+        Self::with_state(nodes, active, Arc::new(AtomicUsize::new(0)))
+    }
+
+    /// Creates a balancer that continues counting the requests in flight and the rotation of a
+    /// previous balancer of the same nodes.
+    pub fn with_state(
+        nodes: Vec<Node>,
+        active: Arc<[AtomicUsize]>,
+        counter: Arc<AtomicUsize>,
+    ) -> Self {
+        assert_eq!(nodes.len(), active.len(), "one count per node");
+        let nodes: Vec<Arc<Node>> = nodes.into_iter().map(Arc::new).collect();
+        Self {
+            nodes: nodes.into(),
+            active,
+            counter,
+        }
+    }
+
+    /// Returns the index of the node with the fewest requests in flight, the first one in
+    /// rotation order on ties.
+    fn least_loaded(&self) -> Option<usize> {
+        let len = self.nodes.len();
+        if len == 0 {
+            return None;
+        }
+        let start = self.counter.fetch_add(1, Ordering::Relaxed) % len;
+        (0..len)
+            .map(|offset| (start + offset) % len)
+            .min_by_key(|&index| self.active[index].load(Ordering::Relaxed))
+    }
+
+    /// Returns the number of requests in flight to each node, in the order of the nodes.
+    pub fn in_flight(&self) -> Vec<usize> {
+        self.active
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect()
+    }
+}
+
+impl LoadBalance for LeastConnectionsBalancer {
+    fn get_node(&self) -> Option<Arc<Node>> {
+        self.least_loaded().map(|index| self.nodes[index].clone())
+    }
+
+    fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn acquire_node(&self) -> Option<(Arc<Node>, Option<InFlight>)> {
+        let index = self.least_loaded()?;
+        self.active[index].fetch_add(1, Ordering::Relaxed);
+        let in_flight = InFlight {
+            active: self.active.clone(),
+            index,
+        };
+        Some((self.nodes[index].clone(), Some(in_flight)))
+    }
+}
+
+/// Counts a request as in flight to its node until dropped.
+pub struct InFlight {
+    active: Arc<[AtomicUsize]>,
+    index: usize,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.active[self.index].fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::LeastConnectionsBalancer;
+    use crate::load_balance::{node::Node, LoadBalance};
+
+    fn nodes(count: usize) -> Vec<Node> {
+        (0..count)
+            .map(|i| Node::new(format!("10.0.0.{i}"), 80))
+            .collect()
+    }
+
+    #[test]
+    fn test_picks_node_with_fewest_requests_in_flight() {
+        let balancer = LeastConnectionsBalancer::new(nodes(3));
+
+        let (first, _first) = balancer.acquire_node().unwrap();
+        let (second, _second) = balancer.acquire_node().unwrap();
+        let (third, third_guard) = balancer.acquire_node().unwrap();
+        assert_eq!(balancer.in_flight(), vec![1, 1, 1]);
+        assert_ne!(first, second);
+        assert_ne!(second, third);
+
+        // only the third node is idle once its request completes
+        drop(third_guard);
+        for _ in 0..5 {
+            assert_eq!(balancer.get_node(), Some(third.clone()));
+        }
+    }
+
+    #[test]
+    fn test_ties_are_broken_in_round_robin_order() {
+        let nodes = nodes(3);
+        let balancer = LeastConnectionsBalancer::new(nodes.clone());
+
+        for _ in 0..3 {
+            for node in &nodes {
+                assert_eq!(balancer.get_node(), Some(Arc::new(node.clone())));
+            }
+        }
+        assert_eq!(balancer.in_flight(), vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn test_empty_nodes() {
+        let balancer = LeastConnectionsBalancer::new(vec![]);
+        assert!(balancer.get_node().is_none());
+        assert!(balancer.acquire_node().is_none());
+    }
+}
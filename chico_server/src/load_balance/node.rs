@@ -1,31 +1,110 @@
-use std::{
-    net::{AddrParseError, SocketAddr},
-    str::FromStr,
-};
+use std::{fmt::Display, net::SocketAddr, str::FromStr};
 
+/// An upstream target, identified by a host (hostname or IP literal) and a port.
+///
+/// Hostnames are resolved lazily when connecting, see [`crate::load_balance::dns::DnsCache`].
 #[allow(dead_code)]
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
 pub struct Node {
-    pub addr: SocketAddr,
+    pub host: String,
+    pub port: u16,
+    /// The host is the name of DNS SRV records, their targets are connected to instead.
+    pub srv: bool,
 }
 
 #[allow(dead_code)]
 impl Node {
-    pub fn new(addr: SocketAddr) -> Self {
-        Self { addr }
+    pub fn new(host: String, port: u16) -> Self {
+        Self {
+            host,
+            port,
+            srv: false,
+        }
+    }
+
+    /// Creates the node of the targets of the SRV records of `name`.
+    pub fn srv(name: String) -> Self {
+        Self {
+            host: name,
+            port: 0,
+            srv: true,
+        }
+    }
+}
+
+impl Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.srv {
+            return write!(f, "srv://{}", self.host);
+        }
+        write!(f, "{}:{}", self.host, self.port)
     }
 }
 
 impl From<SocketAddr> for Node {
     fn from(value: SocketAddr) -> Self {
-        Node::new(value)
+        let host = match value {
+            SocketAddr::V4(addr) => addr.ip().to_string(),
+            SocketAddr::V6(addr) => format!("[{}]", addr.ip()),
+        };
+        Node::new(host, value.port())
     }
 }
 
 impl FromStr for Node {
-    type Err = AddrParseError;
+    type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        s.parse::<SocketAddr>().map(|addr| addr.into())
+        let Some((host, port)) = s.rsplit_once(':') else {
+            return Err(format!("missing port in upstream address '{s}'"));
+        };
+
+        if host.is_empty() {
+            return Err(format!("missing host in upstream address '{s}'"));
+        }
+
+        let port = port
+            .parse::<u16>()
+            .map_err(|e| format!("invalid port in upstream address '{s}': {e}"))?;
+
+        Ok(Node::new(host.to_string(), port))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::Node;
+
+    #[rstest]
+    #[case("127.0.0.1:80", "127.0.0.1", 80)]
+    #[case("localhost:3000", "localhost", 3000)]
+    #[case("backend.internal:8080", "backend.internal", 8080)]
+    #[case("[::1]:9000", "[::1]", 9000)]
+    fn test_node_from_str(#[case] input: &str, #[case] host: &str, #[case] port: u16) {
+        let node: Node = input.parse().unwrap();
+        assert_eq!(node, Node::new(host.to_string(), port));
+        assert_eq!(node.to_string(), input);
+    }
+
+    #[rstest]
+    #[case("localhost")]
+    #[case(":80")]
+    #[case("localhost:http")]
+    fn test_node_from_str_invalid(#[case] input: &str) {
+        assert!(input.parse::<Node>().is_err());
+    }
+
+    #[test]
+    fn test_srv_node_display() {
+        let node = Node::srv("_http._tcp.backend.service.consul".to_string());
+        assert_eq!(node.to_string(), "srv://_http._tcp.backend.service.consul");
+    }
+
+    #[test]
+    fn test_node_from_socket_addr() {
+        let node: Node = "[::1]:9000".parse::<std::net::SocketAddr>().unwrap().into();
+        assert_eq!(node.to_string(), "[::1]:9000");
     }
 }
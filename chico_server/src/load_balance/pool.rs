@@ -0,0 +1,138 @@
+//! # PoolStates
+//!
+//! Keeps the selection state of the load balancers alive across plan rebuilds.
+//!
+//! A rebuilt plan creates new balancers. Without shared state every balancer would start its
+//! rotation from the first upstream again, so frequent rebuilds would skew the traffic towards
+//! it. Balancers of an unchanged pool of upstreams (same nodes, same order) pick up the counter of
+//! the balancer they replace instead. Least connections balancers also pick up the counts of the
+//! requests still in flight, weighted round-robin balancers the current weights.
+
+use std::{
+    collections::HashMap,
+    sync::{atomic::AtomicUsize, Arc, Mutex, Weak},
+};
+
+use crate::load_balance::{node::Node, weighted_round_robin::CurrentWeights};
+
+#[derive(Default)]
+pub struct PoolStates {
+    counters: Mutex<HashMap<Vec<Node>, Weak<AtomicUsize>>>,
+    /// Requests in flight to each node of a least connections pool.
+    active: Mutex<HashMap<Vec<Node>, Weak<[AtomicUsize]>>>,
+    /// Current weights of the nodes of a weighted round-robin pool.
+    current_weights: Mutex<HashMap<Vec<Node>, Weak<CurrentWeights>>>,
+}
+
+impl PoolStates {
+    /// Returns the rotation counter of the given pool.
+    ///
+    /// The counter is shared as long as a balancer of the pool holds it; once every balancer of
+    /// the pool is dropped, the next call starts a new rotation.
+    pub fn counter(&self, nodes: &[Node]) -> Arc<AtomicUsize> {
+        let mut counters = self.counters.lock().unwrap();
+        counters.retain(|_, counter| counter.strong_count() > 0);
+
+        if let Some(counter) = counters.get(nodes).and_then(Weak::upgrade) {
+            return counter;
+        }
+
+        let counter = Arc::new(AtomicUsize::new(0));
+        counters.insert(nodes.to_vec(), Arc::downgrade(&counter));
+        counter
+    }
+
+    /// Returns the counts of requests in flight to each node of the given pool.
+    ///
+    /// Requests still in flight to a replaced balancer keep counting for the new one.
+    pub fn in_flight(&self, nodes: &[Node]) -> Arc<[AtomicUsize]> {
+        let mut active = self.active.lock().unwrap();
+        active.retain(|_, counts| counts.strong_count() > 0);
+
+        if let Some(counts) = active.get(nodes).and_then(Weak::upgrade) {
+            return counts;
+        }
+
+        let counts: Arc<[AtomicUsize]> = nodes.iter().map(|_| AtomicUsize::new(0)).collect();
+        active.insert(nodes.to_vec(), Arc::downgrade(&counts));
+        counts
+    }
+
+    /// Returns the current weights of the nodes of the given pool.
+    pub fn current_weights(&self, nodes: &[Node]) -> Arc<CurrentWeights> {
+        let mut current_weights = self.current_weights.lock().unwrap();
+        current_weights.retain(|_, current| current.strong_count() > 0);
+
+        if let Some(current) = current_weights.get(nodes).and_then(Weak::upgrade) {
+            return current;
+        }
+
+        let current = Arc::new(Mutex::new(vec![0; nodes.len()]));
+        current_weights.insert(nodes.to_vec(), Arc::downgrade(&current));
+        current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::Ordering, Arc};
+
+    use crate::load_balance::node::Node;
+
+    use super::PoolStates;
+
+    fn nodes(addrs: &[&str]) -> Vec<Node> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_same_pool_shares_counter() {
+        let states = PoolStates::default();
+        let pool = nodes(&["127.0.0.1:80", "127.0.0.1:81"]);
+
+        let first = states.counter(&pool);
+        first.store(7, Ordering::Relaxed);
+        let second = states.counter(&pool);
+
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn test_changed_pool_gets_new_counter() {
+        let states = PoolStates::default();
+
+        let first = states.counter(&nodes(&["127.0.0.1:80", "127.0.0.1:81"]));
+        let reordered = states.counter(&nodes(&["127.0.0.1:81", "127.0.0.1:80"]));
+        let extended = states.counter(&nodes(&["127.0.0.1:80", "127.0.0.1:81", "127.0.0.1:82"]));
+
+        assert!(!Arc::ptr_eq(&first, &reordered));
+        assert!(!Arc::ptr_eq(&first, &extended));
+    }
+
+    #[test]
+    fn test_counter_is_released_when_unused() {
+        let states = PoolStates::default();
+        let pool = nodes(&["127.0.0.1:80"]);
+
+        let counter = states.counter(&pool);
+        counter.store(3, Ordering::Relaxed);
+        drop(counter);
+
+        assert_eq!(states.counter(&pool).load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_same_pool_shares_in_flight_counts() {
+        let states = PoolStates::default();
+        let pool = nodes(&["127.0.0.1:80", "127.0.0.1:81"]);
+
+        let first = states.in_flight(&pool);
+        first[1].store(2, Ordering::Relaxed);
+        let second = states.in_flight(&pool);
+
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(second.len(), 2);
+        drop((first, second));
+        assert_eq!(states.in_flight(&pool)[1].load(Ordering::Relaxed), 0);
+    }
+}
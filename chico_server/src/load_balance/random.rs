@@ -0,0 +1,98 @@
+//! # RandomBalancer
+//!
+//! Picks an upstream `Node` at random for each request, for `lb_policy random`.
+//!
+//! - Nothing is shared between the requests: each worker thread draws from its own generator,
+//!   seeded from the operating system when the thread first picks a node.
+//! - Over many requests the nodes get an even share, without the lockstep of round-robin when
+//!   several instances balance the same pool.
+
+use std::{cell::RefCell, sync::Arc};
+
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::load_balance::{node::Node, LoadBalance};
+
+thread_local! {
+    /// Generator of the current worker thread.
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_os_rng());
+}
+
+pub struct RandomBalancer {
+    nodes: Arc<[Arc<Node>]>,
+}
+
+impl RandomBalancer {
+    pub fn new(nodes: Vec<Node>) -> Self {
+        let nodes: Vec<Arc<Node>> = nodes.into_iter().map(Arc::new).collect();
+        Self {
+            nodes: nodes.into(),
+        }
+    }
+}
+
+impl LoadBalance for RandomBalancer {
+    fn get_node(&self) -> Option<Arc<Node>> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let index = RNG.with_borrow_mut(|rng| rng.random_range(0..self.nodes.len()));
+        Some(self.nodes[index].clone())
+    }
+
+    fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::RandomBalancer;
+    use crate::load_balance::{node::Node, LoadBalance};
+
+    fn nodes(count: u8) -> Vec<Node> {
+        (1..=count)
+            .map(|i| Node::new(format!("10.0.0.{i}"), 80))
+            .collect()
+    }
+
+    #[test]
+    fn test_all_nodes_are_eventually_picked() {
+        let balancer = RandomBalancer::new(nodes(4));
+
+        let picked: HashSet<Node> = (0..1000)
+            .map(|_| (*balancer.get_node().unwrap()).clone())
+            .collect();
+
+        assert_eq!(picked, nodes(4).into_iter().collect());
+    }
+
+    #[test]
+    fn test_all_nodes_are_eventually_picked_from_each_worker() {
+        let balancer = std::sync::Arc::new(RandomBalancer::new(nodes(3)));
+
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let balancer = balancer.clone();
+                std::thread::spawn(move || {
+                    (0..1000)
+                        .map(|_| (*balancer.get_node().unwrap()).clone())
+                        .collect::<HashSet<Node>>()
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            assert_eq!(worker.join().unwrap(), nodes(3).into_iter().collect());
+        }
+    }
+
+    #[test]
+    fn test_no_node_without_nodes() {
+        let balancer = RandomBalancer::new(vec![]);
+        assert!(balancer.get_node().is_none());
+        assert_eq!(balancer.node_count(), 0);
+    }
+}
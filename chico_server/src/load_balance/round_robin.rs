@@ -5,9 +5,11 @@
 //! - Thread-safe via atomic counter.
 //! - Automatically resets counter when it exceeds a configured threshold to prevent overflow.
 //! - Uses `Arc<Node>` for efficient sharing.
+//! - The counter can be shared with a previous balancer of the same pool, see [`super::pool::PoolStates`].
 //!
 //! ## Example
 //! ```rust
+//! # use chico_server::load_balance::{round_robin::RoundRobinBalancer, LoadBalance};
 //! let nodes = vec![
 //!     "127.0.0.1:80".parse().unwrap(),
 //!     "1.0.0.1:9090".parse().unwrap(),
@@ -32,7 +34,7 @@ use crate::load_balance::{node::Node, LoadBalance};
 /// to avoid integer overflow.
 pub struct RoundRobinBalancer {
     nodes: Arc<[Arc<Node>]>,
-    counter: AtomicUsize,
+    counter: Arc<AtomicUsize>,
 }
 
 #[cfg(test)]
@@ -47,11 +49,18 @@ impl RoundRobinBalancer {
     ///
     /// Each node is internally wrapped in an `Arc` for cheap cloning and sharing.
     pub fn new(nodes: Vec<Node>) -> Self {
+        Self::with_counter(nodes, Arc::new(AtomicUsize::new(0)))
+    }
+
+    /// Creates a new `RoundRobinBalancer` that continues the rotation of the given counter.
+    ///
+    /// Used to keep the rotation going when the plan is rebuilt with the same pool of nodes.
+    pub fn with_counter(nodes: Vec<Node>, counter: Arc<AtomicUsize>) -> Self {
         let arc_nodes: Vec<Arc<Node>> = nodes.into_iter().map(Arc::new).collect();
 
         Self {
             nodes: arc_nodes.into(),
-            counter: AtomicUsize::new(0),
+            counter,
         }
     }
 
@@ -86,6 +95,10 @@ impl LoadBalance for RoundRobinBalancer {
     fn get_node(&self) -> Option<Arc<Node>> {
         self.next()
     }
+
+    fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
 }
 
 #[cfg(test)]
@@ -222,4 +235,54 @@ mod tests {
             );
         }
     }
+
+    /// Every node receives its share (±1) of `nodes * picks_per_node` picks made concurrently.
+    #[rstest::rstest]
+    #[case(1, 100, 4)]
+    #[case(2, 500, 8)]
+    #[case(3, 1000, 16)]
+    #[case(5, 777, 7)]
+    #[case(7, 1000, 3)]
+    #[case(16, 250, 32)]
+    fn test_concurrent_picks_are_evenly_distributed(
+        #[case] node_count: usize,
+        #[case] picks_per_node: usize,
+        #[case] threads: usize,
+    ) {
+        let nodes: Vec<Node> = (0..node_count)
+            .map(|i| Node::new(format!("10.0.0.{i}"), 80))
+            .collect();
+        let balancer = Arc::new(RoundRobinBalancer::new(nodes.clone()));
+        let counts = Arc::new(Mutex::new(std::collections::HashMap::<Node, usize>::new()));
+
+        let total = node_count * picks_per_node;
+        let handles: Vec<_> = (0..threads)
+            .map(|thread| {
+                let balancer = balancer.clone();
+                let counts = counts.clone();
+                // spread the picks over the threads, the first ones take the remainder
+                let picks = total / threads + usize::from(thread < total % threads);
+                std::thread::spawn(move || {
+                    for _ in 0..picks {
+                        let node = balancer.get_node().unwrap();
+                        *counts.lock().unwrap().entry((*node).clone()).or_default() += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let counts = counts.lock().unwrap();
+        assert_eq!(counts.values().sum::<usize>(), total);
+        for node in &nodes {
+            let count = counts.get(node).copied().unwrap_or_default();
+            assert!(
+                count.abs_diff(picks_per_node) <= 1,
+                "Node {node} got {count} picks, expected {picks_per_node}±1"
+            );
+        }
+    }
 }
@@ -0,0 +1,168 @@
+//! # WeightedRoundRobinBalancer
+//!
+//! Takes turns between the upstream `Node`s in proportion to their weights, with the smooth
+//! weighted round-robin of nginx: for weights `3:1` the picks go `a a b a`, rather than `a a a b`.
+//!
+//! - On each pick every node's current weight grows by its weight, the node with the highest
+//!   current weight is picked and its current weight drops by the total of the weights.
+//! - Over a cycle of `total` picks each node is picked `weight` times.
+//! - The current weights can be shared with a previous balancer of the same pool, see
+//!   [`super::pool::PoolStates`].
+
+use std::sync::{Arc, Mutex};
+
+use crate::load_balance::{node::Node, LoadBalance};
+
+/// Current weight of each node of a pool, in the order of the nodes.
+pub type CurrentWeights = Mutex<Vec<i64>>;
+
+pub struct WeightedRoundRobinBalancer {
+    nodes: Arc<[Arc<Node>]>,
+    weights: Vec<i64>,
+    /// Current weight of the node of the same index.
+    current: Arc<CurrentWeights>,
+}
+
+impl WeightedRoundRobinBalancer {
+    /// Creates a balancer from nodes and their weights, nodes of weight 0 are never picked.
+    pub fn new(nodes: Vec<(Node, u32)>) -> Self {
+        let current = Arc::new(Mutex::new(vec![0; nodes.len()]));
+        Self::with_state(nodes, current)
+    }
+
+    /// Creates a balancer that continues the rotation of a previous balancer of the same nodes.
+    pub fn with_state(nodes: Vec<(Node, u32)>, current: Arc<CurrentWeights>) -> Self {
+        assert_eq!(
+            nodes.len(),
+            current.lock().unwrap().len(),
+            "one current weight per node"
+        );
+        let (nodes, weights): (Vec<Arc<Node>>, Vec<i64>) = nodes
+            .into_iter()
+            .map(|(node, weight)| (Arc::new(node), i64::from(weight)))
+            .unzip();
+        Self {
+            nodes: nodes.into(),
+            weights,
+            current,
+        }
+    }
+
+    fn next(&self) -> Option<Arc<Node>> {
+        let total: i64 = self.weights.iter().sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut current = self.current.lock().unwrap();
+        for (current, weight) in current.iter_mut().zip(&self.weights) {
+            *current += weight;
+        }
+        // the first of the highest on ties
+        let (index, _) = current
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| self.weights[*index] > 0)
+            .rev()
+            .max_by_key(|(_, current)| **current)?;
+        current[index] -= total;
+
+        Some(self.nodes[index].clone())
+    }
+}
+
+impl LoadBalance for WeightedRoundRobinBalancer {
+    fn get_node(&self) -> Option<Arc<Node>> {
+        self.next()
+    }
+
+    fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use rstest::rstest;
+
+    use super::WeightedRoundRobinBalancer;
+    use crate::load_balance::{node::Node, LoadBalance};
+
+    fn node(i: u8) -> Node {
+        Node::new(format!("10.0.0.{i}"), 80)
+    }
+
+    fn picks(balancer: &WeightedRoundRobinBalancer, count: usize) -> Vec<Node> {
+        (0..count)
+            .map(|_| (*balancer.get_node().unwrap()).clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_picks_are_smooth() {
+        let balancer = WeightedRoundRobinBalancer::new(vec![(node(1), 3), (node(2), 1)]);
+
+        assert_eq!(
+            picks(&balancer, 8),
+            vec![
+                node(1),
+                node(1),
+                node(2),
+                node(1),
+                node(1),
+                node(1),
+                node(2),
+                node(1)
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case(vec![3, 1])]
+    #[case(vec![5, 1, 1])]
+    #[case(vec![2, 3, 4])]
+    #[case(vec![1, 0, 2])]
+    fn test_cycle_follows_weights(#[case] weights: Vec<u32>) {
+        let nodes = weights
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| (node(i as u8), *weight))
+            .collect();
+        let balancer = WeightedRoundRobinBalancer::new(nodes);
+        let total: u32 = weights.iter().sum();
+
+        let mut counts = HashMap::<Node, u32>::new();
+        for picked in picks(&balancer, total as usize * 10) {
+            *counts.entry(picked).or_default() += 1;
+        }
+
+        for (i, weight) in weights.iter().enumerate() {
+            assert_eq!(
+                counts.get(&node(i as u8)).copied().unwrap_or_default(),
+                weight * 10
+            );
+        }
+    }
+
+    #[test]
+    fn test_no_node_without_weight() {
+        let balancer = WeightedRoundRobinBalancer::new(vec![(node(1), 0)]);
+        assert!(balancer.get_node().is_none());
+    }
+
+    #[test]
+    fn test_shared_state_continues_rotation() {
+        let current = Arc::new(std::sync::Mutex::new(vec![0, 0]));
+        let first = WeightedRoundRobinBalancer::with_state(
+            vec![(node(1), 2), (node(2), 1)],
+            current.clone(),
+        );
+        assert_eq!(picks(&first, 1), vec![node(1)]);
+
+        let second =
+            WeightedRoundRobinBalancer::with_state(vec![(node(1), 2), (node(2), 1)], current);
+        assert_eq!(picks(&second, 2), vec![node(2), node(1)]);
+    }
+}
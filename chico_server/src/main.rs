@@ -1,38 +1,84 @@
 #![cfg_attr(feature = "strict", deny(warnings))]
+use chico_file::diff::{config_hash, diff};
+use chico_server::{
+    cli,
+    config::{validate_config_file, ConfigExt, ValidationOptions},
+    plan::ServerPlan,
+    reload::{self, read_pid_file, write_pid_file, PlanHandle},
+    server,
+    server::run_server,
+};
 use clap::Parser;
-use config::validate_config_file;
-use server::run_server;
-use std::process::ExitCode;
-mod cli;
-mod config;
-mod handlers;
-mod load_balance;
-mod plan;
-mod server;
-#[cfg(test)]
-mod test_utils;
-mod virtual_host;
+use crates_tracing::LogRotation;
+use std::{path::Path, process::ExitCode, sync::Arc};
 #[tokio::main]
 async fn main() -> ExitCode {
-    crates_tracing::init("chico.log".to_string(), "chico".to_string());
-
     let cli = cli::Cli::parse();
+
+    let log_rotation = if cli.external_log_rotation {
+        LogRotation::External
+    } else {
+        LogRotation::Daily
+    };
+    let logs = crates_tracing::init("chico.log".to_string(), "chico".to_string(), log_rotation);
+
     match cli.command {
-        cli::Commands::Run { config } => {
-            let result = validate_config_file(config.as_str()).await;
+        cli::Commands::Run {
+            config,
+            pid_file,
+            validation,
+        } => {
+            let options: ValidationOptions = validation.into();
+            let result = validate_config_file(config.as_str(), &options).await;
 
             let Ok(conf) = result else {
                 eprintln!("{}", result.err().unwrap());
                 return ExitCode::FAILURE;
             };
-            let server = async {
-                run_server(conf).await;
-            };
+            if let Some(level) = conf.log_level() {
+                if let Err(e) = logs.set_level(level) {
+                    eprintln!("Failed to set the log level. reason: {}", e);
+                }
+            }
+            if let Some(path) = &conf.options.access_log {
+                if let Err(e) = logs.set_access_log(Path::new(path)) {
+                    eprintln!("Failed to open the access log {}. reason: {}", path, e);
+                    return ExitCode::FAILURE;
+                }
+            }
+            #[cfg(unix)]
+            server::reopen_logs_on_signal(logs);
+            #[cfg(not(unix))]
+            drop(logs);
+
+            if let Some(pid_file) = &pid_file {
+                if let Err(e) = write_pid_file(Path::new(pid_file), &config) {
+                    eprintln!("Failed to write the pid file {}. reason: {}", pid_file, e);
+                    return ExitCode::FAILURE;
+                }
+            }
+            let mut plans = PlanHandle::new(ServerPlan::from_config(&conf));
+            if let Some(pid_file) = &pid_file {
+                let status_file = reload::status_file_path(Path::new(pid_file));
+                plans = plans.with_status_file(status_file.clone());
+                if let Err(e) = plans.save_status() {
+                    eprintln!(
+                        "Failed to write the reload status {}. reason: {}",
+                        status_file.display(),
+                        e
+                    );
+                    return ExitCode::FAILURE;
+                }
+            }
+            let plans = Arc::new(plans);
+            #[cfg(unix)]
+            server::reload_on_signal(plans.clone(), config, options);
+            #[cfg(not(unix))]
+            drop(options);
 
             // listen to shutdown from stdio only in tests https://github.com/Alirexaa/chico/issues/99
             #[cfg(feature = "stdin_shutdown")]
             {
-                use std::sync::Arc;
                 use tokio::select;
 
                 let notify = Arc::new(tokio::sync::Notify::new());
@@ -46,33 +92,182 @@ async fn main() -> ExitCode {
                     while let Ok(Some(line)) = reader.next_line().await {
                         if line.trim() == "shutdown" {
                             println!("Shutdown command received from stdin.");
-                            notify_clone.notify_waiters();
+                            notify_clone.notify_one();
                             break;
                         }
                     }
                 });
 
-                let shutdown = async { notify.notified().await };
-
-                select! {
-                    _ = server => {}
-                    _ = shutdown => {}
-                }
+                // the connections are drained like on a signal
+                let shutdown = async {
+                    select! {
+                        _ = server::shutdown_signal() => {}
+                        _ = notify.notified() => {}
+                    }
+                };
+                run_server(conf, plans, shutdown).await;
             }
             #[cfg(not(feature = "stdin_shutdown"))]
-            server.await;
+            run_server(conf, plans, server::shutdown_signal()).await;
 
+            if let Some(pid_file) = pid_file {
+                let _ = std::fs::remove_file(reload::status_file_path(Path::new(&pid_file)));
+                let _ = std::fs::remove_file(pid_file);
+            }
             return ExitCode::SUCCESS;
         }
-        cli::Commands::Validate { config } => {
-            let result = validate_config_file(config.as_str()).await;
+        cli::Commands::Reload {
+            pid_file,
+            validation,
+        } => return reload_server(Path::new(&pid_file), &validation.into()).await,
+        cli::Commands::Status { pid_file, metrics } => {
+            return server_status(Path::new(&pid_file), metrics).await
+        }
+        cli::Commands::Validate {
+            config,
+            diff,
+            validation,
+        } => {
+            let options = validation.into();
+            if let Some([old, new]) = diff.as_deref() {
+                return diff_config_files(old, new, &options).await;
+            }
+            let config = config.expect("clap requires --config without --diff");
+            let result = validate_config_file(config.as_str(), &options).await;
 
-            if let Err(e) = result {
-                eprintln!("{}", e);
-                return ExitCode::FAILURE;
+            let conf = match result {
+                Ok(conf) => conf,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return ExitCode::FAILURE;
+                }
             };
             println!("✅✅✅ Specified config is valid.");
+            println!("Config hash: {:016x}", config_hash(&conf));
             return ExitCode::SUCCESS;
         }
     }
 }
+
+/// Validates the config file of the server of `pid_file` and asks the server to reload it, the
+/// server keeps its current config when the new one is invalid.
+async fn reload_server(pid_file: &Path, options: &ValidationOptions) -> ExitCode {
+    let (pid, config) = match read_pid_file(pid_file) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(e) = validate_config_file(&config, options).await {
+        eprintln!("{}", e);
+        eprintln!("The server keeps its current config.");
+        return ExitCode::FAILURE;
+    }
+
+    #[cfg(unix)]
+    {
+        if let Err(e) = reload::signal_reload(pid) {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+        println!(
+            "✅ Config {} is valid, reload sent to the server (pid {}).",
+            config, pid
+        );
+        ExitCode::SUCCESS
+    }
+    #[cfg(not(unix))]
+    {
+        eprintln!("Reloading a running server is only supported on Unix, restart the server (pid {}) instead.", pid);
+        ExitCode::FAILURE
+    }
+}
+
+/// Prints whether the server of `pid_file` is running, with the ports, virtual hosts and routes
+/// of its config file and the outcome of its reloads, failing when it is not running. With
+/// `metrics`, only the reload metrics are printed.
+async fn server_status(pid_file: &Path, metrics: bool) -> ExitCode {
+    let (pid, config) = match read_pid_file(pid_file) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Chico is not running. {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if !reload::is_running(pid) {
+        eprintln!(
+            "Chico is not running, the server (pid {}) of {} has exited.",
+            pid,
+            pid_file.display()
+        );
+        return ExitCode::FAILURE;
+    }
+
+    // the config file may have changed since it was loaded, the server saves what it serves
+    let status = match reload::read_status_file(&reload::status_file_path(pid_file)) {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if metrics {
+        print!("{}", reload::reload_metrics(&status));
+        return ExitCode::SUCCESS;
+    }
+
+    println!("✅ Chico is running (pid {}).", pid);
+    println!("Config: {}", config);
+    print_reload_status(&status);
+    let ports: Vec<String> = status["ports"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(ToString::to_string)
+        .collect();
+    println!("Ports: {}", ports.join(", "));
+    println!("Virtual hosts: {}", status["virtual_hosts"]);
+    println!("Routes: {}", status["routes"]);
+    ExitCode::SUCCESS
+}
+
+/// Prints the reload counters of a reload status and the error of the last failed reload.
+fn print_reload_status(reloads: &serde_json::Value) {
+    println!("Config generation: {}", reloads["generation"]);
+    println!(
+        "Reloads: {} succeeded, {} failed",
+        reloads["successes"], reloads["failures"]
+    );
+    if let Some(last_success) = reloads["last_success"].as_u64() {
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(last_success);
+        println!("Last successful reload: {}", httpdate::fmt_http_date(time));
+    }
+    if let Some(last_error) = reloads["last_error"].as_str() {
+        println!("Last reload error: {}", last_error);
+    }
+}
+
+/// Prints the changes from the `old` config file to the `new` one, failing when there are some
+/// so CI can gate deployments on it.
+async fn diff_config_files(old: &str, new: &str, options: &ValidationOptions) -> ExitCode {
+    let mut configs = vec![];
+    for path in [old, new] {
+        match validate_config_file(path, options).await {
+            Ok(conf) => configs.push(conf),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let changes = diff(&configs[0], &configs[1]);
+    if changes.is_empty() {
+        println!("No changes.");
+        return ExitCode::SUCCESS;
+    }
+    print!("{}", changes);
+    ExitCode::FAILURE
+}
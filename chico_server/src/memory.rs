@@ -0,0 +1,210 @@
+//! # MemoryBudget
+//!
+//! Caps the memory held by the in-memory caches of all routes together, from the
+//! `memory_budget` of the options. Each cache is bounded on its own, but a config with many
+//! cached routes can still add up past the memory of its container.
+//!
+//! - Caches register with the budget and report the bytes they add and remove.
+//! - When the total goes over the budget, the caches are asked to shed the excess in the order
+//!   of [`ShedPriority`]: the contents that are cheapest to rebuild go first.
+//! - Each time the budget is exceeded is a pressure event, logged and counted, see
+//!   [`MemoryBudget::metrics`].
+
+use std::sync::{
+    atomic::{AtomicI64, AtomicU64, Ordering},
+    Arc, Mutex, Weak,
+};
+
+use tracing::warn;
+
+/// Order in which the caches shed memory under pressure, the lowest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ShedPriority {
+    /// Minified files, minified again from the files on disk.
+    MinifiedFiles,
+    /// Responses of the handlers, possibly of slow or remote upstreams.
+    ResponseCache,
+}
+
+/// A cache holding memory accounted by a [`MemoryBudget`].
+pub trait MemoryConsumer: Send + Sync {
+    /// Bytes currently held.
+    fn usage(&self) -> usize;
+
+    /// Frees at least `bytes` when it holds that much, returns the bytes freed.
+    ///
+    /// The freed bytes must not be reported to the budget, it accounts for them itself.
+    fn shed(&self, bytes: usize) -> usize;
+}
+
+pub struct MemoryBudget {
+    limit: u64,
+    /// Bytes held by all consumers, it may be briefly negative while a consumer sheds bytes it
+    /// has not reported yet.
+    usage: AtomicI64,
+    pressure_events: AtomicU64,
+    consumers: Mutex<Vec<(ShedPriority, Weak<dyn MemoryConsumer>)>>,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            usage: AtomicI64::new(0),
+            pressure_events: AtomicU64::new(0),
+            consumers: Mutex::new(vec![]),
+        }
+    }
+
+    /// Adds a consumer, it is dropped from the budget when the consumer is dropped.
+    pub fn register(&self, priority: ShedPriority, consumer: Weak<dyn MemoryConsumer>) {
+        let mut consumers = self.consumers.lock().unwrap();
+        consumers.retain(|(_, consumer)| consumer.strong_count() > 0);
+        consumers.push((priority, consumer));
+    }
+
+    /// Reports the usage of a consumer going from `before` to `after` bytes, and sheds memory
+    /// when the total is over the budget.
+    ///
+    /// Must be called without holding the locks of the consumer, shedding takes them.
+    pub fn resize(&self, before: usize, after: usize) {
+        let delta = after as i64 - before as i64;
+        let usage = self.usage.fetch_add(delta, Ordering::SeqCst) + delta;
+        if delta > 0 && usage > self.limit as i64 {
+            self.relieve(usage as u64 - self.limit);
+        }
+    }
+
+    /// Bytes held by all consumers.
+    pub fn usage(&self) -> u64 {
+        self.usage.load(Ordering::SeqCst).max(0) as u64
+    }
+
+    /// Times the budget was exceeded.
+    pub fn pressure_events(&self) -> u64 {
+        self.pressure_events.load(Ordering::SeqCst)
+    }
+
+    /// Returns the budget, the usage and the pressure events in the Prometheus text format.
+    pub fn metrics(&self) -> String {
+        format!(
+            "# HELP chico_memory_budget_bytes Bytes the in-memory caches may hold together.\n\
+             # TYPE chico_memory_budget_bytes gauge\n\
+             chico_memory_budget_bytes {}\n\
+             # HELP chico_memory_usage_bytes Bytes held by the in-memory caches.\n\
+             # TYPE chico_memory_usage_bytes gauge\n\
+             chico_memory_usage_bytes {}\n\
+             # HELP chico_memory_pressure_total Times the caches went over the memory budget.\n\
+             # TYPE chico_memory_pressure_total counter\n\
+             chico_memory_pressure_total {}\n",
+            self.limit,
+            self.usage(),
+            self.pressure_events()
+        )
+    }
+
+    /// Asks the consumers to shed `excess` bytes in the order of their priority.
+    fn relieve(&self, excess: u64) {
+        self.pressure_events.fetch_add(1, Ordering::SeqCst);
+        warn!(
+            "in-memory caches are {} bytes over the memory budget of {} bytes, shedding",
+            excess, self.limit
+        );
+
+        let mut consumers: Vec<(ShedPriority, Arc<dyn MemoryConsumer>)> = self
+            .consumers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(priority, consumer)| Some((*priority, consumer.upgrade()?)))
+            .collect();
+        consumers.sort_by_key(|(priority, _)| *priority);
+
+        let mut excess = excess as usize;
+        for (_, consumer) in consumers {
+            if excess == 0 {
+                break;
+            }
+            let freed = consumer.shed(excess);
+            self.usage.fetch_sub(freed as i64, Ordering::SeqCst);
+            excess = excess.saturating_sub(freed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{MemoryBudget, MemoryConsumer, ShedPriority};
+
+    /// Consumer holding a number of bytes.
+    #[derive(Default)]
+    struct Held(Mutex<usize>);
+
+    impl MemoryConsumer for Held {
+        fn usage(&self) -> usize {
+            *self.0.lock().unwrap()
+        }
+
+        fn shed(&self, bytes: usize) -> usize {
+            let mut held = self.0.lock().unwrap();
+            let freed = bytes.min(*held);
+            *held -= freed;
+            freed
+        }
+    }
+
+    fn grow(budget: &MemoryBudget, consumer: &Held, bytes: usize) {
+        let before = consumer.usage();
+        *consumer.0.lock().unwrap() += bytes;
+        budget.resize(before, before + bytes);
+    }
+
+    #[test]
+    fn test_consumers_shed_in_priority_order() {
+        let budget = MemoryBudget::new(100);
+        let responses = Arc::new(Held::default());
+        let minified = Arc::new(Held::default());
+        budget.register(ShedPriority::ResponseCache, Arc::downgrade(&responses) as _);
+        budget.register(ShedPriority::MinifiedFiles, Arc::downgrade(&minified) as _);
+
+        grow(&budget, &minified, 30);
+        grow(&budget, &responses, 60);
+        assert_eq!(budget.pressure_events(), 0);
+
+        grow(&budget, &responses, 50);
+
+        assert_eq!(minified.usage(), 0);
+        assert_eq!(responses.usage(), 100);
+        assert_eq!(budget.usage(), 100);
+        assert_eq!(budget.pressure_events(), 1);
+    }
+
+    #[test]
+    fn test_dropped_consumers_are_skipped() {
+        let budget = MemoryBudget::new(10);
+        let dropped = Arc::new(Held::default());
+        budget.register(ShedPriority::MinifiedFiles, Arc::downgrade(&dropped) as _);
+        drop(dropped);
+        let responses = Arc::new(Held::default());
+        budget.register(ShedPriority::ResponseCache, Arc::downgrade(&responses) as _);
+
+        grow(&budget, &responses, 15);
+
+        assert_eq!(responses.usage(), 10);
+        assert_eq!(budget.usage(), 10);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let budget = MemoryBudget::new(1024);
+        budget.resize(0, 512);
+
+        let metrics = budget.metrics();
+
+        assert!(metrics.contains("\nchico_memory_budget_bytes 1024\n"));
+        assert!(metrics.contains("\nchico_memory_usage_bytes 512\n"));
+        assert!(metrics.contains("\nchico_memory_pressure_total 0\n"));
+    }
+}
@@ -0,0 +1,9 @@
+pub mod auth;
+pub mod cache;
+pub mod compression;
+pub mod concurrency;
+pub mod cors;
+pub mod csp;
+pub mod header;
+pub mod minify;
+pub mod rate_limit;
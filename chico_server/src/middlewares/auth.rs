@@ -0,0 +1,160 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chico_file::types::{BasicUser, Middleware};
+use http::{header, Request};
+
+use crate::plan::path_matches;
+
+/// Realm of the challenge when the `auth` middleware sets none.
+const DEFAULT_REALM: &str = "Restricted";
+
+/// HTTP Basic authentication of a route.
+///
+/// Requests to a path matching one of the `except` patterns are served without credentials, the
+/// patterns are matched like route paths.
+pub struct BasicAuth {
+    users: Vec<BasicUser>,
+    realm: String,
+    except: Vec<String>,
+}
+
+impl BasicAuth {
+    /// Returns the authentication of the `auth` middleware, or `None` when the route has none.
+    pub fn from_middlewares(middlewares: &[Middleware]) -> Option<Self> {
+        middlewares.iter().find_map(|middleware| match middleware {
+            Middleware::Auth {
+                users,
+                realm,
+                except,
+            } => Some(Self {
+                users: users.clone(),
+                realm: realm.clone().unwrap_or_else(|| DEFAULT_REALM.to_string()),
+                except: except.clone(),
+            }),
+            _ => None,
+        })
+    }
+
+    /// Returns true when the request is exempt or has the credentials of a user.
+    pub fn authorizes<B>(&self, request: &Request<B>) -> bool {
+        let path = request.uri().path();
+        if self
+            .except
+            .iter()
+            .any(|pattern| path_matches(pattern, path))
+        {
+            return true;
+        }
+
+        let Some((username, password)) = credentials(request) else {
+            return false;
+        };
+        self.users.iter().any(|user| {
+            // Every user is compared, so the time taken does not tell which part was wrong
+            constant_time_eq(user.username.as_bytes(), username.as_bytes())
+                & constant_time_eq(user.password.expose().as_bytes(), password.as_bytes())
+        })
+    }
+
+    /// Returns the value of the `WWW-Authenticate` header asking for credentials.
+    pub fn challenge(&self) -> String {
+        format!(
+            "Basic realm=\"{}\", charset=\"UTF-8\"",
+            self.realm.replace(['\\', '"'], "")
+        )
+    }
+}
+
+/// Returns the username and password of the `Authorization: Basic` header.
+fn credentials<B>(request: &Request<B>) -> Option<(String, String)> {
+    let value = request
+        .headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?;
+    let (scheme, encoded) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use chico_file::types::{BasicUser, Middleware};
+    use http::{header, Request};
+    use rstest::rstest;
+
+    use super::BasicAuth;
+
+    fn auth(realm: Option<&str>, except: &[&str]) -> BasicAuth {
+        BasicAuth::from_middlewares(&[Middleware::Auth {
+            users: vec![
+                BasicUser::new("admin", "s3cr3t"),
+                BasicUser::new("bob", "hunter2"),
+            ],
+            realm: realm.map(str::to_string),
+            except: except.iter().map(|pattern| pattern.to_string()).collect(),
+        }])
+        .unwrap()
+    }
+
+    fn request(path: &str, authorization: Option<&str>) -> Request<()> {
+        let mut builder = Request::builder().uri(path);
+        if let Some(authorization) = authorization {
+            builder = builder.header(header::AUTHORIZATION, authorization);
+        }
+        builder.body(()).unwrap()
+    }
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", STANDARD.encode(credentials))
+    }
+
+    #[test]
+    fn test_route_without_auth_middleware_has_no_auth() {
+        assert!(BasicAuth::from_middlewares(&[Middleware::Log]).is_none());
+    }
+
+    #[rstest]
+    #[case(Some(basic("admin:s3cr3t")), true)]
+    #[case(Some(basic("bob:hunter2")), true)]
+    #[case(Some(basic("admin:hunter2")), false)]
+    #[case(Some(basic("admin")), false)]
+    #[case(Some("Bearer token".to_string()), false)]
+    #[case(Some("Basic not-base64!".to_string()), false)]
+    #[case(None, false)]
+    fn test_authorizes_users(#[case] authorization: Option<String>, #[case] expected: bool) {
+        let request = request("/admin", authorization.as_deref());
+
+        assert_eq!(auth(None, &[]).authorizes(&request), expected);
+    }
+
+    #[rstest]
+    #[case("/public/index.html", true)]
+    #[case("/.well-known/acme-challenge/token", true)]
+    #[case("/status", true)]
+    #[case("/status/details", false)]
+    #[case("/private/index.html", false)]
+    fn test_exempt_paths_need_no_credentials(#[case] path: &str, #[case] expected: bool) {
+        let auth = auth(
+            None,
+            &["/public/*", "/.well-known/acme-challenge/*", "/status"],
+        );
+
+        assert_eq!(auth.authorizes(&request(path, None)), expected);
+    }
+
+    #[rstest]
+    #[case(None, "Basic realm=\"Restricted\", charset=\"UTF-8\"")]
+    #[case(Some("Staging"), "Basic realm=\"Staging\", charset=\"UTF-8\"")]
+    fn test_challenge_has_realm(#[case] realm: Option<&str>, #[case] expected: &str) {
+        assert_eq!(auth(realm, &[]).challenge(), expected);
+    }
+}
@@ -0,0 +1,584 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use http::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode};
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::{Bytes, Frame};
+use tokio::time::Instant;
+
+use crate::{
+    handlers::{full, BoxBody},
+    memory::{MemoryBudget, MemoryConsumer, ShedPriority},
+};
+
+/// Total size of the bodies cached by a route, the least recently used responses are evicted
+/// above it.
+const DEFAULT_CAPACITY: usize = 64 * 1024 * 1024;
+
+/// In-memory cache of the successful `GET` responses of a route.
+///
+/// Responses are stored with their body for `ttl`, or until they are evicted when `immutable` is
+/// set. When `serve_stale_on_error` is set, an expired response is kept that much longer and
+/// served, with a `Warning: 110` header, in place of a server error.
+///
+/// A fresh `200` answers requests whose `If-None-Match` matches its `ETag` with a
+/// `304 Not Modified`.
+pub struct ResponseCache {
+    ttl: Duration,
+    serve_stale_on_error: Option<Duration>,
+    immutable: bool,
+    /// Maximum total size of the cached bodies, in bytes.
+    capacity: usize,
+    entries: Arc<Mutex<Entries>>,
+    budget: Option<Arc<MemoryBudget>>,
+}
+
+#[derive(Default)]
+struct Entries {
+    responses: HashMap<String, CachedResponse>,
+    /// Total size of the cached bodies.
+    size: usize,
+    /// Incremented on each use, the entry with the lowest `last_used` is evicted first.
+    clock: u64,
+}
+
+impl Entries {
+    fn get(&mut self, key: &str) -> Option<&CachedResponse> {
+        self.clock += 1;
+        let entry = self.responses.get_mut(key)?;
+        entry.last_used = self.clock;
+        Some(entry)
+    }
+
+    fn insert(&mut self, key: String, mut entry: CachedResponse, capacity: usize) {
+        self.remove(&key);
+        while self.size + entry.body.len() > capacity && self.evict_oldest() {}
+
+        self.clock += 1;
+        entry.last_used = self.clock;
+        self.size += entry.body.len();
+        self.responses.insert(key, entry);
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.responses.remove(key) {
+            self.size -= entry.body.len();
+        }
+    }
+
+    /// Removes the least recently used entry, returns false when there is none.
+    fn evict_oldest(&mut self) -> bool {
+        let Some(oldest) = self
+            .responses
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone())
+        else {
+            return false;
+        };
+        self.remove(&oldest);
+        true
+    }
+}
+
+impl MemoryConsumer for Mutex<Entries> {
+    fn usage(&self) -> usize {
+        self.lock().unwrap().size
+    }
+
+    fn shed(&self, bytes: usize) -> usize {
+        let mut entries = self.lock().unwrap();
+        let before = entries.size;
+        while before - entries.size < bytes && entries.evict_oldest() {}
+        before - entries.size
+    }
+}
+
+/// State of a cached response, it goes from fresh to stale to expired as it ages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Freshness {
+    /// Served in place of calling the handler.
+    Fresh,
+    /// Served only in place of a server error of the handler.
+    Stale,
+    /// Removed from the cache.
+    Expired,
+}
+
+struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored_at: Instant,
+    last_used: u64,
+}
+
+impl CachedResponse {
+    fn to_response(&self) -> Response<BoxBody> {
+        let mut response = Response::new(full(self.body.clone()));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        response.headers_mut().insert(
+            header::AGE,
+            HeaderValue::from(self.stored_at.elapsed().as_secs()),
+        );
+        response
+    }
+
+    /// Returns true when the `If-None-Match` header lists the `ETag` of the response.
+    fn matches_etag(&self, if_none_match: &HeaderValue) -> bool {
+        let Some(etag) = self.headers.get(header::ETAG) else {
+            return false;
+        };
+        let (Ok(etag), Ok(if_none_match)) = (etag.to_str(), if_none_match.to_str()) else {
+            return false;
+        };
+        // `If-None-Match` uses the weak comparison
+        let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+        if_none_match
+            .split(',')
+            .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+    }
+
+    /// Returns the `304 Not Modified` answering a conditional request for the response.
+    fn to_not_modified(&self) -> Response<BoxBody> {
+        let mut response = Response::new(full(""));
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        for name in [
+            header::CACHE_CONTROL,
+            header::CONTENT_LOCATION,
+            header::DATE,
+            header::ETAG,
+            header::EXPIRES,
+            header::VARY,
+        ] {
+            for value in self.headers.get_all(&name) {
+                response.headers_mut().append(name.clone(), value.clone());
+            }
+        }
+        response.headers_mut().insert(
+            header::AGE,
+            HeaderValue::from(self.stored_at.elapsed().as_secs()),
+        );
+        response
+    }
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration, serve_stale_on_error: Option<Duration>) -> Self {
+        Self::with_capacity(ttl, serve_stale_on_error, DEFAULT_CAPACITY)
+    }
+
+    pub fn with_capacity(
+        ttl: Duration,
+        serve_stale_on_error: Option<Duration>,
+        capacity: usize,
+    ) -> Self {
+        Self {
+            ttl,
+            serve_stale_on_error,
+            immutable: false,
+            capacity,
+            entries: Arc::new(Mutex::new(Entries::default())),
+            budget: None,
+        }
+    }
+
+    /// Accounts the cached responses in the memory budget, the least recently used responses are
+    /// evicted when the caches of all routes are over it.
+    pub fn with_memory_budget(mut self, budget: &Arc<MemoryBudget>) -> Self {
+        budget.register(
+            ShedPriority::ResponseCache,
+            Arc::downgrade(&self.entries) as _,
+        );
+        self.budget = Some(budget.clone());
+        self
+    }
+
+    /// Changes the entries, then reports their new size to the memory budget.
+    fn update<R>(&self, change: impl FnOnce(&mut Entries) -> R) -> R {
+        let (before, after, result) = {
+            let mut entries = self.entries.lock().unwrap();
+            let before = entries.size;
+            let result = change(&mut entries);
+            (before, entries.size, result)
+        };
+        if let Some(budget) = &self.budget {
+            budget.resize(before, after);
+        }
+        result
+    }
+
+    /// Keep the responses until they are evicted, for content that never changes under its key
+    /// like fingerprinted assets.
+    pub fn with_immutable(mut self, immutable: bool) -> Self {
+        self.immutable = immutable;
+        self
+    }
+
+    fn freshness(&self, entry: &CachedResponse) -> Freshness {
+        let age = entry.stored_at.elapsed();
+        if self.immutable || age < self.ttl {
+            Freshness::Fresh
+        } else if age < self.ttl + self.serve_stale_on_error.unwrap_or_default() {
+            Freshness::Stale
+        } else {
+            Freshness::Expired
+        }
+    }
+
+    /// Returns the cache key of the request, or `None` when its response is not cached.
+    pub fn key<B>(request: &Request<B>) -> Option<String> {
+        if request.method() != Method::GET {
+            return None;
+        }
+        let host = request
+            .headers()
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .unwrap_or_default();
+        let path = request
+            .uri()
+            .path_and_query()
+            .map_or("/", |path| path.as_str());
+        Some(format!("{} {host}{path}", request.method()))
+    }
+
+    /// Returns the cached response of the key if it is fresh, or a `304 Not Modified` when the
+    /// request headers hold an `If-None-Match` matching its `ETag`.
+    pub fn get(&self, key: &str, request_headers: &HeaderMap) -> Option<Response<BoxBody>> {
+        self.update(|entries| {
+            let entry = entries.get(key)?;
+            match self.freshness(entry) {
+                Freshness::Fresh => {
+                    let if_none_match = request_headers.get(header::IF_NONE_MATCH);
+                    if entry.status == StatusCode::OK
+                        && if_none_match.is_some_and(|tags| entry.matches_etag(tags))
+                    {
+                        return Some(entry.to_not_modified());
+                    }
+                    Some(entry.to_response())
+                }
+                Freshness::Stale => None,
+                Freshness::Expired => {
+                    entries.remove(key);
+                    None
+                }
+            }
+        })
+    }
+
+    /// Stores a successful response of the handler, or replaces a server error by the stale
+    /// response of the key if it is still within the grace period.
+    ///
+    /// Responses setting cookies or larger than the capacity are not stored.
+    pub async fn store(&self, key: String, response: Response<BoxBody>) -> Response<BoxBody> {
+        if response.status().is_server_error() {
+            return self.stale(&key).unwrap_or(response);
+        }
+        if !response.status().is_success() || !self.is_storable(response.headers()) {
+            return response;
+        }
+
+        let (parts, body) = response.into_parts();
+        let body = match body.collect().await {
+            Ok(collected) => collected.to_bytes(),
+            // The client gets the error as well, nothing is cached
+            Err(error) => {
+                let failed = futures_util::stream::iter([Err::<Frame<Bytes>, _>(error)]);
+                return Response::from_parts(parts, StreamBody::new(failed).boxed());
+            }
+        };
+        if body.len() > self.capacity {
+            return Response::from_parts(parts, full(body));
+        }
+
+        let entry = CachedResponse {
+            status: parts.status,
+            headers: parts.headers,
+            body,
+            stored_at: Instant::now(),
+            last_used: 0,
+        };
+        let response = entry.to_response();
+        self.update(|entries| entries.insert(key, entry, self.capacity));
+        response
+    }
+
+    fn is_storable(&self, headers: &HeaderMap) -> bool {
+        // A body announced larger than the capacity is not buffered
+        let too_large = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok()?.parse::<usize>().ok())
+            .is_some_and(|length| length > self.capacity);
+        !too_large && !headers.contains_key(header::SET_COOKIE)
+    }
+
+    fn stale(&self, key: &str) -> Option<Response<BoxBody>> {
+        self.serve_stale_on_error?;
+        self.update(|entries| {
+            let entry = entries.get(key)?;
+            match self.freshness(entry) {
+                Freshness::Fresh | Freshness::Stale => {
+                    let mut response = entry.to_response();
+                    response.headers_mut().insert(
+                        header::WARNING,
+                        HeaderValue::from_static("110 - \"Response is Stale\""),
+                    );
+                    Some(response)
+                }
+                Freshness::Expired => {
+                    entries.remove(key);
+                    None
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use claims::{assert_none, assert_some};
+    use http::{header, HeaderMap, Method, Response, StatusCode};
+    use rstest::rstest;
+
+    use super::{Freshness, ResponseCache};
+    use crate::{
+        handlers::full,
+        testing::{collect_body, request, MockBody},
+    };
+
+    fn key(path: &str) -> String {
+        let request = request().uri(path).body(MockBody::new(b"")).unwrap();
+        ResponseCache::key(&request).unwrap()
+    }
+
+    fn response(status: u16, body: &'static str) -> Response<crate::handlers::BoxBody> {
+        Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body(full(body))
+            .unwrap()
+    }
+
+    fn freshness(cache: &ResponseCache, key: &str) -> Option<Freshness> {
+        let entries = cache.entries.lock().unwrap();
+        entries
+            .responses
+            .get(key)
+            .map(|entry| cache.freshness(entry))
+    }
+
+    #[test]
+    fn test_key_includes_method_host_and_path() {
+        assert_eq!(
+            key("http://localhost/api?page=2"),
+            "GET localhost/api?page=2"
+        );
+
+        let request = request()
+            .method(Method::POST)
+            .uri("http://localhost/api")
+            .body(MockBody::new(b""))
+            .unwrap();
+        assert_none!(ResponseCache::key(&request));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_successful_response_is_served_until_it_expires() {
+        let cache = ResponseCache::new(Duration::from_secs(60), None);
+
+        let stored = cache.store(key("/api"), response(200, "cached")).await;
+        assert_eq!(collect_body(stored).await, "cached");
+
+        let hit = assert_some!(cache.get(&key("/api"), &HeaderMap::new()));
+        assert_eq!(hit.status(), StatusCode::OK);
+        assert_eq!(hit.headers()[header::CONTENT_TYPE], "text/plain");
+        assert_eq!(collect_body(hit).await, "cached");
+        assert_none!(cache.get(&key("/other"), &HeaderMap::new()));
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert_none!(cache.get(&key("/api"), &HeaderMap::new()));
+    }
+
+    #[tokio::test]
+    async fn test_error_response_is_not_stored() {
+        let cache = ResponseCache::new(Duration::from_secs(60), None);
+
+        cache.store(key("/api"), response(404, "missing")).await;
+        cache.store(key("/api"), response(502, "down")).await;
+
+        assert_none!(cache.get(&key("/api"), &HeaderMap::new()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stale_response_is_served_on_server_error_within_grace() {
+        let cache = ResponseCache::new(Duration::from_secs(60), Some(Duration::from_secs(3600)));
+        cache.store(key("/api"), response(200, "cached")).await;
+
+        tokio::time::advance(Duration::from_secs(120)).await;
+        assert_none!(cache.get(&key("/api"), &HeaderMap::new()));
+
+        let served = cache.store(key("/api"), response(502, "down")).await;
+        assert_eq!(served.status(), StatusCode::OK);
+        assert_eq!(
+            served.headers()[header::WARNING],
+            "110 - \"Response is Stale\""
+        );
+        assert_eq!(collect_body(served).await, "cached");
+
+        // a client error of the handler is not replaced
+        let served = cache.store(key("/api"), response(404, "missing")).await;
+        assert_eq!(served.status(), StatusCode::NOT_FOUND);
+
+        tokio::time::advance(Duration::from_secs(3600)).await;
+        let served = cache.store(key("/api"), response(502, "down")).await;
+        assert_eq!(served.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stale_response_is_not_served_without_grace() {
+        let cache = ResponseCache::new(Duration::from_secs(60), None);
+        cache.store(key("/api"), response(200, "cached")).await;
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        let served = cache.store(key("/api"), response(503, "down")).await;
+
+        assert_eq!(served.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cached_response_has_age_header() {
+        let cache = ResponseCache::new(Duration::from_secs(60), None);
+        let stored = cache.store(key("/api"), response(200, "cached")).await;
+        assert_eq!(stored.headers()[header::AGE], "0");
+
+        tokio::time::advance(Duration::from_secs(42)).await;
+
+        let hit = assert_some!(cache.get(&key("/api"), &HeaderMap::new()));
+        assert_eq!(hit.headers()[header::AGE], "42");
+    }
+
+    #[tokio::test]
+    async fn test_response_setting_cookie_is_not_stored() {
+        let cache = ResponseCache::new(Duration::from_secs(60), None);
+        let mut with_cookie = response(200, "private");
+        with_cookie
+            .headers_mut()
+            .insert(header::SET_COOKIE, "session=1".parse().unwrap());
+
+        let served = cache.store(key("/api"), with_cookie).await;
+
+        assert_eq!(collect_body(served).await, "private");
+        assert_none!(cache.get(&key("/api"), &HeaderMap::new()));
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_response_is_evicted() {
+        let cache = ResponseCache::with_capacity(Duration::from_secs(60), None, 10);
+        cache.store(key("/a"), response(200, "aaaaa")).await;
+        cache.store(key("/b"), response(200, "bbbbb")).await;
+        assert_some!(cache.get(&key("/a"), &HeaderMap::new()));
+
+        cache.store(key("/c"), response(200, "ccccc")).await;
+
+        assert_some!(cache.get(&key("/a"), &HeaderMap::new()));
+        assert_none!(cache.get(&key("/b"), &HeaderMap::new()));
+        assert_some!(cache.get(&key("/c"), &HeaderMap::new()));
+    }
+
+    #[tokio::test]
+    async fn test_response_larger_than_capacity_is_not_stored() {
+        let cache = ResponseCache::with_capacity(Duration::from_secs(60), None, 4);
+
+        let served = cache.store(key("/api"), response(200, "too large")).await;
+
+        assert_eq!(collect_body(served).await, "too large");
+        assert_none!(cache.get(&key("/api"), &HeaderMap::new()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_freshness_goes_from_fresh_to_stale_to_expired() {
+        let cache = ResponseCache::new(Duration::from_secs(60), Some(Duration::from_secs(600)));
+        cache.store(key("/api"), response(200, "cached")).await;
+        assert_eq!(freshness(&cache, &key("/api")), Some(Freshness::Fresh));
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert_eq!(freshness(&cache, &key("/api")), Some(Freshness::Stale));
+        assert_none!(cache.get(&key("/api"), &HeaderMap::new()));
+        // stale responses are kept for errors of the handler
+        assert_eq!(freshness(&cache, &key("/api")), Some(Freshness::Stale));
+
+        tokio::time::advance(Duration::from_secs(600)).await;
+        assert_eq!(freshness(&cache, &key("/api")), Some(Freshness::Expired));
+        assert_none!(cache.get(&key("/api"), &HeaderMap::new()));
+        assert_none!(freshness(&cache, &key("/api")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_immutable_response_stays_fresh() {
+        let cache = ResponseCache::new(Duration::from_secs(60), None).with_immutable(true);
+        cache
+            .store(key("/app.3f2a1b.js"), response(200, "app"))
+            .await;
+
+        tokio::time::advance(Duration::from_secs(365 * 24 * 3600)).await;
+
+        assert_eq!(
+            freshness(&cache, &key("/app.3f2a1b.js")),
+            Some(Freshness::Fresh)
+        );
+        let hit = assert_some!(cache.get(&key("/app.3f2a1b.js"), &HeaderMap::new()));
+        assert_eq!(collect_body(hit).await, "app");
+    }
+
+    #[rstest]
+    #[case("\"v1\"", true)]
+    #[case("W/\"v1\"", true)]
+    #[case("\"v0\", \"v1\"", true)]
+    #[case("*", true)]
+    #[case("\"v2\"", false)]
+    #[tokio::test]
+    async fn test_fresh_response_answers_if_none_match_with_304(
+        #[case] if_none_match: &str,
+        #[case] not_modified: bool,
+    ) {
+        let cache = ResponseCache::new(Duration::from_secs(60), None);
+        let mut tagged = response(200, "cached");
+        tagged
+            .headers_mut()
+            .insert(header::ETAG, "\"v1\"".parse().unwrap());
+        cache.store(key("/api"), tagged).await;
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, if_none_match.parse().unwrap());
+
+        let hit = assert_some!(cache.get(&key("/api"), &headers));
+
+        if not_modified {
+            assert_eq!(hit.status(), StatusCode::NOT_MODIFIED);
+            assert_eq!(hit.headers()[header::ETAG], "\"v1\"");
+            assert!(!hit.headers().contains_key(header::CONTENT_TYPE));
+            assert_eq!(collect_body(hit).await, "");
+        } else {
+            assert_eq!(hit.status(), StatusCode::OK);
+            assert_eq!(collect_body(hit).await, "cached");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_response_without_etag_ignores_if_none_match() {
+        let cache = ResponseCache::new(Duration::from_secs(60), None);
+        cache.store(key("/api"), response(200, "cached")).await;
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, "*".parse().unwrap());
+
+        let hit = assert_some!(cache.get(&key("/api"), &headers));
+
+        assert_eq!(hit.status(), StatusCode::OK);
+    }
+}
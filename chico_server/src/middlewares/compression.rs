@@ -0,0 +1,573 @@
+use std::{
+    io::Write,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use chico_file::types::{CompressionLevel, Middleware};
+use flate2::{
+    write::{GzDecoder, GzEncoder, ZlibDecoder},
+    Compression,
+};
+use http::{header, HeaderMap, HeaderValue, Request, Response, StatusCode};
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Frame};
+use tracing::warn;
+
+use crate::handlers::BoxBody;
+
+/// Compresses responses with gzip for clients accepting it.
+pub struct Gzip {
+    level: CompressionLevel,
+}
+
+impl Gzip {
+    pub fn new(level: CompressionLevel) -> Self {
+        Self { level }
+    }
+
+    /// Compresses the body of the response as it is sent.
+    ///
+    /// Responses that are already encoded, partial or without body are returned unchanged.
+    pub fn compress(&self, response: Response<BoxBody>) -> Response<BoxBody> {
+        encode(response, "gzip", || {
+            Ok(Coder::GzipEncoder(GzEncoder::new(
+                Vec::new(),
+                Compression::new(self.level.get()),
+            )))
+        })
+    }
+}
+
+/// Compresses responses with zstd for clients accepting it.
+///
+/// With a dictionary, small responses similar to the dictionary content compress much better.
+/// The client must decode them with the same dictionary.
+pub struct Zstd {
+    dictionary: Option<Vec<u8>>,
+}
+
+impl Zstd {
+    pub fn new(dictionary: Option<Vec<u8>>) -> Self {
+        Self { dictionary }
+    }
+
+    /// Returns the compression of the `compress zstd` middleware, or `None` when the route has
+    /// none.
+    ///
+    /// A dictionary that cannot be read is left out, the config validation reports it.
+    pub fn from_middlewares(middlewares: &[Middleware]) -> Option<Self> {
+        middlewares.iter().find_map(|middleware| match middleware {
+            Middleware::Zstd { dictionary } => {
+                let dictionary = dictionary.as_ref().and_then(|path| {
+                    std::fs::read(path)
+                        .inspect_err(|e| warn!("Failed to read zstd dictionary {}: {}", path, e))
+                        .ok()
+                });
+                Some(Self::new(dictionary))
+            }
+            _ => None,
+        })
+    }
+
+    /// Checks that the dictionary of a `compress zstd` middleware can be read.
+    pub fn validate(middleware: &Middleware) -> Result<(), String> {
+        if let Middleware::Zstd {
+            dictionary: Some(path),
+        } = middleware
+        {
+            std::fs::metadata(path)
+                .map_err(|e| format!("failed to read zstd dictionary {path}: {e}"))?;
+        }
+        Ok(())
+    }
+
+    /// Compresses the body of the response as it is sent.
+    ///
+    /// Responses that are already encoded, partial or without body are returned unchanged.
+    pub fn compress(&self, response: Response<BoxBody>) -> Response<BoxBody> {
+        encode(response, "zstd", || {
+            let dictionary = self.dictionary.as_deref().unwrap_or_default();
+            let encoder = zstd::stream::write::Encoder::with_dictionary(
+                Vec::new(),
+                zstd::DEFAULT_COMPRESSION_LEVEL,
+                dictionary,
+            )?;
+            Ok(Coder::ZstdEncoder(encoder))
+        })
+    }
+}
+
+/// Encodes the body of the response with the coder of `coding`.
+///
+/// Responses that are already encoded, partial or without body, or whose coder cannot be
+/// created, are returned unchanged.
+fn encode(
+    response: Response<BoxBody>,
+    coding: &'static str,
+    coder: impl FnOnce() -> std::io::Result<Coder>,
+) -> Response<BoxBody> {
+    if !has_body(response.status(), response.headers())
+        || response.headers().contains_key(header::CONTENT_ENCODING)
+    {
+        return response;
+    }
+    let coder = match coder() {
+        Ok(coder) => coder,
+        Err(e) => {
+            warn!("Failed to create the {} encoder: {}", coding, e);
+            return response;
+        }
+    };
+
+    let (mut parts, body) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.remove(header::ACCEPT_RANGES);
+    parts
+        .headers
+        .insert(header::CONTENT_ENCODING, HeaderValue::from_static(coding));
+    vary_on_encoding(&mut parts.headers);
+
+    let body = CodingBody {
+        inner: body,
+        coder: Some(coder),
+    };
+    Response::from_parts(parts, body.boxed())
+}
+
+/// Content codings accepted by a client, from the `Accept-Encoding` header of its request.
+pub struct AcceptEncoding {
+    /// Lowercase codings, without the ones refused with `q=0`.
+    codings: Vec<String>,
+}
+
+impl AcceptEncoding {
+    pub fn from_request<B>(request: &Request<B>) -> Self {
+        let codings = request
+            .headers()
+            .get_all(header::ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|coding| {
+                let mut params = coding.split(';').map(str::trim);
+                let name = normalize_coding(params.next().unwrap_or_default());
+                let refused = params.any(|param| {
+                    param
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .is_some_and(|q| q == 0.0)
+                });
+                (!name.is_empty() && !refused).then_some(name)
+            })
+            .collect();
+        Self { codings }
+    }
+
+    /// Whether the client accepts the coding, `x-gzip` is an alias of `gzip`.
+    pub fn accepts(&self, coding: &str) -> bool {
+        let coding = normalize_coding(coding);
+        self.codings
+            .iter()
+            .any(|accepted| *accepted == coding || accepted == "*")
+    }
+}
+
+fn normalize_coding(coding: &str) -> String {
+    match coding.to_ascii_lowercase().as_str() {
+        "x-gzip" => "gzip".to_string(),
+        coding => coding.to_string(),
+    }
+}
+
+/// Decodes a gzip or deflate encoded response the client does not accept.
+///
+/// Responses in an accepted or unknown encoding are returned unchanged, they get a
+/// `Vary: Accept-Encoding` header as the client decides how they are sent.
+pub fn transcode(response: Response<BoxBody>, accepted: &AcceptEncoding) -> Response<BoxBody> {
+    let coding = response
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| normalize_coding(value.trim()));
+    let Some(coding) = coding else {
+        return response;
+    };
+
+    let (mut parts, body) = response.into_parts();
+    vary_on_encoding(&mut parts.headers);
+    let coder = match coding.as_str() {
+        _ if accepted.accepts(&coding) || !has_body(parts.status, &parts.headers) => None,
+        "gzip" => Some(Coder::GzipDecoder(GzDecoder::new(Vec::new()))),
+        "deflate" => Some(Coder::DeflateDecoder(ZlibDecoder::new(Vec::new()))),
+        _ => None,
+    };
+    let Some(coder) = coder else {
+        return Response::from_parts(parts, body);
+    };
+
+    parts.headers.remove(header::CONTENT_ENCODING);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.remove(header::ACCEPT_RANGES);
+    let body = CodingBody {
+        inner: body,
+        coder: Some(coder),
+    };
+    Response::from_parts(parts, body.boxed())
+}
+
+/// Adds `Accept-Encoding` to the `Vary` header of the response unless it is already listed.
+pub fn vary_on_encoding(headers: &mut HeaderMap) {
+    let listed = headers
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|field| {
+            let field = field.trim();
+            field == "*" || field.eq_ignore_ascii_case("accept-encoding")
+        });
+    if !listed {
+        headers.append(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+}
+
+/// Whether the response has a full body that may be encoded or decoded.
+fn has_body(status: StatusCode, headers: &HeaderMap) -> bool {
+    !status.is_informational()
+        && status != StatusCode::PARTIAL_CONTENT
+        && status != StatusCode::NO_CONTENT
+        && status != StatusCode::NOT_MODIFIED
+        && !headers.contains_key(header::CONTENT_RANGE)
+}
+
+/// Encoder or decoder writing its output into a buffer.
+enum Coder {
+    GzipEncoder(GzEncoder<Vec<u8>>),
+    GzipDecoder(GzDecoder<Vec<u8>>),
+    DeflateDecoder(ZlibDecoder<Vec<u8>>),
+    ZstdEncoder(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Coder {
+    /// Codes the data and returns the output available so far.
+    fn write(&mut self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        fn write_all<W: Write>(coder: &mut W, data: &[u8]) -> std::io::Result<()> {
+            coder.write_all(data)?;
+            coder.flush()
+        }
+
+        Ok(match self {
+            Coder::GzipEncoder(coder) => {
+                write_all(coder, data)?;
+                std::mem::take(coder.get_mut())
+            }
+            Coder::GzipDecoder(coder) => {
+                write_all(coder, data)?;
+                std::mem::take(coder.get_mut())
+            }
+            Coder::DeflateDecoder(coder) => {
+                write_all(coder, data)?;
+                std::mem::take(coder.get_mut())
+            }
+            Coder::ZstdEncoder(coder) => {
+                write_all(coder, data)?;
+                std::mem::take(coder.get_mut())
+            }
+        })
+    }
+
+    /// Returns the remaining output, like the gzip trailer.
+    fn finish(self) -> std::io::Result<Vec<u8>> {
+        match self {
+            Coder::GzipEncoder(coder) => coder.finish(),
+            Coder::GzipDecoder(coder) => coder.finish(),
+            Coder::DeflateDecoder(coder) => coder.finish(),
+            Coder::ZstdEncoder(coder) => coder.finish(),
+        }
+    }
+}
+
+/// Body coding the data frames of the inner body, the coder is flushed after each frame so
+/// streamed responses are not held back.
+struct CodingBody {
+    inner: BoxBody,
+    /// Taken once the inner body has ended and the remaining output is written.
+    coder: Option<Coder>,
+}
+
+impl Body for CodingBody {
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        loop {
+            let Some(coder) = this.coder.as_mut() else {
+                return Poll::Ready(None);
+            };
+
+            let frame = match Pin::new(&mut this.inner).poll_frame(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Err(error))) => return Poll::Ready(Some(Err(error))),
+                Poll::Ready(Some(Ok(frame))) => frame,
+                Poll::Ready(None) => {
+                    let coder = this.coder.take().unwrap();
+                    return Poll::Ready(Some(coder.finish().map(|data| Frame::data(data.into()))));
+                }
+            };
+
+            // Trailers are dropped, they may describe the body before coding
+            let Ok(data) = frame.into_data() else {
+                continue;
+            };
+            match coder.write(&data) {
+                Err(error) => return Poll::Ready(Some(Err(error))),
+                Ok(output) if !output.is_empty() => {
+                    return Poll::Ready(Some(Ok(Frame::data(output.into()))))
+                }
+                Ok(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use chico_file::types::CompressionLevel;
+    use flate2::read::GzDecoder;
+    use http::{header, Response, StatusCode};
+    use http_body_util::BodyExt;
+    use rstest::rstest;
+
+    use super::{transcode, AcceptEncoding, Gzip, Zstd};
+    use crate::{
+        handlers::{full, BoxBody},
+        testing::{request, MockBody},
+    };
+
+    async fn body_bytes(response: Response<BoxBody>) -> Vec<u8> {
+        response
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes()
+            .to_vec()
+    }
+
+    fn decompress(data: &[u8]) -> String {
+        let mut decoded = String::new();
+        GzDecoder::new(data).read_to_string(&mut decoded).unwrap();
+        decoded
+    }
+
+    fn compressible_content() -> String {
+        (0..2000)
+            .map(|i| format!("<li id=\"item-{i}\">Item {} of the list</li>", i * 7 % 13))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[rstest]
+    #[case("gzip", true)]
+    #[case("gzip, deflate, br", true)]
+    #[case("deflate, GZIP;q=0.5", true)]
+    #[case("*", true)]
+    #[case("br", false)]
+    #[case("gzip;q=0", false)]
+    #[case("x-gzip", true)]
+    #[case("", false)]
+    fn test_accepts_gzip(#[case] accept_encoding: &str, #[case] expected: bool) {
+        let request = request()
+            .header(header::ACCEPT_ENCODING, accept_encoding)
+            .body(MockBody::new(b""))
+            .unwrap();
+        assert_eq!(
+            AcceptEncoding::from_request(&request).accepts("gzip"),
+            expected
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compress_sets_headers_and_compresses_body() {
+        let content = compressible_content();
+        let response = Response::builder()
+            .header(header::CONTENT_LENGTH, content.len())
+            .body(full(content.clone()))
+            .unwrap();
+
+        let response = Gzip::new(CompressionLevel::DEFAULT).compress(response);
+
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[header::VARY], "Accept-Encoding");
+        assert!(!response.headers().contains_key(header::CONTENT_LENGTH));
+        let body = body_bytes(response).await;
+        assert!(body.len() < content.len());
+        assert_eq!(decompress(&body), content);
+    }
+
+    #[tokio::test]
+    async fn test_compress_higher_level_yields_smaller_body() {
+        let content = compressible_content();
+        let compress = |level| {
+            Gzip::new(CompressionLevel::new(level).unwrap())
+                .compress(Response::new(full(content.clone())))
+        };
+
+        let fastest = body_bytes(compress(1)).await;
+        let best = body_bytes(compress(9)).await;
+
+        assert!(best.len() < fastest.len());
+        assert_eq!(decompress(&best), content);
+        assert_eq!(decompress(&fastest), content);
+    }
+
+    #[tokio::test]
+    async fn test_zstd_dictionary_yields_smaller_body() {
+        // Small JSON responses share most of their bytes with the samples of the dictionary
+        let dictionary = (0..20)
+            .flat_map(|id| format!(r#"{{"id":{id},"name":"user-{id}","email":"user-{id}@example.com","active":true,"roles":["reader"]}}"#).into_bytes())
+            .collect::<Vec<u8>>();
+        let content = r#"{"id":4242,"name":"user-4242","email":"user-4242@example.com","active":false,"roles":["reader"]}"#;
+        let compress = |dictionary| {
+            let response = Zstd::new(dictionary).compress(Response::new(full(content)));
+            assert_eq!(response.headers()[header::CONTENT_ENCODING], "zstd");
+            body_bytes(response)
+        };
+
+        let plain = compress(None).await;
+        let with_dictionary = compress(Some(dictionary.clone())).await;
+
+        assert!(
+            with_dictionary.len() < plain.len(),
+            "{} >= {}",
+            with_dictionary.len(),
+            plain.len()
+        );
+        let mut decoded = String::new();
+        zstd::stream::read::Decoder::with_dictionary(with_dictionary.as_slice(), &dictionary)
+            .unwrap()
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, content);
+    }
+
+    #[rstest]
+    #[case(StatusCode::PARTIAL_CONTENT, None)]
+    #[case(StatusCode::NOT_MODIFIED, None)]
+    #[case(StatusCode::OK, Some("br"))]
+    #[tokio::test]
+    async fn test_compress_skips_encoded_and_partial_responses(
+        #[case] status: StatusCode,
+        #[case] content_encoding: Option<&str>,
+    ) {
+        let mut response = Response::builder().status(status);
+        if let Some(content_encoding) = content_encoding {
+            response = response.header(header::CONTENT_ENCODING, content_encoding);
+        }
+        let response = response.body(full("Hello")).unwrap();
+
+        let response = Gzip::new(CompressionLevel::DEFAULT).compress(response);
+
+        assert_ne!(
+            response.headers().get(header::CONTENT_ENCODING),
+            Some(&http::HeaderValue::from_static("gzip"))
+        );
+        assert_eq!(body_bytes(response).await, b"Hello");
+    }
+
+    fn gzipped(content: &str) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn deflated(content: &str) -> Vec<u8> {
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn encoded_response(coding: &str, body: Vec<u8>) -> Response<BoxBody> {
+        Response::builder()
+            .header(header::CONTENT_ENCODING, coding)
+            .header(header::CONTENT_LENGTH, body.len())
+            .body(full(body))
+            .unwrap()
+    }
+
+    fn accepting(accept_encoding: &str) -> AcceptEncoding {
+        let request = request()
+            .header(header::ACCEPT_ENCODING, accept_encoding)
+            .body(MockBody::new(b""))
+            .unwrap();
+        AcceptEncoding::from_request(&request)
+    }
+
+    #[rstest]
+    #[case("gzip", gzipped as fn(&str) -> Vec<u8>)]
+    #[case("deflate", deflated as fn(&str) -> Vec<u8>)]
+    #[tokio::test]
+    async fn test_transcode_decodes_encoding_the_client_does_not_accept(
+        #[case] coding: &str,
+        #[case] encode: fn(&str) -> Vec<u8>,
+    ) {
+        let content = compressible_content();
+        let response = encoded_response(coding, encode(&content));
+
+        let response = transcode(response, &accepting("br"));
+
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+        assert!(!response.headers().contains_key(header::CONTENT_LENGTH));
+        assert_eq!(response.headers()[header::VARY], "Accept-Encoding");
+        assert_eq!(body_bytes(response).await, content.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_transcode_passes_through_accepted_encoding() {
+        let body = gzipped("Hello");
+        let response = encoded_response("gzip", body.clone());
+
+        let response = transcode(response, &accepting("gzip, br"));
+
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[header::VARY], "Accept-Encoding");
+        assert_eq!(body_bytes(response).await, body);
+    }
+
+    #[tokio::test]
+    async fn test_transcode_forwards_unknown_encoding() {
+        let response = encoded_response("br", b"brotli".to_vec());
+
+        let response = transcode(response, &accepting("gzip"));
+
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "br");
+        assert_eq!(body_bytes(response).await, b"brotli");
+    }
+
+    #[tokio::test]
+    async fn test_transcode_fails_body_on_corrupt_data() {
+        let response = encoded_response("gzip", b"not gzip".to_vec());
+
+        let response = transcode(response, &accepting("identity"));
+
+        assert!(response.into_body().collect().await.is_err());
+    }
+
+    #[test]
+    fn test_vary_on_encoding_is_not_repeated() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(header::VARY, "Origin, accept-encoding".parse().unwrap());
+
+        super::vary_on_encoding(&mut headers);
+
+        assert_eq!(headers.get_all(header::VARY).iter().count(), 1);
+    }
+}
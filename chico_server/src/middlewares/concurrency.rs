@@ -0,0 +1,165 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use chico_file::types::ConcurrencyQueue;
+use http::Response;
+use http_body_util::BodyExt;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::handlers::BoxBody;
+
+/// Limits the number of requests of a route handled at the same time.
+///
+/// Requests above the limit wait in a FIFO queue when one is configured, they are rejected when
+/// the queue is full or when they waited longer than the queue timeout.
+pub struct ConcurrencyLimit {
+    limit: usize,
+    semaphore: Arc<Semaphore>,
+    queue: Option<ConcurrencyQueue>,
+    queued: AtomicUsize,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(limit: usize, queue: Option<ConcurrencyQueue>) -> Self {
+        Self {
+            limit,
+            semaphore: Arc::new(Semaphore::new(limit)),
+            queue,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Waits for a slot, the slot is held until the returned permit is dropped.
+    ///
+    /// Returns the time the client should wait before retrying when the request is rejected: the
+    /// queue timeout, or one second without queue.
+    pub async fn acquire(&self) -> Result<OwnedSemaphorePermit, Duration> {
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+
+        let Some(queue) = self.queue else {
+            return Err(Duration::from_secs(1));
+        };
+        let queued = self
+            .queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                (queued < queue.size).then_some(queued + 1)
+            });
+        if queued.is_err() {
+            return Err(queue.timeout);
+        }
+
+        let permit = tokio::time::timeout(queue.timeout, self.semaphore.clone().acquire_owned())
+            .await
+            .ok()
+            .and_then(Result::ok);
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+        permit.ok_or(queue.timeout)
+    }
+
+    /// Number of requests being handled.
+    pub fn in_flight(&self) -> usize {
+        self.limit - self.semaphore.available_permits()
+    }
+
+    /// Number of requests waiting for a slot.
+    pub fn queue_depth(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+}
+
+/// Keeps the slot taken until the response body is sent, a proxied body may still be streamed
+/// from the upstream when the handler returns.
+pub fn hold_until_sent(
+    response: Response<BoxBody>,
+    permit: OwnedSemaphorePermit,
+) -> Response<BoxBody> {
+    response.map(|body| {
+        body.map_frame(move |frame| {
+            let _ = &permit;
+            frame
+        })
+        .boxed()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chico_file::types::ConcurrencyQueue;
+    use claims::{assert_err, assert_ok};
+    use http::Response;
+
+    use super::{hold_until_sent, ConcurrencyLimit};
+    use crate::{handlers::full, testing::collect_body};
+
+    #[tokio::test]
+    async fn test_requests_above_limit_are_rejected_without_queue() {
+        let limit = ConcurrencyLimit::new(2, None);
+
+        let first = assert_ok!(limit.acquire().await);
+        let _second = assert_ok!(limit.acquire().await);
+        assert_eq!(limit.in_flight(), 2);
+        assert_eq!(assert_err!(limit.acquire().await), Duration::from_secs(1));
+
+        drop(first);
+        let _permit = assert_ok!(limit.acquire().await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queued_request_gets_released_slot() {
+        let limit = std::sync::Arc::new(ConcurrencyLimit::new(
+            1,
+            Some(ConcurrencyQueue {
+                size: 1,
+                timeout: Duration::from_secs(5),
+            }),
+        ));
+        let permit = assert_ok!(limit.acquire().await);
+
+        let waiting = tokio::spawn({
+            let limit = limit.clone();
+            async move { limit.acquire().await.is_ok() }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(limit.queue_depth(), 1);
+
+        drop(permit);
+        assert!(waiting.await.unwrap());
+        assert_eq!(limit.queue_depth(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queued_request_is_rejected_after_timeout() {
+        let limit = ConcurrencyLimit::new(
+            1,
+            Some(ConcurrencyQueue {
+                size: 1,
+                timeout: Duration::from_secs(5),
+            }),
+        );
+        let _permit = assert_ok!(limit.acquire().await);
+
+        assert_eq!(assert_err!(limit.acquire().await), Duration::from_secs(5));
+        assert_eq!(limit.queue_depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_slot_is_held_until_body_is_sent() {
+        let limit = ConcurrencyLimit::new(1, None);
+        let permit = assert_ok!(limit.acquire().await);
+
+        let response = hold_until_sent(Response::new(full("Hello")), permit);
+        assert_eq!(limit.in_flight(), 1);
+
+        assert_eq!(collect_body(response).await, "Hello");
+        assert_eq!(limit.in_flight(), 0);
+    }
+}
@@ -0,0 +1,157 @@
+use http::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode};
+
+use crate::handlers::{BoxBody, ALLOWED_METHODS};
+
+/// Allows cross-origin requests to a route.
+///
+/// Preflight requests are answered without reaching the route handler, the other responses of the
+/// route get the `Access-Control-Allow-*` headers.
+pub struct Cors {
+    allow_origin: HeaderValue,
+}
+
+impl Cors {
+    /// Allows requests from `origin`, `*` allows any origin.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the origin is not a valid header value.
+    pub fn new(origin: &str) -> Self {
+        Self {
+            allow_origin: HeaderValue::from_str(origin).expect("Expected valid CORS origin"),
+        }
+    }
+
+    /// Returns true for a CORS preflight: an `OPTIONS` request with `Origin` and
+    /// `Access-Control-Request-Method` headers.
+    pub fn is_preflight<B>(request: &Request<B>) -> bool {
+        request.method() == Method::OPTIONS
+            && request.headers().contains_key(header::ORIGIN)
+            && request
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    /// Answers a preflight with `204 No Content`, allowing the headers the preflight asked for.
+    pub fn preflight_response<B>(&self, request: &Request<B>) -> Response<BoxBody> {
+        let allow_headers = request
+            .headers()
+            .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .cloned()
+            .unwrap_or(HeaderValue::from_static("*"));
+
+        let mut response = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(crate::handlers::full(""))
+            .unwrap();
+        self.insert_headers(response.headers_mut(), allow_headers);
+        response
+    }
+
+    /// Adds the CORS headers to a response of the route, replacing the ones set by the handler.
+    pub fn apply(&self, response: &mut Response<BoxBody>) {
+        self.insert_headers(response.headers_mut(), HeaderValue::from_static("*"));
+    }
+
+    fn insert_headers(&self, headers: &mut HeaderMap, allow_headers: HeaderValue) {
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_ORIGIN,
+            self.allow_origin.clone(),
+        );
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_static(ALLOWED_METHODS),
+        );
+        headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{header, Method, Response, StatusCode};
+    use rstest::rstest;
+
+    use super::Cors;
+    use crate::{
+        handlers::full,
+        testing::{collect_body, request, MockBody},
+    };
+
+    #[rstest]
+    #[case(Method::OPTIONS, true, true, true)]
+    #[case(Method::OPTIONS, true, false, false)]
+    #[case(Method::OPTIONS, false, true, false)]
+    #[case(Method::GET, true, true, false)]
+    fn test_is_preflight(
+        #[case] method: Method,
+        #[case] origin: bool,
+        #[case] request_method: bool,
+        #[case] expected: bool,
+    ) {
+        let mut builder = request().method(method);
+        if origin {
+            builder = builder.header(header::ORIGIN, "https://app.example.com");
+        }
+        if request_method {
+            builder = builder.header(header::ACCESS_CONTROL_REQUEST_METHOD, "PUT");
+        }
+        let request = builder.body(MockBody::new(b"")).unwrap();
+
+        assert_eq!(Cors::is_preflight(&request), expected);
+    }
+
+    #[tokio::test]
+    async fn test_preflight_response() {
+        let cors = Cors::new("https://app.example.com");
+        let request = request()
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, "https://app.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+            .body(MockBody::new(b""))
+            .unwrap();
+
+        let response = cors.preflight_response(&request);
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_METHODS],
+            "GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS"
+        );
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type"
+        );
+        assert_eq!(collect_body(response).await, "");
+    }
+
+    #[test]
+    fn test_apply_replaces_handler_headers() {
+        let cors = Cors::new("*");
+        let mut response = Response::builder()
+            .header(
+                header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                "https://other.example.com",
+            )
+            .body(full("Hello"))
+            .unwrap();
+
+        cors.apply(&mut response);
+
+        let headers = response.headers();
+        assert_eq!(
+            headers
+                .get_all(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .iter()
+                .count(),
+            1
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_HEADERS], "*");
+    }
+}
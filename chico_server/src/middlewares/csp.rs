@@ -0,0 +1,157 @@
+use chico_file::types;
+use http::{header, HeaderName, HeaderValue, Response};
+
+use crate::handlers::BoxBody;
+
+/// Adds the `csp` or `csp_report_only` policy of a virtual host, or of the options, to its HTML
+/// responses.
+///
+/// Responses already carrying the header keep their policy, like the pages of an upstream with
+/// its own.
+pub struct ContentSecurityPolicy {
+    name: HeaderName,
+    value: HeaderValue,
+}
+
+impl ContentSecurityPolicy {
+    /// # Panics
+    ///
+    /// Panics if the policy is invalid, see [`ContentSecurityPolicy::validate`].
+    pub fn new(csp: &types::ContentSecurityPolicy) -> Self {
+        let name = if csp.report_only {
+            header::CONTENT_SECURITY_POLICY_REPORT_ONLY
+        } else {
+            header::CONTENT_SECURITY_POLICY
+        };
+        Self {
+            name,
+            value: HeaderValue::from_str(&csp.policy).expect("Expected valid policy"),
+        }
+    }
+
+    /// Checks that the policy is a non-empty header value.
+    pub fn validate(csp: &types::ContentSecurityPolicy) -> Result<(), String> {
+        if csp.policy.trim().is_empty() {
+            return Err("csp needs a policy".to_string());
+        }
+        HeaderValue::from_str(&csp.policy)
+            .map(|_| ())
+            .map_err(|_| format!("invalid csp policy {:?}", csp.policy))
+    }
+
+    pub fn apply(&self, response: &mut Response<BoxBody>) {
+        if !is_html(response) || response.headers().contains_key(&self.name) {
+            return;
+        }
+        response
+            .headers_mut()
+            .insert(self.name.clone(), self.value.clone());
+    }
+}
+
+fn is_html(response: &Response<BoxBody>) -> bool {
+    response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("text/html"))
+}
+
+#[cfg(test)]
+mod tests {
+    use chico_file::types;
+    use http::{header, Response};
+    use rstest::rstest;
+
+    use super::ContentSecurityPolicy;
+    use crate::handlers::full;
+
+    fn csp(report_only: bool) -> ContentSecurityPolicy {
+        ContentSecurityPolicy::new(&types::ContentSecurityPolicy {
+            policy: "default-src 'self'".to_string(),
+            report_only,
+        })
+    }
+
+    fn response(content_type: &str) -> Response<crate::handlers::BoxBody> {
+        Response::builder()
+            .header(header::CONTENT_TYPE, content_type)
+            .body(full("body"))
+            .unwrap()
+    }
+
+    #[rstest]
+    #[case("text/html")]
+    #[case("text/html; charset=utf-8")]
+    #[case("Text/HTML")]
+    fn test_csp_is_added_to_html_responses(#[case] content_type: &str) {
+        let mut response = response(content_type);
+
+        csp(false).apply(&mut response);
+
+        assert_eq!(
+            response.headers()[header::CONTENT_SECURITY_POLICY],
+            "default-src 'self'"
+        );
+        assert!(!response
+            .headers()
+            .contains_key(header::CONTENT_SECURITY_POLICY_REPORT_ONLY));
+    }
+
+    #[rstest]
+    #[case("application/json")]
+    #[case("text/css")]
+    #[case("text/plain")]
+    fn test_csp_is_not_added_to_other_responses(#[case] content_type: &str) {
+        let mut response = response(content_type);
+
+        csp(false).apply(&mut response);
+
+        assert!(!response
+            .headers()
+            .contains_key(header::CONTENT_SECURITY_POLICY));
+    }
+
+    #[test]
+    fn test_csp_report_only() {
+        let mut response = response("text/html");
+
+        csp(true).apply(&mut response);
+
+        assert_eq!(
+            response.headers()[header::CONTENT_SECURITY_POLICY_REPORT_ONLY],
+            "default-src 'self'"
+        );
+        assert!(!response
+            .headers()
+            .contains_key(header::CONTENT_SECURITY_POLICY));
+    }
+
+    #[test]
+    fn test_csp_keeps_policy_of_response() {
+        let mut response = response("text/html");
+        response.headers_mut().insert(
+            header::CONTENT_SECURITY_POLICY,
+            "script-src 'none'".parse().unwrap(),
+        );
+
+        csp(false).apply(&mut response);
+
+        assert_eq!(
+            response.headers()[header::CONTENT_SECURITY_POLICY],
+            "script-src 'none'"
+        );
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("default-src 'self'\n")]
+    fn test_validate_rejects_invalid_policy(#[case] policy: &str) {
+        let csp = types::ContentSecurityPolicy {
+            policy: policy.to_string(),
+            report_only: false,
+        };
+        assert!(ContentSecurityPolicy::validate(&csp).is_err());
+    }
+}
@@ -0,0 +1,432 @@
+use chico_file::types::{HeaderOperator, Middleware};
+use http::{HeaderMap, HeaderName, HeaderValue, Response};
+
+use crate::handlers::BoxBody;
+
+/// Changes the response headers of a route with its `header` middlewares.
+///
+/// `+` (add), `=` (set) and `~` (replace) apply before the handler: they prepare headers the
+/// handler's response overrides when it sets the same field. `>` and `~>` are their deferred
+/// variants and apply to the response of the handler, like `-` (delete) and `?` (default) which
+/// are always about the final response. Rules apply in the order of the config.
+pub struct HeaderRules {
+    rules: Vec<HeaderRule>,
+}
+
+struct HeaderRule {
+    operator: HeaderOperator,
+    name: FieldName,
+    value: String,
+    replace_with: String,
+}
+
+/// Field targeted by a rule, `-` may use a prefix or suffix `*` wildcard.
+enum FieldName {
+    Exact(HeaderName),
+    Prefix(String),
+    Suffix(String),
+}
+
+impl FieldName {
+    fn new(operator: &HeaderOperator, name: &str) -> Self {
+        let name = name.to_ascii_lowercase();
+        if *operator == HeaderOperator::Delete {
+            if let Some(prefix) = name.strip_suffix('*') {
+                return FieldName::Prefix(prefix.to_string());
+            }
+            if let Some(suffix) = name.strip_prefix('*') {
+                return FieldName::Suffix(suffix.to_string());
+            }
+        }
+        FieldName::Exact(
+            HeaderName::from_bytes(name.as_bytes()).expect("Expected valid header name"),
+        )
+    }
+
+    fn matches(&self, name: &HeaderName) -> bool {
+        match self {
+            FieldName::Exact(field) => field == name,
+            FieldName::Prefix(prefix) => name.as_str().starts_with(prefix.as_str()),
+            FieldName::Suffix(suffix) => name.as_str().ends_with(suffix.as_str()),
+        }
+    }
+}
+
+impl HeaderRules {
+    /// Returns the rules of the `header` middlewares, or `None` when the route has none.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a header name or value is invalid, see [`HeaderRules::validate`].
+    pub fn from_middlewares(middlewares: &[Middleware]) -> Option<Self> {
+        let rules: Vec<_> = middlewares
+            .iter()
+            .filter_map(|middleware| match middleware {
+                Middleware::Header {
+                    operator,
+                    name,
+                    value,
+                    replace_with,
+                } => Some(HeaderRule {
+                    operator: operator.clone(),
+                    name: FieldName::new(operator, name),
+                    value: value.clone().unwrap_or_default(),
+                    replace_with: replace_with.clone().unwrap_or_default(),
+                }),
+                _ => None,
+            })
+            .collect();
+        (!rules.is_empty()).then_some(Self { rules })
+    }
+
+    /// Checks the arguments of a `header` middleware.
+    pub fn validate(middleware: &Middleware) -> Result<(), String> {
+        let Middleware::Header {
+            operator,
+            name,
+            value,
+            ..
+        } = middleware
+        else {
+            return Ok(());
+        };
+
+        // `*` is allowed in header names, the wildcards of `-` need no special case
+        if HeaderName::from_bytes(name.as_bytes()).is_err() {
+            return Err(format!("invalid header name {name}"));
+        }
+
+        match operator {
+            HeaderOperator::Add
+            | HeaderOperator::Set
+            | HeaderOperator::DeferSet
+            | HeaderOperator::Default => {
+                let value = value
+                    .as_deref()
+                    .ok_or_else(|| format!("header {name} needs a value"))?;
+                HeaderValue::from_str(value)
+                    .map_err(|_| format!("invalid value {value} of header {name}"))?;
+            }
+            HeaderOperator::Replace | HeaderOperator::DeferReplace => {
+                if value.is_none() {
+                    return Err(format!("header {name} needs the text to replace"));
+                }
+            }
+            HeaderOperator::Delete => {}
+        }
+        Ok(())
+    }
+
+    /// Applies the rules to the response of the route.
+    pub fn apply(&self, response: &mut Response<BoxBody>) {
+        let mut prepared = HeaderMap::new();
+        for rule in self.rules.iter().filter(|rule| !rule.is_deferred()) {
+            rule.apply(&mut prepared);
+        }
+
+        let headers = response.headers_mut();
+        for name in prepared.keys() {
+            if !headers.contains_key(name) {
+                for value in prepared.get_all(name) {
+                    headers.append(name.clone(), value.clone());
+                }
+            }
+        }
+
+        for rule in self.rules.iter().filter(|rule| rule.is_deferred()) {
+            rule.apply(headers);
+        }
+    }
+
+    /// Applies the rules in their order to `headers`, the deferred ones as the others, for the
+    /// `header_up` and `header_down` rules of a proxy which change the headers as they are.
+    pub fn apply_in_order(&self, headers: &mut HeaderMap) {
+        for rule in &self.rules {
+            rule.apply(headers);
+        }
+    }
+}
+
+impl HeaderRule {
+    /// Returns true for the rules applying to the response of the handler.
+    fn is_deferred(&self) -> bool {
+        matches!(
+            self.operator,
+            HeaderOperator::DeferSet
+                | HeaderOperator::DeferReplace
+                | HeaderOperator::Delete
+                | HeaderOperator::Default
+        )
+    }
+
+    fn apply(&self, headers: &mut HeaderMap) {
+        if let HeaderOperator::Delete = self.operator {
+            let names: Vec<_> = headers
+                .keys()
+                .filter(|name| self.name.matches(name))
+                .cloned()
+                .collect();
+            for name in names {
+                headers.remove(name);
+            }
+            return;
+        }
+
+        let FieldName::Exact(name) = &self.name else {
+            return;
+        };
+        match self.operator {
+            HeaderOperator::Add => {
+                headers.append(name, self.header_value());
+            }
+            HeaderOperator::Set | HeaderOperator::DeferSet => {
+                headers.insert(name, self.header_value());
+            }
+            HeaderOperator::Default => {
+                if !headers.contains_key(name) {
+                    headers.insert(name, self.header_value());
+                }
+            }
+            HeaderOperator::Replace | HeaderOperator::DeferReplace => {
+                let values: Vec<_> = headers
+                    .get_all(name)
+                    .iter()
+                    .map(|value| self.replace(value))
+                    .collect();
+                headers.remove(name);
+                for value in values {
+                    headers.append(name, value);
+                }
+            }
+            HeaderOperator::Delete => {}
+        }
+    }
+
+    fn header_value(&self) -> HeaderValue {
+        HeaderValue::from_str(&self.value).expect("Expected valid header value")
+    }
+
+    /// Replaces each occurrence of the searched text, a value that is not text is kept.
+    fn replace(&self, value: &HeaderValue) -> HeaderValue {
+        value
+            .to_str()
+            .ok()
+            .and_then(|text| {
+                HeaderValue::from_str(&text.replace(&self.value, &self.replace_with)).ok()
+            })
+            .unwrap_or_else(|| value.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chico_file::types::{HeaderOperator, Middleware};
+    use claims::{assert_err, assert_ok};
+    use http::{header, Response};
+    use rstest::rstest;
+
+    use super::HeaderRules;
+    use crate::handlers::{full, BoxBody};
+
+    fn rule(operator: HeaderOperator, name: &str, value: Option<&str>) -> Middleware {
+        Middleware::Header {
+            operator,
+            name: name.to_string(),
+            value: value.map(str::to_string),
+            replace_with: None,
+        }
+    }
+
+    fn replace(operator: HeaderOperator, name: &str, search: &str, with: &str) -> Middleware {
+        Middleware::Header {
+            operator,
+            name: name.to_string(),
+            value: Some(search.to_string()),
+            replace_with: Some(with.to_string()),
+        }
+    }
+
+    fn response(headers: &[(&str, &str)]) -> Response<BoxBody> {
+        let mut builder = Response::builder();
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(full("")).unwrap()
+    }
+
+    fn apply(middlewares: &[Middleware], response: &mut Response<BoxBody>) {
+        HeaderRules::from_middlewares(middlewares)
+            .unwrap()
+            .apply(response);
+    }
+
+    fn values(response: &Response<BoxBody>, name: &str) -> Vec<String> {
+        response
+            .headers()
+            .get_all(name)
+            .iter()
+            .map(|value| value.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_route_without_header_middleware_has_no_rules() {
+        assert!(HeaderRules::from_middlewares(&[Middleware::Log]).is_none());
+    }
+
+    #[test]
+    fn test_add_set_and_default() {
+        let mut response = response(&[("x-existing", "handler")]);
+
+        apply(
+            &[
+                rule(HeaderOperator::Add, "X-Added", Some("1")),
+                rule(HeaderOperator::Add, "X-Added", Some("2")),
+                rule(HeaderOperator::Set, "X-Set", Some("set")),
+                rule(HeaderOperator::Default, "X-Existing", Some("default")),
+                rule(HeaderOperator::Default, "X-Missing", Some("default")),
+            ],
+            &mut response,
+        );
+
+        assert_eq!(values(&response, "x-added"), ["1", "2"]);
+        assert_eq!(values(&response, "x-set"), ["set"]);
+        assert_eq!(values(&response, "x-existing"), ["handler"]);
+        assert_eq!(values(&response, "x-missing"), ["default"]);
+    }
+
+    #[test]
+    fn test_handler_overrides_set_but_not_deferred_set() {
+        let mut response = response(&[("server", "upstream"), ("x-powered-by", "php")]);
+
+        apply(
+            &[
+                rule(HeaderOperator::Set, "Server", Some("chico")),
+                rule(HeaderOperator::DeferSet, "X-Powered-By", Some("chico")),
+            ],
+            &mut response,
+        );
+
+        assert_eq!(values(&response, "server"), ["upstream"]);
+        assert_eq!(values(&response, "x-powered-by"), ["chico"]);
+    }
+
+    #[rstest]
+    #[case(HeaderOperator::Replace, vec!["max-age=60"])]
+    #[case(HeaderOperator::DeferReplace, vec!["max-age=3600"])]
+    fn test_replace_applies_before_or_after_the_handler(
+        #[case] operator: HeaderOperator,
+        #[case] expected: Vec<&str>,
+    ) {
+        let mut response = response(&[(header::CACHE_CONTROL.as_str(), "max-age=60")]);
+
+        apply(
+            &[replace(operator, "Cache-Control", "60", "3600")],
+            &mut response,
+        );
+
+        assert_eq!(values(&response, "cache-control"), expected);
+    }
+
+    #[test]
+    fn test_replace_rewrites_prepared_header() {
+        let mut response = response(&[]);
+
+        apply(
+            &[
+                rule(HeaderOperator::Set, "X-Frame", Some("SAMEORIGIN")),
+                replace(HeaderOperator::Replace, "X-Frame", "SAME", "DENY-"),
+            ],
+            &mut response,
+        );
+
+        assert_eq!(values(&response, "x-frame"), ["DENY-ORIGIN"]);
+    }
+
+    #[test]
+    fn test_deferred_replace_rewrites_every_value_and_may_remove_text() {
+        let mut response =
+            response(&[("set-cookie", "a=1; Secure"), ("set-cookie", "b=2; Secure")]);
+
+        apply(
+            &[replace(
+                HeaderOperator::DeferReplace,
+                "Set-Cookie",
+                "; Secure",
+                "",
+            )],
+            &mut response,
+        );
+
+        assert_eq!(values(&response, "set-cookie"), ["a=1", "b=2"]);
+    }
+
+    #[test]
+    fn test_apply_in_order_overrides_existing_headers() {
+        let mut response = response(&[("server", "upstream"), ("authorization", "Basic old")]);
+
+        HeaderRules::from_middlewares(&[
+            rule(HeaderOperator::Set, "Authorization", Some("Bearer token")),
+            rule(HeaderOperator::Delete, "Server", None),
+            rule(HeaderOperator::Add, "Via", Some("chico")),
+            replace(HeaderOperator::Replace, "Via", "chico", "1.1 chico"),
+        ])
+        .unwrap()
+        .apply_in_order(response.headers_mut());
+
+        assert_eq!(values(&response, "authorization"), ["Bearer token"]);
+        assert!(values(&response, "server").is_empty());
+        assert_eq!(values(&response, "via"), ["1.1 chico"]);
+    }
+
+    #[test]
+    fn test_delete_with_wildcards() {
+        let mut response = response(&[
+            ("x-debug-id", "1"),
+            ("x-debug-time", "2"),
+            ("x-request-secret", "3"),
+            ("server", "upstream"),
+        ]);
+
+        apply(
+            &[
+                rule(HeaderOperator::Delete, "X-Debug-*", None),
+                rule(HeaderOperator::Delete, "*-Secret", None),
+                rule(HeaderOperator::Delete, "Server", None),
+            ],
+            &mut response,
+        );
+
+        assert!(response.headers().is_empty(), "{:?}", response.headers());
+    }
+
+    #[rstest]
+    #[case(rule(HeaderOperator::Set, "X-Name", Some("value")))]
+    #[case(rule(HeaderOperator::Delete, "X-Debug-*", None))]
+    #[case(rule(HeaderOperator::Delete, "*", None))]
+    #[case(replace(HeaderOperator::DeferReplace, "Location", "http:", "https:"))]
+    fn test_validate_accepts(#[case] middleware: Middleware) {
+        assert_ok!(HeaderRules::validate(&middleware));
+    }
+
+    #[rstest]
+    #[case(
+        rule(HeaderOperator::Set, "X-Name", None),
+        "header X-Name needs a value"
+    )]
+    #[case(
+        rule(HeaderOperator::Add, "X Name", Some("v")),
+        "invalid header name X Name"
+    )]
+    #[case(
+        rule(HeaderOperator::Delete, "X-Name:*", None),
+        "invalid header name X-Name:*"
+    )]
+    #[case(
+        rule(HeaderOperator::Replace, "Location", None),
+        "header Location needs the text to replace"
+    )]
+    fn test_validate_rejects(#[case] middleware: Middleware, #[case] error: &str) {
+        assert_eq!(assert_err!(HeaderRules::validate(&middleware)), error);
+    }
+}
@@ -0,0 +1,482 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use chico_file::types::{Middleware, MinifyKind};
+use futures_util::stream;
+use http::{header, Response, StatusCode};
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::{Bytes, Frame};
+use tracing::debug;
+
+use crate::{
+    handlers::{file::ServedFile, full, BoxBody},
+    memory::{MemoryBudget, MemoryConsumer, ShedPriority},
+};
+
+/// Responses larger than this are sent as they are.
+const MAX_MINIFY_SIZE: u64 = 1024 * 1024;
+
+/// Total size of the minified files kept by a route, the cache is emptied above it.
+const CACHE_CAPACITY: usize = 16 * 1024 * 1024;
+
+/// Minifies the `text/html`, `text/css` and `application/javascript` responses of a route.
+///
+/// Only complete `200` responses of a known size up to [`MAX_MINIFY_SIZE`] are minified, before
+/// they are compressed. The minified content of a served file is cached until the modification
+/// time of the file changes. A body that cannot be minified is sent unchanged.
+pub struct Minify {
+    kinds: Vec<MinifyKind>,
+    cache: Arc<Mutex<MinifiedFiles>>,
+    budget: Option<Arc<MemoryBudget>>,
+}
+
+#[derive(Default)]
+struct MinifiedFiles {
+    files: HashMap<PathBuf, (SystemTime, Bytes)>,
+    /// Total size of the cached contents.
+    size: usize,
+}
+
+impl Minify {
+    pub fn new(kinds: Vec<MinifyKind>) -> Self {
+        Self {
+            kinds,
+            cache: Arc::new(Mutex::new(MinifiedFiles::default())),
+            budget: None,
+        }
+    }
+
+    /// Accounts the minified files in the memory budget, they are the first to go when the
+    /// caches of all routes are over it.
+    pub fn with_memory_budget(mut self, budget: &Arc<MemoryBudget>) -> Self {
+        budget.register(
+            ShedPriority::MinifiedFiles,
+            Arc::downgrade(&self.cache) as _,
+        );
+        self.budget = Some(budget.clone());
+        self
+    }
+
+    /// Returns the minification of the `minify` middleware, or `None` when the route has none.
+    pub fn from_middlewares(middlewares: &[Middleware]) -> Option<Self> {
+        middlewares.iter().find_map(|middleware| match middleware {
+            Middleware::Minify(kinds) => Some(Self::new(kinds.clone())),
+            _ => None,
+        })
+    }
+
+    /// Returns the response with its body minified.
+    ///
+    /// Responses of other kinds, partial, encoded, of unknown size or above the size limit are
+    /// returned unchanged.
+    pub async fn apply(&self, response: Response<BoxBody>) -> Response<BoxBody> {
+        let Some(kind) = self.kind_of(&response) else {
+            return response;
+        };
+        let served_file = response.extensions().get::<ServedFile>().cloned();
+        let len = served_file.as_ref().map(|file| file.len).or_else(|| {
+            response
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok()?.parse().ok())
+        });
+        if len.is_none_or(|len| len > MAX_MINIFY_SIZE) {
+            return response;
+        }
+        // Files are cached by path and modification time
+        let cache_key = served_file.and_then(|file| Some((file.path, file.modified?)));
+
+        let (mut parts, body) = response.into_parts();
+        parts.headers.remove(header::CONTENT_LENGTH);
+        if let Some((path, modified)) = &cache_key {
+            if let Some((cached_modified, minified)) = self.cache.lock().unwrap().files.get(path) {
+                if cached_modified == modified {
+                    return Response::from_parts(parts, full(minified.clone()));
+                }
+            }
+        }
+
+        let body = match body.collect().await {
+            Ok(collected) => collected.to_bytes(),
+            // The client gets the error as well
+            Err(error) => {
+                let failed = stream::iter([Err::<Frame<Bytes>, _>(error)]);
+                return Response::from_parts(parts, StreamBody::new(failed).boxed());
+            }
+        };
+        let Some(minified) = std::str::from_utf8(&body)
+            .ok()
+            .and_then(|content| minify(kind, content))
+        else {
+            debug!("could not minify the {:?} response, sent unchanged", kind);
+            return Response::from_parts(parts, full(body));
+        };
+        let minified = Bytes::from(minified);
+
+        if let Some((path, modified)) = cache_key {
+            let (before, after) = {
+                let mut cache = self.cache.lock().unwrap();
+                let before = cache.size;
+                cache.insert(path, modified, minified.clone());
+                (before, cache.size)
+            };
+            if let Some(budget) = &self.budget {
+                budget.resize(before, after);
+            }
+        }
+        Response::from_parts(parts, full(minified))
+    }
+
+    /// Returns the kind of a complete, not encoded response this middleware minifies.
+    fn kind_of(&self, response: &Response<BoxBody>) -> Option<MinifyKind> {
+        if response.status() != StatusCode::OK
+            || response.headers().contains_key(header::CONTENT_ENCODING)
+        {
+            return None;
+        }
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)?
+            .to_str()
+            .ok()?;
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let kind = match mime.as_str() {
+            "text/html" => MinifyKind::Html,
+            "text/css" => MinifyKind::Css,
+            "application/javascript" | "text/javascript" => MinifyKind::Js,
+            _ => return None,
+        };
+        self.kinds.contains(&kind).then_some(kind)
+    }
+}
+
+impl MinifiedFiles {
+    fn insert(&mut self, path: PathBuf, modified: SystemTime, minified: Bytes) {
+        if let Some((_, previous)) = self.files.remove(&path) {
+            self.size -= previous.len();
+        }
+        if self.size + minified.len() > CACHE_CAPACITY {
+            self.files.clear();
+            self.size = 0;
+        }
+        self.size += minified.len();
+        self.files.insert(path, (modified, minified));
+    }
+}
+
+impl MemoryConsumer for Mutex<MinifiedFiles> {
+    fn usage(&self) -> usize {
+        self.lock().unwrap().size
+    }
+
+    fn shed(&self, bytes: usize) -> usize {
+        let mut cache = self.lock().unwrap();
+        let mut freed = 0;
+        while freed < bytes {
+            let Some(path) = cache.files.keys().next().cloned() else {
+                break;
+            };
+            let (_, minified) = cache.files.remove(&path).unwrap();
+            cache.size -= minified.len();
+            freed += minified.len();
+        }
+        freed
+    }
+}
+
+/// Returns the minified content, `None` when it cannot be parsed.
+fn minify(kind: MinifyKind, content: &str) -> Option<String> {
+    match kind {
+        MinifyKind::Html => minify_html(content),
+        MinifyKind::Css => minifier::css::minify(content)
+            .ok()
+            .map(|minified| minified.to_string()),
+        MinifyKind::Js => minifier::js::minify(content)
+            .ok()
+            .map(|minified| minified.to_string()),
+    }
+}
+
+/// Elements whose content is copied unchanged.
+const RAW_ELEMENTS: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+/// Conservative HTML minification: comments are removed, except conditional comments, and runs
+/// of whitespace between text and tags collapse to a single space or line break. Tags and the
+/// content of `pre`, `textarea`, `script` and `style` are kept as they are.
+///
+/// Returns `None` on an unterminated comment, tag or raw element.
+fn minify_html(html: &str) -> Option<String> {
+    // ASCII lowercasing keeps the byte offsets
+    let lowercase = html.to_ascii_lowercase();
+    let mut minified = String::with_capacity(html.len());
+    // Whitespace seen since the last output, a line break when the run has one
+    let mut whitespace = None;
+    let mut i = 0;
+
+    while i < html.len() {
+        let rest = &html[i..];
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->")? + "-->".len();
+            if rest.starts_with("<!--[if") {
+                push_pending(&mut minified, &mut whitespace);
+                minified.push_str(&rest[..end]);
+            }
+            i += end;
+            continue;
+        }
+
+        if rest.starts_with('<')
+            && rest[1..]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || matches!(c, '/' | '!' | '?'))
+        {
+            let end = tag_end(rest)?;
+            push_pending(&mut minified, &mut whitespace);
+            minified.push_str(&rest[..end]);
+
+            let name: String = rest[1..]
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric())
+                .collect::<String>()
+                .to_ascii_lowercase();
+            i += end;
+            if RAW_ELEMENTS.contains(&name.as_str()) {
+                let content_len = lowercase[i..].find(&format!("</{name}"))?;
+                minified.push_str(&html[i..i + content_len]);
+                i += content_len;
+            }
+            continue;
+        }
+
+        let c = rest.chars().next().unwrap();
+        if c.is_whitespace() {
+            if whitespace != Some('\n') {
+                whitespace = Some(if c == '\n' { '\n' } else { ' ' });
+            }
+        } else {
+            push_pending(&mut minified, &mut whitespace);
+            minified.push(c);
+        }
+        i += c.len_utf8();
+    }
+
+    Some(minified)
+}
+
+/// Writes the pending whitespace, leading whitespace is dropped.
+fn push_pending(minified: &mut String, whitespace: &mut Option<char>) {
+    if let Some(c) = whitespace.take() {
+        if !minified.is_empty() {
+            minified.push(c);
+        }
+    }
+}
+
+/// Returns the offset after the `>` closing the tag at the start of `tag`, quoted attribute
+/// values may contain `>`.
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in tag.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return Some(i + 1),
+            (None, _) => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use chico_file::types::MinifyKind;
+    use http::{header, Response, StatusCode};
+    use rstest::rstest;
+
+    use super::{minify_html, Minify};
+    use crate::{
+        handlers::{file::ServedFile, full},
+        memory::MemoryBudget,
+        middlewares::cache::ResponseCache,
+        testing::collect_body,
+    };
+
+    fn response(content_type: &str, body: &'static str) -> Response<crate::handlers::BoxBody> {
+        Response::builder()
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::CONTENT_LENGTH, body.len())
+            .body(full(body))
+            .unwrap()
+    }
+
+    fn file_response(
+        body: &'static str,
+        modified: SystemTime,
+    ) -> Response<crate::handlers::BoxBody> {
+        Response::builder()
+            .header(header::CONTENT_TYPE, "text/html")
+            .extension(ServedFile {
+                path: PathBuf::from("/site/index.html"),
+                len: body.len() as u64,
+                modified: Some(modified),
+            })
+            .body(full(body))
+            .unwrap()
+    }
+
+    #[rstest]
+    #[case("<p>  a \t b  </p>", "<p> a b </p>")]
+    #[case(
+        "<ul>\n  <li>a</li>\n  <li>b</li>\n</ul>\n",
+        "<ul>\n<li>a</li>\n<li>b</li>\n</ul>"
+    )]
+    #[case("<p>a<!-- note -->b</p>", "<p>ab</p>")]
+    #[case(
+        "<!--[if IE]><p>ie</p><![endif]-->",
+        "<!--[if IE]><p>ie</p><![endif]-->"
+    )]
+    #[case(
+        "<pre>  a\n   b </pre>  <p> c </p>",
+        "<pre>  a\n   b </pre> <p> c </p>"
+    )]
+    #[case("<SCRIPT>if (a  <  b) {}</SCRIPT>", "<SCRIPT>if (a  <  b) {}</SCRIPT>")]
+    #[case(
+        "<a title='x > y'  href=\"/\">  x</a>",
+        "<a title='x > y'  href=\"/\"> x</a>"
+    )]
+    #[case("1 < 2", "1 < 2")]
+    fn test_minify_html(#[case] html: &str, #[case] expected: &str) {
+        assert_eq!(minify_html(html).as_deref(), Some(expected));
+    }
+
+    #[rstest]
+    #[case("<p>a<!-- unterminated")]
+    #[case("<p class='a>b</p>")]
+    #[case("<script>let a = 1;")]
+    fn test_minify_html_invalid(#[case] html: &str) {
+        assert_eq!(minify_html(html), None);
+    }
+
+    #[rstest]
+    #[case("text/css", "a {\n  color: red;\n}\n", "a{color:red;}")]
+    #[case(
+        "application/javascript",
+        "function add(a, b) {\n  return a + b;\n}\n",
+        "function add(a,b){return a+b;}"
+    )]
+    #[case("text/html; charset=utf-8", "<p>\n  a\n</p>", "<p>\na\n</p>")]
+    #[tokio::test]
+    async fn test_minifies_configured_kinds(
+        #[case] content_type: &str,
+        #[case] body: &'static str,
+        #[case] expected: &str,
+    ) {
+        let minify = Minify::new(vec![MinifyKind::Html, MinifyKind::Css, MinifyKind::Js]);
+
+        let response = minify.apply(response(content_type, body)).await;
+
+        assert!(response.headers().get(header::CONTENT_LENGTH).is_none());
+        assert_eq!(collect_body(response).await, expected);
+    }
+
+    #[tokio::test]
+    async fn test_leaves_other_kinds_unchanged() {
+        let minify = Minify::new(vec![MinifyKind::Css]);
+
+        let html = minify.apply(response("text/html", "<p>  a  </p>")).await;
+        let text = minify.apply(response("text/plain", "a  b")).await;
+
+        assert_eq!(collect_body(html).await, "<p>  a  </p>");
+        assert_eq!(collect_body(text).await, "a  b");
+    }
+
+    #[tokio::test]
+    async fn test_serves_original_when_minification_fails() {
+        let minify = Minify::new(vec![MinifyKind::Html]);
+
+        let response = minify.apply(response("text/html", "<p>a<!-- b")).await;
+
+        assert_eq!(collect_body(response).await, "<p>a<!-- b");
+    }
+
+    #[tokio::test]
+    async fn test_leaves_responses_of_unknown_size_or_status_unchanged() {
+        let minify = Minify::new(vec![MinifyKind::Html]);
+        let unknown_size = Response::builder()
+            .header(header::CONTENT_TYPE, "text/html")
+            .body(full("<p>  a  </p>"))
+            .unwrap();
+        let mut not_found = response("text/html", "<p>  a  </p>");
+        *not_found.status_mut() = StatusCode::NOT_FOUND;
+
+        let unknown_size = minify.apply(unknown_size).await;
+        let not_found = minify.apply(not_found).await;
+
+        assert_eq!(collect_body(unknown_size).await, "<p>  a  </p>");
+        assert_eq!(collect_body(not_found).await, "<p>  a  </p>");
+    }
+
+    #[tokio::test]
+    async fn test_cache_is_keyed_by_modification_time() {
+        let minify = Minify::new(vec![MinifyKind::Html]);
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+
+        let first = minify.apply(file_response("<p>  a  </p>", modified)).await;
+        // same file and modification time: the cached content is served
+        let cached = minify.apply(file_response("<p>  b  </p>", modified)).await;
+        // the file changed
+        let changed = minify
+            .apply(file_response(
+                "<p>  b  </p>",
+                modified + Duration::from_secs(1),
+            ))
+            .await;
+
+        assert_eq!(collect_body(first).await, "<p> a </p>");
+        assert_eq!(collect_body(cached).await, "<p> a </p>");
+        assert_eq!(collect_body(changed).await, "<p> b </p>");
+    }
+
+    #[tokio::test]
+    async fn test_minified_files_are_shed_first_when_response_cache_fills_budget() {
+        let budget = Arc::new(MemoryBudget::new(64));
+        let minify = Minify::new(vec![MinifyKind::Html]).with_memory_budget(&budget);
+        let cache = ResponseCache::new(Duration::from_secs(60), None).with_memory_budget(&budget);
+        let page = minify
+            .apply(file_response("<p>  cached  </p>", SystemTime::UNIX_EPOCH))
+            .await;
+        assert_eq!(collect_body(page).await, "<p> cached </p>");
+        assert_eq!(budget.usage(), 15);
+
+        for path in ["/a", "/b"] {
+            let response = Response::new(full("x".repeat(30)));
+            collect_body(cache.store(format!("GET localhost{path}"), response).await).await;
+        }
+
+        assert_eq!(minify.cache.lock().unwrap().size, 0);
+        assert!(minify.cache.lock().unwrap().files.is_empty());
+        for path in ["/a", "/b"] {
+            let key = format!("GET localhost{path}");
+            assert!(cache.get(&key, &http::HeaderMap::new()).is_some());
+        }
+        assert_eq!(budget.usage(), 60);
+        assert_eq!(budget.pressure_events(), 1);
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Number of tracked clients above which the clients with a full bucket are forgotten.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Key of the bucket shared by all clients.
+const SHARED: IpAddr = IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED);
+
+/// Token bucket rate limiter keyed by client address.
+///
+/// Each client gets a bucket of `requests` tokens that refills continuously, a full bucket every
+/// `window`. A request takes a token and is rejected when the bucket is empty, so bursts up to
+/// `requests` are accepted.
+pub struct RateLimiter {
+    capacity: f64,
+    /// Tokens added per second
+    refill_rate: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(requests: u32, window: Duration) -> Self {
+        Self {
+            capacity: requests as f64,
+            refill_rate: requests as f64 / window.as_secs_f64(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from the bucket of the client.
+    ///
+    /// Returns the time until the next token is available when the bucket is empty.
+    pub fn check(&self, client: IpAddr) -> Result<(), Duration> {
+        self.check_at(client, Instant::now())
+    }
+
+    /// Takes a token from the bucket all clients share, for limits of a whole virtual host.
+    pub fn check_shared(&self) -> Result<(), Duration> {
+        self.check(SHARED)
+    }
+
+    fn check_at(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= PRUNE_THRESHOLD && !buckets.contains_key(&client) {
+            buckets.retain(|_, bucket| self.refilled(bucket, now) < self.capacity);
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: self.capacity,
+            updated_at: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.refill_rate,
+            ))
+        }
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        (bucket.tokens + elapsed.as_secs_f64() * self.refill_rate).min(self.capacity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::IpAddr,
+        time::{Duration, Instant},
+    };
+
+    use claims::{assert_err, assert_ok};
+
+    use super::RateLimiter;
+
+    fn client(last_octet: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, last_octet])
+    }
+
+    #[test]
+    fn test_burst_up_to_limit_is_accepted() {
+        let limiter = RateLimiter::new(10, Duration::from_secs(1));
+        let now = Instant::now();
+
+        for _ in 0..10 {
+            assert_ok!(limiter.check_at(client(1), now));
+        }
+        let retry_after = assert_err!(limiter.check_at(client(1), now));
+        assert_eq!(retry_after, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_tokens_are_refilled_over_the_window() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert_ok!(limiter.check_at(client(1), now));
+        assert_ok!(limiter.check_at(client(1), now));
+        assert_err!(limiter.check_at(client(1), now + Duration::from_secs(29)));
+
+        assert_ok!(limiter.check_at(client(1), now + Duration::from_secs(30)));
+        assert_err!(limiter.check_at(client(1), now + Duration::from_secs(30)));
+
+        // an idle client gets a full bucket, not more
+        let later = now + Duration::from_secs(3600);
+        assert_ok!(limiter.check_at(client(1), later));
+        assert_ok!(limiter.check_at(client(1), later));
+        assert_err!(limiter.check_at(client(1), later));
+    }
+
+    #[test]
+    fn test_clients_have_their_own_bucket() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(1));
+        let now = Instant::now();
+
+        assert_ok!(limiter.check_at(client(1), now));
+        assert_err!(limiter.check_at(client(1), now));
+        assert_ok!(limiter.check_at(client(2), now));
+    }
+
+    #[test]
+    fn test_shared_bucket_is_separate_from_clients() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(1));
+
+        assert_ok!(limiter.check_shared());
+        assert_err!(limiter.check_shared());
+        assert_ok!(limiter.check(client(1)));
+    }
+
+    #[test]
+    fn test_clients_with_full_bucket_are_pruned() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(1));
+        let now = Instant::now();
+
+        for i in 0..super::PRUNE_THRESHOLD {
+            let ip = IpAddr::from((i as u32).to_be_bytes());
+            assert_ok!(limiter.check_at(ip, now));
+        }
+        assert_err!(limiter.check_at(IpAddr::from(0u32.to_be_bytes()), now));
+
+        assert_ok!(limiter.check_at(client(1), now + Duration::from_secs(1)));
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+    }
+}
@@ -1,130 +1,627 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{cmp::Reverse, collections::HashMap, sync::Arc};
 
-use chico_file::types::Config;
-use crates_uri::UriExt;
-use http::Uri;
+use chico_file::types::{
+    Config, Domain, ErrorFormat, LoadBalancer, Matcher, MatcherCondition, MergeSlashes, Middleware,
+    RoutePath, Upstream,
+};
+use http::{HeaderMap, HeaderName, Method};
+use regex::Regex;
+use tracing::error;
 
 use crate::{
+    client::ClientResolver,
     handlers::{
-        file::FileHandler, redirect::RedirectHandler, respond::RespondHandler,
-        reverse_proxy::ReverseProxyHandler,
+        browse::BrowseHandler, dir::DirHandler, error::ErrorPages, file::FileHandler,
+        not_found::NotFoundHandler, redirect::RedirectHandler, respond::RespondHandler,
+        respond_echo::RespondEchoHandler, reverse_proxy::ReverseProxyHandler,
+        rewrite::RewriteHandler, try_files::TryFilesHandler,
+    },
+    load_balance::{
+        canary::Canary, circuit_breaker::CircuitBreaker, dns::DnsCache, health::PassiveHealth,
+        ip_hash::IpHashBalancer, least_connections::LeastConnectionsBalancer, node::Node,
+        pool::PoolStates, random::RandomBalancer, round_robin::RoundRobinBalancer,
+        weighted_round_robin::WeightedRoundRobinBalancer, LoadBalance, SingleUpstream,
     },
-    load_balance::{node::Node, round_robin::RoundRobinBalancer, LoadBalance, SingleUpstream},
+    memory::MemoryBudget,
+    middlewares::{
+        auth::BasicAuth,
+        cache::ResponseCache,
+        compression::{Gzip, Zstd},
+        concurrency::ConcurrencyLimit,
+        cors::Cors,
+        csp::ContentSecurityPolicy,
+        header::HeaderRules,
+        minify::Minify,
+        rate_limit::RateLimiter,
+    },
+    virtual_host::VirtualHostExt,
 };
 
 pub struct ServerPlan {
     virtual_hosts: HashMap<String, VirtualHostPlan>,
+    pool_states: Arc<PoolStates>,
+    /// Answers the requests no virtual host or route matches.
+    pub not_found: NotFoundHandler,
+    /// Host of the HTTP/1.0 requests without Host header.
+    pub default_host: Option<Domain>,
+    /// Answers `421` to the requests whose Host is served by another virtual host than the server
+    /// name of their TLS connection.
+    pub strict_sni_host: bool,
+    /// Methods accepted by the virtual hosts without their own, all methods when unset.
+    pub allowed_methods: Option<Vec<Method>>,
+    /// Budget the caches of all routes share, unlimited when unset.
+    pub memory_budget: Option<Arc<MemoryBudget>>,
 }
 
 impl ServerPlan {
+    /// Returns the virtual host serving `host` on `port`.
+    ///
+    /// A domain equal to the host takes precedence over wildcard domains, then the wildcard with
+    /// the longest suffix wins and `*.` wins over `**.` with the same suffix.
     pub fn find_virtual_host(&self, host: &str, port: u16) -> Option<&VirtualHostPlan> {
-        //todo: do more advanced search and pattern matching for virtual host
-        let vh = self.virtual_hosts.iter().find(|&vh| {
-            Uri::from_str(&vh.1.domain).unwrap().host().unwrap() == host && vh.1.get_port() == port
-        });
-        match vh {
-            Some((_, vhp)) => Some(vhp),
-            None => None,
-        }
+        self.virtual_hosts
+            .values()
+            .filter(|vh| vh.port == port)
+            .filter_map(|vh| Some((host_match(&vh.domain.host, host)?, vh)))
+            .max_by_key(|(host_match, _)| *host_match)
+            .map(|(_, vh)| vh)
+    }
+
+    pub fn virtual_host_count(&self) -> usize {
+        self.virtual_hosts.len()
+    }
+
+    /// Returns the number of routes of all virtual hosts.
+    pub fn route_count(&self) -> usize {
+        self.virtual_hosts
+            .values()
+            .map(|vh| vh.routes.len() + vh.matcher_routes.len() + vh.regex_routes.len())
+            .sum()
     }
 }
 
+/// How a request host matches the host of a virtual host, ordered by precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum HostMatch {
+    /// `**.` matched one or more labels before the suffix of the given length.
+    AnyDepth(usize),
+    /// `*.` matched exactly one label before the suffix of the given length.
+    OneLabel(usize),
+    Exact,
+}
+
+/// Matches `host` against the host of a domain, a leading `*.` stands for exactly one label and
+/// a leading `**.` for one or more labels.
+fn host_match(pattern: &str, host: &str) -> Option<HostMatch> {
+    let host = host.to_ascii_lowercase();
+    let pattern = pattern.to_ascii_lowercase();
+
+    if let Some(suffix) = pattern.strip_prefix("**.") {
+        let labels = host.strip_suffix(suffix)?.strip_suffix('.')?;
+        return (!labels.is_empty()).then_some(HostMatch::AnyDepth(suffix.len()));
+    }
+    if let Some(suffix) = pattern.strip_prefix("*.") {
+        let label = host.strip_suffix(suffix)?.strip_suffix('.')?;
+        return (!label.is_empty() && !label.contains('.'))
+            .then_some(HostMatch::OneLabel(suffix.len()));
+    }
+    (pattern == host).then_some(HostMatch::Exact)
+}
+
 pub struct VirtualHostPlan {
-    domain: String,
+    domain: Domain,
+    port: u16,
     routes: HashMap<String, RoutePlan>,
+    /// Routes of named matchers in declaration order, tried before the path routes.
+    matcher_routes: Vec<(MatcherPlan, RoutePlan)>,
+    /// Routes of regular expressions from the most specific, tried after the exact paths and before
+    /// the prefixes.
+    regex_routes: Vec<(Regex, RoutePlan)>,
+    pub error_format: Option<ErrorFormat>,
+    pub client_resolver: ClientResolver,
+    /// Methods accepted before routing, all methods are accepted when unset.
+    pub allowed_methods: Option<Vec<Method>>,
+    /// Pages replacing the built-in error responses of the virtual host.
+    pub error_pages: ErrorPages,
+    /// Content-Security-Policy of the HTML responses, the one of the options when the virtual
+    /// host has none.
+    pub csp: Option<ContentSecurityPolicy>,
+    /// Limit of the requests of all clients together, checked before the limits of the routes.
+    pub rate_limiter: Option<RateLimiter>,
+    /// Handling of request paths with repeated slashes.
+    pub merge_slashes: MergeSlashes,
 }
 
 impl VirtualHostPlan {
+    /// Returns the most specific route of the path: the route of the exact path, else the most
+    /// specific regular expression matching it, else the longest prefix.
     pub fn find_route(&self, path: &str) -> Option<&RoutePlan> {
-        //todo: do more advanced search and pattern matching for request path
-        let route = self.routes.iter().find(|&r| {
-            if r.0.ends_with("/*") {
-                let asterisk_index = r.0.rfind("*").unwrap();
-                path.starts_with(&r.0[..asterisk_index])
-            } else {
-                r.0 == path
+        self.routes
+            .get(path)
+            .or_else(|| {
+                self.regex_routes
+                    .iter()
+                    .find(|(regex, _)| regex.is_match(path))
+                    .map(|(_, plan)| plan)
+            })
+            .or_else(|| {
+                self.routes
+                    .iter()
+                    .filter(|(pattern, _)| path_matches(pattern, path))
+                    .max_by_key(|(pattern, _)| pattern.len())
+                    .map(|(_, plan)| plan)
+            })
+    }
+
+    /// Returns the route of the first named matcher the request matches, or the route of the
+    /// request path when no matcher matches.
+    pub fn find_route_for(&self, path: &str, headers: &HeaderMap) -> Option<&RoutePlan> {
+        self.matcher_routes
+            .iter()
+            .find(|(matcher, _)| matcher.matches(path, headers))
+            .map(|(_, plan)| plan)
+            .or_else(|| self.find_route(path))
+    }
+}
+
+/// Conditions of a named matcher, a request matches when all of them hold.
+pub struct MatcherPlan {
+    conditions: Vec<MatcherCondition>,
+}
+
+impl MatcherPlan {
+    pub fn new(matcher: &Matcher) -> Self {
+        Self {
+            conditions: matcher.conditions.clone(),
+        }
+    }
+
+    pub fn matches(&self, path: &str, headers: &HeaderMap) -> bool {
+        self.conditions.iter().all(|condition| match condition {
+            MatcherCondition::Path(patterns) => {
+                patterns.iter().any(|pattern| path_matches(pattern, path))
             }
-        });
+            MatcherCondition::Header { name, value } => headers
+                .get_all(name.as_str())
+                .iter()
+                .any(|header_value| header_value == value.as_str()),
+        })
+    }
+
+    /// Returns the path handlers of the matcher route strip from request paths, the first path
+    /// condition or `/` without one.
+    fn route_path(&self) -> String {
+        self.conditions
+            .iter()
+            .find_map(|condition| match condition {
+                MatcherCondition::Path(patterns) => patterns.first().cloned(),
+                _ => None,
+            })
+            .unwrap_or_else(|| "/".to_string())
+    }
+}
+
+/// Returns true when the request path matches the route path `pattern`, a pattern ending with `/*`
+/// matches every path under it.
+pub fn path_matches(pattern: &str, path: &str) -> bool {
+    if pattern.ends_with("/*") {
+        let asterisk_index = pattern.rfind("*").unwrap();
+        path.starts_with(&pattern[..asterisk_index])
+    } else {
+        pattern == path
+    }
+}
 
-        match route {
-            Some((_, plan)) => Some(plan),
-            None => None,
+pub struct RoutePlan {
+    pub handler: HandlerPlan,
+    /// Overrides the error format of the virtual host.
+    pub error_format: Option<ErrorFormat>,
+    pub cors: Option<Cors>,
+    /// Limits the requests of each client, the state lives as long as the plan.
+    pub rate_limiter: Option<RateLimiter>,
+    /// Limits the requests handled at the same time, shared by all clients.
+    pub concurrency_limit: Option<ConcurrencyLimit>,
+    pub gzip: Option<Gzip>,
+    /// Preferred over gzip for clients accepting both.
+    pub zstd: Option<Zstd>,
+    /// Decode the responses in an encoding the client does not accept.
+    pub transcode: bool,
+    pub cache: Option<ResponseCache>,
+    pub headers: Option<HeaderRules>,
+    /// Minifies the responses before they are compressed.
+    pub minify: Option<Minify>,
+    /// Credentials requests of the route need.
+    pub auth: Option<BasicAuth>,
+    /// Methods the route serves, all methods when empty.
+    pub methods: Vec<Method>,
+}
+
+impl RoutePlan {
+    #[allow(dead_code)]
+    pub fn new(handler: HandlerPlan) -> Self {
+        Self {
+            handler,
+            error_format: None,
+            cors: None,
+            rate_limiter: None,
+            concurrency_limit: None,
+            gzip: None,
+            zstd: None,
+            transcode: false,
+            cache: None,
+            headers: None,
+            minify: None,
+            auth: None,
+            methods: vec![],
         }
     }
-    fn get_port(&self) -> u16 {
-        Uri::from_str(&self.domain)
-            .expect("Expected Valid host")
-            .get_port()
+
+    /// Returns true when the route serves requests with the given method.
+    pub fn allows(&self, method: &Method) -> bool {
+        self.methods.is_empty() || self.methods.contains(method)
     }
 }
 
-pub enum RoutePlan {
+pub enum HandlerPlan {
     File(FileHandler),
+    Dir(DirHandler),
+    Browse(BrowseHandler),
     Respond(RespondHandler),
+    RespondEcho(RespondEchoHandler),
     Redirect(RedirectHandler),
     ReverseProxy(ReverseProxyHandler),
+    /// Dispatched again by `handle_request` with the rewritten path.
+    Rewrite(RewriteHandler),
+    TryFiles(TryFilesHandler),
 }
 
 impl ServerPlan {
     pub fn from_config(config: &Config) -> Self {
+        Self::build(config, Arc::new(PoolStates::default()))
+    }
+
+    /// Builds a plan for the given config that replaces this one.
+    ///
+    /// Load balancers of unchanged upstream pools continue their rotation where this plan left it.
+    pub fn rebuild(&self, config: &Config) -> Self {
+        Self::build(config, self.pool_states.clone())
+    }
+
+    fn build(config: &Config, pool_states: Arc<PoolStates>) -> Self {
         let mut vhosts = HashMap::new();
+        let memory_budget = config
+            .options
+            .memory_budget
+            .map(|limit| Arc::new(MemoryBudget::new(limit)));
 
         for vh in &config.virtual_hosts {
             let mut routes = HashMap::new();
+            let mut matcher_routes = vec![];
+            let mut regex_routes = vec![];
             for r in &vh.routes {
+                let matcher = r
+                    .path
+                    .strip_prefix('@')
+                    .and_then(|name| vh.matchers.iter().find(|matcher| matcher.name == name))
+                    .map(MatcherPlan::new);
+                let route_path = matcher
+                    .as_ref()
+                    .map_or_else(|| r.path.clone(), MatcherPlan::route_path);
                 let handler = match &r.handler {
-                    chico_file::types::Handler::File(path) => {
-                        RoutePlan::File(FileHandler::new(path.clone(), r.path.clone()))
-                    }
+                    chico_file::types::Handler::File(path) => HandlerPlan::File(
+                        FileHandler::new(path.clone(), route_path.clone())
+                            .with_sniff_extensionless(r.sniff_extensionless)
+                            .with_max_ranges(r.max_ranges)
+                            .with_negotiate_language(r.negotiate_language),
+                    ),
                     chico_file::types::Handler::Proxy(proxy_config) => {
-                        let balancer: Box<dyn LoadBalance> = match &proxy_config.load_balancer {
-                            chico_file::types::LoadBalancer::NoBalancer(upstream) => {
-                                Box::new(SingleUpstream::new(Node::new(
-                                    upstream.get_host_port().parse().unwrap(),
-                                )))
-                            }
-                            chico_file::types::LoadBalancer::RoundRobin(upstreams) => {
-                                Box::new(RoundRobinBalancer::new(
-                                    upstreams
-                                        .iter()
-                                        .map(|u| Node::new(u.get_host_port().parse().unwrap()))
-                                        .collect(),
-                                ))
-                            }
-                        };
-                        RoutePlan::ReverseProxy(ReverseProxyHandler::with_timeouts(
+                        let balancer =
+                            build_load_balancer(&proxy_config.load_balancer, &pool_states);
+                        let mut handler = ReverseProxyHandler::with_timeouts(
                             balancer,
-                            proxy_config.request_timeout,
-                            proxy_config.connection_timeout,
-                        ))
+                            proxy_config
+                                .request_timeout
+                                .or(config.options.default_request_timeout),
+                            proxy_config
+                                .connection_timeout
+                                .or(config.options.default_connection_timeout),
+                        )
+                        .with_dns_cache(
+                            DnsCache::new(proxy_config.resolve_ttl)
+                                .with_stale_max(proxy_config.dns_stale_max),
+                        )
+                        .with_forwarded(proxy_config.forwarded)
+                        .with_host_header(proxy_config.host_header.clone())
+                        .with_strip_request_headers(
+                            proxy_config
+                                .strip_request_headers
+                                .iter()
+                                // the names are checked by the parser
+                                .filter_map(|name| HeaderName::from_bytes(name.as_bytes()).ok())
+                                .collect(),
+                        );
+                        if let Some(rules) = HeaderRules::from_middlewares(&proxy_config.header_up)
+                        {
+                            handler = handler.with_header_up(rules);
+                        }
+                        if let Some(rules) =
+                            HeaderRules::from_middlewares(&proxy_config.header_down)
+                        {
+                            handler = handler.with_header_down(rules);
+                        }
+                        if let Some(max_fails) = proxy_config.max_fails {
+                            handler = handler.with_passive_health(PassiveHealth::new(
+                                max_fails,
+                                proxy_config.fail_timeout,
+                            ));
+                        }
+                        if let Some(circuit_breaker) = &proxy_config.circuit_breaker {
+                            handler =
+                                handler.with_circuit_breaker(CircuitBreaker::new(circuit_breaker));
+                        }
+                        if let Some(canary) = &proxy_config.canary {
+                            handler = handler.with_canary(Canary::new(
+                                canary.matcher.clone(),
+                                build_load_balancer(&canary.load_balancer, &pool_states),
+                            ));
+                        }
+                        HandlerPlan::ReverseProxy(handler)
+                    }
+                    chico_file::types::Handler::Dir(roots) => HandlerPlan::Dir(
+                        DirHandler::with_roots(roots.clone(), route_path.clone())
+                            .with_sniff_extensionless(r.sniff_extensionless)
+                            .with_max_ranges(r.max_ranges),
+                    ),
+                    chico_file::types::Handler::Browse(root) => HandlerPlan::Browse(
+                        BrowseHandler::new(root.clone(), route_path.clone())
+                            .with_sniff_extensionless(r.sniff_extensionless)
+                            .with_max_ranges(r.max_ranges),
+                    ),
+                    chico_file::types::Handler::Respond { status, body } => HandlerPlan::Respond(
+                        RespondHandler::new(status.unwrap_or(200), body.clone()),
+                    ),
+                    chico_file::types::Handler::RespondFile { file, status } => {
+                        // the file is checked by the config validation, it may be gone since
+                        let status = status.unwrap_or(200);
+                        let handler = RespondHandler::from_file(status, file).unwrap_or_else(|e| {
+                            error!("could not read the respond file {}: {}", file, e);
+                            RespondHandler::internal_server_error()
+                        });
+                        HandlerPlan::Respond(handler)
                     }
-                    chico_file::types::Handler::Dir(_) => todo!(),
-                    chico_file::types::Handler::Browse(_) => todo!(),
-                    chico_file::types::Handler::Respond { status, body } => {
-                        RoutePlan::Respond(RespondHandler::new(status.unwrap_or(200), body.clone()))
+                    chico_file::types::Handler::RespondEcho(param) => {
+                        HandlerPlan::RespondEcho(RespondEchoHandler::new(param.clone()))
                     }
                     chico_file::types::Handler::Redirect { path, status_code } => {
-                        RoutePlan::Redirect(RedirectHandler::new(
+                        HandlerPlan::Redirect(RedirectHandler::new(
                             path.clone()
                                 .expect("path parameter for redirect handler exepted"),
                             *status_code,
                         ))
                     }
+                    chico_file::types::Handler::Rewrite(target) => HandlerPlan::Rewrite(
+                        RewriteHandler::new(target.clone(), route_path.clone()),
+                    ),
+                    chico_file::types::Handler::TryFiles(candidates) => HandlerPlan::TryFiles(
+                        TryFilesHandler::new(candidates.clone(), route_path.clone())
+                            .with_sniff_extensionless(r.sniff_extensionless)
+                            .with_max_ranges(r.max_ranges),
+                    ),
                 };
 
-                routes.insert(r.path.clone(), handler);
+                let plan = RoutePlan {
+                    handler,
+                    error_format: r.error_format,
+                    cors: r
+                        .middlewares
+                        .iter()
+                        .find_map(|middleware| match middleware {
+                            Middleware::Cors(origin) => Some(Cors::new(origin)),
+                            _ => None,
+                        }),
+                    rate_limiter: r
+                        .middlewares
+                        .iter()
+                        .find_map(|middleware| match middleware {
+                            Middleware::RateLimit { requests, window } => {
+                                Some(RateLimiter::new(*requests, *window))
+                            }
+                            _ => None,
+                        }),
+                    concurrency_limit: r.middlewares.iter().find_map(
+                        |middleware| match middleware {
+                            Middleware::MaxConcurrent { limit, queue } => {
+                                Some(ConcurrencyLimit::new(*limit, *queue))
+                            }
+                            _ => None,
+                        },
+                    ),
+                    gzip: r
+                        .middlewares
+                        .iter()
+                        .find_map(|middleware| match middleware {
+                            Middleware::Gzip(level) => Some(Gzip::new(*level)),
+                            _ => None,
+                        }),
+                    zstd: Zstd::from_middlewares(&r.middlewares),
+                    transcode: r.transcode,
+                    cache: r
+                        .middlewares
+                        .iter()
+                        .find_map(|middleware| match middleware {
+                            Middleware::Cache {
+                                ttl,
+                                serve_stale_on_error,
+                                immutable,
+                            } => {
+                                let cache = ResponseCache::new(*ttl, *serve_stale_on_error)
+                                    .with_immutable(*immutable);
+                                Some(match &memory_budget {
+                                    Some(budget) => cache.with_memory_budget(budget),
+                                    None => cache,
+                                })
+                            }
+                            _ => None,
+                        }),
+                    headers: HeaderRules::from_middlewares(&r.middlewares),
+                    minify: Minify::from_middlewares(&r.middlewares).map(|minify| {
+                        match &memory_budget {
+                            Some(budget) => minify.with_memory_budget(budget),
+                            None => minify,
+                        }
+                    }),
+                    auth: BasicAuth::from_middlewares(&r.middlewares),
+                    methods: r.methods.clone(),
+                };
+                match (matcher, r.path_kind()) {
+                    (Some(matcher), _) => matcher_routes.push((matcher, plan)),
+                    // the expressions are checked by the config validation
+                    (None, RoutePath::Regex(pattern)) => match Regex::new(pattern) {
+                        Ok(regex) => regex_routes.push((regex, plan)),
+                        Err(e) => error!("invalid regex of route {}: {}", r.path, e),
+                    },
+                    (None, _) => {
+                        routes.insert(r.path.clone(), plan);
+                    }
+                }
             }
+            // the sort is stable, equally specific expressions keep their declaration order
+            regex_routes.sort_by_key(|(regex, _)| Reverse(regex_specificity(regex.as_str())));
             vhosts.insert(
-                vh.domain.clone(),
+                vh.domain.to_string(),
                 VirtualHostPlan {
                     domain: vh.domain.clone(),
+                    port: vh.get_port(config.options.default_port),
                     routes,
+                    matcher_routes,
+                    regex_routes,
+                    error_format: vh.error_format,
+                    client_resolver: ClientResolver::new(
+                        vh.trusted_proxies.clone(),
+                        vh.forwarded_header,
+                    ),
+                    allowed_methods: vh.allowed_methods.clone(),
+                    error_pages: ErrorPages::new(&vh.error_pages),
+                    csp: vh
+                        .csp
+                        .as_ref()
+                        .or(config.options.csp.as_ref())
+                        .map(ContentSecurityPolicy::new),
+                    rate_limiter: vh
+                        .rate_limit
+                        .map(|rate| RateLimiter::new(rate.requests, rate.window)),
+                    merge_slashes: vh.merge_slashes,
                 },
             );
         }
 
         ServerPlan {
             virtual_hosts: vhosts,
+            pool_states,
+            not_found: NotFoundHandler::new(config.options.not_found.clone()),
+            default_host: config.options.default_host.clone(),
+            strict_sni_host: config.options.strict_sni_host.unwrap_or(false),
+            allowed_methods: config.options.allowed_methods.clone(),
+            memory_budget,
+        }
+    }
+}
+
+/// Ranks a route regular expression by the length of its literal prefix, then by its length.
+///
+/// The literal prefix is the text every match starts with, it is empty when the expression has
+/// an alternative at its top level.
+fn regex_specificity(pattern: &str) -> (usize, usize) {
+    let mut chars = pattern
+        .strip_prefix('^')
+        .unwrap_or(pattern)
+        .chars()
+        .peekable();
+    let mut prefix = 0;
+    while let Some(c) = chars.next() {
+        let literal = match c {
+            '\\' => chars
+                .next_if(|next| !next.is_ascii_alphanumeric())
+                .is_some(),
+            '.' | '[' | '(' | ')' | '{' | '}' | '|' | '*' | '+' | '?' | '^' | '$' => false,
+            _ => true,
+        };
+        if !literal {
+            break;
+        }
+        // an optional character is not part of every match
+        match chars.peek() {
+            Some('?' | '*' | '{') => break,
+            _ => prefix += 1,
+        }
+    }
+
+    if has_top_level_alternative(pattern) {
+        prefix = 0;
+    }
+    (prefix, pattern.len())
+}
+
+/// Returns true when the pattern has a `|` outside of groups and character classes.
+fn has_top_level_alternative(pattern: &str) -> bool {
+    let mut depth = 0;
+    let mut in_class = false;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '(' if !in_class => depth += 1,
+            ')' if !in_class => depth -= 1,
+            '|' if !in_class && depth == 0 => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Returns the node of the upstream, the node of its targets for an SRV upstream.
+fn node(upstream: &Upstream) -> Node {
+    match upstream.srv_name() {
+        Some(name) => Node::srv(name.to_string()),
+        None => upstream.get_host_port().parse::<Node>().unwrap(),
+    }
+}
+
+fn build_load_balancer(
+    load_balancer: &LoadBalancer,
+    pool_states: &PoolStates,
+) -> Box<dyn LoadBalance> {
+    match load_balancer {
+        LoadBalancer::NoBalancer(upstream) => Box::new(SingleUpstream::new(node(upstream))),
+        LoadBalancer::RoundRobin(upstreams) => {
+            let nodes: Vec<Node> = upstreams.iter().map(node).collect();
+            let counter = pool_states.counter(&nodes);
+            Box::new(RoundRobinBalancer::with_counter(nodes, counter))
+        }
+        LoadBalancer::LeastConnections(upstreams) => {
+            let nodes: Vec<Node> = upstreams.iter().map(node).collect();
+            let active = pool_states.in_flight(&nodes);
+            let counter = pool_states.counter(&nodes);
+            Box::new(LeastConnectionsBalancer::with_state(nodes, active, counter))
+        }
+        LoadBalancer::IpHash(upstreams) => {
+            Box::new(IpHashBalancer::new(upstreams.iter().map(node).collect()))
+        }
+        LoadBalancer::Random(upstreams) => {
+            Box::new(RandomBalancer::new(upstreams.iter().map(node).collect()))
+        }
+        LoadBalancer::WeightedRoundRobin(upstreams) => {
+            let nodes: Vec<Node> = upstreams.iter().map(node).collect();
+            let current = pool_states.current_weights(&nodes);
+            let weighted = nodes
+                .into_iter()
+                .zip(upstreams.iter().map(Upstream::weight))
+                .collect();
+            Box::new(WeightedRoundRobinBalancer::with_state(weighted, current))
         }
     }
 }
@@ -138,8 +635,10 @@ mod tests {
     use rstest::rstest;
 
     use crate::{
-        handlers::file::FileHandler,
-        plan::{RoutePlan, VirtualHostPlan},
+        client::ClientResolver,
+        handlers::{error::ErrorPages, file::FileHandler, respond::RespondHandler},
+        load_balance::node::Node,
+        plan::{HandlerPlan, RoutePlan, ServerPlan, VirtualHostPlan},
     };
 
     #[rstest]
@@ -156,17 +655,30 @@ mod tests {
     #[case("/api/products/get/*", "/api/products/get/1")]
     fn test_find_route_success(#[case] path: &str, #[case] search_value: &str) {
         let mut routes = HashMap::new();
-        let route_plan = RoutePlan::File(FileHandler::new("".to_string(), path.to_string()));
+        let route_plan = RoutePlan::new(HandlerPlan::File(FileHandler::new(
+            "".to_string(),
+            path.to_string(),
+        )));
         routes.insert(path.to_string(), route_plan);
 
         let virtual_hosts = VirtualHostPlan {
-            domain: "".to_string(),
+            domain: "localhost".parse().unwrap(),
+            port: 80,
             routes,
+            matcher_routes: vec![],
+            regex_routes: vec![],
+            error_format: None,
+            client_resolver: ClientResolver::default(),
+            allowed_methods: None,
+            error_pages: ErrorPages::default(),
+            csp: None,
+            rate_limiter: None,
+            merge_slashes: Default::default(),
         };
 
         let route = assert_some!(virtual_hosts.find_route(search_value));
-        match route {
-            RoutePlan::File(handler) => {
+        match &route.handler {
+            HandlerPlan::File(handler) => {
                 assert_eq!(handler.path, "");
                 assert_eq!(handler.route, path);
             }
@@ -188,15 +700,228 @@ mod tests {
     #[case("/api/products/get/*", "/api/products/get")]
     fn test_find_route_fail(#[case] path: &str, #[case] search_value: &str) {
         let mut routes = HashMap::new();
-        let route_plan = RoutePlan::File(FileHandler::new("".to_string(), path.to_string()));
+        let route_plan = RoutePlan::new(HandlerPlan::File(FileHandler::new(
+            "".to_string(),
+            path.to_string(),
+        )));
         routes.insert(path.to_string(), route_plan);
 
         let virtual_hosts = VirtualHostPlan {
-            domain: "".to_string(),
+            domain: "localhost".parse().unwrap(),
+            port: 80,
             routes,
+            matcher_routes: vec![],
+            regex_routes: vec![],
+            error_format: None,
+            client_resolver: ClientResolver::default(),
+            allowed_methods: None,
+            error_pages: ErrorPages::default(),
+            csp: None,
+            rate_limiter: None,
+            merge_slashes: Default::default(),
         };
 
         let route = virtual_hosts.find_route(search_value);
         assert!(route.is_none(), "Expected no route to be found");
     }
+
+    #[test]
+    fn test_find_virtual_host_uses_default_port() {
+        let (_, config) = chico_file::parse_config(
+            "options { default_port 8080 }\nlocalhost { route / { respond 200 } }",
+        )
+        .unwrap();
+
+        let plan = ServerPlan::from_config(&config);
+
+        assert_some!(plan.find_virtual_host("localhost", 8080));
+        assert!(plan.find_virtual_host("localhost", 80).is_none());
+    }
+
+    #[test]
+    fn test_find_virtual_host_of_block_with_multiple_domains() {
+        let (_, config) =
+            chico_file::parse_config("example.com www.example.com { route / { respond 200 } }")
+                .unwrap();
+
+        let plan = ServerPlan::from_config(&config);
+
+        assert_some!(plan.find_virtual_host("example.com", 80));
+        assert_some!(plan.find_virtual_host("www.example.com", 80));
+        assert!(plan.find_virtual_host("api.example.com", 80).is_none());
+    }
+
+    #[rstest]
+    #[case("*.example.com", "a.example.com", true)]
+    #[case("*.example.com", "B.Example.com", true)]
+    #[case("*.example.com", "a.b.example.com", false)]
+    #[case("*.example.com", "example.com", false)]
+    #[case("*.example.com", "aexample.com", false)]
+    #[case("**.example.com", "a.example.com", true)]
+    #[case("**.example.com", "a.b.example.com", true)]
+    #[case("**.example.com", "example.com", false)]
+    fn test_find_virtual_host_with_wildcard_domain(
+        #[case] domain: &str,
+        #[case] host: &str,
+        #[case] found: bool,
+    ) {
+        let (_, config) =
+            chico_file::parse_config(&format!("{domain} {{ route / {{ respond 200 }} }}")).unwrap();
+
+        let plan = ServerPlan::from_config(&config);
+
+        assert_eq!(plan.find_virtual_host(host, 80).is_some(), found);
+    }
+
+    #[rstest]
+    #[case("api.example.com", "api.example.com")]
+    #[case("a.example.com", "*.example.com")]
+    #[case("a.eu.example.com", "*.eu.example.com")]
+    #[case("a.b.eu.example.com", "**.eu.example.com")]
+    #[case("a.b.example.com", "**.example.com")]
+    fn test_find_virtual_host_prefers_most_specific_domain(
+        #[case] host: &str,
+        #[case] domain: &str,
+    ) {
+        let (_, config) = chico_file::parse_config(
+            "**.example.com *.example.com api.example.com { route / { respond 200 } }
+            *.eu.example.com **.eu.example.com { route / { respond 200 } }",
+        )
+        .unwrap();
+
+        let plan = ServerPlan::from_config(&config);
+
+        assert_eq!(
+            plan.find_virtual_host(host, 80).unwrap().domain.to_string(),
+            domain
+        );
+    }
+
+    #[rstest]
+    #[case("/api/v2/users", "regex")]
+    #[case("/api/v2", "exact")]
+    #[case("/api/docs", "api prefix")]
+    #[case("/api/v2x/users", "api prefix")]
+    #[case("/about", "root prefix")]
+    fn test_find_route_prefers_most_specific_path(#[case] path: &str, #[case] expected: &str) {
+        let (_, config) = chico_file::parse_config(
+            r#"localhost {
+                route /* { respond "root prefix" }
+                route /api/* { respond "api prefix" }
+                route ~^/api/v[0-9]+/ { respond "regex" }
+                route /api/v2 { respond "exact" }
+            }"#,
+        )
+        .unwrap();
+
+        let plan = ServerPlan::from_config(&config);
+        let vh = plan.find_virtual_host("localhost", 80).unwrap();
+
+        match &vh.find_route(path).unwrap().handler {
+            HandlerPlan::Respond(handler) => assert_eq!(
+                *handler,
+                RespondHandler::new(200, Some(expected.to_string()))
+            ),
+            _ => panic!("Unexpected route type"),
+        }
+    }
+
+    #[rstest]
+    #[case("/api/v2/users", "narrow")]
+    #[case("/api/v1/users", "narrow")]
+    #[case("/api/docs", "broad")]
+    #[case("/about", "broad")]
+    fn test_find_route_prefers_most_specific_regex(#[case] path: &str, #[case] expected: &str) {
+        let (_, config) = chico_file::parse_config(
+            r#"localhost {
+                route ~^/.* { respond "broad" }
+                route ~^/api/v[0-9]+/ { respond "narrow" }
+            }"#,
+        )
+        .unwrap();
+
+        let plan = ServerPlan::from_config(&config);
+        let vh = plan.find_virtual_host("localhost", 80).unwrap();
+
+        match &vh.find_route(path).unwrap().handler {
+            HandlerPlan::Respond(handler) => assert_eq!(
+                *handler,
+                RespondHandler::new(200, Some(expected.to_string()))
+            ),
+            _ => panic!("Unexpected route type"),
+        }
+    }
+
+    #[rstest]
+    #[case("^/api/v[0-9]+/", (6, 14))]
+    #[case("^/api/users", (10, 11))]
+    #[case("^/api/users?", (9, 12))]
+    #[case("^/files/.+\\.png$", (7, 16))]
+    #[case("^/a\\.b", (4, 6))]
+    #[case("^/api|^/admin", (0, 13))]
+    #[case("^/(api|admin)", (1, 13))]
+    #[case("\\d+", (0, 3))]
+    fn test_regex_specificity(#[case] pattern: &str, #[case] expected: (usize, usize)) {
+        assert_eq!(super::regex_specificity(pattern), expected);
+    }
+
+    fn pick_upstream(plan: &ServerPlan) -> Node {
+        let vh = plan.find_virtual_host("localhost", 80).unwrap();
+        match &vh.find_route("/api").unwrap().handler {
+            HandlerPlan::ReverseProxy(handler) => (*handler.get_node().unwrap()).clone(),
+            _ => panic!("Unexpected route type"),
+        }
+    }
+
+    #[test]
+    fn test_rebuild_keeps_round_robin_rotation() {
+        let (_, config) = chico_file::parse_config(
+            r#"
+            localhost {
+                route /api {
+                    proxy {
+                        upstreams http://127.0.0.1:9001 http://127.0.0.1:9002 http://127.0.0.1:9003
+                        lb_policy round_robin
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        let mut plan = ServerPlan::from_config(&config);
+        let mut counts: HashMap<Node, usize> = HashMap::new();
+
+        // 100 picks with a reload every 10 picks, a reset rotation would favor the first upstream
+        for _ in 0..10 {
+            for _ in 0..10 {
+                *counts.entry(pick_upstream(&plan)).or_default() += 1;
+            }
+            plan = plan.rebuild(&config);
+        }
+
+        assert_eq!(counts.len(), 3);
+        for (node, count) in counts {
+            assert!(
+                (33..=34).contains(&count),
+                "Node {node} got {count} of 100 picks"
+            );
+        }
+    }
+
+    #[test]
+    fn test_rebuild_restarts_rotation_of_changed_pool() {
+        let parse = |upstreams: &str| {
+            let input = format!(
+                "localhost {{ route /api {{ proxy {{ upstreams {upstreams} lb_policy round_robin }} }} }}"
+            );
+            chico_file::parse_config(&input).unwrap().1
+        };
+
+        let plan = ServerPlan::from_config(&parse("http://127.0.0.1:9001 http://127.0.0.1:9002"));
+        pick_upstream(&plan);
+
+        let plan = plan.rebuild(&parse("http://127.0.0.1:9003 http://127.0.0.1:9001"));
+        assert_eq!(pick_upstream(&plan), "127.0.0.1:9003".parse().unwrap());
+    }
 }
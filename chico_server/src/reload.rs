@@ -0,0 +1,581 @@
+//! # Reload
+//!
+//! Replaces the plan of a running server with the plan of a new config. Reloads are serialized:
+//! a reload requested while another one is in progress waits for it to complete, so the configs
+//! are applied in the order the reloads were requested and the last one wins.
+//!
+//! Only the plan is replaced, the listeners and their TLS certificates are kept.
+//!
+//! The outcome of the reloads is kept for dashboards: counters and the time of the last
+//! successful reload in the Prometheus text format, see [`PlanHandle::reload_metrics`], and the
+//! last [`RELOAD_HISTORY`] attempts with their errors, see [`PlanHandle::reload_status`].
+//!
+//! A running server reloads its config file on SIGHUP. `chico reload` finds the server and its
+//! config file from the pid file written by `chico run --pid-file`, validates the config and
+//! sends the signal, see [`read_pid_file`] and [`signal_reload`]. `chico status` reads the same
+//! pid file, and the status the server saves next to it once it listens and after each reload,
+//! see [`status_file_path`]. The status describes the ports and plan actually served, which may
+//! differ from the config file when it changed since.
+
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::{
+    config::{validate_config_file, ValidationOptions},
+    plan::ServerPlan,
+};
+
+/// Plan served by the listeners, shared with the connections.
+pub struct PlanHandle {
+    current: RwLock<Arc<ServerPlan>>,
+    /// Held for the whole parse, validate and swap of a reload.
+    reloading: Mutex<()>,
+    reloads: std::sync::Mutex<ReloadHistory>,
+    /// File the status is saved to after each reload, for `chico status`.
+    status_file: Option<PathBuf>,
+    /// Ports the listeners are bound to, kept across reloads.
+    ports: std::sync::Mutex<Vec<u16>>,
+}
+
+/// Reload attempts kept for the status.
+pub const RELOAD_HISTORY: usize = 10;
+
+/// Outcome of a reload.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReloadAttempt {
+    /// Unix time the reload completed at, in seconds.
+    pub timestamp: u64,
+    /// Generation of the plan served after the reload, the initial plan is generation 0.
+    pub generation: u64,
+    /// First line of the error of a failed reload.
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct ReloadHistory {
+    generation: u64,
+    successes: u64,
+    failures: u64,
+    last_success: Option<u64>,
+    /// Oldest first.
+    attempts: VecDeque<ReloadAttempt>,
+}
+
+impl ReloadHistory {
+    fn record(&mut self, result: &Result<(), String>) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        match result {
+            Ok(()) => {
+                self.generation += 1;
+                self.successes += 1;
+                self.last_success = Some(timestamp);
+            }
+            Err(_) => self.failures += 1,
+        }
+        if self.attempts.len() == RELOAD_HISTORY {
+            self.attempts.pop_front();
+        }
+        self.attempts.push_back(ReloadAttempt {
+            timestamp,
+            generation: self.generation,
+            error: result
+                .as_ref()
+                .err()
+                .map(|error| error.lines().next().unwrap_or_default().to_string()),
+        });
+    }
+}
+
+impl PlanHandle {
+    pub fn new(plan: ServerPlan) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(plan)),
+            reloading: Mutex::new(()),
+            reloads: std::sync::Mutex::new(ReloadHistory::default()),
+            status_file: None,
+            ports: std::sync::Mutex::default(),
+        }
+    }
+
+    /// Saves the status to `path` after each reload, see [`PlanHandle::save_status`].
+    pub fn with_status_file(mut self, path: PathBuf) -> Self {
+        self.status_file = Some(path);
+        self
+    }
+
+    /// Writes the status to the status file, if any, see [`read_status_file`].
+    pub fn save_status(&self) -> std::io::Result<()> {
+        let Some(path) = &self.status_file else {
+            return Ok(());
+        };
+        crates_fs::atomic_write(path, self.status().to_string().as_bytes(), 0o644)
+    }
+
+    /// Records a port a listener is bound to and saves the status.
+    pub fn listening_on(&self, port: u16) {
+        self.ports.lock().unwrap().push(port);
+        if let Err(e) = self.save_status() {
+            warn!("Failed to save the status. reason: {e}");
+        }
+    }
+
+    /// Returns the reload status with the ports listened on and the number of virtual hosts and
+    /// routes of the current plan.
+    pub fn status(&self) -> serde_json::Value {
+        let plan = self.current();
+        let mut status = self.reload_status();
+        status["ports"] = serde_json::json!(*self.ports.lock().unwrap());
+        status["virtual_hosts"] = plan.virtual_host_count().into();
+        status["routes"] = plan.route_count().into();
+        status
+    }
+
+    /// Returns the plan to serve a new connection with.
+    ///
+    /// A connection keeps the plan it started with, a reload applies to the next connections.
+    pub fn current(&self) -> Arc<ServerPlan> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Validates the config file at `path` and swaps the current plan for its plan.
+    ///
+    /// The current plan is kept when the config is invalid.
+    pub async fn reload(&self, path: &str, options: &ValidationOptions) -> Result<(), String> {
+        let _reloading = match self.reloading.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                info!(
+                    "Another reload is in progress, the reload of {path} waits for it to complete"
+                );
+                self.reloading.lock().await
+            }
+        };
+
+        let result = match validate_config_file(path, options).await {
+            Ok(config) => {
+                let plan = self.current().rebuild(&config);
+                *self.current.write().unwrap() = Arc::new(plan);
+                info!("Config {path} reloaded");
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Reload of {path} rejected, the current config is kept: {e}");
+                Err(e)
+            }
+        };
+        self.reloads.lock().unwrap().record(&result);
+        if let Err(e) = self.save_status() {
+            warn!("Failed to save the reload status. reason: {e}");
+        }
+        result
+    }
+
+    /// Returns the reload counters and the time of the last successful reload, in the Prometheus
+    /// text format.
+    pub fn reload_metrics(&self) -> String {
+        reload_metrics(&self.reload_status())
+    }
+
+    /// Returns the generation of the current plan, the reload counters, the error of the last
+    /// failed reload and the last reload attempts, newest first.
+    pub fn reload_status(&self) -> serde_json::Value {
+        let reloads = self.reloads.lock().unwrap();
+        let attempts: Vec<serde_json::Value> = reloads
+            .attempts
+            .iter()
+            .rev()
+            .map(|attempt| {
+                serde_json::json!({
+                    "timestamp": attempt.timestamp,
+                    "generation": attempt.generation,
+                    "result": if attempt.error.is_none() { "success" } else { "failure" },
+                    "error": attempt.error,
+                })
+            })
+            .collect();
+        let last_error = reloads
+            .attempts
+            .iter()
+            .rev()
+            .find_map(|attempt| attempt.error.clone());
+        serde_json::json!({
+            "generation": reloads.generation,
+            "successes": reloads.successes,
+            "failures": reloads.failures,
+            "last_success": reloads.last_success,
+            "last_error": last_error,
+            "attempts": attempts,
+        })
+    }
+}
+
+/// Returns the reload metrics of a reload status, see [`PlanHandle::reload_status`].
+pub fn reload_metrics(status: &serde_json::Value) -> String {
+    let mut metrics = format!(
+        "# HELP chico_config_reload_total Config reloads by result.\n\
+         # TYPE chico_config_reload_total counter\n\
+         chico_config_reload_total{{result=\"success\"}} {}\n\
+         chico_config_reload_total{{result=\"failure\"}} {}\n",
+        status["successes"].as_u64().unwrap_or_default(),
+        status["failures"].as_u64().unwrap_or_default()
+    );
+    if let Some(last_success) = status["last_success"].as_u64() {
+        metrics.push_str(&format!(
+            "# HELP chico_config_last_reload_timestamp Unix time of the last successful config reload.\n\
+             # TYPE chico_config_last_reload_timestamp gauge\n\
+             chico_config_last_reload_timestamp {last_success}\n"
+        ));
+    }
+    metrics
+}
+
+/// Returns the file the server of the pid file at `pid_file` saves its status to.
+pub fn status_file_path(pid_file: &Path) -> PathBuf {
+    let mut path = pid_file.as_os_str().to_owned();
+    path.push(".reload.json");
+    PathBuf::from(path)
+}
+
+/// Returns the status saved by [`PlanHandle::save_status`].
+pub fn read_status_file(path: &Path) -> Result<serde_json::Value, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        format!(
+            "Failed to read the server status {}. reason: {e}",
+            path.display()
+        )
+    })?;
+    serde_json::from_str(&content)
+        .map_err(|e| format!("Invalid server status {}. reason: {e}", path.display()))
+}
+
+/// Writes the pid file of this process serving the config file at `config_path`: the process id
+/// on the first line, the absolute path of the config file on the second.
+///
+/// The file is replaced atomically, `chico reload` and `chico status` never read a partial one.
+pub fn write_pid_file(path: &Path, config_path: &str) -> std::io::Result<()> {
+    let config_path = std::fs::canonicalize(config_path)?;
+    crates_fs::atomic_write(
+        path,
+        format!("{}\n{}\n", std::process::id(), config_path.display()).as_bytes(),
+        0o644,
+    )
+}
+
+/// Returns the process id and the config file of the server of a pid file, see
+/// [`write_pid_file`].
+pub fn read_pid_file(path: &Path) -> Result<(u32, String), String> {
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        format!(
+            "Failed to read the pid file {}, is the server running with --pid-file? reason: {e}",
+            path.display()
+        )
+    })?;
+    let mut lines = content.lines();
+    let pid = lines.next().and_then(|pid| pid.trim().parse::<u32>().ok());
+    let config_path = lines.next().filter(|config_path| !config_path.is_empty());
+    match (pid, config_path) {
+        (Some(pid), Some(config_path)) => Ok((pid, config_path.to_string())),
+        _ => Err(format!("Invalid pid file {}", path.display())),
+    }
+}
+
+/// Asks the server of process `pid` to reload its config file, see
+/// [`crate::server::reload_on_signal`].
+#[cfg(unix)]
+pub fn signal_reload(pid: u32) -> Result<(), String> {
+    let output = std::process::Command::new("kill")
+        .args(["-HUP", &pid.to_string()])
+        .output()
+        .map_err(|e| format!("Failed to signal the server (pid {pid}). reason: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Failed to signal the server (pid {pid}). reason: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Returns true when the process `pid` is running, as checked by `chico status`.
+#[cfg(unix)]
+pub fn is_running(pid: u32) -> bool {
+    // signal 0 only checks that the process exists
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Returns true when the process `pid` is running, as checked by `chico status`.
+#[cfg(windows)]
+pub fn is_running(pid: u32) -> bool {
+    std::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {pid}"), "/NH"])
+        .output()
+        .is_ok_and(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .split_whitespace()
+                .any(|field| field == pid.to_string())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, path::Path};
+
+    use claims::{assert_err, assert_ok};
+    use rstest::rstest;
+    use tempfile::NamedTempFile;
+
+    use super::{
+        read_pid_file, read_status_file, reload_metrics, status_file_path, write_pid_file,
+        PlanHandle, RELOAD_HISTORY,
+    };
+    use crate::{config::ValidationOptions, plan::ServerPlan};
+
+    fn config_file(content: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    fn initial_handle() -> PlanHandle {
+        let (_, config) =
+            chico_file::parse_config("initial.localhost { route / { respond 200 } }").unwrap();
+        PlanHandle::new(ServerPlan::from_config(&config))
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_reloads_apply_in_order() {
+        let handle = initial_handle();
+        let first = config_file("first.localhost { route / { respond 200 } }");
+        let second = config_file("second.localhost { route /api { respond 201 } }");
+        let options = ValidationOptions::default();
+
+        let (first_result, second_result) = tokio::join!(
+            handle.reload(first.path().to_str().unwrap(), &options),
+            handle.reload(second.path().to_str().unwrap(), &options),
+        );
+        assert_ok!(first_result);
+        assert_ok!(second_result);
+
+        // The second reload waited for the first one, its plan is the one served
+        let plan = handle.current();
+        assert!(plan.find_virtual_host("initial.localhost", 80).is_none());
+        assert!(plan.find_virtual_host("first.localhost", 80).is_none());
+        let vh = plan.find_virtual_host("second.localhost", 80).unwrap();
+        assert!(vh.find_route("/api").is_some());
+    }
+
+    #[test]
+    fn test_pid_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("chico.chf");
+        std::fs::write(&config, "").unwrap();
+        let pid_file = dir.path().join("chico.pid");
+
+        write_pid_file(&pid_file, config.to_str().unwrap()).unwrap();
+
+        let (pid, config_path) = read_pid_file(&pid_file).unwrap();
+        assert_eq!(pid, std::process::id());
+        assert_eq!(
+            Path::new(&config_path),
+            std::fs::canonicalize(&config).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_pid_file_is_never_read_partially_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("chico.chf");
+        std::fs::write(&config, "").unwrap();
+        let pid_file = dir.path().join("chico.pid");
+        write_pid_file(&pid_file, config.to_str().unwrap()).unwrap();
+
+        std::thread::scope(|scope| {
+            let writer = scope.spawn(|| {
+                for _ in 0..200 {
+                    write_pid_file(&pid_file, config.to_str().unwrap()).unwrap();
+                }
+            });
+            while !writer.is_finished() {
+                assert_ok!(read_pid_file(&pid_file));
+            }
+        });
+
+        // no temporary file is left behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("1234\n")]
+    #[case("not a pid\n/etc/chico.chf\n")]
+    fn test_read_invalid_pid_file(#[case] content: &str) {
+        let pid_file = config_file(content);
+        assert_err!(read_pid_file(pid_file.path()));
+    }
+
+    #[test]
+    fn test_read_missing_pid_file() {
+        let error = read_pid_file(Path::new("/missing/chico.pid")).unwrap_err();
+        assert!(error.contains("--pid-file"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_reload_keeps_current_plan() {
+        let handle = initial_handle();
+        let invalid = config_file("invalid.localhost { route / { respond } ");
+
+        assert_err!(
+            handle
+                .reload(
+                    invalid.path().to_str().unwrap(),
+                    &ValidationOptions::default()
+                )
+                .await
+        );
+
+        assert!(handle
+            .current()
+            .find_virtual_host("initial.localhost", 80)
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_reloads_are_counted_by_result() {
+        let handle = initial_handle();
+        let invalid = config_file("invalid.localhost { route / { respond } ");
+        let valid = config_file("valid.localhost { route / { respond 200 } }");
+        let options = ValidationOptions::default();
+
+        assert_err!(
+            handle
+                .reload(invalid.path().to_str().unwrap(), &options)
+                .await
+        );
+        assert_ok!(
+            handle
+                .reload(valid.path().to_str().unwrap(), &options)
+                .await
+        );
+
+        let metrics = handle.reload_metrics();
+        assert!(metrics.contains("\nchico_config_reload_total{result=\"success\"} 1\n"));
+        assert!(metrics.contains("\nchico_config_reload_total{result=\"failure\"} 1\n"));
+        assert!(metrics.contains("\nchico_config_last_reload_timestamp "));
+
+        let status = handle.reload_status();
+        assert_eq!(status["generation"], 1);
+        assert!(status["last_error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Failed to parse config file."));
+        let attempts = status["attempts"].as_array().unwrap();
+        assert_eq!(attempts.len(), 2);
+        assert_eq!(attempts[0]["result"], "success");
+        assert_eq!(attempts[0]["generation"], 1);
+        assert!(attempts[0]["error"].is_null());
+        assert_eq!(attempts[1]["result"], "failure");
+        assert_eq!(attempts[1]["generation"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_reload_status_is_saved_after_each_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let status_file = status_file_path(&dir.path().join("chico.pid"));
+        let handle = initial_handle().with_status_file(status_file.clone());
+        let invalid = config_file("invalid.localhost { route / { respond } ");
+
+        handle.save_status().unwrap();
+        assert_eq!(read_status_file(&status_file).unwrap()["generation"], 0);
+
+        assert_err!(
+            handle
+                .reload(
+                    invalid.path().to_str().unwrap(),
+                    &ValidationOptions::default()
+                )
+                .await
+        );
+
+        let status = read_status_file(&status_file).unwrap();
+        assert_eq!(status, handle.status());
+        assert_eq!(status["failures"], 1);
+        assert_eq!(reload_metrics(&status), handle.reload_metrics());
+        assert_eq!(status_file, dir.path().join("chico.pid.reload.json"));
+    }
+
+    #[tokio::test]
+    async fn test_status_describes_the_served_plan_not_the_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let status_file = status_file_path(&dir.path().join("chico.pid"));
+        let handle = initial_handle().with_status_file(status_file.clone());
+        let config = config_file(
+            "reloaded.localhost { route / { respond 200 } route /api { respond 201 } }
+            other.localhost { route / { respond 200 } }",
+        );
+        let options = ValidationOptions::default();
+
+        handle.listening_on(80);
+        let status = read_status_file(&status_file).unwrap();
+        assert_eq!(status["ports"], serde_json::json!([80]));
+        assert_eq!(status["virtual_hosts"], 1);
+        assert_eq!(status["routes"], 1);
+
+        assert_ok!(
+            handle
+                .reload(config.path().to_str().unwrap(), &options)
+                .await
+        );
+        // a config file changed after the reload is not what the server serves
+        std::fs::write(config.path(), "broken.localhost { route / {").unwrap();
+        assert_err!(
+            handle
+                .reload(config.path().to_str().unwrap(), &options)
+                .await
+        );
+
+        let status = read_status_file(&status_file).unwrap();
+        assert_eq!(status["ports"], serde_json::json!([80]));
+        assert_eq!(status["virtual_hosts"], 2);
+        assert_eq!(status["routes"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_reload_history_keeps_last_attempts() {
+        let handle = initial_handle();
+        let valid = config_file("valid.localhost { route / { respond 200 } }");
+
+        for _ in 0..RELOAD_HISTORY + 2 {
+            assert_ok!(
+                handle
+                    .reload(
+                        valid.path().to_str().unwrap(),
+                        &ValidationOptions::default()
+                    )
+                    .await
+            );
+        }
+
+        let status = handle.reload_status();
+        let attempts = status["attempts"].as_array().unwrap();
+        assert_eq!(attempts.len(), RELOAD_HISTORY);
+        assert_eq!(attempts[0]["generation"], RELOAD_HISTORY as u64 + 2);
+        assert!(status["last_error"].is_null());
+        // no timestamp before the first successful reload
+        assert!(!initial_handle()
+            .reload_metrics()
+            .contains("chico_config_last_reload_timestamp"));
+    }
+}
@@ -1,29 +1,178 @@
 use chico_file::types::Config;
 use http::{Request, Response};
 use hyper::body::Incoming;
-use hyper::{server::conn::http1, service::service_fn};
-use hyper_util::rt::TokioIo;
+use hyper::service::service_fn;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::{GracefulShutdown, Watcher};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::select;
-use tokio::{net::TcpListener, sync::broadcast};
-use tracing::{error, info, info_span};
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, watch},
+};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info, info_span, warn};
 
+use crate::client::{PeerAddr, ServerName, TlsConnection};
+use crate::interim::Interim;
 use crate::plan::ServerPlan;
+use crate::reload::PlanHandle;
+use crate::tls;
+use crate::virtual_host::VirtualHostExt;
+use crate::write_timeout::WriteTimeout;
 use crate::{
     config::ConfigExt,
-    handlers::{self, BoxBody},
+    handlers::{self, full, BoxBody},
 };
 
-pub async fn run_server(config: Config) {
-    let ports = config.get_ports();
+/// Time given to open connections to complete their requests once the shutdown started, unless
+/// the config sets `graceful_shutdown_timeout`.
+pub(crate) const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time a client has to send the complete headers of a request, unless the config sets
+/// `header_read_timeout`. Bounds the connections held open by clients dribbling their headers.
+const HEADER_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Timeouts of the client connections.
+#[derive(Clone, Copy)]
+struct ConnectionTimeouts {
+    /// Time the client may go without reading any of a response, unbounded when unset.
+    write: Option<Duration>,
+    /// Time the client has to send the complete headers of a request.
+    header_read: Duration,
+}
+
+impl Default for ConnectionTimeouts {
+    fn default() -> Self {
+        Self {
+            write: None,
+            header_read: HEADER_READ_TIMEOUT,
+        }
+    }
+}
+
+/// Time given to the open connections of a listener to close once the shutdown started.
+#[derive(Clone, Copy)]
+struct DrainTimeouts {
+    /// `graceful_shutdown_timeout`, for the connections serving requests.
+    connections: Duration,
+    /// `websocket_drain`, for the upgraded connections.
+    tunnels: Duration,
+}
+
+/// Watches a connection for the shutdown of its listener.
+struct ConnectionShutdown {
+    /// Set once the shutdown started, the requests received from then on are answered with 503.
+    draining: Arc<AtomicBool>,
+    graceful: Watcher,
+    tunnels: Tunnels,
+}
+
+/// Request extension tracking the upgraded connections of a listener, like WebSocket tunnels.
+///
+/// hyper hands an upgraded connection over to the reverse proxy, so the [`GracefulShutdown`] of
+/// its listener no longer watches it. On shutdown the tunnels are given `websocket_drain` to
+/// close by themselves, the ones still open are closed afterwards.
+#[derive(Clone)]
+pub struct Tunnels {
+    /// Set once the tunnels must close, each open tunnel holds a receiver.
+    closing: Arc<watch::Sender<bool>>,
+}
+
+/// Held by an open tunnel, see [`Tunnels::watch`].
+pub struct TunnelWatcher {
+    closing: watch::Receiver<bool>,
+}
+
+impl Tunnels {
+    fn new() -> Self {
+        let (closing, _) = watch::channel(false);
+        Self {
+            closing: Arc::new(closing),
+        }
+    }
+
+    /// Returns the watcher of a new tunnel, to hold until the tunnel is closed.
+    pub fn watch(&self) -> TunnelWatcher {
+        TunnelWatcher {
+            closing: self.closing.subscribe(),
+        }
+    }
+
+    /// Waits up to `drain` for the open tunnels to close, then closes the remaining ones.
+    async fn shutdown(&self, drain: Duration) {
+        let open = self.closing.receiver_count();
+        if open == 0 {
+            return;
+        }
+        info!(
+            "Waiting up to {:?} for {} upgraded connections to close",
+            drain, open
+        );
+        select! {
+            _ = self.closing.closed() => {
+                info!("All upgraded connections of the listener are closed");
+            }
+            _ = tokio::time::sleep(drain) => {
+                warn!(
+                    "Closing {} upgraded connections still open after {:?}",
+                    self.closing.receiver_count(),
+                    drain
+                );
+                let _ = self.closing.send(true);
+                self.closing.closed().await;
+            }
+        }
+    }
+}
 
-    let socket_addresses = ports
-        .into_iter()
-        .map(|port| SocketAddr::from(([127, 0, 0, 1], port)));
+impl TunnelWatcher {
+    /// Completes once the tunnel must close.
+    pub async fn closing(&mut self) {
+        let _ = self.closing.wait_for(|closing| *closing).await;
+    }
+}
+use crates_tracing::{LogHandle, ACCESS_LOG_TARGET};
+
+/// Serves the virtual hosts of the config until `shutdown` completes, like [`shutdown_signal`].
+///
+/// On shutdown the listeners stop accepting connections and the open connections are given
+/// `graceful_shutdown_timeout` to complete their requests before they are closed. Upgraded
+/// connections, like WebSocket tunnels, are given `websocket_drain` instead.
+///
+/// New connections are served with the current plan of `plans`, so a reload applies to them
+/// without restarting the listeners.
+pub async fn run_server(
+    config: Config,
+    plans: Arc<PlanHandle>,
+    shutdown: impl Future<Output = ()>,
+) {
+    let mut ports = config.get_ports();
+    ports.sort_unstable();
+    ports.dedup();
 
     let mut listeners = vec![];
 
-    for addr in socket_addresses {
+    for port in ports {
+        let virtual_hosts = config
+            .virtual_hosts
+            .iter()
+            .filter(|vh| vh.get_port(config.options.default_port) == port)
+            .collect::<Vec<_>>();
+        let tls = match tls::build_acceptor(&virtual_hosts) {
+            Ok(tls) => tls,
+            Err(e) => {
+                error!("Failed to set up TLS on port {}: {}", port, e);
+                return;
+            }
+        };
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
         let listener = match TcpListener::bind(addr).await {
             Ok(listener) => listener,
             Err(e) => {
@@ -31,7 +180,8 @@ pub async fn run_server(config: Config) {
                 return;
             }
         };
-        listeners.push(listener);
+        listeners.push((listener, tls));
+        plans.listening_on(port);
 
         // We wait for following text to be written in standard output (stdout) in integration tests.
         // Any change at this message should be applied in tests.
@@ -46,21 +196,49 @@ pub async fn run_server(config: Config) {
 
     let mut handles = vec![];
 
-    let plan = Arc::new(ServerPlan::from_config(&config));
+    let draining = Arc::new(AtomicBool::new(false));
+    let drain_timeout = config
+        .options
+        .graceful_shutdown_timeout
+        .unwrap_or(DRAIN_TIMEOUT);
+    let drain = DrainTimeouts {
+        connections: drain_timeout,
+        tunnels: config.options.websocket_drain.unwrap_or(drain_timeout),
+    };
+    let timeouts = ConnectionTimeouts {
+        write: config.options.write_timeout,
+        header_read: config
+            .options
+            .header_read_timeout
+            .unwrap_or(HEADER_READ_TIMEOUT),
+    };
 
-    for listener in listeners {
+    for (listener, tls) in listeners {
         let mut rx = shutdown_tx.subscribe();
-        let plan_clone = plan.clone();
-        let join_handle =
-            tokio::spawn(async move { handle_listener(plan_clone, listener, &mut rx).await });
+        let plans_clone = plans.clone();
+        let draining_clone = draining.clone();
+        let join_handle = tokio::spawn(async move {
+            handle_listener(
+                plans_clone,
+                listener,
+                tls,
+                draining_clone,
+                drain,
+                timeouts,
+                &mut rx,
+            )
+            .await
+        });
         handles.push(join_handle);
     }
 
-    // Wait for shutdown signal (Ctrl+C)
-    shutdown_signal().await;
+    shutdown.await;
 
     info!("Shutdown signal received, notifying listeners...");
 
+    // Requests received from now on are answered with 503
+    draining.store(true, Ordering::SeqCst);
+
     // Send shutdown notification to all listener tasks
     let _ = shutdown_tx.send(());
 
@@ -71,16 +249,22 @@ pub async fn run_server(config: Config) {
 }
 
 async fn handle_listener(
-    plan: Arc<ServerPlan>,
+    plans: Arc<PlanHandle>,
     listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    draining: Arc<AtomicBool>,
+    drain: DrainTimeouts,
+    timeouts: ConnectionTimeouts,
     shutdown: &mut broadcast::Receiver<()>,
 ) {
+    let graceful = GracefulShutdown::new();
+    let tunnels = Tunnels::new();
     loop {
         let span = info_span!("listener.accept.loop");
         let _guard = span.enter();
         select! {
             res = listener.accept() => {
-                let (stream, _) = match res {
+                let (stream, peer) = match res {
                     Ok(conn) => conn,
                     Err(e) => {
                         error!("Error accepting connection: {:?}", e);
@@ -88,12 +272,21 @@ async fn handle_listener(
                     }
                 };
 
-                let plan_clone = plan.clone();
+                let connection = handle_connection(
+                    plans.current(),
+                    stream,
+                    peer,
+                    tls.clone(),
+                    timeouts,
+                    ConnectionShutdown {
+                        draining: draining.clone(),
+                        graceful: graceful.watcher(),
+                        tunnels: tunnels.clone(),
+                    },
+                );
 
                 // Spawn a tokio task to serve multiple connections concurrently
-                tokio::spawn(async move {
-                    handle_connection(plan_clone, stream).await;
-                });
+                tokio::spawn(connection);
             }
             _ = shutdown.recv() => {
                 info!("Shutdown signal received, stopping listener");
@@ -101,25 +294,133 @@ async fn handle_listener(
             }
         }
     }
+
+    // Let the open connections complete their requests, idle keep-alive connections are closed
+    let connections = async {
+        select! {
+            _ = graceful.shutdown() => {
+                info!("All connections of the listener are closed");
+            }
+            _ = tokio::time::sleep(drain.connections) => {
+                warn!("Timed out after {:?} waiting for connections to close", drain.connections);
+            }
+        }
+    };
+    tokio::join!(connections, tunnels.shutdown(drain.tunnels));
 }
 
-async fn handle_connection(plan: Arc<ServerPlan>, stream: tokio::net::TcpStream) {
+/// Serves the connection, watched by `shutdown` to drain it.
+///
+/// The TLS handshake, if any, runs in the connection task so it does not hold up the listener,
+/// the server name sent by the client is attached to the requests. A client has the header read
+/// timeout to complete the handshake. The connection is dropped when the client does not read for
+/// the write timeout, TLS records included.
+async fn handle_connection(
+    plan: Arc<ServerPlan>,
+    stream: tokio::net::TcpStream,
+    peer: SocketAddr,
+    tls: Option<TlsAcceptor>,
+    timeouts: ConnectionTimeouts,
+    shutdown: ConnectionShutdown,
+) {
+    let local_port = stream.local_addr().map(|addr| addr.port()).ok();
+    let stream = WriteTimeout::new(stream, timeouts.write);
+    let header_read_timeout = timeouts.header_read;
+    let Some(acceptor) = tls else {
+        return serve_connection(
+            plan,
+            stream,
+            peer,
+            false,
+            None,
+            header_read_timeout,
+            shutdown,
+        )
+        .await;
+    };
+    // hyper bounds the header read only once the handshake completed
+    let handshake = tokio::time::timeout(header_read_timeout, acceptor.accept(stream)).await;
+    let Ok(handshake) = handshake else {
+        debug!(
+            "TLS handshake with {} timed out after {:?}",
+            peer, header_read_timeout
+        );
+        return;
+    };
+    match handshake {
+        Ok(stream) => {
+            let server_name =
+                stream
+                    .get_ref()
+                    .1
+                    .server_name()
+                    .zip(local_port)
+                    .map(|(host, port)| ServerName {
+                        host: host.to_string(),
+                        port,
+                    });
+            serve_connection(
+                plan,
+                stream,
+                peer,
+                true,
+                server_name,
+                header_read_timeout,
+                shutdown,
+            )
+            .await
+        }
+        Err(err) => debug!("TLS handshake with {} failed: {:?}", peer, err),
+    }
+}
+
+async fn serve_connection<S>(
+    plan: Arc<ServerPlan>,
+    stream: S,
+    peer: SocketAddr,
+    secure: bool,
+    server_name: Option<ServerName>,
+    header_read_timeout: Duration,
+    shutdown: ConnectionShutdown,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let stream = Interim::new(stream);
+    let interim = stream.responses();
     // Use an adapter to access something implementing `tokio::io` traits as if they implement
     // `hyper::rt` IO traits.
     let io = TokioIo::new(stream);
 
-    let plan_clone = plan.clone();
-
-    let service = service_fn(move |req| {
-        let plan_clone = plan_clone.clone();
-        async move { handle_request(req, plan_clone).await }
+    let ConnectionShutdown {
+        draining,
+        graceful,
+        tunnels,
+    } = shutdown;
+    let service = service_fn(move |mut req: Request<Incoming>| {
+        req.extensions_mut().insert(PeerAddr(peer));
+        req.extensions_mut().insert(tunnels.clone());
+        req.extensions_mut().insert(interim.clone());
+        if secure {
+            req.extensions_mut().insert(TlsConnection);
+        }
+        if let Some(server_name) = &server_name {
+            req.extensions_mut().insert(server_name.clone());
+        }
+        let plan_clone = plan.clone();
+        let draining_clone = draining.clone();
+        async move { handle_request(req, plan_clone, &draining_clone).await }
     });
 
-    if let Err(err) = http1::Builder::new()
-        // `service_fn` converts our function in a `Service`
-        .serve_connection(io, service)
-        .await
-    {
+    // `service_fn` converts our function in a `Service`, upgraded connections (WebSocket) are
+    // handed over to the reverse proxy
+    let mut builder = auto::Builder::new(TokioExecutor::new()).http1_only();
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(header_read_timeout);
+    let connection = graceful.watch(builder.serve_connection_with_upgrades(io, service));
+
+    if let Err(err) = connection.await {
         error!("Error serving connection: {:?}", err);
     }
 }
@@ -127,11 +428,47 @@ async fn handle_connection(plan: Arc<ServerPlan>, stream: tokio::net::TcpStream)
 async fn handle_request(
     request: Request<Incoming>,
     plan: Arc<ServerPlan>,
+    draining: &AtomicBool,
 ) -> Result<Response<BoxBody>, Infallible> {
+    if draining.load(Ordering::SeqCst) {
+        return Ok(shutting_down_response());
+    }
+
+    let started = Instant::now();
+    let peer = request
+        .extensions()
+        .get::<PeerAddr>()
+        .map(|peer| peer.0.ip().to_string())
+        .unwrap_or_else(|| "-".to_string());
+    let request_line = format!(
+        "{} {} {:?}",
+        request.method(),
+        request.uri(),
+        request.version()
+    );
+
     let response = handlers::handle_request(request, plan).await;
+
+    info!(
+        target: ACCESS_LOG_TARGET,
+        "{} \"{}\" {} {}ms",
+        peer,
+        request_line,
+        response.status().as_u16(),
+        started.elapsed().as_millis()
+    );
     Ok(response)
 }
 
+/// Tells the client the server is going away, so it retries the request elsewhere.
+fn shutting_down_response() -> Response<BoxBody> {
+    Response::builder()
+        .status(http::StatusCode::SERVICE_UNAVAILABLE)
+        .header(http::header::CONNECTION, "close")
+        .body(full("503 Service Unavailable - server is shutting down."))
+        .unwrap()
+}
+
 pub async fn shutdown_signal() {
     #[cfg(unix)]
     {
@@ -178,3 +515,557 @@ pub async fn shutdown_signal() {
         }
     }
 }
+
+/// Reopens the log files whenever SIGUSR1 is received, as sent by logrotate after renaming them.
+///
+/// The signal handler is installed before returning, so no signal sent afterwards is missed.
+#[cfg(unix)]
+pub fn reopen_logs_on_signal(logs: LogHandle) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut reopen = signal(SignalKind::user_defined1())
+        .expect("failed to install 'user defined 1' signal handler");
+
+    tokio::spawn(async move {
+        while reopen.recv().await.is_some() {
+            match logs.reopen() {
+                Ok(()) => info!("Log files reopened"),
+                Err(e) => error!("Failed to reopen log files: {:?}", e),
+            }
+        }
+    });
+}
+
+/// Reloads the config file at `path` whenever SIGHUP is received, as sent by `chico reload`.
+///
+/// An invalid config is logged and the current plan is kept. Like
+/// [`reopen_logs_on_signal`], the signal handler is installed before returning.
+#[cfg(unix)]
+pub fn reload_on_signal(
+    plans: Arc<PlanHandle>,
+    path: String,
+    options: crate::config::ValidationOptions,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut reload =
+        signal(SignalKind::hangup()).expect("failed to install 'hangup' signal handler");
+
+    tokio::spawn(async move {
+        while reload.recv().await.is_some() {
+            // the outcome is logged by the reload
+            let _ = plans.reload(&path, &options).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use hyper_util::server::graceful::GracefulShutdown;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::{plan::ServerPlan, reload::PlanHandle};
+
+    use super::{
+        handle_connection, handle_listener, ConnectionShutdown, ConnectionTimeouts, DrainTimeouts,
+        Tunnels,
+    };
+
+    /// Reads a response without body from a keep-alive connection.
+    async fn read_head(client: &mut TcpStream) -> String {
+        let mut response = vec![];
+        let mut buf = [0; 1024];
+        while !response.ends_with(b"\r\n\r\n") {
+            let read = client.read(&mut buf).await.unwrap();
+            assert!(read > 0, "connection closed before the response ended");
+            response.extend_from_slice(&buf[..read]);
+        }
+        String::from_utf8(response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_request_during_drain_receives_503() {
+        let (_, config) =
+            chico_file::parse_config("localhost { route / { respond 204 } }").unwrap();
+        let plan = Arc::new(ServerPlan::from_config(&config));
+        let draining = Arc::new(AtomicBool::new(false));
+        let graceful = GracefulShutdown::new();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, peer) = listener.accept().await.unwrap();
+        tokio::spawn(handle_connection(
+            plan,
+            stream,
+            peer,
+            None,
+            ConnectionTimeouts::default(),
+            ConnectionShutdown {
+                draining: draining.clone(),
+                graceful: graceful.watcher(),
+                tunnels: Tunnels::new(),
+            },
+        ));
+
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        client.write_all(request).await.unwrap();
+        let response = read_head(&mut client).await;
+        assert!(response.starts_with("HTTP/1.1 204"), "{response}");
+
+        // The next request on the same keep-alive connection arrives during the drain
+        draining.store(true, Ordering::SeqCst);
+        client.write_all(request).await.unwrap();
+        let mut response = String::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_string(&mut response))
+            .await
+            .expect("the connection should be closed after the 503")
+            .unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 503"), "{response}");
+        assert!(response.to_ascii_lowercase().contains("connection: close"));
+    }
+
+    #[tokio::test]
+    async fn test_proxied_response_ended_by_upstream_close_completes() {
+        // The upstream sends a response without Content-Length and ends it by closing the
+        // connection
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf).await.unwrap();
+            stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").await.unwrap();
+        });
+
+        let (_, config) = chico_file::parse_config(&format!(
+            "localhost {{ route / {{ proxy 127.0.0.1:{upstream_port} }} }}"
+        ))
+        .unwrap();
+        let plan = Arc::new(ServerPlan::from_config(&config));
+        let graceful = GracefulShutdown::new();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, peer) = listener.accept().await.unwrap();
+        tokio::spawn(handle_connection(
+            plan,
+            stream,
+            peer,
+            None,
+            ConnectionTimeouts::default(),
+            ConnectionShutdown {
+                draining: Arc::new(AtomicBool::new(false)),
+                graceful: graceful.watcher(),
+                tunnels: Tunnels::new(),
+            },
+        ));
+
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        // The body is relayed chunked, the last chunk ends the response
+        let mut response = vec![];
+        let mut buf = [0; 1024];
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !response.ends_with(b"\r\n0\r\n\r\n") {
+                let read = client.read(&mut buf).await.unwrap();
+                assert!(read > 0, "connection closed before the response ended");
+                response.extend_from_slice(&buf[..read]);
+            }
+        })
+        .await
+        .expect("the response should complete once the upstream closed the connection");
+
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(
+            response.contains("transfer-encoding: chunked"),
+            "{response}"
+        );
+    }
+
+    /// Serves one connection proxying to an upstream sending `103 Early Hints`, then its final
+    /// response once `final_response` fires. The connection is served until the returned
+    /// `GracefulShutdown` is dropped.
+    async fn start_early_hints_connection(
+        final_response: tokio::sync::oneshot::Receiver<()>,
+    ) -> (TcpStream, GracefulShutdown) {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf).await.unwrap();
+            stream
+                .write_all(
+                    b"HTTP/1.1 103 Early Hints\r\n\
+                    Link: </style.css>; rel=preload; as=style\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            let _ = final_response.await;
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello")
+                .await
+                .unwrap();
+        });
+
+        let (_, config) = chico_file::parse_config(&format!(
+            "localhost {{ route / {{ proxy 127.0.0.1:{upstream_port} }} }}"
+        ))
+        .unwrap();
+        let plan = Arc::new(ServerPlan::from_config(&config));
+        let graceful = GracefulShutdown::new();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, peer) = listener.accept().await.unwrap();
+        tokio::spawn(handle_connection(
+            plan,
+            stream,
+            peer,
+            None,
+            ConnectionTimeouts::default(),
+            ConnectionShutdown {
+                draining: Arc::new(AtomicBool::new(false)),
+                graceful: graceful.watcher(),
+                tunnels: Tunnels::new(),
+            },
+        ));
+        (client, graceful)
+    }
+
+    #[tokio::test]
+    async fn test_early_hints_are_sent_before_the_final_response() {
+        let (final_response, final_response_rx) = tokio::sync::oneshot::channel();
+        let (mut client, _graceful) = start_early_hints_connection(final_response_rx).await;
+
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        // the upstream holds back its final response until the client saw the hints
+        let hints = tokio::time::timeout(Duration::from_secs(5), read_head(&mut client))
+            .await
+            .expect("the early hints should arrive before the final response");
+        assert_eq!(
+            hints,
+            "HTTP/1.1 103 Early Hints\r\nlink: </style.css>; rel=preload; as=style\r\n\r\n"
+        );
+
+        final_response.send(()).unwrap();
+        let mut response = vec![];
+        let mut buf = [0; 1024];
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !response.ends_with(b"Hello") {
+                let read = client.read(&mut buf).await.unwrap();
+                assert!(read > 0, "connection closed before the response ended");
+                response.extend_from_slice(&buf[..read]);
+            }
+        })
+        .await
+        .expect("the final response should follow the early hints");
+
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(!response.contains("link:"), "{response}");
+    }
+
+    #[tokio::test]
+    async fn test_early_hints_are_merged_into_the_final_response_of_http_1_0_clients() {
+        let (final_response, final_response_rx) = tokio::sync::oneshot::channel();
+        let (mut client, _graceful) = start_early_hints_connection(final_response_rx).await;
+
+        client
+            .write_all(b"GET / HTTP/1.0\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        final_response.send(()).unwrap();
+        let mut response = String::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_string(&mut response))
+            .await
+            .expect("the connection should be closed after the response")
+            .unwrap();
+
+        assert!(response.starts_with("HTTP/1.0 200 OK"), "{response}");
+        assert!(
+            response.contains("link: </style.css>; rel=preload; as=style"),
+            "{response}"
+        );
+        assert!(!response.contains("103"), "{response}");
+    }
+
+    #[tokio::test]
+    async fn test_client_not_reading_the_response_is_dropped_after_write_timeout() {
+        // large enough to fill the socket buffers of both ends
+        const SIZE: usize = 64 * 1024 * 1024;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large.bin");
+        std::fs::write(&path, vec![b'x'; SIZE]).unwrap();
+
+        let (_, config) = chico_file::parse_config(&format!(
+            "localhost {{ route /large {{ file {} }} }}",
+            path.display()
+        ))
+        .unwrap();
+        let plan = Arc::new(ServerPlan::from_config(&config));
+        let graceful = GracefulShutdown::new();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, peer) = listener.accept().await.unwrap();
+        let connection = tokio::spawn(handle_connection(
+            plan,
+            stream,
+            peer,
+            None,
+            ConnectionTimeouts {
+                write: Some(Duration::from_millis(200)),
+                ..Default::default()
+            },
+            ConnectionShutdown {
+                draining: Arc::new(AtomicBool::new(false)),
+                graceful: graceful.watcher(),
+                tunnels: Tunnels::new(),
+            },
+        ));
+
+        client
+            .write_all(b"GET /large HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+
+        // the client stalls, the server gives up on the connection
+        tokio::time::timeout(Duration::from_secs(5), connection)
+            .await
+            .expect("the connection should be dropped after the write timeout")
+            .unwrap();
+
+        // only what was buffered before the timeout arrives
+        let mut received = 0;
+        let mut buf = vec![0; 64 * 1024];
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match client.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => received += read,
+                }
+            }
+        })
+        .await
+        .expect("the connection should be closed");
+        assert!(received < SIZE, "received the whole response");
+    }
+
+    #[tokio::test]
+    async fn test_stalled_tls_handshake_is_dropped_after_header_read_timeout() {
+        let (_, config) = chico_file::parse_config(
+            "https://localhost {
+                tls resources/test_cases/tls/cert.pem resources/test_cases/tls/key.pem
+                route / { respond 200 }
+            }",
+        )
+        .unwrap();
+        let acceptor = crate::tls::build_acceptor(&[&config.virtual_hosts[0]]).unwrap();
+        let plan = Arc::new(ServerPlan::from_config(&config));
+        let graceful = GracefulShutdown::new();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        // the client connects but never sends its ClientHello
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, peer) = listener.accept().await.unwrap();
+        let connection = tokio::spawn(handle_connection(
+            plan,
+            stream,
+            peer,
+            acceptor,
+            ConnectionTimeouts {
+                header_read: Duration::from_millis(200),
+                ..Default::default()
+            },
+            ConnectionShutdown {
+                draining: Arc::new(AtomicBool::new(false)),
+                graceful: graceful.watcher(),
+                tunnels: Tunnels::new(),
+            },
+        ));
+
+        tokio::time::timeout(Duration::from_secs(5), connection)
+            .await
+            .expect("the connection should be dropped after the header read timeout")
+            .unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_websocket_tunnel_is_given_websocket_drain_on_shutdown() {
+        // The upstream switches protocols and echoes what it receives
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let mut head = vec![];
+            let mut buf = [0; 1024];
+            while !head.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buf).await.unwrap();
+                head.extend_from_slice(&buf[..read]);
+            }
+            stream
+                .write_all(
+                    b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            let (mut reader, mut writer) = stream.into_split();
+            let _ = tokio::io::copy(&mut reader, &mut writer).await;
+        });
+
+        let (_, config) = chico_file::parse_config(&format!(
+            "localhost {{ route / {{ proxy 127.0.0.1:{upstream_port} }} }}"
+        ))
+        .unwrap();
+        let plans = Arc::new(PlanHandle::new(ServerPlan::from_config(&config)));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_tx, mut shutdown_rx) = tokio::sync::broadcast::channel(1);
+        let drain = DrainTimeouts {
+            connections: Duration::from_secs(10),
+            tunnels: Duration::from_millis(500),
+        };
+        let listener_task = tokio::spawn(async move {
+            handle_listener(
+                plans,
+                listener,
+                None,
+                Arc::new(AtomicBool::new(false)),
+                drain,
+                ConnectionTimeouts::default(),
+                &mut shutdown_rx,
+            )
+            .await
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\r\n")
+            .await
+            .unwrap();
+        let response = read_head(&mut client).await;
+        assert!(response.starts_with("HTTP/1.1 101"), "{response}");
+        let mut echo = [0; 4];
+        client.write_all(b"ping").await.unwrap();
+        client.read_exact(&mut echo).await.unwrap();
+        assert_eq!(&echo, b"ping");
+
+        let shutdown = std::time::Instant::now();
+        shutdown_tx.send(()).unwrap();
+
+        // the tunnel is still served during the drain
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        client.write_all(b"pong").await.unwrap();
+        client.read_exact(&mut echo).await.unwrap();
+        assert_eq!(&echo, b"pong");
+
+        // then closed once it elapsed, without waiting for graceful_shutdown_timeout
+        let mut rest = vec![];
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut rest))
+            .await
+            .expect("the tunnel should be closed after websocket_drain")
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), listener_task)
+            .await
+            .expect("the listener should stop once the tunnel is closed")
+            .unwrap();
+        let elapsed = shutdown.elapsed();
+        assert!(elapsed >= Duration::from_millis(500), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn test_tunnels_shutdown_completes_once_tunnels_close() {
+        let tunnels = Tunnels::new();
+        // without open tunnels there is nothing to wait for
+        tokio::time::timeout(
+            Duration::from_millis(100),
+            tunnels.shutdown(Duration::from_secs(60)),
+        )
+        .await
+        .unwrap();
+
+        let tunnel = tunnels.watch();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(tunnel);
+        });
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            tunnels.shutdown(Duration::from_secs(60)),
+        )
+        .await
+        .expect("the shutdown should complete once the tunnel closed by itself");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sigusr1_reopens_log_file() {
+        use std::{fs, io::Write};
+
+        use crates_tracing::{LogFile, LogHandle};
+
+        use super::reopen_logs_on_signal;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chico.log");
+        let rotated = dir.path().join("chico.log.1");
+
+        let mut log_file = LogFile::open(&path).unwrap();
+        reopen_logs_on_signal(LogHandle::from(log_file.clone()));
+
+        log_file.write_all(b"before rotation\n").unwrap();
+        fs::rename(&path, &rotated).unwrap();
+
+        let status = std::process::Command::new("kill")
+            .args(["-USR1", &std::process::id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // reopening creates the file at the original path again
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !path.exists() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("log file was not reopened");
+
+        log_file.write_all(b"after rotation\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&rotated).unwrap(), "before rotation\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "after rotation\n");
+    }
+}
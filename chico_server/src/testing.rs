@@ -0,0 +1,101 @@
+//! # Testing
+//!
+//! Test doubles to unit-test request handlers without starting a server. Enabled by the
+//! `testing` feature.
+//!
+//! # Examples
+//!
+//! Testing a custom handler:
+//!
+//! ```
+//! use chico_server::handlers::{full, BoxBody, RequestHandler};
+//! use chico_server::testing::{collect_body, request, MockBody};
+//! use http::{Request, Response};
+//!
+//! struct Greeter;
+//!
+//! impl RequestHandler for Greeter {
+//!     async fn handle<B>(&self, request: Request<B>) -> Response<BoxBody>
+//!     where
+//!         B: hyper::body::Body + Send + 'static,
+//!         B::Data: Send,
+//!         B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+//!     {
+//!         Response::new(full(format!("Hello from {}", request.uri().path())))
+//!     }
+//! }
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let request = request().uri("/greet").body(MockBody::new(b"")).unwrap();
+//! let response = Greeter.handle(request).await;
+//!
+//! assert_eq!(response.status(), http::StatusCode::OK);
+//! assert_eq!(collect_body(response).await, "Hello from /greet");
+//! # }
+//! ```
+
+use http::{request, Request, Response};
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes};
+
+use crate::handlers::BoxBody;
+
+/// Request body sending the given bytes in a single frame.
+#[derive(Clone, Copy)]
+pub struct MockBody {
+    data: &'static [u8],
+}
+
+impl MockBody {
+    pub fn new(data: &'static [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl Body for MockBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<hyper::body::Frame<Self::Data>, Self::Error>>> {
+        if self.data.is_empty() {
+            std::task::Poll::Ready(None)
+        } else {
+            let data = self.data;
+            self.data = &[];
+            std::task::Poll::Ready(Some(Ok(hyper::body::Frame::data(Bytes::from(data)))))
+        }
+    }
+}
+
+/// Returns a builder of a `GET http://localhost/` request with the `Host: localhost` header.
+///
+/// ```
+/// use chico_server::testing::{request, MockBody};
+///
+/// let request = request()
+///     .method(http::Method::HEAD)
+///     .uri("http://localhost/index.html")
+///     .body(MockBody::new(b""))
+///     .unwrap();
+///
+/// assert_eq!(request.headers()[http::header::HOST], "localhost");
+/// ```
+pub fn request() -> request::Builder {
+    Request::builder()
+        .uri("http://localhost/")
+        .header(http::header::HOST, "localhost")
+}
+
+/// Reads the whole body of the response as a string.
+///
+/// # Panics
+///
+/// Panics if the body fails or is not valid UTF-8.
+pub async fn collect_body(response: Response<BoxBody>) -> String {
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    String::from_utf8(body.to_vec()).unwrap()
+}
@@ -0,0 +1,247 @@
+//! # TLS
+//!
+//! Terminates TLS on the listeners of the virtual hosts having a `tls` directive. A listener
+//! serving several of them picks the certificate from the SNI name sent by the client, the
+//! certificate of the first virtual host is used for unknown names.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chico_file::types::{TlsConfig, VirtualHost};
+use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+/// Loads the certificate chain and the private key, the key must belong to the certificate.
+pub fn load_certified_key(tls: &TlsConfig) -> Result<CertifiedKey, String> {
+    let certs = CertificateDer::pem_file_iter(&tls.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("failed to read certificate {}: {}", tls.cert, e))?;
+    if certs.is_empty() {
+        return Err(format!("no certificate found in {}", tls.cert));
+    }
+    let key = PrivateKeyDer::from_pem_file(&tls.key)
+        .map_err(|e| format!("failed to read private key {}: {}", tls.key, e))?;
+
+    CertifiedKey::from_der(certs, key, &ring::default_provider()).map_err(|e| {
+        format!(
+            "private key {} does not match certificate {}: {}",
+            tls.key, tls.cert, e
+        )
+    })
+}
+
+/// Returns the time the certificate `cert` expires at, or `None` when its validity cannot be
+/// read.
+pub fn not_after(cert: &CertificateDer) -> Option<SystemTime> {
+    // Certificate ::= SEQUENCE { tbsCertificate SEQUENCE { [0] version OPTIONAL, serialNumber,
+    // signature, issuer, validity SEQUENCE { notBefore, notAfter }, ... }, ... }
+    let (_, certificate, _) = der_element(cert.as_ref(), 0x30)?;
+    let (_, mut tbs, _) = der_element(certificate, 0x30)?;
+    if tbs.first() == Some(&0xa0) {
+        tbs = der_element(tbs, 0xa0)?.2;
+    }
+    let (_, _, rest) = der_element(tbs, 0x02)?;
+    let (_, _, rest) = der_element(rest, 0x30)?;
+    let (_, _, rest) = der_element(rest, 0x30)?;
+    let (_, validity, _) = der_element(rest, 0x30)?;
+    let (_, _, rest) = der_element(validity, validity.first().copied()?)?;
+    let (tag, time, _) = der_element(rest, rest.first().copied()?)?;
+    parse_der_time(tag, time)
+}
+
+/// Splits the DER element starting `input` into its tag, content and the bytes following it,
+/// `None` when its tag is not `tag`.
+fn der_element(input: &[u8], tag: u8) -> Option<(u8, &[u8], &[u8])> {
+    let (&first, rest) = input.split_first()?;
+    if first != tag {
+        return None;
+    }
+    let (&length, mut rest) = rest.split_first()?;
+    let length = if length < 0x80 {
+        length as usize
+    } else {
+        let bytes = (length & 0x7f) as usize;
+        if bytes == 0 || bytes > 4 || rest.len() < bytes {
+            return None;
+        }
+        let (length, after) = rest.split_at(bytes);
+        rest = after;
+        length
+            .iter()
+            .fold(0usize, |length, &byte| (length << 8) | byte as usize)
+    };
+    if rest.len() < length {
+        return None;
+    }
+    let (content, rest) = rest.split_at(length);
+    Some((tag, content, rest))
+}
+
+/// Parses an UTCTime (`YYMMDDHHMMSSZ`) or a GeneralizedTime (`YYYYMMDDHHMMSSZ`).
+fn parse_der_time(tag: u8, time: &[u8]) -> Option<SystemTime> {
+    let time = std::str::from_utf8(time).ok()?.strip_suffix('Z')?;
+    let (year, rest) = match tag {
+        0x17 => {
+            let year: i64 = time.get(..2)?.parse().ok()?;
+            (
+                if year < 50 { 2000 + year } else { 1900 + year },
+                &time[2..],
+            )
+        }
+        0x18 => (time.get(..4)?.parse().ok()?, &time[4..]),
+        _ => return None,
+    };
+    if rest.len() != 10 || !rest.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let field = |index: usize| rest[index..index + 2].parse::<i64>().unwrap();
+    let (month, day) = (field(0), field(2));
+    let seconds =
+        days_from_civil(year, month, day) * 86_400 + field(4) * 3_600 + field(6) * 60 + field(8);
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(seconds).ok()?))
+}
+
+/// Returns the number of days from 1970-01-01 to the given date of the Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Returns the acceptor of a listener serving the given virtual hosts, or `None` when none of
+/// them has a `tls` directive.
+pub fn build_acceptor(virtual_hosts: &[&VirtualHost]) -> Result<Option<TlsAcceptor>, String> {
+    let mut certs = HashMap::new();
+    let mut default = None;
+    for vh in virtual_hosts {
+        let Some(tls) = &vh.tls else {
+            continue;
+        };
+        let key = Arc::new(load_certified_key(tls)?);
+        let host = vh.domain.host.trim_matches(['[', ']']).to_ascii_lowercase();
+        certs.insert(host, key.clone());
+        default.get_or_insert(key);
+    }
+    let Some(default) = default else {
+        return Ok(None);
+    };
+
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("failed to configure TLS: {}", e))?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(SniResolver { certs, default }));
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Some(TlsAcceptor::from(Arc::new(config))))
+}
+
+#[derive(Debug)]
+struct SniResolver {
+    /// Certificates by lowercase host name.
+    certs: HashMap<String, Arc<CertifiedKey>>,
+    /// Certificate for clients without SNI or with an unknown name.
+    default: Arc<CertifiedKey>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let cert = client_hello
+            .server_name()
+            .and_then(|name| self.certs.get(&name.to_ascii_lowercase()))
+            .unwrap_or(&self.default);
+        Some(cert.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chico_file::types::TlsConfig;
+    use claims::{assert_err, assert_ok};
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{build_acceptor, load_certified_key, not_after};
+
+    fn tls(cert: &str, key: &str) -> TlsConfig {
+        TlsConfig {
+            cert: format!("resources/test_cases/tls/{cert}"),
+            key: format!("resources/test_cases/tls/{key}"),
+        }
+    }
+
+    #[test]
+    fn test_load_certified_key() {
+        assert_ok!(load_certified_key(&tls("cert.pem", "key.pem")));
+    }
+
+    #[test]
+    fn test_load_certified_key_rejects_missing_files() {
+        let error = assert_err!(load_certified_key(&tls("missing.pem", "key.pem")));
+        assert!(
+            error.starts_with("failed to read certificate resources/test_cases/tls/missing.pem")
+        );
+
+        let error = assert_err!(load_certified_key(&tls("cert.pem", "missing.pem")));
+        assert!(
+            error.starts_with("failed to read private key resources/test_cases/tls/missing.pem")
+        );
+    }
+
+    #[test]
+    fn test_load_certified_key_rejects_key_of_other_certificate() {
+        let error = assert_err!(load_certified_key(&tls("cert.pem", "other_key.pem")));
+        assert!(
+            error.starts_with("private key resources/test_cases/tls/other_key.pem does not match")
+        );
+    }
+
+    #[test]
+    fn test_not_after() {
+        // UTCTime: Jan 1 00:00:00 2021 GMT
+        let key = load_certified_key(&tls("expired_cert.pem", "expired_key.pem")).unwrap();
+        assert_eq!(
+            not_after(key.end_entity_cert().unwrap()),
+            Some(UNIX_EPOCH + Duration::from_secs(1_609_459_200))
+        );
+
+        // GeneralizedTime: Sep 22 20:08:11 2126 GMT
+        let key = load_certified_key(&tls("cert.pem", "key.pem")).unwrap();
+        assert_eq!(
+            not_after(key.end_entity_cert().unwrap()),
+            Some(UNIX_EPOCH + Duration::from_secs(4_945_781_291))
+        );
+    }
+
+    #[test]
+    fn test_build_acceptor_only_for_tls_virtual_hosts() {
+        let (_, config) = chico_file::parse_config(
+            "https://localhost:3443 {
+                tls resources/test_cases/tls/cert.pem resources/test_cases/tls/key.pem
+                route / { respond 200 }
+            }
+            localhost:3000 { route / { respond 200 } }",
+        )
+        .unwrap();
+
+        let tls_hosts = [&config.virtual_hosts[0]];
+        assert!(assert_ok!(build_acceptor(&tls_hosts)).is_some());
+
+        let plain_hosts = [&config.virtual_hosts[1]];
+        assert!(assert_ok!(build_acceptor(&plain_hosts)).is_none());
+    }
+}
@@ -1,17 +1,24 @@
-use std::str::FromStr;
-
 use chico_file::types::VirtualHost;
-use crates_uri::UriExt;
-use http::Uri;
 
 pub trait VirtualHostExt {
-    fn get_port(&self) -> u16;
+    /// Returns the port of the domain, or `default_port` when the domain has none.
+    ///
+    /// Without default port the port of the scheme is used: 443 for HTTPS and 80 otherwise.
+    fn get_port(&self, default_port: Option<u16>) -> u16;
+    /// Returns true when the domain has the `https` scheme.
+    fn is_https(&self) -> bool;
 }
 
 impl VirtualHostExt for VirtualHost {
-    fn get_port(&self) -> u16 {
-        Uri::from_str(&self.domain)
-            .expect("Expected Valid host")
-            .get_port()
+    fn get_port(&self, default_port: Option<u16>) -> u16 {
+        match (self.domain.port, default_port) {
+            (Some(port), _) | (None, Some(port)) => port,
+            (None, None) if self.domain.is_https() => 443,
+            (None, None) => 80,
+        }
+    }
+
+    fn is_https(&self) -> bool {
+        self.domain.is_https()
     }
 }
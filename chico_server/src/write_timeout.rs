@@ -0,0 +1,176 @@
+//! # WriteTimeout
+//!
+//! Drops connections whose client stops reading the response. A client that never reads keeps
+//! the socket buffers full, so a write to the connection waits forever and the connection holds
+//! on to the file or upstream response it streams.
+//!
+//! The timeout starts when a write cannot make progress and is reset by every write that does,
+//! so slow clients are served as long as they keep consuming data.
+
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{sleep, Sleep},
+};
+use tracing::debug;
+
+/// Stream whose writes fail with [`io::ErrorKind::TimedOut`] once they made no progress for the
+/// timeout. Without timeout it behaves like the stream it wraps.
+pub struct WriteTimeout<S> {
+    inner: S,
+    timeout: Option<Duration>,
+    /// Running while a write is pending.
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> WriteTimeout<S> {
+    pub fn new(inner: S, timeout: Option<Duration>) -> Self {
+        Self {
+            inner,
+            timeout,
+            deadline: None,
+        }
+    }
+
+    /// Waits for the deadline of the pending write, starting it on the first call.
+    fn poll_deadline<T>(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<T>> {
+        let Some(timeout) = self.timeout else {
+            return Poll::Pending;
+        };
+        let deadline = self
+            .deadline
+            .get_or_insert_with(|| Box::pin(sleep(timeout)));
+        match deadline.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                debug!("client did not read the response for {:?}", timeout);
+                Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "client did not read the response within the write timeout",
+                )))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Stops the deadline once the write completed, or gives the deadline's verdict while it waits.
+    fn progress<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        poll: Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        match poll {
+            Poll::Pending => self.poll_deadline(cx),
+            ready => {
+                self.deadline = None;
+                ready
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for WriteTimeout<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for WriteTimeout<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        this.progress(cx, poll)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write_vectored(cx, bufs);
+        this.progress(cx, poll)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_flush(cx);
+        this.progress(cx, poll)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_shutdown(cx);
+        this.progress(cx, poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::WriteTimeout;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_write_fails_when_client_does_not_read() {
+        let (server, _client) = duplex(16);
+        let mut server = WriteTimeout::new(server, Some(Duration::from_secs(30)));
+
+        let error = server.write_all(&[0; 64]).await.unwrap_err();
+
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_reader_within_timeout_receives_everything() {
+        let (server, mut client) = duplex(16);
+        let mut server = WriteTimeout::new(server, Some(Duration::from_secs(30)));
+
+        let reader = tokio::spawn(async move {
+            let mut received = vec![];
+            let mut buf = [0; 16];
+            loop {
+                // each read comes well within the timeout, the whole transfer does not
+                tokio::time::sleep(Duration::from_secs(20)).await;
+                match client.read(&mut buf).await.unwrap() {
+                    0 => return received,
+                    read => received.extend_from_slice(&buf[..read]),
+                }
+            }
+        });
+        server.write_all(&[7; 64]).await.unwrap();
+        server.shutdown().await.unwrap();
+
+        assert_eq!(reader.await.unwrap(), vec![7; 64]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_without_timeout_write_waits_for_the_client() {
+        let (server, _client) = duplex(16);
+        let mut server = WriteTimeout::new(server, None);
+
+        let write = tokio::time::timeout(Duration::from_secs(3600), server.write_all(&[0; 64]));
+
+        assert!(write.await.is_err(), "the write should still wait");
+    }
+}
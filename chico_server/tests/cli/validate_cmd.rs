@@ -46,6 +46,32 @@ fn test_validate_command_should_return_error_for_invalid_config() {
         ));
 }
 
+#[test]
+fn test_validate_command_should_return_error_for_missing_respond_file() {
+    let content = r#"
+    localhost {
+        route / {
+            respond file:/path/to/missing/maintenance.html 503
+        }
+    }
+    "#;
+
+    let mut temp_file = NamedTempFile::new().unwrap();
+    let _ = temp_file.write_all(content.as_bytes());
+    let file_path = temp_file.path().to_str().unwrap();
+
+    let mut cmd = assert_cmd::Command::cargo_bin("chico").unwrap();
+    cmd.arg("validate")
+        .arg("--config")
+        .arg(file_path)
+        .assert()
+        .failure()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Failed to parse config file. reason: failed to read respond file /path/to/missing/maintenance.html in host localhost route /",
+        ));
+}
+
 #[test]
 fn test_validate_command_should_return_success_for_valid_config() {
     let content = r#"
@@ -76,3 +102,151 @@ fn test_validate_command_should_return_success_for_valid_config() {
             "✅✅✅ Specified config is valid.",
         ));
 }
+
+#[test]
+fn test_validate_command_should_fail_above_route_limit_in_strict_mode() {
+    let content = r#"
+    localhost {
+        route / {
+            file index.html
+        }
+        route /about {
+            file about.html
+        }
+        route /blog {
+            file blog.html
+        }
+    }
+    "#;
+
+    let mut temp_file = NamedTempFile::new().unwrap();
+    let _ = temp_file.write_all(content.as_bytes());
+    let file_path = temp_file.path().to_str().unwrap();
+
+    let mut cmd = assert_cmd::Command::cargo_bin("chico").unwrap();
+    cmd.arg("validate")
+        .arg("--config")
+        .arg(file_path)
+        .arg("--max-routes")
+        .arg("2")
+        .assert()
+        .success();
+
+    let mut cmd = assert_cmd::Command::cargo_bin("chico").unwrap();
+    cmd.arg("validate")
+        .arg("--config")
+        .arg(file_path)
+        .arg("--max-routes")
+        .arg("2")
+        .arg("--strict")
+        .assert()
+        .failure()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Failed to parse config file. reason: config declares 3 routes, more than the limit of 2",
+        ));
+}
+
+#[test]
+fn test_validate_command_should_report_duplicate_domain_across_included_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let sites = dir.path().join("sites-enabled");
+    std::fs::create_dir(&sites).unwrap();
+    for name in ["a.chf", "b.chf"] {
+        std::fs::write(
+            sites.join(name),
+            "example.com {\n    route / {\n        respond 200\n    }\n}\n",
+        )
+        .unwrap();
+    }
+    let main = dir.path().join("chico.chf");
+    std::fs::write(&main, "include sites-enabled/*.chf\n").unwrap();
+
+    let mut cmd = assert_cmd::Command::cargo_bin("chico").unwrap();
+    cmd.arg("validate")
+        .arg("--config")
+        .arg(&main)
+        .assert()
+        .failure()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Failed to parse config file. reason: duplicate domain found: example.com",
+        ));
+}
+
+#[test]
+fn test_validate_command_should_report_duplicate_options_blocks() {
+    let dir = tempfile::tempdir().unwrap();
+    let main = dir.path().join("chico.chf");
+    std::fs::write(
+        &main,
+        "options {\n    default_port 8080\n}\noptions {\n    log_level info\n}\nlocalhost {\n    route / {\n        respond 200\n    }\n}\n",
+    )
+    .unwrap();
+
+    let mut cmd = assert_cmd::Command::cargo_bin("chico").unwrap();
+    cmd.arg("validate")
+        .arg("--config")
+        .arg(&main)
+        .assert()
+        .failure()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Failed to parse config file. Duplicate options block",
+        ));
+}
+
+#[test]
+fn test_validate_command_diff_should_fail_and_print_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let old = dir.path().join("old.chf");
+    let new = dir.path().join("new.chf");
+    std::fs::write(
+        &old,
+        "localhost {\n    route / {\n        file index.html\n    }\n}\n",
+    )
+    .unwrap();
+    std::fs::write(
+        &new,
+        "# home page moved\nlocalhost {\n    route / {\n        file home.html\n    }\n    route /api {\n        proxy http://localhost:3000\n    }\n}\n",
+    )
+    .unwrap();
+
+    let mut cmd = assert_cmd::Command::cargo_bin("chico").unwrap();
+    cmd.arg("validate")
+        .arg("--diff")
+        .arg(&old)
+        .arg(&new)
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(predicate::str::contains(
+            "~ localhost\n  ~ route /\n    ~ handler: File(\"index.html\") -> File(\"home.html\")\n  + route /api\n",
+        ));
+}
+
+#[test]
+fn test_validate_command_diff_should_succeed_without_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let old = dir.path().join("old.chf");
+    let new = dir.path().join("new.chf");
+    std::fs::write(
+        &old,
+        "a.example.com {\n    route / {\n        respond 200\n    }\n}\nb.example.com {\n    route / {\n        respond 204\n    }\n}\n",
+    )
+    .unwrap();
+    std::fs::write(
+        &new,
+        "b.example.com { route / { respond 204 } }\na.example.com { route / { respond 200 } }\n",
+    )
+    .unwrap();
+
+    let mut cmd = assert_cmd::Command::cargo_bin("chico").unwrap();
+    cmd.arg("validate")
+        .arg("--diff")
+        .arg(&old)
+        .arg(&new)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No changes."));
+}
@@ -20,6 +20,14 @@ pub(crate) struct ServerFixture {
 
 impl ServerFixture {
     pub fn run_app<T: AsRef<std::ffi::OsStr>>(config_path: T) -> ServerFixture {
+        ServerFixture::run_app_with_args(config_path, &[])
+    }
+
+    /// Runs the app like [`ServerFixture::run_app`] with more arguments of `chico run`.
+    pub fn run_app_with_args<T: AsRef<std::ffi::OsStr>>(
+        config_path: T,
+        args: &[&std::ffi::OsStr],
+    ) -> ServerFixture {
         use assert_cmd::cargo::CommandCargoExt;
 
         let mut binding = std::process::Command::cargo_bin("chico").expect("Failed to find binary");
@@ -27,6 +35,7 @@ impl ServerFixture {
             .arg("run")
             .arg("--config")
             .arg(config_path)
+            .args(args)
             .stdout(Stdio::piped())
             .stdin(Stdio::piped())
             .stderr(Stdio::piped());
@@ -96,12 +105,10 @@ impl ServerFixture {
         {
             self.shutdown_via_stdin();
         }
-        // we kill the process when we do not want to collect coverage, mostly in local dev when we want to run cargo test
+        // we signal the process when we do not want to collect coverage, mostly in local dev when we want to run cargo test
         #[cfg(not(feature = "stdin_shutdown"))]
         {
-            if let Err(e) = self.process.kill() {
-                eprintln!("Failed to kill the server process: {}", e);
-            }
+            self.shutdown_via_signal();
         }
 
         if let Err(e) = self.process.wait() {
@@ -130,6 +137,13 @@ impl ServerFixture {
         }
     }
 
+    /// Returns the output lines not read yet, the app must be stopped.
+    #[allow(dead_code)]
+    pub fn remaining_logs(&mut self) -> Vec<String> {
+        // The channel is closed once the output threads reach the end of the stopped process output
+        self.log_receiver.iter().collect()
+    }
+
     pub fn wait_for_start(&mut self) {
         self.wait_for_text("Start listening to incoming requests on");
     }
@@ -143,6 +157,27 @@ impl ServerFixture {
         &self.exe_path
     }
 
+    /// Sends SIGTERM so the server drains its connections like in production, other platforms
+    /// kill the process.
+    #[cfg(not(feature = "stdin_shutdown"))]
+    fn shutdown_via_signal(&mut self) {
+        #[cfg(unix)]
+        {
+            let terminated = std::process::Command::new("kill")
+                .arg("-TERM")
+                .arg(self.process.id().to_string())
+                .status()
+                .is_ok_and(|status| status.success());
+            if terminated {
+                return;
+            }
+            eprintln!("Failed to send SIGTERM to the server process, killing it");
+        }
+        if let Err(e) = self.process.kill() {
+            eprintln!("Failed to kill the server process: {}", e);
+        }
+    }
+
     #[cfg(feature = "stdin_shutdown")]
     fn shutdown_via_stdin(&mut self) {
         use std::io::Write;
@@ -199,6 +234,35 @@ mod serial_integration {
         assert_eq!(&response.text().await.unwrap(), "<h1>Example</h1>");
     }
 
+    #[tokio::test]
+    async fn test_respond_handler_body_from_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let page = dir.path().join("maintenance.html");
+        std::fs::write(&page, "<h1>Back soon</h1>").unwrap();
+        let config_file_path = dir.path().join("respond_file.chf");
+        std::fs::write(
+            &config_file_path,
+            format!(
+                "localhost:3000 {{ route / {{ respond file:{} 503 }} }}",
+                page.display()
+            ),
+        )
+        .unwrap();
+
+        let mut app = ServerFixture::run_app(&config_file_path);
+        app.wait_for_start();
+        let response = reqwest::get("http://localhost:3000/").await;
+        app.stop_app();
+
+        let response = response.unwrap();
+        assert_eq!(&response.status(), &StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.headers()[reqwest::header::CONTENT_TYPE],
+            "text/html"
+        );
+        assert_eq!(&response.text().await.unwrap(), "<h1>Back soon</h1>");
+    }
+
     #[tokio::test]
     async fn test_respond_handler_403_status_code() {
         let config_file_path =
@@ -362,24 +426,26 @@ mod serial_integration {
 
         app.wait_for_start();
 
-        let response = reqwest::get("http://localhost:3000").await;
+        let response = reqwest::get("http://localhost:3000").await.unwrap();
+        // The body is streamed from the file, read it before the server stops
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.text().await;
 
         // Cleanup resources before assertion
         app.stop_app();
         _ = std::fs::remove_file(file_path);
 
-        let response = response.unwrap();
-        assert_eq!(&response.status(), &StatusCode::OK);
+        assert_eq!(&status, &StatusCode::OK);
         assert_eq!(
-            response
-                .headers()
+            headers
                 .get(http::header::CONTENT_TYPE)
                 .unwrap()
                 .to_str()
                 .unwrap(),
             "text/html"
         );
-        assert_eq!(&response.text().await.unwrap(), content);
+        assert_eq!(&body.unwrap(), content);
     }
 
     #[tokio::test]
@@ -401,24 +467,28 @@ mod serial_integration {
 
         app.wait_for_start();
 
-        let response = reqwest::get("http://localhost:3000/downloads/hello.txt").await;
+        let response = reqwest::get("http://localhost:3000/downloads/hello.txt")
+            .await
+            .unwrap();
+        // The body is streamed from the file, read it before the server stops
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.text().await;
 
         // Cleanup resources before assertion
         app.stop_app();
         _ = std::fs::remove_file(file_path);
 
-        let response = response.unwrap();
-        assert_eq!(&response.status(), &StatusCode::OK);
+        assert_eq!(&status, &StatusCode::OK);
         assert_eq!(
-            response
-                .headers()
+            headers
                 .get(http::header::CONTENT_TYPE)
                 .unwrap()
                 .to_str()
                 .unwrap(),
             "text/plain"
         );
-        assert_eq!(&response.text().await.unwrap(), content);
+        assert_eq!(&body.unwrap(), content);
     }
 
     #[tokio::test]
@@ -439,6 +509,68 @@ mod serial_integration {
         assert_eq!(&response.text().await.unwrap(), "");
     }
 
+    #[tokio::test]
+    async fn test_try_files_falls_back_to_index_for_missing_asset() {
+        let config_file_path = Path::new("resources/test_cases/try-files/try_files.chf");
+        assert!(config_file_path.exists());
+
+        let mut app = ServerFixture::run_app(config_file_path);
+
+        let dir = Path::new(app.get_executing_dir()).join("spa");
+        std::fs::create_dir_all(dir.join("assets")).expect("Expected to create directories");
+        std::fs::write(dir.join("assets/app.js"), "console.log('app');").unwrap();
+        std::fs::write(dir.join("index.html"), "<h1>App</h1>").unwrap();
+
+        app.wait_for_start();
+
+        let asset = reqwest::get("http://localhost:3000/assets/app.js").await;
+        let missing = reqwest::get("http://localhost:3000/assets/missing.js").await;
+        // The bodies are streamed from the files, read them before the server stops
+        let asset = asset.unwrap();
+        let asset_status = asset.status();
+        let asset_body = asset.text().await;
+        let missing = missing.unwrap();
+        let missing_status = missing.status();
+        let missing_body = missing.text().await;
+
+        // Cleanup resources before assertion
+        app.stop_app();
+        _ = std::fs::remove_dir_all(dir);
+
+        assert_eq!(&asset_status, &StatusCode::OK);
+        assert_eq!(&asset_body.unwrap(), "console.log('app');");
+        assert_eq!(&missing_status, &StatusCode::OK);
+        assert_eq!(&missing_body.unwrap(), "<h1>App</h1>");
+    }
+
+    #[tokio::test]
+    async fn test_error_page_replaces_built_in_not_found() {
+        let config_file_path = Path::new("resources/test_cases/error-page/error_page.chf");
+        assert!(config_file_path.exists());
+
+        let mut app = ServerFixture::run_app(config_file_path);
+
+        let dir = Path::new(app.get_executing_dir()).join("errors");
+        std::fs::create_dir_all(&dir).expect("Expected to create directories");
+        std::fs::write(dir.join("404.html"), "<h1>Nothing here</h1>").unwrap();
+
+        app.wait_for_start();
+
+        let response = reqwest::get("http://localhost:3000/missing").await;
+        let response = response.unwrap();
+        let status = response.status();
+        let content_type = response.headers()[reqwest::header::CONTENT_TYPE].clone();
+        let body = response.text().await;
+
+        // Cleanup resources before assertion
+        app.stop_app();
+        _ = std::fs::remove_dir_all(dir);
+
+        assert_eq!(&status, &StatusCode::NOT_FOUND);
+        assert_eq!(content_type, "text/html");
+        assert_eq!(&body.unwrap(), "<h1>Nothing here</h1>");
+    }
+
     #[tokio::test]
     async fn test_file_handler_head_request() {
         let config_file_path =
@@ -524,24 +656,27 @@ mod serial_integration {
             .get("http://localhost:3000/test.txt")
             .header(http::header::RANGE, "bytes=0-4")
             .send()
-            .await;
+            .await
+            .unwrap();
+        // The body is streamed from the file, read it before the server stops
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.text().await;
 
         // Cleanup resources before assertion
         app.stop_app();
         _ = std::fs::remove_file(file_path);
 
-        let response = response.unwrap();
-        assert_eq!(&response.status(), &StatusCode::PARTIAL_CONTENT);
+        assert_eq!(&status, &StatusCode::PARTIAL_CONTENT);
         assert_eq!(
-            response
-                .headers()
+            headers
                 .get(http::header::CONTENT_RANGE)
                 .unwrap()
                 .to_str()
                 .unwrap(),
             "bytes 0-4/35"
         );
-        assert_eq!(response.text().await.unwrap(), "Hello");
+        assert_eq!(body.unwrap(), "Hello");
     }
 
     #[tokio::test]
@@ -781,4 +916,894 @@ mod serial_integration {
 
         assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
     }
+
+    #[tokio::test]
+    async fn test_error_format_auto_negotiates_with_accept_header() {
+        let config_file_path = Path::new("resources/test_cases/error-format/error-format.chf");
+        assert!(config_file_path.exists());
+
+        let mut app = ServerFixture::run_app(config_file_path);
+        app.wait_for_start();
+
+        let client = reqwest::Client::new();
+        let json_response = client
+            .get("http://localhost:8080/unknown")
+            .header("accept", "application/json")
+            .header("x-request-id", "req-42")
+            .send()
+            .await;
+        let html_response = client
+            .get("http://localhost:8080/unknown")
+            .header("accept", "text/html")
+            .send()
+            .await;
+        app.stop_app();
+
+        let json_response = json_response.unwrap();
+        assert_eq!(json_response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(json_response.headers()["content-type"], "application/json");
+        let body: serde_json::Value = json_response.json().await.unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "status": 404, "error": "Not Found", "request_id": "req-42" })
+        );
+
+        let html_response = html_response.unwrap();
+        assert_eq!(html_response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            html_response.headers()["content-type"],
+            "text/html; charset=utf-8"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_format_route_override_on_proxy_bad_gateway() {
+        let config_file_path = Path::new("resources/test_cases/error-format/error-format.chf");
+        assert!(config_file_path.exists());
+
+        let mut app = ServerFixture::run_app(config_file_path);
+        app.wait_for_start();
+
+        let client = reqwest::Client::new();
+        let api_response = client
+            .get("http://localhost:8080/api/users")
+            .header("accept", "text/html")
+            .send()
+            .await;
+        let legacy_response = client
+            .get("http://localhost:8080/legacy/users")
+            .header("accept", "application/json")
+            .send()
+            .await;
+        app.stop_app();
+
+        let api_response = api_response.unwrap();
+        assert_eq!(api_response.status(), StatusCode::BAD_GATEWAY);
+        let body: serde_json::Value = api_response.json().await.unwrap();
+        assert_eq!(body["status"], 502);
+        assert_eq!(body["error"], "Bad Gateway");
+        assert!(body["request_id"].as_str().is_some_and(|id| !id.is_empty()));
+
+        let legacy_response = legacy_response.unwrap();
+        assert_eq!(legacy_response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            legacy_response.text().await.unwrap(),
+            "502 Bad Gateway - could not connect to upstream server."
+        );
+    }
+
+    /// Sends a raw request and returns the raw response, the connection is closed by the server.
+    async fn send_raw(port: u16, raw_request: &str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        stream.write_all(raw_request.as_bytes()).await.unwrap();
+        let mut response = vec![];
+        stream.read_to_end(&mut response).await.unwrap();
+        String::from_utf8(response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_trace_and_connect_are_rejected_and_never_proxied() {
+        // Count the connections the upstream of the proxy receives
+        let upstream = tokio::net::TcpListener::bind("127.0.0.1:9000")
+            .await
+            .unwrap();
+        let mut app = start_reverse_proxy();
+        app.wait_for_start();
+
+        let trace = send_raw(
+            8080,
+            "TRACE /api HTTP/1.1\r\nHost: localhost:8080\r\nCookie: session=reflected-value\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        let connect = send_raw(
+            8080,
+            "CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        let upstream_connection =
+            tokio::time::timeout(Duration::from_millis(500), upstream.accept()).await;
+
+        app.stop_app();
+
+        assert!(trace.starts_with("HTTP/1.1 405"), "{trace}");
+        let allow = trace
+            .lines()
+            .find(|line| line.to_ascii_lowercase().starts_with("allow:"))
+            .expect("405 response should have an Allow header");
+        assert!(!allow.contains("TRACE") && !allow.contains("CONNECT"));
+        assert!(!trace.contains("reflected-value"));
+        assert!(!trace.contains("Cookie"));
+
+        assert!(connect.starts_with("HTTP/1.1 501"), "{connect}");
+        assert!(!connect.contains("example.com"));
+
+        assert!(
+            upstream_connection.is_err(),
+            "TRACE and CONNECT should not be forwarded to the upstream"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cors_headers_and_preflight() {
+        let config_file_path = Path::new("resources/test_cases/cors/cors.chf");
+        assert!(config_file_path.exists());
+
+        let mut app = ServerFixture::run_app(config_file_path);
+        app.wait_for_start();
+
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let preflight = client
+            .request(reqwest::Method::OPTIONS, "http://localhost:3000/api")
+            .header("origin", "https://app.example.com")
+            .header("access-control-request-method", "PUT")
+            .header("access-control-request-headers", "content-type")
+            .send()
+            .await;
+        let response = client
+            .get("http://localhost:3000/api")
+            .header("origin", "https://app.example.com")
+            .send()
+            .await;
+        let redirect = client.get("http://localhost:3000/public").send().await;
+        app.stop_app();
+
+        let preflight = preflight.unwrap();
+        assert_eq!(preflight.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            preflight.headers()["access-control-allow-origin"],
+            "https://app.example.com"
+        );
+        assert_eq!(
+            preflight.headers()["access-control-allow-headers"],
+            "content-type"
+        );
+
+        let response = response.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["access-control-allow-origin"],
+            "https://app.example.com"
+        );
+        assert_eq!(response.text().await.unwrap(), "Hello");
+
+        let redirect = redirect.unwrap();
+        assert_eq!(redirect.status(), StatusCode::FOUND);
+        assert_eq!(redirect.headers()["access-control-allow-origin"], "*");
+    }
+
+    #[tokio::test]
+    async fn test_route_methods_reject_other_methods_with_405() {
+        let config_file_path = Path::new("resources/test_cases/route-methods/route_methods.chf");
+        assert!(config_file_path.exists());
+
+        let mut app = ServerFixture::run_app(config_file_path);
+        app.wait_for_start();
+
+        let client = reqwest::Client::new();
+        let mut responses = vec![];
+        for method in [
+            http::Method::GET,
+            http::Method::POST,
+            http::Method::PUT,
+            http::Method::DELETE,
+        ] {
+            responses.push(
+                client
+                    .request(method, "http://localhost:3000/api/users")
+                    .send()
+                    .await,
+            );
+        }
+        app.stop_app();
+
+        let mut responses = responses.into_iter().map(Result::unwrap);
+        for _ in 0..2 {
+            let response = responses.next().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.text().await.unwrap(), "Users");
+        }
+        for response in responses {
+            assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+            assert_eq!(response.headers()[http::header::ALLOW], "GET, POST");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_rejects_burst_with_429() {
+        let config_file_path = Path::new("resources/test_cases/rate-limit/rate_limit.chf");
+        assert!(config_file_path.exists());
+
+        let mut app = ServerFixture::run_app(config_file_path);
+        app.wait_for_start();
+
+        let client = reqwest::Client::new();
+        let mut statuses = vec![];
+        let mut retry_after = None;
+        for _ in 0..7 {
+            let response = client
+                .get("http://localhost:3000/limited")
+                .send()
+                .await
+                .unwrap();
+            if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                retry_after = response.headers().get("retry-after").cloned();
+            }
+            statuses.push(response.status());
+        }
+        app.stop_app();
+
+        assert_eq!(
+            statuses,
+            [
+                vec![StatusCode::OK; 5],
+                vec![StatusCode::TOO_MANY_REQUESTS; 2]
+            ]
+            .concat()
+        );
+        let retry_after: u64 = retry_after.unwrap().to_str().unwrap().parse().unwrap();
+        assert!((1..=12).contains(&retry_after), "{retry_after}");
+    }
+
+    #[tokio::test]
+    async fn test_wildcard_domain_serves_subdomains() {
+        let config_file_path =
+            Path::new("resources/test_cases/wildcard-domain/wildcard_domain.chf");
+        assert!(config_file_path.exists());
+
+        let mut app = ServerFixture::run_app(config_file_path);
+        app.wait_for_start();
+
+        let client = reqwest::Client::new();
+        let mut bodies = vec![];
+        for host in [
+            "shop.localhost:3000",
+            "api.localhost:3000",
+            "a.b.localhost:3000",
+        ] {
+            let response = client
+                .get("http://127.0.0.1:3000/")
+                .header("host", host)
+                .send()
+                .await
+                .unwrap();
+            bodies.push((response.status(), response.text().await.unwrap()));
+        }
+        app.stop_app();
+
+        assert_eq!(bodies[0], (StatusCode::OK, "Subdomain".to_string()));
+        assert_eq!(bodies[1], (StatusCode::OK, "API".to_string()));
+        assert_eq!(bodies[2].0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_tls_host_is_served_over_https() {
+        let config_file_path = Path::new("resources/test_cases/tls/tls.chf");
+        assert!(config_file_path.exists());
+
+        let mut app = ServerFixture::run_app(config_file_path);
+        app.wait_for_start();
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        let response = client.get("https://localhost:3443/api").send().await;
+        let plain_response = reqwest::get("http://localhost:3443/api").await;
+        app.stop_app();
+
+        let response = response.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "Hello over TLS");
+        assert!(plain_response.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_host_not_matching_server_name_is_misdirected_with_strict_sni_host() {
+        let cert = std::fs::canonicalize("resources/test_cases/tls/cert.pem").unwrap();
+        let key = std::fs::canonicalize("resources/test_cases/tls/key.pem").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut statuses = vec![];
+        for strict_sni_host in ["off", "on"] {
+            let client = reqwest::Client::builder()
+                .danger_accept_invalid_certs(true)
+                .build()
+                .unwrap();
+            let config_file_path = dir.path().join(format!("sni_{strict_sni_host}.chf"));
+            std::fs::write(
+                &config_file_path,
+                format!(
+                    "options {{ strict_sni_host {strict_sni_host} }}
+https://localhost:3443 {{
+    tls {cert} {key}
+    route / {{ respond \"localhost\" 200 }}
+}}
+https://example.localhost:3443 {{
+    tls {cert} {key}
+    route / {{ respond \"example\" 200 }}
+}}",
+                    cert = cert.display(),
+                    key = key.display()
+                ),
+            )
+            .unwrap();
+
+            let mut app = ServerFixture::run_app(&config_file_path);
+            app.wait_for_start();
+            // the client sends localhost as server name
+            for host in ["localhost:3443", "example.localhost:3443"] {
+                let response = client
+                    .get("https://localhost:3443/")
+                    .header("host", host)
+                    .send()
+                    .await
+                    .unwrap();
+                statuses.push((response.status(), response.text().await.unwrap()));
+            }
+            app.stop_app();
+        }
+
+        assert_eq!(
+            statuses,
+            vec![
+                (StatusCode::OK, "localhost".to_string()),
+                (StatusCode::OK, "example".to_string()),
+                (StatusCode::OK, "localhost".to_string()),
+                (
+                    StatusCode::MISDIRECTED_REQUEST,
+                    "421 Misdirected Request".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_access_log_is_written_to_configured_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let access_log_path = dir.path().join("access.log");
+        let config_file_path = dir.path().join("access_log.chf");
+        let mut file = File::create(&config_file_path).unwrap();
+        write!(
+            file,
+            "options {{ access_log {} }}
+localhost:3000 {{ route /hello {{ respond \"Hello\" 200 }} }}",
+            access_log_path.display()
+        )
+        .unwrap();
+
+        let mut app = ServerFixture::run_app(&config_file_path);
+        app.wait_for_start();
+        let response = reqwest::get("http://localhost:3000/hello").await;
+        app.stop_app();
+        let diagnostic_logs = app.remaining_logs();
+
+        assert_eq!(response.unwrap().status(), StatusCode::OK);
+        let access_log = std::fs::read_to_string(&access_log_path).unwrap();
+        assert!(
+            access_log.contains("\"GET /hello HTTP/1.1\" 200"),
+            "{access_log}"
+        );
+        assert!(
+            !diagnostic_logs
+                .iter()
+                .any(|line| line.contains("GET /hello")),
+            "{diagnostic_logs:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cache_serves_stale_response_when_upstream_starts_failing() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let config_file_path = Path::new("resources/test_cases/cache/stale_if_error.chf");
+        assert!(config_file_path.exists());
+        let upstream = tokio::net::TcpListener::bind("127.0.0.1:9002")
+            .await
+            .unwrap();
+        let upstream = tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: 5\r\nConnection: close\r\n\r\nfresh")
+                .await
+                .unwrap();
+            stream.shutdown().await.unwrap();
+            // the listener is dropped, later requests of the proxy fail with 502
+        });
+
+        let mut app = ServerFixture::run_app(config_file_path);
+        app.wait_for_start();
+        let client = reqwest::Client::new();
+        let first = client.get("http://localhost:3000/api").send().await;
+        upstream.await.unwrap();
+        let conditional = client
+            .get("http://localhost:3000/api")
+            .header(http::header::IF_NONE_MATCH, "\"v1\"")
+            .send()
+            .await;
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        let stale = client.get("http://localhost:3000/api").send().await;
+        app.stop_app();
+
+        let first = first.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(first.text().await.unwrap(), "fresh");
+
+        assert_eq!(conditional.unwrap().status(), StatusCode::NOT_MODIFIED);
+
+        let stale = stale.unwrap();
+        assert_eq!(stale.status(), StatusCode::OK);
+        assert_eq!(
+            stale.headers()[http::header::WARNING],
+            "110 - \"Response is Stale\""
+        );
+        let age: u64 = stale.headers()[http::header::AGE]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(age >= 1, "age {age}");
+        assert_eq!(stale.text().await.unwrap(), "fresh");
+    }
+
+    #[tokio::test]
+    async fn test_reverse_proxy_tunnels_websocket_upgrade() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let config_file_path =
+            Path::new("resources/test_cases/reverse-proxy-handler/websocket.chf");
+        assert!(config_file_path.exists());
+        // a small echo backend: accepts the upgrade, then sends back whatever it reads
+        let upstream = tokio::net::TcpListener::bind("127.0.0.1:9003")
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\r\n")
+                .await
+                .unwrap();
+            let (mut reader, mut writer) = stream.split();
+            let _ = tokio::io::copy(&mut reader, &mut writer).await;
+        });
+
+        let mut app = ServerFixture::run_app(config_file_path);
+        app.wait_for_start();
+        let tunnel = async {
+            let mut stream = tokio::net::TcpStream::connect("127.0.0.1:3000").await?;
+            stream
+                .write_all(b"GET /chat HTTP/1.1\r\nHost: localhost:3000\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\r\n")
+                .await?;
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                let mut byte = [0; 1];
+                stream.read_exact(&mut byte).await?;
+                head.push(byte[0]);
+            }
+            stream.write_all(b"ping").await?;
+            let mut echo = [0; 4];
+            stream.read_exact(&mut echo).await?;
+            Ok::<_, std::io::Error>((String::from_utf8_lossy(&head).to_lowercase(), echo))
+        };
+        let tunnel = tokio::time::timeout(Duration::from_secs(5), tunnel).await;
+        app.stop_app();
+
+        let (head, echo) = tunnel.unwrap().unwrap();
+        assert!(
+            head.starts_with("http/1.1 101 switching protocols\r\n"),
+            "{head}"
+        );
+        assert!(head.contains("\r\nupgrade: websocket\r\n"), "{head}");
+        assert_eq!(&echo, b"ping");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_in_flight_request_completes_during_shutdown_drain() {
+        use std::io::Read;
+
+        let config_file_path = Path::new("resources/test_cases/reverse-proxy-handler/drain.chf");
+        assert!(config_file_path.exists());
+        // the upstream runs on its own thread, stop_app and recv block the thread of the test
+        let upstream = std::net::TcpListener::bind("127.0.0.1:9004").unwrap();
+        let (received_sender, received) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = upstream.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            received_sender.send(()).unwrap();
+            std::thread::sleep(Duration::from_secs(1));
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\n\r\nslow")
+                .unwrap();
+        });
+
+        let mut app = ServerFixture::run_app(config_file_path);
+        app.wait_for_start();
+        let response = tokio::spawn(reqwest::get("http://localhost:3000/slow"));
+        received.recv_timeout(Duration::from_secs(5)).unwrap();
+        let started = std::time::Instant::now();
+        app.stop_app();
+        let stopped_after = started.elapsed();
+        let logs = app.remaining_logs();
+
+        let response = response.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "slow");
+        assert!(
+            logs.iter()
+                .any(|line| line.contains("Shutdown signal received")),
+            "{logs:?}"
+        );
+        assert!(stopped_after < Duration::from_secs(5), "{stopped_after:?}");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_changes_request_and_response_headers() {
+        use axum::routing::get;
+        use axum::Router;
+
+        let upstream = Router::new().route(
+            "/whoami",
+            get(async |headers: axum::http::HeaderMap| {
+                let header = |name: &str| {
+                    headers
+                        .get(name)
+                        .map(|value| value.to_str().unwrap().to_string())
+                        .unwrap_or_default()
+                };
+                (
+                    [("server", "upstream/1.0"), ("x-upstream-debug", "on")],
+                    format!("{};{}", header("authorization"), header("x-debug")),
+                )
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:9005")
+            .await
+            .unwrap();
+        tokio::spawn(async move { axum::serve::serve(listener, upstream).await.unwrap() });
+
+        let dir = tempfile::tempdir().unwrap();
+        let config_file_path = dir.path().join("header_up_down.chf");
+        std::fs::write(
+            &config_file_path,
+            r#"localhost:3000 {
+    route /* {
+        proxy {
+            upstreams http://127.0.0.1:9005
+            header_up =Authorization "Bearer test-token"
+            header_up -X-Debug
+            header_down -Server
+            header_down -X-Upstream-*
+            header_down +X-Proxied-By chico
+        }
+    }
+}"#,
+        )
+        .unwrap();
+
+        let mut app = ServerFixture::run_app(&config_file_path);
+        app.wait_for_start();
+        let response = reqwest::Client::new()
+            .get("http://localhost:3000/whoami")
+            .header("x-debug", "1")
+            .send()
+            .await;
+        app.stop_app();
+
+        let response = response.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("server").is_none());
+        assert!(response.headers().get("x-upstream-debug").is_none());
+        assert_eq!(response.headers()["x-proxied-by"], "chico");
+        assert_eq!(response.text().await.unwrap(), "Bearer test-token;");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_sends_host_of_client_with_preserve_host() {
+        use axum::Router;
+
+        let upstream = Router::new().fallback(async |headers: axum::http::HeaderMap| {
+            headers[axum::http::header::HOST]
+                .to_str()
+                .unwrap()
+                .to_string()
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:9005")
+            .await
+            .unwrap();
+        tokio::spawn(async move { axum::serve::serve(listener, upstream).await.unwrap() });
+
+        let dir = tempfile::tempdir().unwrap();
+        let config_file_path = dir.path().join("preserve_host.chf");
+        std::fs::write(
+            &config_file_path,
+            r#"localhost:3000 {
+    route /on {
+        proxy {
+            upstreams http://127.0.0.1:9005
+            preserve_host on
+        }
+    }
+    route /off {
+        proxy {
+            upstreams http://127.0.0.1:9005
+            preserve_host off
+        }
+    }
+}"#,
+        )
+        .unwrap();
+
+        let mut app = ServerFixture::run_app(&config_file_path);
+        app.wait_for_start();
+        let client = reqwest::Client::new();
+        let preserved = client.get("http://localhost:3000/on").send().await;
+        let rewritten = client.get("http://localhost:3000/off").send().await;
+        app.stop_app();
+
+        assert_eq!(preserved.unwrap().text().await.unwrap(), "localhost:3000");
+        assert_eq!(rewritten.unwrap().text().await.unwrap(), "127.0.0.1:9005");
+    }
+
+    #[tokio::test]
+    async fn test_respond_route_answers_expect_continue_without_waiting_for_body() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let config_file_path = dir.path().join("expect_continue.chf");
+        std::fs::write(
+            &config_file_path,
+            "localhost:3000 { route / { respond \"Hello\" 200 } }",
+        )
+        .unwrap();
+
+        let mut app = ServerFixture::run_app(&config_file_path);
+        app.wait_for_start();
+        let mut stream = tokio::net::TcpStream::connect("127.0.0.1:3000")
+            .await
+            .unwrap();
+        // the body announced by Content-Length is never sent
+        stream
+            .write_all(
+                b"POST / HTTP/1.1\r\nHost: localhost:3000\r\nExpect: 100-continue\r\nContent-Length: 5\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = vec![];
+        let read =
+            tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response)).await;
+        app.stop_app();
+
+        assert!(read.is_ok(), "the response waited for the body");
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.ends_with("Hello"), "{response}");
+    }
+
+    #[tokio::test]
+    async fn test_connection_dribbling_headers_is_closed_after_header_read_timeout() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let config_file_path = dir.path().join("header_read_timeout.chf");
+        std::fs::write(
+            &config_file_path,
+            "options { header_read_timeout 1s }
+localhost:3000 { route / { respond \"Hello\" 200 } }",
+        )
+        .unwrap();
+
+        let mut app = ServerFixture::run_app(&config_file_path);
+        app.wait_for_start();
+        let mut stream = tokio::net::TcpStream::connect("127.0.0.1:3000")
+            .await
+            .unwrap();
+        let started = std::time::Instant::now();
+        // one header line every 300ms, the header block never ends
+        let dribble = async {
+            stream.write_all(b"GET / HTTP/1.1\r\n").await?;
+            for i in 0.. {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                stream
+                    .write_all(format!("X-Slow-{i}: a\r\n").as_bytes())
+                    .await?;
+            }
+            Ok::<_, std::io::Error>(())
+        };
+        let dribbled = tokio::time::timeout(Duration::from_secs(5), dribble).await;
+        let closed_after = started.elapsed();
+        let mut response = vec![];
+        let _ = stream.read_to_end(&mut response).await;
+        app.stop_app();
+
+        // writing fails once the server closed the connection
+        assert!(matches!(dribbled, Ok(Err(_))), "{dribbled:?}");
+        assert!(
+            closed_after >= Duration::from_secs(1) && closed_after < Duration::from_secs(4),
+            "{closed_after:?}"
+        );
+        assert!(!response.starts_with(b"HTTP/1.1 200"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_reload_serves_changed_respond_body() {
+        use assert_cmd::cargo::CommandCargoExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let config_file_path = dir.path().join("reload.chf");
+        let pid_file_path = dir.path().join("chico.pid");
+        std::fs::write(
+            &config_file_path,
+            "localhost:3000 { route / { respond \"Before\" 200 } }",
+        )
+        .unwrap();
+        let reload = || {
+            std::process::Command::cargo_bin("chico")
+                .unwrap()
+                .arg("reload")
+                .arg("--pid-file")
+                .arg(&pid_file_path)
+                .output()
+                .unwrap()
+        };
+        let chico_status = |args: &[&str]| {
+            std::process::Command::cargo_bin("chico")
+                .unwrap()
+                .arg("status")
+                .arg("--pid-file")
+                .arg(&pid_file_path)
+                .args(args)
+                .output()
+                .unwrap()
+        };
+
+        let mut app = ServerFixture::run_app_with_args(
+            &config_file_path,
+            &["--pid-file".as_ref(), pid_file_path.as_os_str()],
+        );
+        app.wait_for_start();
+        let before = reqwest::get("http://localhost:3000/").await.unwrap();
+        assert_eq!(before.text().await.unwrap(), "Before");
+
+        // an invalid config is reported and the current one is kept
+        std::fs::write(
+            &config_file_path,
+            "localhost:3000 { route / { respond \"After\" 200 }",
+        )
+        .unwrap();
+        let rejected = reload();
+        assert!(!rejected.status.success());
+        assert!(String::from_utf8_lossy(&rejected.stderr).contains("keeps its current config"));
+
+        std::fs::write(
+            &config_file_path,
+            "localhost:3000 { route / { respond \"After\" 200 } }",
+        )
+        .unwrap();
+        let reloaded = reload();
+        assert!(reloaded.status.success(), "{reloaded:?}");
+        app.wait_for_text("reloaded");
+        let after = reqwest::get("http://localhost:3000/").await.unwrap();
+        let after = after.text().await.unwrap();
+        let reloaded_status = chico_status(&[]);
+
+        // a signal sent directly with an invalid config is rejected by the server
+        std::fs::write(
+            &config_file_path,
+            "localhost:3000 { route / { respond \"Broken\" 200 }",
+        )
+        .unwrap();
+        let (pid, _) = chico_server::reload::read_pid_file(&pid_file_path).unwrap();
+        chico_server::reload::signal_reload(pid).unwrap();
+        app.wait_for_text("rejected");
+        let failed_status = chico_status(&[]);
+        let metrics = chico_status(&["--metrics"]);
+        app.stop_app();
+
+        assert_eq!(after, "After");
+        let stdout = String::from_utf8_lossy(&reloaded_status.stdout);
+        assert!(stdout.contains("\nConfig generation: 1\n"), "{stdout}");
+        assert!(
+            stdout.contains("\nReloads: 1 succeeded, 0 failed\n"),
+            "{stdout}"
+        );
+        assert!(stdout.contains("\nLast successful reload: "), "{stdout}");
+        let stdout = String::from_utf8_lossy(&failed_status.stdout);
+        assert!(
+            stdout.contains("\nReloads: 1 succeeded, 1 failed\n"),
+            "{stdout}"
+        );
+        assert!(
+            stdout.contains("\nLast reload error: Failed to parse config file."),
+            "{stdout}"
+        );
+        assert!(metrics.status.success(), "{metrics:?}");
+        let stdout = String::from_utf8_lossy(&metrics.stdout);
+        assert!(stdout.contains("\nchico_config_reload_total{result=\"failure\"} 1\n"));
+        assert!(stdout.contains("\nchico_config_last_reload_timestamp "));
+        assert!(!pid_file_path.exists());
+        assert!(!chico_server::reload::status_file_path(&pid_file_path).exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_status_reports_running_server_and_fails_once_stopped() {
+        use assert_cmd::cargo::CommandCargoExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let config_file_path = dir.path().join("status.chf");
+        let pid_file_path = dir.path().join("chico.pid");
+        std::fs::write(
+            &config_file_path,
+            "localhost:3000 { route / { respond 200 } route /api { respond 201 } }
+example.localhost:3000 { route / { respond 200 } }",
+        )
+        .unwrap();
+        let status = || {
+            std::process::Command::cargo_bin("chico")
+                .unwrap()
+                .arg("status")
+                .arg("--pid-file")
+                .arg(&pid_file_path)
+                .output()
+                .unwrap()
+        };
+
+        let mut app = ServerFixture::run_app_with_args(
+            &config_file_path,
+            &["--pid-file".as_ref(), pid_file_path.as_os_str()],
+        );
+        app.wait_for_start();
+        // the status describes what the server serves, not the config file changed since
+        std::fs::write(&config_file_path, "changed.localhost:4000 { route / {").unwrap();
+        let running = status();
+        // a pid file left behind by a server that did not exit cleanly
+        let pid_file = std::fs::read_to_string(&pid_file_path).unwrap();
+        app.stop_app();
+        let stopped = status();
+        std::fs::write(&pid_file_path, &pid_file).unwrap();
+        let exited = status();
+
+        assert!(running.status.success(), "{running:?}");
+        let stdout = String::from_utf8_lossy(&running.stdout);
+        assert!(stdout.contains("Chico is running (pid "), "{stdout}");
+        assert!(stdout.contains("\nPorts: 3000\n"), "{stdout}");
+        assert!(stdout.contains("\nVirtual hosts: 2\n"), "{stdout}");
+        assert!(stdout.contains("\nRoutes: 3\n"), "{stdout}");
+        assert!(!stopped.status.success());
+        assert!(String::from_utf8_lossy(&stopped.stderr).contains("Chico is not running."));
+        assert!(!exited.status.success());
+        assert!(String::from_utf8_lossy(&exited.stderr).contains("has exited"));
+    }
 }
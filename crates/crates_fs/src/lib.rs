@@ -0,0 +1,185 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Atomically replaces the content of the file at `path` with `bytes`.
+///
+/// The content is written to a temporary file in the same directory, flushed to disk and then
+/// renamed over `path`, so readers see either the previous content or the new one, never a
+/// partially written file. The directory is synced afterwards to persist the rename.
+///
+/// On Unix the file is created with the permission bits given in `mode` (e.g. `0o600` for private
+/// keys). `mode` is ignored on other platforms.
+///
+/// # Examples
+///
+/// ```
+/// let dir = tempfile::tempdir().unwrap();
+/// let path = dir.path().join("chico.pid");
+///
+/// crates_fs::atomic_write(&path, b"4242", 0o644).unwrap();
+/// assert_eq!(std::fs::read(&path).unwrap(), b"4242");
+/// ```
+pub fn atomic_write(path: &Path, bytes: &[u8], mode: u32) -> io::Result<()> {
+    let temp_path = write_temp(path, bytes, mode)?;
+
+    if let Err(err) = fs::rename(&temp_path, path) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err);
+    }
+
+    sync_dir(&parent_dir(path))
+}
+
+/// Writes `bytes` to a new temporary file next to `path` and returns the temporary path.
+fn write_temp(path: &Path, bytes: &[u8], mode: u32) -> io::Result<PathBuf> {
+    let temp_path = temp_path_for(path)?;
+
+    let result = (|| {
+        let mut file = create_new(&temp_path, mode)?;
+        file.write_all(bytes)?;
+        file.sync_all()
+    })();
+
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err);
+    }
+
+    Ok(temp_path)
+}
+
+fn temp_path_for(path: &Path) -> io::Result<PathBuf> {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+    let Some(file_name) = path.file_name() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a file path", path.display()),
+        ));
+    };
+
+    let temp_name = format!(
+        ".{}.{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    );
+    Ok(parent_dir(path).join(temp_name))
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+#[cfg(unix)]
+fn create_new(path: &Path, mode: u32) -> io::Result<File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(path)?;
+    // The mode given to open is reduced by the umask, set it explicitly.
+    file.set_permissions(fs::Permissions::from_mode(mode))?;
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn create_new(path: &Path, _mode: u32) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    // Directories cannot be opened for syncing on Windows, the rename is persisted by the OS.
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{atomic_write, write_temp};
+
+    #[test]
+    fn test_atomic_write_creates_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+
+        atomic_write(&path, b"{}", 0o644).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"{}");
+    }
+
+    #[test]
+    fn test_atomic_write_replaces_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chico.pid");
+        fs::write(&path, b"1").unwrap();
+
+        atomic_write(&path, b"4242", 0o644).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"4242");
+        // no temporary file is left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_crash_before_rename_keeps_previous_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chico.pid");
+        fs::write(&path, b"previous").unwrap();
+
+        // Simulate a crash right after the temporary file is written, before the rename.
+        let temp_path = write_temp(&path, b"partial new content", 0o644).unwrap();
+
+        assert_ne!(temp_path, path);
+        assert_eq!(temp_path.parent(), path.parent());
+        assert_eq!(fs::read(&path).unwrap(), b"previous");
+    }
+
+    #[test]
+    fn test_crash_before_rename_leaves_no_file_at_final_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("account.json");
+
+        write_temp(&path, b"{\"key\": \"...\"}", 0o600).unwrap();
+
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_atomic_write_rejects_path_without_file_name() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(atomic_write(&dir.path().join(".."), b"", 0o644).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_atomic_write_sets_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key.pem");
+        fs::write(&path, b"old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        atomic_write(&path, b"secret", 0o600).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use tracing::{Metadata, Subscriber};
+use tracing_subscriber::{
+    filter::{dynamic_filter_fn, filter_fn},
+    fmt::MakeWriter,
+    layer::{Context, Filter},
+    registry::LookupSpan,
+    Layer,
+};
+
+use crate::LogFile;
+
+/// Target of the access log events, one event per served request.
+pub const ACCESS_LOG_TARGET: &str = "chico::access";
+
+/// Destination of the access log.
+///
+/// Access log events are written with the diagnostic logs until a file is set, then only to that
+/// file. Clones share the same destination.
+#[derive(Clone, Default)]
+pub struct AccessLog {
+    file: Arc<Mutex<Option<LogFile>>>,
+}
+
+impl AccessLog {
+    /// Writes the access log to the file at `path`, or back to the diagnostic logs with `None`.
+    pub fn set_file(&self, path: Option<&Path>) -> io::Result<()> {
+        let file = path.map(LogFile::open).transpose()?;
+        *self.file.lock().unwrap() = file;
+        Ok(())
+    }
+
+    /// Reopens the access log file at its path, after it was renamed by an external rotation.
+    pub fn reopen(&self) -> io::Result<()> {
+        match self.file.lock().unwrap().as_ref() {
+            Some(file) => file.reopen(),
+            None => Ok(()),
+        }
+    }
+
+    fn is_separate(&self) -> bool {
+        self.file.lock().unwrap().is_some()
+    }
+
+    /// Returns the layer writing the access log events to the file.
+    pub fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_level(false)
+            .with_target(false)
+            .with_writer(self.clone())
+            .with_filter(filter_fn(is_access_log))
+    }
+
+    /// Returns the filter of the diagnostic layers, leaving out the access log events while they
+    /// are written to their own file.
+    ///
+    /// The filter is dynamic, it is evaluated for each event as the file may be set at runtime.
+    pub fn diagnostic_filter<S>(&self) -> impl Filter<S> + Clone {
+        let access_log = self.clone();
+        dynamic_filter_fn(move |metadata: &Metadata<'_>, _: &Context<'_, S>| {
+            !is_access_log(metadata) || !access_log.is_separate()
+        })
+    }
+}
+
+fn is_access_log(metadata: &Metadata<'_>) -> bool {
+    metadata.target() == ACCESS_LOG_TARGET
+}
+
+impl<'a> MakeWriter<'a> for AccessLog {
+    type Writer = AccessLogWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        AccessLogWriter(self.file.lock().unwrap().clone())
+    }
+}
+
+/// Writer of one access log event, events are dropped while no file is set.
+pub struct AccessLogWriter(Option<LogFile>);
+
+impl Write for AccessLogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            Some(file) => file.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.0 {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tracing::info;
+    use tracing_subscriber::{layer::SubscriberExt, Layer};
+
+    use super::{AccessLog, ACCESS_LOG_TARGET};
+    use crate::LogFile;
+
+    fn log_with(access_log: &AccessLog, diagnostic: &LogFile) {
+        let subscriber = tracing_subscriber::registry()
+            .with(access_log.layer())
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(std::sync::Mutex::new(diagnostic.clone()))
+                    .with_filter(access_log.diagnostic_filter()),
+            );
+        tracing::subscriber::with_default(subscriber, || {
+            info!(target: ACCESS_LOG_TARGET, "127.0.0.1 \"GET /index.html HTTP/1.1\" 200");
+            info!("upstream pool ready");
+        });
+    }
+
+    #[test]
+    fn test_access_log_events_are_written_to_their_own_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let access_path = dir.path().join("access.log");
+        let diagnostic_path = dir.path().join("chico.log");
+        let diagnostic = LogFile::open(&diagnostic_path).unwrap();
+        let access_log = AccessLog::default();
+        access_log.set_file(Some(&access_path)).unwrap();
+
+        log_with(&access_log, &diagnostic);
+
+        let access = fs::read_to_string(&access_path).unwrap();
+        let diagnostic = fs::read_to_string(&diagnostic_path).unwrap();
+        assert!(
+            access.contains("\"GET /index.html HTTP/1.1\" 200"),
+            "{access}"
+        );
+        assert!(!access.contains("upstream pool ready"), "{access}");
+        assert!(diagnostic.contains("upstream pool ready"), "{diagnostic}");
+        assert!(!diagnostic.contains("GET /index.html"), "{diagnostic}");
+    }
+
+    #[test]
+    fn test_access_log_events_go_to_diagnostic_log_without_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let diagnostic_path = dir.path().join("chico.log");
+        let diagnostic = LogFile::open(&diagnostic_path).unwrap();
+
+        log_with(&AccessLog::default(), &diagnostic);
+
+        let diagnostic = fs::read_to_string(&diagnostic_path).unwrap();
+        assert!(diagnostic.contains("GET /index.html"), "{diagnostic}");
+        assert!(diagnostic.contains("upstream pool ready"), "{diagnostic}");
+    }
+}
@@ -1,4 +1,7 @@
-use std::path::PathBuf;
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
 
 use directories::ProjectDirs;
 use opentelemetry::{trace::TracerProvider, KeyValue};
@@ -6,49 +9,131 @@ use opentelemetry_sdk::Resource;
 use tracing::{info, level_filters::LevelFilter};
 use tracing_opentelemetry::OpenTelemetryLayer;
 use tracing_subscriber::{
-    filter::Targets, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
+    filter::{FilterExt, Targets},
+    layer::SubscriberExt,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
 };
 
+mod access_log;
+mod log_file;
+
+pub use access_log::{AccessLog, ACCESS_LOG_TARGET};
+pub use log_file::LogFile;
+
+/// How the log file is rotated.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LogRotation {
+    /// A new file is started every day.
+    #[default]
+    Daily,
+    /// An external tool like logrotate renames the file and asks for it to be reopened through
+    /// [`LogHandle::reopen`]. The built-in daily rotation is disabled.
+    External,
+}
+
+/// Handle to the log files of the initialized `tracing` framework.
+#[derive(Clone, Default)]
+pub struct LogHandle {
+    file: Option<LogFile>,
+    filter: Option<reload::Handle<Targets, Registry>>,
+    access_log: AccessLog,
+}
+
+impl From<LogFile> for LogHandle {
+    fn from(file: LogFile) -> Self {
+        Self {
+            file: Some(file),
+            filter: None,
+            access_log: AccessLog::default(),
+        }
+    }
+}
+
+impl LogHandle {
+    /// Reopens the log files at their path, after they were renamed by an external rotation.
+    ///
+    /// The diagnostic log is left alone with the built-in daily rotation, which manages its
+    /// files itself.
+    pub fn reopen(&self) -> io::Result<()> {
+        if let Some(file) = &self.file {
+            file.reopen()?;
+        }
+        self.access_log.reopen()
+    }
+
+    /// Writes the access log to the file at `path` instead of the diagnostic logs.
+    pub fn set_access_log(&self, path: &Path) -> io::Result<()> {
+        self.access_log.set_file(Some(path))
+    }
+
+    /// Changes the level of the events written by chico, e.g. to the one set in the config.
+    pub fn set_level(&self, level: LevelFilter) -> Result<(), String> {
+        match &self.filter {
+            Some(filter) => filter.reload(targets(level)).map_err(|e| e.to_string()),
+            None => Ok(()),
+        }
+    }
+}
+
 /// Initializes the `tracing` logging framework.
 ///
 /// Regular CLI output is influenced by the optional
 /// [`RUST_LOG`](tracing_subscriber::filter::EnvFilter) environment variable
 /// and is showing all `INFO` level events by default.
-pub fn init(log_file_name: String, app_name: String) {
-    init_with_default_level(LevelFilter::DEBUG, log_file_name, app_name);
+pub fn init(log_file_name: String, app_name: String, rotation: LogRotation) -> LogHandle {
+    init_with_default_level(LevelFilter::DEBUG, log_file_name, app_name, rotation)
 }
 
-fn init_with_default_level(level: LevelFilter, log_file_name: String, app_name: String) {
-    let filter = Targets::new()
-        .with_target("chico", level)
-        .with_target("tokio", LevelFilter::OFF)
-        .with_target("hyper", LevelFilter::OFF)
-        .with_target("opentelemetry_sdk", LevelFilter::OFF)
-        .with_target("opentelemetry-otlp", LevelFilter::OFF);
+fn init_with_default_level(
+    level: LevelFilter,
+    log_file_name: String,
+    app_name: String,
+    rotation: LogRotation,
+) -> LogHandle {
+    let (filter, filter_handle) = reload::Layer::new(targets(level));
 
-    let env_filter = create_env_filter(level);
+    let access_log = AccessLog::default();
+    let env_filter = create_env_filter();
 
     let stdout_layer = tracing_subscriber::fmt::layer()
         .compact()
-        .with_filter(env_filter)
+        .with_filter(env_filter.and(access_log.diagnostic_filter()))
         .boxed();
 
     let log_dir = get_log_dir(app_name);
     info!("logs directory {log_dir:?}");
-    let file_appender = tracing_appender::rolling::daily(log_dir, log_file_name);
-
-    let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
+    let mut handle = LogHandle {
+        file: None,
+        filter: Some(filter_handle),
+        access_log: access_log.clone(),
+    };
+    let (non_blocking, _guard) = match rotation {
+        LogRotation::Daily => {
+            let file_appender = tracing_appender::rolling::daily(log_dir, log_file_name);
+            tracing_appender::non_blocking(file_appender)
+        }
+        LogRotation::External => {
+            let log_file =
+                LogFile::open(log_dir.join(log_file_name)).expect("failed to open the log file");
+            handle.file = Some(log_file.clone());
+            // The writer thread of non_blocking is the only one writing to the file, events
+            // queued while the file is reopened are written to the new file.
+            tracing_appender::non_blocking(log_file)
+        }
+    };
 
     // Save guard to keep the file open and Prevents drop during runtime
     Box::leak(Box::new(_guard));
 
-    let env_filter = create_env_filter(level);
+    let env_filter = create_env_filter();
 
     let file_layer = tracing_subscriber::fmt::layer()
         .with_ansi(false)
         .compact()
         .with_writer(non_blocking)
-        .with_filter(env_filter)
+        .with_filter(env_filter.and(access_log.diagnostic_filter()))
         .boxed();
 
     let otlp_exporter = opentelemetry_otlp::SpanExporterBuilder::new()
@@ -67,21 +152,39 @@ fn init_with_default_level(level: LevelFilter, log_file_name: String, app_name:
 
     let tracer = tracer_provider.tracer("chico");
 
-    let env_filter = create_env_filter(level);
+    let env_filter = create_env_filter();
 
     let telemetry = OpenTelemetryLayer::new(tracer).with_filter(env_filter);
 
     tracing_subscriber::registry()
+        .with(filter)
         .with(stdout_layer)
         .with(file_layer)
+        .with(access_log.layer())
         .with(telemetry)
-        .with(filter)
         .init();
+
+    handle
+}
+
+/// Returns the filter of the events of chico at `level`, the noisy dependencies are silenced.
+///
+/// The access log is written whatever the level.
+fn targets(level: LevelFilter) -> Targets {
+    Targets::new()
+        .with_target("chico", level)
+        .with_target(ACCESS_LOG_TARGET, LevelFilter::INFO)
+        .with_target("tokio", LevelFilter::OFF)
+        .with_target("hyper", LevelFilter::OFF)
+        .with_target("opentelemetry_sdk", LevelFilter::OFF)
+        .with_target("opentelemetry-otlp", LevelFilter::OFF)
 }
 
-fn create_env_filter(level: LevelFilter) -> EnvFilter {
+/// Filters the events with `RUST_LOG`. Without it everything the reloadable targets filter
+/// enables is written, so the level set through [`LogHandle::set_level`] takes effect.
+fn create_env_filter() -> EnvFilter {
     EnvFilter::builder()
-        .with_default_directive(level.into())
+        .with_default_directive(LevelFilter::TRACE.into())
         .from_env_lossy()
 }
 
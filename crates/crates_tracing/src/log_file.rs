@@ -0,0 +1,123 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// A log file that can be reopened at its path after it was renamed by an external tool like
+/// logrotate.
+///
+/// Clones share the same handle. Writes and reopening are serialized, so a write lands either in
+/// the renamed file or in the new one, never in between.
+#[derive(Clone)]
+pub struct LogFile {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+}
+
+impl LogFile {
+    /// Opens the file at `path` for appending, creating it and its directory when missing.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = open_append(&path)?;
+        Ok(Self {
+            path,
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Closes the current handle and opens the file at the original path again.
+    ///
+    /// On failure the previous handle is kept, so no event is lost.
+    pub fn reopen(&self) -> io::Result<()> {
+        let new_file = open_append(&self.path)?;
+        let mut file = self.file.lock().unwrap();
+        file.flush()?;
+        *file = new_file;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.lock().unwrap().write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.file.lock().unwrap().write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.lock().unwrap().flush()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use super::LogFile;
+
+    #[test]
+    fn test_reopen_writes_to_new_file_after_rename() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chico.log");
+        let rotated = dir.path().join("chico.log.1");
+
+        let mut log_file = LogFile::open(&path).unwrap();
+        log_file.write_all(b"before\n").unwrap();
+
+        fs::rename(&path, &rotated).unwrap();
+        // without reopening the handle still points to the renamed file
+        log_file.write_all(b"still old\n").unwrap();
+
+        log_file.reopen().unwrap();
+        log_file.write_all(b"after\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&rotated).unwrap(), "before\nstill old\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "after\n");
+    }
+
+    #[test]
+    fn test_reopen_appends_to_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chico.log");
+
+        let mut log_file = LogFile::open(&path).unwrap();
+        log_file.write_all(b"first\n").unwrap();
+        log_file.reopen().unwrap();
+        log_file.write_all(b"second\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first\nsecond\n");
+    }
+
+    #[test]
+    fn test_clones_share_the_reopened_handle() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logs").join("chico.log");
+
+        let log_file = LogFile::open(&path).unwrap();
+        let mut writer = log_file.clone();
+
+        fs::rename(&path, dir.path().join("rotated.log")).unwrap();
+        log_file.reopen().unwrap();
+        writer.write_all(b"event\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "event\n");
+    }
+}